@@ -14,6 +14,8 @@ cross() {
     docker run -it --rm -v $PWD:/work majorz/rust-$ARCH:rust-1.23.0 "$@"
 }
 
-cross cargo build --release --target=$TARGET
+# Further arguments are passed to cargo, e.g. `--no-default-features` for a
+# minimal binary
+cross cargo build --release --target=$TARGET "${@:3}"
 
 cross cross-strip target/$TARGET/release/wifi-connect
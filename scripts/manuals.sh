@@ -0,0 +1,12 @@
+#!/bin/sh
+
+# Regenerates docs/command-line-arguments.md and the wifi-connect.1 man page
+# next to the binary from the command line definitions of a build, e.g.
+# `cargo build --release && scripts/manuals.sh`
+
+set -e
+
+BINARY=${1:-target/release/wifi-connect}
+
+"$BINARY" --help-markdown > docs/command-line-arguments.md
+"$BINARY" --help-man > "$(dirname "$BINARY")/wifi-connect.1"
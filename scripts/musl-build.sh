@@ -0,0 +1,31 @@
+#!/bin/bash
+
+# Builds a fully static binary for Alpine based images. The libdbus based
+# network-manager feature is left out, NetworkManager is driven over the
+# pure Rust D-Bus client instead, so nothing but musl is linked in. The build
+# also writes the man page next to the binary and refreshes the Markdown
+# reference.
+
+set -ev
+
+ARCH=${1:-aarch64}
+
+case $ARCH in
+    aarch64) PLATFORM=linux/arm64 ;;
+    x86_64) PLATFORM=linux/amd64 ;;
+    *)
+        printf 'Unsupported architecture %s, use aarch64 or x86_64\n' "$ARCH"
+        exit 1
+        ;;
+esac
+
+TARGET=$ARCH-unknown-linux-musl
+
+# Further arguments are passed to cargo, e.g. `--features compression`
+docker run -i --rm --platform "$PLATFORM" -v "$PWD":/work -w /work \
+    rust:alpine \
+    sh -c "apk add --no-cache musl-dev && \
+        cargo build --release --target=$TARGET --no-default-features ${*:2} && \
+        scripts/manuals.sh target/$TARGET/release/wifi-connect"
+
+file "target/$TARGET/release/wifi-connect"
@@ -0,0 +1,244 @@
+//! Access log of the HTTP servers, with `--access-log`, to tell which clients
+//! used the portal during a provisioning session in dispute afterwards. Every
+//! request is logged once its response is known, in the Common or Combined
+//! Log Format of web servers, or as a JSON object per line.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use iron::{headers, AroundMiddleware, Handler, IronResult, Request, Response};
+
+use errors::*;
+use history::utc_fields;
+
+pub const ACCESS_LOG_FORMATS: &[&str] = &["common", "combined", "json"];
+
+const MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessLogFormat {
+    Common,
+    /// Common with the referrer and user agent
+    Combined,
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        match value {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!(
+                "'{}' is not one of {}",
+                value,
+                ACCESS_LOG_FORMATS.join(", ")
+            )),
+        }
+    }
+}
+
+/// Where the access log goes, from `--access-log <path|stderr>`
+#[derive(Clone, Debug)]
+pub enum AccessLogTarget {
+    Stderr,
+    File(PathBuf),
+}
+
+impl AccessLogTarget {
+    pub fn parse(value: &str) -> Self {
+        if value == "stderr" {
+            AccessLogTarget::Stderr
+        } else {
+            AccessLogTarget::File(PathBuf::from(value))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AccessLogSettings {
+    pub target: AccessLogTarget,
+    pub format: AccessLogFormat,
+}
+
+/// Middleware writing a line per request, shared by the handler threads
+pub struct AccessLog {
+    format: AccessLogFormat,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AccessLog {
+    /// Opens the log, appending to the file so that restarts keep the
+    /// earlier sessions
+    pub fn open(settings: &AccessLogSettings) -> Result<Self> {
+        let writer: Box<dyn Write + Send> = match settings.target {
+            AccessLogTarget::Stderr => Box::new(io::stderr()),
+            AccessLogTarget::File(ref path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .chain_err(|| ErrorKind::AccessLog(path.display().to_string()))?,
+            ),
+        };
+
+        Ok(AccessLog {
+            format: settings.format,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+}
+
+impl AroundMiddleware for AccessLog {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(AccessLogHandler {
+            handler,
+            format: self.format,
+            writer: self.writer,
+        })
+    }
+}
+
+struct AccessLogHandler {
+    handler: Box<dyn Handler>,
+    format: AccessLogFormat,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Handler for AccessLogHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let started = Instant::now();
+        let result = self.handler.handle(req);
+
+        // Errors carry the response sent to the client as well
+        let response = match result {
+            Ok(ref response) => response,
+            Err(ref err) => &err.response,
+        };
+
+        let entry = Entry::new(req, response, started);
+        let line = match self.format {
+            AccessLogFormat::Common => entry.common(),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                entry.common(),
+                or_dash(entry.referer.as_ref().map(|referer| escape(referer))),
+                or_dash(entry.user_agent.as_ref().map(|agent| escape(agent)))
+            ),
+            AccessLogFormat::Json => entry.json(),
+        };
+
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            warn!("Writing the access log failed: {}", e);
+        }
+
+        result
+    }
+}
+
+/// Fields of a logged request
+struct Entry {
+    timestamp: u64,
+    client: String,
+    method: String,
+    path: String,
+    protocol: String,
+    status: Option<u16>,
+    bytes: Option<u64>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    duration_ms: u64,
+}
+
+impl Entry {
+    fn new(req: &Request, response: &Response, started: Instant) -> Self {
+        let elapsed = started.elapsed();
+
+        let mut path = format!("/{}", req.url.path().join("/"));
+        if let Some(query) = req.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_secs())
+                .unwrap_or(0),
+            client: req.remote_addr.ip().to_string(),
+            method: req.method.to_string(),
+            path,
+            protocol: req.version.to_string(),
+            status: response.status.map(|status| status.to_u16()),
+            bytes: response
+                .headers
+                .get::<headers::ContentLength>()
+                .map(|length| length.0),
+            referer: req
+                .headers
+                .get::<headers::Referer>()
+                .map(|referer| referer.to_string()),
+            user_agent: req
+                .headers
+                .get::<headers::UserAgent>()
+                .map(|user_agent| user_agent.to_string()),
+            duration_ms: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+        }
+    }
+
+    /// `client - - [time] "request" status bytes`, with `-` for the unknown
+    /// fields. There is no remote user, the portal has no logins.
+    fn common(&self) -> String {
+        let (year, month, day, hour, minute, second) = utc_fields(self.timestamp);
+
+        format!(
+            "{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {} {}\" {} {}",
+            self.client,
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            hour,
+            minute,
+            second,
+            self.method,
+            escape(&self.path),
+            self.protocol,
+            or_dash(self.status),
+            or_dash(self.bytes)
+        )
+    }
+
+    fn json(&self) -> String {
+        json!({
+            "timestamp": self.timestamp,
+            "client": self.client,
+            "method": self.method,
+            "path": self.path,
+            "protocol": self.protocol,
+            "status": self.status,
+            "bytes": self.bytes,
+            "referer": self.referer,
+            "user_agent": self.user_agent,
+            "duration_ms": self.duration_ms,
+        })
+        .to_string()
+    }
+}
+
+/// Escapes quotes and control characters, which would break up the line
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+fn or_dash<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
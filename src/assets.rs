@@ -0,0 +1,220 @@
+//! Compression and caching of the UI assets. Phones at the edge of the
+//! hotspot's range get a fraction of a single 2.4 GHz stream, so the assets
+//! are sent gzip or deflate compressed when the browser accepts it, and with
+//! an ETag for revalidating them. Bundles with a content hash in their name
+//! never change, so browsers may keep them without asking again. With
+//! `--low-memory` the files are streamed from flash as they are instead.
+//! Builds without the `compression` feature only add the cache headers.
+
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "compression")]
+use flate2::write::{GzEncoder, ZlibEncoder};
+#[cfg(feature = "compression")]
+use flate2::Compression;
+use iron::headers::{
+    AcceptEncoding, CacheControl, CacheDirective, ContentEncoding, ContentLength, ContentType,
+    ETag, Encoding, EntityTag, IfNoneMatch,
+};
+use iron::{status, AfterMiddleware, Chain, IronError, IronResult, Request, Response};
+use staticfile::Static;
+
+use low_memory::is_low_memory;
+
+/// Smaller bodies gain less than the compression headers cost
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Seconds bundles with a content hash are cached for
+const IMMUTABLE_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// Compressed bodies by ETag and encoding, as the assets only change with
+/// a new build of the UI
+type CompressedCache = Arc<Mutex<HashMap<(String, &'static str), Arc<Vec<u8>>>>>;
+
+/// Serves the files of `directory` compressed and with cache headers
+pub fn static_assets(directory: &Path) -> Chain {
+    let mut chain = Chain::new(Static::new(directory));
+    if !is_low_memory() {
+        chain.link_after(AssetHeaders {
+            compressed: Arc::new(Mutex::new(HashMap::new())),
+        });
+    }
+    chain
+}
+
+struct AssetHeaders {
+    compressed: CompressedCache,
+}
+
+impl AfterMiddleware for AssetHeaders {
+    fn after(&self, req: &mut Request, mut response: Response) -> IronResult<Response> {
+        let mut body = match response.body.take() {
+            Some(body) => body,
+            None => return Ok(response),
+        };
+
+        let mut content = Vec::new();
+        if let Err(e) = body.write_body(&mut content) {
+            return Err(IronError::new(e, status::InternalServerError));
+        }
+
+        let etag = EntityTag::strong(format!("{:016x}", fnv1a(&content)));
+
+        let cache = if is_hashed(req.url.path().last().map_or("", |name| *name)) {
+            vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(IMMUTABLE_MAX_AGE),
+                CacheDirective::Extension("immutable".into(), None),
+            ]
+        } else {
+            vec![CacheDirective::NoCache]
+        };
+
+        response.headers.set(CacheControl(cache));
+        response.headers.set(ETag(etag.clone()));
+        response
+            .headers
+            .set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+
+        let not_modified = match req.headers.get::<IfNoneMatch>() {
+            Some(&IfNoneMatch::Any) => true,
+            Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => false,
+        };
+
+        if not_modified {
+            response.status = Some(status::NotModified);
+            response.headers.remove::<ContentLength>();
+            return Ok(response);
+        }
+
+        let encoding = if content.len() >= MIN_COMPRESSED_SIZE && is_compressible(&response) {
+            accepted_encoding(req)
+        } else {
+            None
+        };
+
+        let content = match encoding {
+            Some(encoding) => match self.compress(etag.tag(), encoding, &content) {
+                Some(compressed) => {
+                    response.headers.set(ContentEncoding(vec![encoding]));
+                    compressed.to_vec()
+                }
+                None => content,
+            },
+            None => content,
+        };
+
+        response.headers.set(ContentLength(content.len() as u64));
+        response.body = Some(Box::new(content));
+
+        Ok(response)
+    }
+}
+
+impl AssetHeaders {
+    fn compress(&self, tag: &str, encoding: Encoding, content: &[u8]) -> Option<Arc<Vec<u8>>> {
+        let name = match encoding {
+            Encoding::Gzip => "gzip",
+            _ => "deflate",
+        };
+
+        let key = (tag.to_string(), name);
+
+        if let Some(compressed) = self.compressed.lock().unwrap().get(&key) {
+            return Some(compressed.clone());
+        }
+
+        match encode(&encoding, content) {
+            Ok(compressed) => {
+                let compressed = Arc::new(compressed);
+                self.compressed
+                    .lock()
+                    .unwrap()
+                    .insert(key, compressed.clone());
+                Some(compressed)
+            }
+            Err(e) => {
+                warn!("Compressing an asset failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+fn encode(encoding: &Encoding, content: &[u8]) -> io::Result<Vec<u8>> {
+    match *encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(content).and_then(|_| encoder.finish())
+        }
+        _ => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(content).and_then(|_| encoder.finish())
+        }
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn encode(_encoding: &Encoding, _content: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "built without the `compression` feature",
+    ))
+}
+
+/// gzip if the browser accepts it, deflate otherwise, or none of them
+fn accepted_encoding(req: &Request) -> Option<Encoding> {
+    if !cfg!(feature = "compression") {
+        return None;
+    }
+
+    let accepted = req.headers.get::<AcceptEncoding>()?;
+
+    let accepts = |encoding: Encoding| {
+        accepted
+            .iter()
+            .any(|item| item.item == encoding && item.quality.0 > 0)
+    };
+
+    if accepts(Encoding::Gzip) {
+        Some(Encoding::Gzip)
+    } else if accepts(Encoding::Deflate) {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Text formats, the images and fonts of the UI are compressed already
+fn is_compressible(response: &Response) -> bool {
+    let content_type = match response.headers.get::<ContentType>() {
+        Some(content_type) => content_type.to_string(),
+        None => return false,
+    };
+
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("image/svg+xml")
+}
+
+/// Whether the file name carries a content hash, as in `main.3c4f2a1b.js`
+fn is_hashed(name: &str) -> bool {
+    name.split('.')
+        .skip(1)
+        .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// FNV-1a hash of the content, for the ETag
+fn fnv1a(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,761 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use backend::{
+    find_wireless_interface, is_wireless_interface, Backend, ForgetFilter, ForgetScope,
+};
+use channels::rssi_to_percent;
+use config::Config;
+use errors::*;
+use exit::Shutdown;
+use ipconfig::IpConfig;
+use link::get_link_statistics;
+use network::{
+    get_interface_address, get_interface_ipv4, get_interface_ipv6, ConnectedNetwork, Network,
+    NetworkCredentials, SavedNetwork, SavedNetworkDetails,
+};
+use passpoint::PasspointProfile;
+use profile::{validate_secret, ProfileSettings, ProfileUpdate};
+use ssid::parse_ssid;
+use wiphy::{require_hotspot_mode, set_power_save, HotspotMode};
+
+const IWD_STATE_DIRECTORY: &str = "/var/lib/iwd";
+
+/// Seconds to wait for iwd to pick up a known network written to its state
+/// directory
+const KNOWN_NETWORK_TIMEOUT: u64 = 5;
+
+/// Drives iwd through `iwctl`, for distributions without NetworkManager.
+/// Known networks are read from and provisioned into iwd's state directory.
+pub struct IwdBackend {
+    interface: String,
+    portal_ssid: String,
+    networks: Vec<Network>,
+    shutdown: Shutdown,
+}
+
+impl IwdBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let interface = match config.interface {
+            Some(ref interface) => {
+                if !is_wireless_interface(interface) {
+                    bail!(ErrorKind::NotAWiFiDevice(interface.clone()))
+                }
+                interface.clone()
+            }
+            None => match find_wireless_interface() {
+                Some(interface) => interface,
+                None => bail!(ErrorKind::NoWiFiDevice),
+            },
+        };
+
+        info!("WiFi device: {}", interface);
+
+        Ok(IwdBackend {
+            interface,
+            portal_ssid: config.ssid.clone(),
+            networks: Vec::new(),
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    fn station(&self, args: &[&str]) -> Result<String> {
+        let mut station_args = vec!["station", self.interface.as_str()];
+        station_args.extend_from_slice(args);
+        iwctl(&station_args)
+    }
+
+    fn set_mode(&self, mode: &str) -> Result<()> {
+        iwctl(&["device", self.interface.as_str(), "set-property", "Mode", mode]).map(|_| ())
+    }
+
+    /// Known networks `--forget-all` removes with the filter
+    fn forgettable_networks(&self, filter: &ForgetFilter) -> Result<Vec<String>> {
+        if filter.older_than.is_some() {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "forgetting networks by when they were last used".into()
+            ));
+        }
+
+        Ok(self
+            .saved_networks()?
+            .into_iter()
+            .map(|saved| saved.ssid)
+            .filter(|ssid| !filter.keeps_ssid(ssid.as_bytes()))
+            .collect())
+    }
+}
+
+impl Backend for IwdBackend {
+    fn name(&self) -> &'static str {
+        "iwd"
+    }
+
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    fn request_scan(&self) -> Result<()> {
+        self.station(&["scan"]).map(|_| ())
+    }
+
+    fn is_scanning(&self) -> Result<bool> {
+        let output = self.station(&["show"])?;
+
+        Ok(output.lines().any(|line| {
+            line.trim()
+                .strip_prefix("Scanning")
+                .is_some_and(|value| value.trim() == "yes")
+        }))
+    }
+
+    fn scan(&mut self) -> Result<Vec<Network>> {
+        info!("Scanning for available networks...");
+
+        // Signal as RSSI in dBm instead of bars
+        let output = self.station(&["get-networks", "rssi-dbms"])?;
+
+        let mut networks = parse_table(&output)
+            .into_iter()
+            .filter_map(|columns| {
+                let mut columns = columns.into_iter().rev();
+                let rssi = columns.next()?.parse::<i32>().unwrap_or(-100);
+                let security = columns.next()?;
+                let ssid = columns.rev().collect::<Vec<_>>().join(" ");
+
+                Some(Network::new(
+                    ssid.as_bytes(),
+                    iwd_security_to_network_security(&security),
+                    rssi_to_percent(rssi),
+                ))
+            })
+            .filter(|network| !network.ssid.is_empty() && network.ssid != self.portal_ssid)
+            .collect::<Vec<_>>();
+
+        networks.dedup_by(|a, b| a.ssid == b.ssid);
+
+        self.networks = networks.clone();
+
+        Ok(networks)
+    }
+
+    fn connect(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) -> Result<()> {
+        // iwctl only takes SSIDs as text, so hex-encoded ones are decoded
+        let decoded = match String::from_utf8(parse_ssid(&network.ssid)?) {
+            Ok(decoded) => decoded,
+            Err(_) => bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "SSIDs that are not valid UTF-8".into()
+            )),
+        };
+        let ssid = &decoded;
+
+        let security = match self.networks.iter().find(|n| n.ssid == *ssid) {
+            Some(visible) => visible.security.clone(),
+            None => bail!(ErrorKind::NetworkNotFound(ssid.clone())),
+        };
+
+        if security == "enterprise" {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "enterprise networks".into()
+            ));
+        }
+
+        // Power saving is switched on the device once connected instead
+        let unsupported = ProfileSettings {
+            powersave: None,
+            ..settings.clone()
+        };
+
+        if !unsupported.is_empty() || settings.has_custom_eap() {
+            warn!("Connection profile settings are not supported by the iwd backend");
+        }
+
+        info!("Connecting to access point '{}'...", ssid);
+
+        // iwd reads the passphrase from the known network, as passing it to
+        // iwctl would show it in the process list. The file is put back as
+        // it was should the network not come up.
+        let known_network = if security == "none" {
+            None
+        } else {
+            let path = known_network_path(ssid, "psk");
+            let previous = fs::read(&path).ok();
+            let contents = psk_contents(ssid, network.passphrase.expose())?;
+            write_known_network(ssid, &path, &contents)?;
+
+            if !wait_for_known_network(ssid, &self.shutdown) {
+                warn!("iwd has not picked up the known network '{}' yet", ssid);
+            }
+
+            Some((path, previous))
+        };
+
+        let connected = iwctl(&["station", self.interface.as_str(), "connect", ssid.as_str()]);
+
+        if let Err(e) = connected {
+            if let Some((path, previous)) = known_network {
+                let restored = match previous {
+                    Some(previous) => write_private_file(&path, &previous),
+                    None => fs::remove_file(&path),
+                };
+
+                if let Err(e) = restored {
+                    warn!("Restoring {} failed: {}", path.display(), e);
+                }
+            }
+
+            return Err(e).chain_err(|| {
+                ErrorKind::ConnectionNotActivated(ssid.clone(), "iwctl connect".into())
+            });
+        }
+
+        if let Some(enabled) = settings.powersave {
+            if let Err(e) = set_power_save(&self.interface, enabled) {
+                warn!("{}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_network(
+        &mut self,
+        network: &NetworkCredentials,
+        _settings: &ProfileSettings,
+    ) -> Result<()> {
+        let (extension, contents) = if network.passphrase.is_empty() {
+            ("open", String::new())
+        } else {
+            ("psk", psk_contents(&network.ssid, network.passphrase.expose())?)
+        };
+
+        let path = known_network_path(&network.ssid, extension);
+
+        info!("Saving network '{}' for later use", network.ssid);
+
+        write_known_network(&network.ssid, &path, &contents)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        self.station(&["disconnect"]).map(|_| ())
+    }
+
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool> {
+        let mut total_time = 0;
+
+        // iwd itself has no connectivity check, so wait for an address,
+        // which may be IPv6 only
+        loop {
+            if get_interface_address(&self.interface).is_some() {
+                return Ok(true);
+            } else if total_time >= timeout {
+                return Ok(false);
+            }
+
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                return Ok(false);
+            }
+
+            total_time += 1;
+        }
+    }
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        let output = self.station(&["show"])?;
+
+        let mut ssid = None;
+        let mut security = "none".to_string();
+        let mut signal_strength = 0;
+
+        for line in output.lines() {
+            let line = line.trim();
+
+            if let Some(value) = line.strip_prefix("Connected network") {
+                ssid = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("Security") {
+                security = iwd_security_to_network_security(value.trim()).to_string();
+            } else if let Some(value) = line.strip_prefix("RSSI") {
+                let rssi = value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|rssi| rssi.parse::<i32>().ok())
+                    .unwrap_or(-100);
+                signal_strength = rssi_to_percent(rssi);
+            }
+        }
+
+        Ok(ssid.map(|ssid| ConnectedNetwork {
+            ssid,
+            security,
+            signal_strength,
+            interface: self.interface.clone(),
+            ip_address: get_interface_ipv4(&self.interface),
+            ipv6_addresses: get_interface_ipv6(&self.interface),
+            // Only NetworkManager keeps IP config objects
+            ipv4: IpConfig::default(),
+            ipv6: IpConfig::default(),
+            link: get_link_statistics(&self.interface),
+            vpn: None,
+        }))
+    }
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        let mut saved_networks = Vec::new();
+
+        for entry in fs::read_dir(IWD_STATE_DIRECTORY)? {
+            let path = entry?.path();
+
+            let (stem, extension) = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(extension)) => (stem.to_string_lossy(), extension.to_string_lossy()),
+                _ => continue,
+            };
+
+            let security = match extension.as_ref() {
+                "psk" => "wpa",
+                "8021x" => "enterprise",
+                "open" => "none",
+                _ => continue,
+            };
+
+            if let Some(ssid) = decode_known_network_name(&stem) {
+                saved_networks.push(SavedNetwork {
+                    ssid,
+                    security: security.to_string(),
+                });
+            }
+        }
+
+        saved_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+        Ok(saved_networks)
+    }
+
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        let known = [("psk", "wpa"), ("8021x", "enterprise"), ("open", "none")]
+            .iter()
+            .map(|&(extension, security)| (known_network_path(ssid, extension), security))
+            .find(|&(ref path, _)| path.exists());
+
+        let (path, security) = match known {
+            Some(known) => known,
+            None => return Ok(None),
+        };
+
+        let contents = fs::read_to_string(&path)?;
+
+        let setting = |name: &str| {
+            let prefix = format!("{}=", name);
+
+            contents
+                .lines()
+                .map(|line| line.trim())
+                .find(|line| line.starts_with(&prefix))
+                .map(|line| line[prefix.len()..].trim().to_string())
+        };
+
+        // iwd configures addresses through DHCP unless an address is given
+        let ipv4_method = match setting("Address") {
+            Some(_) => "manual",
+            None => "auto",
+        };
+
+        Ok(Some(SavedNetworkDetails {
+            ssid: ssid.to_string(),
+            security: security.to_string(),
+            autoconnect: setting("AutoConnect").map_or(true, |value| value != "false"),
+            ipv4_method: Some(ipv4_method.to_string()),
+            dns: setting("DNS")
+                .map(|servers| servers.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            ..Default::default()
+        }))
+    }
+
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool> {
+        if let Err(reason) = update.validate() {
+            bail!(ErrorKind::InvalidProfileUpdate(reason));
+        }
+
+        // Only autoconnect is a property of known networks, the rest of the
+        // settings live in files iwd owns
+        let autoconnect = match *update {
+            ProfileUpdate {
+                passphrase: None,
+                autoconnect_priority: None,
+                autoconnect: Some(autoconnect),
+                address: None,
+                gateway: None,
+                ref dns,
+                dhcp: false,
+            } if dns.is_empty() => autoconnect,
+            _ => bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "changing settings of saved networks other than autoconnect".into()
+            )),
+        };
+
+        if !self.saved_networks()?.iter().any(|saved| saved.ssid == ssid) {
+            warn!("Network '{}' not found in saved connections", ssid);
+            return Ok(false);
+        }
+
+        info!("Updating WiFi network: {}", ssid);
+
+        let value = if autoconnect { "yes" } else { "no" };
+        iwctl(&["known-networks", ssid, "set-property", "AutoConnect", value])?;
+
+        Ok(true)
+    }
+
+    fn forget_network(&self, ssid: &str) -> Result<bool> {
+        if !self.saved_networks()?.iter().any(|saved| saved.ssid == ssid) {
+            warn!("Network '{}' not found in saved connections", ssid);
+            return Ok(false);
+        }
+
+        info!("Forgetting WiFi network: {}", ssid);
+        iwctl(&["known-networks", ssid, "forget"])?;
+
+        Ok(true)
+    }
+
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()> {
+        info!("Forgetting all WiFi connections...");
+
+        for ssid in self.forgettable_networks(filter)? {
+            info!("Deleting WiFi connection: {}", ssid);
+
+            if let Err(e) = iwctl(&["known-networks", ssid.as_str(), "forget"]) {
+                error!("Deleting WiFi connection failed: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Known networks of iwd are named after their SSID and carry no data
+    // telling who created them
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>> {
+        bail!(ErrorKind::UnsupportedByBackend(
+            "iwd".into(),
+            "telling apart networks saved by WiFi Connect".into()
+        ))
+    }
+
+    fn forget_managed(&self) -> Result<usize> {
+        bail!(ErrorKind::UnsupportedByBackend(
+            "iwd".into(),
+            "telling apart networks saved by WiFi Connect".into()
+        ))
+    }
+
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>> {
+        let ssids: Vec<String> = match scope {
+            ForgetScope::All(filter) => self.forgettable_networks(filter)?,
+            ForgetScope::Network(ssid) => self
+                .saved_networks()?
+                .into_iter()
+                .map(|saved| saved.ssid)
+                .filter(|saved| saved == ssid)
+                .collect(),
+            ForgetScope::Managed => bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "telling apart networks saved by WiFi Connect".into()
+            )),
+            // Access points of iwd are not saved as known networks
+            ForgetScope::Portal(_) => Vec::new(),
+        };
+
+        Ok(ssids
+            .into_iter()
+            .map(|ssid| format!("known network {}", ssid))
+            .collect())
+    }
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()> {
+        if require_hotspot_mode(&self.interface, config)? == HotspotMode::WiFiDirect {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "the WiFi Direct fallback".into()
+            ));
+        }
+
+        if config.portal_channel.is_some() {
+            warn!("Setting the access point channel is not supported by the iwd backend");
+        }
+
+        if config.portal_band.is_some() {
+            warn!("Setting the access point band is not supported by the iwd backend");
+        }
+
+        if config.client_isolation {
+            warn!("Client isolation is not supported by the iwd backend");
+        }
+
+        if config.portal_owe {
+            warn!("Enhanced Open (OWE) is not supported by the iwd backend");
+        }
+
+        info!("Starting access point...");
+
+        let passphrase = match config.passphrase {
+            Some(ref passphrase) => passphrase.expose(),
+            None => bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "open access points".into()
+            )),
+        };
+
+        // Started from a profile holding the passphrase, as passing it to
+        // iwctl would show it in the process list
+        if config.ssid.contains('/') {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "iwd".into(),
+                "access point SSIDs containing '/'".into()
+            ));
+        }
+
+        let directory = PathBuf::from(IWD_STATE_DIRECTORY).join("ap");
+        let profile = directory.join(format!("{}.ap", config.ssid));
+
+        fs::create_dir_all(&directory).chain_err(|| ErrorKind::CreateCaptivePortal)?;
+        let contents = psk_contents(&config.ssid, passphrase)?;
+        write_known_network(&config.ssid, &profile, &contents)
+            .chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+        self.set_mode("ap").chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+        iwctl(&["ap", self.interface.as_str(), "start-profile", config.ssid.as_str()])
+            .chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+        // iwd does not assign the gateway address used by dnsmasq
+        let address = format!("{}/24", config.gateway);
+        let _ = Command::new("ip")
+            .args(&["addr", "add", address.as_str(), "dev", self.interface.as_str()])
+            .status();
+
+        info!("Access point '{}' created", config.ssid);
+        Ok(())
+    }
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()> {
+        info!("Stopping access point '{}'...", config.ssid);
+
+        iwctl(&["ap", self.interface.as_str(), "stop"])
+            .chain_err(|| ErrorKind::StopAccessPoint)?;
+
+        let address = format!("{}/24", config.gateway);
+        let _ = Command::new("ip")
+            .args(&["addr", "del", address.as_str(), "dev", self.interface.as_str()])
+            .status();
+
+        self.set_mode("station").chain_err(|| ErrorKind::StopAccessPoint)?;
+
+        info!("Access point '{}' stopped", config.ssid);
+        Ok(())
+    }
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool {
+        let output = match iwctl(&["ap", self.interface.as_str(), "show"]) {
+            Ok(output) => output,
+            Err(_) => return false,
+        };
+
+        let started = output
+            .lines()
+            .any(|line| line.trim().starts_with("Started") && line.trim().ends_with("yes"));
+        let same_ssid = output.lines().any(|line| {
+            line.trim().starts_with("Name") && line.trim()["Name".len()..].trim() == ssid
+        });
+
+        started && same_ssid
+    }
+
+    fn install_passpoint(&self, profile: &PasspointProfile) -> Result<()> {
+        let directory = PathBuf::from(IWD_STATE_DIRECTORY).join("hotspot");
+        let path = directory.join(format!("{}.conf", profile.name));
+
+        let mut contents = format!(
+            "[Hotspot]\nName={}\nNAIRealmNames={}\n",
+            profile.name, profile.realm
+        );
+
+        if let Some(ref domain) = profile.domain {
+            contents.push_str(&format!("Domain={}\n", domain));
+        }
+
+        if !profile.roaming_consortium.is_empty() {
+            contents.push_str(&format!(
+                "RoamingConsortium={}\n",
+                profile.roaming_consortium.join(",")
+            ));
+        }
+
+        // The outer identity is anonymous, the tunnel carries the real one
+        let eap = profile.eap.to_uppercase();
+        contents.push_str(&format!(
+            "\n[Security]\nEAP-Method={eap}\nEAP-Identity=anonymous@{realm}\n\
+             EAP-{eap}-Phase2-Method=MSCHAPV2\nEAP-{eap}-Phase2-Identity={identity}\n\
+             EAP-{eap}-Phase2-Password={password}\n",
+            eap = eap,
+            realm = profile.realm,
+            identity = profile.identity,
+            password = profile.passphrase.expose()
+        ));
+
+        if let Some(ref ca_cert) = profile.ca_cert {
+            contents.push_str(&format!("EAP-{}-CACert={}\n", eap, ca_cert.display()));
+        }
+
+        info!("Installing Passpoint profile '{}'", profile.name);
+
+        let error = || ErrorKind::Passpoint(profile.name.clone());
+
+        fs::create_dir_all(&directory).chain_err(error)?;
+        fs::write(&path, contents).chain_err(error)
+    }
+}
+
+fn psk_contents(ssid: &str, passphrase: &str) -> Result<String> {
+    if let Err(reason) = validate_secret(passphrase) {
+        bail!(ErrorKind::InvalidSecret(ssid.to_string(), reason));
+    }
+
+    Ok(format!("[Security]\nPassphrase={}\n", passphrase))
+}
+
+/// Writes a known network or access point profile readable by root only, as
+/// it holds the passphrase in the clear
+fn write_known_network(ssid: &str, path: &Path, contents: &str) -> Result<()> {
+    write_private_file(path, contents.as_bytes())
+        .chain_err(|| ErrorKind::AddConnection(ssid.to_string()))
+}
+
+fn write_private_file(path: &Path, contents: &[u8]) -> ::std::io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+fn wait_for_known_network(ssid: &str, shutdown: &Shutdown) -> bool {
+    for _ in 0..KNOWN_NETWORK_TIMEOUT {
+        if iwctl(&["known-networks", ssid, "show"]).is_ok() {
+            return true;
+        }
+
+        if shutdown.sleep(Duration::from_secs(1)) {
+            break;
+        }
+    }
+
+    false
+}
+
+fn iwctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("iwctl")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::Iwctl(args.join(" ")))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::Iwctl(args.join(" ")));
+    }
+
+    Ok(strip_ansi_escapes(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the control sequence up to its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+/// Rows of an `iwctl` table, which follow the header framed by dashed lines
+fn parse_table(output: &str) -> Vec<Vec<String>> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .map(|line| {
+            line.split_whitespace()
+                .filter(|column| *column != ">")
+                .map(|column| column.to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|columns| columns.len() >= 3)
+        .collect()
+}
+
+fn iwd_security_to_network_security(security: &str) -> &'static str {
+    match security {
+        "psk" => "wpa",
+        "8021x" => "enterprise",
+        "wep" => "wep",
+        _ => "none",
+    }
+}
+
+/// iwd stores networks with plain alphanumeric SSIDs by name and everything
+/// else hex encoded with a `=` prefix
+fn known_network_path(ssid: &str, extension: &str) -> PathBuf {
+    let plain = ssid
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ');
+
+    let name = if plain {
+        ssid.to_string()
+    } else {
+        let hex = ssid
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        format!("={}", hex)
+    };
+
+    PathBuf::from(IWD_STATE_DIRECTORY).join(format!("{}.{}", name, extension))
+}
+
+fn decode_known_network_name(name: &str) -> Option<String> {
+    if !name.starts_with('=') {
+        return Some(name.to_string());
+    }
+
+    let hex = &name[1..];
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,361 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json;
+
+use backend::{Backend, ForgetFilter, ForgetScope, MOCK_BACKEND};
+use config::Config;
+use errors::*;
+use exit::Shutdown;
+use ipconfig::IpConfig;
+use link::LinkStatistics;
+use network::{ConnectedNetwork, Network, NetworkCredentials, SavedNetwork, SavedNetworkDetails};
+use profile::{ProfileSettings, ProfileUpdate};
+
+const MOCK_INTERFACE: &str = "mock0";
+const MOCK_IP_ADDRESS: &str = "10.0.0.2";
+const MOCK_GATEWAY: &str = "10.0.0.1";
+const MOCK_BITRATE: f64 = 72.2;
+const MOCK_TX_POWER: f64 = 20.0;
+const MOCK_FREQUENCY: u32 = 2437;
+
+#[derive(Clone, Deserialize)]
+struct MockNetwork {
+    ssid: String,
+    #[serde(default = "default_security")]
+    security: String,
+    #[serde(default = "default_signal")]
+    signal: u8,
+    /// Connecting fails with an authentication failure unless this one is given
+    #[serde(default)]
+    passphrase: Option<String>,
+    /// Connecting always fails with this reason. `association-timeout` and
+    /// `dhcp-failed` simulate the matching failures.
+    #[serde(default)]
+    fail: Option<String>,
+}
+
+/// Scan results and connection outcomes loaded from `--mock-script`
+#[derive(Deserialize)]
+struct MockScript {
+    #[serde(default)]
+    networks: Vec<MockNetwork>,
+    /// Seconds a connection attempt takes
+    #[serde(default)]
+    connect_delay: u64,
+    /// Whether Internet connectivity is reported once connected
+    #[serde(default = "default_connectivity")]
+    connectivity: bool,
+}
+
+impl Default for MockScript {
+    fn default() -> Self {
+        let network = |ssid: &str, security: &str, signal: u8, passphrase: Option<&str>| MockNetwork {
+            ssid: ssid.to_string(),
+            security: security.to_string(),
+            signal,
+            passphrase: passphrase.map(String::from),
+            fail: None,
+        };
+
+        MockScript {
+            networks: vec![
+                network("Home", "wpa", 82, Some("password")),
+                network("Office", "enterprise", 64, None),
+                network("Cafe", "none", 41, None),
+            ],
+            connect_delay: 2,
+            connectivity: true,
+        }
+    }
+}
+
+fn default_security() -> String {
+    "wpa".into()
+}
+
+fn default_signal() -> u8 {
+    50
+}
+
+fn default_connectivity() -> bool {
+    true
+}
+
+/// Simulated WiFi device for developing and testing the portal, the HTTP API
+/// and the CLI without WiFi hardware or root
+pub struct MockBackend {
+    interface: String,
+    portal_ssid: String,
+    script: MockScript,
+    saved: RefCell<Vec<SavedNetwork>>,
+    connected: Option<MockNetwork>,
+    hotspot: bool,
+    shutdown: Shutdown,
+}
+
+impl MockBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let script = match config.mock_script {
+            Some(ref path) => load_script(path)?,
+            None => MockScript::default(),
+        };
+
+        let interface = config
+            .interface
+            .clone()
+            .unwrap_or_else(|| MOCK_INTERFACE.to_string());
+
+        info!("WiFi device: {} (simulated)", interface);
+
+        Ok(MockBackend {
+            interface,
+            portal_ssid: config.ssid.clone(),
+            script,
+            saved: RefCell::new(Vec::new()),
+            connected: None,
+            hotspot: false,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    fn find_network(&self, ssid: &str) -> Option<&MockNetwork> {
+        self.script.networks.iter().find(|network| network.ssid == ssid)
+    }
+
+    fn remember(&self, ssid: &str, security: String) {
+        let mut saved = self.saved.borrow_mut();
+        saved.retain(|saved| saved.ssid != ssid);
+        saved.push(SavedNetwork {
+            ssid: ssid.to_string(),
+            security,
+        });
+    }
+}
+
+impl Backend for MockBackend {
+    fn name(&self) -> &'static str {
+        MOCK_BACKEND
+    }
+
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    fn uses_dnsmasq(&self) -> bool {
+        false
+    }
+
+    fn request_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<Vec<Network>> {
+        let portal_ssid = &self.portal_ssid;
+
+        Ok(self
+            .script
+            .networks
+            .iter()
+            .filter(|network| network.ssid != *portal_ssid)
+            .map(|network| {
+                Network::new(network.ssid.as_bytes(), &network.security, network.signal)
+            })
+            .collect())
+    }
+
+    fn connect(&mut self, network: &NetworkCredentials, _settings: &ProfileSettings) -> Result<()> {
+        let ssid = &network.ssid;
+
+        let mock_network = match self.find_network(ssid) {
+            Some(mock_network) => mock_network.clone(),
+            None => bail!(ErrorKind::NetworkNotFound(ssid.clone())),
+        };
+
+        info!("Connecting to access point '{}' (simulated)...", ssid);
+
+        self.shutdown.sleep(Duration::from_secs(self.script.connect_delay));
+
+        if let Some(reason) = mock_network.fail.clone() {
+            match reason.as_str() {
+                "association-timeout" => bail!(ErrorKind::AssociationTimeout(ssid.clone())),
+                "dhcp-failed" => bail!(ErrorKind::DhcpFailed(ssid.clone())),
+                _ => bail!(ErrorKind::ConnectionNotActivated(ssid.clone(), reason)),
+            }
+        }
+
+        if let Some(ref passphrase) = mock_network.passphrase {
+            if passphrase != network.passphrase.expose() {
+                bail!(ErrorKind::AuthenticationFailed(ssid.clone()));
+            }
+        }
+
+        let security = mock_network.security.clone();
+        self.remember(ssid, security);
+        self.connected = Some(mock_network);
+
+        Ok(())
+    }
+
+    fn save_network(
+        &mut self,
+        network: &NetworkCredentials,
+        _settings: &ProfileSettings,
+    ) -> Result<()> {
+        let security = match self.find_network(&network.ssid) {
+            Some(mock_network) => mock_network.security.clone(),
+            None => default_security(),
+        };
+
+        self.remember(&network.ssid, security);
+
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        self.connected = None;
+        Ok(())
+    }
+
+    fn wait_for_connectivity(&self, _timeout: u64) -> Result<bool> {
+        Ok(self.connected.is_some() && self.script.connectivity)
+    }
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        Ok(self.connected.as_ref().map(|network| ConnectedNetwork {
+            ssid: network.ssid.clone(),
+            security: network.security.clone(),
+            signal_strength: network.signal,
+            interface: self.interface.clone(),
+            ip_address: Some(MOCK_IP_ADDRESS.to_string()),
+            ipv6_addresses: Vec::new(),
+            ipv4: IpConfig {
+                addresses: vec![format!("{}/24", MOCK_IP_ADDRESS)],
+                gateways: vec![MOCK_GATEWAY.to_string()],
+                dns: vec![MOCK_GATEWAY.to_string()],
+            },
+            ipv6: IpConfig::default(),
+            link: LinkStatistics {
+                rx_bitrate: Some(MOCK_BITRATE),
+                tx_bitrate: Some(MOCK_BITRATE),
+                tx_power: Some(MOCK_TX_POWER),
+                frequency: Some(MOCK_FREQUENCY),
+                // Inverse of the percentage mapping of real backends
+                signal: Some(i32::from(network.signal) / 2 - 100),
+                tx_retries: Some(0),
+                tx_failed: Some(0),
+            },
+            vpn: None,
+        }))
+    }
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        Ok(self
+            .saved
+            .borrow()
+            .iter()
+            .map(|saved| SavedNetwork {
+                ssid: saved.ssid.clone(),
+                security: saved.security.clone(),
+            })
+            .collect())
+    }
+
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        Ok(self
+            .saved
+            .borrow()
+            .iter()
+            .find(|saved| saved.ssid == ssid)
+            .map(|saved| SavedNetworkDetails {
+                ssid: saved.ssid.clone(),
+                security: saved.security.clone(),
+                autoconnect: true,
+                ..Default::default()
+            }))
+    }
+
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool> {
+        if let Err(reason) = update.validate() {
+            bail!(ErrorKind::InvalidProfileUpdate(reason));
+        }
+
+        let found = self.saved.borrow().iter().any(|saved| saved.ssid == ssid);
+
+        if found {
+            info!("Updating WiFi network '{}' (simulated)", ssid);
+        }
+
+        Ok(found)
+    }
+
+    fn forget_network(&self, ssid: &str) -> Result<bool> {
+        let mut saved = self.saved.borrow_mut();
+        let count = saved.len();
+        saved.retain(|saved| saved.ssid != ssid);
+        Ok(saved.len() != count)
+    }
+
+    // Simulated networks have never been used, so only --except keeps any
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()> {
+        self.saved
+            .borrow_mut()
+            .retain(|saved| filter.keeps_ssid(saved.ssid.as_bytes()));
+        Ok(())
+    }
+
+    // Networks are only ever saved by the simulation itself
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>> {
+        self.saved_networks()
+    }
+
+    fn forget_managed(&self) -> Result<usize> {
+        let mut saved = self.saved.borrow_mut();
+        let count = saved.len();
+        saved.clear();
+        Ok(count)
+    }
+
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>> {
+        Ok(self
+            .saved
+            .borrow()
+            .iter()
+            .filter(|saved| match scope {
+                ForgetScope::All(filter) => !filter.keeps_ssid(saved.ssid.as_bytes()),
+                ForgetScope::Managed => true,
+                ForgetScope::Network(ssid) => saved.ssid == ssid,
+                ForgetScope::Portal(_) => false,
+            })
+            .map(|saved| format!("simulated network {}", saved.ssid))
+            .collect())
+    }
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()> {
+        info!("Starting access point '{}' (simulated)", config.ssid);
+        self.hotspot = true;
+        Ok(())
+    }
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()> {
+        info!("Stopping access point '{}' (simulated)", config.ssid);
+        self.hotspot = false;
+        Ok(())
+    }
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool {
+        self.hotspot && ssid == self.portal_ssid
+    }
+}
+
+fn load_script(path: &Path) -> Result<MockScript> {
+    let file = File::open(path).chain_err(|| ErrorKind::MockScript(path.display().to_string()))?;
+
+    serde_json::from_reader(file).chain_err(|| ErrorKind::MockScript(path.display().to_string()))
+}
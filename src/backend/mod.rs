@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "network-manager")]
+use network_manager::NetworkManager;
+
+use config::Config;
+use errors::*;
+use exit::Shutdown;
+use network::{ConnectedNetwork, Network, NetworkCredentials, SavedNetwork, SavedNetworkDetails};
+#[cfg(feature = "network-manager")]
+use nm::list_wifi_interfaces;
+use passpoint::PasspointProfile;
+use profile::{ProfileSettings, ProfileUpdate};
+use ssid::matches_ssid;
+use wiphy::{list_wireless_interfaces, WiFiInterface};
+
+mod iwd;
+mod mock;
+#[cfg(feature = "network-manager")]
+mod nm;
+#[cfg(not(feature = "network-manager"))]
+mod nm_dbus;
+mod openwrt;
+
+pub use self::iwd::IwdBackend;
+pub use self::mock::MockBackend;
+#[cfg(feature = "network-manager")]
+pub use self::nm::NetworkManagerBackend;
+#[cfg(not(feature = "network-manager"))]
+pub use self::nm_dbus::NetworkManagerDbusBackend;
+pub use self::openwrt::OpenWrtBackend;
+
+pub const BACKENDS: &[&str] = &[DEFAULT_BACKEND, IWD_BACKEND, OPENWRT_BACKEND, MOCK_BACKEND];
+pub const DEFAULT_BACKEND: &str = "network-manager";
+pub const IWD_BACKEND: &str = "iwd";
+pub const OPENWRT_BACKEND: &str = "openwrt";
+
+/// Simulation backend, the only one available outside Linux
+pub const MOCK_BACKEND: &str = "mock";
+
+/// Milliseconds between checks whether a requested scan has completed
+const SCAN_POLL_INTERVAL: u64 = 250;
+
+/// Network seen in a scan across several interfaces, with the interfaces
+/// that saw it
+#[derive(Clone, Debug, Serialize)]
+pub struct ScannedNetwork {
+    #[serde(flatten)]
+    pub network: Network,
+    pub interfaces: Vec<String>,
+}
+
+/// Restricts `--forget-all` to some of the saved networks
+#[derive(Clone, Debug, Default)]
+pub struct ForgetFilter {
+    /// SSIDs of networks to keep, from `--except`
+    pub except: Vec<String>,
+    /// Only forget networks not used for this many days, from `--older-than`
+    pub older_than: Option<u64>,
+}
+
+impl ForgetFilter {
+    pub fn keeps_ssid(&self, ssid: &[u8]) -> bool {
+        self.except.iter().any(|except| matches_ssid(ssid, except))
+    }
+
+    /// Whether a network last used at the given Unix time is old enough to
+    /// be forgotten. Networks never used count as old.
+    pub fn is_stale(&self, last_used: Option<u64>) -> bool {
+        let (older_than, last_used) = match (self.older_than, last_used) {
+            (Some(older_than), Some(last_used)) => (older_than, last_used),
+            _ => return true,
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        now.saturating_sub(last_used) >= older_than * 24 * 60 * 60
+    }
+}
+
+/// Which saved networks `--gc-connections` prunes: those never used, or not
+/// used for `older_than` days, except for the `keep` used most recently
+#[derive(Clone, Debug, Default)]
+pub struct GcPolicy {
+    pub keep: usize,
+    pub older_than: Option<u64>,
+}
+
+impl GcPolicy {
+    /// Whether a network last used at the given Unix time is pruned, unless
+    /// it is among the ones kept
+    pub fn is_stale(&self, last_used: Option<u64>) -> bool {
+        match self.older_than {
+            Some(older_than) => ForgetFilter {
+                except: Vec::new(),
+                older_than: Some(older_than),
+            }
+            .is_stale(last_used),
+            None => last_used.is_none(),
+        }
+    }
+}
+
+/// Saved profiles a destructive command deletes, to list them in a dry run
+#[derive(Clone, Copy, Debug)]
+pub enum ForgetScope<'a> {
+    All(&'a ForgetFilter),
+    Network(&'a str),
+    Managed,
+    /// Access point profiles of the portal with the given SSID
+    Portal(&'a str),
+}
+
+/// Operations WiFi Connect needs from the system's WiFi management daemon
+pub trait Backend {
+    fn name(&self) -> &'static str;
+
+    /// Makes scans, connection attempts and connectivity checks give up
+    /// early once shutdown is triggered
+    fn set_shutdown(&mut self, shutdown: Shutdown);
+
+    /// Interface of the WiFi device managed by the backend
+    fn interface(&self) -> &str;
+
+    /// Whether DHCP and DNS for the hotspot have to be served by dnsmasq
+    fn uses_dnsmasq(&self) -> bool {
+        true
+    }
+
+    fn request_scan(&self) -> Result<()>;
+
+    /// Whether the scan started by `request_scan` is still running. Backends
+    /// scanning synchronously are done once `request_scan` returns.
+    fn is_scanning(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Visible networks, excluding the captive portal's own SSID
+    fn scan(&mut self) -> Result<Vec<Network>>;
+
+    /// Connects to a network from the last scan and returns once it is
+    /// activated, or with the reason it was not
+    fn connect(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) -> Result<()>;
+
+    /// Stores a network for later use without connecting to it
+    fn save_network(&mut self, network: &NetworkCredentials, settings: &ProfileSettings)
+        -> Result<()>;
+
+    fn disconnect(&mut self) -> Result<()>;
+
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool>;
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>>;
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>>;
+
+    /// Settings of the saved profile of a network, if there is one
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>>;
+
+    /// Changes the saved profile of a network, returning whether there is one
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool>;
+
+    fn forget_network(&self, ssid: &str) -> Result<bool>;
+
+    /// Forgets the saved networks the filter does not keep
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()>;
+
+    /// Saved networks whose profiles were created by WiFi Connect
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>>;
+
+    /// Forgets the networks saved by WiFi Connect, returning how many
+    fn forget_managed(&self) -> Result<usize>;
+
+    /// Describes the saved profiles in the scope without touching them
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>>;
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()>;
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()>;
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool;
+
+    /// Deletes the saved networks the policy prunes, returning how many
+    fn gc_connections(&self, _policy: &GcPolicy) -> Result<usize> {
+        bail!(ErrorKind::UnsupportedByBackend(
+            self.name().into(),
+            "pruning saved networks".into()
+        ))
+    }
+
+    /// Installs a Passpoint profile, so that the networks of its operator and
+    /// roaming partners are joined automatically
+    fn install_passpoint(&self, _profile: &PasspointProfile) -> Result<()> {
+        bail!(ErrorKind::UnsupportedByBackend(
+            self.name().into(),
+            "Passpoint profiles".into()
+        ))
+    }
+}
+
+pub fn init_backend(config: &Config) -> Result<Box<dyn Backend>> {
+    // NetworkManager, iwd and netifd are Linux services, elsewhere only the portal,
+    // the UI and the command line can be tried out against the simulation
+    if !cfg!(target_os = "linux") {
+        if config.backend != MOCK_BACKEND {
+            bail!(ErrorKind::UnsupportedByBackend(
+                config.backend.clone(),
+                "this operating system".into()
+            ));
+        }
+
+        warn!("Simulation only, no WiFi is managed outside Linux");
+    }
+
+    let backend: Box<dyn Backend> = match config.backend.as_str() {
+        IWD_BACKEND => Box::new(IwdBackend::new(config)?),
+        OPENWRT_BACKEND => Box::new(OpenWrtBackend::new(config)?),
+        MOCK_BACKEND => Box::new(MockBackend::new(config)?),
+        #[cfg(feature = "network-manager")]
+        _ => Box::new(NetworkManagerBackend::new(config)?),
+        #[cfg(not(feature = "network-manager"))]
+        _ => Box::new(NetworkManagerDbusBackend::new(config)?),
+    };
+
+    debug!("Using {} backend on {}", backend.name(), backend.interface());
+
+    Ok(backend)
+}
+
+/// Finds a wireless interface through sysfs, for backends that have no device
+/// enumeration of their own. Interfaces supporting access point mode that are
+/// not up are preferred.
+pub fn find_wireless_interface() -> Option<String> {
+    let mut interfaces = list_wireless_interfaces();
+
+    interfaces.sort_by_key(|interface| (interface.ap_mode == Some(false), interface.state == "up"));
+
+    interfaces.into_iter().next().map(|interface| interface.interface)
+}
+
+pub fn is_wireless_interface(interface: &str) -> bool {
+    Path::new("/sys/class/net")
+        .join(interface)
+        .join("wireless")
+        .exists()
+}
+
+/// WiFi interfaces as known to the selected backend
+pub fn list_interfaces(config: &Config) -> Result<Vec<WiFiInterface>> {
+    match config.backend.as_str() {
+        #[cfg(feature = "network-manager")]
+        DEFAULT_BACKEND => list_wifi_interfaces(&NetworkManager::new()),
+        _ => Ok(list_wireless_interfaces()),
+    }
+}
+
+/// Scans with all interfaces concurrently, each through a backend of its own
+/// in a separate thread, and merges the results by SSID. Interfaces failing
+/// to scan are skipped.
+pub fn scan_interfaces(config: &Config, interfaces: &[String]) -> Vec<ScannedNetwork> {
+    let handles = interfaces
+        .iter()
+        .map(|interface| {
+            let mut config = config.clone();
+            config.interface = Some(interface.clone());
+
+            let interface = interface.clone();
+            let handle = thread::spawn(move || scan_interface(&config));
+
+            (interface, handle)
+        })
+        .collect::<Vec<_>>();
+
+    let mut merged: Vec<ScannedNetwork> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for (interface, handle) in handles {
+        let networks = match handle.join() {
+            Ok(Ok(networks)) => networks,
+            Ok(Err(e)) => {
+                warn!("Scanning with {} failed: {}", interface, e);
+                continue;
+            }
+            Err(_) => {
+                warn!("Scanning with {} panicked", interface);
+                continue;
+            }
+        };
+
+        for network in networks {
+            match index.get(&network.ssid_hex) {
+                Some(&position) => merged[position].interfaces.push(interface.clone()),
+                None => {
+                    index.insert(network.ssid_hex.clone(), merged.len());
+                    merged.push(ScannedNetwork {
+                        network,
+                        interfaces: vec![interface.clone()],
+                    });
+                }
+            }
+        }
+    }
+
+    merged
+}
+
+fn scan_interface(config: &Config) -> Result<Vec<Network>> {
+    let mut backend = init_backend(config)?;
+
+    request_and_wait_for_scan(&*backend, config.scan_timeout, |duration| {
+        thread::sleep(duration);
+        false
+    });
+
+    backend.scan()
+}
+
+/// Requests a scan and waits for it to complete, for up to `timeout`
+/// seconds. `sleep` waits between the checks and returns whether to give up
+/// early, e.g. on shutdown, which is returned in turn.
+pub fn request_and_wait_for_scan<F>(backend: &dyn Backend, timeout: u64, mut sleep: F) -> bool
+where
+    F: FnMut(Duration) -> bool,
+{
+    if let Err(e) = backend.request_scan() {
+        warn!("Failed to request scan on {}: {}", backend.interface(), e);
+        return false;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    loop {
+        match backend.is_scanning() {
+            Ok(false) => return false,
+            Ok(true) => {}
+            Err(e) => {
+                warn!("Checking the scan on {} failed: {}", backend.interface(), e);
+                return false;
+            }
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            warn!(
+                "Scan on {} did not complete within {}s",
+                backend.interface(),
+                timeout
+            );
+            return false;
+        }
+
+        let interval = Duration::from_millis(SCAN_POLL_INTERVAL);
+        if sleep(interval.min(deadline - now)) {
+            return true;
+        }
+    }
+}
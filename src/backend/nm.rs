@@ -0,0 +1,367 @@
+use network_manager::{
+    AccessPoint, AccessPointCredentials, Connection, ConnectionState, Device, NetworkManager,
+};
+
+use std::cell::Cell;
+use std::path::PathBuf;
+
+use backend::{Backend, ForgetFilter, ForgetScope, GcPolicy};
+use certificates::eap_tls_certificates;
+use channels::PortalBand;
+use config::Config;
+use connections::with_connections;
+use dualband::{start_dual_band_portal, stop_dual_band_portal};
+use errors::*;
+use exit::Shutdown;
+use low_memory::is_low_memory;
+use network::{
+    connection_failure, get_device_state_reason, ConnectedNetwork, Network, NetworkCredentials,
+    SavedNetwork, SavedNetworkDetails,
+};
+use nm::{
+    add_network_profile, connect_enterprise, connect_to_access_point, connect_with_profile,
+    create_portal, deactivate_connection, delete_connection,
+    delete_existing_connections_to_same_network, describe_connections_in_scope,
+    disconnect_from_network, find_access_point, find_device, forget_all_wifi_connections,
+    forget_managed_connections, forget_specific_network, gc_wifi_connections, get_access_points,
+    get_connected_network, get_last_scan, get_managed_networks, get_networks_from_access_points,
+    get_saved_network_details, get_saved_networks, in_forget_scope, init_access_point_credentials,
+    is_access_point_connection, is_same_ssid, stop_portal, update_saved_network,
+    wait_for_connectivity,
+};
+use profile::{managed_connection_id, ProfileSettings, ProfileUpdate};
+use retry::retry_nm;
+use ssid::display_ssid;
+use wiphy::{require_hotspot_mode, start_p2p_group, stop_p2p_group, HotspotMode};
+
+pub struct NetworkManagerBackend {
+    manager: NetworkManager,
+    device: Device,
+    portal_ssid: String,
+    access_points: Vec<AccessPoint>,
+    portal_connection: Option<Connection>,
+    scan_timeout: u64,
+    /// `LastScan` of the device when a scan was requested, which changes
+    /// once the scan completes
+    requested_scan: Cell<Option<i64>>,
+    connect_timeout: u64,
+    certificate_directory: PathBuf,
+    /// Group interface when the portal runs as a WiFi Direct group owner
+    p2p_group: Option<String>,
+    shutdown: Shutdown,
+}
+
+impl NetworkManagerBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let manager = NetworkManager::new();
+        debug!("NetworkManager connection initialized");
+
+        let device = find_device(&manager, &config.interface)?;
+
+        Ok(NetworkManagerBackend {
+            manager,
+            device,
+            portal_ssid: config.ssid.clone(),
+            access_points: Vec::new(),
+            portal_connection: None,
+            scan_timeout: config.scan_timeout,
+            requested_scan: Cell::new(None),
+            connect_timeout: config.connect_timeout,
+            certificate_directory: config.certificate_directory.clone(),
+            p2p_group: None,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    fn interface_option(&self) -> Option<String> {
+        Some(self.device.interface().to_string())
+    }
+}
+
+impl Backend for NetworkManagerBackend {
+    fn name(&self) -> &'static str {
+        "network-manager"
+    }
+
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    fn interface(&self) -> &str {
+        match self.p2p_group {
+            Some(ref group) => group,
+            None => self.device.interface(),
+        }
+    }
+
+    fn request_scan(&self) -> Result<()> {
+        if let Some(wifi_device) = self.device.as_wifi_device() {
+            self.requested_scan
+                .set(get_last_scan(self.device.interface()));
+            wifi_device.request_scan()?;
+        }
+
+        Ok(())
+    }
+
+    fn is_scanning(&self) -> Result<bool> {
+        Ok(self
+            .requested_scan
+            .get()
+            .is_some_and(|requested| get_last_scan(self.device.interface()) == Some(requested)))
+    }
+
+    fn scan(&mut self) -> Result<Vec<Network>> {
+        let access_points = get_access_points(
+            &self.device,
+            &self.portal_ssid,
+            self.scan_timeout,
+            &self.shutdown,
+        )?;
+        let networks = get_networks_from_access_points(&access_points);
+
+        // Listed again when connecting with --low-memory
+        if !is_low_memory() {
+            self.access_points = access_points;
+        }
+
+        Ok(networks)
+    }
+
+    fn connect(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) -> Result<()> {
+        let ssid = &network.ssid;
+
+        delete_existing_connections_to_same_network(&self.manager, ssid);
+
+        let listed;
+        let access_points = if self.access_points.is_empty() {
+            listed = get_access_points(
+                &self.device,
+                &self.portal_ssid,
+                self.scan_timeout,
+                &self.shutdown,
+            )?;
+            &listed
+        } else {
+            &self.access_points
+        };
+
+        let access_point = match find_access_point(access_points, ssid) {
+            Some(access_point) => access_point,
+            None => bail!(ErrorKind::NetworkNotFound(ssid.clone())),
+        };
+
+        info!("Connecting to access point '{}'...", ssid);
+
+        let credentials =
+            init_access_point_credentials(access_point, &network.identity, network.passphrase.expose());
+
+        // Named so that the connection can be told apart from ones
+        // provisioned otherwise
+        let name = display_ssid(access_point.ssid().as_bytes());
+        let settings = ProfileSettings {
+            id: Some(managed_connection_id(&name)),
+            ..settings.for_network(self.device.interface(), &name)
+        };
+
+        if let Some(certificates) = eap_tls_certificates(&self.certificate_directory, ssid) {
+            info!("Authenticating to '{}' with uploaded certificates", ssid);
+
+            return connect_enterprise(
+                self.device.interface(),
+                network,
+                Some(&certificates),
+                &settings,
+                self.connect_timeout,
+            );
+        }
+
+        if let AccessPointCredentials::Enterprise { .. } = credentials {
+            if settings.has_custom_eap() {
+                return connect_enterprise(
+                    self.device.interface(),
+                    network,
+                    None,
+                    &settings,
+                    self.connect_timeout,
+                );
+            }
+        }
+
+        if settings.cloned_mac.is_some() {
+            return connect_with_profile(
+                self.device.interface(),
+                access_point,
+                network,
+                &settings,
+                self.connect_timeout,
+            );
+        }
+
+        let (connection, state) = connect_to_access_point(
+            &self.device,
+            access_point,
+            &credentials,
+            &settings,
+            self.connect_timeout,
+            &self.shutdown,
+        )?;
+
+        if state == ConnectionState::Activated {
+            return Ok(());
+        }
+
+        // Read before deleting the connection moves the device on
+        let reason = get_device_state_reason(self.device.interface());
+
+        if let Err(err) = delete_connection(&connection) {
+            error!("Deleting connection object failed: {}", err)
+        }
+
+        bail!(connection_failure(ssid, &format!("{:?}", state), reason))
+    }
+
+    fn save_network(
+        &mut self,
+        network: &NetworkCredentials,
+        settings: &ProfileSettings,
+    ) -> Result<()> {
+        delete_existing_connections_to_same_network(&self.manager, &network.ssid);
+
+        let certificates = eap_tls_certificates(&self.certificate_directory, &network.ssid);
+
+        // Networks out of range are saved all the same
+        let listed;
+        let access_points = if self.access_points.is_empty() {
+            listed = get_access_points(
+                &self.device,
+                &self.portal_ssid,
+                self.scan_timeout,
+                &self.shutdown,
+            )
+            .unwrap_or_default();
+            &listed
+        } else {
+            &self.access_points
+        };
+
+        add_network_profile(network, access_points, certificates.as_ref(), settings)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        disconnect_from_network(&self.manager, &self.interface_option())
+    }
+
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool> {
+        wait_for_connectivity(&self.manager, timeout, &self.shutdown)
+    }
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        get_connected_network(&self.manager, &self.interface_option())
+    }
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        get_saved_networks(&self.manager)
+    }
+
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        get_saved_network_details(&self.manager, ssid)
+    }
+
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool> {
+        update_saved_network(&self.manager, ssid, update)
+    }
+
+    fn forget_network(&self, ssid: &str) -> Result<bool> {
+        forget_specific_network(&self.manager, ssid)
+    }
+
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()> {
+        forget_all_wifi_connections(&self.manager, filter)
+    }
+
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>> {
+        get_managed_networks(&self.manager)
+    }
+
+    fn forget_managed(&self) -> Result<usize> {
+        forget_managed_connections(&self.manager)
+    }
+
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>> {
+        describe_connections_in_scope(&self.manager, scope)
+    }
+
+    fn gc_connections(&self, policy: &GcPolicy) -> Result<usize> {
+        gc_wifi_connections(&self.manager, policy)
+    }
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()> {
+        let interface = self.device.interface().to_string();
+
+        if require_hotspot_mode(&interface, config)? == HotspotMode::WiFiDirect {
+            self.p2p_group = Some(start_p2p_group(&interface, config)?);
+            return Ok(());
+        }
+
+        self.portal_connection = Some(create_portal(&self.device, config)?);
+
+        if config.portal_band == Some(PortalBand::Both) {
+            if let Err(e) = start_dual_band_portal(&interface, config) {
+                warn!("{}, serving 2.4 GHz only", e);
+                stop_dual_band_portal();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()> {
+        if config.portal_band == Some(PortalBand::Both) {
+            stop_dual_band_portal();
+        }
+
+        if let Some(group) = self.p2p_group.take() {
+            return stop_p2p_group(self.device.interface(), &group);
+        }
+
+        if let Some(connection) = self.portal_connection.take() {
+            return stop_portal(&connection, config);
+        }
+
+        // The hotspot may have been started by another WiFi Connect process
+        with_connections(&self.manager, |connections| {
+            for connection in connections {
+                if in_forget_scope(connection, ForgetScope::Portal(&config.ssid)) {
+                    info!("Deactivating hotspot connection");
+                    let _ = deactivate_connection(connection);
+                    let _ = delete_connection(connection);
+                }
+            }
+        })
+    }
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool {
+        if self.p2p_group.is_some() {
+            return true;
+        }
+
+        // Only the active connections, instead of the settings of every
+        // saved profile
+        let connections = retry_nm("Listing active connections", || {
+            self.manager.get_active_connections()
+        });
+
+        if let Ok(connections) = connections {
+            for connection in connections {
+                if is_access_point_connection(&connection) && is_same_ssid(&connection, ssid) {
+                    if let Ok(state) = connection.get_state() {
+                        return state == ConnectionState::Activated;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,1627 @@
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backend::{Backend, ForgetFilter, ForgetScope, GcPolicy};
+use certificates::{eap_tls_certificates, EapTls};
+use channels::{band_of_channel, resolve_portal_channel, Band, PortalBand};
+use config::Config;
+use dbus::{Connection, Value};
+use dualband::{start_dual_band_portal, stop_dual_band_portal};
+use errors::*;
+use exit::Shutdown;
+use ipconfig::get_ip_configs;
+use link::get_link_statistics;
+use low_memory::is_low_memory;
+use network::{
+    activate_profile, connection_failure, get_interface_ipv4, get_interface_ipv6,
+    probe_connectivity, ConnectedNetwork, Network, NetworkCredentials, SavedNetwork,
+    SavedNetworkDetails,
+};
+use profile::{
+    add_connection_profile, managed_connection_id, profile_security, update_connection_profile,
+    ProfileSettings, ProfileUpdate, MANAGED_ID_PREFIX, MANAGED_MARKER,
+};
+use retry::{retry_nm, retry_nm_change};
+use ssid::{display_ssid, matches_ssid};
+use wiphy::{require_hotspot_mode, start_p2p_group, stop_p2p_group, supports_ap_mode, HotspotMode};
+
+const NM_DESTINATION: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_INTERFACE: &str = "org.freedesktop.NetworkManager";
+const SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+const SETTINGS_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings";
+const PROFILE_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+const DEVICE_INTERFACE: &str = "org.freedesktop.NetworkManager.Device";
+const WIRELESS_INTERFACE: &str = "org.freedesktop.NetworkManager.Device.Wireless";
+const ACCESS_POINT_INTERFACE: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const ACTIVE_INTERFACE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+
+/// Object path standing for none, e.g. no specific access point
+const NO_OBJECT: &str = "/";
+
+const WIRELESS_SETTING: &str = "802-11-wireless";
+const SECURITY_SETTING: &str = "802-11-wireless-security";
+
+const DEVICE_TYPE_WIFI: u32 = 2;
+
+const DEVICE_STATE_UNMANAGED: u32 = 10;
+const DEVICE_STATE_ACTIVATED: u32 = 100;
+
+const ACTIVE_STATE_ACTIVATING: u32 = 1;
+const ACTIVE_STATE_ACTIVATED: u32 = 2;
+const ACTIVE_STATE_DEACTIVATING: u32 = 3;
+const ACTIVE_STATE_DEACTIVATED: u32 = 4;
+
+const CONNECTIVITY_UNKNOWN: u32 = 0;
+const CONNECTIVITY_LIMITED: u32 = 3;
+const CONNECTIVITY_FULL: u32 = 4;
+
+const AP_FLAGS_PRIVACY: u32 = 0x1;
+const AP_SEC_KEY_MGMT_PSK: u32 = 0x100;
+const AP_SEC_KEY_MGMT_802_1X: u32 = 0x200;
+const AP_SEC_KEY_MGMT_SAE: u32 = 0x400;
+const AP_SEC_KEY_MGMT_EAP_SUITE_B_192: u32 = 0x2000;
+
+/// Prefix length of the hotspot network, as with the network-manager crate
+const HOTSPOT_PREFIX: u32 = 24;
+
+/// Access point from a scan, as the object path NetworkManager connects to
+struct AccessPoint {
+    path: String,
+    ssid: Vec<u8>,
+    strength: u8,
+    security: &'static str,
+}
+
+/// Connection profile with the settings WiFi Connect looks at
+struct Profile {
+    path: String,
+    id: String,
+    uuid: String,
+    kind: String,
+    mode: String,
+    ssid: Vec<u8>,
+    settings: Value,
+}
+
+impl Profile {
+    fn is_wifi(&self) -> bool {
+        self.kind == WIRELESS_SETTING
+    }
+
+    fn is_access_point(&self) -> bool {
+        self.is_wifi() && self.mode == "ap"
+    }
+
+    fn is_same_ssid(&self, ssid: &str) -> bool {
+        matches_ssid(&self.ssid, ssid)
+    }
+
+    fn setting(&self, setting: &str, key: &str) -> Option<&Value> {
+        self.settings.get(setting)?.get(key)
+    }
+
+    fn key_mgmt(&self) -> Option<String> {
+        self.setting(SECURITY_SETTING, "key-mgmt")
+            .and_then(Value::as_str)
+            .map(String::from)
+    }
+
+    /// Unix time of the last activation, `None` if never activated
+    fn last_used(&self) -> Option<u64> {
+        self.setting("connection", "timestamp")
+            .and_then(Value::as_u64)
+            .filter(|&timestamp| timestamp != 0)
+    }
+
+    /// Created by WiFi Connect, recognized by the name and the marker in the
+    /// user data
+    fn is_managed(&self) -> bool {
+        self.is_wifi()
+            && !self.is_access_point()
+            && self.id.starts_with(MANAGED_ID_PREFIX)
+            && self
+                .setting("user", "data")
+                .and_then(|data| data.get(managed_data_key()))
+                .and_then(Value::as_str)
+                == Some("yes")
+    }
+
+    /// Whether a destructive command deletes the profile, shared by the
+    /// commands and their dry runs so that both agree
+    fn in_forget_scope(&self, scope: ForgetScope) -> bool {
+        match scope {
+            ForgetScope::All(filter) => {
+                self.is_wifi()
+                    && !filter.keeps_ssid(&self.ssid)
+                    && (filter.older_than.is_none() || filter.is_stale(self.last_used()))
+            }
+            ForgetScope::Network(ssid) => {
+                self.is_wifi() && !self.is_access_point() && self.is_same_ssid(ssid)
+            }
+            ForgetScope::Managed => self.is_managed(),
+            ForgetScope::Portal(ssid) => self.is_access_point() && self.is_same_ssid(ssid),
+        }
+    }
+}
+
+/// NetworkManager over the D-Bus client of WiFi Connect instead of libdbus,
+/// for builds without the `network-manager` feature like static musl
+/// binaries. Enterprise networks with uploaded certificates or a custom EAP
+/// method and changes to saved profiles go through `nmcli`, as with the
+/// libdbus backend.
+pub struct NetworkManagerDbusBackend {
+    bus: Connection,
+    device: String,
+    interface: String,
+    portal_ssid: String,
+    access_points: Vec<AccessPoint>,
+    /// Profile of the hotspot started by this process
+    portal_connection: Option<String>,
+    scan_timeout: u64,
+    /// `LastScan` of the device when a scan was requested, which changes
+    /// once the scan completes
+    requested_scan: Cell<Option<i64>>,
+    connect_timeout: u64,
+    certificate_directory: PathBuf,
+    /// Group interface when the portal runs as a WiFi Direct group owner
+    p2p_group: Option<String>,
+    shutdown: Shutdown,
+}
+
+impl NetworkManagerDbusBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let bus = Connection::system().chain_err(|| ErrorKind::NetworkManagerUnavailable)?;
+        debug!("NetworkManager connection initialized");
+
+        let mut backend = NetworkManagerDbusBackend {
+            bus,
+            device: String::new(),
+            interface: String::new(),
+            portal_ssid: config.ssid.clone(),
+            access_points: Vec::new(),
+            portal_connection: None,
+            scan_timeout: config.scan_timeout,
+            requested_scan: Cell::new(None),
+            connect_timeout: config.connect_timeout,
+            certificate_directory: config.certificate_directory.clone(),
+            p2p_group: None,
+            shutdown: Shutdown::new(),
+        };
+
+        let (device, interface) = backend.find_device(&config.interface)?;
+        backend.device = device;
+        backend.interface = interface;
+
+        Ok(backend)
+    }
+
+    fn call(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>> {
+        self.bus.call(NM_DESTINATION, path, interface, member, args)
+    }
+
+    /// First value of the reply of a call, retried on transient errors
+    fn call_value(
+        &self,
+        operation: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<Value> {
+        let reply = retry_nm(operation, || self.call(path, interface, member, args))?;
+
+        reply.into_iter().next().ok_or_else(|| {
+            ErrorKind::DBus(format!("{}.{}", interface, member), "no reply".into()).into()
+        })
+    }
+
+    fn property(&self, path: &str, interface: &str, property: &str) -> Result<Value> {
+        retry_nm(&format!("Reading {}", property), || {
+            self.bus
+                .get_property(NM_DESTINATION, path, interface, property)
+        })
+    }
+
+    /// When the device last completed a scan, in `CLOCK_BOOTTIME`
+    /// milliseconds. NetworkManager only has it since 1.12.
+    fn last_scan(&self) -> Option<i64> {
+        self.bus
+            .get_property(NM_DESTINATION, &self.device, WIRELESS_INTERFACE, "LastScan")
+            .ok()?
+            .as_i64()
+    }
+
+    fn u32_property(&self, path: &str, interface: &str, property: &str) -> Result<u32> {
+        self.property(path, interface, property)?
+            .as_u32()
+            .ok_or_else(|| {
+                ErrorKind::DBus(property.into(), "not an unsigned integer".into()).into()
+            })
+    }
+
+    fn find_device(&self, interface: &Option<String>) -> Result<(String, String)> {
+        if let Some(ref interface) = *interface {
+            let device = self
+                .call_value(
+                    "Getting the device",
+                    NM_PATH,
+                    NM_INTERFACE,
+                    "GetDeviceByIpIface",
+                    &[Value::String(interface.clone())],
+                )
+                .chain_err(|| ErrorKind::DeviceByInterface(interface.clone()))?;
+            let device = device.as_str().unwrap_or(NO_OBJECT).to_string();
+
+            info!("Targeted WiFi device: {}", interface);
+
+            if self.u32_property(&device, DEVICE_INTERFACE, "DeviceType")? != DEVICE_TYPE_WIFI {
+                bail!(ErrorKind::NotAWiFiDevice(interface.clone()))
+            }
+
+            if self.u32_property(&device, DEVICE_INTERFACE, "State")? == DEVICE_STATE_UNMANAGED {
+                bail!(ErrorKind::UnmanagedDevice(interface.clone()))
+            }
+
+            return Ok((device, interface.clone()));
+        }
+
+        let devices = self
+            .call_value("Listing devices", NM_PATH, NM_INTERFACE, "GetDevices", &[])
+            .chain_err(|| ErrorKind::NetworkManagerUnavailable)?;
+
+        // Devices supporting access point mode and not connected first,
+        // otherwise in NetworkManager's order
+        let mut candidates = Vec::new();
+
+        for device in devices.as_paths().unwrap_or_default() {
+            if self.u32_property(&device, DEVICE_INTERFACE, "DeviceType")? != DEVICE_TYPE_WIFI {
+                continue;
+            }
+
+            let state = self.u32_property(&device, DEVICE_INTERFACE, "State")?;
+
+            if state == DEVICE_STATE_UNMANAGED {
+                continue;
+            }
+
+            let interface = self.property(&device, DEVICE_INTERFACE, "Interface")?;
+            let interface = interface.as_str().unwrap_or_default().to_string();
+
+            let ap_capable = supports_ap_mode(&interface) != Some(false);
+            let connected = state == DEVICE_STATE_ACTIVATED;
+
+            candidates.push((!ap_capable, connected, device, interface));
+        }
+
+        candidates.sort_by_key(|&(not_ap_capable, connected, _, _)| (not_ap_capable, connected));
+
+        match candidates.into_iter().next() {
+            Some((_, _, device, interface)) => {
+                info!("WiFi device: {}", interface);
+                Ok((device, interface))
+            }
+            None => bail!(ErrorKind::NoWiFiDevice),
+        }
+    }
+
+    fn device_state(&self) -> Result<u32> {
+        self.u32_property(&self.device, DEVICE_INTERFACE, "State")
+    }
+
+    /// Reason NetworkManager gives for the last state change of the device
+    fn device_state_reason(&self) -> Option<u32> {
+        let reason = self
+            .property(&self.device, DEVICE_INTERFACE, "StateReason")
+            .ok()?;
+
+        reason.as_struct()?.get(1)?.as_u32()
+    }
+
+    fn access_point(&self, path: &str) -> Result<AccessPoint> {
+        let property = |name| self.property(path, ACCESS_POINT_INTERFACE, name);
+        let flag = |name| -> Result<u32> { Ok(property(name)?.as_u32().unwrap_or(0)) };
+
+        Ok(AccessPoint {
+            path: path.to_string(),
+            ssid: property("Ssid")?.as_bytes().unwrap_or_default(),
+            strength: property("Strength")?.as_u8().unwrap_or(0).min(100),
+            security: access_point_security(flag("Flags")?, flag("WpaFlags")?, flag("RsnFlags")?),
+        })
+    }
+
+    fn list_access_points(&self) -> Result<Vec<AccessPoint>> {
+        let paths = self.call_value(
+            "Listing access points",
+            &self.device,
+            WIRELESS_INTERFACE,
+            "GetAllAccessPoints",
+            &[],
+        )?;
+
+        let mut access_points = Vec::new();
+
+        // Access points vanishing since they were listed are skipped
+        for path in paths.as_paths().unwrap_or_default() {
+            if let Ok(access_point) = self.access_point(&path) {
+                access_points.push(access_point);
+            }
+        }
+
+        Ok(access_points)
+    }
+
+    /// Visible access points, strongest first and one per SSID, retrying
+    /// for up to `scan_timeout` seconds as the list may be empty right after
+    /// stopping the hotspot
+    fn get_access_points(&self) -> Result<Vec<AccessPoint>> {
+        info!("Scanning for available networks...");
+        let retries_allowed = self.scan_timeout.max(1);
+        let mut retries = 0;
+
+        while retries < retries_allowed {
+            let mut access_points = self
+                .list_access_points()
+                .chain_err(|| ErrorKind::NoAccessPoints)?;
+
+            access_points.sort_by_key(|ap| Reverse(ap.strength));
+
+            let mut inserted = HashSet::new();
+            access_points.retain(|ap| {
+                !ap.ssid.is_empty()
+                    && ap.ssid != self.portal_ssid.as_bytes()
+                    && inserted.insert(ap.ssid.clone())
+            });
+
+            if !access_points.is_empty() {
+                info!(
+                    "Found {} access points: {:?}",
+                    access_points.len(),
+                    access_points
+                        .iter()
+                        .map(|ap| display_ssid(&ap.ssid))
+                        .collect::<Vec<_>>()
+                );
+                return Ok(access_points);
+            }
+
+            retries += 1;
+            info!("No access points found - retry #{}", retries);
+
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                break;
+            }
+        }
+
+        warn!("No access points found - giving up...");
+        Ok(vec![])
+    }
+
+    fn profile(&self, path: &str) -> Result<Profile> {
+        let settings = self.call_value(
+            "Reading the connection",
+            path,
+            PROFILE_INTERFACE,
+            "GetSettings",
+            &[],
+        )?;
+
+        let text = |setting: &str, key: &str| {
+            settings
+                .get(setting)
+                .and_then(|values| values.get(key))
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        Ok(Profile {
+            path: path.to_string(),
+            id: text("connection", "id"),
+            uuid: text("connection", "uuid"),
+            kind: text("connection", "type"),
+            mode: text(WIRELESS_SETTING, "mode"),
+            ssid: settings
+                .get(WIRELESS_SETTING)
+                .and_then(|values| values.get("ssid"))
+                .and_then(Value::as_bytes)
+                .unwrap_or_default(),
+            settings,
+        })
+    }
+
+    fn profiles(&self) -> Result<Vec<Profile>> {
+        let paths = self.call_value(
+            "Listing connections",
+            SETTINGS_PATH,
+            SETTINGS_INTERFACE,
+            "ListConnections",
+            &[],
+        )?;
+
+        // Profiles deleted since they were listed are skipped
+        Ok(paths
+            .as_paths()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| self.profile(path).ok())
+            .collect())
+    }
+
+    fn find_saved_profile(&self, ssid: &str) -> Result<Option<Profile>> {
+        Ok(self
+            .profiles()?
+            .into_iter()
+            .find(|profile| profile.in_forget_scope(ForgetScope::Network(ssid))))
+    }
+
+    /// Active connections as their path, profile path and state
+    fn active_connections(&self) -> Result<Vec<(String, String, u32)>> {
+        let paths = self.property(NM_PATH, NM_INTERFACE, "ActiveConnections")?;
+
+        Ok(paths
+            .as_paths()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|active| {
+                let profile = self
+                    .property(&active, ACTIVE_INTERFACE, "Connection")
+                    .ok()?;
+                let profile = profile.as_str()?.to_string();
+                let state = self.active_state(&active);
+                Some((active, profile, state))
+            })
+            .collect())
+    }
+
+    /// State of an active connection, deactivated once it is gone
+    fn active_state(&self, active: &str) -> u32 {
+        self.bus
+            .get_property(NM_DESTINATION, active, ACTIVE_INTERFACE, "State")
+            .ok()
+            .and_then(|state| state.as_u32())
+            .unwrap_or(ACTIVE_STATE_DEACTIVATED)
+    }
+
+    fn find_active(&self, profile: &str) -> Option<String> {
+        self.active_connections()
+            .ok()?
+            .into_iter()
+            .find(|(_, path, _)| path == profile)
+            .map(|(active, _, _)| active)
+    }
+
+    /// Profile for `ssid` a failed call added anyway, with its active
+    /// connection, activated if it is not already
+    fn find_added_profile(&self, ssid: &[u8], access_point: bool) -> Option<(String, String)> {
+        let profile = self.profiles().ok()?.into_iter().find(|profile| {
+            profile.is_wifi() && profile.ssid == ssid && profile.is_access_point() == access_point
+        })?;
+
+        let active = match self.find_active(&profile.path) {
+            Some(active) => active,
+            None => {
+                let active = self
+                    .call(
+                        NM_PATH,
+                        NM_INTERFACE,
+                        "ActivateConnection",
+                        &[
+                            Value::ObjectPath(profile.path.clone()),
+                            Value::ObjectPath(self.device.clone()),
+                            Value::ObjectPath(NO_OBJECT.into()),
+                        ],
+                    )
+                    .ok()?;
+                active.first()?.as_str()?.to_string()
+            }
+        };
+
+        Some((profile.path, active))
+    }
+
+    /// Adds a profile and activates it on the device, returning the profile
+    /// and active connection paths. A call failing with a transient error
+    /// may have added the profile all the same.
+    fn add_and_activate(
+        &self,
+        operation: &str,
+        settings: &Value,
+        ssid: &[u8],
+        access_point: &str,
+    ) -> Result<(String, String)> {
+        let is_access_point = access_point == NO_OBJECT;
+
+        retry_nm_change(
+            operation,
+            || self.find_added_profile(ssid, is_access_point),
+            || -> Result<(String, String)> {
+                let reply = self.call(
+                    NM_PATH,
+                    NM_INTERFACE,
+                    "AddAndActivateConnection",
+                    &[
+                        settings.clone(),
+                        Value::ObjectPath(self.device.clone()),
+                        Value::ObjectPath(access_point.into()),
+                    ],
+                )?;
+
+                match (
+                    reply.first().and_then(Value::as_str),
+                    reply.get(1).and_then(Value::as_str),
+                ) {
+                    (Some(profile), Some(active)) => Ok((profile.to_string(), active.to_string())),
+                    _ => bail!(ErrorKind::DBus(
+                        operation.into(),
+                        "no connection added".into()
+                    )),
+                }
+            },
+        )
+    }
+
+    fn wait_for_activation(&self, active: &str, timeout: u64) -> u32 {
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        let mut state = self.active_state(active);
+
+        while state <= ACTIVE_STATE_ACTIVATING && Instant::now() < deadline {
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                break;
+            }
+            state = self.active_state(active);
+        }
+
+        state
+    }
+
+    fn deactivate(&self, profile: &str) -> Result<()> {
+        if let Some(active) = self.find_active(profile) {
+            retry_nm_change(
+                "Deactivating the connection",
+                || {
+                    if self.active_state(&active) >= ACTIVE_STATE_DEACTIVATING {
+                        Some(())
+                    } else {
+                        None
+                    }
+                },
+                || {
+                    self.call(
+                        NM_PATH,
+                        NM_INTERFACE,
+                        "DeactivateConnection",
+                        &[Value::ObjectPath(active.clone())],
+                    )
+                    .map(|_| ())
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a profile, done once it is no longer listed
+    fn delete(&self, profile: &str) -> Result<()> {
+        retry_nm_change(
+            "Deleting the connection",
+            || match self.profile(profile) {
+                Ok(_) => None,
+                Err(_) => Some(()),
+            },
+            || {
+                self.call(profile, PROFILE_INTERFACE, "Delete", &[])
+                    .map(|_| ())
+            },
+        )
+    }
+
+    fn delete_existing_profiles_to_same_network(&self, ssid: &str) {
+        let profiles = match self.profiles() {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                error!("Getting existing connections failed: {}", e);
+                return;
+            }
+        };
+
+        for profile in &profiles {
+            if profile.is_wifi() && profile.is_same_ssid(ssid) {
+                info!(
+                    "Deleting existing WiFi connection to the same network: {}",
+                    display_ssid(&profile.ssid)
+                );
+
+                if let Err(e) = self.delete(&profile.path) {
+                    error!("Deleting existing WiFi connection failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Connects through a profile added with `nmcli`, for the EAP methods
+    /// and certificates that are not set up over D-Bus
+    fn connect_with_nmcli(
+        &self,
+        network: &NetworkCredentials,
+        certificates: Option<&EapTls>,
+        settings: &ProfileSettings,
+    ) -> Result<()> {
+        add_connection_profile(
+            &network.ssid,
+            "enterprise",
+            &network.identity,
+            network.passphrase.expose(),
+            certificates,
+            settings,
+        )?;
+
+        activate_profile(&self.interface, &network.ssid, self.connect_timeout)
+    }
+
+    fn create_portal(&self, config: &Config) -> Result<String> {
+        let passphrase = config.passphrase.as_ref().map(|p| p.expose());
+
+        let band = config.portal_band.map(|band| band.primary());
+
+        // Scanned before the access point takes over the interface
+        let channel = config.portal_channel.and_then(|channel| {
+            resolve_portal_channel(&self.interface, channel, band.unwrap_or(Band::TwoGHz))
+        });
+
+        let settings = ProfileSettings {
+            channel,
+            band,
+            ap_isolation: config.client_isolation,
+            ..Default::default()
+        };
+
+        if let Some(channel) = settings.channel {
+            info!("Moving access point to channel {}...", channel);
+        } else if let Some(band) = settings.band {
+            info!("Moving access point to {}...", band.name());
+        }
+
+        if settings.ap_isolation {
+            info!("Isolating access point clients...");
+        }
+
+        // Enhanced Open only replaces an open access point
+        let owe = config.portal_owe && passphrase.is_none();
+
+        info!("Starting access point...");
+
+        if owe {
+            info!("Enabling Enhanced Open (OWE)...");
+
+            let settings = ProfileSettings {
+                owe: true,
+                ..settings.clone()
+            };
+
+            match self.activate_portal(config, None, &settings) {
+                Ok(profile) => return Ok(profile),
+                Err(e) => {
+                    debug!("Enhanced Open access point failed: {}", e);
+                    warn!("Enhanced Open (OWE) is not supported, falling back to an open access point");
+                }
+            }
+        }
+
+        self.activate_portal(config, passphrase, &settings)
+    }
+
+    fn activate_portal(
+        &self,
+        config: &Config,
+        passphrase: Option<&str>,
+        settings: &ProfileSettings,
+    ) -> Result<String> {
+        let ssid = config.ssid.as_bytes();
+
+        let mut builder = SettingsBuilder::default();
+        builder.set("connection", "id", Value::String(config.ssid.clone()));
+        builder.set("connection", "type", Value::String(WIRELESS_SETTING.into()));
+        builder.set("connection", "autoconnect", Value::Bool(false));
+        builder.set(WIRELESS_SETTING, "ssid", Value::bytes(ssid));
+        builder.set(WIRELESS_SETTING, "mode", Value::String("ap".into()));
+        builder.set("ipv4", "method", Value::String("shared".into()));
+        builder.set(
+            "ipv4",
+            "address-data",
+            Value::Array(
+                "a{sv}".into(),
+                vec![Value::dict(vec![
+                    ("address", Value::String(config.gateway.to_string())),
+                    ("prefix", Value::UInt32(HOTSPOT_PREFIX)),
+                ])],
+            ),
+        );
+        builder.set("ipv6", "method", Value::String("ignore".into()));
+
+        if let Some(passphrase) = passphrase {
+            builder.set(
+                SECURITY_SETTING,
+                "key-mgmt",
+                Value::String("wpa-psk".into()),
+            );
+            builder.set(SECURITY_SETTING, "psk", Value::String(passphrase.into()));
+            builder.set(SECURITY_SETTING, "proto", strings(&["rsn"]));
+            builder.set(SECURITY_SETTING, "pairwise", strings(&["ccmp"]));
+            builder.set(SECURITY_SETTING, "group", strings(&["ccmp"]));
+        }
+
+        builder.apply(settings);
+
+        let (profile, active) = self.add_and_activate(
+            "Creating the access point",
+            &builder.build(),
+            ssid,
+            NO_OBJECT,
+        )?;
+
+        let state = self.wait_for_activation(&active, self.connect_timeout);
+
+        if state != ACTIVE_STATE_ACTIVATED {
+            if let Err(e) = self.delete(&profile) {
+                error!("Deleting the access point connection failed: {}", e);
+            }
+
+            bail!(ErrorKind::ConnectionNotActivated(
+                config.ssid.clone(),
+                active_state_name(state).into()
+            ));
+        }
+
+        info!("Access point '{}' created", config.ssid);
+        Ok(profile)
+    }
+
+    fn stop_portal(&self, profile: &str, config: &Config) -> Result<()> {
+        info!("Stopping access point '{}'...", config.ssid);
+        self.deactivate(profile)?;
+        self.delete(profile)?;
+        thread::sleep(Duration::from_secs(1));
+        info!("Access point '{}' stopped", config.ssid);
+        Ok(())
+    }
+
+    /// Deletes the profiles in the scope, returning how many. Failures are
+    /// logged and do not stop the others from being deleted.
+    fn delete_in_scope(&self, scope: ForgetScope, what: &str) -> Result<usize> {
+        let mut deleted = 0;
+
+        for profile in self.profiles()? {
+            if !profile.in_forget_scope(scope) {
+                continue;
+            }
+
+            info!("Deleting {}: {}", what, display_ssid(&profile.ssid));
+
+            match self.delete(&profile.path) {
+                Ok(()) => deleted += 1,
+                Err(e) => error!("Deleting {} failed: {}", what, e),
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+impl Backend for NetworkManagerDbusBackend {
+    fn name(&self) -> &'static str {
+        "network-manager"
+    }
+
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    fn interface(&self) -> &str {
+        match self.p2p_group {
+            Some(ref group) => group,
+            None => &self.interface,
+        }
+    }
+
+    fn request_scan(&self) -> Result<()> {
+        self.requested_scan.set(self.last_scan());
+
+        retry_nm("Requesting a scan", || {
+            self.call(
+                &self.device,
+                WIRELESS_INTERFACE,
+                "RequestScan",
+                &[Value::dict(vec![])],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn is_scanning(&self) -> Result<bool> {
+        Ok(self
+            .requested_scan
+            .get()
+            .is_some_and(|requested| self.last_scan() == Some(requested)))
+    }
+
+    fn scan(&mut self) -> Result<Vec<Network>> {
+        let access_points = self.get_access_points()?;
+        let networks = access_points
+            .iter()
+            .map(|ap| Network::new(&ap.ssid, ap.security, ap.strength))
+            .collect();
+
+        // Listed again when connecting with --low-memory
+        if !is_low_memory() {
+            self.access_points = access_points;
+        }
+
+        Ok(networks)
+    }
+
+    fn connect(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) -> Result<()> {
+        let ssid = &network.ssid;
+
+        self.delete_existing_profiles_to_same_network(ssid);
+
+        let listed;
+        let access_points = if self.access_points.is_empty() {
+            listed = self.get_access_points()?;
+            &listed
+        } else {
+            &self.access_points
+        };
+
+        let access_point = match access_points
+            .iter()
+            .find(|access_point| matches_ssid(&access_point.ssid, ssid))
+        {
+            Some(access_point) => access_point,
+            None => bail!(ErrorKind::NetworkNotFound(ssid.clone())),
+        };
+
+        info!("Connecting to access point '{}'...", ssid);
+
+        // Named so that the connection can be told apart from ones
+        // provisioned otherwise
+        let name = display_ssid(&access_point.ssid);
+        let settings = ProfileSettings {
+            id: Some(managed_connection_id(&name)),
+            ..settings.for_network(&self.interface, &name)
+        };
+
+        if let Some(certificates) = eap_tls_certificates(&self.certificate_directory, ssid) {
+            info!("Authenticating to '{}' with uploaded certificates", ssid);
+            return self.connect_with_nmcli(network, Some(&certificates), &settings);
+        }
+
+        if access_point.security == "enterprise" && settings.has_custom_eap() {
+            return self.connect_with_nmcli(network, None, &settings);
+        }
+
+        let profile_settings = network_settings(
+            &access_point.ssid,
+            access_point.security,
+            network,
+            &settings,
+        );
+
+        let (profile, active) = self.add_and_activate(
+            "Activating the connection",
+            &profile_settings,
+            &access_point.ssid,
+            &access_point.path,
+        )?;
+
+        let state = self.wait_for_activation(&active, self.connect_timeout);
+
+        if state == ACTIVE_STATE_ACTIVATED {
+            return Ok(());
+        }
+
+        // Read before deleting the connection moves the device on
+        let reason = self.device_state_reason();
+
+        if let Err(err) = self.delete(&profile) {
+            error!("Deleting connection object failed: {}", err)
+        }
+
+        bail!(connection_failure(ssid, active_state_name(state), reason))
+    }
+
+    fn save_network(
+        &mut self,
+        network: &NetworkCredentials,
+        settings: &ProfileSettings,
+    ) -> Result<()> {
+        self.delete_existing_profiles_to_same_network(&network.ssid);
+
+        let certificates = eap_tls_certificates(&self.certificate_directory, &network.ssid);
+
+        // Networks out of range are saved all the same
+        let listed;
+        let access_points = if self.access_points.is_empty() {
+            listed = self.get_access_points().unwrap_or_default();
+            &listed
+        } else {
+            &self.access_points
+        };
+
+        let security = match access_points
+            .iter()
+            .find(|access_point| matches_ssid(&access_point.ssid, &network.ssid))
+        {
+            Some(access_point) => access_point.security,
+            None if network.passphrase.is_empty() => "none",
+            None => "wpa",
+        };
+
+        info!("Saving network '{}' for later use", network.ssid);
+
+        if certificates.is_some() || (security == "enterprise" && settings.has_custom_eap()) {
+            return add_connection_profile(
+                &network.ssid,
+                security,
+                &network.identity,
+                network.passphrase.expose(),
+                certificates.as_ref(),
+                settings,
+            );
+        }
+
+        let settings = ProfileSettings {
+            id: Some(
+                settings
+                    .id
+                    .clone()
+                    .unwrap_or_else(|| managed_connection_id(&network.ssid)),
+            ),
+            ..settings.clone()
+        };
+
+        let profile_settings =
+            network_settings(network.ssid.as_bytes(), security, network, &settings);
+
+        retry_nm("Adding the connection", || {
+            self.call(
+                SETTINGS_PATH,
+                SETTINGS_INTERFACE,
+                "AddConnection",
+                slice::from_ref(&profile_settings),
+            )
+        })
+        .chain_err(|| ErrorKind::AddConnection(network.ssid.clone()))?;
+
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        if self.device_state()? != DEVICE_STATE_ACTIVATED {
+            println!("No active connection found.");
+            return Ok(());
+        }
+
+        retry_nm("Disconnecting the device", || {
+            self.call(&self.device, DEVICE_INTERFACE, "Disconnect", &[])
+        })?;
+
+        println!("Disconnected successfully.");
+
+        Ok(())
+    }
+
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool> {
+        let mut total_time = 0;
+
+        loop {
+            let connectivity = self
+                .call_value(
+                    "Checking connectivity",
+                    NM_PATH,
+                    NM_INTERFACE,
+                    "CheckConnectivity",
+                    &[],
+                )?
+                .as_u32()
+                .unwrap_or(CONNECTIVITY_UNKNOWN);
+
+            // NetworkManager reports an unknown state with its check disabled
+            let connected = match connectivity {
+                CONNECTIVITY_FULL | CONNECTIVITY_LIMITED => true,
+                CONNECTIVITY_UNKNOWN => probe_connectivity(),
+                _ => false,
+            };
+
+            if connected {
+                debug!(
+                    "Connectivity established: {} / {}s elapsed",
+                    connectivity, total_time
+                );
+
+                return Ok(true);
+            } else if total_time >= timeout {
+                debug!(
+                    "Timeout reached in waiting for connectivity: {} / {}s elapsed",
+                    connectivity, total_time
+                );
+
+                return Ok(false);
+            }
+
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                return Ok(false);
+            }
+
+            total_time += 1;
+
+            debug!(
+                "Still waiting for connectivity: {} / {}s elapsed",
+                connectivity, total_time
+            );
+        }
+    }
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        if self.device_state()? != DEVICE_STATE_ACTIVATED {
+            return Ok(None);
+        }
+
+        let path = self.property(&self.device, WIRELESS_INTERFACE, "ActiveAccessPoint")?;
+
+        let access_point = match path.as_str() {
+            Some(path) if path != NO_OBJECT => self.access_point(path)?,
+            _ => return Ok(None),
+        };
+
+        // The device is activated as the hotspot as well
+        if access_point.ssid == self.portal_ssid.as_bytes() {
+            return Ok(None);
+        }
+
+        let (ipv4, ipv6) = get_ip_configs(&self.interface);
+
+        Ok(Some(ConnectedNetwork {
+            ssid: display_ssid(&access_point.ssid),
+            security: access_point.security.to_string(),
+            signal_strength: access_point.strength,
+            interface: self.interface.clone(),
+            ip_address: get_interface_ipv4(&self.interface),
+            ipv6_addresses: get_interface_ipv6(&self.interface),
+            ipv4,
+            ipv6,
+            link: get_link_statistics(&self.interface),
+            vpn: None,
+        }))
+    }
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        let mut seen_ssids = HashSet::new();
+
+        let mut saved_networks = self
+            .profiles()?
+            .into_iter()
+            .filter(|profile| profile.is_wifi() && !profile.is_access_point())
+            .filter_map(|profile| {
+                let ssid = display_ssid(&profile.ssid);
+
+                if ssid.is_empty() || !seen_ssids.insert(ssid.clone()) {
+                    return None;
+                }
+
+                Some(SavedNetwork {
+                    ssid,
+                    security: profile_security(profile.key_mgmt()).to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        saved_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+        Ok(saved_networks)
+    }
+
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        let profile = match self.find_saved_profile(ssid)? {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+
+        let text = |setting: &str, key: &str| {
+            profile
+                .setting(setting, key)
+                .and_then(Value::as_str)
+                .map(String::from)
+                .filter(|value| !value.is_empty())
+        };
+
+        let ipv4_dns = profile
+            .setting("ipv4", "dns")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_u32)
+            // Addresses are in network byte order
+            .map(|address| Ipv4Addr::from(address.to_ne_bytes()).to_string());
+
+        let ipv6_dns = profile
+            .setting("ipv6", "dns")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_bytes)
+            .filter(|address| address.len() == 16)
+            .map(|address| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&address);
+                Ipv6Addr::from(octets).to_string()
+            });
+
+        let cloned_mac = text(WIRELESS_SETTING, "assigned-mac-address").or_else(|| {
+            profile
+                .setting(WIRELESS_SETTING, "cloned-mac-address")
+                .and_then(Value::as_bytes)
+                .filter(|mac| !mac.is_empty())
+                .map(|mac| {
+                    mac.iter()
+                        .map(|octet| format!("{:02X}", octet))
+                        .collect::<Vec<_>>()
+                        .join(":")
+                })
+        });
+
+        Ok(Some(SavedNetworkDetails {
+            ssid: display_ssid(&profile.ssid),
+            security: profile_security(profile.key_mgmt()).to_string(),
+            autoconnect: profile
+                .setting("connection", "autoconnect")
+                .and_then(Value::as_bool)
+                .unwrap_or(true),
+            autoconnect_priority: profile
+                .setting("connection", "autoconnect-priority")
+                .and_then(Value::as_i32),
+            last_used: profile.last_used(),
+            ipv4_method: text("ipv4", "method"),
+            ipv6_method: text("ipv6", "method"),
+            dns: ipv4_dns.chain(ipv6_dns).collect(),
+            cloned_mac,
+        }))
+    }
+
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool> {
+        let profile = match self.find_saved_profile(ssid)? {
+            Some(profile) => profile,
+            None => {
+                warn!("Network '{}' not found in saved connections", ssid);
+                return Ok(false);
+            }
+        };
+
+        info!("Updating WiFi network: {}", ssid);
+        update_connection_profile(&profile.uuid, profile_security(profile.key_mgmt()), update)?;
+
+        Ok(true)
+    }
+
+    fn forget_network(&self, ssid: &str) -> Result<bool> {
+        let mut found = false;
+
+        for profile in self.profiles()? {
+            if profile.in_forget_scope(ForgetScope::Network(ssid)) {
+                info!("Forgetting WiFi network: {}", ssid);
+                self.delete(&profile.path)
+                    .chain_err(|| ErrorKind::DeleteAccessPoint)?;
+                found = true;
+            }
+        }
+
+        if !found {
+            warn!("Network '{}' not found in saved connections", ssid);
+        }
+
+        Ok(found)
+    }
+
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()> {
+        info!("Forgetting all WiFi connections...");
+
+        self.delete_in_scope(ForgetScope::All(filter), "WiFi connection")
+            .map(|_| ())
+    }
+
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>> {
+        let mut managed_networks = self
+            .profiles()?
+            .into_iter()
+            .filter(Profile::is_managed)
+            .map(|profile| SavedNetwork {
+                ssid: display_ssid(&profile.ssid),
+                security: profile_security(profile.key_mgmt()).to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        managed_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+        Ok(managed_networks)
+    }
+
+    fn forget_managed(&self) -> Result<usize> {
+        info!("Forgetting WiFi networks saved by WiFi Connect...");
+
+        self.delete_in_scope(ForgetScope::Managed, "WiFi connection")
+    }
+
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>> {
+        Ok(self
+            .profiles()?
+            .iter()
+            .filter(|profile| profile.in_forget_scope(scope))
+            .map(|profile| {
+                format!(
+                    "{} ({}, SSID: {})",
+                    profile.id,
+                    profile.uuid,
+                    display_ssid(&profile.ssid)
+                )
+            })
+            .collect())
+    }
+
+    fn gc_connections(&self, policy: &GcPolicy) -> Result<usize> {
+        let active = self
+            .active_connections()?
+            .into_iter()
+            .map(|(_, profile, _)| profile)
+            .collect::<HashSet<_>>();
+
+        // Active profiles and access points are never pruned
+        let mut candidates = self
+            .profiles()?
+            .into_iter()
+            .filter(|profile| {
+                profile.is_wifi() && !profile.is_access_point() && !active.contains(&profile.path)
+            })
+            .collect::<Vec<_>>();
+
+        // Most recently used first, never used last
+        candidates.sort_by_key(|profile| Reverse(profile.last_used()));
+
+        let mut pruned = 0;
+
+        for profile in candidates.iter().skip(policy.keep) {
+            if !policy.is_stale(profile.last_used()) {
+                continue;
+            }
+
+            info!("Pruning WiFi connection: {}", display_ssid(&profile.ssid));
+
+            match self.delete(&profile.path) {
+                Ok(()) => pruned += 1,
+                Err(e) => error!("Deleting WiFi connection failed: {}", e),
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()> {
+        let interface = self.interface.clone();
+
+        if require_hotspot_mode(&interface, config)? == HotspotMode::WiFiDirect {
+            self.p2p_group = Some(start_p2p_group(&interface, config)?);
+            return Ok(());
+        }
+
+        // Left behind by a WiFi Connect process that did not stop cleanly
+        self.delete_in_scope(ForgetScope::Portal(&config.ssid), "access point connection")
+            .chain_err(|| ErrorKind::DeleteAccessPoint)?;
+
+        self.portal_connection = Some(
+            self.create_portal(config)
+                .chain_err(|| ErrorKind::CreateCaptivePortal)?,
+        );
+
+        if config.portal_band == Some(PortalBand::Both) {
+            if let Err(e) = start_dual_band_portal(&interface, config) {
+                warn!("{}, serving 2.4 GHz only", e);
+                stop_dual_band_portal();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()> {
+        if config.portal_band == Some(PortalBand::Both) {
+            stop_dual_band_portal();
+        }
+
+        if let Some(group) = self.p2p_group.take() {
+            return stop_p2p_group(&self.interface, &group);
+        }
+
+        if let Some(profile) = self.portal_connection.take() {
+            return self
+                .stop_portal(&profile, config)
+                .chain_err(|| ErrorKind::StopAccessPoint);
+        }
+
+        // The hotspot may have been started by another WiFi Connect process
+        for profile in self.profiles()? {
+            if profile.in_forget_scope(ForgetScope::Portal(&config.ssid)) {
+                info!("Deactivating hotspot connection");
+                let _ = self.deactivate(&profile.path);
+                let _ = self.delete(&profile.path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool {
+        if self.p2p_group.is_some() {
+            return true;
+        }
+
+        // Only the profiles of the active connections, instead of the
+        // settings of every saved one
+        let connections = match self.active_connections() {
+            Ok(connections) => connections,
+            Err(_) => return false,
+        };
+
+        connections.iter().any(|&(_, ref path, state)| {
+            state == ACTIVE_STATE_ACTIVATED
+                && self
+                    .profile(path)
+                    .is_ok_and(|profile| profile.in_forget_scope(ForgetScope::Portal(ssid)))
+        })
+    }
+}
+
+/// Builds the `a{sa{sv}}` settings of a connection profile
+#[derive(Default)]
+struct SettingsBuilder {
+    settings: Vec<(&'static str, Vec<(&'static str, Value)>)>,
+}
+
+impl SettingsBuilder {
+    fn set(&mut self, setting: &'static str, key: &'static str, value: Value) {
+        let index = match self.settings.iter().position(|&(name, _)| name == setting) {
+            Some(index) => index,
+            None => {
+                self.settings.push((setting, Vec::new()));
+                self.settings.len() - 1
+            }
+        };
+
+        let values = &mut self.settings[index].1;
+        values.retain(|&(name, _)| name != key);
+        values.push((key, value));
+    }
+
+    /// The profile settings WiFi Connect adds on top of the network-manager
+    /// defaults, the same ones `nmcli` is given otherwise
+    fn apply(&mut self, settings: &ProfileSettings) {
+        if let Some(ref id) = settings.id {
+            self.set("connection", "id", Value::String(id.clone()));
+            self.set(
+                "user",
+                "data",
+                Value::Array(
+                    "{ss}".into(),
+                    vec![Value::DictEntry(
+                        Box::new(Value::String(managed_data_key().into())),
+                        Box::new(Value::String("yes".into())),
+                    )],
+                ),
+            );
+        }
+
+        if let Some(ref cloned_mac) = settings.cloned_mac {
+            self.set(
+                WIRELESS_SETTING,
+                "assigned-mac-address",
+                Value::String(cloned_mac.clone()),
+            );
+        }
+
+        if settings.metered {
+            self.set("connection", "metered", Value::Int32(1));
+        }
+
+        if settings.no_autoconnect {
+            self.set("connection", "autoconnect", Value::Bool(false));
+        }
+
+        if let Some(autoconnect_retries) = settings.autoconnect_retries {
+            self.set(
+                "connection",
+                "autoconnect-retries",
+                Value::Int32(autoconnect_retries as i32),
+            );
+        }
+
+        if let Some(autoconnect_priority) = settings.autoconnect_priority {
+            self.set(
+                "connection",
+                "autoconnect-priority",
+                Value::Int32(autoconnect_priority),
+            );
+        }
+
+        let band = settings.channel.and_then(band_of_channel).or(settings.band);
+
+        if let Some(band) = band {
+            self.set(
+                WIRELESS_SETTING,
+                "band",
+                Value::String(band.nm_band().into()),
+            );
+        }
+
+        if let Some(channel) = settings.channel {
+            self.set(WIRELESS_SETTING, "channel", Value::UInt32(channel));
+        }
+
+        if settings.ap_isolation {
+            self.set(WIRELESS_SETTING, "ap-isolation", Value::Int32(1));
+        }
+
+        if settings.owe {
+            self.set(SECURITY_SETTING, "key-mgmt", Value::String("owe".into()));
+        }
+
+        if let Some(ref proxy_pac_url) = settings.proxy_pac_url {
+            self.set("proxy", "method", Value::Int32(1));
+            self.set("proxy", "pac-url", Value::String(proxy_pac_url.clone()));
+        }
+
+        if let Some(powersave) = settings.powersave_mode() {
+            self.set(WIRELESS_SETTING, "powersave", Value::UInt32(powersave));
+        }
+    }
+
+    fn build(self) -> Value {
+        Value::settings(
+            self.settings
+                .into_iter()
+                .map(|(setting, values)| (setting, Value::dict(values)))
+                .collect(),
+        )
+    }
+}
+
+/// Settings of a client profile for the network, authenticating enterprise
+/// networks with PEAP and MSCHAPv2
+fn network_settings(
+    ssid: &[u8],
+    security: &str,
+    network: &NetworkCredentials,
+    settings: &ProfileSettings,
+) -> Value {
+    let passphrase = network.passphrase.expose();
+
+    let mut builder = SettingsBuilder::default();
+    builder.set("connection", "type", Value::String(WIRELESS_SETTING.into()));
+    builder.set(WIRELESS_SETTING, "ssid", Value::bytes(ssid));
+    builder.set(
+        WIRELESS_SETTING,
+        "mode",
+        Value::String("infrastructure".into()),
+    );
+
+    match security {
+        "enterprise" => {
+            builder.set(
+                SECURITY_SETTING,
+                "key-mgmt",
+                Value::String("wpa-eap".into()),
+            );
+            builder.set("802-1x", "eap", strings(&["peap"]));
+            builder.set("802-1x", "phase2-auth", Value::String("mschapv2".into()));
+            builder.set(
+                "802-1x",
+                "identity",
+                Value::String(network.identity.clone()),
+            );
+            builder.set("802-1x", "password", Value::String(passphrase.into()));
+        }
+        "wpa" => {
+            builder.set(
+                SECURITY_SETTING,
+                "key-mgmt",
+                Value::String("wpa-psk".into()),
+            );
+            builder.set(SECURITY_SETTING, "psk", Value::String(passphrase.into()));
+        }
+        "wep" => {
+            // Keys of 5 or 13 characters or their hex form, else a passphrase
+            let key_type = match passphrase.len() {
+                5 | 10 | 13 | 26 => 1,
+                _ => 2,
+            };
+
+            builder.set(SECURITY_SETTING, "key-mgmt", Value::String("none".into()));
+            builder.set(
+                SECURITY_SETTING,
+                "wep-key0",
+                Value::String(passphrase.into()),
+            );
+            builder.set(SECURITY_SETTING, "wep-key-type", Value::UInt32(key_type));
+        }
+        _ => {}
+    }
+
+    builder.apply(settings);
+    builder.build()
+}
+
+/// Security of an access point from its capability flags
+fn access_point_security(flags: u32, wpa_flags: u32, rsn_flags: u32) -> &'static str {
+    let security = wpa_flags | rsn_flags;
+
+    if security & (AP_SEC_KEY_MGMT_802_1X | AP_SEC_KEY_MGMT_EAP_SUITE_B_192) != 0 {
+        "enterprise"
+    } else if security & (AP_SEC_KEY_MGMT_PSK | AP_SEC_KEY_MGMT_SAE) != 0 {
+        "wpa"
+    } else if security == 0 && flags & AP_FLAGS_PRIVACY != 0 {
+        "wep"
+    } else {
+        // Enhanced Open is set up by NetworkManager without credentials
+        "none"
+    }
+}
+
+fn active_state_name(state: u32) -> &'static str {
+    match state {
+        ACTIVE_STATE_ACTIVATING => "Activating",
+        ACTIVE_STATE_ACTIVATED => "Activated",
+        ACTIVE_STATE_DEACTIVATING => "Deactivating",
+        ACTIVE_STATE_DEACTIVATED => "Deactivated",
+        _ => "Unknown",
+    }
+}
+
+/// Key of the managed marker in the `user.data` setting
+fn managed_data_key() -> &'static str {
+    MANAGED_MARKER.trim_start_matches("user.")
+}
+
+fn strings(values: &[&str]) -> Value {
+    Value::Array(
+        "s".into(),
+        values
+            .iter()
+            .map(|value| Value::String((*value).into()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::access_point_security;
+
+    #[test]
+    fn classifies_access_point_security() {
+        // WPA2-Personal with CCMP, WPA3-Personal and WPA2-Enterprise
+        assert_eq!(access_point_security(0x1, 0, 0x188), "wpa");
+        assert_eq!(access_point_security(0x1, 0, 0x488), "wpa");
+        assert_eq!(access_point_security(0x1, 0x288, 0x288), "enterprise");
+        assert_eq!(access_point_security(0x1, 0, 0), "wep");
+        assert_eq!(access_point_security(0, 0, 0), "none");
+        // Enhanced Open
+        assert_eq!(access_point_security(0x1, 0, 0x888), "none");
+    }
+}
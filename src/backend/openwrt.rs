@@ -0,0 +1,1114 @@
+use std::collections::HashMap;
+use std::process::Command;
+use std::time::Duration;
+
+use serde_json::{self, Value};
+
+use backend::{Backend, ForgetFilter, ForgetScope};
+use channels::rssi_to_percent;
+use config::Config;
+use errors::*;
+use exit::Shutdown;
+use ipconfig::IpConfig;
+use link::get_link_statistics;
+use network::{
+    get_interface_ipv4, get_interface_ipv6, ConnectedNetwork, Network, NetworkCredentials,
+    SavedNetwork, SavedNetworkDetails,
+};
+use profile::{ProfileSettings, ProfileUpdate};
+use ssid::parse_ssid;
+use wiphy::{require_hotspot_mode, set_power_save, HotspotMode};
+
+/// Prefix of the uci sections WiFi Connect creates, to tell them apart from
+/// the ones of the image or LuCI
+const SECTION_PREFIX: &str = "wificonnect_";
+
+/// wifi-iface section of the captive portal access point
+const PORTAL_SECTION: &str = "wificonnect_portal";
+
+/// netifd interface of the portal, carrying the gateway address
+const PORTAL_NETWORK: &str = "wificonnect";
+
+/// The system dnsmasq of OpenWrt binds every interface as it comes up, so the
+/// portal interface is excluded from it while the portal's own one runs
+const SYSTEM_DNSMASQ: &str = "dhcp.@dnsmasq[0]";
+
+/// netifd interface of client connections, DHCP on the upstream WiFi. Images
+/// meant as repeaters usually have it in the firewall's wan zone already.
+const STATION_NETWORK: &str = "wwan";
+
+/// Seconds netifd is given to bring up a wifi-iface after a reload
+const IFACE_UP_TIMEOUT: u64 = 15;
+
+/// Drives OpenWrt's netifd through uci and ubus, for customer premise
+/// equipment without NetworkManager. Saved networks are station wifi-iface
+/// sections of `/etc/config/wireless`, only one of the ones WiFi Connect
+/// created is enabled at a time. Sections provisioned with the image are
+/// listed and can be forgotten, but are never switched on or off.
+pub struct OpenWrtBackend {
+    radio: String,
+    /// Network device of the portal while it runs, of the station otherwise
+    interface: String,
+    portal_ssid: String,
+    /// uci encryption of the networks of the last scan, by SSID
+    encryptions: HashMap<String, &'static str>,
+    /// Stations of WiFi Connect disabled while the portal runs, as the radio
+    /// cannot keep scanning for them next to an access point
+    suspended: Vec<String>,
+    shutdown: Shutdown,
+}
+
+/// Section of a uci config, from `uci -X show`
+struct UciSection {
+    name: String,
+    kind: String,
+    options: HashMap<String, String>,
+}
+
+impl UciSection {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    fn is_station(&self) -> bool {
+        self.kind == "wifi-iface" && self.option("mode") == Some("sta")
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.option("disabled") == Some("1")
+    }
+
+    /// Whether WiFi Connect created the section
+    fn is_managed(&self) -> bool {
+        self.name.starts_with(SECTION_PREFIX)
+    }
+}
+
+impl OpenWrtBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let radios = wireless_status()?;
+
+        // Either a radio or a network device of one can be given
+        let radio = match config.interface {
+            Some(ref interface) => radios
+                .iter()
+                .find(|&(radio, ifnames)| radio == interface || ifnames.contains(interface))
+                .map(|(radio, _)| radio.clone())
+                .ok_or_else(|| ErrorKind::NotAWiFiDevice(interface.clone()))?,
+            None => match radios.keys().min() {
+                Some(radio) => radio.clone(),
+                None => bail!(ErrorKind::NoWiFiDevice),
+            },
+        };
+
+        // iwinfo accepts radio names as well until a device is up
+        let interface = match config.interface {
+            Some(ref interface) if *interface != radio => interface.clone(),
+            _ => radios[&radio]
+                .first()
+                .cloned()
+                .unwrap_or_else(|| radio.clone()),
+        };
+
+        info!("WiFi device: {} ({})", interface, radio);
+
+        Ok(OpenWrtBackend {
+            radio,
+            interface,
+            portal_ssid: config.ssid.clone(),
+            encryptions: HashMap::new(),
+            suspended: Vec::new(),
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Station sections of the radio, whoever created them
+    fn stations(&self) -> Result<Vec<UciSection>> {
+        Ok(uci_show("wireless")?
+            .into_iter()
+            .filter(|section| {
+                section.is_station() && section.option("device") == Some(&*self.radio)
+            })
+            .collect())
+    }
+
+    /// Network device of a wifi-iface section of the radio, once it is up
+    fn section_ifname(&self, section: &str) -> Option<String> {
+        let status = ubus("network.wireless", "status", &json!({})).ok()?;
+
+        status[&self.radio]["interfaces"]
+            .as_array()?
+            .iter()
+            .find(|iface| iface["section"] == section)
+            .and_then(|iface| iface["ifname"].as_str())
+            .map(String::from)
+    }
+
+    /// Waits for netifd to bring up the device of a section after a reload
+    fn wait_for_ifname(&self, section: &str) -> Option<String> {
+        for _ in 0..IFACE_UP_TIMEOUT {
+            if let Some(ifname) = self.section_ifname(section) {
+                return Some(ifname);
+            }
+
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Device of the enabled station section, if one is up
+    fn station_ifname(&self) -> Option<String> {
+        let stations = self.stations().ok()?;
+
+        stations
+            .iter()
+            .filter(|station| !station.is_disabled())
+            .filter_map(|station| self.section_ifname(&station.name))
+            .next()
+    }
+
+    /// Saved networks `--forget-all` removes with the filter
+    fn forgettable_sections(&self, filter: &ForgetFilter) -> Result<Vec<UciSection>> {
+        if filter.older_than.is_some() {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "openwrt".into(),
+                "forgetting networks by when they were last used".into()
+            ));
+        }
+
+        Ok(self
+            .stations()?
+            .into_iter()
+            .filter(|station| !filter.keeps_ssid(station.option("ssid").unwrap_or("").as_bytes()))
+            .collect())
+    }
+
+    /// Writes and enables the station section of a network, switching off
+    /// the other stations of WiFi Connect, as only one can scan for its
+    /// network on the radio. Returns the device of the station.
+    fn activate_station(
+        &self,
+        section: &str,
+        ssid: &str,
+        encryption: &str,
+        network: &NetworkCredentials,
+        settings: &ProfileSettings,
+        switched_off: &[String],
+    ) -> Result<String> {
+        let error = || ErrorKind::ConnectionNotActivated(ssid.to_string(), "uci".into());
+
+        for station in switched_off {
+            uci_set(station, "disabled", "1").chain_err(error)?;
+        }
+
+        ensure_station_network().chain_err(error)?;
+        write_station(section, &self.radio, ssid, encryption, network).chain_err(error)?;
+
+        // Set before the first association, so the AP never sees the
+        // hardware address
+        if let Some(ref cloned_mac) = settings.cloned_mac {
+            uci_set(section, "macaddr", cloned_mac).chain_err(error)?;
+        }
+
+        uci(&["commit", "wireless"]).chain_err(error)?;
+        reload_network().chain_err(error)?;
+
+        // Wrong passphrases only show as the association never completing
+        match self.wait_for_ifname(section) {
+            Some(ifname) => Ok(ifname),
+            None => bail!(ErrorKind::ConnectionNotActivated(
+                ssid.to_string(),
+                "interface not up".into()
+            )),
+        }
+    }
+
+    fn delete_sections(&self, sections: &[UciSection]) -> Result<()> {
+        for section in sections {
+            info!(
+                "Deleting WiFi connection: {}",
+                section.option("ssid").unwrap_or(&section.name)
+            );
+            uci(&["delete", &format!("wireless.{}", section.name)])?;
+        }
+
+        if !sections.is_empty() {
+            uci(&["commit", "wireless"])?;
+            reload_network()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend for OpenWrtBackend {
+    fn name(&self) -> &'static str {
+        "openwrt"
+    }
+
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    // iwinfo scans synchronously, so the results are read right away
+    fn request_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<Vec<Network>> {
+        info!("Scanning for available networks...");
+
+        let results = ubus("iwinfo", "scan", &json!({ "device": self.interface }))?;
+
+        let mut scanned = results["results"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|result| {
+                let ssid = result["ssid"].as_str()?.to_string();
+                let rssi = result["signal"].as_i64().unwrap_or(-100) as i32;
+                Some((ssid, rssi, uci_encryption(&result["encryption"])))
+            })
+            .filter(|&(ref ssid, _, _)| !ssid.is_empty() && *ssid != self.portal_ssid)
+            .collect::<Vec<_>>();
+
+        // Strongest access point of each network first
+        scanned.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut networks = Vec::new();
+        self.encryptions.clear();
+
+        for (ssid, rssi, encryption) in scanned {
+            if self.encryptions.contains_key(&ssid) {
+                continue;
+            }
+
+            networks.push(Network::new(
+                ssid.as_bytes(),
+                uci_encryption_to_network_security(encryption),
+                rssi_to_percent(rssi),
+            ));
+            self.encryptions.insert(ssid, encryption);
+        }
+
+        Ok(networks)
+    }
+
+    fn connect(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) -> Result<()> {
+        // uci only takes SSIDs as text, so hex-encoded ones are decoded
+        let ssid = match String::from_utf8(parse_ssid(&network.ssid)?) {
+            Ok(decoded) => decoded,
+            Err(_) => bail!(ErrorKind::UnsupportedByBackend(
+                "openwrt".into(),
+                "SSIDs that are not valid UTF-8".into()
+            )),
+        };
+
+        let encryption = match self.encryptions.get(&ssid) {
+            Some(encryption) => *encryption,
+            None => bail!(ErrorKind::NetworkNotFound(ssid.clone())),
+        };
+
+        // The MAC address is a wifi-iface option and power saving is
+        // switched on the device once connected
+        let unsupported = ProfileSettings {
+            cloned_mac: None,
+            powersave: None,
+            ..settings.clone()
+        };
+
+        if !unsupported.is_empty() || settings.has_custom_eap() {
+            warn!("Connection profile settings are not supported by the openwrt backend");
+        }
+
+        info!("Connecting to access point '{}'...", ssid);
+
+        let section = station_section(&ssid);
+        let stations = self.stations()?;
+
+        // Kept to put things back as they were should the network not come up
+        let previous = stations.iter().find(|station| station.name == section);
+        let switched_off = stations
+            .iter()
+            .filter(|station| {
+                station.is_managed() && station.name != section && !station.is_disabled()
+            })
+            .map(|station| station.name.clone())
+            .collect::<Vec<_>>();
+
+        let ifname = match self.activate_station(
+            &section,
+            &ssid,
+            encryption,
+            network,
+            settings,
+            &switched_off,
+        ) {
+            Ok(ifname) => ifname,
+            Err(e) => {
+                if let Err(e) = restore_stations(&section, previous, &switched_off) {
+                    error!("Restoring the stations failed: {}", e);
+                }
+                return Err(e);
+            }
+        };
+
+        self.interface = ifname;
+
+        if let Some(enabled) = settings.powersave {
+            if let Err(e) = set_power_save(&self.interface, enabled) {
+                warn!("{}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_network(
+        &mut self,
+        network: &NetworkCredentials,
+        _settings: &ProfileSettings,
+    ) -> Result<()> {
+        let ssid = match String::from_utf8(parse_ssid(&network.ssid)?) {
+            Ok(decoded) => decoded,
+            Err(_) => bail!(ErrorKind::UnsupportedByBackend(
+                "openwrt".into(),
+                "SSIDs that are not valid UTF-8".into()
+            )),
+        };
+
+        // Without a scan result the passphrase tells WPA2 from open networks
+        let encryption = match self.encryptions.get(&ssid) {
+            Some(encryption) => *encryption,
+            None if network.passphrase.is_empty() => "none",
+            None => "psk2",
+        };
+
+        info!("Saving network '{}' for later use", ssid);
+
+        let section = station_section(&ssid);
+        let error = || ErrorKind::AddConnection(ssid.clone());
+
+        ensure_station_network().chain_err(error)?;
+        write_station(&section, &self.radio, &ssid, encryption, network).chain_err(error)?;
+
+        // Saved networks are enabled once connected to
+        uci_set(&section, "disabled", "1").chain_err(error)?;
+        uci(&["commit", "wireless"]).chain_err(error)?;
+
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        for station in self.stations()? {
+            if station.is_managed() && !station.is_disabled() {
+                uci_set(&station.name, "disabled", "1")?;
+            }
+        }
+
+        uci(&["commit", "wireless"])?;
+        reload_network()
+    }
+
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool> {
+        let object = format!("network.interface.{}", STATION_NETWORK);
+        let mut total_time = 0;
+
+        // netifd has no connectivity check, so wait for the DHCP lease
+        loop {
+            let status = ubus(&object, "status", &json!({})).unwrap_or(Value::Null);
+            let has_address = status["ipv4-address"]
+                .as_array()
+                .map_or(false, |addresses| !addresses.is_empty());
+
+            if status["up"] == true && has_address {
+                return Ok(true);
+            } else if total_time >= timeout {
+                return Ok(false);
+            }
+
+            if self.shutdown.sleep(Duration::from_secs(1)) {
+                return Ok(false);
+            }
+
+            total_time += 1;
+        }
+    }
+
+    fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        let ifname = match self.station_ifname() {
+            Some(ifname) => ifname,
+            None => return Ok(None),
+        };
+
+        let info = ubus("iwinfo", "info", &json!({ "device": ifname }))?;
+
+        let ssid = match info["ssid"].as_str() {
+            Some(ssid) if !ssid.is_empty() => ssid.to_string(),
+            _ => return Ok(None),
+        };
+
+        // Stations not associated yet report no signal
+        let rssi = match info["signal"].as_i64() {
+            Some(rssi) => rssi as i32,
+            None => return Ok(None),
+        };
+
+        Ok(Some(ConnectedNetwork {
+            ssid,
+            security: uci_encryption_to_network_security(uci_encryption(&info["encryption"]))
+                .to_string(),
+            signal_strength: rssi_to_percent(rssi),
+            interface: ifname.clone(),
+            ip_address: get_interface_ipv4(&ifname),
+            ipv6_addresses: get_interface_ipv6(&ifname),
+            // Only NetworkManager keeps IP config objects
+            ipv4: IpConfig::default(),
+            ipv6: IpConfig::default(),
+            link: get_link_statistics(&ifname),
+            vpn: None,
+        }))
+    }
+
+    fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        let mut saved_networks = self
+            .stations()?
+            .into_iter()
+            .filter_map(|station| {
+                Some(SavedNetwork {
+                    ssid: station.option("ssid")?.to_string(),
+                    security: uci_encryption_to_network_security(
+                        station.option("encryption").unwrap_or("none"),
+                    )
+                    .to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        saved_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+        saved_networks.dedup_by(|a, b| a.ssid == b.ssid);
+
+        Ok(saved_networks)
+    }
+
+    fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        let stations = self.stations()?;
+
+        let station = match stations
+            .iter()
+            .find(|station| station.option("ssid") == Some(ssid))
+        {
+            Some(station) => station,
+            None => return Ok(None),
+        };
+
+        // Addressing is configured on the netifd interface of the station
+        let network = station.option("network").unwrap_or(STATION_NETWORK);
+        let interface = uci_show("network")?
+            .into_iter()
+            .find(|section| section.kind == "interface" && section.name == network);
+
+        let ipv4_method = interface.as_ref().map(|interface| {
+            match interface.option("proto") {
+                Some("static") => "manual",
+                _ => "auto",
+            }
+            .to_string()
+        });
+
+        Ok(Some(SavedNetworkDetails {
+            ssid: ssid.to_string(),
+            security: uci_encryption_to_network_security(
+                station.option("encryption").unwrap_or("none"),
+            )
+            .to_string(),
+            autoconnect: !station.is_disabled(),
+            ipv4_method,
+            dns: interface
+                .as_ref()
+                .and_then(|interface| interface.option("dns"))
+                .map(|servers| servers.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            cloned_mac: station.option("macaddr").map(String::from),
+            ..Default::default()
+        }))
+    }
+
+    fn update_network(&self, ssid: &str, update: &ProfileUpdate) -> Result<bool> {
+        if let Err(reason) = update.validate() {
+            bail!(ErrorKind::InvalidProfileUpdate(reason));
+        }
+
+        // Addressing belongs to the netifd interface shared by all saved
+        // networks, so only the wifi-iface options can be changed
+        let (passphrase, autoconnect) = match *update {
+            ProfileUpdate {
+                ref passphrase,
+                autoconnect_priority: None,
+                autoconnect,
+                address: None,
+                gateway: None,
+                ref dns,
+                dhcp: false,
+            } if dns.is_empty() => (passphrase, autoconnect),
+            _ => bail!(ErrorKind::UnsupportedByBackend(
+                "openwrt".into(),
+                "changing settings of saved networks other than the passphrase and autoconnect"
+                    .into()
+            )),
+        };
+
+        let stations = self.stations()?;
+        let matching = stations
+            .iter()
+            .filter(|station| station.option("ssid") == Some(ssid))
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            warn!("Network '{}' not found in saved connections", ssid);
+            return Ok(false);
+        }
+
+        info!("Updating WiFi network: {}", ssid);
+
+        for station in matching {
+            if let Some(ref passphrase) = *passphrase {
+                uci_set(&station.name, "key", passphrase.expose())?;
+            }
+
+            if let Some(autoconnect) = autoconnect {
+                uci_set(
+                    &station.name,
+                    "disabled",
+                    if autoconnect { "0" } else { "1" },
+                )?;
+            }
+        }
+
+        uci(&["commit", "wireless"])?;
+        reload_network()?;
+
+        Ok(true)
+    }
+
+    fn forget_network(&self, ssid: &str) -> Result<bool> {
+        let matching = self
+            .stations()?
+            .into_iter()
+            .filter(|station| station.option("ssid") == Some(ssid))
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            warn!("Network '{}' not found in saved connections", ssid);
+            return Ok(false);
+        }
+
+        info!("Forgetting WiFi network: {}", ssid);
+        self.delete_sections(&matching)?;
+
+        Ok(true)
+    }
+
+    fn forget_all(&self, filter: &ForgetFilter) -> Result<()> {
+        info!("Forgetting all WiFi connections...");
+
+        let sections = self.forgettable_sections(filter)?;
+        self.delete_sections(&sections)
+    }
+
+    fn managed_networks(&self) -> Result<Vec<SavedNetwork>> {
+        Ok(self
+            .stations()?
+            .into_iter()
+            .filter(|station| station.is_managed())
+            .filter_map(|station| {
+                Some(SavedNetwork {
+                    ssid: station.option("ssid")?.to_string(),
+                    security: uci_encryption_to_network_security(
+                        station.option("encryption").unwrap_or("none"),
+                    )
+                    .to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn forget_managed(&self) -> Result<usize> {
+        let managed = self
+            .stations()?
+            .into_iter()
+            .filter(|station| station.is_managed())
+            .collect::<Vec<_>>();
+
+        self.delete_sections(&managed)?;
+
+        Ok(managed.len())
+    }
+
+    fn profiles_in_scope(&self, scope: ForgetScope) -> Result<Vec<String>> {
+        let sections = match scope {
+            ForgetScope::All(filter) => self.forgettable_sections(filter)?,
+            ForgetScope::Network(ssid) => self
+                .stations()?
+                .into_iter()
+                .filter(|station| station.option("ssid") == Some(ssid))
+                .collect(),
+            ForgetScope::Managed => self
+                .stations()?
+                .into_iter()
+                .filter(|station| station.is_managed())
+                .collect(),
+            ForgetScope::Portal(ssid) => uci_show("wireless")?
+                .into_iter()
+                .filter(|section| section.name == PORTAL_SECTION)
+                .filter(|section| section.option("ssid") == Some(ssid))
+                .collect(),
+        };
+
+        Ok(sections
+            .iter()
+            .map(|section| {
+                format!(
+                    "wireless.{} {}",
+                    section.name,
+                    section.option("ssid").unwrap_or("")
+                )
+            })
+            .collect())
+    }
+
+    fn start_hotspot(&mut self, config: &Config) -> Result<()> {
+        if require_hotspot_mode(&self.interface, config)? == HotspotMode::WiFiDirect {
+            bail!(ErrorKind::UnsupportedByBackend(
+                "openwrt".into(),
+                "the WiFi Direct fallback".into()
+            ));
+        }
+
+        // The channel and band are options of the radio, shared with the
+        // other networks of the image
+        if config.portal_channel.is_some() {
+            warn!("Setting the access point channel is not supported by the openwrt backend");
+        }
+
+        if config.portal_band.is_some() {
+            warn!("Setting the access point band is not supported by the openwrt backend");
+        }
+
+        info!("Starting access point...");
+
+        let error = || ErrorKind::CreateCaptivePortal;
+
+        self.suspended.clear();
+        for station in self.stations()? {
+            if station.is_managed() && !station.is_disabled() {
+                uci_set(&station.name, "disabled", "1").chain_err(error)?;
+                self.suspended.push(station.name);
+            }
+        }
+
+        exclude_from_system_dnsmasq(true).chain_err(error)?;
+
+        let network = format!("network.{}", PORTAL_NETWORK);
+        uci(&["set", &format!("{}=interface", network)]).chain_err(error)?;
+        uci(&["set", &format!("{}.proto=static", network)]).chain_err(error)?;
+        uci(&["set", &format!("{}.ipaddr={}", network, config.gateway)]).chain_err(error)?;
+        uci(&["set", &format!("{}.netmask=255.255.255.0", network)]).chain_err(error)?;
+        uci(&["commit", "network"]).chain_err(error)?;
+
+        let (encryption, key) = match config.passphrase {
+            Some(ref passphrase) => ("psk2", Some(passphrase.expose())),
+            None if config.portal_owe => ("owe", None),
+            None => ("none", None),
+        };
+
+        uci(&["set", &format!("wireless.{}=wifi-iface", PORTAL_SECTION)]).chain_err(error)?;
+        uci_set(PORTAL_SECTION, "device", &self.radio).chain_err(error)?;
+        uci_set(PORTAL_SECTION, "mode", "ap").chain_err(error)?;
+        uci_set(PORTAL_SECTION, "network", PORTAL_NETWORK).chain_err(error)?;
+        uci_set(PORTAL_SECTION, "ssid", &config.ssid).chain_err(error)?;
+        uci_set(PORTAL_SECTION, "encryption", encryption).chain_err(error)?;
+        uci_set(
+            PORTAL_SECTION,
+            "isolate",
+            if config.client_isolation { "1" } else { "0" },
+        )
+        .chain_err(error)?;
+
+        if let Some(key) = key {
+            uci_set(PORTAL_SECTION, "key", key).chain_err(error)?;
+        }
+
+        uci(&["commit", "wireless"]).chain_err(error)?;
+        reload_network().chain_err(error)?;
+
+        // dnsmasq and the client monitor are bound to the access point
+        self.interface = match self.wait_for_ifname(PORTAL_SECTION) {
+            Some(ifname) => ifname,
+            None => bail!(ErrorKind::CreateCaptivePortal),
+        };
+
+        info!("Access point '{}' created", config.ssid);
+        Ok(())
+    }
+
+    fn stop_hotspot(&mut self, config: &Config) -> Result<()> {
+        info!("Stopping access point '{}'...", config.ssid);
+
+        let error = || ErrorKind::StopAccessPoint;
+
+        uci(&["delete", &format!("wireless.{}", PORTAL_SECTION)]).chain_err(error)?;
+        uci(&["delete", &format!("network.{}", PORTAL_NETWORK)]).chain_err(error)?;
+
+        for station in self.suspended.drain(..) {
+            uci_set(&station, "disabled", "0").chain_err(error)?;
+        }
+
+        uci(&["commit", "wireless"]).chain_err(error)?;
+        uci(&["commit", "network"]).chain_err(error)?;
+        reload_network().chain_err(error)?;
+
+        exclude_from_system_dnsmasq(false).chain_err(error)?;
+
+        self.interface = self.station_ifname().unwrap_or_else(|| self.radio.clone());
+
+        info!("Access point '{}' stopped", config.ssid);
+        Ok(())
+    }
+
+    fn is_hotspot_running(&self, ssid: &str) -> bool {
+        let same_ssid = uci(&["-q", "get", &format!("wireless.{}.ssid", PORTAL_SECTION)])
+            .map_or(false, |value| value.trim() == ssid);
+
+        same_ssid && self.section_ifname(PORTAL_SECTION).is_some()
+    }
+}
+
+fn ubus(object: &str, method: &str, args: &Value) -> Result<Value> {
+    let call = format!("{} {}", object, method);
+
+    let output = Command::new("ubus")
+        .args(&["call", object, method, &args.to_string()])
+        .output()
+        .chain_err(|| ErrorKind::Ubus(call.clone()))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::Ubus(call));
+    }
+
+    // Calls without a reply print nothing
+    if output.stdout.iter().all(|byte| byte.is_ascii_whitespace()) {
+        return Ok(Value::Null);
+    }
+
+    serde_json::from_slice(&output.stdout).chain_err(|| ErrorKind::Ubus(call))
+}
+
+fn uci(args: &[&str]) -> Result<String> {
+    let output = Command::new("uci")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::Uci(args.join(" ")))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::Uci(args.join(" ")));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Sets an option of a section of the wireless config, uncommitted
+fn uci_set(section: &str, option: &str, value: &str) -> Result<()> {
+    let assignment = format!("wireless.{}.{}={}", section, option, value);
+
+    // Passphrases are kept out of the error message
+    let output = Command::new("uci")
+        .args(&["set", &assignment])
+        .output()
+        .chain_err(|| ErrorKind::Uci(format!("set wireless.{}.{}", section, option)))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::Uci(format!(
+            "set wireless.{}.{}",
+            section, option
+        )));
+    }
+
+    Ok(())
+}
+
+/// Sections of a uci config with their options. Anonymous sections are
+/// listed by their generated names with `-X`.
+fn uci_show(config: &str) -> Result<Vec<UciSection>> {
+    let output = uci(&["-X", "show", config])?;
+    let mut sections: Vec<UciSection> = Vec::new();
+
+    for line in output.lines() {
+        let (key, value) = match line.find('=') {
+            Some(index) => (&line[..index], unquote(&line[index + 1..])),
+            None => continue,
+        };
+
+        let mut parts = key.splitn(3, '.').skip(1);
+
+        match (parts.next(), parts.next()) {
+            (Some(name), None) => sections.push(UciSection {
+                name: name.to_string(),
+                kind: value,
+                options: HashMap::new(),
+            }),
+            (Some(name), Some(option)) => {
+                if let Some(section) = sections
+                    .iter_mut()
+                    .rev()
+                    .find(|section| section.name == name)
+                {
+                    section.options.insert(option.to_string(), value);
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Values are single quoted, lists as several quoted items separated by
+/// spaces, and quotes within them written as `'\''`
+fn unquote(value: &str) -> String {
+    value
+        .split("' '")
+        .map(|item| item.trim_matches('\'').replace("'\\''", "'"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reloads netifd, which applies the committed wireless and network configs
+fn reload_network() -> Result<()> {
+    ubus("network", "reload", &json!({})).map(|_| ())
+}
+
+/// Radios with the network devices of their wifi-ifaces that are up
+fn wireless_status() -> Result<HashMap<String, Vec<String>>> {
+    let status = ubus("network.wireless", "status", &json!({}))?;
+
+    let radios = match status.as_object() {
+        Some(radios) => radios,
+        None => return Ok(HashMap::new()),
+    };
+
+    Ok(radios
+        .iter()
+        .map(|(radio, state)| {
+            let ifnames = state["interfaces"]
+                .as_array()
+                .map(|interfaces| {
+                    interfaces
+                        .iter()
+                        .filter_map(|iface| iface["ifname"].as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            (radio.clone(), ifnames)
+        })
+        .collect())
+}
+
+/// Puts things back after a failed connection attempt: the section of the
+/// network as it was before, or none if it was new, and the stations
+/// switched off for it enabled again
+fn restore_stations(
+    section: &str,
+    previous: Option<&UciSection>,
+    switched_off: &[String],
+) -> Result<()> {
+    // Fails when the section was never written
+    let _ = uci(&["-q", "delete", &format!("wireless.{}", section)]);
+
+    if let Some(previous) = previous {
+        uci(&["set", &format!("wireless.{}={}", section, previous.kind)])?;
+
+        for (option, value) in &previous.options {
+            uci_set(section, option, value)?;
+        }
+    }
+
+    for station in switched_off {
+        uci_set(station, "disabled", "0")?;
+    }
+
+    uci(&["commit", "wireless"])?;
+    reload_network()
+}
+
+/// Excludes the portal interface from the system dnsmasq, or includes it
+/// again, so that it does not take the DNS port on the portal address
+fn exclude_from_system_dnsmasq(exclude: bool) -> Result<()> {
+    let entry = format!("{}.notinterface={}", SYSTEM_DNSMASQ, PORTAL_NETWORK);
+
+    // Removed first, so that the entry is never listed twice. Fails when it
+    // is not listed.
+    let _ = uci(&["-q", "del_list", &entry]);
+
+    if exclude {
+        uci(&["add_list", &entry])?;
+    }
+
+    uci(&["commit", "dhcp"])?;
+
+    match Command::new("/etc/init.d/dnsmasq").arg("reload").status() {
+        Ok(ref status) if status.success() => {}
+        _ => warn!("Reloading the system dnsmasq failed"),
+    }
+
+    Ok(())
+}
+
+/// Creates the DHCP interface of client connections unless the image has one
+fn ensure_station_network() -> Result<()> {
+    let network = format!("network.{}", STATION_NETWORK);
+
+    if uci(&["-q", "get", &network]).is_ok() {
+        return Ok(());
+    }
+
+    info!(
+        "Creating the '{}' interface for client connections",
+        STATION_NETWORK
+    );
+
+    uci(&["set", &format!("{}=interface", network)])?;
+    uci(&["set", &format!("{}.proto=dhcp", network)])?;
+    uci(&["commit", "network"]).map(|_| ())
+}
+
+/// Writes the station section of a network, enabled
+fn write_station(
+    section: &str,
+    radio: &str,
+    ssid: &str,
+    encryption: &str,
+    network: &NetworkCredentials,
+) -> Result<()> {
+    uci(&["set", &format!("wireless.{}=wifi-iface", section)])?;
+    uci_set(section, "device", radio)?;
+    uci_set(section, "mode", "sta")?;
+    uci_set(section, "network", STATION_NETWORK)?;
+    uci_set(section, "ssid", ssid)?;
+    uci_set(section, "encryption", encryption)?;
+    uci_set(section, "disabled", "0")?;
+
+    let passphrase = network.passphrase.expose();
+
+    match uci_encryption_to_network_security(encryption) {
+        "wpa" => uci_set(section, "key", passphrase)?,
+        // Keys of 10 or 26 hex digits are taken as they are, others as text
+        "wep" => {
+            let hex = (passphrase.len() == 10 || passphrase.len() == 26)
+                && passphrase.chars().all(|c| c.is_ascii_hexdigit());
+            let key = if hex {
+                passphrase.to_string()
+            } else {
+                format!("s:{}", passphrase)
+            };
+
+            uci_set(section, "key", "1")?;
+            uci_set(section, "key1", &key)?;
+        }
+        // PEAP with MSCHAPv2 inside, like the other backends
+        "enterprise" => {
+            uci_set(section, "eap_type", "peap")?;
+            uci_set(section, "auth", "EAP-MSCHAPV2")?;
+            uci_set(section, "identity", &network.identity)?;
+            uci_set(section, "password", passphrase)?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// uci section of a network saved by WiFi Connect. Section names are limited
+/// to alphanumerics and underscores, so the SSID is hex encoded.
+fn station_section(ssid: &str) -> String {
+    let hex = ssid
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("{}{}", SECTION_PREFIX, hex)
+}
+
+/// uci encryption for joining a network as reported by iwinfo, preferring
+/// WPA3 only where WPA2 is not offered
+fn uci_encryption(encryption: &Value) -> &'static str {
+    if encryption["enabled"] != true {
+        return "none";
+    }
+
+    let authentication = encryption["authentication"]
+        .as_array()
+        .map(|suites| {
+            suites
+                .iter()
+                .filter_map(|suite| suite.as_str())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let wpa = encryption["wpa"]
+        .as_array()
+        .map(|versions| {
+            versions
+                .iter()
+                .filter_map(|version| version.as_u64())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if authentication.contains(&"802.1x") {
+        if wpa == [3] {
+            "wpa3"
+        } else {
+            "wpa2"
+        }
+    } else if authentication.contains(&"sae") {
+        if authentication.contains(&"psk") {
+            "sae-mixed"
+        } else {
+            "sae"
+        }
+    } else if authentication.contains(&"owe") {
+        "owe"
+    } else if authentication.contains(&"psk") {
+        if wpa.contains(&2) {
+            "psk2"
+        } else {
+            "psk"
+        }
+    } else {
+        "wep-open"
+    }
+}
+
+fn uci_encryption_to_network_security(encryption: &str) -> &'static str {
+    if encryption.starts_with("psk") || encryption.starts_with("sae") {
+        "wpa"
+    } else if encryption.starts_with("wpa") {
+        "enterprise"
+    } else if encryption.starts_with("wep") {
+        "wep"
+    } else {
+        "none"
+    }
+}
@@ -0,0 +1,102 @@
+//! SSIDs WiFi Connect never connects to, e.g. carrier auto-connect networks
+//! or a known rogue access point. `--block-ssid` and `--unblock-ssid` update
+//! the blocklist file, so that a network stays blocked across restarts.
+//! Blocked networks are left out of the scan results of the portal and
+//! connecting to them is refused.
+
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::Path;
+
+use serde_json;
+
+use errors::*;
+use low_memory::to_json_file;
+use ssid::{matches_ssid, parse_ssid};
+
+pub const DEFAULT_BLOCKLIST_FILE: &str = "/var/lib/wifi-connect/blocked-ssids.json";
+
+/// Blocked SSIDs stored in `path`. A missing file blocks nothing.
+pub fn load_blocklist(path: &Path) -> Result<Vec<String>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).chain_err(|| ErrorKind::Blocklist(path.display().to_string())),
+    };
+
+    serde_json::from_str(&contents).chain_err(|| ErrorKind::Blocklist(path.display().to_string()))
+}
+
+/// Adds `block` to and removes `unblock` from the blocklist in `path` and
+/// returns the blocked SSIDs. The file is only written when it changes.
+pub fn update_blocklist(path: &Path, block: &[String], unblock: &[String]) -> Result<Vec<String>> {
+    let mut blocked = load_blocklist(path)?;
+    let previous = blocked.clone();
+
+    for ssid in block {
+        if !blocked.contains(ssid) {
+            info!("Blocking '{}'", ssid);
+            blocked.push(ssid.clone());
+        }
+    }
+
+    blocked.retain(|ssid| {
+        let unblocked = unblock.contains(ssid);
+        if unblocked {
+            info!("Unblocking '{}'", ssid);
+        }
+        !unblocked
+    });
+
+    if blocked == previous {
+        return Ok(blocked);
+    }
+
+    let error = || ErrorKind::Blocklist(path.display().to_string());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(error)?;
+    }
+
+    let json = to_json_file(&blocked).chain_err(error)?;
+
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, json).chain_err(error)?;
+    fs::rename(&temporary, path).chain_err(error)?;
+
+    Ok(blocked)
+}
+
+/// Whether `ssid`, as given by the user or listed by the portal, refers to a
+/// blocked network. Both sides are decoded, so that neither the `hex:` form
+/// nor the `\xNN` escapes of a blocked SSID get past the blocklist.
+pub fn is_blocked(blocked: &[String], ssid: &str) -> bool {
+    let raw = decode_ssid(ssid);
+
+    blocked
+        .iter()
+        .any(|blocked| matches_ssid(&raw, blocked) || matches_ssid(&decode_ssid(blocked), ssid))
+}
+
+/// Raw bytes of an SSID, taking malformed hex as plain text
+fn decode_ssid(ssid: &str) -> Vec<u8> {
+    parse_ssid(ssid).unwrap_or_else(|_| ssid.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_blocked;
+
+    #[test]
+    fn blocks_ssid_in_any_form() {
+        let blocked = vec!["Carrier WiFi".to_string(), "hex:4c6162ff".to_string()];
+
+        assert!(is_blocked(&blocked, "Carrier WiFi"));
+        assert!(is_blocked(&blocked, "hex:436172726965722057694669"));
+        assert!(is_blocked(&blocked, "hex:4C6162FF"));
+        assert!(is_blocked(&blocked, "Lab\\xff"));
+
+        assert!(!is_blocked(&blocked, "Carrier"));
+        assert!(!is_blocked(&blocked, "hex:4c6162"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Captive Portal API (RFC 8908). The API URI is handed out with DHCP option
+//! 114 (RFC 8910) and the portal URL with option 160 (RFC 7710), so that
+//! clients supporting them learn about the portal without probing and show
+//! it more reliably.
+
+use config::Config;
+
+/// Path of the Captive Portal API on the portal server
+pub const CAPPORT_PATH: &str = "/.well-known/captive-portal";
+
+/// DHCP option carrying the Captive Portal API URI
+const DHCP_OPTION_CAPTIVE_PORTAL: u8 = 114;
+
+/// DHCP option of RFC 7710 carrying the portal URL, still honored by older
+/// clients
+const DHCP_OPTION_CAPTIVE_PORTAL_LEGACY: u8 = 160;
+
+const HTTP_PORT: u16 = 80;
+
+/// Served at `CAPPORT_PATH` as `application/captive+json`
+#[derive(Serialize)]
+pub struct CaptivePortalState {
+    pub captive: bool,
+    #[serde(rename = "user-portal-url")]
+    pub user_portal_url: String,
+    #[serde(rename = "venue-info-url", skip_serializing_if = "Option::is_none")]
+    pub venue_info_url: Option<String>,
+    #[serde(rename = "can-extend-session")]
+    pub can_extend_session: bool,
+}
+
+/// Clients on the hotspot stay captive until the portal is gone
+pub fn captive_portal_state(config: &Config) -> CaptivePortalState {
+    CaptivePortalState {
+        captive: true,
+        user_portal_url: portal_url(config),
+        venue_info_url: config.venue_info_url.clone(),
+        can_extend_session: false,
+    }
+}
+
+/// dnsmasq arguments advertising the Captive Portal API URI and the portal
+/// URL
+pub fn capport_dhcp_options(config: &Config) -> Vec<String> {
+    let portal_url = portal_url(config);
+
+    vec![
+        format!(
+            "--dhcp-option={},\"{}{}\"",
+            DHCP_OPTION_CAPTIVE_PORTAL,
+            origin(&portal_url),
+            CAPPORT_PATH
+        ),
+        format!(
+            "--dhcp-option={},\"{}\"",
+            DHCP_OPTION_CAPTIVE_PORTAL_LEGACY, portal_url
+        ),
+    ]
+}
+
+/// `--portal-url`, defaulting to the portal server on the gateway
+fn portal_url(config: &Config) -> String {
+    if let Some(ref portal_url) = config.portal_url {
+        return portal_url.clone();
+    }
+
+    if config.listening_port == HTTP_PORT {
+        format!("http://{}/", config.gateway)
+    } else {
+        format!("http://{}:{}/", config.gateway, config.listening_port)
+    }
+}
+
+/// Only HTTP URLs are opened by the clients honoring the DHCP options
+pub fn validate_portal_url(value: String) -> ::std::result::Result<(), String> {
+    let host = value
+        .splitn(2, "://")
+        .nth(1)
+        .map(|rest| rest.split('/').next().unwrap_or(""))
+        .unwrap_or("");
+
+    if (value.starts_with("http://") || value.starts_with("https://")) && !host.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not an HTTP URL", value))
+    }
+}
+
+/// Scheme and authority of an HTTP URL
+fn origin(url: &str) -> &str {
+    let authority_start = url.find("://").map_or(0, |index| index + 3);
+
+    match url[authority_start..].find('/') {
+        Some(index) => &url[..authority_start + index],
+        None => url,
+    }
+}
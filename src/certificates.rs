@@ -0,0 +1,151 @@
+//! Certificates for EAP-TLS networks, uploaded through the portal. They are
+//! kept per SSID in `--certificate-dir`, readable by root only, and once a
+//! client certificate and its private key are uploaded connecting to the
+//! network authenticates with them, the passphrase unlocking the key.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use errors::*;
+use ssid::hex_ssid;
+
+pub const DEFAULT_CERTIFICATE_DIRECTORY: &str = "/var/lib/wifi-connect/certificates";
+
+/// Largest certificate or key accepted, a chain of a few certificates
+pub const MAX_CERTIFICATE_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CertificateKind {
+    /// CA certificate the RADIUS server is verified against
+    Ca,
+    Client,
+    PrivateKey,
+}
+
+impl CertificateKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ca" => Some(CertificateKind::Ca),
+            "client" => Some(CertificateKind::Client),
+            "key" => Some(CertificateKind::PrivateKey),
+            _ => None,
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match *self {
+            CertificateKind::Ca => "ca.pem",
+            CertificateKind::Client => "client.pem",
+            CertificateKind::PrivateKey => "client.key",
+        }
+    }
+
+    /// Whether `label` of a PEM block is one this kind is stored as
+    fn accepts(&self, label: &str) -> bool {
+        match *self {
+            CertificateKind::Ca | CertificateKind::Client => label == "CERTIFICATE",
+            CertificateKind::PrivateKey => label.ends_with("PRIVATE KEY"),
+        }
+    }
+}
+
+/// Certificates uploaded for an EAP-TLS network
+#[derive(Clone, Debug, PartialEq)]
+pub struct EapTls {
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: PathBuf,
+    pub private_key: PathBuf,
+}
+
+/// Checks that `contents` is PEM of the expected kind and size
+pub fn validate_certificate(
+    kind: CertificateKind,
+    contents: &[u8],
+) -> ::std::result::Result<(), String> {
+    if contents.len() > MAX_CERTIFICATE_SIZE {
+        return Err(format!(
+            "Certificates are limited to {} bytes",
+            MAX_CERTIFICATE_SIZE
+        ));
+    }
+
+    let text = match ::std::str::from_utf8(contents) {
+        Ok(text) => text,
+        Err(_) => return Err("Certificates have to be PEM encoded".into()),
+    };
+
+    let labels = text
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with("-----BEGIN ") && line.ends_with("-----") {
+                Some(&line[11..line.len() - 5])
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if labels.is_empty() {
+        return Err("Certificates have to be PEM encoded".into());
+    }
+
+    if let Some(label) = labels.iter().find(|label| !kind.accepts(label)) {
+        return Err(format!("Unexpected '{}' in the uploaded file", label));
+    }
+
+    Ok(())
+}
+
+/// Stores the certificate for the network and returns its path. Existing
+/// ones of the same kind are replaced.
+pub fn store_certificate(
+    directory: &Path,
+    ssid: &str,
+    kind: CertificateKind,
+    contents: &[u8],
+) -> Result<PathBuf> {
+    let network_directory = network_directory(directory, ssid);
+    let path = network_directory.join(kind.file_name());
+    let error = || ErrorKind::Certificate(path.display().to_string());
+
+    fs::create_dir_all(&network_directory).chain_err(error)?;
+    fs::set_permissions(directory, fs::Permissions::from_mode(0o700)).chain_err(error)?;
+
+    // Restricted before the contents are written
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, b"").chain_err(error)?;
+    fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600)).chain_err(error)?;
+    fs::write(&temporary, contents).chain_err(error)?;
+    fs::rename(&temporary, &path).chain_err(error)?;
+
+    info!("Stored {:?} certificate for '{}'", kind, ssid);
+
+    Ok(path)
+}
+
+/// Certificates to connect to the network over EAP-TLS with, if a client
+/// certificate and its private key were uploaded for it
+pub fn eap_tls_certificates(directory: &Path, ssid: &str) -> Option<EapTls> {
+    let network_directory = network_directory(directory, ssid);
+    let existing = |kind: CertificateKind| {
+        let path = network_directory.join(kind.file_name());
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    };
+
+    Some(EapTls {
+        ca_cert: existing(CertificateKind::Ca),
+        client_cert: existing(CertificateKind::Client)?,
+        private_key: existing(CertificateKind::PrivateKey)?,
+    })
+}
+
+/// Named after the hex encoded SSID, which may contain any byte
+fn network_directory(directory: &Path, ssid: &str) -> PathBuf {
+    directory.join(hex_ssid(ssid.as_bytes()))
+}
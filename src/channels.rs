@@ -0,0 +1,328 @@
+use std::process::Command;
+use std::str::FromStr;
+
+use errors::*;
+use ssid::display_ssid;
+
+/// Non-overlapping 2.4 GHz channels
+const CANDIDATES_2GHZ: &[u32] = &[1, 6, 11];
+
+/// 5 GHz channels without DFS, which access points may use right away
+const CANDIDATES_5GHZ: &[u32] = &[36, 40, 44, 48, 149, 153, 157, 161, 165];
+
+/// 2.4 GHz channels this far apart or further do not overlap
+const OVERLAP_2GHZ: u32 = 5;
+
+/// Channel of the portal access point given with `--portal-channel`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortalChannel {
+    /// The least congested channel of the portal band, picked when starting
+    /// the hotspot
+    Auto,
+    Fixed(u32),
+}
+
+impl FromStr for PortalChannel {
+    type Err = String;
+
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        if value == "auto" {
+            return Ok(PortalChannel::Auto);
+        }
+
+        match value.parse::<u32>() {
+            Ok(channel) if band_of_channel(channel).is_some() => Ok(PortalChannel::Fixed(channel)),
+            _ => Err(format!("'{}' is not a WiFi channel or 'auto'", value)),
+        }
+    }
+}
+
+pub fn validate_portal_channel(value: String) -> ::std::result::Result<(), String> {
+    value.parse::<PortalChannel>().map(|_| ())
+}
+
+/// Band of the portal access point given with `--portal-band`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortalBand {
+    Single(Band),
+    /// 2.4 GHz on the portal interface plus 5 GHz on a virtual AP interface
+    /// with the same SSID
+    Both,
+}
+
+impl PortalBand {
+    /// Band of the access point on the portal interface
+    pub fn primary(&self) -> Band {
+        match *self {
+            PortalBand::Single(band) => band,
+            PortalBand::Both => Band::TwoGHz,
+        }
+    }
+}
+
+impl FromStr for PortalBand {
+    type Err = String;
+
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        match value {
+            "2.4" => Ok(PortalBand::Single(Band::TwoGHz)),
+            "5" => Ok(PortalBand::Single(Band::FiveGHz)),
+            "both" => Ok(PortalBand::Both),
+            _ => Err(format!("'{}' is not '2.4', '5' or 'both'", value)),
+        }
+    }
+}
+
+pub fn validate_portal_band(value: String) -> ::std::result::Result<(), String> {
+    value.parse::<PortalBand>().map(|_| ())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Band {
+    TwoGHz,
+    FiveGHz,
+}
+
+impl Band {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Band::TwoGHz => "2.4 GHz",
+            Band::FiveGHz => "5 GHz",
+        }
+    }
+
+    /// Value of the `802-11-wireless.band` NetworkManager property
+    pub fn nm_band(&self) -> &'static str {
+        match *self {
+            Band::TwoGHz => "bg",
+            Band::FiveGHz => "a",
+        }
+    }
+
+    fn candidates(&self) -> &'static [u32] {
+        match *self {
+            Band::TwoGHz => CANDIDATES_2GHZ,
+            Band::FiveGHz => CANDIDATES_5GHZ,
+        }
+    }
+}
+
+pub fn band_of_channel(channel: u32) -> Option<Band> {
+    match channel {
+        1..=14 => Some(Band::TwoGHz),
+        32..=177 => Some(Band::FiveGHz),
+        _ => None,
+    }
+}
+
+/// Access point in the kernel's scan results
+#[derive(Clone, Debug, Serialize)]
+pub struct ScannedAccessPoint {
+    pub bssid: String,
+    pub ssid: String,
+    pub channel: u32,
+    /// Signal in dBm
+    pub signal: i32,
+    /// `none`, `wep`, `wpa` or `enterprise`, as in the scan results
+    pub security: String,
+    /// Key management of the RSN and WPA elements as printed by `iw`, e.g.
+    /// `PSK` and `SAE` for a WPA2/WPA3 transition network
+    pub authentication_suites: Vec<String>,
+}
+
+/// Access points on a channel and how congested it is for a new one,
+/// counting the signal of those on overlapping channels too
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelUsage {
+    pub channel: u32,
+    pub access_points: Vec<ScannedAccessPoint>,
+    pub congestion: u32,
+}
+
+impl ChannelUsage {
+    pub fn band(&self) -> Band {
+        band_of_channel(self.channel).unwrap_or(Band::TwoGHz)
+    }
+}
+
+pub fn rssi_to_percent(rssi: i32) -> u8 {
+    (2 * (rssi + 100)).max(0).min(100) as u8
+}
+
+/// Reads the access points from the last scan of the interface with
+/// `iw scan dump`, which works whichever daemon manages the interface
+pub fn scan_access_points(interface: &str) -> Result<Vec<ScannedAccessPoint>> {
+    let output = Command::new("iw")
+        .args(&["dev", interface, "scan", "dump"])
+        .output()
+        .chain_err(|| ErrorKind::ChannelScan(interface.into()))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::ChannelScan(interface.into()));
+    }
+
+    Ok(parse_scan_dump(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_scan_dump(output: &str) -> Vec<ScannedAccessPoint> {
+    let mut access_points = Vec::new();
+    let mut current: Option<ScannedAccessPoint> = None;
+
+    for line in output.lines() {
+        if line.starts_with("BSS ") {
+            access_points.extend(current.take().filter(|ap| ap.channel != 0));
+
+            let bssid = line[4..].split('(').next().unwrap_or("").trim().to_string();
+
+            current = Some(ScannedAccessPoint {
+                bssid,
+                ssid: String::new(),
+                channel: 0,
+                signal: -100,
+                security: "none".to_string(),
+                authentication_suites: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let ap = match current {
+            Some(ref mut ap) => ap,
+            None => continue,
+        };
+
+        let line = line.trim();
+
+        if line.starts_with("freq:") {
+            // Newer versions of iw print fractional frequencies
+            ap.channel = line[5..]
+                .trim()
+                .split('.')
+                .next()
+                .and_then(|freq| freq.parse::<u32>().ok())
+                .map_or(0, frequency_to_channel);
+        } else if line.starts_with("signal:") {
+            ap.signal = line[7..]
+                .split_whitespace()
+                .next()
+                .and_then(|signal| signal.parse::<f32>().ok())
+                .map_or(-100, |signal| signal as i32);
+        } else if line.starts_with("SSID:") {
+            ap.ssid = display_ssid(line[5..].trim().as_bytes());
+        } else if line.starts_with("capability:") && line.contains("Privacy") {
+            if ap.security == "none" {
+                ap.security = "wep".to_string();
+            }
+        } else if line.starts_with("RSN:") || line.starts_with("WPA:") {
+            if ap.security != "enterprise" {
+                ap.security = "wpa".to_string();
+            }
+        } else if line.starts_with("* Authentication suites:") {
+            if line.contains("802.1X") {
+                ap.security = "enterprise".to_string();
+            }
+
+            for suite in line[24..].split_whitespace() {
+                if !ap.authentication_suites.iter().any(|known| known == suite) {
+                    ap.authentication_suites.push(suite.to_string());
+                }
+            }
+        }
+    }
+
+    access_points.extend(current.filter(|ap| ap.channel != 0));
+
+    access_points
+}
+
+/// Channel of a 2.4 or 5 GHz frequency in MHz, 0 for other bands
+fn frequency_to_channel(frequency: u32) -> u32 {
+    match frequency {
+        2484 => 14,
+        2412..=2472 => (frequency - 2407) / 5,
+        5160..=5885 => (frequency - 5000) / 5,
+        _ => 0,
+    }
+}
+
+/// Groups access points by channel, including the candidate channels for a
+/// new access point even when they are empty
+pub fn channel_report(access_points: &[ScannedAccessPoint]) -> Vec<ChannelUsage> {
+    let mut channels: Vec<u32> = access_points.iter().map(|ap| ap.channel).collect();
+    channels.extend_from_slice(CANDIDATES_2GHZ);
+    channels.extend_from_slice(CANDIDATES_5GHZ);
+    channels.sort();
+    channels.dedup();
+
+    channels
+        .into_iter()
+        .map(|channel| {
+            let mut on_channel: Vec<ScannedAccessPoint> = access_points
+                .iter()
+                .filter(|ap| ap.channel == channel)
+                .cloned()
+                .collect();
+            on_channel.sort_by(|a, b| b.signal.cmp(&a.signal));
+
+            ChannelUsage {
+                channel,
+                access_points: on_channel,
+                congestion: congestion(channel, access_points),
+            }
+        })
+        .collect()
+}
+
+/// Signal of the access points interfering with the channel, in percent
+/// and weighted by how much their 2.4 GHz channels overlap
+fn congestion(channel: u32, access_points: &[ScannedAccessPoint]) -> u32 {
+    access_points
+        .iter()
+        .map(|ap| {
+            let signal = u32::from(rssi_to_percent(ap.signal));
+
+            match band_of_channel(channel) {
+                Some(Band::TwoGHz) if ap.channel <= 14 => {
+                    let distance = (ap.channel as i32 - channel as i32).abs() as u32;
+                    signal * OVERLAP_2GHZ.saturating_sub(distance) / OVERLAP_2GHZ
+                }
+                _ if ap.channel == channel => signal,
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+/// Least congested candidate channel of the band, preferring lower channels
+/// on a tie
+pub fn recommend_channel(report: &[ChannelUsage], band: Band) -> Option<u32> {
+    report
+        .iter()
+        .filter(|usage| band.candidates().contains(&usage.channel))
+        .min_by_key(|usage| (usage.congestion, usage.channel))
+        .map(|usage| usage.channel)
+}
+
+/// Channel for the portal access point, scanning for the least congested
+/// one in `band` when set to `auto`
+pub fn resolve_portal_channel(interface: &str, channel: PortalChannel, band: Band) -> Option<u32> {
+    match channel {
+        PortalChannel::Fixed(channel) => Some(channel),
+        PortalChannel::Auto => match scan_access_points(interface) {
+            Ok(access_points) => {
+                let report = channel_report(&access_points);
+                let channel = recommend_channel(&report, band);
+
+                if let Some(channel) = channel {
+                    info!("Least congested channel for the access point: {}", channel);
+                }
+
+                channel
+            }
+            Err(e) => {
+                warn!("Picking the access point channel failed: {}", e);
+                None
+            }
+        },
+    }
+}
@@ -0,0 +1,140 @@
+//! NetworkManager checkpoints around changes that could cut a remote device
+//! off, with `--with-rollback`. The configuration of the WiFi device is
+//! snapshotted before the change and restored unless Internet connectivity
+//! is back within the timeout. NetworkManager rolls back on its own should
+//! WiFi Connect not get to decide, e.g. when it is killed halfway.
+
+use std::process::Command;
+
+use backend::{Backend, DEFAULT_BACKEND};
+use errors::*;
+use network::get_device_path;
+
+/// Seconds NetworkManager waits past the connectivity timeout before rolling
+/// back by itself
+const ROLLBACK_GRACE: u64 = 15;
+
+/// Rolls back connections created after the checkpoint as well
+/// (`NM_CHECKPOINT_CREATE_FLAG_DELETE_NEW_CONNECTIONS`) and replaces
+/// checkpoints left over (`NM_CHECKPOINT_CREATE_FLAG_DESTROY_ALL`)
+const CHECKPOINT_FLAGS: u32 = 0x1 | 0x2;
+
+const NM_DESTINATION: &str = "--dest=org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+
+/// Applies `change` in a checkpoint of the device of `backend` that is
+/// rolled back unless there is Internet connectivity within `timeout`
+/// seconds of it
+pub fn with_rollback<F>(backend: &mut dyn Backend, timeout: u64, change: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Backend) -> Result<()>,
+{
+    if backend.name() != DEFAULT_BACKEND {
+        bail!(ErrorKind::UnsupportedByBackend(
+            backend.name().into(),
+            "rolling back changes".into()
+        ));
+    }
+
+    let checkpoint = Checkpoint::create(backend.interface(), timeout + ROLLBACK_GRACE)?;
+
+    let result = change(backend);
+
+    let connected = backend.wait_for_connectivity(timeout).unwrap_or_else(|e| {
+        warn!("{}", e);
+        false
+    });
+
+    if connected {
+        checkpoint.destroy()?;
+        return result;
+    }
+
+    warn!(
+        "No Internet connectivity within {} seconds, rolling back",
+        timeout
+    );
+
+    checkpoint.rollback()?;
+
+    // The error of the change, if any, tells more than the rollback
+    result?;
+
+    bail!(ErrorKind::RolledBack(timeout))
+}
+
+/// Checkpoint of the NetworkManager configuration of a device
+struct Checkpoint {
+    path: String,
+}
+
+impl Checkpoint {
+    fn create(interface: &str, rollback_timeout: u64) -> Result<Self> {
+        let device = match get_device_path(interface) {
+            Some(device) => device,
+            None => bail!(ErrorKind::DeviceByInterface(interface.into())),
+        };
+
+        let reply = call(
+            "CheckpointCreate",
+            &[
+                &format!("array:objpath:{}", device),
+                &format!("uint32:{}", rollback_timeout),
+                &format!("uint32:{}", CHECKPOINT_FLAGS),
+            ],
+        )?;
+
+        // The reply is the object path of the new checkpoint
+        let path = reply
+            .split_whitespace()
+            .skip_while(|token| *token != "path")
+            .nth(1)
+            .map(|path| path.trim_matches('"').to_string())
+            .ok_or_else(|| Error::from(ErrorKind::Checkpoint("CheckpointCreate".into())))?;
+
+        info!("Created checkpoint {} of {}", path, interface);
+
+        Ok(Checkpoint { path })
+    }
+
+    /// Keeps the change
+    fn destroy(self) -> Result<()> {
+        call("CheckpointDestroy", &[&format!("objpath:{}", self.path)])?;
+
+        info!("Change kept, checkpoint {} destroyed", self.path);
+
+        Ok(())
+    }
+
+    fn rollback(self) -> Result<()> {
+        call("CheckpointRollback", &[&format!("objpath:{}", self.path)])?;
+
+        info!("Rolled back to checkpoint {}", self.path);
+
+        Ok(())
+    }
+}
+
+/// Calls `method` of the NetworkManager D-Bus interface and returns the reply
+fn call(method: &str, args: &[&str]) -> Result<String> {
+    let method_name = format!("org.freedesktop.NetworkManager.{}", method);
+
+    let output = Command::new("dbus-send")
+        .args(&[
+            "--system",
+            "--print-reply",
+            NM_DESTINATION,
+            NM_PATH,
+            &method_name,
+        ])
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::Checkpoint(method.into()))?;
+
+    if !output.status.success() {
+        warn!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        bail!(ErrorKind::Checkpoint(method.into()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
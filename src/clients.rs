@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use errors::*;
+use events::{EventKind, SharedEventLog};
+
+const POLL_INTERVAL: u64 = 2;
+
+/// nftables table dropping the frames of stations rejected by the MAC filter
+const NFT_TABLE: &str = "wifi_connect_mac_filter";
+
+/// A station associated with the access point, as reported by `iw`
+#[derive(Clone, Debug, Serialize)]
+pub struct Client {
+    pub mac: String,
+    pub signal: Option<i32>,
+    pub connected_time: Option<u64>,
+}
+
+pub type Clients = Arc<Mutex<Vec<Client>>>;
+
+/// Allow/deny lists of station MAC addresses. An empty allow list permits
+/// every station that is not explicitly denied.
+#[derive(Clone, Debug, Default)]
+pub struct MacFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl MacFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn permits(&self, mac: &str) -> bool {
+        let mac = mac.to_lowercase();
+
+        if self.deny.iter().any(|denied| *denied == mac) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|allowed| *allowed == mac)
+    }
+}
+
+/// Drops the frames of stations rejected by the MAC filter as they arrive on
+/// the access point interface, so that they get no DHCP lease and never reach
+/// the portal, even before being disassociated. The rules live in a
+/// dedicated nftables table, which is removed when dropped.
+pub struct MacFilterRules;
+
+impl MacFilterRules {
+    fn add(interface: &str, filter: &MacFilter) -> Result<Self> {
+        info!("Applying the MAC filter on {}", interface);
+
+        // Start from a clean table, e.g. after a crash
+        let _ = nft(&["delete", "table", "netdev", NFT_TABLE]);
+
+        // Removes a partially set up table when returning early
+        let rules = MacFilterRules;
+
+        nft(&["add", "table", "netdev", NFT_TABLE])?;
+
+        let hook = format!(
+            "{{ type filter hook ingress device \"{}\" priority -500 ; policy accept ; }}",
+            interface
+        );
+        nft(&["add", "chain", "netdev", NFT_TABLE, "ingress", &hook])?;
+
+        if !filter.deny.is_empty() {
+            let denied = format!("{{ {} }}", filter.deny.join(", "));
+            nft(&[
+                "add", "rule", "netdev", NFT_TABLE, "ingress", "ether", "saddr", &denied, "drop",
+            ])?;
+        }
+
+        if !filter.allow.is_empty() {
+            let allowed = format!("{{ {} }}", filter.allow.join(", "));
+            nft(&[
+                "add", "rule", "netdev", NFT_TABLE, "ingress", "ether", "saddr", "!=", &allowed,
+                "drop",
+            ])?;
+        }
+
+        Ok(rules)
+    }
+}
+
+impl Drop for MacFilterRules {
+    fn drop(&mut self) {
+        if let Err(e) = nft(&["delete", "table", "netdev", NFT_TABLE]) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Enforces the MAC filter on the access point interface while the returned
+/// rules are kept. They have to be dropped before the interface connects to
+/// a network, as they would filter its frames as well.
+pub fn apply_mac_filter(interface: &str, filter: &MacFilter) -> Result<Option<MacFilterRules>> {
+    if filter.is_empty() {
+        return Ok(None);
+    }
+
+    MacFilterRules::add(interface, filter).map(Some)
+}
+
+fn nft(args: &[&str]) -> Result<()> {
+    let output = Command::new("nft")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::MacFilter("running nft failed".into()))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::MacFilter(
+            String::from_utf8_lossy(&output.stderr).trim().to_string()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Handle to the station polling thread, which stops when dropped
+pub struct ClientMonitor {
+    pub clients: Clients,
+    running: Arc<AtomicBool>,
+}
+
+impl ClientMonitor {
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ClientMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Polls the station list of the access point interface, recording every
+/// association and disassociation as an event. Stations rejected by the
+/// filter, whose frames `apply_mac_filter` drops, are also disassociated.
+pub fn spawn_client_monitor(
+    interface: &str,
+    filter: MacFilter,
+    events: SharedEventLog,
+) -> ClientMonitor {
+    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+    let clients_monitor = clients.clone();
+    let running = Arc::new(AtomicBool::new(true));
+    let running_monitor = running.clone();
+    let interface = interface.to_string();
+
+    thread::spawn(move || {
+        let mut known: HashMap<String, Client> = HashMap::new();
+        let mut failing = false;
+
+        while running_monitor.load(Ordering::SeqCst) {
+            // A failed query, e.g. while the interface is being reconfigured,
+            // is retried on the next poll with the clients left as they were
+            let mut stations = match get_stations(&interface) {
+                Some(stations) => stations,
+                None => {
+                    if !failing {
+                        warn!("Cannot query stations on '{}', retrying", interface);
+                        failing = true;
+                    }
+
+                    thread::sleep(Duration::from_secs(POLL_INTERVAL));
+                    continue;
+                }
+            };
+
+            if failing {
+                info!("Querying stations on '{}' works again", interface);
+                failing = false;
+            }
+
+            stations.retain(|station| {
+                if filter.permits(&station.mac) {
+                    return true;
+                }
+
+                info!("Rejecting client not permitted by MAC filter: {}", station.mac);
+                disconnect_station(&interface, &station.mac);
+                false
+            });
+
+            for station in &stations {
+                if !known.contains_key(&station.mac) {
+                    info!(
+                        "Client joined: {} (signal: {})",
+                        station.mac,
+                        format_signal(station.signal)
+                    );
+
+                    events.lock().unwrap().push(EventKind::ClientJoined {
+                        mac: station.mac.clone(),
+                        signal: station.signal,
+                    });
+                }
+            }
+
+            for (mac, station) in &known {
+                if !stations.iter().any(|s| s.mac == *mac) {
+                    info!(
+                        "Client left: {} (last signal: {})",
+                        mac,
+                        format_signal(station.signal)
+                    );
+
+                    events.lock().unwrap().push(EventKind::ClientLeft {
+                        mac: mac.clone(),
+                        signal: station.signal,
+                    });
+                }
+            }
+
+            known = stations
+                .iter()
+                .map(|station| (station.mac.clone(), station.clone()))
+                .collect();
+
+            *clients_monitor.lock().unwrap() = stations;
+
+            thread::sleep(Duration::from_secs(POLL_INTERVAL));
+        }
+    });
+
+    ClientMonitor { clients, running }
+}
+
+fn disconnect_station(interface: &str, mac: &str) {
+    let result = Command::new("iw")
+        .args(&["dev", interface, "station", "del", mac])
+        .status();
+
+    if let Err(e) = result {
+        warn!("Disconnecting station {} failed: {}", mac, e);
+    }
+}
+
+fn format_signal(signal: Option<i32>) -> String {
+    signal.map_or_else(|| "n/a".to_string(), |signal| format!("{} dBm", signal))
+}
+
+fn get_stations(interface: &str) -> Option<Vec<Client>> {
+    let output = Command::new("iw")
+        .args(&["dev", interface, "station", "dump"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(parse_station_dump(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_station_dump(dump: &str) -> Vec<Client> {
+    let mut clients = Vec::new();
+
+    for line in dump.lines() {
+        let line = line.trim();
+
+        if line.starts_with("Station ") {
+            if let Some(mac) = line.split_whitespace().nth(1) {
+                clients.push(Client {
+                    mac: mac.to_string(),
+                    signal: None,
+                    connected_time: None,
+                });
+            }
+        } else if let Some(client) = clients.last_mut() {
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            let number = value.split_whitespace().next().unwrap_or("");
+
+            match key {
+                "signal" => client.signal = number.parse().ok(),
+                "connected time" => client.connected_time = number.parse().ok(),
+                _ => {}
+            }
+        }
+    }
+
+    clients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_station_dump;
+
+    const STATION_DUMP: &str = "\
+Station 3c:22:fb:8a:1e:07 (on wlan0)
+	inactive time:	1320 ms
+	rx bytes:	48213
+	rx packets:	412
+	tx bytes:	61870
+	tx packets:	287
+	tx retries:	12
+	tx failed:	0
+	signal:  	-52 [-55, -54] dBm
+	signal avg:	-53 [-56, -55] dBm
+	tx bitrate:	72.2 MBit/s MCS 7 short GI
+	rx bitrate:	65.0 MBit/s MCS 6
+	authorized:	yes
+	authenticated:	yes
+	associated:	yes
+	WMM/WME:	yes
+	MFP:		no
+	TDLS peer:	no
+	preamble:	short
+	connected time:	184 seconds
+Station a4:83:e7:12:9c:5d (on wlan0)
+	inactive time:	40 ms
+	rx bytes:	3120
+	tx bytes:	2044
+	authorized:	no
+	authenticated:	yes
+	associated:	yes
+	connected time:	2 seconds
+";
+
+    #[test]
+    fn parses_station_dump() {
+        let clients = parse_station_dump(STATION_DUMP);
+
+        assert_eq!(clients.len(), 2);
+
+        assert_eq!(clients[0].mac, "3c:22:fb:8a:1e:07");
+        assert_eq!(clients[0].signal, Some(-52));
+        assert_eq!(clients[0].connected_time, Some(184));
+
+        assert_eq!(clients[1].mac, "a4:83:e7:12:9c:5d");
+        assert_eq!(clients[1].signal, None);
+        assert_eq!(clients[1].connected_time, Some(2));
+    }
+
+    #[test]
+    fn parses_empty_station_dump() {
+        assert!(parse_station_dump("").is_empty());
+    }
+}
@@ -0,0 +1,509 @@
+//! Minimal CoAP (RFC 7252) server for provisioning from constrained
+//! controllers, like a BLE-to-CoAP bridge. It serves the scan results and
+//! accepts credentials on the portal gateway, handing both to the network
+//! thread like the HTTP portal does.
+//!
+//! Resources:
+//!
+//! * `GET /.well-known/core` - resource discovery in link format
+//! * `GET /networks` - networks in range as JSON, block-wise if large
+//! * `POST /connect` - JSON object with `ssid`, `identity`, `passphrase` and
+//!   the extra form fields, if any
+
+use std::collections::VecDeque;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{self, Map, Value};
+
+use config::Config;
+use fields::{collect_extra_fields, write_extra_fields};
+use network::{NetworkCommand, NetworkCommandResponse};
+use proxy::collect_proxy_settings;
+use secret::Secret;
+use server::is_eula_pending;
+use ssid::validate_ssid;
+use wireguard::collect_wireguard_config;
+
+pub const DEFAULT_COAP_PORT: u16 = 5683;
+
+/// Seconds to wait for the network thread to list the networks
+const NETWORKS_TIMEOUT: u64 = 30;
+
+/// Largest block sent in one datagram (SZX 6)
+const MAX_BLOCK_SIZE: usize = 1024;
+
+/// Answers to confirmable requests kept to answer retransmissions without
+/// handling them twice
+const RECENT_RESPONSES: usize = 16;
+
+const VERSION: u8 = 1;
+
+const TYPE_CON: u8 = 0;
+const TYPE_NON: u8 = 1;
+const TYPE_ACK: u8 = 2;
+const TYPE_RST: u8 = 3;
+
+const CODE_EMPTY: u8 = 0x00;
+const CODE_GET: u8 = 0x01;
+const CODE_POST: u8 = 0x02;
+const CODE_CHANGED: u8 = 0x44;
+const CODE_CONTENT: u8 = 0x45;
+const CODE_BAD_REQUEST: u8 = 0x80;
+const CODE_FORBIDDEN: u8 = 0x83;
+const CODE_NOT_FOUND: u8 = 0x84;
+const CODE_METHOD_NOT_ALLOWED: u8 = 0x85;
+const CODE_INTERNAL_SERVER_ERROR: u8 = 0xa0;
+const CODE_SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_BLOCK2: u16 = 23;
+
+const FORMAT_TEXT: u32 = 0;
+const FORMAT_LINK: u32 = 40;
+const FORMAT_JSON: u32 = 50;
+
+const PAYLOAD_MARKER: u8 = 0xff;
+
+const WELL_KNOWN_CORE: &str =
+    "</networks>;rt=\"wifi-connect.networks\";ct=50,</connect>;rt=\"wifi-connect.connect\";ct=50";
+
+struct Message {
+    kind: u8,
+    code: u8,
+    message_id: u16,
+    token: Vec<u8>,
+    /// Options ordered by number
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn parse(datagram: &[u8]) -> Option<Message> {
+        if datagram.len() < 4 || datagram[0] >> 6 != VERSION {
+            return None;
+        }
+
+        let kind = (datagram[0] >> 4) & 0x03;
+        let token_length = (datagram[0] & 0x0f) as usize;
+        let code = datagram[1];
+        let message_id = u16::from(datagram[2]) << 8 | u16::from(datagram[3]);
+
+        if token_length > 8 || datagram.len() < 4 + token_length {
+            return None;
+        }
+
+        let token = datagram[4..4 + token_length].to_vec();
+
+        let mut options = Vec::new();
+        let mut number = 0u16;
+        let mut rest = &datagram[4 + token_length..];
+
+        while let Some(&byte) = rest.first() {
+            if byte == PAYLOAD_MARKER {
+                rest = &rest[1..];
+                break;
+            }
+
+            rest = &rest[1..];
+
+            let delta = read_extended(byte >> 4, &mut rest)?;
+            let length = read_extended(byte & 0x0f, &mut rest)? as usize;
+
+            if rest.len() < length {
+                return None;
+            }
+
+            number = number.checked_add(delta)?;
+            options.push((number, rest[..length].to_vec()));
+            rest = &rest[length..];
+        }
+
+        Some(Message {
+            kind,
+            code,
+            message_id,
+            token,
+            options,
+            payload: rest.to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut datagram = vec![
+            VERSION << 6 | self.kind << 4 | self.token.len() as u8,
+            self.code,
+            (self.message_id >> 8) as u8,
+            self.message_id as u8,
+        ];
+
+        datagram.extend_from_slice(&self.token);
+
+        let mut previous = 0;
+
+        for &(number, ref value) in &self.options {
+            let (delta, delta_extended) = write_extended(number - previous);
+            let (length, length_extended) = write_extended(value.len() as u16);
+
+            datagram.push(delta << 4 | length);
+            datagram.extend_from_slice(&delta_extended);
+            datagram.extend_from_slice(&length_extended);
+            datagram.extend_from_slice(value);
+
+            previous = number;
+        }
+
+        if !self.payload.is_empty() {
+            datagram.push(PAYLOAD_MARKER);
+            datagram.extend_from_slice(&self.payload);
+        }
+
+        datagram
+    }
+
+    fn path(&self) -> String {
+        let segments: Vec<String> = self
+            .options
+            .iter()
+            .filter(|&&(number, _)| number == OPTION_URI_PATH)
+            .map(|&(_, ref segment)| String::from_utf8_lossy(segment).into_owned())
+            .collect();
+
+        format!("/{}", segments.join("/"))
+    }
+
+    fn option_uint(&self, option: u16) -> Option<u32> {
+        self.options
+            .iter()
+            .find(|&&(number, _)| number == option)
+            .map(|&(_, ref value)| {
+                value
+                    .iter()
+                    .fold(0u32, |uint, &byte| uint << 8 | u32::from(byte))
+            })
+    }
+}
+
+fn read_extended(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    let value = match nibble {
+        13 => {
+            let value = u16::from(*rest.first()?) + 13;
+            *rest = &rest[1..];
+            value
+        }
+        14 => {
+            if rest.len() < 2 {
+                return None;
+            }
+            let value = (u16::from(rest[0]) << 8 | u16::from(rest[1])).checked_add(269)?;
+            *rest = &rest[2..];
+            value
+        }
+        15 => return None,
+        nibble => u16::from(nibble),
+    };
+
+    Some(value)
+}
+
+fn write_extended(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => {
+            let value = value - 269;
+            (14, vec![(value >> 8) as u8, value as u8])
+        }
+    }
+}
+
+fn encode_uint(value: u32) -> Vec<u8> {
+    let bytes = [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ];
+
+    bytes
+        .iter()
+        .skip_while(|&&byte| byte == 0)
+        .cloned()
+        .collect()
+}
+
+struct Reply {
+    code: u8,
+    content_format: Option<u32>,
+    payload: Vec<u8>,
+}
+
+impl Reply {
+    fn new(code: u8, content_format: u32, payload: Vec<u8>) -> Self {
+        Reply {
+            code,
+            content_format: Some(content_format),
+            payload,
+        }
+    }
+
+    fn empty(code: u8) -> Self {
+        Reply {
+            code,
+            content_format: None,
+            payload: Vec::new(),
+        }
+    }
+
+    fn text(code: u8, text: &str) -> Self {
+        Reply::new(code, FORMAT_TEXT, text.as_bytes().to_vec())
+    }
+}
+
+/// Serves the CoAP resources on the portal gateway if `--coap` is set
+pub fn spawn_coap_server(config: &Config, network_tx: Sender<NetworkCommand>) {
+    let port = match config.coap_port {
+        Some(port) => port,
+        None => return,
+    };
+
+    let address = SocketAddr::from((config.gateway, port));
+    let config = config.clone();
+
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind(address) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Starting CoAP server on {} failed: {}", address, e);
+                return;
+            }
+        };
+
+        info!("Starting CoAP server on {}", address);
+
+        serve(&socket, &config, &network_tx);
+    });
+}
+
+fn serve(socket: &UdpSocket, config: &Config, network_tx: &Sender<NetworkCommand>) {
+    let mut buffer = [0u8; 1500];
+    let mut recent: VecDeque<(SocketAddr, u16, Vec<u8>)> = VecDeque::new();
+    let mut next_message_id: u16 = 0;
+
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Receiving CoAP request failed: {}", e);
+                continue;
+            }
+        };
+
+        let request = match Message::parse(&buffer[..length]) {
+            Some(request) => request,
+            None => {
+                debug!("Ignoring malformed CoAP message from {}", peer);
+                continue;
+            }
+        };
+
+        if request.kind == TYPE_ACK || request.kind == TYPE_RST {
+            continue;
+        }
+
+        // Retransmitted confirmable request, answer as before
+        if let Some(&(_, _, ref response)) = recent
+            .iter()
+            .find(|&&(address, id, _)| address == peer && id == request.message_id)
+        {
+            let _ = socket.send_to(response, peer);
+            continue;
+        }
+
+        // Empty confirmable messages are pings
+        let response = if request.code == CODE_EMPTY {
+            Message {
+                kind: TYPE_RST,
+                code: CODE_EMPTY,
+                message_id: request.message_id,
+                token: Vec::new(),
+                options: Vec::new(),
+                payload: Vec::new(),
+            }
+        } else {
+            let reply = handle(&request, config, network_tx);
+
+            let (kind, message_id) = if request.kind == TYPE_CON {
+                (TYPE_ACK, request.message_id)
+            } else {
+                next_message_id = next_message_id.wrapping_add(1);
+                (TYPE_NON, next_message_id)
+            };
+
+            build_response(&request, kind, message_id, reply)
+        };
+
+        let response = response.encode();
+
+        if let Err(e) = socket.send_to(&response, peer) {
+            warn!("Sending CoAP response to {} failed: {}", peer, e);
+        }
+
+        if request.kind == TYPE_CON {
+            if recent.len() == RECENT_RESPONSES {
+                recent.pop_front();
+            }
+            recent.push_back((peer, request.message_id, response));
+        }
+    }
+}
+
+/// Builds the response to `request`, sending large payloads block-wise
+/// (RFC 7959) as asked for by the Block2 option of the request
+fn build_response(request: &Message, kind: u8, message_id: u16, reply: Reply) -> Message {
+    let mut options = Vec::new();
+
+    if let Some(content_format) = reply.content_format {
+        options.push((OPTION_CONTENT_FORMAT, encode_uint(content_format)));
+    }
+
+    let (block, block_size) = match request.option_uint(OPTION_BLOCK2) {
+        Some(block2) => {
+            let size = 16usize << ::std::cmp::min(block2 & 0x07, 6);
+            ((block2 >> 4) as usize, size)
+        }
+        None => (0, MAX_BLOCK_SIZE),
+    };
+
+    let mut payload = reply.payload;
+
+    if payload.len() > block_size || request.option_uint(OPTION_BLOCK2).is_some() {
+        let start = ::std::cmp::min(block * block_size, payload.len());
+        let end = ::std::cmp::min(start + block_size, payload.len());
+        let more = end < payload.len();
+        let szx = (block_size.trailing_zeros() - 4) as u32;
+
+        let block2 = (block as u32) << 4 | (more as u32) << 3 | szx;
+        options.push((OPTION_BLOCK2, encode_uint(block2)));
+
+        payload = payload[start..end].to_vec();
+    }
+
+    Message {
+        kind,
+        code: reply.code,
+        message_id,
+        token: request.token.clone(),
+        options,
+        payload,
+    }
+}
+
+fn handle(request: &Message, config: &Config, network_tx: &Sender<NetworkCommand>) -> Reply {
+    let path = request.path();
+
+    match (request.code, path.as_str()) {
+        (CODE_GET, "/.well-known/core") => Reply::new(
+            CODE_CONTENT,
+            FORMAT_LINK,
+            WELL_KNOWN_CORE.as_bytes().to_vec(),
+        ),
+        (CODE_GET, "/networks") => networks(network_tx),
+        (CODE_POST, "/connect") => connect(&request.payload, config, network_tx),
+        (_, "/.well-known/core") | (_, "/networks") | (_, "/connect") => {
+            Reply::empty(CODE_METHOD_NOT_ALLOWED)
+        }
+        _ => Reply::empty(CODE_NOT_FOUND),
+    }
+}
+
+fn networks(network_tx: &Sender<NetworkCommand>) -> Reply {
+    let (reply_tx, reply_rx) = channel();
+
+    if network_tx
+        .send(NetworkCommand::ListNetworks(reply_tx))
+        .is_err()
+    {
+        return Reply::empty(CODE_SERVICE_UNAVAILABLE);
+    }
+
+    let networks = match reply_rx.recv_timeout(Duration::from_secs(NETWORKS_TIMEOUT)) {
+        Ok(NetworkCommandResponse::Networks(networks)) => networks,
+        Err(_) => return Reply::text(CODE_SERVICE_UNAVAILABLE, "Timed out listing networks"),
+    };
+
+    match serde_json::to_vec(&networks) {
+        Ok(json) => Reply::new(CODE_CONTENT, FORMAT_JSON, json),
+        Err(_) => Reply::empty(CODE_INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn connect(payload: &[u8], config: &Config, network_tx: &Sender<NetworkCommand>) -> Reply {
+    let fields: Map<String, Value> = match serde_json::from_slice(payload) {
+        Ok(fields) => fields,
+        Err(e) => return Reply::text(CODE_BAD_REQUEST, &e.to_string()),
+    };
+
+    let field = |name: &str| fields.get(name).and_then(Value::as_str).map(String::from);
+
+    let ssid = match field("ssid") {
+        Some(ssid) => ssid,
+        None => return Reply::text(CODE_BAD_REQUEST, "Missing 'ssid'"),
+    };
+
+    if let Err(e) = validate_ssid(&ssid) {
+        return Reply::text(CODE_BAD_REQUEST, &e.to_string());
+    }
+
+    info!("Incoming CoAP `connect` to access point `{}` request", ssid);
+
+    if is_eula_pending(config) {
+        return Reply::text(
+            CODE_FORBIDDEN,
+            "The EULA has to be accepted before connecting",
+        );
+    }
+
+    // The port may be given as a number
+    let proxy = match collect_proxy_settings(|name| match fields.get(name) {
+        Some(&Value::Number(ref number)) => Some(number.to_string()),
+        _ => field(name),
+    }) {
+        Ok(proxy) => proxy,
+        Err(reason) => return Reply::text(CODE_BAD_REQUEST, &reason),
+    };
+
+    let wireguard = match collect_wireguard_config(field("wireguard")) {
+        Ok(wireguard) => wireguard,
+        Err(reason) => return Reply::text(CODE_BAD_REQUEST, &reason),
+    };
+
+    if !config.extra_fields.is_empty() {
+        let values = match collect_extra_fields(&config.extra_fields, &field) {
+            Ok(values) => values,
+            Err(reason) => return Reply::text(CODE_BAD_REQUEST, &reason),
+        };
+
+        if let Err(e) = write_extra_fields(&config.extra_fields_file, &values) {
+            error!("{}", e);
+            return Reply::empty(CODE_INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let command = NetworkCommand::Connect {
+        ssid,
+        identity: field("identity").unwrap_or_default(),
+        passphrase: Secret::from(field("passphrase").unwrap_or_default()),
+        proxy,
+        wireguard,
+    };
+
+    if network_tx.send(command).is_err() {
+        return Reply::empty(CODE_SERVICE_UNAVAILABLE);
+    }
+
+    Reply::empty(CODE_CHANGED)
+}
@@ -0,0 +1,53 @@
+//! Networks that connecting to would certainly fail, so that the portal can
+//! grey them out instead of letting users waste attempts on them: WPA3 only
+//! networks when the driver cannot authenticate with SAE, and enterprise
+//! networks with the iwd backend.
+
+use backend::Backend;
+use channels::scan_access_points;
+use network::Network;
+use wiphy::supports_sae;
+
+/// Sets why connecting is unsupported on the networks in `networks` that
+/// cannot be connected to with `backend`
+pub fn flag_unsupported_networks(backend: &dyn Backend, networks: &mut [Network]) {
+    for network in networks.iter_mut() {
+        if backend.name() == "iwd" && network.security == "enterprise" {
+            network.unsupported = Some("Enterprise networks are not supported with iwd".into());
+        }
+    }
+
+    // Left unflagged when unknown, rather than hiding usable networks
+    if supports_sae(backend.interface()) != Some(false) {
+        return;
+    }
+
+    let scanned = match scan_access_points(backend.interface()) {
+        Ok(scanned) => scanned,
+        Err(e) => {
+            debug!("Skipping the WPA3 support check: {}", e);
+            return;
+        }
+    };
+
+    for network in networks.iter_mut() {
+        let mut access_points = scanned
+            .iter()
+            .filter(|access_point| access_point.ssid == network.ssid)
+            .peekable();
+
+        // Transition networks accept WPA2 clients as well, e.g. `PSK` and
+        // `FT/PSK` next to `SAE`
+        let sae_only = access_points.peek().is_some()
+            && access_points.all(|access_point| {
+                let suites = &access_point.authentication_suites;
+                suites.iter().any(|suite| suite.contains("SAE"))
+                    && !suites.iter().any(|suite| suite.contains("PSK"))
+            });
+
+        if sae_only {
+            debug!("'{}' requires WPA3, which the driver lacks", network.ssid);
+            network.unsupported = Some("WPA3 is not supported by the WiFi driver".into());
+        }
+    }
+}
@@ -1,48 +1,304 @@
-use clap::{App, Arg};
+use clap::{self, App, Arg, Shell, SubCommand};
 
 use std::env;
 use std::ffi::OsStr;
+use std::io;
 use std::net::Ipv4Addr;
 use std::path::PathBuf;
+use std::process;
 use std::str::FromStr;
 
+use access_log::{AccessLogFormat, AccessLogSettings, AccessLogTarget, ACCESS_LOG_FORMATS};
+use backend::{ForgetFilter, GcPolicy, BACKENDS, DEFAULT_BACKEND, MOCK_BACKEND};
+use blocklist::DEFAULT_BLOCKLIST_FILE;
+use capport::validate_portal_url;
+use certificates::DEFAULT_CERTIFICATE_DIRECTORY;
+use channels::{validate_portal_band, validate_portal_channel, PortalBand, PortalChannel};
+use clients::MacFilter;
+use coap::DEFAULT_COAP_PORT;
+use dnsmasq::{validate_dhcp_host, validate_lease_time};
+use encryption::{SecretsKey, SecretsPolicy};
+use fields::{parse_extra_field, validate_extra_field, ExtraField, DEFAULT_EXTRA_FIELDS_FILE};
+use firstboot::NTP_SYNC_TIMEOUT;
+use guest::DEFAULT_GUEST_INTERFACE;
+use history::DEFAULT_HISTORY_FILE;
+use low_memory::LOW_MEMORY_HTTP_CONNECTIONS;
+use manual;
+use passphrase_policy::check_strength;
+use profile::{
+    is_mac_address, validate_cloned_mac, ConnectionProfile, ProfileSettings, ProfileUpdate,
+    CONNECTION_PROFILES, EAP_METHODS, PHASE2_AUTHS,
+};
+use proxy::{collect_proxy_settings, ProxySettings, DEFAULT_PROXY_FILE};
+use redirect::validate_redirect_url;
+use remote_state::{StateUrl, DEFAULT_STATE_INTERVAL};
+use retry::DEFAULT_NM_RETRIES;
+use rotation::{device_passphrase, parse_random_passphrase, validate_portal_passphrase};
+use secret::Secret;
+use server::HttpLimits;
+use sim::{SimMethod, SIM_METHODS};
+use speedtest::DEFAULT_SPEED_TEST_URL;
+use ssid::validate_ssid;
+use watchdog::{WatchdogSettings, DEFAULT_RECONNECT_AFTER};
+
 const DEFAULT_GATEWAY: &str = "192.168.42.1";
+/// The simulated portal of development builds outside Linux has no access
+/// point interface to carry the gateway address
+const SIMULATION_GATEWAY: &str = "127.0.0.1";
 const DEFAULT_DHCP_RANGE: &str = "192.168.42.2,192.168.42.254";
 const DEFAULT_SSID: &str = "WiFi Connect";
 const DEFAULT_ACTIVITY_TIMEOUT: &str = "0";
 const DEFAULT_UI_DIRECTORY: &str = "ui";
 const DEFAULT_LISTENING_PORT: &str = "80";
+const DEFAULT_MQTT_TOPIC_PREFIX: &str = "wifi-connect";
+const DEFAULT_API_ADDRESS: &str = "0.0.0.0:8000";
+const DEFAULT_NETWORK_MANAGER_TIMEOUT: &str = "15";
+const DEFAULT_SHUTDOWN_TIMEOUT: &str = "10";
+const DEFAULT_HTTP_CONNECTIONS: &str = "16";
+const DEFAULT_HTTP_KEEP_ALIVE: &str = "5";
+const DEFAULT_HTTP_TIMEOUT: &str = "30";
+const DEFAULT_DNS_PORT: &str = "53";
+const DEFAULT_CONNECT_TIMEOUT: &str = "20";
+const DEFAULT_DHCP_TIMEOUT: &str = "30";
+const DEFAULT_SCAN_TIMEOUT: &str = "10";
+const DEFAULT_SURVEY_INTERVAL: &str = "5";
+const DEFAULT_SURVEY_DURATION: &str = "0";
+const DEFAULT_GC_KEEP: &str = "10";
 
 #[derive(Clone)]
 pub struct Config {
     pub interface: Option<String>,
     pub ssid: String,
-    pub passphrase: Option<String>,
+    pub passphrase: Option<Secret>,
+    /// Encryption of the passphrases kept on disk
+    pub secrets: SecretsPolicy,
+    /// Prints the encrypted value of a passphrase read from stdin with
+    /// `--encrypt-passphrase`
+    pub encrypt_passphrase: bool,
+    /// Length of the generated passphrase with `--portal-passphrase random`
+    pub random_passphrase: Option<usize>,
     pub gateway: Ipv4Addr,
     pub dhcp_range: String,
+    pub dhcp_lease_time: Option<String>,
+    pub dhcp_hosts: Vec<String>,
+    pub dnsmasq_options: Vec<String>,
+    pub dnsmasq_conf: Option<PathBuf>,
+    pub debug_portal_traffic: bool,
+    /// Access log of the HTTP servers with `--access-log`
+    pub access_log: Option<AccessLogSettings>,
     pub listening_port: u16,
     pub activity_timeout: u64,
     pub ui_directory: PathBuf,
     pub forget_all: bool,
+    pub forget_filter: ForgetFilter,
+    pub gc_connections: bool,
+    /// Saved networks pruned by `--gc-connections` and `--gc-interval`
+    pub gc_policy: GcPolicy,
+    /// Hours between prunings in daemon mode
+    pub gc_interval: Option<u64>,
+    pub yes: bool,
     pub list_networks: bool,
+    pub all_interfaces: bool,
+    pub survey: bool,
+    pub survey_interval: u64,
+    pub survey_duration: u64,
+    pub survey_json: bool,
+    pub channel_report: bool,
+    pub portal_channel: Option<PortalChannel>,
+    pub portal_band: Option<PortalBand>,
+    pub client_isolation: bool,
+    pub portal_owe: bool,
+    pub list_interfaces: bool,
     pub list_connected: bool,
+    pub watch: Option<u64>,
     pub list_saved: bool,
+    pub list_managed: bool,
+    pub forget_managed: bool,
     pub forget_network: Option<String>,
-    pub connect: Option<(String, String)>, // (SSID, passphrase)
+    pub show_network: Option<String>,
+    /// Desired state file reconciled with `--apply-state`, and by the
+    /// watchdog whenever it changes
+    pub apply_state: Option<PathBuf>,
+    pub show_drift: Option<PathBuf>,
+    /// Passpoint profile installed with `--install-passpoint`
+    pub install_passpoint: Option<PathBuf>,
+    /// Signed desired state pulled with `--state-url`, once or by the
+    /// watchdog every interval
+    pub state_url: Option<StateUrl>,
+    pub update_network: Option<(String, ProfileUpdate)>,
+    pub connect: Option<(String, Secret)>, // (SSID, passphrase)
+    /// Proxy of the network to connect to with `--connect`
+    pub connect_proxy: Option<ProxySettings>,
+    /// SIM based EAP method of the network to connect to with `--connect`
+    pub connect_eap_sim: Option<SimMethod>,
+    pub proxy_file: PathBuf,
     // New hotspot management commands
     pub start_hotspot: bool,
     pub stop_hotspot: bool,
     pub check_hotspot: bool,
+    pub check_privileges: bool,
+    pub doctor: bool,
+    pub doctor_network: bool,
+    pub ping: Option<String>,
     pub restart_hotspot: bool,
     pub no_dhcp_gateway: bool,
     pub no_dhcp_dns: bool,
     pub no_dhcp_router_option: bool,
-    pub disconnect: bool
+    pub disconnect: bool,
+    pub dry_run: bool,
+    pub notify_exec: Option<String>,
+    pub notify_dbus: bool,
+    pub notify_led: Option<String>,
+    pub mqtt_broker: Option<String>,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_commands: bool,
+    pub api_server: bool,
+    pub api_address: String,
+    pub api_token: Option<String>,
+    pub grpc_address: Option<String>,
+    pub rotate_passphrase: Option<u64>,
+    pub print_qr_code: bool,
+    pub guest_ssid: Option<String>,
+    pub guest_passphrase: Option<Secret>,
+    pub guest_interface: String,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<Secret>,
+    pub device_id: Option<String>,
+    pub redirect_url: Option<String>,
+    pub venue_info_url: Option<String>,
+    pub portal_url: Option<String>,
+    pub mac_filter: MacFilter,
+    pub profile_settings: ProfileSettings,
+    pub prefer_wifi_over_wwan: bool,
+    pub start_network_manager: bool,
+    pub network_manager_timeout: u64,
+    pub nm_retries: usize,
+    pub backend: String,
+    pub shutdown_timeout: u64,
+    /// Connection limits and timeouts of the HTTP servers
+    pub http_limits: HttpLimits,
+    pub low_memory: bool,
+    pub connect_timeout: u64,
+    pub dhcp_timeout: u64,
+    /// Seconds to wait for NTP sync before reporting success
+    pub require_time_sync: Option<u64>,
+    /// Seconds to regain Internet connectivity after `--forget-all` or
+    /// `--connect` before the change is rolled back
+    pub with_rollback: Option<u64>,
+    pub speed_test: Option<String>,
+    pub activate_vpn: Option<String>,
+    pub coap_port: Option<u16>,
+    pub ssdp: bool,
+    pub scan_timeout: u64,
+    pub pidfile: Option<PathBuf>,
+    pub history: bool,
+    pub extra_fields: Vec<ExtraField>,
+    pub extra_fields_file: PathBuf,
+    pub first_boot: bool,
+    pub eula: Option<String>,
+    pub history_file: PathBuf,
+    /// SSIDs added to and removed from the blocklist file, applied by `run`
+    /// once the command is known not to be a dry run
+    pub block_ssids: Vec<String>,
+    pub unblock_ssids: Vec<String>,
+    pub blocklist_file: PathBuf,
+    /// SSIDs hidden from the portal and refused, read from the blocklist file
+    /// by `run`
+    pub blocked_ssids: Vec<String>,
+    /// Certificates of EAP-TLS networks uploaded through the portal
+    pub certificate_directory: PathBuf,
+    pub sandbox: bool,
+    pub wifi_direct_fallback: bool,
+    pub dnsmasq_restart: bool,
+    pub dns_port: u16,
+    pub mock_script: Option<PathBuf>,
+    /// Monitoring only with `wifi-connect watchdog`
+    pub watchdog: Option<WatchdogSettings>,
 }
 
+/// Environment variables taking the place of the options with these names
+/// when they are not given on the command line
+const ENV_VARS: &[(&str, &str)] = &[
+    ("portal-interface", "PORTAL_INTERFACE"),
+    ("portal-ssid", "PORTAL_SSID"),
+    ("portal-channel", "PORTAL_CHANNEL"),
+    ("portal-band", "PORTAL_BAND"),
+    ("portal-passphrase", "PORTAL_PASSPHRASE"),
+    ("portal-gateway", "PORTAL_GATEWAY"),
+    ("portal-dhcp-range", "PORTAL_DHCP_RANGE"),
+    ("dhcp-lease-time", "DHCP_LEASE_TIME"),
+    ("dhcp-host", "DHCP_HOSTS"),
+    ("portal-listening-port", "PORTAL_LISTENING_PORT"),
+    ("activity-timeout", "ACTIVITY_TIMEOUT"),
+    ("ui-directory", "UI_DIRECTORY"),
+    ("notify-exec", "NOTIFY_EXEC"),
+    ("notify-led", "NOTIFY_LED"),
+    ("mqtt-broker", "MQTT_BROKER"),
+    ("mqtt-topic-prefix", "MQTT_TOPIC_PREFIX"),
+    ("api-address", "API_ADDRESS"),
+    ("api-token", "API_TOKEN"),
+    ("grpc-address", "GRPC_ADDRESS"),
+    ("rotate-passphrase", "ROTATE_PASSPHRASE"),
+    ("gc-interval", "GC_INTERVAL"),
+    ("keep", "GC_KEEP"),
+    ("guest-ssid", "GUEST_SSID"),
+    ("guest-passphrase", "GUEST_PASSPHRASE"),
+    ("guest-interface", "GUEST_INTERFACE"),
+    ("webhook-url", "WEBHOOK_URL"),
+    ("webhook-secret", "WEBHOOK_SECRET"),
+    ("device-id", "DEVICE_ID"),
+    ("redirect-url", "REDIRECT_URL"),
+    ("venue-info-url", "VENUE_INFO_URL"),
+    ("portal-url", "PORTAL_URL"),
+    ("allow-mac", "ALLOW_MAC"),
+    ("deny-mac", "DENY_MAC"),
+    ("cloned-mac", "CLONED_MAC"),
+    ("connection-profile", "CONNECTION_PROFILE"),
+    ("wifi-powersave", "WIFI_POWERSAVE"),
+    ("eap-method", "EAP_METHOD"),
+    ("eap-phase2-auth", "EAP_PHASE2_AUTH"),
+    ("network-manager-timeout", "NETWORK_MANAGER_TIMEOUT"),
+    ("nm-retries", "NM_RETRIES"),
+    ("dnsmasq-opt", "DNSMASQ_OPTS"),
+    ("dnsmasq-conf", "DNSMASQ_CONF"),
+    ("dns-port", "DNS_PORT"),
+    ("access-log", "ACCESS_LOG"),
+    ("access-log-format", "ACCESS_LOG_FORMAT"),
+    ("shutdown-timeout", "SHUTDOWN_TIMEOUT"),
+    ("http-connections", "HTTP_CONNECTIONS"),
+    ("http-keep-alive", "HTTP_KEEP_ALIVE"),
+    ("http-timeout", "HTTP_TIMEOUT"),
+    ("connect-timeout", "CONNECT_TIMEOUT"),
+    ("dhcp-timeout", "DHCP_TIMEOUT"),
+    ("scan-timeout", "SCAN_TIMEOUT"),
+    ("pidfile", "PIDFILE"),
+    ("history-file", "HISTORY_FILE"),
+    ("block-ssid", "BLOCK_SSIDS"),
+    ("blocklist-file", "BLOCKLIST_FILE"),
+    ("certificate-dir", "CERTIFICATE_DIR"),
+    ("extra-fields-file", "EXTRA_FIELDS_FILE"),
+    ("proxy-file", "PROXY_FILE"),
+    ("activate-vpn", "ACTIVATE_VPN"),
+    ("apply-state", "APPLY_STATE"),
+    ("secrets-key", "SECRETS_KEY"),
+    ("state-url", "STATE_URL"),
+    ("state-secret", "STATE_SECRET"),
+    ("state-interval", "STATE_INTERVAL"),
+    ("with-rollback", "WITH_ROLLBACK"),
+    ("mock-script", "MOCK_SCRIPT"),
+    ("backend", "BACKEND"),
+    ("reconnect-after", "WATCHDOG_RECONNECT_AFTER"),
+    ("portal-after", "WATCHDOG_PORTAL_AFTER"),
+];
 
-pub fn get_config() -> Config {
-    let matches = App::new(crate_name!())
+/// Hands the command line interface of WiFi Connect to `f`, which parses
+/// the arguments or generates shell completions and manuals. Some help
+/// texts are formatted and only live as long as this call.
+fn with_cli<R, F>(f: F) -> R
+where
+    F: for<'b> FnOnce(App<'static, 'b>) -> R,
+{
+    f(App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
@@ -63,6 +319,7 @@ pub fn get_config() -> Config {
                     "SSID of the captive portal WiFi network (default: {})",
                     DEFAULT_SSID
                 ))
+                .validator(validate_ssid_arg)
                 .takes_value(true),
         )
         .arg(
@@ -70,9 +327,44 @@ pub fn get_config() -> Config {
                 .short("p")
                 .long("portal-passphrase")
                 .value_name("passphrase")
-                .help("WPA2 Passphrase of the captive portal WiFi network (default: none)")
+                .help("WPA2 Passphrase of the captive portal WiFi network, 8 to 63 printable ASCII characters, or random[:length] for one generated on first run and kept in a root-only state file (default: none)")
+                .validator(validate_portal_passphrase)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-passphrase-strength-check")
+                .long("portal-passphrase-strength-check")
+                .help("Refuse a portal passphrase that is a common password, a sequence or contains the SSID, for hotspots customers join")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("portal-channel")
+                .long("portal-channel")
+                .value_name("channel")
+                .help("Channel of the captive portal WiFi network, or 'auto' for the least congested one of the portal band (default: chosen by NetworkManager)")
+                .validator(validate_portal_channel)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("portal-band")
+                .long("portal-band")
+                .value_name("band")
+                .help("Band of the captive portal WiFi network: '2.4', '5', or 'both' for a 5 GHz access point with the same SSID next to the 2.4 GHz one, where the adapter supports it (default: 2.4)")
+                .validator(validate_portal_band)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-owe")
+                .long("portal-owe")
+                .help("Encrypt the open captive portal WiFi network with Enhanced Open (OWE), falling back to plain open where the adapter does not support it. Ignored with a portal passphrase.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("client-isolation")
+                .long("client-isolation")
+                .help("Keep the stations on the portal and guest hotspots from reaching each other (requires NetworkManager 1.28)")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("portal-gateway")
                 .short("g")
@@ -80,7 +372,7 @@ pub fn get_config() -> Config {
                 .value_name("gateway")
                 .help(&format!(
                     "Gateway of the captive portal WiFi network (default: {})",
-                    DEFAULT_GATEWAY
+                    default_gateway()
                 ))
                 .takes_value(true),
         )
@@ -95,6 +387,24 @@ pub fn get_config() -> Config {
                 ))
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("dhcp-lease-time")
+                .long("dhcp-lease-time")
+                .value_name("time")
+                .help("DHCP lease time, in seconds or with an m, h, d or w suffix, or 'infinite'")
+                .validator(validate_lease_time)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dhcp-host")
+                .long("dhcp-host")
+                .value_name("mac,ip[,name]")
+                .help("Static DHCP reservation, can be given multiple times")
+                .validator(validate_dhcp_host)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("portal-listening-port")
                 .short("o")
@@ -125,49 +435,359 @@ pub fn get_config() -> Config {
                 ))
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help(
+                    "Print the connection profiles and processes --forget-all, \
+                     --forget-network, --forget-managed, --stop-hotspot or starting the portal \
+                     would affect, without changing anything",
+                )
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("forget-all")
                 .long("forget-all")
                 .help("Forget all saved WiFi networks and exit")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("except")
+                .long("except")
+                .value_name("ssid,...")
+                .help("Comma separated SSIDs of networks --forget-all keeps")
+                .requires("forget-all")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("older-than")
+                .long("older-than")
+                .value_name("days")
+                .help("Only forget networks not used for this many days with --forget-all, or also prune them with --gc-connections and --gc-interval")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep")
+                .long("keep")
+                .value_name("n")
+                .help(&format!(
+                    "Never prune the n most recently used networks with --gc-connections and --gc-interval (default: {})",
+                    DEFAULT_GC_KEEP
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .long("yes")
+                .help("Forget without asking for confirmation, required when not running interactively")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("list-networks")
                 .long("list-networks")
                 .help("List all available WiFi networks and exit")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("all-interfaces")
+                .long("all-interfaces")
+                .help("Scan with all WiFi interfaces at once when listing networks")
+                .requires("list-networks")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("survey")
+                .long("survey")
+                .help("Scan repeatedly and print the signal history of every network, for site surveys")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("seconds")
+                .help(&format!(
+                    "Seconds between survey scans (default: {})",
+                    DEFAULT_SURVEY_INTERVAL
+                ))
+                .requires("survey")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .value_name("seconds")
+                .help("Seconds the survey runs for, until interrupted by default")
+                .requires("survey")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Stream survey results as JSON lines")
+                .requires("survey")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("channel-report")
+                .long("channel-report")
+                .help("Report access points per channel, recommend the least congested channels and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("list-interfaces")
+                .long("list-interfaces")
+                .help("List all WiFi interfaces with driver, MAC address, AP mode support and state and exit")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("list-connected")
                 .long("list-connected")
                 .help("List currently connected WiFi network and exit")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .value_name("seconds")
+                .help("Refresh the connected WiFi network every given seconds until interrupted")
+                .requires("list-connected")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("list-saved")
                 .long("list-saved")
                 .help("List all saved WiFi networks and exit")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("list-managed")
+                .long("list-managed")
+                .help("List the WiFi networks saved by WiFi Connect and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("forget-managed")
+                .long("forget-managed")
+                .help("Forget the WiFi networks saved by WiFi Connect, keeping other ones, and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("gc-connections")
+                .long("gc-connections")
+                .help("Prune the saved WiFi networks never used, or not used for --older-than days, and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("gc-interval")
+                .long("gc-interval")
+                .value_name("hours")
+                .help("Prune saved WiFi networks as --gc-connections does every interval hours with --api-server or the watchdog")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("forget-network")
                 .long("forget-network")
                 .value_name("ssid")
-                .help("Forget a specific WiFi network by SSID and exit")
+                .help("Forget a specific WiFi network by SSID, or hex:<bytes> for raw SSIDs, and exit")
+                .validator(validate_ssid_arg)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("show-network")
+                .long("show-network")
+                .value_name("ssid")
+                .help("Show the settings of a saved WiFi network and exit")
+                .validator(validate_ssid_arg)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("apply-state")
+                .long("apply-state")
+                .value_name("file")
+                .help("Create, update and remove saved WiFi networks to match the desired state file and exit, or reconcile it whenever it changes with the watchdog")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("secrets-key")
+                .long("secrets-key")
+                .value_name("tpm|keyfile")
+                .help("Encrypt passphrases stored on disk, and decrypt enc: passphrases of desired states, with the TPM or a keyfile")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("encrypt-secrets")
+                .long("encrypt-secrets")
+                .help("Store passphrases on disk encrypted with --secrets-key only, and reject plaintext passphrases in desired states")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("encrypt-passphrase")
+                .long("encrypt-passphrase")
+                .help("Print the passphrase read from stdin encrypted with --secrets-key and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("state-url")
+                .long("state-url")
+                .value_name("url")
+                .help("Pull a desired state signed with --state-secret from this URL and apply it, or with the watchdog every --state-interval")
+                .validator(validate_redirect_url)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-secret")
+                .long("state-secret")
+                .value_name("secret")
+                .help("Secret the desired state from --state-url is signed with using HMAC-SHA256")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-interval")
+                .long("state-interval")
+                .value_name("seconds")
+                .help(&format!(
+                    "Seconds between pulls of the desired state by the watchdog (default: {})",
+                    DEFAULT_STATE_INTERVAL
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("show-drift")
+                .long("show-drift")
+                .value_name("file")
+                .help("Show how the saved WiFi networks differ from the desired state file and exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("install-passpoint")
+                .long("install-passpoint")
+                .value_name("file")
+                .help("Install the Passpoint (Hotspot 2.0) profile from the file and exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("update-network")
+                .long("update-network")
+                .value_name("ssid")
+                .help("Change the settings of a saved WiFi network and exit")
+                .validator(validate_ssid_arg)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .long("priority")
+                .value_name("priority")
+                .help("Autoconnect priority of the updated network, higher ones are preferred")
+                .requires("update-network")
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("autoconnect")
+                .long("autoconnect")
+                .value_name("yes|no")
+                .help("Whether the updated network is connected to automatically")
+                .requires("update-network")
+                .possible_values(&["yes", "no"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("static-ip")
+                .long("static-ip")
+                .value_name("address/prefix")
+                .help("Static IPv4 address of the updated network, e.g. 192.168.1.10/24")
+                .requires("update-network")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("static-gateway")
+                .long("static-gateway")
+                .value_name("gateway")
+                .help("Gateway used with the static IPv4 address")
+                .requires("static-ip")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("static-dns")
+                .long("static-dns")
+                .value_name("servers")
+                .help("Comma separated DNS servers of the updated network")
+                .requires("update-network")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dhcp")
+                .long("dhcp")
+                .help("Switch the updated network from a static address back to DHCP")
+                .requires("update-network")
+                .conflicts_with("static-ip")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("connect")
                 .long("connect")
                 .value_name("ssid")
-                .help("Connect to a specific WiFi network")
+                .help("Connect to a specific WiFi network by SSID, or hex:<bytes> for raw SSIDs")
+                .validator(validate_ssid_arg)
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("passphrase")
                 .long("passphrase")
                 .value_name("passphrase")
-                .help("Passphrase for the WiFi network to connect to")
+                .help("Passphrase for the WiFi network to connect to or update")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eap-sim")
+                .long("eap-sim")
+                .value_name("method")
+                .help("Authenticate to the network to connect to with the SIM of the modem, e.g. a carrier offload network")
+                .possible_values(SIM_METHODS)
+                .requires("connect")
+                .conflicts_with("passphrase")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .value_name("host:port")
+                .help("Mandatory HTTP proxy of the network to connect to, written to the proxy file")
+                .requires("connect")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-pac-url")
+                .long("proxy-pac-url")
+                .value_name("url")
+                .help("Proxy auto-config URL of the network to connect to, stored in its connection profile")
+                .requires("connect")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-username")
+                .long("proxy-username")
+                .value_name("username")
+                .help("Username for the --proxy")
+                .requires("proxy")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-password")
+                .long("proxy-password")
+                .value_name("password")
+                .help("Password for the --proxy")
+                .requires("proxy-username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-file")
+                .long("proxy-file")
+                .value_name("path")
+                .help(&format!(
+                    "Shell variables file the proxy of the connected network is written to, passed to the --notify-exec hook in WIFI_CONNECT_PROXY (default: {})",
+                    DEFAULT_PROXY_FILE
+                ))
                 .takes_value(true),
         )
         // New hotspot management arguments
@@ -189,6 +809,32 @@ pub fn get_config() -> Config {
                 .help("Check hotspot status and exit")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("check-privileges")
+                .long("check-privileges")
+                .help("Report which privileges are available to run without root and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("doctor")
+                .long("doctor")
+                .help("Check whether the system can run the captive portal, print hints for failures and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .help("Also check DNS resolution and trace the route to the Internet with --doctor")
+                .requires("doctor")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ping")
+                .long("ping")
+                .value_name("host")
+                .help("Time TCP connections to the host, optionally given as host:port, and exit")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("restart-hotspot")
                 .long("restart-hotspot")
@@ -219,98 +865,1605 @@ pub fn get_config() -> Config {
                     .long("disconnect")
                     .help("Disconnects from the current WiFi network"),
         )
-        .get_matches();
-
-    let interface: Option<String> = matches.value_of("portal-interface").map_or_else(
-        || env::var("PORTAL_INTERFACE").ok(),
-        |v| Some(v.to_string()),
-    );
-
-    let ssid: String = matches.value_of("portal-ssid").map_or_else(
-        || env::var("PORTAL_SSID").unwrap_or_else(|_| DEFAULT_SSID.to_string()),
-        String::from,
-    );
-
-    let passphrase: Option<String> = matches.value_of("portal-passphrase").map_or_else(
-        || env::var("PORTAL_PASSPHRASE").ok(),
-        |v| Some(v.to_string()),
-    );
-
-    let gateway = Ipv4Addr::from_str(&matches.value_of("portal-gateway").map_or_else(
-        || env::var("PORTAL_GATEWAY").unwrap_or_else(|_| DEFAULT_GATEWAY.to_string()),
-        String::from,
-    ))
-    .expect("Cannot parse gateway address");
-
-    let dhcp_range = matches.value_of("portal-dhcp-range").map_or_else(
-        || env::var("PORTAL_DHCP_RANGE").unwrap_or_else(|_| DEFAULT_DHCP_RANGE.to_string()),
-        String::from,
-    );
-
-    let listening_port = matches
-        .value_of("portal-listening-port")
-        .map_or_else(
-            || {
-                env::var("PORTAL_LISTENING_PORT")
-                    .unwrap_or_else(|_| DEFAULT_LISTENING_PORT.to_string())
-            },
-            String::from,
+        .arg(
+            Arg::with_name("notify-exec")
+                .long("notify-exec")
+                .value_name("command")
+                .help("Shell command run on every state transition (state passed in WIFI_CONNECT_* variables)")
+                .takes_value(true),
         )
-        .parse::<u16>()
-        .expect("Cannot parse listening port number");
-
-    let activity_timeout = u64::from_str(&matches.value_of("activity-timeout").map_or_else(
-        || env::var("ACTIVITY_TIMEOUT").unwrap_or_else(|_| DEFAULT_ACTIVITY_TIMEOUT.to_string()),
-        String::from,
-    ))
-    .expect("Cannot parse activity timeout");
-
-    let ui_directory = get_ui_directory(matches.value_of("ui-directory"));
-    let forget_all = matches.is_present("forget-all");
-    let list_networks = matches.is_present("list-networks");
-    let list_connected = matches.is_present("list-connected");
-    let list_saved = matches.is_present("list-saved");
-    let forget_network = matches.value_of("forget-network").map(|s| s.to_string());
-    let connect = if let Some(ssid) = matches.value_of("connect") {
-        let passphrase = matches.value_of("passphrase").unwrap_or("").to_string();
-        Some((ssid.to_string(), passphrase))
-    } else {
-        None
-    };
-
-    // New hotspot command flags
-    let start_hotspot = matches.is_present("start-hotspot");
-    let stop_hotspot = matches.is_present("stop-hotspot");
-    let check_hotspot = matches.is_present("check-hotspot");
-    let restart_hotspot = matches.is_present("restart-hotspot");
-    let no_dhcp_gateway = matches.is_present("no-dhcp-gateway");
-    let no_dhcp_dns = matches.is_present("no-dhcp-dns");
-    let no_dhcp_router_option = matches.is_present("no-dhcp-router-option");
-
-    Config {
-        interface,
-        ssid,
-        passphrase,
-        gateway,
-        dhcp_range,
-        listening_port,
-        activity_timeout,
-        ui_directory,
-        forget_all,
-        list_networks,
-        list_connected,
-        list_saved,
-        forget_network,
-        connect,
-        start_hotspot,
-        stop_hotspot,
-        check_hotspot,
-        restart_hotspot,
-        no_dhcp_gateway,
-        no_dhcp_dns,
-        no_dhcp_router_option,
-        disconnect: matches.is_present("disconnect"),
-    }
+        .arg(
+            Arg::with_name("notify-dbus")
+                .long("notify-dbus")
+                .help("Emit a StateChanged D-Bus signal on every state transition")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("notify-led")
+                .long("notify-led")
+                .value_name("led")
+                .help("Name of a /sys/class/leds LED reflecting the current state")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-broker")
+                .long("mqtt-broker")
+                .value_name("host[:port]")
+                .help("MQTT broker receiving retained provisioning state messages (requires the mosquitto clients)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-topic-prefix")
+                .long("mqtt-topic-prefix")
+                .value_name("prefix")
+                .help(&format!(
+                    "Prefix of the MQTT state and command topics (default: {})",
+                    DEFAULT_MQTT_TOPIC_PREFIX
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-commands")
+                .long("mqtt-commands")
+                .help("Accept connect/forget commands on <prefix>/command/+")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("api-server")
+                .long("api-server")
+                .help("Run the management HTTP API (hotspot start/stop) instead of the captive portal")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("api-address")
+                .long("api-address")
+                .value_name("address")
+                .help(&format!(
+                    "Listening address of the management HTTP API, whose hotspot routes control the access point only, without the captive portal (default: {})",
+                    DEFAULT_API_ADDRESS
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("api-token")
+                .long("api-token")
+                .value_name("token")
+                .help("Bearer token required by the management HTTP API routes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("grpc-address")
+                .long("grpc-address")
+                .value_name("address")
+                .help("Also serve the management API over gRPC on this address (requires the `grpc` feature)")
+                .requires("api-server")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rotate-passphrase")
+                .long("rotate-passphrase")
+                .value_name("interval")
+                .help("Replace the hotspot passphrase with a random one every interval seconds, or only on `POST /hotspot/rotate` with 0. The current passphrase is kept across restarts.")
+                .requires("api-server")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-qr-code")
+                .long("print-qr-code")
+                .help("Print the QR code joining the hotspot whenever it starts or its passphrase changes, or with --check-hotspot for the device label (requires qrencode)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("guest-ssid")
+                .long("guest-ssid")
+                .value_name("ssid")
+                .help("SSID of an always-on guest hotspot sharing the internet connection, run next to the setup hotspot")
+                .validator(validate_ssid_arg)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("guest-passphrase")
+                .long("guest-passphrase")
+                .value_name("passphrase")
+                .help("WPA2 passphrase of the guest hotspot (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("guest-interface")
+                .long("guest-interface")
+                .value_name("interface")
+                .help(&format!(
+                    "Interface of the guest hotspot, added as a virtual AP interface if missing (default: {})",
+                    DEFAULT_GUEST_INTERFACE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("webhook-url")
+                .long("webhook-url")
+                .value_name("url")
+                .help("POST the outcome of provisioning as JSON to this URL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("webhook-secret")
+                .long("webhook-secret")
+                .value_name("secret")
+                .help("Sign webhook payloads with HMAC-SHA256 using this secret")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("device-id")
+                .long("device-id")
+                .value_name("id")
+                .help("Device ID reported in webhooks, over SSDP and in --redirect-url (default: machine ID)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("redirect-url")
+                .long("redirect-url")
+                .value_name("url")
+                .help("Send the client on to this URL, like an app deep link, once credentials are accepted ({ssid} and {device_id} are filled in)")
+                .validator(validate_redirect_url)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("venue-info-url")
+                .long("venue-info-url")
+                .value_name("url")
+                .help("Venue information page announced through the Captive Portal API (RFC 8908)")
+                .validator(validate_redirect_url)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-url")
+                .long("portal-url")
+                .value_name("url")
+                .help("Portal URL advertised with DHCP options 114 and 160 and the Captive Portal API (default: http://<gateway>/)")
+                .validator(validate_portal_url)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow-mac")
+                .long("allow-mac")
+                .value_name("mac,...")
+                .help("Only permit these stations to connect to the hotspot")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("deny-mac")
+                .long("deny-mac")
+                .value_name("mac,...")
+                .help("Refuse these stations a connection to the hotspot")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cloned-mac")
+                .long("cloned-mac")
+                .value_name("mac|random|stable")
+                .help("MAC address used by the created WiFi connection")
+                .validator(validate_cloned_mac)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connection-profile")
+                .long("connection-profile")
+                .value_name("profile")
+                .help("Tuning of the created WiFi connection: 'campus' turns power saving off and prefers 5 GHz access points for devices roaming between access points, 'low-power' turns power saving on (default: default)")
+                .possible_values(CONNECTION_PROFILES)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wifi-powersave")
+                .long("wifi-powersave")
+                .value_name("on|off")
+                .help("Power saving of the WiFi driver while connected, overriding the --connection-profile (default: driver default)")
+                .possible_values(&["on", "off"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eap-method")
+                .long("eap-method")
+                .value_name("method")
+                .help("EAP method of enterprise networks authenticating with a password (default: peap)")
+                .possible_values(EAP_METHODS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eap-phase2-auth")
+                .long("eap-phase2-auth")
+                .value_name("auth")
+                .help("Inner authentication of the EAP method of enterprise networks (default: mschapv2)")
+                .possible_values(PHASE2_AUTHS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("backend")
+                .help(&format!(
+                    "WiFi management backend (default: {})",
+                    default_backend()
+                ))
+                .possible_values(BACKENDS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mock-script")
+                .long("mock-script")
+                .value_name("path")
+                .help("JSON file with the scan results and connection outcomes of the mock backend")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-start-network-manager")
+                .long("no-start-network-manager")
+                .help("Fail instead of starting the NetworkManager service through systemd when it is not running")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("network-manager-timeout")
+                .long("network-manager-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to wait for NetworkManager to become available (default: {})",
+                    DEFAULT_NETWORK_MANAGER_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nm-retries")
+                .long("nm-retries")
+                .value_name("count")
+                .help(&format!(
+                    "Retries of NetworkManager calls failing with transient D-Bus errors (default: {})",
+                    DEFAULT_NM_RETRIES
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to wait for in-flight HTTP requests on shutdown (default: {})",
+                    DEFAULT_SHUTDOWN_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("low-memory")
+                .long("low-memory")
+                .help(&format!(
+                    "Save memory on boards with 128 MB of RAM or less: serve {} HTTP connections at once unless --http-connections is given, cache no scan results or connection profiles, stream the UI uncompressed and write compact JSON",
+                    LOW_MEMORY_HTTP_CONNECTIONS
+                ))
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("http-connections")
+                .long("http-connections")
+                .value_name("count")
+                .help(&format!(
+                    "HTTP connections served at once, each taking a thread (default: {})",
+                    DEFAULT_HTTP_CONNECTIONS
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http-keep-alive")
+                .long("http-keep-alive")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds idle HTTP connections are kept open, or 0 to close them after each request (default: {})",
+                    DEFAULT_HTTP_KEEP_ALIVE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http-timeout")
+                .long("http-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to read an HTTP request or write its response (default: {})",
+                    DEFAULT_HTTP_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connect-timeout")
+                .long("connect-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to wait for a connection to activate (default: {})",
+                    DEFAULT_CONNECT_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dhcp-timeout")
+                .long("dhcp-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to wait for an IP address after connecting (default: {})",
+                    DEFAULT_DHCP_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("require-time-sync")
+                .long("require-time-sync")
+                .value_name("timeout")
+                .help(&format!(
+                    "Only report success once the clock is synchronized over NTP, waiting up to the given seconds after connecting, and exit with code 35 otherwise (default: {})",
+                    NTP_SYNC_TIMEOUT
+                ))
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("with-rollback")
+                .long("with-rollback")
+                .value_name("timeout")
+                .help("Roll back --forget-all or --connect unless there is Internet connectivity within the given seconds, exiting with code 36 (network-manager backend only)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("speed-test")
+                .long("speed-test")
+                .value_name("url")
+                .help(&format!(
+                    "Download a test payload once connected and report the throughput and latency (default: {})",
+                    DEFAULT_SPEED_TEST_URL
+                ))
+                .validator(validate_portal_url)
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("activate-vpn")
+                .long("activate-vpn")
+                .value_name("name")
+                .help("VPN or WireGuard connection provisioned in NetworkManager to activate once connected with Internet connectivity")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coap")
+                .long("coap")
+                .value_name("port")
+                .help(&format!(
+                    "Also serve the networks and accept credentials over CoAP/UDP on the portal gateway (default port: {})",
+                    DEFAULT_COAP_PORT
+                ))
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("ssdp")
+                .long("ssdp")
+                .help("Advertise the portal, or the management HTTP API, over SSDP for discovery by apps")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("scan-timeout")
+                .long("scan-timeout")
+                .value_name("timeout")
+                .help(&format!(
+                    "Seconds to wait for a requested scan to complete and for access points to show up in it (default: {})",
+                    DEFAULT_SCAN_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pidfile")
+                .long("pidfile")
+                .value_name("path")
+                .help("Lock file ensuring a single instance manages the interface")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("extra-field")
+                .long("extra-field")
+                .value_name("name,label[,required]")
+                .help("Additional portal form field passed to the --notify-exec hook, can be given multiple times")
+                .validator(validate_extra_field)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("extra-fields-file")
+                .long("extra-fields-file")
+                .value_name("path")
+                .help(&format!(
+                    "JSON file receiving the values of the extra form fields (default: {})",
+                    DEFAULT_EXTRA_FIELDS_FILE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("first-boot")
+                .long("first-boot")
+                .help("Let the portal also set the timezone, accept the EULA and wait for NTP sync after connecting")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("eula")
+                .long("eula")
+                .value_name("path")
+                .help("Text file with the EULA that has to be accepted before connecting in --first-boot mode")
+                .requires("first-boot")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .help("Print the recorded provisioning attempts and exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("history-file")
+                .long("history-file")
+                .value_name("path")
+                .help(&format!(
+                    "File recording the outcome of provisioning attempts (default: {})",
+                    DEFAULT_HISTORY_FILE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("block-ssid")
+                .long("block-ssid")
+                .value_name("ssid")
+                .help("Add a network to the blocklist, hiding it from the portal and refusing to connect to it (multiple allowed)")
+                .validator(validate_ssid_arg)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("unblock-ssid")
+                .long("unblock-ssid")
+                .value_name("ssid")
+                .help("Remove a network from the blocklist (multiple allowed)")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("blocklist-file")
+                .long("blocklist-file")
+                .value_name("path")
+                .help(&format!(
+                    "File keeping the blocked networks across restarts (default: {})",
+                    DEFAULT_BLOCKLIST_FILE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("certificate-dir")
+                .long("certificate-dir")
+                .value_name("path")
+                .help(&format!(
+                    "Directory keeping the certificates of EAP-TLS networks uploaded through the portal (default: {})",
+                    DEFAULT_CERTIFICATE_DIRECTORY
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sandbox")
+                .long("sandbox")
+                .help("Restrict filesystem access and syscalls of the captive portal before it is set up, leaving writable only the files of WiFi Connect and its backend")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dnsmasq-opt")
+                .long("dnsmasq-opt")
+                .value_name("option")
+                .help("Extra dnsmasq option, e.g. domain=setup.lan, can be given multiple times")
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dnsmasq-conf")
+                .long("dnsmasq-conf")
+                .value_name("path")
+                .help("dnsmasq configuration file with extra directives")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debug-portal-traffic")
+                .long("debug-portal-traffic")
+                .help("Log DNS queries, DHCP exchanges and HTTP requests of portal clients")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("access-log")
+                .long("access-log")
+                .value_name("path|stderr")
+                .help("Log every request to the portal and the management API with the client address and user agent")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("access-log-format")
+                .long("access-log-format")
+                .value_name("format")
+                .help("Format of the access log lines (default: common)")
+                .possible_values(ACCESS_LOG_FORMATS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dns-port")
+                .long("dns-port")
+                .value_name("port")
+                .help(&format!(
+                    "Port dnsmasq serves DNS on, with portal DNS traffic redirected to it by nftables (default: {})",
+                    DEFAULT_DNS_PORT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dnsmasq-restart")
+                .long("dnsmasq-restart")
+                .help("Restart dnsmasq when it exits unexpectedly instead of shutting down the portal")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("wifi-direct-fallback")
+                .long("wifi-direct-fallback")
+                .help("Start the portal as a WiFi Direct group when the driver lacks access point mode")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("prefer-wifi-over-wwan")
+                .long("prefer-wifi-over-wwan")
+                .help("Only start the captive portal when neither WiFi nor a cellular modem is connected")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("metered")
+                .long("metered")
+                .help("Mark the created WiFi connection as metered")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-autoconnect")
+                .long("no-autoconnect")
+                .help("Disable autoconnect on the created WiFi connection")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("autoconnect-retries")
+                .long("autoconnect-retries")
+                .value_name("retries")
+                .help("Number of autoconnect attempts for the created WiFi connection (0 - forever)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("completions")
+                .long("completions")
+                .value_name("shell")
+                .help("Print the completion script for the shell and exit")
+                .possible_values(&["bash", "zsh", "fish"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("help-markdown")
+                .long("help-markdown")
+                .help("Print the command line reference as Markdown and exit"),
+        )
+        .arg(
+            Arg::with_name("help-man")
+                .long("help-man")
+                .help("Print the man page and exit"),
+        )
+        .subcommand(
+            SubCommand::with_name("watchdog")
+                .about("Monitor the WiFi connection without the portal, reconnecting to saved networks in range")
+                .arg(
+                    Arg::with_name("reconnect-after")
+                        .long("reconnect-after")
+                        .value_name("minutes")
+                        .help(&format!(
+                            "Minutes disconnected before reconnecting to a saved network in range (default: {})",
+                            DEFAULT_RECONNECT_AFTER
+                        ))
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("portal-after")
+                        .long("portal-after")
+                        .value_name("minutes")
+                        .help("Launch the portal once no saved network has been in range for the given minutes")
+                        .takes_value(true),
+                ),
+        ))
+}
+
+pub fn get_config() -> Config {
+    let matches = with_cli(|app| app.get_matches());
+
+    if let Some(shell) = matches.value_of("completions") {
+        let shell = shell.parse::<Shell>().expect("Cannot parse shell");
+        with_cli(|mut app| app.gen_completions_to(crate_name!(), shell, &mut io::stdout()));
+        process::exit(0);
+    }
+
+    if matches.is_present("help-markdown") {
+        print!("{}", with_cli(manual::markdown));
+        process::exit(0);
+    }
+
+    if matches.is_present("help-man") {
+        print!("{}", with_cli(manual::man_page));
+        process::exit(0);
+    }
+
+    let interface: Option<String> = matches.value_of("portal-interface").map_or_else(
+        || env::var(env_var("portal-interface")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let ssid: String = matches.value_of("portal-ssid").map_or_else(
+        || env::var(env_var("portal-ssid")).unwrap_or_else(|_| DEFAULT_SSID.to_string()),
+        String::from,
+    );
+
+    // The environment variable fallback bypasses the argument validator
+    if let Err(e) = validate_ssid_arg(ssid.clone()) {
+        clap::Error::value_validation_auto(e).exit();
+    }
+
+    let portal_channel = matches
+        .value_of("portal-channel")
+        .map_or_else(|| env::var(env_var("portal-channel")).ok(), |v| Some(v.to_string()))
+        .map(|channel| {
+            channel
+                .parse::<PortalChannel>()
+                .unwrap_or_else(|e| clap::Error::value_validation_auto(e).exit())
+        });
+
+    let client_isolation = matches.is_present("client-isolation");
+
+    let portal_band = matches
+        .value_of("portal-band")
+        .map_or_else(|| env::var(env_var("portal-band")).ok(), |v| Some(v.to_string()))
+        .map(|band| {
+            band.parse::<PortalBand>()
+                .unwrap_or_else(|e| clap::Error::value_validation_auto(e).exit())
+        });
+
+    let portal_passphrase: Option<String> = matches.value_of("portal-passphrase").map_or_else(
+        || env::var(env_var("portal-passphrase")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let random_passphrase = portal_passphrase
+        .as_ref()
+        .and_then(|passphrase| parse_random_passphrase(passphrase));
+
+    // The passphrase may come from the environment, which clap does not know of
+    if let Some(ref passphrase) = portal_passphrase {
+        if let Err(e) = validate_portal_passphrase(passphrase.clone()) {
+            clap::Error::value_validation_auto(e).exit();
+        }
+    }
+
+    if matches.is_present("portal-passphrase-strength-check") && random_passphrase.is_none() {
+        if let Some(ref passphrase) = portal_passphrase {
+            if let Err(e) = check_strength(passphrase, &ssid) {
+                clap::Error::value_validation_auto(e).exit();
+            }
+        }
+    }
+
+    let secrets = SecretsPolicy {
+        key: matches
+            .value_of("secrets-key")
+            .map_or_else(
+                || env::var(env_var("secrets-key")).ok(),
+                |v| Some(v.to_string()),
+            )
+            .map(|key| SecretsKey::parse(&key)),
+        required: matches.is_present("encrypt-secrets"),
+    };
+
+    let encrypt_passphrase = matches.is_present("encrypt-passphrase");
+
+    // The key may come from the environment, which clap does not know of
+    if (secrets.required || encrypt_passphrase) && secrets.key.is_none() {
+        clap::Error::value_validation_auto("encrypting secrets requires --secrets-key".into())
+            .exit();
+    }
+
+    let passphrase: Option<Secret> = match random_passphrase {
+        Some(length) => Some(
+            device_passphrase(length, &secrets).expect("Cannot generate the hotspot passphrase"),
+        ),
+        None => portal_passphrase.map(Secret::from),
+    };
+
+    let gateway = Ipv4Addr::from_str(&matches.value_of("portal-gateway").map_or_else(
+        || env::var(env_var("portal-gateway")).unwrap_or_else(|_| default_gateway().to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse gateway address");
+
+    let dhcp_range = matches.value_of("portal-dhcp-range").map_or_else(
+        || env::var(env_var("portal-dhcp-range")).unwrap_or_else(|_| DEFAULT_DHCP_RANGE.to_string()),
+        String::from,
+    );
+
+    let dhcp_lease_time: Option<String> = matches.value_of("dhcp-lease-time").map_or_else(
+        || env::var(env_var("dhcp-lease-time")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    // Reservations contain commas, so the environment variable separates them
+    // with semicolons
+    let dhcp_hosts: Vec<String> = match matches.values_of("dhcp-host") {
+        Some(values) => values.map(String::from).collect(),
+        None => env::var(env_var("dhcp-host"))
+            .unwrap_or_default()
+            .split(';')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect(),
+    };
+
+    // The environment variable fallbacks bypass the argument validators
+    let invalid = dhcp_lease_time
+        .iter()
+        .map(|time| validate_lease_time(time.clone()))
+        .chain(dhcp_hosts.iter().map(|host| validate_dhcp_host(host.clone())))
+        .find(|result| result.is_err());
+
+    if let Some(Err(e)) = invalid {
+        clap::Error::value_validation_auto(e).exit();
+    }
+
+    let listening_port = matches
+        .value_of("portal-listening-port")
+        .map_or_else(
+            || {
+                env::var(env_var("portal-listening-port"))
+                    .unwrap_or_else(|_| DEFAULT_LISTENING_PORT.to_string())
+            },
+            String::from,
+        )
+        .parse::<u16>()
+        .expect("Cannot parse listening port number");
+
+    let activity_timeout = u64::from_str(&matches.value_of("activity-timeout").map_or_else(
+        || env::var(env_var("activity-timeout")).unwrap_or_else(|_| DEFAULT_ACTIVITY_TIMEOUT.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse activity timeout");
+
+    let ui_directory = get_ui_directory(matches.value_of("ui-directory"));
+    let forget_all = matches.is_present("forget-all");
+    let forget_filter = ForgetFilter {
+        except: matches
+            .value_of("except")
+            .map(|ssids| {
+                ssids
+                    .split(',')
+                    .map(|ssid| ssid.trim().to_string())
+                    .filter(|ssid| !ssid.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        older_than: matches
+            .value_of("older-than")
+            .map(|v| u64::from_str(v).expect("Cannot parse older than days")),
+    };
+    let gc_connections = matches.is_present("gc-connections");
+    let gc_policy = GcPolicy {
+        keep: usize::from_str(&matches.value_of("keep").map_or_else(
+            || env::var(env_var("keep")).unwrap_or_else(|_| DEFAULT_GC_KEEP.to_string()),
+            String::from,
+        ))
+        .expect("Cannot parse the number of networks to keep"),
+        older_than: forget_filter.older_than,
+    };
+    let gc_interval = matches
+        .value_of("gc-interval")
+        .map_or_else(
+            || env::var(env_var("gc-interval")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|interval| {
+            interval
+                .parse::<u64>()
+                .expect("Cannot parse connection pruning interval")
+        })
+        .filter(|&interval| interval != 0);
+
+    if forget_filter.older_than.is_some() && !forget_all && !gc_connections && gc_interval.is_none()
+    {
+        clap::Error::value_validation_auto(
+            "--older-than requires --forget-all, --gc-connections or --gc-interval".into(),
+        )
+        .exit();
+    }
+
+    let list_networks = matches.is_present("list-networks");
+
+    let survey_interval = u64::from_str(
+        matches
+            .value_of("interval")
+            .unwrap_or(DEFAULT_SURVEY_INTERVAL),
+    )
+    .expect("Cannot parse survey interval");
+
+    let survey_duration = u64::from_str(
+        matches
+            .value_of("duration")
+            .unwrap_or(DEFAULT_SURVEY_DURATION),
+    )
+    .expect("Cannot parse survey duration");
+    let list_connected = matches.is_present("list-connected");
+
+    let watch = matches
+        .value_of("watch")
+        .map(|v| u64::from_str(v).expect("Cannot parse watch interval"));
+    let list_saved = matches.is_present("list-saved");
+    let forget_network = matches.value_of("forget-network").map(|s| s.to_string());
+    let connect = if let Some(ssid) = matches.value_of("connect") {
+        let passphrase = Secret::from(matches.value_of("passphrase").unwrap_or(""));
+        Some((ssid.to_string(), passphrase))
+    } else {
+        None
+    };
+
+    let mut proxy_address = matches.value_of("proxy").unwrap_or("").rsplitn(2, ':');
+    let proxy_port = proxy_address.next().map(String::from);
+    let proxy_host = proxy_address.next().map(String::from);
+
+    let connect_proxy = collect_proxy_settings(|name| match name {
+        "proxy_host" => proxy_host.clone(),
+        "proxy_port" => proxy_port.clone(),
+        "proxy_pac_url" => matches.value_of("proxy-pac-url").map(String::from),
+        "proxy_username" => matches.value_of("proxy-username").map(String::from),
+        "proxy_password" => matches.value_of("proxy-password").map(String::from),
+        _ => None,
+    })
+    .unwrap_or_else(|e| clap::Error::value_validation_auto(e).exit());
+
+    let proxy_file = PathBuf::from(matches.value_of("proxy-file").map_or_else(
+        || env::var(env_var("proxy-file")).unwrap_or_else(|_| DEFAULT_PROXY_FILE.to_string()),
+        String::from,
+    ));
+
+    let update_network = matches.value_of("update-network").map(|ssid| {
+        let update = ProfileUpdate {
+            passphrase: matches.value_of("passphrase").map(Secret::from),
+            autoconnect_priority: matches
+                .value_of("priority")
+                .map(|v| i32::from_str(v).expect("Cannot parse autoconnect priority")),
+            autoconnect: matches.value_of("autoconnect").map(|v| v == "yes"),
+            address: matches.value_of("static-ip").map(String::from),
+            gateway: matches.value_of("static-gateway").map(String::from),
+            dns: matches
+                .value_of("static-dns")
+                .map(|servers| {
+                    servers
+                        .split(',')
+                        .map(|server| server.trim().to_string())
+                        .filter(|server| !server.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            dhcp: matches.is_present("dhcp"),
+        };
+
+        if let Err(e) = update.validate() {
+            clap::Error::value_validation_auto(e).exit();
+        }
+
+        (ssid.to_string(), update)
+    });
+
+    // New hotspot command flags
+    let start_hotspot = matches.is_present("start-hotspot");
+    let stop_hotspot = matches.is_present("stop-hotspot");
+    let check_hotspot = matches.is_present("check-hotspot");
+    let check_privileges = matches.is_present("check-privileges");
+    let restart_hotspot = matches.is_present("restart-hotspot");
+    let no_dhcp_gateway = matches.is_present("no-dhcp-gateway");
+    let no_dhcp_dns = matches.is_present("no-dhcp-dns");
+    let no_dhcp_router_option = matches.is_present("no-dhcp-router-option");
+
+    let notify_exec: Option<String> = matches.value_of("notify-exec").map_or_else(
+        || env::var(env_var("notify-exec")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let notify_dbus = matches.is_present("notify-dbus");
+
+    let notify_led: Option<String> = matches.value_of("notify-led").map_or_else(
+        || env::var(env_var("notify-led")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mqtt_broker: Option<String> = matches.value_of("mqtt-broker").map_or_else(
+        || env::var(env_var("mqtt-broker")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    if matches.is_present("sandbox") && !cfg!(target_os = "linux") {
+        clap::Error::value_validation_auto("--sandbox is only available on Linux".into()).exit();
+    }
+
+    let mqtt_topic_prefix: String = matches.value_of("mqtt-topic-prefix").map_or_else(
+        || env::var(env_var("mqtt-topic-prefix")).unwrap_or_else(|_| DEFAULT_MQTT_TOPIC_PREFIX.to_string()),
+        String::from,
+    );
+
+    let mqtt_commands = matches.is_present("mqtt-commands");
+
+    let api_server = matches.is_present("api-server");
+
+    let api_address: String = matches.value_of("api-address").map_or_else(
+        || env::var(env_var("api-address")).unwrap_or_else(|_| DEFAULT_API_ADDRESS.to_string()),
+        String::from,
+    );
+
+    let api_token: Option<String> = matches.value_of("api-token").map_or_else(
+        || env::var(env_var("api-token")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let grpc_address: Option<String> = matches.value_of("grpc-address").map_or_else(
+        || env::var(env_var("grpc-address")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let access_log_format = matches
+        .value_of("access-log-format")
+        .map_or_else(
+            || env::var(env_var("access-log-format")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map_or(AccessLogFormat::Common, |format| {
+            format
+                .parse::<AccessLogFormat>()
+                .unwrap_or_else(|e| clap::Error::value_validation_auto(e).exit())
+        });
+
+    let access_log = matches
+        .value_of("access-log")
+        .map_or_else(
+            || env::var(env_var("access-log")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|target| AccessLogSettings {
+            target: AccessLogTarget::parse(&target),
+            format: access_log_format,
+        });
+
+    let rotate_passphrase = matches
+        .value_of("rotate-passphrase")
+        .map_or_else(|| env::var(env_var("rotate-passphrase")).ok(), |v| Some(v.to_string()))
+        .map(|interval| {
+            interval
+                .parse::<u64>()
+                .expect("Cannot parse passphrase rotation interval")
+        });
+
+    let guest_ssid: Option<String> = matches.value_of("guest-ssid").map_or_else(
+        || env::var(env_var("guest-ssid")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let guest_passphrase: Option<Secret> = matches
+        .value_of("guest-passphrase")
+        .map_or_else(|| env::var(env_var("guest-passphrase")).ok(), |v| Some(v.to_string()))
+        .map(Secret::from);
+
+    let guest_interface: String = matches.value_of("guest-interface").map_or_else(
+        || env::var(env_var("guest-interface")).unwrap_or_else(|_| DEFAULT_GUEST_INTERFACE.to_string()),
+        String::from,
+    );
+
+    let webhook_url: Option<String> = matches.value_of("webhook-url").map_or_else(
+        || env::var(env_var("webhook-url")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let webhook_secret: Option<Secret> = matches
+        .value_of("webhook-secret")
+        .map_or_else(|| env::var(env_var("webhook-secret")).ok(), |v| Some(v.to_string()))
+        .map(Secret::from);
+
+    let device_id: Option<String> = matches.value_of("device-id").map_or_else(
+        || env::var(env_var("device-id")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let redirect_url: Option<String> = matches.value_of("redirect-url").map_or_else(
+        || env::var(env_var("redirect-url")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let venue_info_url: Option<String> = matches.value_of("venue-info-url").map_or_else(
+        || env::var(env_var("venue-info-url")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let portal_url: Option<String> = matches.value_of("portal-url").map_or_else(
+        || env::var(env_var("portal-url")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mac_filter = MacFilter {
+        allow: get_mac_list(matches.value_of("allow-mac"), env_var("allow-mac")),
+        deny: get_mac_list(matches.value_of("deny-mac"), env_var("deny-mac")),
+    };
+
+    let cloned_mac: Option<String> = matches.value_of("cloned-mac").map_or_else(
+        || env::var(env_var("cloned-mac")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let autoconnect_retries = matches
+        .value_of("autoconnect-retries")
+        .map(|v| v.parse::<u32>().expect("Cannot parse autoconnect retries"));
+
+    let network_manager_timeout = u64::from_str(&matches.value_of("network-manager-timeout").map_or_else(
+        || {
+            env::var(env_var("network-manager-timeout"))
+                .unwrap_or_else(|_| DEFAULT_NETWORK_MANAGER_TIMEOUT.to_string())
+        },
+        String::from,
+    ))
+    .expect("Cannot parse NetworkManager timeout");
+
+    let nm_retries = usize::from_str(&matches.value_of("nm-retries").map_or_else(
+        || env::var(env_var("nm-retries")).unwrap_or_else(|_| DEFAULT_NM_RETRIES.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse NetworkManager retries");
+
+    let dnsmasq_options: Vec<String> = match matches.values_of("dnsmasq-opt") {
+        Some(values) => values.map(String::from).collect(),
+        None => env::var(env_var("dnsmasq-opt"))
+            .unwrap_or_default()
+            .split(';')
+            .map(|option| option.trim().to_string())
+            .filter(|option| !option.is_empty())
+            .collect(),
+    };
+
+    let dnsmasq_conf: Option<PathBuf> = matches
+        .value_of("dnsmasq-conf")
+        .map_or_else(|| env::var(env_var("dnsmasq-conf")).ok(), |v| Some(v.to_string()))
+        .map(PathBuf::from);
+
+    let dns_port = matches
+        .value_of("dns-port")
+        .map_or_else(
+            || env::var(env_var("dns-port")).unwrap_or_else(|_| DEFAULT_DNS_PORT.to_string()),
+            String::from,
+        )
+        .parse::<u16>()
+        .expect("Cannot parse DNS port number");
+
+    let shutdown_timeout = u64::from_str(&matches.value_of("shutdown-timeout").map_or_else(
+        || env::var(env_var("shutdown-timeout")).unwrap_or_else(|_| DEFAULT_SHUTDOWN_TIMEOUT.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse shutdown timeout");
+
+    let low_memory = matches.is_present("low-memory");
+
+    let http_limits = HttpLimits {
+        connections: usize::from_str(&matches.value_of("http-connections").map_or_else(
+            || {
+                env::var(env_var("http-connections")).unwrap_or_else(|_| {
+                    if low_memory {
+                        LOW_MEMORY_HTTP_CONNECTIONS.to_string()
+                    } else {
+                        DEFAULT_HTTP_CONNECTIONS.to_string()
+                    }
+                })
+            },
+            String::from,
+        ))
+        .expect("Cannot parse HTTP connection limit"),
+        keep_alive: u64::from_str(&matches.value_of("http-keep-alive").map_or_else(
+            || {
+                env::var(env_var("http-keep-alive"))
+                    .unwrap_or_else(|_| DEFAULT_HTTP_KEEP_ALIVE.to_string())
+            },
+            String::from,
+        ))
+        .expect("Cannot parse HTTP keep-alive timeout"),
+        timeout: u64::from_str(&matches.value_of("http-timeout").map_or_else(
+            || {
+                env::var(env_var("http-timeout"))
+                    .unwrap_or_else(|_| DEFAULT_HTTP_TIMEOUT.to_string())
+            },
+            String::from,
+        ))
+        .expect("Cannot parse HTTP timeout"),
+    };
+
+    if http_limits.connections == 0 || http_limits.timeout == 0 {
+        clap::Error::value_validation_auto(
+            "--http-connections and --http-timeout have to be at least 1".into(),
+        )
+        .exit();
+    }
+
+    let connect_timeout = u64::from_str(&matches.value_of("connect-timeout").map_or_else(
+        || env::var(env_var("connect-timeout")).unwrap_or_else(|_| DEFAULT_CONNECT_TIMEOUT.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse connect timeout");
+
+    let dhcp_timeout = u64::from_str(&matches.value_of("dhcp-timeout").map_or_else(
+        || env::var(env_var("dhcp-timeout")).unwrap_or_else(|_| DEFAULT_DHCP_TIMEOUT.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse DHCP timeout");
+
+    let require_time_sync = if matches.is_present("require-time-sync") {
+        Some(
+            matches
+                .value_of("require-time-sync")
+                .map_or(NTP_SYNC_TIMEOUT, |timeout| {
+                    u64::from_str(timeout).expect("Cannot parse time sync timeout")
+                }),
+        )
+    } else {
+        None
+    };
+
+    let with_rollback = matches
+        .value_of("with-rollback")
+        .map_or_else(
+            || env::var(env_var("with-rollback")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|timeout| u64::from_str(&timeout).expect("Cannot parse rollback timeout"));
+
+    let coap_port = if matches.is_present("coap") {
+        Some(matches.value_of("coap").map_or(DEFAULT_COAP_PORT, |port| {
+            port.parse::<u16>().expect("Cannot parse CoAP port")
+        }))
+    } else {
+        None
+    };
+
+    let activate_vpn = matches.value_of("activate-vpn").map_or_else(
+        || env::var(env_var("activate-vpn")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let ssdp = matches.is_present("ssdp");
+
+    let speed_test = if matches.is_present("speed-test") {
+        Some(
+            matches
+                .value_of("speed-test")
+                .unwrap_or(DEFAULT_SPEED_TEST_URL)
+                .to_string(),
+        )
+    } else {
+        None
+    };
+
+    let scan_timeout = u64::from_str(&matches.value_of("scan-timeout").map_or_else(
+        || env::var(env_var("scan-timeout")).unwrap_or_else(|_| DEFAULT_SCAN_TIMEOUT.to_string()),
+        String::from,
+    ))
+    .expect("Cannot parse scan timeout");
+
+    let history_file = PathBuf::from(matches.value_of("history-file").map_or_else(
+        || env::var(env_var("history-file")).unwrap_or_else(|_| DEFAULT_HISTORY_FILE.to_string()),
+        String::from,
+    ));
+
+    // SSIDs may contain commas, so the environment variable separates them
+    // with semicolons
+    let block_ssids: Vec<String> = match matches.values_of("block-ssid") {
+        Some(values) => values.map(String::from).collect(),
+        None => env::var(env_var("block-ssid"))
+            .unwrap_or_default()
+            .split(';')
+            .filter(|ssid| !ssid.is_empty())
+            .map(String::from)
+            .collect(),
+    };
+
+    let unblock_ssids: Vec<String> = matches
+        .values_of("unblock-ssid")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+
+    let blocklist_file = PathBuf::from(matches.value_of("blocklist-file").map_or_else(
+        || {
+            env::var(env_var("blocklist-file"))
+                .unwrap_or_else(|_| DEFAULT_BLOCKLIST_FILE.to_string())
+        },
+        String::from,
+    ));
+
+    let certificate_directory = PathBuf::from(matches.value_of("certificate-dir").map_or_else(
+        || {
+            env::var(env_var("certificate-dir"))
+                .unwrap_or_else(|_| DEFAULT_CERTIFICATE_DIRECTORY.to_string())
+        },
+        String::from,
+    ));
+
+    let extra_fields = matches
+        .values_of("extra-field")
+        .map(|values| {
+            values
+                .map(|value| parse_extra_field(value).expect("Cannot parse extra field"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let extra_fields_file = PathBuf::from(matches.value_of("extra-fields-file").map_or_else(
+        || {
+            env::var(env_var("extra-fields-file"))
+                .unwrap_or_else(|_| DEFAULT_EXTRA_FIELDS_FILE.to_string())
+        },
+        String::from,
+    ));
+
+    let pidfile: Option<PathBuf> = matches
+        .value_of("pidfile")
+        .map_or_else(|| env::var(env_var("pidfile")).ok(), |v| Some(v.to_string()))
+        .map(PathBuf::from);
+
+    let mock_script: Option<PathBuf> = matches
+        .value_of("mock-script")
+        .map_or_else(|| env::var(env_var("mock-script")).ok(), |v| Some(v.to_string()))
+        .map(PathBuf::from);
+
+    let backend: String = matches.value_of("backend").map_or_else(
+        || env::var(env_var("backend")).unwrap_or_else(|_| default_backend().to_string()),
+        String::from,
+    );
+
+    let apply_state: Option<PathBuf> = matches
+        .value_of("apply-state")
+        .map_or_else(
+            || env::var(env_var("apply-state")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(PathBuf::from);
+
+    let state_url = matches
+        .value_of("state-url")
+        .map_or_else(
+            || env::var(env_var("state-url")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|url| {
+            // Unsigned manifests are never applied, so ask for the secret
+            // up front
+            let secret = matches
+                .value_of("state-secret")
+                .map_or_else(
+                    || env::var(env_var("state-secret")).ok(),
+                    |v| Some(v.to_string()),
+                )
+                .map(Secret::from)
+                .unwrap_or_else(|| {
+                    clap::Error::value_validation_auto("--state-url requires --state-secret".into())
+                        .exit()
+                });
+
+            let interval = u64::from_str(&matches.value_of("state-interval").map_or_else(
+                || {
+                    env::var(env_var("state-interval"))
+                        .unwrap_or_else(|_| DEFAULT_STATE_INTERVAL.to_string())
+                },
+                String::from,
+            ))
+            .expect("Cannot parse state interval");
+
+            StateUrl {
+                url,
+                secret,
+                interval,
+            }
+        });
+
+    let watchdog = matches.subcommand_matches("watchdog").map(|watchdog| {
+        let reconnect_after = u64::from_str(&watchdog.value_of("reconnect-after").map_or_else(
+            || {
+                env::var(env_var("reconnect-after"))
+                    .unwrap_or_else(|_| DEFAULT_RECONNECT_AFTER.to_string())
+            },
+            String::from,
+        ))
+        .expect("Cannot parse reconnect delay");
+
+        let portal_after = watchdog
+            .value_of("portal-after")
+            .map_or_else(
+                || env::var(env_var("portal-after")).ok(),
+                |v| Some(v.to_string()),
+            )
+            .map(|v| u64::from_str(&v).expect("Cannot parse portal delay"));
+
+        WatchdogSettings {
+            reconnect_after,
+            portal_after,
+            desired_state: apply_state.clone(),
+            state_url: state_url.clone(),
+            secrets: secrets.clone(),
+            scan_timeout,
+        }
+    });
+
+    let connection_profile = matches
+        .value_of("connection-profile")
+        .map_or_else(
+            || env::var(env_var("connection-profile")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|profile| {
+            ConnectionProfile::from_str(&profile)
+                .unwrap_or_else(|e| clap::Error::value_validation_auto(e).exit())
+        })
+        .unwrap_or_default();
+
+    let powersave = matches
+        .value_of("wifi-powersave")
+        .map_or_else(
+            || env::var(env_var("wifi-powersave")).ok(),
+            |v| Some(v.to_string()),
+        )
+        .map(|powersave| match powersave.as_str() {
+            "on" => true,
+            "off" => false,
+            _ => {
+                clap::Error::value_validation_auto(format!("'{}' is not one of on, off", powersave))
+                    .exit()
+            }
+        });
+
+    let eap_method = matches.value_of("eap-method").map_or_else(
+        || env::var(env_var("eap-method")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    if let Some(ref method) = eap_method {
+        if !EAP_METHODS.contains(&method.as_str()) {
+            clap::Error::value_validation_auto(format!(
+                "'{}' is not one of {}",
+                method,
+                EAP_METHODS.join(", ")
+            ))
+            .exit();
+        }
+    }
+
+    let phase2_auth = matches.value_of("eap-phase2-auth").map_or_else(
+        || env::var(env_var("eap-phase2-auth")).ok(),
+        |v| Some(v.to_string()),
+    );
+
+    if let Some(ref auth) = phase2_auth {
+        if !PHASE2_AUTHS.contains(&auth.as_str()) {
+            clap::Error::value_validation_auto(format!(
+                "'{}' is not one of {}",
+                auth,
+                PHASE2_AUTHS.join(", ")
+            ))
+            .exit();
+        }
+    }
+
+    let profile_settings = ProfileSettings {
+        id: None,
+        cloned_mac,
+        metered: matches.is_present("metered"),
+        no_autoconnect: matches.is_present("no-autoconnect"),
+        autoconnect_retries,
+        autoconnect_priority: None,
+        channel: None,
+        band: None,
+        ap_isolation: false,
+        owe: false,
+        proxy_pac_url: None,
+        connection_profile,
+        powersave,
+        eap_method,
+        phase2_auth,
+    };
+
+    Config {
+        interface,
+        ssid,
+        passphrase,
+        random_passphrase,
+        gateway,
+        dhcp_range,
+        dhcp_lease_time,
+        dhcp_hosts,
+        dnsmasq_options,
+        dnsmasq_conf,
+        debug_portal_traffic: matches.is_present("debug-portal-traffic"),
+        access_log,
+        listening_port,
+        activity_timeout,
+        ui_directory,
+        forget_all,
+        forget_filter,
+        gc_connections,
+        gc_policy,
+        gc_interval,
+        yes: matches.is_present("yes"),
+        list_networks,
+        all_interfaces: matches.is_present("all-interfaces"),
+        survey: matches.is_present("survey"),
+        survey_interval,
+        survey_duration,
+        survey_json: matches.is_present("json"),
+        channel_report: matches.is_present("channel-report"),
+        portal_channel,
+        portal_band,
+        client_isolation,
+        portal_owe: matches.is_present("portal-owe"),
+        list_interfaces: matches.is_present("list-interfaces"),
+        list_connected,
+        watch,
+        list_saved,
+        list_managed: matches.is_present("list-managed"),
+        forget_managed: matches.is_present("forget-managed"),
+        forget_network,
+        show_network: matches.value_of("show-network").map(String::from),
+        apply_state,
+        show_drift: matches.value_of("show-drift").map(PathBuf::from),
+        install_passpoint: matches.value_of("install-passpoint").map(PathBuf::from),
+        state_url,
+        update_network,
+        connect,
+        connect_proxy,
+        connect_eap_sim: matches.value_of("eap-sim").and_then(SimMethod::parse),
+        proxy_file,
+        start_hotspot,
+        stop_hotspot,
+        check_hotspot,
+        check_privileges,
+        doctor: matches.is_present("doctor"),
+        doctor_network: matches.is_present("network"),
+        ping: matches.value_of("ping").map(String::from),
+        restart_hotspot,
+        no_dhcp_gateway,
+        no_dhcp_dns,
+        no_dhcp_router_option,
+        disconnect: matches.is_present("disconnect"),
+        dry_run: matches.is_present("dry-run"),
+        notify_exec,
+        notify_dbus,
+        notify_led,
+        mqtt_broker,
+        mqtt_topic_prefix,
+        mqtt_commands,
+        api_server,
+        api_address,
+        api_token,
+        grpc_address,
+        rotate_passphrase,
+        print_qr_code: matches.is_present("print-qr-code"),
+        guest_ssid,
+        guest_passphrase,
+        guest_interface,
+        webhook_url,
+        webhook_secret,
+        device_id,
+        redirect_url,
+        venue_info_url,
+        portal_url,
+        mac_filter,
+        profile_settings,
+        prefer_wifi_over_wwan: matches.is_present("prefer-wifi-over-wwan"),
+        start_network_manager: !matches.is_present("no-start-network-manager"),
+        network_manager_timeout,
+        nm_retries,
+        backend,
+        shutdown_timeout,
+        http_limits,
+        low_memory,
+        connect_timeout,
+        dhcp_timeout,
+        require_time_sync,
+        with_rollback,
+        speed_test,
+        activate_vpn,
+        coap_port,
+        ssdp,
+        scan_timeout,
+        pidfile,
+        history: matches.is_present("history"),
+        extra_fields,
+        extra_fields_file,
+        first_boot: matches.is_present("first-boot"),
+        eula: matches.value_of("eula").map(String::from),
+        history_file,
+        block_ssids,
+        unblock_ssids,
+        blocklist_file,
+        blocked_ssids: Vec::new(),
+        certificate_directory,
+        sandbox: matches.is_present("sandbox"),
+        wifi_direct_fallback: matches.is_present("wifi-direct-fallback"),
+        dnsmasq_restart: matches.is_present("dnsmasq-restart"),
+        dns_port,
+        mock_script,
+        watchdog,
+        secrets,
+        encrypt_passphrase,
+    }
+}
+
+/// Environment variable counterpart of an option, if it has one
+pub fn env_var_of(arg: &str) -> Option<&'static str> {
+    ENV_VARS
+        .iter()
+        .find(|&&(name, _)| name == arg)
+        .map(|&(_, var)| var)
+}
+
+fn env_var(arg: &str) -> &'static str {
+    env_var_of(arg).expect("Option without environment variable")
+}
+
+/// Outside Linux only the simulation can run, so that the portal, the UI
+/// and the command line can be developed on macOS
+fn default_backend() -> &'static str {
+    if cfg!(target_os = "linux") {
+        DEFAULT_BACKEND
+    } else {
+        MOCK_BACKEND
+    }
+}
+
+fn default_gateway() -> &'static str {
+    if cfg!(target_os = "linux") {
+        DEFAULT_GATEWAY
+    } else {
+        SIMULATION_GATEWAY
+    }
+}
+
+fn validate_ssid_arg(value: String) -> ::std::result::Result<(), String> {
+    validate_ssid(&value).map_err(|e| e.to_string())
+}
+
+fn get_mac_list(cmd_mac_list: Option<&str>, var: &str) -> Vec<String> {
+    let mac_list = cmd_mac_list.map_or_else(|| env::var(var).unwrap_or_default(), String::from);
+
+    mac_list
+        .split(',')
+        .map(|mac| mac.trim().to_lowercase())
+        .filter(|mac| !mac.is_empty())
+        .inspect(|mac| {
+            // A malformed entry would otherwise never match and silently
+            // let the station through
+            if !is_mac_address(mac) {
+                clap::Error::value_validation_auto(format!(
+                    "'{}' is not a MAC address of the form xx:xx:xx:xx:xx:xx",
+                    mac
+                ))
+                .exit();
+            }
+        })
+        .collect()
 }
 
 fn get_ui_directory(cmd_ui_directory: Option<&str>) -> PathBuf {
@@ -318,7 +2471,7 @@ fn get_ui_directory(cmd_ui_directory: Option<&str>) -> PathBuf {
         return PathBuf::from(ui_directory);
     }
 
-    if let Ok(ui_directory) = env::var("UI_DIRECTORY") {
+    if let Ok(ui_directory) = env::var(env_var("ui-directory")) {
         return PathBuf::from(ui_directory);
     }
 
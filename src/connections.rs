@@ -0,0 +1,93 @@
+//! Cache of the connection profiles of NetworkManager. The network-manager
+//! crate reads the settings of every profile with a D-Bus round trip of its
+//! own when listing them, which takes seconds on devices with dozens of
+//! stale profiles. The profiles are kept per thread, as the D-Bus connection
+//! of the crate is, and listed anew once NetworkManager reports other
+//! profile paths than they were listed with. Profiles get a new path when
+//! added, so this catches the changes of other programs too. Settings
+//! changed in place are not noticed, which is fine for the SSID, mode and
+//! name the cached profiles are looked at for. Nothing is cached with
+//! `--low-memory`.
+
+use std::cell::RefCell;
+use std::process::Command;
+
+use network_manager::{Connection, NetworkManager};
+
+use errors::*;
+use low_memory::is_low_memory;
+use retry::retry_nm;
+
+const NM_DESTINATION: &str = "--dest=org.freedesktop.NetworkManager";
+const NM_SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+
+struct CachedConnections {
+    paths: Vec<String>,
+    connections: Vec<Connection>,
+}
+
+thread_local! {
+    static CACHE: RefCell<Option<CachedConnections>> = RefCell::new(None);
+}
+
+/// Calls `f` with the connection profiles, listed anew only when they
+/// changed. `f` must not list the profiles itself.
+pub fn with_connections<F, T>(manager: &NetworkManager, f: F) -> Result<T>
+where
+    F: FnOnce(&[Connection]) -> T,
+{
+    let paths = match connection_paths() {
+        Some(paths) if !is_low_memory() => paths,
+        // Without the paths there is nothing to tell a stale cache by
+        _ => {
+            let connections = retry_nm("Listing connections", || manager.get_connections())?;
+            return Ok(f(&connections));
+        }
+    };
+
+    CACHE.with(|cache| {
+        if let Some(ref cached) = *cache.borrow() {
+            if cached.paths == paths {
+                return Ok(f(&cached.connections));
+            }
+        }
+
+        let connections = retry_nm("Listing connections", || manager.get_connections())?;
+
+        debug!("Listed {} connection profiles", connections.len());
+
+        let result = f(&connections);
+
+        *cache.borrow_mut() = Some(CachedConnections { paths, connections });
+
+        Ok(result)
+    })
+}
+
+/// Object paths of the profiles, with a single D-Bus call
+fn connection_paths() -> Option<Vec<String>> {
+    let output = Command::new("dbus-send")
+        .args(&[
+            "--system",
+            "--print-reply",
+            NM_DESTINATION,
+            NM_SETTINGS_PATH,
+            "org.freedesktop.NetworkManager.Settings.ListConnections",
+        ])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        debug!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        return None;
+    }
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(|token| token.trim_matches('"'))
+            .filter(|token| token.starts_with(NM_SETTINGS_PATH))
+            .map(String::from)
+            .collect(),
+    )
+}
@@ -0,0 +1,104 @@
+//! Checks of the credentials entered in the portal that need no connection
+//! attempt, served by `POST /validate`. A typo caught there costs the user a
+//! moment instead of a failed connection, which takes the hotspot down until
+//! the connect timeout passes.
+
+/// Security types of the scan results
+pub const SECURITY_TYPES: &[&str] = &["none", "wep", "wpa", "enterprise"];
+
+/// What is wrong with one of the fields of the credentials
+#[derive(Debug, Serialize)]
+pub struct CredentialProblem {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl CredentialProblem {
+    pub fn new(field: &'static str, message: String) -> Self {
+        CredentialProblem { field, message }
+    }
+}
+
+/// Problems of the identity and passphrase for a network of `security`.
+/// EAP-TLS networks with uploaded certificates need no passphrase, as it
+/// only unlocks the private key.
+pub fn check_credentials(
+    security: &str,
+    identity: &str,
+    passphrase: &str,
+    has_certificates: bool,
+) -> Vec<CredentialProblem> {
+    let mut problems = Vec::new();
+
+    let passphrase_problem = match security {
+        "none" => None,
+        "wep" => check_wep_key(passphrase),
+        "wpa" => check_wpa_passphrase(passphrase),
+        "enterprise" => {
+            if identity.is_empty() {
+                problems.push(CredentialProblem::new(
+                    "identity",
+                    "Enterprise networks require a user".into(),
+                ));
+            }
+
+            if passphrase.is_empty() && !has_certificates {
+                Some("Enterprise networks require a password".to_string())
+            } else {
+                None
+            }
+        }
+        _ => {
+            problems.push(CredentialProblem::new(
+                "security",
+                format!("'{}' is not one of {}", security, SECURITY_TYPES.join(", ")),
+            ));
+            None
+        }
+    };
+
+    if let Some(message) = passphrase_problem {
+        problems.push(CredentialProblem::new("passphrase", message));
+    }
+
+    problems
+}
+
+/// 8 to 63 printable ASCII characters, or the PSK as 64 hex digits
+fn check_wpa_passphrase(passphrase: &str) -> Option<String> {
+    if passphrase.len() == 64 && is_hex(passphrase) {
+        return None;
+    }
+
+    if passphrase.len() < 8 || passphrase.len() > 63 {
+        return Some(format!(
+            "WPA passphrases have 8 to 63 characters, not {}",
+            passphrase.chars().count()
+        ));
+    }
+
+    if !passphrase.chars().all(|c| c >= ' ' && c <= '~') {
+        return Some("WPA passphrases may only contain printable ASCII characters".into());
+    }
+
+    None
+}
+
+/// 5 or 13 ASCII characters, or 10 or 26 hex digits, for 64 and 128 bit WEP
+fn check_wep_key(key: &str) -> Option<String> {
+    let valid = match key.len() {
+        5 | 13 => key.is_ascii(),
+        10 | 26 => is_hex(key),
+        _ => false,
+    };
+
+    if valid {
+        None
+    } else {
+        Some("WEP keys have 5 or 13 characters, or 10 or 26 hex digits".into())
+    }
+}
+
+fn is_hex(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii_hexdigit())
+}
@@ -0,0 +1,902 @@
+//! Minimal D-Bus client speaking the wire protocol over the system bus
+//! socket, for the NetworkManager backend of builds without the libdbus
+//! based `network-manager` feature, like static musl binaries. Only method
+//! calls are supported: replies are read synchronously and signals in
+//! between are dropped.
+//!
+//! Messages are sent little-endian and read in either byte order. The
+//! supported types are the basic ones, arrays, structs, dict entries and
+//! variants; unix file descriptors are not.
+
+use std::cell::Cell;
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use nix::unistd::getuid;
+
+use errors::*;
+
+const SYSTEM_BUS_ADDRESS_VARIABLE: &str = "DBUS_SYSTEM_BUS_ADDRESS";
+
+const DEFAULT_SYSTEM_BUS_SOCKET: &str = "/var/run/dbus/system_bus_socket";
+
+/// Seconds to wait for a reply, the default of libdbus
+const CALL_TIMEOUT: u64 = 25;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// Largest message the bus daemon accepts
+const MAX_MESSAGE_SIZE: usize = 128 * 1024 * 1024;
+
+const PROTOCOL_VERSION: u8 = 1;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Value of a D-Bus message body
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    String(String),
+    ObjectPath(String),
+    Signature(String),
+    /// Signature of the elements, which an empty array needs as well
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match *self {
+            Value::Byte(_) => "y".into(),
+            Value::Bool(_) => "b".into(),
+            Value::Int16(_) => "n".into(),
+            Value::UInt16(_) => "q".into(),
+            Value::Int32(_) => "i".into(),
+            Value::UInt32(_) => "u".into(),
+            Value::Int64(_) => "x".into(),
+            Value::UInt64(_) => "t".into(),
+            Value::Double(_) => "d".into(),
+            Value::String(_) => "s".into(),
+            Value::ObjectPath(_) => "o".into(),
+            Value::Signature(_) => "g".into(),
+            Value::Array(ref element, _) => format!("a{}", element),
+            Value::Struct(ref fields) => {
+                let fields: String = fields.iter().map(Value::signature).collect();
+                format!("({})", fields)
+            }
+            Value::DictEntry(ref key, ref value) => {
+                format!("{{{}{}}}", key.signature(), value.signature())
+            }
+            Value::Variant(_) => "v".into(),
+        }
+    }
+
+    pub fn bytes(bytes: &[u8]) -> Value {
+        Value::Array("y".into(), bytes.iter().cloned().map(Value::Byte).collect())
+    }
+
+    /// `a{sv}` dictionary, like the settings of a connection profile
+    pub fn dict(entries: Vec<(&str, Value)>) -> Value {
+        Value::Array(
+            "{sv}".into(),
+            entries
+                .into_iter()
+                .map(|(key, value)| {
+                    Value::DictEntry(
+                        Box::new(Value::String(key.into())),
+                        Box::new(Value::Variant(Box::new(value))),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// `a{sa{sv}}` dictionary of dictionaries, like a connection profile
+    pub fn settings(settings: Vec<(&str, Value)>) -> Value {
+        Value::Array(
+            "{sa{sv}}".into(),
+            settings
+                .into_iter()
+                .map(|(name, setting)| {
+                    Value::DictEntry(Box::new(Value::String(name.into())), Box::new(setting))
+                })
+                .collect(),
+        )
+    }
+
+    /// The value inside any variants
+    pub fn inner(&self) -> &Value {
+        match *self {
+            Value::Variant(ref value) => value.inner(),
+            ref value => value,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self.inner() {
+            Value::String(ref value)
+            | Value::ObjectPath(ref value)
+            | Value::Signature(ref value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self.inner() {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> Option<u8> {
+        match *self.inner() {
+            Value::Byte(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self.inner() {
+            Value::Int32(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self.inner() {
+            Value::UInt32(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self.inner() {
+            Value::Int64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self.inner() {
+            Value::UInt64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self.inner() {
+            Value::Array(_, ref items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&[Value]> {
+        match *self.inner() {
+            Value::Struct(ref fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// Contents of a byte array, like an SSID
+    pub fn as_bytes(&self) -> Option<Vec<u8>> {
+        self.as_array()?.iter().map(Value::as_u8).collect()
+    }
+
+    /// Object paths of an `ao` array
+    pub fn as_paths(&self) -> Option<Vec<String>> {
+        self.as_array()?
+            .iter()
+            .map(|path| path.as_str().map(String::from))
+            .collect()
+    }
+
+    /// Value of a dictionary entry with a string key, without its variant
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_array()?.iter().find_map(|entry| match *entry {
+            Value::DictEntry(ref k, ref value) if k.as_str() == Some(key) => Some(value.inner()),
+            _ => None,
+        })
+    }
+}
+
+/// Connection to the system bus
+pub struct Connection {
+    stream: UnixStream,
+    serial: Cell<u32>,
+}
+
+impl Connection {
+    /// Connects and authenticates to the system bus, at the address of
+    /// `DBUS_SYSTEM_BUS_ADDRESS` if set
+    pub fn system() -> Result<Self> {
+        let path = system_bus_socket();
+
+        let stream = UnixStream::connect(&path)
+            .chain_err(|| ErrorKind::DBus("Connecting to the system bus".into(), path.clone()))?;
+
+        stream
+            .set_read_timeout(Some(Duration::from_secs(CALL_TIMEOUT)))
+            .chain_err(|| ErrorKind::DBus("Connecting to the system bus".into(), path))?;
+
+        authenticate(&stream)?;
+
+        let connection = Connection {
+            stream,
+            serial: Cell::new(0),
+        };
+
+        connection.call(BUS_NAME, BUS_PATH, BUS_NAME, "Hello", &[])?;
+
+        Ok(connection)
+    }
+
+    /// Calls a method and returns the values of the reply
+    pub fn call(
+        &self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>> {
+        let method = format!("{}.{}", interface, member);
+
+        let serial = self.serial.get().wrapping_add(1).max(1);
+        self.serial.set(serial);
+
+        let message = encode_call(serial, destination, path, interface, member, args);
+
+        (&self.stream)
+            .write_all(&message)
+            .chain_err(|| ErrorKind::DBus(method.clone(), "sending the call failed".into()))?;
+
+        loop {
+            let reply = read_message(&self.stream, &method)?;
+
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+
+            match reply.kind {
+                METHOD_RETURN => return Ok(reply.body),
+                ERROR => {
+                    let name = reply.error_name.unwrap_or_default();
+                    let message = reply
+                        .body
+                        .first()
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string();
+
+                    bail!(ErrorKind::DBus(method, format!("{}: {}", name, message)))
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Reads a property with `org.freedesktop.DBus.Properties.Get`
+    pub fn get_property(
+        &self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        property: &str,
+    ) -> Result<Value> {
+        let reply = self.call(
+            destination,
+            path,
+            PROPERTIES_INTERFACE,
+            "Get",
+            &[
+                Value::String(interface.into()),
+                Value::String(property.into()),
+            ],
+        )?;
+
+        match reply.into_iter().next() {
+            Some(Value::Variant(value)) => Ok(*value),
+            _ => bail!(malformed(&format!("{}.{}", interface, property))),
+        }
+    }
+}
+
+/// Path of the system bus socket. Only `unix:path=` addresses are supported,
+/// others are skipped.
+fn system_bus_socket() -> String {
+    let address = env::var(SYSTEM_BUS_ADDRESS_VARIABLE).unwrap_or_default();
+
+    address
+        .split(';')
+        .filter_map(|address| {
+            let parameters = address.trim().strip_prefix("unix:")?;
+            parameters
+                .split(',')
+                .find_map(|parameter| parameter.strip_prefix("path="))
+                .map(String::from)
+        })
+        .next()
+        .unwrap_or_else(|| DEFAULT_SYSTEM_BUS_SOCKET.to_string())
+}
+
+/// SASL EXTERNAL authentication with the user ID of the process
+fn authenticate(stream: &UnixStream) -> Result<()> {
+    let error =
+        |reason: &str| ErrorKind::DBus("Authenticating to the system bus".into(), reason.into());
+
+    let uid = getuid().to_string();
+    let uid_hex: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+
+    let mut writer = stream;
+    writer
+        .write_all(format!("\0AUTH EXTERNAL {}\r\n", uid_hex).as_bytes())
+        .chain_err(|| error("sending the credentials failed"))?;
+
+    let mut reader = stream;
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !line.ends_with(b"\r\n") {
+        reader
+            .read_exact(&mut byte)
+            .chain_err(|| error("no answer from the bus"))?;
+        line.push(byte[0]);
+
+        if line.len() > 512 {
+            bail!(error("answer too long"));
+        }
+    }
+
+    if !line.starts_with(b"OK ") {
+        bail!(error(String::from_utf8_lossy(&line).trim()));
+    }
+
+    writer
+        .write_all(b"BEGIN\r\n")
+        .chain_err(|| error("starting the session failed"))?;
+
+    Ok(())
+}
+
+fn malformed(method: &str) -> ErrorKind {
+    ErrorKind::DBus(method.into(), "malformed message".into())
+}
+
+fn encode_call(
+    serial: u32,
+    destination: &str,
+    path: &str,
+    interface: &str,
+    member: &str,
+    args: &[Value],
+) -> Vec<u8> {
+    let mut body = Encoder::default();
+    for arg in args {
+        body.write(arg);
+    }
+
+    let field = |code: u8, value: Value| {
+        Value::Struct(vec![Value::Byte(code), Value::Variant(Box::new(value))])
+    };
+
+    let mut fields = vec![
+        field(FIELD_PATH, Value::ObjectPath(path.into())),
+        field(FIELD_INTERFACE, Value::String(interface.into())),
+        field(FIELD_MEMBER, Value::String(member.into())),
+        field(FIELD_DESTINATION, Value::String(destination.into())),
+    ];
+
+    if !args.is_empty() {
+        let signature = args.iter().map(Value::signature).collect();
+        fields.push(field(FIELD_SIGNATURE, Value::Signature(signature)));
+    }
+
+    let mut message = Encoder::default();
+    message
+        .buffer
+        .extend_from_slice(&[b'l', METHOD_CALL, 0, PROTOCOL_VERSION]);
+    message.write(&Value::UInt32(body.buffer.len() as u32));
+    message.write(&Value::UInt32(serial));
+    message.write(&Value::Array("(yv)".into(), fields));
+    message.pad(8);
+    message.buffer.extend_from_slice(&body.buffer);
+
+    message.buffer
+}
+
+struct Message {
+    kind: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<Value>,
+}
+
+fn read_message(stream: &UnixStream, method: &str) -> Result<Message> {
+    let mut reader = stream;
+
+    let mut buffer = vec![0u8; 16];
+    reader
+        .read_exact(&mut buffer)
+        .chain_err(|| ErrorKind::DBus(method.into(), "no reply".into()))?;
+
+    let big_endian = match buffer[0] {
+        b'l' => false,
+        b'B' => true,
+        _ => bail!(malformed(method)),
+    };
+
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    let body_length = read_u32(&buffer[4..8]) as usize;
+    let fields_length = read_u32(&buffer[12..16]) as usize;
+    let header_length = align(16 + fields_length, 8);
+
+    if header_length + body_length > MAX_MESSAGE_SIZE {
+        bail!(malformed(method));
+    }
+
+    buffer.resize(header_length + body_length, 0);
+    reader
+        .read_exact(&mut buffer[16..])
+        .chain_err(|| ErrorKind::DBus(method.into(), "reply cut short".into()))?;
+
+    let mut decoder = Decoder {
+        buffer: &buffer[..header_length],
+        position: 12,
+        big_endian,
+    };
+
+    let fields = decoder.read("a(yv)").ok_or_else(|| malformed(method))?;
+
+    let mut reply_serial = None;
+    let mut error_name = None;
+    let mut signature = String::new();
+
+    for field in fields.as_array().unwrap_or_default() {
+        let (code, value) = match field.as_struct() {
+            Some(&[Value::Byte(code), ref value]) => (code, value),
+            _ => bail!(malformed(method)),
+        };
+
+        match code {
+            FIELD_REPLY_SERIAL => reply_serial = value.as_u32(),
+            FIELD_ERROR_NAME => error_name = value.as_str().map(String::from),
+            FIELD_SIGNATURE => signature = value.as_str().unwrap_or_default().to_string(),
+            _ => {}
+        }
+    }
+
+    let mut decoder = Decoder {
+        buffer: &buffer,
+        position: header_length,
+        big_endian,
+    };
+
+    let mut body = Vec::new();
+    let mut types = signature.as_str();
+
+    while !types.is_empty() {
+        let (single, rest) = split_type(types).ok_or_else(|| malformed(method))?;
+        body.push(decoder.read(single).ok_or_else(|| malformed(method))?);
+        types = rest;
+    }
+
+    Ok(Message {
+        kind: buffer[1],
+        reply_serial,
+        error_name,
+        body,
+    })
+}
+
+fn align(position: usize, alignment: usize) -> usize {
+    position.div_ceil(alignment) * alignment
+}
+
+/// Alignment of the type starting the signature
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'n') | Some(b'q') => 2,
+        Some(b'b') | Some(b'i') | Some(b'u') | Some(b's') | Some(b'o') | Some(b'a')
+        | Some(b'h') => 4,
+        Some(b'x') | Some(b't') | Some(b'd') | Some(b'(') | Some(b'{') => 8,
+        _ => 1,
+    }
+}
+
+/// Splits the first complete type off a signature, which must be ASCII
+fn split_type(signature: &str) -> Option<(&str, &str)> {
+    if !signature.is_ascii() {
+        return None;
+    }
+
+    let length = match *signature.as_bytes().first()? {
+        b'a' => 1 + split_type(&signature[1..])?.0.len(),
+        open @ b'(' | open @ b'{' => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut depth = 0;
+            let mut end = None;
+
+            for (index, &byte) in signature.as_bytes().iter().enumerate() {
+                if byte == open {
+                    depth += 1;
+                } else if byte == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(index + 1);
+                        break;
+                    }
+                }
+            }
+
+            end?
+        }
+        _ => 1,
+    };
+
+    Some(signature.split_at(length))
+}
+
+/// Marshals values little-endian, aligned relative to the start of the
+/// buffer, which starts 8-aligned in the message
+#[derive(Default)]
+struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    fn pad(&mut self, alignment: usize) {
+        let length = align(self.buffer.len(), alignment);
+        self.buffer.resize(length, 0);
+    }
+
+    fn write_string(&mut self, value: &str) {
+        self.pad(4);
+        self.buffer
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    fn write_signature(&mut self, value: &str) {
+        self.buffer.push(value.len() as u8);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+    }
+
+    fn write(&mut self, value: &Value) {
+        match *value {
+            Value::Byte(value) => self.buffer.push(value),
+            Value::Bool(value) => self.write(&Value::UInt32(value as u32)),
+            Value::Int16(value) => {
+                self.pad(2);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::UInt16(value) => {
+                self.pad(2);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::Int32(value) => {
+                self.pad(4);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::UInt32(value) => {
+                self.pad(4);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::Int64(value) => {
+                self.pad(8);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::UInt64(value) => {
+                self.pad(8);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::Double(value) => {
+                self.pad(8);
+                self.buffer
+                    .extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            Value::String(ref value) | Value::ObjectPath(ref value) => self.write_string(value),
+            Value::Signature(ref value) => self.write_signature(value),
+            Value::Array(ref element, ref items) => {
+                self.pad(4);
+                let length_position = self.buffer.len();
+                self.buffer.extend_from_slice(&[0; 4]);
+
+                // The length leaves out the padding up to the first element
+                self.pad(alignment(element));
+                let start = self.buffer.len();
+
+                for item in items {
+                    self.write(item);
+                }
+
+                let length = (self.buffer.len() - start) as u32;
+                self.buffer[length_position..length_position + 4]
+                    .copy_from_slice(&length.to_le_bytes());
+            }
+            Value::Struct(ref fields) => {
+                self.pad(8);
+                for field in fields {
+                    self.write(field);
+                }
+            }
+            Value::DictEntry(ref key, ref value) => {
+                self.pad(8);
+                self.write(key);
+                self.write(value);
+            }
+            Value::Variant(ref value) => {
+                self.write_signature(&value.signature());
+                self.write(value);
+            }
+        }
+    }
+}
+
+/// Unmarshals values of a message, `position` counting from its start
+struct Decoder<'a> {
+    buffer: &'a [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, alignment: usize, length: usize) -> Option<&'a [u8]> {
+        let start = align(self.position, alignment);
+        let end = start.checked_add(length)?;
+        let bytes = self.buffer.get(start..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn read_fixed<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N, N)?);
+
+        if self.big_endian {
+            bytes.reverse();
+        }
+
+        Some(bytes)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        self.read_fixed().map(u32::from_le_bytes)
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let length = self.read_u32()? as usize;
+        let bytes = self.take(1, length + 1)?;
+        String::from_utf8(bytes[..length].to_vec()).ok()
+    }
+
+    /// Signatures are ASCII, anything else is a malformed message
+    fn read_signature(&mut self) -> Option<String> {
+        let length = *self.take(1, 1)?.first()? as usize;
+        let bytes = &self.take(1, length + 1)?[..length];
+
+        if !bytes.is_ascii() {
+            return None;
+        }
+
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    /// Reads a value of a single complete type
+    fn read(&mut self, signature: &str) -> Option<Value> {
+        let value = match *signature.as_bytes().first()? {
+            b'y' => Value::Byte(*self.take(1, 1)?.first()?),
+            b'b' => Value::Bool(self.read_u32()? != 0),
+            b'n' => Value::Int16(i16::from_le_bytes(self.read_fixed()?)),
+            b'q' => Value::UInt16(u16::from_le_bytes(self.read_fixed()?)),
+            b'i' => Value::Int32(i32::from_le_bytes(self.read_fixed()?)),
+            b'u' | b'h' => Value::UInt32(self.read_u32()?),
+            b'x' => Value::Int64(i64::from_le_bytes(self.read_fixed()?)),
+            b't' => Value::UInt64(u64::from_le_bytes(self.read_fixed()?)),
+            b'd' => Value::Double(f64::from_bits(u64::from_le_bytes(self.read_fixed()?))),
+            b's' => Value::String(self.read_string()?),
+            b'o' => Value::ObjectPath(self.read_string()?),
+            b'g' => Value::Signature(self.read_signature()?),
+            b'a' => {
+                let element = split_type(&signature[1..])?.0;
+                let length = self.read_u32()? as usize;
+
+                self.take(alignment(element), 0)?;
+                let end = self.position.checked_add(length)?;
+
+                if end > self.buffer.len() {
+                    return None;
+                }
+
+                let mut items = Vec::new();
+                while self.position < end {
+                    items.push(self.read(element)?);
+                }
+
+                Value::Array(element.to_string(), items)
+            }
+            b'(' => {
+                self.take(8, 0)?;
+
+                let mut types = &signature[1..signature.len() - 1];
+                let mut fields = Vec::new();
+
+                while !types.is_empty() {
+                    let (single, rest) = split_type(types)?;
+                    fields.push(self.read(single)?);
+                    types = rest;
+                }
+
+                Value::Struct(fields)
+            }
+            b'{' => {
+                self.take(8, 0)?;
+
+                let (key, rest) = split_type(&signature[1..signature.len() - 1])?;
+                let key = self.read(key)?;
+                let value = self.read(rest)?;
+
+                Value::DictEntry(Box::new(key), Box::new(value))
+            }
+            b'v' => {
+                let signature = self.read_signature()?;
+                let value = self.read(split_type(&signature)?.0)?;
+
+                Value::Variant(Box::new(value))
+            }
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_call, split_type, Decoder, Encoder, Value};
+
+    #[test]
+    fn splits_signatures() {
+        assert_eq!(split_type("a{sa{sv}}o"), Some(("a{sa{sv}}", "o")));
+        assert_eq!(split_type("(yv)"), Some(("(yv)", "")));
+        assert_eq!(split_type("aayu"), Some(("aay", "u")));
+        assert_eq!(split_type("(ya"), None);
+        assert_eq!(split_type("éu"), None);
+    }
+
+    #[test]
+    fn rejects_non_ascii_signatures() {
+        // A variant whose signature is the two bytes of 'é'
+        let buffer = [2, 0xc3, 0xa9, 0];
+
+        let mut decoder = Decoder {
+            buffer: &buffer,
+            position: 0,
+            big_endian: false,
+        };
+
+        assert_eq!(decoder.read("v"), None);
+    }
+
+    #[test]
+    fn round_trips_connection_settings() {
+        let settings = Value::settings(vec![
+            (
+                "802-11-wireless",
+                Value::dict(vec![
+                    ("ssid", Value::bytes(b"Cafe")),
+                    ("mode", Value::String("ap".into())),
+                    ("channel", Value::UInt32(6)),
+                ]),
+            ),
+            (
+                "ipv4",
+                Value::dict(vec![(
+                    "address-data",
+                    Value::Array(
+                        "a{sv}".into(),
+                        vec![Value::dict(vec![
+                            ("address", Value::String("192.168.42.1".into())),
+                            ("prefix", Value::UInt32(24)),
+                        ])],
+                    ),
+                )]),
+            ),
+            ("ipv6", Value::dict(vec![])),
+        ]);
+
+        let mut encoder = Encoder::default();
+        encoder.write(&Value::Byte(1));
+        encoder.write(&settings);
+
+        let mut decoder = Decoder {
+            buffer: &encoder.buffer,
+            position: 1,
+            big_endian: false,
+        };
+
+        let decoded = decoder.read(&settings.signature()).unwrap();
+
+        assert_eq!(decoded, settings);
+        assert_eq!(decoder.position, encoder.buffer.len());
+
+        let wireless = decoded.get("802-11-wireless").unwrap();
+        assert_eq!(
+            wireless.get("ssid").and_then(Value::as_bytes),
+            Some(b"Cafe".to_vec())
+        );
+        assert_eq!(wireless.get("channel").and_then(Value::as_u32), Some(6));
+    }
+
+    #[test]
+    fn encodes_method_calls() {
+        let message = encode_call(
+            7,
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "GetDeviceByIpIface",
+            &[Value::String("wlan0".into())],
+        );
+
+        // Header, fields padded to 8 and the string argument
+        assert_eq!(&message[..4], &[b'l', 1, 0, 1]);
+        assert_eq!(&message[4..8], &10u32.to_le_bytes());
+        assert_eq!(&message[8..12], &7u32.to_le_bytes());
+        assert_eq!(message.len() % 8, 2);
+        assert_eq!(&message[message.len() - 10..], b"\x05\0\0\0wlan0\0");
+
+        let mut decoder = Decoder {
+            buffer: &message,
+            position: 12,
+            big_endian: false,
+        };
+
+        let fields = decoder.read("a(yv)").unwrap();
+        let signature = fields
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|field| field.as_struct().unwrap()[0] == Value::Byte(8))
+            .map(|field| field.as_struct().unwrap()[1].clone());
+
+        assert_eq!(
+            signature,
+            Some(Value::Variant(Box::new(Value::Signature("s".into()))))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Declarative network configuration for fleets. A desired state file lists
+//! the networks a device should know of, and reconciling it creates, updates
+//! and removes saved networks to match: `--show-drift` prints what would
+//! change, `--apply-state` applies it. Only networks saved by WiFi Connect
+//! are removed, so that profiles provisioned otherwise are left alone.
+//! Passphrases may be encrypted with `--secrets-key`.
+//!
+//! ```json
+//! {"networks": [{"ssid": "Plant", "passphrase": "...", "priority": 10}]}
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde_json;
+
+use backend::Backend;
+use encryption::SecretsPolicy;
+use errors::*;
+use network::NetworkCredentials;
+use profile::{ProfileSettings, ProfileUpdate};
+use secret::Secret;
+
+#[derive(Debug, Deserialize)]
+pub struct DesiredState {
+    pub networks: Vec<DesiredNetwork>,
+}
+
+/// Saved network as it should be. Unset settings are not reconciled.
+#[derive(Debug, Deserialize)]
+pub struct DesiredNetwork {
+    pub ssid: String,
+    #[serde(default)]
+    pub identity: String,
+    /// Only used when the network is created, as saved secrets cannot be
+    /// compared
+    #[serde(default)]
+    pub passphrase: Secret,
+    #[serde(default)]
+    pub priority: Option<i32>,
+    #[serde(default)]
+    pub autoconnect: Option<bool>,
+}
+
+/// Difference between a saved network and the desired state
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    /// Desired, but not saved
+    Missing { ssid: String },
+    /// Saved with settings other than the desired ones, which are listed
+    Changed {
+        ssid: String,
+        priority: Option<i32>,
+        autoconnect: Option<bool>,
+    },
+    /// Saved by WiFi Connect, but no longer desired
+    Unwanted { ssid: String },
+}
+
+pub fn load_desired_state(path: &Path, secrets: &SecretsPolicy) -> Result<DesiredState> {
+    let contents = fs::read_to_string(path)
+        .chain_err(|| ErrorKind::DesiredState(path.display().to_string()))?;
+
+    parse_desired_state(&contents, &path.display().to_string(), secrets)
+}
+
+/// Desired state read from `source`, a file or URL named in errors, with
+/// the passphrases decrypted
+pub fn parse_desired_state(
+    contents: &str,
+    source: &str,
+    secrets: &SecretsPolicy,
+) -> Result<DesiredState> {
+    let mut state: DesiredState =
+        serde_json::from_str(contents).chain_err(|| ErrorKind::DesiredState(source.into()))?;
+
+    let mut ssids = HashSet::new();
+
+    for network in &state.networks {
+        if !ssids.insert(&network.ssid) {
+            bail!(ErrorKind::DesiredState(format!(
+                "{}: '{}' is listed more than once",
+                source, network.ssid
+            )));
+        }
+    }
+
+    for network in &mut state.networks {
+        network.passphrase = secrets.open(
+            &network.passphrase,
+            &format!("{}: '{}'", source, network.ssid),
+        )?;
+    }
+
+    Ok(state)
+}
+
+/// Modification time of the desired state file, to reconcile it again once
+/// it changes
+pub fn desired_state_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// What reconciling `state` would change in the saved networks of `backend`
+pub fn find_drift(backend: &dyn Backend, state: &DesiredState) -> Result<Vec<Drift>> {
+    let mut drift = Vec::new();
+
+    for network in &state.networks {
+        let details = match backend.saved_network_details(&network.ssid)? {
+            Some(details) => details,
+            None => {
+                drift.push(Drift::Missing {
+                    ssid: network.ssid.clone(),
+                });
+                continue;
+            }
+        };
+
+        let priority = network
+            .priority
+            .filter(|priority| *priority != details.autoconnect_priority.unwrap_or(0));
+        let autoconnect = network
+            .autoconnect
+            .filter(|autoconnect| *autoconnect != details.autoconnect);
+
+        if priority.is_some() || autoconnect.is_some() {
+            drift.push(Drift::Changed {
+                ssid: network.ssid.clone(),
+                priority,
+                autoconnect,
+            });
+        }
+    }
+
+    for managed in backend.managed_networks()? {
+        if !state
+            .networks
+            .iter()
+            .any(|network| network.ssid == managed.ssid)
+        {
+            drift.push(Drift::Unwanted { ssid: managed.ssid });
+        }
+    }
+
+    Ok(drift)
+}
+
+/// Reconciles the saved networks of `backend` with `state` and returns the
+/// drift that was corrected
+pub fn apply_desired_state(backend: &mut dyn Backend, state: &DesiredState) -> Result<Vec<Drift>> {
+    let drift = find_drift(&*backend, state)?;
+
+    for item in &drift {
+        match *item {
+            Drift::Missing { ref ssid } => {
+                let network = match state.networks.iter().find(|network| network.ssid == *ssid) {
+                    Some(network) => network,
+                    None => continue,
+                };
+
+                let credentials = NetworkCredentials {
+                    ssid: network.ssid.clone(),
+                    identity: network.identity.clone(),
+                    passphrase: network.passphrase.clone(),
+                    proxy: None,
+                    wireguard: None,
+                };
+
+                let settings = ProfileSettings {
+                    autoconnect_priority: network.priority,
+                    no_autoconnect: network.autoconnect == Some(false),
+                    ..Default::default()
+                };
+
+                backend.save_network(&credentials, &settings)?;
+            }
+            Drift::Changed {
+                ref ssid,
+                priority,
+                autoconnect,
+            } => {
+                let update = ProfileUpdate {
+                    autoconnect_priority: priority,
+                    autoconnect,
+                    ..Default::default()
+                };
+
+                backend.update_network(ssid, &update)?;
+            }
+            Drift::Unwanted { ref ssid } => {
+                backend.forget_network(ssid)?;
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+pub fn print_drift(drift: &[Drift]) {
+    if drift.is_empty() {
+        println!("Saved networks match the desired state");
+        return;
+    }
+
+    for item in drift {
+        match *item {
+            Drift::Missing { ref ssid } => println!("+ {}", ssid),
+            Drift::Changed {
+                ref ssid,
+                priority,
+                autoconnect,
+            } => {
+                let mut changes = Vec::new();
+
+                if let Some(priority) = priority {
+                    changes.push(format!("priority {}", priority));
+                }
+
+                if let Some(autoconnect) = autoconnect {
+                    changes.push(format!("autoconnect {}", autoconnect));
+                }
+
+                println!("~ {} ({})", ssid, changes.join(", "));
+            }
+            Drift::Unwanted { ref ssid } => println!("- {}", ssid),
+        }
+    }
+}
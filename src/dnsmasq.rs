@@ -1,11 +1,126 @@
-use std::process::{Child, Command};
-
-use network_manager::Device;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
+use capport::capport_dhcp_options;
 use config::Config;
+use dualband::{dual_band_dnsmasq_args, is_dual_band_portal_running};
 use errors::*;
+use ports::{check_port_conflicts, DNS_PORT};
+use profile::is_mac_address;
+
+/// Time dnsmasq gets to fail on startup, e.g. when port 53 is taken
+const STARTUP_GRACE_PERIOD: u64 = 500;
+
+/// Restarts attempted by `--dnsmasq-restart` before giving up
+const MAX_RESTARTS: u32 = 5;
+
+/// nftables table holding the `--dns-port` redirect
+const NFT_TABLE: &str = "wifi_connect";
+
+/// Kept next to the state of WiFi Connect instead of the system dnsmasq's
+/// defaults, so that the sandbox does not have to allow those directories
+pub const DNSMASQ_LEASE_FILE: &str = "/var/lib/wifi-connect/dnsmasq.leases";
+pub const DNSMASQ_PID_FILE: &str = "/run/wifi-connect/dnsmasq.pid";
+
+/// Running dnsmasq process, watched by a thread that reports it exiting
+/// unexpectedly and optionally restarts it
+pub struct Dnsmasq {
+    child: Arc<Mutex<Child>>,
+    stopped: Arc<AtomicBool>,
+    redirect: Option<DnsRedirect>,
+}
+
+impl Drop for Dnsmasq {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+impl Dnsmasq {
+    pub fn stop(&mut self) -> Result<()> {
+        // Dropping the redirect removes it
+        self.redirect.take();
+
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let mut child = self.child.lock().unwrap();
+
+        // Already exited, e.g. after a failure reported by the watchdog
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+
+        child.kill()?;
+
+        child.wait()?;
+
+        Ok(())
+    }
+}
+
+/// Starts dnsmasq with its log forwarded to ours. `on_failure` is called from
+/// the watchdog thread once dnsmasq exits and is not restarted.
+pub fn start_dnsmasq<F>(config: &Config, interface: &str, on_failure: F) -> Result<Dnsmasq>
+where
+    F: FnOnce(Error) + Send + 'static,
+{
+    check_port_conflicts(config)?;
+
+    let args = build_args(config, interface);
+
+    let mut child = spawn_dnsmasq(&args)?;
+
+    thread::sleep(Duration::from_millis(STARTUP_GRACE_PERIOD));
+
+    if let Some(status) = child.try_wait()? {
+        bail!(ErrorKind::DnsmasqExited(status.to_string()));
+    }
 
-pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
+    let redirect = if config.dns_port != DNS_PORT {
+        match DnsRedirect::add(interface, config.dns_port) {
+            Ok(redirect) => Some(redirect),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+
+    let dnsmasq = Dnsmasq {
+        child: Arc::new(Mutex::new(child)),
+        stopped: Arc::new(AtomicBool::new(false)),
+        redirect,
+    };
+
+    spawn_watchdog(
+        dnsmasq.child.clone(),
+        dnsmasq.stopped.clone(),
+        args,
+        config.dnsmasq_restart,
+        on_failure,
+    );
+
+    Ok(dnsmasq)
+}
+
+pub fn stop_dnsmasq(dnsmasq: &mut Dnsmasq) -> Result<()> {
+    dnsmasq.stop()
+}
+
+fn build_args(config: &Config, interface: &str) -> Vec<String> {
     // Dynamically build dnsmasq arguments so that we can optionally omit the
     // router (gateway) and DNS advertisement when requested by the user
     let mut args: Vec<String> = Vec::new();
@@ -14,7 +129,16 @@ pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
         args.push(format!("--address=/#/{}", config.gateway));
     }
 
-    args.push(format!("--dhcp-range={}", config.dhcp_range));
+    match config.dhcp_lease_time {
+        Some(ref lease_time) => {
+            args.push(format!("--dhcp-range={},{}", config.dhcp_range, lease_time))
+        }
+        None => args.push(format!("--dhcp-range={}", config.dhcp_range)),
+    }
+
+    for host in &config.dhcp_hosts {
+        args.push(format!("--dhcp-host={}", host));
+    }
 
     if !config.no_dhcp_gateway {
         args.push(format!("--dhcp-option=option:router,{}", config.gateway));
@@ -23,25 +147,237 @@ pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
         args.push("--dhcp-option=option:router".to_string());
     }
 
-    args.push(format!("--interface={}", device.interface()));
+    args.extend(capport_dhcp_options(config));
+
+    args.push(format!("--interface={}", interface));
+
+    // Only bind the portal address, so that DNS servers listening elsewhere,
+    // like the systemd-resolved stub on 127.0.0.53, do not conflict
+    args.push(format!("--listen-address={}", config.gateway));
+
+    if is_dual_band_portal_running(config) {
+        args.extend(dual_band_dnsmasq_args(config));
+    }
+
+    if config.dns_port != DNS_PORT {
+        args.push(format!("--port={}", config.dns_port));
+    }
+
+    args.push(format!("--dhcp-leasefile={}", DNSMASQ_LEASE_FILE));
+    args.push(format!("--pid-file={}", DNSMASQ_PID_FILE));
 
     // Static arguments that are always required
     args.push("--keep-in-foreground".to_string());
     args.push("--bind-interfaces".to_string());
     args.push("--except-interface=lo".to_string());
-    args.push("--conf-file".to_string());
     args.push("--no-hosts".to_string());
 
-    Command::new("dnsmasq")
-        .args(&args)
-        .spawn()
-        .chain_err(|| ErrorKind::Dnsmasq)
+    // Log to stderr, which is forwarded to our log
+    args.push("--log-facility=-".to_string());
+
+    if config.debug_portal_traffic {
+        args.push("--log-queries".to_string());
+        args.push("--log-dhcp".to_string());
+    }
+
+    // Without a path, dnsmasq skips its default /etc/dnsmasq.conf
+    match config.dnsmasq_conf {
+        Some(ref path) => args.push(format!("--conf-file={}", path.display())),
+        None => args.push("--conf-file".to_string()),
+    }
+
+    // Passed last, so that they are able to override the options above
+    for option in &config.dnsmasq_options {
+        if option.starts_with("--") {
+            args.push(option.clone());
+        } else {
+            args.push(format!("--{}", option));
+        }
+    }
+
+    args
 }
 
-pub fn stop_dnsmasq(dnsmasq: &mut Child) -> Result<()> {
-    dnsmasq.kill()?;
+/// Accepts lease times the way dnsmasq does: seconds, optionally with an m,
+/// h, d or w suffix, or `infinite`
+pub fn validate_lease_time(value: String) -> ::std::result::Result<(), String> {
+    let digits = value.trim_end_matches(|c| "mhdw".contains(c));
+
+    if value == "infinite"
+        || (!digits.is_empty()
+            && value.len() - digits.len() <= 1
+            && digits.chars().all(|c| c.is_ascii_digit()))
+    {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a DHCP lease time", value))
+    }
+}
+
+/// Accepts static reservations in the form `mac,ip[,name]`
+pub fn validate_dhcp_host(value: String) -> ::std::result::Result<(), String> {
+    let fields: Vec<&str> = value.split(',').map(|field| field.trim()).collect();
+
+    let valid = (fields.len() == 2 || fields.len() == 3)
+        && is_mac_address(fields[0])
+        && Ipv4Addr::from_str(fields[1]).is_ok()
+        && fields.get(2).map_or(true, |name| !name.is_empty());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a DHCP reservation of the form mac,ip[,name]", value))
+    }
+}
+
+/// Redirects DNS queries of portal clients to dnsmasq running on
+/// `--dns-port`, through a dedicated nftables table
+struct DnsRedirect;
+
+impl DnsRedirect {
+    fn add(interface: &str, port: u16) -> Result<Self> {
+        info!("Redirecting DNS on {} to port {}", interface, port);
+
+        // Start from a clean table, e.g. after a crash
+        let _ = nft(&["delete", "table", "ip", NFT_TABLE]);
+
+        // Removes a partially set up table when returning early
+        let redirect = DnsRedirect;
 
-    dnsmasq.wait()?;
+        nft(&["add", "table", "ip", NFT_TABLE])?;
+        nft(&[
+            "add", "chain", "ip", NFT_TABLE, "prerouting",
+            "{ type nat hook prerouting priority -100 ; }",
+        ])?;
+
+        let target = format!(":{}", port);
+
+        for protocol in &["udp", "tcp"] {
+            nft(&[
+                "add", "rule", "ip", NFT_TABLE, "prerouting", "iifname", interface, protocol,
+                "dport", "53", "redirect", "to", &target,
+            ])?;
+        }
+
+        Ok(redirect)
+    }
+}
+
+impl Drop for DnsRedirect {
+    fn drop(&mut self) {
+        if let Err(e) = nft(&["delete", "table", "ip", NFT_TABLE]) {
+            warn!("{}", e);
+        }
+    }
+}
+
+fn nft(args: &[&str]) -> Result<()> {
+    let output = Command::new("nft")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::DnsRedirect("running nft failed".into()))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::DnsRedirect(
+            String::from_utf8_lossy(&output.stderr).trim().to_string()
+        ));
+    }
 
     Ok(())
 }
+
+fn spawn_dnsmasq(args: &[String]) -> Result<Child> {
+    for file in &[DNSMASQ_LEASE_FILE, DNSMASQ_PID_FILE] {
+        if let Some(directory) = Path::new(file).parent() {
+            fs::create_dir_all(directory).chain_err(|| ErrorKind::Dnsmasq)?;
+        }
+    }
+
+    let mut child = Command::new("dnsmasq")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::Dnsmasq)?;
+
+    if let Some(stdout) = child.stdout.take() {
+        forward_output(stdout);
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        forward_output(stderr);
+    }
+
+    Ok(child)
+}
+
+fn forward_output<R>(output: R)
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => info!("dnsmasq: {}", line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+fn spawn_watchdog<F>(
+    child: Arc<Mutex<Child>>,
+    stopped: Arc<AtomicBool>,
+    args: Vec<String>,
+    restart: bool,
+    on_failure: F,
+) where
+    F: FnOnce(Error) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut restarts = 0;
+
+        loop {
+            thread::sleep(Duration::from_secs(1));
+
+            let mut child = child.lock().unwrap();
+
+            // Checked while holding the lock, as `stop` raises the flag
+            // before killing the process
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let status = match child.try_wait() {
+                Ok(Some(status)) => status,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Checking dnsmasq failed, watchdog stopped: {}", e);
+                    return;
+                }
+            };
+
+            error!("dnsmasq exited unexpectedly: {}", status);
+
+            if restart && restarts < MAX_RESTARTS {
+                restarts += 1;
+
+                info!("Restarting dnsmasq ({}/{})...", restarts, MAX_RESTARTS);
+
+                match spawn_dnsmasq(&args) {
+                    Ok(restarted) => {
+                        *child = restarted;
+                        continue;
+                    }
+                    Err(e) => error!("Restarting dnsmasq failed: {}", e),
+                }
+            }
+
+            stopped.store(true, Ordering::SeqCst);
+
+            on_failure(ErrorKind::DnsmasqExited(status.to_string()).into());
+
+            return;
+        }
+    });
+}
@@ -0,0 +1,535 @@
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::net::{IpAddr, TcpListener, ToSocketAddrs, UdpSocket};
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "network-manager")]
+use network_manager::NetworkManager;
+
+#[cfg(feature = "network-manager")]
+use backend::DEFAULT_BACKEND;
+use backend::{init_backend, MOCK_BACKEND};
+use config::Config;
+#[cfg(feature = "network-manager")]
+use nm::find_device;
+use wiphy::{interface_modes, phy_name};
+
+/// Name looked up by the DNS checks
+const DNS_TEST_NAME: &str = "example.com";
+
+/// Public address the traceroute heads for, outside of any ISP network
+const TRACEROUTE_TARGET: &str = "1.1.1.1";
+
+/// Hops the traceroute probes before giving up
+const TRACEROUTE_MAX_HOPS: &str = "15";
+
+/// Seconds a DNS query or traceroute probe may take
+const NETWORK_CHECK_TIMEOUT: u64 = 3;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Outcome of a single self-diagnostic check
+#[derive(Serialize)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: String) -> Self {
+        Check {
+            name: name.to_string(),
+            passed: true,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn fail(name: &str, detail: String, hint: &str) -> Self {
+        Check {
+            name: name.to_string(),
+            passed: false,
+            detail,
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Checks whether the system can run the captive portal. Port checks are
+/// left out while the portal itself is running and holding them.
+pub fn run_checks(config: &Config, check_ports: bool) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let interface = match config.backend.as_str() {
+        #[cfg(feature = "network-manager")]
+        DEFAULT_BACKEND => {
+            let running = check_network_manager();
+            let nm_running = running.passed;
+            checks.push(running);
+
+            if nm_running {
+                let (check, interface) = check_nm_device(config);
+                checks.push(check);
+                interface
+            } else {
+                None
+            }
+        }
+        _ => {
+            let (check, interface) = check_backend_device(config);
+            checks.push(check);
+            interface
+        }
+    };
+
+    if let Some(ref interface) = interface {
+        checks.push(check_ap_mode(interface));
+    }
+
+    checks.push(check_rfkill());
+
+    if config.backend != MOCK_BACKEND {
+        checks.push(check_dnsmasq());
+    }
+
+    if check_ports {
+        checks.push(check_port("DNS port", Protocol::Udp, config.dns_port));
+        checks.push(check_port("DHCP port", Protocol::Udp, 67));
+        checks.push(check_port("HTTP port", Protocol::Tcp, config.listening_port));
+    }
+
+    checks
+}
+
+/// Checks the upstream network of the connected WiFi: DNS resolution with
+/// every configured resolver and the route towards the Internet. Failures
+/// here while the WiFi link is up point at the ISP rather than the WiFi.
+pub fn run_network_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let resolvers = read_resolvers();
+
+    if resolvers.is_empty() {
+        checks.push(Check::fail(
+            "DNS resolvers",
+            format!("no nameserver in {}", RESOLV_CONF),
+            "Check that DHCP on the connected network hands out DNS servers",
+        ));
+    } else {
+        for resolver in &resolvers {
+            checks.push(check_resolver(resolver));
+        }
+    }
+
+    checks.push(check_system_resolution());
+    checks.push(check_traceroute());
+
+    checks
+}
+
+pub fn print_checks(checks: &[Check]) {
+    for check in checks {
+        let status = if check.passed { "PASS" } else { "FAIL" };
+        println!("[{}] {}: {}", status, check.name, check.detail);
+
+        if let Some(ref hint) = check.hint {
+            println!("       -> {}", hint);
+        }
+    }
+}
+
+#[cfg(feature = "network-manager")]
+fn check_network_manager() -> Check {
+    let name = "NetworkManager";
+
+    match NetworkManager::new().get_state() {
+        Ok(state) => Check::pass(name, format!("running ({:?})", state)),
+        Err(e) => Check::fail(
+            name,
+            format!("not reachable over D-Bus: {}", e),
+            "Start it with `systemctl start NetworkManager`",
+        ),
+    }
+}
+
+#[cfg(feature = "network-manager")]
+fn check_nm_device(config: &Config) -> (Check, Option<String>) {
+    let name = "WiFi device";
+
+    match find_device(&NetworkManager::new(), &config.interface) {
+        Ok(device) => {
+            let interface = device.interface().to_string();
+            let detail = format!("{} is managed by NetworkManager", interface);
+            (Check::pass(name, detail), Some(interface))
+        }
+        Err(e) => (
+            Check::fail(
+                name,
+                e.to_string(),
+                "Check that the WiFi adapter is plugged in and not listed in NetworkManager's unmanaged-devices",
+            ),
+            None,
+        ),
+    }
+}
+
+fn check_backend_device(config: &Config) -> (Check, Option<String>) {
+    let name = "WiFi device";
+
+    match init_backend(config) {
+        Ok(backend) => {
+            let interface = backend.interface().to_string();
+            let detail = format!("{} ({} backend)", interface, backend.name());
+            (Check::pass(name, detail), Some(interface))
+        }
+        Err(e) => (
+            Check::fail(name, e.to_string(), "Check that the WiFi adapter is plugged in"),
+            None,
+        ),
+    }
+}
+
+fn check_ap_mode(interface: &str) -> Check {
+    let name = "Access point mode";
+
+    let phy = match phy_name(interface) {
+        Some(phy) => phy,
+        None => return Check::pass(name, format!("not checked, {} has no wiphy", interface)),
+    };
+
+    match interface_modes(interface) {
+        Some(ref modes) if modes.iter().any(|mode| mode == "AP") => {
+            Check::pass(name, format!("supported by {}", phy))
+        }
+        Some(ref modes) if modes.iter().any(|mode| mode == "P2P-GO") => Check::fail(
+            name,
+            format!("{} does not support AP mode, only WiFi Direct", phy),
+            "Pass --wifi-direct-fallback or use a WiFi adapter that supports access point mode",
+        ),
+        Some(_) => Check::fail(
+            name,
+            format!("{} does not list AP among its supported interface modes", phy),
+            "Use a WiFi adapter whose driver supports access point mode",
+        ),
+        None => Check::fail(
+            name,
+            format!("querying {} with iw failed", phy),
+            "Install the `iw` package",
+        ),
+    }
+}
+
+fn check_rfkill() -> Check {
+    let name = "rfkill";
+
+    let entries = match fs::read_dir("/sys/class/rfkill") {
+        Ok(entries) => entries,
+        Err(_) => return Check::pass(name, "no rfkill switches".into()),
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let read = |attribute: &str| {
+            fs::read_to_string(path.join(attribute))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        if read("type") != "wlan" {
+            continue;
+        }
+
+        if read("hard") == "1" {
+            return Check::fail(
+                name,
+                format!("{} is hard blocked", read("name")),
+                "Turn on the hardware WiFi switch",
+            );
+        }
+
+        if read("soft") == "1" {
+            return Check::fail(
+                name,
+                format!("{} is soft blocked", read("name")),
+                "Run `rfkill unblock wifi`",
+            );
+        }
+    }
+
+    Check::pass(name, "WiFi is not blocked".into())
+}
+
+fn check_dnsmasq() -> Check {
+    let name = "dnsmasq";
+
+    let result = Command::new("dnsmasq")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    match result {
+        Ok(ref status) if status.success() => Check::pass(name, "installed".into()),
+        _ => Check::fail(
+            name,
+            "binary not found".into(),
+            "Install the `dnsmasq` package (the service itself can stay disabled)",
+        ),
+    }
+}
+
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+fn check_port(name: &str, protocol: Protocol, port: u16) -> Check {
+    let address = ("0.0.0.0", port);
+
+    let result = match protocol {
+        Protocol::Tcp => TcpListener::bind(address).map(drop),
+        Protocol::Udp => UdpSocket::bind(address).map(drop),
+    };
+
+    match result {
+        Ok(()) => Check::pass(name, format!("{} is free", port)),
+        Err(ref e) if e.kind() == IoErrorKind::AddrInUse => Check::fail(
+            name,
+            format!("{} is already in use", port),
+            "Stop the service listening on it, e.g. `systemctl stop dnsmasq systemd-resolved`",
+        ),
+        Err(ref e) if e.kind() == IoErrorKind::PermissionDenied => {
+            Check::pass(name, format!("{} not checked, binding requires root", port))
+        }
+        Err(e) => Check::fail(name, format!("{}: {}", port, e), "Check the network configuration"),
+    }
+}
+
+/// Nameservers listed in `/etc/resolv.conf`
+fn read_resolvers() -> Vec<IpAddr> {
+    let contents = fs::read_to_string(RESOLV_CONF).unwrap_or_default();
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+
+            match (tokens.next(), tokens.next()) {
+                (Some("nameserver"), Some(address)) => address.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Sends an `A` query for the test name straight to `resolver`, bypassing
+/// the system resolver and its caches
+fn check_resolver(resolver: &IpAddr) -> Check {
+    let name = format!("DNS via {}", resolver);
+
+    match query_resolver(resolver) {
+        Ok(elapsed) => Check::pass(
+            &name,
+            format!("{} resolved in {} ms", DNS_TEST_NAME, elapsed),
+        ),
+        Err(reason) => Check::fail(
+            &name,
+            reason,
+            "The resolver handed out by the network does not answer, contact the network or Internet provider",
+        ),
+    }
+}
+
+/// Round trip time in milliseconds of a query answered with records
+fn query_resolver(resolver: &IpAddr) -> ::std::result::Result<u64, String> {
+    let local = if resolver.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+    let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(Duration::from_secs(NETWORK_CHECK_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    // The query ID only has to tell our answer apart from stray packets
+    let id = process::id() as u16;
+    let query = build_dns_query(id, DNS_TEST_NAME);
+
+    let start = Instant::now();
+
+    socket
+        .send_to(&query, (*resolver, 53))
+        .map_err(|e| e.to_string())?;
+
+    let mut response = [0; 512];
+
+    loop {
+        let (len, _) = socket.recv_from(&mut response).map_err(|e| match e.kind() {
+            IoErrorKind::WouldBlock | IoErrorKind::TimedOut => {
+                format!("no answer within {} seconds", NETWORK_CHECK_TIMEOUT)
+            }
+            _ => e.to_string(),
+        })?;
+
+        if len < 12 || response[0..2] != [(id >> 8) as u8, id as u8] {
+            continue;
+        }
+
+        let elapsed = start.elapsed();
+        let elapsed = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+        let rcode = response[3] & 0x0f;
+        let answers = (u16::from(response[6]) << 8) | u16::from(response[7]);
+
+        return match (rcode, answers) {
+            (0, 0) => Err(format!("no records for {}", DNS_TEST_NAME)),
+            (0, _) => Ok(elapsed),
+            (rcode, _) => Err(format!("answered with error code {}", rcode)),
+        };
+    }
+}
+
+/// Recursive `A` query for `name` in DNS wire format
+fn build_dns_query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = vec![
+        (id >> 8) as u8,
+        id as u8,
+        0x01, // Recursion desired
+        0x00,
+        0x00,
+        0x01, // One question
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+    ];
+
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    // Root label, then type A and class IN
+    query.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+
+    query
+}
+
+fn check_system_resolution() -> Check {
+    let name = "DNS resolution";
+
+    match (DNS_TEST_NAME, 80).to_socket_addrs() {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => Check::pass(
+                name,
+                format!("{} resolves to {}", DNS_TEST_NAME, address.ip()),
+            ),
+            None => Check::fail(
+                name,
+                format!("no addresses for {}", DNS_TEST_NAME),
+                "Check the resolvers in /etc/resolv.conf",
+            ),
+        },
+        Err(e) => Check::fail(
+            name,
+            format!("resolving {} failed: {}", DNS_TEST_NAME, e),
+            "Check the resolvers in /etc/resolv.conf",
+        ),
+    }
+}
+
+/// Traces the route towards a public address with `traceroute`. The hops
+/// reached tell whether traffic stops at the access point, inside the ISP
+/// network or beyond it.
+fn check_traceroute() -> Check {
+    let name = "Traceroute";
+
+    let output = Command::new("traceroute")
+        .args(&[
+            "-n",
+            "-q",
+            "1",
+            "-w",
+            &NETWORK_CHECK_TIMEOUT.to_string(),
+            "-m",
+            TRACEROUTE_MAX_HOPS,
+            TRACEROUTE_TARGET,
+        ])
+        .stderr(Stdio::null())
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(_) => {
+            return Check::fail(
+                name,
+                "binary not found".into(),
+                "Install the `traceroute` package",
+            )
+        }
+    };
+
+    let hops = parse_traceroute(&String::from_utf8_lossy(&output.stdout));
+
+    let route = hops
+        .iter()
+        .map(|&(hop, ref address)| match *address {
+            Some(ref address) => format!("{} {}", hop, address),
+            None => format!("{} *", hop),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let reached = hops.iter().any(|&(_, ref address)| {
+        address.as_ref().map(String::as_str) == Some(TRACEROUTE_TARGET)
+    });
+
+    if reached {
+        return Check::pass(name, format!("{} reached: {}", TRACEROUTE_TARGET, route));
+    }
+
+    let last = hops
+        .iter()
+        .filter_map(|&(hop, ref address)| address.as_ref().map(|address| (hop, address)))
+        .last();
+
+    match last {
+        Some((1, address)) => Check::fail(
+            name,
+            format!("stops at the gateway {}: {}", address, route),
+            "The access point has no working uplink, check its Internet connection",
+        ),
+        Some((hop, address)) => Check::fail(
+            name,
+            format!("stops at hop {} ({}): {}", hop, address, route),
+            "Traffic leaves the local network but stops upstream, contact the Internet provider",
+        ),
+        None => Check::fail(
+            name,
+            format!("no hop answered towards {}", TRACEROUTE_TARGET),
+            "Check that the WiFi network is connected and has a gateway",
+        ),
+    }
+}
+
+/// Hop numbers with the address that answered them, `None` for `*`
+fn parse_traceroute(output: &str) -> Vec<(u32, Option<String>)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let hop = tokens.next()?.parse::<u32>().ok()?;
+
+            let address = match tokens.next() {
+                Some("*") | None => None,
+                Some(address) => Some(address.to_string()),
+            };
+
+            Some((hop, address))
+        })
+        .collect()
+}
@@ -0,0 +1,198 @@
+//! Dual-band portal with `--portal-band both`. Next to the 2.4 GHz access
+//! point on the portal interface, a 5 GHz one with the same SSID runs on a
+//! virtual AP interface, so that phones connect at decent speed while old
+//! setup clients still see the 2.4 GHz network. It gets the /24 following
+//! the portal subnet, served by the same dnsmasq, and its clients reach the
+//! portal gateway through the host.
+
+use std::net::Ipv4Addr;
+
+use channels::PortalBand;
+use config::Config;
+use errors::*;
+use profile::{managed_connection_id, nmcli, set_profile_secrets};
+use wiphy::{add_ap_interface, delete_interface, interface_exists, supports_5ghz};
+
+/// Virtual AP interface of the 5 GHz access point
+pub const DUAL_BAND_INTERFACE: &str = "ap5g0";
+
+/// Channel of the 5 GHz access point, without DFS so that it starts right
+/// away
+const DUAL_BAND_CHANNEL: &str = "36";
+
+/// dnsmasq tag of the DHCP clients on the 5 GHz subnet
+const DUAL_BAND_TAG: &str = "portal5g";
+
+pub fn start_dual_band_portal(parent: &str, config: &Config) -> Result<()> {
+    if supports_5ghz(parent) == Some(false) {
+        warn!("{} does not support 5 GHz", parent);
+        bail!(ErrorKind::StartDualBandPortal);
+    }
+
+    info!("Starting 5 GHz access point...");
+
+    if !interface_exists(DUAL_BAND_INTERFACE) {
+        add_ap_interface(parent, DUAL_BAND_INTERFACE)
+            .chain_err(|| ErrorKind::StartDualBandPortal)?;
+    }
+
+    let id = connection_id();
+    let address = format!("{}/24", dual_band_gateway(config.gateway));
+
+    // A profile left behind by an earlier run may hold stale settings
+    nmcli(&["connection", "delete", &id]);
+
+    let mut args = vec![
+        "connection",
+        "add",
+        "type",
+        "wifi",
+        "ifname",
+        DUAL_BAND_INTERFACE,
+        "con-name",
+        id.as_str(),
+        "autoconnect",
+        "no",
+        "ssid",
+        config.ssid.as_str(),
+        "802-11-wireless.mode",
+        "ap",
+        "802-11-wireless.band",
+        "a",
+        "802-11-wireless.channel",
+        DUAL_BAND_CHANNEL,
+        "ipv4.method",
+        "manual",
+        "ipv4.addresses",
+        address.as_str(),
+        "ipv6.method",
+        "ignore",
+    ];
+
+    if config.client_isolation {
+        args.extend(&["802-11-wireless.ap-isolation", "yes"]);
+    }
+
+    // Enhanced Open as on the portal access point
+    let owe = config.portal_owe && config.passphrase.is_none();
+
+    // The passphrase is stored separately to keep it off the command line
+    if config.passphrase.is_some() {
+        args.extend(&["wifi-sec.key-mgmt", "wpa-psk"]);
+    } else if owe {
+        args.extend(&["wifi-sec.key-mgmt", "owe"]);
+    }
+
+    if !nmcli(&args) {
+        bail!(ErrorKind::StartDualBandPortal);
+    }
+
+    if let Some(ref passphrase) = config.passphrase {
+        let psk = ("802-11-wireless-security.psk", passphrase.expose());
+
+        if !set_profile_secrets(&["id", &id], &[psk]) {
+            bail!(ErrorKind::StartDualBandPortal);
+        }
+    }
+
+    if !nmcli(&["connection", "up", &id]) {
+        if !owe {
+            bail!(ErrorKind::StartDualBandPortal);
+        }
+
+        warn!(
+            "Enhanced Open (OWE) is not supported on {}, falling back to open",
+            DUAL_BAND_INTERFACE
+        );
+
+        let remove_security = [
+            "connection",
+            "modify",
+            id.as_str(),
+            "remove",
+            "802-11-wireless-security",
+        ];
+
+        if !nmcli(&remove_security) || !nmcli(&["connection", "up", &id]) {
+            bail!(ErrorKind::StartDualBandPortal);
+        }
+    }
+
+    info!("5 GHz access point '{}' created", config.ssid);
+
+    Ok(())
+}
+
+/// Removes the 5 GHz access point and its interface, also when left behind
+/// by an earlier run
+pub fn stop_dual_band_portal() {
+    let id = connection_id();
+
+    nmcli(&["connection", "down", &id]);
+    nmcli(&["connection", "delete", &id]);
+
+    if interface_exists(DUAL_BAND_INTERFACE) {
+        if let Err(e) = delete_interface(DUAL_BAND_INTERFACE) {
+            warn!("{}", e);
+        }
+    }
+}
+
+pub fn is_dual_band_portal_running(config: &Config) -> bool {
+    config.portal_band == Some(PortalBand::Both) && interface_exists(DUAL_BAND_INTERFACE)
+}
+
+/// dnsmasq arguments serving DHCP and DNS on the 5 GHz subnet. Its clients
+/// get their own gateway as router, the tagged option taking precedence.
+pub fn dual_band_dnsmasq_args(config: &Config) -> Vec<String> {
+    let gateway = dual_band_gateway(config.gateway);
+    let range = shift_range(&config.dhcp_range, config.gateway, gateway);
+
+    let mut args = vec![
+        format!("--interface={}", DUAL_BAND_INTERFACE),
+        format!("--listen-address={}", gateway),
+    ];
+
+    match config.dhcp_lease_time {
+        Some(ref lease_time) => args.push(format!(
+            "--dhcp-range=set:{},{},{}",
+            DUAL_BAND_TAG, range, lease_time
+        )),
+        None => args.push(format!("--dhcp-range=set:{},{}", DUAL_BAND_TAG, range)),
+    }
+
+    if !config.no_dhcp_gateway {
+        args.push(format!(
+            "--dhcp-option=tag:{},option:router,{}",
+            DUAL_BAND_TAG, gateway
+        ));
+    }
+
+    args
+}
+
+/// Gateway of the 5 GHz subnet, in the /24 following the portal one
+fn dual_band_gateway(gateway: Ipv4Addr) -> Ipv4Addr {
+    let octets = gateway.octets();
+
+    Ipv4Addr::new(octets[0], octets[1], octets[2].wrapping_add(1), octets[3])
+}
+
+/// Moves the addresses of a DHCP range in the portal /24 to the 5 GHz one
+fn shift_range(range: &str, from: Ipv4Addr, to: Ipv4Addr) -> String {
+    range
+        .split(',')
+        .map(|part| match part.trim().parse::<Ipv4Addr>() {
+            Ok(address) if address.octets()[..3] == from.octets()[..3] => {
+                let octets = to.octets();
+                Ipv4Addr::new(octets[0], octets[1], octets[2], address.octets()[3]).to_string()
+            }
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn connection_id() -> String {
+    managed_connection_id("portal-5ghz")
+}
@@ -0,0 +1,184 @@
+//! Passphrases encrypted at rest. Secrets WiFi Connect keeps on disk, the
+//! generated hotspot passphrase and the passphrases of desired state files,
+//! may be encrypted with `--secrets-key`: either `tpm`, sealing them to the
+//! TPM of the device with `systemd-creds`, or a keyfile used with
+//! `openssl enc`. With `--encrypt-secrets` plaintext passphrases are neither
+//! written nor accepted.
+//!
+//! Encrypted values are base64 prefixed with `enc:`. `--encrypt-passphrase`
+//! prints the value for a passphrase read from stdin; with a keyfile it can
+//! be produced off the device as well:
+//!
+//! ```text
+//! openssl enc -aes-256-cbc -pbkdf2 -iter 100000 -salt -a -A -pass file:<keyfile>
+//! ```
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use errors::*;
+use secret::Secret;
+
+const ENCRYPTED_PREFIX: &str = "enc:";
+
+/// `--secrets-key` value sealing secrets to the TPM
+pub const TPM_KEY: &str = "tpm";
+
+/// Name the TPM sealed credentials are bound to
+const CREDENTIAL_NAME: &str = "wifi-connect";
+
+const OPENSSL_ARGS: &[&str] = &[
+    "enc",
+    "-aes-256-cbc",
+    "-pbkdf2",
+    "-iter",
+    "100000",
+    "-salt",
+    "-a",
+    "-A",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecretsKey {
+    Tpm,
+    Keyfile(PathBuf),
+}
+
+impl SecretsKey {
+    pub fn parse(value: &str) -> Self {
+        if value == TPM_KEY {
+            SecretsKey::Tpm
+        } else {
+            SecretsKey::Keyfile(PathBuf::from(value))
+        }
+    }
+}
+
+/// How secrets are stored on disk, from `--secrets-key` and
+/// `--encrypt-secrets`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretsPolicy {
+    pub key: Option<SecretsKey>,
+    /// Rejects plaintext passphrases
+    pub required: bool,
+}
+
+impl SecretsPolicy {
+    /// Passphrase read from `source`, decrypted if it is encrypted
+    pub fn open(&self, value: &Secret, source: &str) -> Result<Secret> {
+        if value.expose().starts_with(ENCRYPTED_PREFIX) {
+            let key = match self.key {
+                Some(ref key) => key,
+                None => bail!(ErrorKind::SecretEncryption(source.into())),
+            };
+
+            return decrypt(key, &value.expose()[ENCRYPTED_PREFIX.len()..], source);
+        }
+
+        if self.required && !value.is_empty() {
+            bail!(ErrorKind::PlaintextSecret(source.into()));
+        }
+
+        Ok(value.clone())
+    }
+
+    /// Passphrase about to be written to `destination`, encrypted with
+    /// `--encrypt-secrets`
+    pub fn seal(&self, value: &Secret, destination: &str) -> Result<Secret> {
+        match self.key {
+            Some(ref key) if self.required => encrypt_passphrase(key, value, destination),
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+/// Encrypted value of `passphrase` as accepted in place of the passphrase
+pub fn encrypt_passphrase(
+    key: &SecretsKey,
+    passphrase: &Secret,
+    destination: &str,
+) -> Result<Secret> {
+    let encrypted = match *key {
+        SecretsKey::Tpm => run(
+            Command::new("systemd-creds").args(&[
+                "encrypt",
+                "--with-key=tpm2",
+                &format!("--name={}", CREDENTIAL_NAME),
+                "-",
+                "-",
+            ]),
+            passphrase.expose(),
+            destination,
+        )?,
+        SecretsKey::Keyfile(ref path) => run(
+            Command::new("openssl")
+                .args(OPENSSL_ARGS)
+                .arg("-pass")
+                .arg(format!("file:{}", path.display())),
+            passphrase.expose(),
+            destination,
+        )?,
+    };
+
+    // `systemd-creds` wraps its base64 output
+    let encrypted = encrypted
+        .expose()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .concat();
+
+    Ok(Secret::from(format!("{}{}", ENCRYPTED_PREFIX, encrypted)))
+}
+
+fn decrypt(key: &SecretsKey, encrypted: &str, source: &str) -> Result<Secret> {
+    match *key {
+        SecretsKey::Tpm => run(
+            Command::new("systemd-creds").args(&[
+                "decrypt",
+                &format!("--name={}", CREDENTIAL_NAME),
+                "-",
+                "-",
+            ]),
+            encrypted,
+            source,
+        ),
+        SecretsKey::Keyfile(ref path) => run(
+            Command::new("openssl")
+                .args(OPENSSL_ARGS)
+                .arg("-d")
+                .arg("-pass")
+                .arg(format!("file:{}", path.display())),
+            encrypted,
+            source,
+        ),
+    }
+}
+
+/// Runs `command` with `input` on stdin, keeping secrets off its command
+/// line
+fn run(command: &mut Command, input: &str, secret: &str) -> Result<Secret> {
+    let error = || ErrorKind::SecretEncryption(secret.into());
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(error)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).chain_err(error)?;
+    }
+
+    let output = child.wait_with_output().chain_err(error)?;
+
+    if !output.status.success() {
+        warn!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        bail!(error());
+    }
+
+    Ok(Secret::from(
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
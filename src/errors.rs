@@ -1,123 +1,592 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::result;
+use std::sync::mpsc::{RecvError, SendError};
+
+#[cfg(feature = "network-manager")]
 use network_manager;
+use nix;
 
 use network;
 
-error_chain! {
-    foreign_links {
-        Io(::std::io::Error);
-        Recv(::std::sync::mpsc::RecvError);
-        SendNetworkCommand(::std::sync::mpsc::SendError<network::NetworkCommand>);
-        Nix(::nix::Error);
-    }
+pub type Result<T> = result::Result<T, Error>;
 
-    links {
-        NetworkManager(network_manager::errors::Error, network_manager::errors::ErrorKind);
-    }
+/// Returns early with an error built from an `ErrorKind`
+#[macro_export]
+macro_rules! bail {
+    ($e:expr) => {
+        return Err($e.into());
+    };
+}
 
-    errors {
-        RecvAccessPointSSIDs {
-            description("Receiving access point SSIDs failed")
-        }
+/// Failure kinds of WiFi Connect. Variants carry the SSID, interface or
+/// other context of the failure, while the underlying cause, if any, is
+/// available through `Error::source`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Io,
+    Recv,
+    SendNetworkCommand,
+    Nix,
+    NetworkManager,
+    RecvAccessPointSSIDs,
+    SendAccessPointSSIDs,
+    SerializeAccessPointSSIDs,
+    RecvNetworkCommand,
+    SendNetworkCommandActivate,
+    SendNetworkCommandConnect,
+    DeviceByInterface(String),
+    NotAWiFiDevice(String),
+    UnmanagedDevice(String),
+    NoWiFiDevice,
+    NoAccessPoints,
+    CreateCaptivePortal,
+    StopAccessPoint,
+    SendNetworkCommandStop,
+    DeleteAccessPoint,
+    StartHTTPServer(String, String),
+    StartActiveNetworkManager,
+    StartNetworkManager,
+    NetworkManagerNotRunning(String),
+    NetworkManagerUnavailable,
+    Dnsmasq,
+    BlockExitSignals,
+    TrapExitSignals,
+    RootPrivilegesRequired(String),
+    InsufficientPrivileges(String, String),
+    NetworkNotFound(String),
+    ConnectionNotActivated(String, String),
+    UnsupportedByBackend(String, String),
+    Iwctl(String),
+    Ubus(String),
+    Uci(String),
+    Notify(String),
+    ModifyConnection(String),
+    AddConnection(String),
+    InvalidSecret(String, String),
+    NoWpaControlInterface(String),
+    MqttSubscribe(String),
+    AlreadyRunning(String),
+    PidFile(String),
+    Sandbox(String),
+    MockScript(String),
+    DiagnosticsFailed(usize),
+    ApModeUnsupported(String),
+    InvalidSsid(String, String),
+    DnsmasqExited(String),
+    PortInUse(u16, String, String),
+    DnsRedirect(String),
+    MacFilter(String),
+    AuthenticationFailed(String),
+    AssociationTimeout(String),
+    DhcpFailed(String),
+    ChannelScan(String),
+    ReadConnection(String),
+    InvalidProfileUpdate(String),
+    ConfirmationRequired(String),
+    SpeedTest(String),
+    Ping(String),
+    History(String),
+    SetTimezone(String),
+    AcceptEula(String),
+    ExtraFields(String),
+    SendHotspotCommand,
+    StartGrpcServer(String, String),
+    NoGuestHotspot,
+    StartGuestHotspot(String),
+    StopGuestHotspot(String),
+    VirtualInterface(String),
+    StartDualBandPortal,
+    RotatePassphrase,
+    PassphraseRotationDisabled,
+    QrCode,
+    ProxyFile(String),
+    ActivateVpn(String),
+    ProvisionWireguard,
+    WireguardHandshake(u64),
+    ClockUnsynchronized(u64),
+    PowerSave(String),
+    Checkpoint(String),
+    RolledBack(u64),
+    DesiredState(String),
+    StateSignature(String),
+    SecretEncryption(String),
+    PlaintextSecret(String),
+    Blocklist(String),
+    BlockedSsid(String),
+    Certificate(String),
+    Passpoint(String),
+    SimNotFound,
+    ConnectionTimestamps,
+    AccessLog(String),
+    DBus(String, String),
+}
 
-        SendAccessPointSSIDs {
-            description("Sending access point SSIDs failed")
+impl ErrorKind {
+    pub fn description(&self) -> &str {
+        match *self {
+            ErrorKind::Io => "I/O error",
+            ErrorKind::Recv => "Receiving from channel failed",
+            ErrorKind::SendNetworkCommand => "Sending network command failed",
+            ErrorKind::Nix => "System call failed",
+            ErrorKind::NetworkManager => "NetworkManager error",
+            ErrorKind::RecvAccessPointSSIDs => "Receiving access point SSIDs failed",
+            ErrorKind::SendAccessPointSSIDs => "Sending access point SSIDs failed",
+            ErrorKind::SerializeAccessPointSSIDs => "Serializing access point SSIDs failed",
+            ErrorKind::RecvNetworkCommand => "Receiving network command failed",
+            ErrorKind::SendNetworkCommandActivate => "Sending NetworkCommand::Activate failed",
+            ErrorKind::SendNetworkCommandConnect => "Sending NetworkCommand::Connect failed",
+            ErrorKind::DeviceByInterface(_) => "Cannot find network device with interface name",
+            ErrorKind::NotAWiFiDevice(_) => "Not a WiFi device",
+            ErrorKind::UnmanagedDevice(_) => "Unmanaged device",
+            ErrorKind::NoWiFiDevice => "Cannot find a WiFi device",
+            ErrorKind::NoAccessPoints => "Getting access points failed",
+            ErrorKind::CreateCaptivePortal => "Creating the captive portal failed",
+            ErrorKind::StopAccessPoint => "Stopping the access point failed",
+            ErrorKind::SendNetworkCommandStop => "Sending NetworkCommand::Stop failed",
+            ErrorKind::DeleteAccessPoint => "Deleting access point connection profile failed",
+            ErrorKind::StartHTTPServer(_, _) => "Cannot start HTTP server",
+            ErrorKind::StartActiveNetworkManager => {
+                "Starting the NetworkManager service with active state failed"
+            }
+            ErrorKind::StartNetworkManager => "Starting the NetworkManager service failed",
+            ErrorKind::NetworkManagerNotRunning(_) => "The NetworkManager service is not running",
+            ErrorKind::NetworkManagerUnavailable => {
+                "NetworkManager is not reachable over D-Bus - make sure it is installed and the system bus (DBUS_SYSTEM_BUS_ADDRESS) is accessible"
+            }
+            ErrorKind::Dnsmasq => "Spawning dnsmasq failed",
+            ErrorKind::BlockExitSignals => "Blocking exit signals failed",
+            ErrorKind::TrapExitSignals => "Trapping exit signals failed",
+            ErrorKind::RootPrivilegesRequired(_) => "Root privileges required",
+            ErrorKind::InsufficientPrivileges(_, _) => "Insufficient privileges",
+            ErrorKind::NetworkNotFound(_) => "Network not found",
+            ErrorKind::ConnectionNotActivated(_, _) => "Connection to access point not activated",
+            ErrorKind::UnsupportedByBackend(_, _) => "Not supported by the selected backend",
+            ErrorKind::Iwctl(_) => "Running iwctl failed",
+            ErrorKind::Ubus(_) => "Calling ubus failed",
+            ErrorKind::Uci(_) => "Running uci failed",
+            ErrorKind::Notify(_) => "Sending state notification failed",
+            ErrorKind::ModifyConnection(_) => "Modifying connection profile failed",
+            ErrorKind::AddConnection(_) => "Adding connection profile failed",
+            ErrorKind::InvalidSecret(_, _) => "Invalid passphrase for the connection profile",
+            ErrorKind::NoWpaControlInterface(_) => "No wpa_supplicant control interface",
+            ErrorKind::MqttSubscribe(_) => "Subscribing to MQTT topic failed",
+            ErrorKind::AlreadyRunning(_) => "Another instance is already running",
+            ErrorKind::PidFile(_) => "Locking the PID file failed",
+            ErrorKind::Sandbox(_) => "Applying the sandbox failed",
+            ErrorKind::MockScript(_) => "Loading the mock backend script failed",
+            ErrorKind::DiagnosticsFailed(_) => "Self-diagnostics failed",
+            ErrorKind::ApModeUnsupported(_) => "WiFi driver does not support access point mode",
+            ErrorKind::InvalidSsid(_, _) => "Invalid SSID",
+            ErrorKind::DnsmasqExited(_) => "dnsmasq exited unexpectedly",
+            ErrorKind::PortInUse(_, _, _) => "Port needed by dnsmasq is in use",
+            ErrorKind::DnsRedirect(_) => "Redirecting DNS to the alternate port failed",
+            ErrorKind::MacFilter(_) => "Applying the MAC filter failed",
+            ErrorKind::AuthenticationFailed(_) => "Authentication failed - wrong password",
+            ErrorKind::AssociationTimeout(_) => "Associating with the access point timed out",
+            ErrorKind::DhcpFailed(_) => "No IP address obtained via DHCP",
+            ErrorKind::ChannelScan(_) => "Reading the scan results failed",
+            ErrorKind::ReadConnection(_) => "Reading connection profile failed",
+            ErrorKind::InvalidProfileUpdate(_) => "Invalid changes to the saved network",
+            ErrorKind::ConfirmationRequired(_) => "Confirmation required",
+            ErrorKind::SpeedTest(_) => "Speed test failed",
+            ErrorKind::Ping(_) => "Resolving the host to ping failed",
+            ErrorKind::History(_) => "Accessing the provisioning history failed",
+            ErrorKind::SetTimezone(_) => "Setting the timezone failed",
+            ErrorKind::AcceptEula(_) => "Recording the EULA acceptance failed",
+            ErrorKind::ExtraFields(_) => "Storing the extra form fields failed",
+            ErrorKind::SendHotspotCommand => "Sending hotspot command failed",
+            ErrorKind::StartGrpcServer(_, _) => "Cannot start gRPC server",
+            ErrorKind::NoGuestHotspot => "No guest hotspot configured with --guest-ssid",
+            ErrorKind::StartGuestHotspot(_) => "Starting the guest hotspot failed",
+            ErrorKind::StopGuestHotspot(_) => "Stopping the guest hotspot failed",
+            ErrorKind::VirtualInterface(_) => "Managing the virtual interface failed",
+            ErrorKind::StartDualBandPortal => "Starting the 5 GHz access point failed",
+            ErrorKind::RotatePassphrase => "Rotating the hotspot passphrase failed",
+            ErrorKind::PassphraseRotationDisabled => {
+                "Passphrase rotation is not enabled with --rotate-passphrase"
+            }
+            ErrorKind::QrCode => "Rendering the QR code with qrencode failed",
+            ErrorKind::ProxyFile(_) => "Writing the proxy file failed",
+            ErrorKind::ActivateVpn(_) => "Activating the VPN connection failed",
+            ErrorKind::ProvisionWireguard => "Creating the WireGuard connection failed",
+            ErrorKind::WireguardHandshake(_) => "No WireGuard handshake with the peer",
+            ErrorKind::ClockUnsynchronized(_) => "Connected, but the clock is not synchronized",
+            ErrorKind::PowerSave(_) => "Setting the power saving of the interface failed",
+            ErrorKind::Checkpoint(_) => "NetworkManager checkpoint operation failed",
+            ErrorKind::RolledBack(_) => "No Internet connectivity after the change, rolled back",
+            ErrorKind::DesiredState(_) => "Reading the desired state failed",
+            ErrorKind::StateSignature(_) => "The desired state is not signed with the state secret",
+            ErrorKind::SecretEncryption(_) => "Encrypting or decrypting a passphrase failed",
+            ErrorKind::PlaintextSecret(_) => "Plaintext passphrase rejected with --encrypt-secrets",
+            ErrorKind::Blocklist(_) => "Accessing the SSID blocklist failed",
+            ErrorKind::BlockedSsid(_) => "The network is blocked",
+            ErrorKind::Certificate(_) => "Storing the certificate failed",
+            ErrorKind::Passpoint(_) => "Installing the Passpoint profile failed",
+            ErrorKind::SimNotFound => "No modem with a SIM found",
+            ErrorKind::ConnectionTimestamps => "Reading the connection timestamps failed",
+            ErrorKind::AccessLog(_) => "Opening the access log failed",
+            ErrorKind::DBus(_, _) => "D-Bus call failed",
         }
+    }
+}
 
-        SerializeAccessPointSSIDs {
-            description("Serializing access point SSIDs failed")
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::DeviceByInterface(ref interface) => write!(
+                f,
+                "Cannot find network device with interface name '{}'",
+                interface
+            ),
+            ErrorKind::InvalidSsid(ref ssid, ref reason) => {
+                write!(f, "Invalid SSID '{}': {}", ssid, reason)
+            }
+            ErrorKind::DnsmasqExited(ref status) => {
+                write!(f, "dnsmasq exited unexpectedly ({})", status)
+            }
+            ErrorKind::PortInUse(port, ref user, ref remediation) => write!(
+                f,
+                "Port {} is already used by {}: {}",
+                port, user, remediation
+            ),
+            ErrorKind::DnsRedirect(ref reason) => {
+                write!(f, "Redirecting DNS to the alternate port failed: {}", reason)
+            }
+            ErrorKind::MacFilter(ref reason) => {
+                write!(f, "Applying the MAC filter failed: {}", reason)
+            }
+            ErrorKind::AuthenticationFailed(ref ssid) => write!(
+                f,
+                "Authentication with '{}' failed - check the password",
+                ssid
+            ),
+            ErrorKind::AssociationTimeout(ref ssid) => {
+                write!(f, "Associating with '{}' timed out", ssid)
+            }
+            ErrorKind::DhcpFailed(ref ssid) => {
+                write!(f, "No IP address obtained via DHCP on '{}'", ssid)
+            }
+            ErrorKind::ChannelScan(ref interface) => {
+                write!(f, "Reading the scan results of {} with `iw` failed", interface)
+            }
+            ErrorKind::NotAWiFiDevice(ref interface) => write!(f, "Not a WiFi device: {}", interface),
+            ErrorKind::UnmanagedDevice(ref interface) => write!(f, "Unmanaged device: {}", interface),
+            ErrorKind::StartHTTPServer(ref address, ref reason) => write!(
+                f,
+                "Cannot start HTTP server on '{}': {}",
+                address, reason
+            ),
+            ErrorKind::NetworkManagerNotRunning(ref state) => write!(
+                f,
+                "The NetworkManager service is not running ({}) - start it with `systemctl start NetworkManager` or run without --no-start-network-manager",
+                state
+            ),
+            ErrorKind::RootPrivilegesRequired(ref app) => {
+                write!(f, "You need root privileges to run {}", app)
+            }
+            ErrorKind::InsufficientPrivileges(ref app, ref missing) => write!(
+                f,
+                "You need root privileges or polkit authorization to run {} - missing: {} (see --check-privileges)",
+                app, missing
+            ),
+            ErrorKind::NetworkNotFound(ref ssid) => write!(f, "Network not found: {}", ssid),
+            ErrorKind::ConnectionNotActivated(ref ssid, ref state) => write!(
+                f,
+                "Connection to access point '{}' not activated: {}",
+                ssid, state
+            ),
+            ErrorKind::UnsupportedByBackend(ref backend, ref feature) => write!(
+                f,
+                "The {} backend does not support {}",
+                backend, feature
+            ),
+            ErrorKind::Iwctl(ref command) => write!(f, "Running `iwctl {}` failed", command),
+            ErrorKind::Ubus(ref call) => write!(f, "Calling `ubus call {}` failed", call),
+            ErrorKind::Uci(ref command) => write!(f, "Running `uci {}` failed", command),
+            ErrorKind::Notify(ref backend) => write!(
+                f,
+                "Sending state notification via '{}' failed",
+                backend
+            ),
+            ErrorKind::ModifyConnection(ref uuid) => {
+                write!(f, "Modifying connection profile '{}' failed", uuid)
+            }
+            ErrorKind::ReadConnection(ref uuid) => {
+                write!(f, "Reading connection profile '{}' failed", uuid)
+            }
+            ErrorKind::AccessLog(ref path) => write!(f, "Opening the access log {} failed", path),
+            ErrorKind::DBus(ref call, ref reason) => write!(f, "{} failed: {}", call, reason),
+            ErrorKind::InvalidProfileUpdate(ref reason) => {
+                write!(f, "Invalid changes to the saved network: {}", reason)
+            }
+            ErrorKind::ConfirmationRequired(ref command) => write!(
+                f,
+                "{} needs confirmation, pass --yes when not running interactively",
+                command
+            ),
+            ErrorKind::SpeedTest(ref url) => write!(f, "Downloading '{}' for the speed test failed", url),
+            ErrorKind::Ping(ref host) => write!(f, "Cannot resolve '{}' to ping it", host),
+            ErrorKind::History(ref path) => {
+                write!(f, "Accessing the provisioning history in {} failed", path)
+            }
+            ErrorKind::SetTimezone(ref timezone) => {
+                write!(f, "Setting the timezone to '{}' failed", timezone)
+            }
+            ErrorKind::AcceptEula(ref path) => {
+                write!(f, "Recording the EULA acceptance in {} failed", path)
+            }
+            ErrorKind::ExtraFields(ref path) => {
+                write!(f, "Storing the extra form fields in {} failed", path)
+            }
+            ErrorKind::StartGrpcServer(ref address, ref reason) => {
+                write!(f, "Cannot start gRPC server on '{}': {}", address, reason)
+            }
+            ErrorKind::StartGuestHotspot(ref ssid) => {
+                write!(f, "Starting the guest hotspot '{}' failed", ssid)
+            }
+            ErrorKind::StopGuestHotspot(ref ssid) => {
+                write!(f, "Stopping the guest hotspot '{}' failed", ssid)
+            }
+            ErrorKind::VirtualInterface(ref interface) => {
+                write!(f, "Managing the virtual interface {} failed", interface)
+            }
+            ErrorKind::ProxyFile(ref path) => write!(f, "Writing the proxy file {} failed", path),
+            ErrorKind::ActivateVpn(ref name) => {
+                write!(f, "Activating the VPN connection '{}' failed", name)
+            }
+            ErrorKind::PowerSave(ref interface) => {
+                write!(f, "Setting the power saving of {} failed", interface)
+            }
+            ErrorKind::Checkpoint(ref method) => {
+                write!(f, "NetworkManager checkpoint operation {} failed", method)
+            }
+            ErrorKind::DesiredState(ref path) => {
+                write!(f, "Reading the desired state failed: {}", path)
+            }
+            ErrorKind::StateSignature(ref url) => write!(
+                f,
+                "The desired state from {} is not signed with the state secret",
+                url
+            ),
+            ErrorKind::SecretEncryption(ref secret) => {
+                write!(f, "Encrypting or decrypting the passphrase of {} failed", secret)
+            }
+            ErrorKind::PlaintextSecret(ref secret) => write!(
+                f,
+                "The passphrase of {} is not encrypted, as --encrypt-secrets requires",
+                secret
+            ),
+            ErrorKind::Blocklist(ref path) => {
+                write!(f, "Accessing the SSID blocklist in {} failed", path)
+            }
+            ErrorKind::BlockedSsid(ref ssid) => write!(f, "The network '{}' is blocked", ssid),
+            ErrorKind::Certificate(ref path) => write!(f, "Storing the certificate {} failed", path),
+            ErrorKind::Passpoint(ref profile) => {
+                write!(f, "Installing the Passpoint profile {} failed", profile)
+            }
+            ErrorKind::RolledBack(timeout) => write!(
+                f,
+                "No Internet connectivity within {} seconds after the change, rolled back",
+                timeout
+            ),
+            ErrorKind::ClockUnsynchronized(timeout) => write!(
+                f,
+                "Connected, but the clock is not synchronized over NTP within {} seconds",
+                timeout
+            ),
+            ErrorKind::WireguardHandshake(timeout) => write!(
+                f,
+                "No WireGuard handshake with the peer within {} seconds",
+                timeout
+            ),
+            ErrorKind::AddConnection(ref ssid) => {
+                write!(f, "Adding connection profile for '{}' failed", ssid)
+            }
+            ErrorKind::NoWpaControlInterface(ref interface) => write!(
+                f,
+                "No wpa_supplicant control interface for {}, WiFi Direct needs wpa_supplicant \
+                 running with ctrl_interface=/run/wpa_supplicant",
+                interface
+            ),
+            ErrorKind::InvalidSecret(ref ssid, ref reason) => write!(
+                f,
+                "Invalid passphrase for the connection profile of '{}': {}",
+                ssid, reason
+            ),
+            ErrorKind::MqttSubscribe(ref topic) => {
+                write!(f, "Subscribing to MQTT topic '{}' failed", topic)
+            }
+            ErrorKind::AlreadyRunning(ref pid) => {
+                write!(f, "Another instance is already running (PID {})", pid)
+            }
+            ErrorKind::PidFile(ref path) => write!(f, "Locking the PID file '{}' failed", path),
+            ErrorKind::Sandbox(ref stage) => write!(f, "Applying the {} sandbox failed", stage),
+            ErrorKind::MockScript(ref path) => {
+                write!(f, "Loading the mock backend script '{}' failed", path)
+            }
+            ErrorKind::DiagnosticsFailed(count) => {
+                write!(f, "Self-diagnostics failed: {} check(s) did not pass", count)
+            }
+            ErrorKind::ApModeUnsupported(ref interface) => write!(
+                f,
+                "The WiFi driver of {} does not support access point mode",
+                interface
+            ),
+            _ => f.write_str(self.description()),
         }
+    }
+}
 
-        RecvNetworkCommand {
-            description("Receiving network command failed")
-        }
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Box<dyn StdError + Send + 'static>>,
+}
 
-        SendNetworkCommandActivate {
-            description("Sending NetworkCommand::Activate failed")
-        }
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Error { kind, source: None }
+    }
 
-        SendNetworkCommandConnect {
-            description("Sending NetworkCommand::Connect failed")
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: StdError + Send + 'static,
+    {
+        Error {
+            kind,
+            source: Some(Box::new(source)),
         }
+    }
 
-        DeviceByInterface(interface: String) {
-            description("Cannot find network device with interface name")
-            display("Cannot find network device with interface name '{}'", interface)
-        }
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 
-        NotAWiFiDevice(interface: String) {
-            description("Not a WiFi device")
-            display("Not a WiFi device: {}", interface)
+    /// Iterates over this error followed by its chain of causes
+    pub fn iter(&self) -> Iter {
+        Iter {
+            next: Some(self as &dyn StdError),
         }
+    }
 
-        UnmanagedDevice(interface: String) {
-            description("Unmanaged device")
-            display("Unmanaged device: {}", interface)
+    /// Errors converted from other crates only wrap their source and are
+    /// displayed as it
+    fn is_transparent(&self) -> bool {
+        match self.kind {
+            ErrorKind::Io
+            | ErrorKind::Recv
+            | ErrorKind::SendNetworkCommand
+            | ErrorKind::Nix
+            | ErrorKind::NetworkManager => self.source.is_some(),
+            _ => false,
         }
+    }
+}
 
-        NoWiFiDevice {
-            description("Cannot find a WiFi device")
-        }
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("kind", &self.kind)
+            .field("source", &self.source)
+            .finish()
+    }
+}
 
-        NoAccessPoints {
-            description("Getting access points failed")
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Some(ref source) if self.is_transparent() => fmt::Display::fmt(source, f),
+            _ => fmt::Display::fmt(&self.kind, f),
         }
+    }
+}
 
-        CreateCaptivePortal {
-            description("Creating the captive portal failed")
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.source {
+            Some(ref source) if self.is_transparent() => source.source(),
+            Some(ref source) => Some(&**source as &(dyn StdError + 'static)),
+            None => None,
         }
+    }
+}
 
-        StopAccessPoint {
-            description("Stopping the access point failed")
-        }
+pub struct Iter<'a> {
+    next: Option<&'a (dyn StdError + 'static)>,
+}
 
-        SendNetworkCommandStop {
-            description("Sending NetworkCommand::Stop failed")
-        }
-        
-        DeleteAccessPoint {
-            description("Deleting access point connection profile failed")
-        }
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a (dyn StdError + 'static);
 
-        StartHTTPServer(address: String, reason: String) {
-            description("Cannot start HTTP server")
-            display("Cannot start HTTP server on '{}': {}", address, reason)
-        }
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        self.next = current.source();
+        Some(current)
+    }
+}
 
-        StartActiveNetworkManager {
-            description("Starting the NetworkManager service with active state failed")
-        }
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
 
-        StartNetworkManager {
-            description("Starting the NetworkManager service failed")
-        }
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::with_source(ErrorKind::Io, e)
+    }
+}
 
-        Dnsmasq {
-            description("Spawning dnsmasq failed")
-        }
+impl From<RecvError> for Error {
+    fn from(e: RecvError) -> Self {
+        Error::with_source(ErrorKind::Recv, e)
+    }
+}
 
-        BlockExitSignals {
-            description("Blocking exit signals failed")
-        }
+impl From<SendError<network::NetworkCommand>> for Error {
+    fn from(e: SendError<network::NetworkCommand>) -> Self {
+        Error::with_source(ErrorKind::SendNetworkCommand, e)
+    }
+}
 
-        TrapExitSignals {
-            description("Trapping exit signals failed")
-        }
+impl From<nix::Error> for Error {
+    fn from(e: nix::Error) -> Self {
+        Error::with_source(ErrorKind::Nix, e)
+    }
+}
 
-        RootPrivilegesRequired(app: String) {
-            description("Root privileges required")
-            display("You need root privileges to run {}", app)
-        }
-        
-        NetworkNotFound(ssid: String) {
-            description("Network not found")
-            display("Network not found: {}", ssid)
-        }
+#[cfg(feature = "network-manager")]
+impl From<network_manager::errors::Error> for Error {
+    fn from(e: network_manager::errors::Error) -> Self {
+        Error::with_source(ErrorKind::NetworkManager, e)
+    }
+}
+
+/// Wraps the error of a result as the cause of a WiFi Connect error kind
+pub trait ResultExt<T> {
+    fn chain_err<F, K>(self, callback: F) -> Result<T>
+    where
+        F: FnOnce() -> K,
+        K: Into<ErrorKind>;
+}
+
+impl<T, E> ResultExt<T> for result::Result<T, E>
+where
+    E: StdError + Send + 'static,
+{
+    fn chain_err<F, K>(self, callback: F) -> Result<T>
+    where
+        F: FnOnce() -> K,
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|e| Error::with_source(callback().into(), e))
     }
 }
 
 pub fn exit_code(e: &Error) -> i32 {
     match *e.kind() {
-        ErrorKind::Dnsmasq => 3,
+        ErrorKind::Dnsmasq | ErrorKind::DnsmasqExited(_) => 3,
         ErrorKind::RecvAccessPointSSIDs => 4,
         ErrorKind::SendAccessPointSSIDs => 5,
         ErrorKind::SerializeAccessPointSSIDs => 6,
@@ -136,8 +605,80 @@ pub fn exit_code(e: &Error) -> i32 {
         ErrorKind::StartNetworkManager => 19,
         ErrorKind::BlockExitSignals => 21,
         ErrorKind::TrapExitSignals => 22,
-        ErrorKind::RootPrivilegesRequired(_) => 23,
+        ErrorKind::RootPrivilegesRequired(_) | ErrorKind::InsufficientPrivileges(_, _) => 23,
         ErrorKind::UnmanagedDevice(_) => 24,
-        _ => 1,
+        ErrorKind::NetworkManagerNotRunning(_) => 25,
+        ErrorKind::NetworkManagerUnavailable => 26,
+        ErrorKind::AlreadyRunning(_) => 27,
+        ErrorKind::Sandbox(_) => 28,
+        ErrorKind::DiagnosticsFailed(_) => 29,
+        ErrorKind::ApModeUnsupported(_) => 30,
+        ErrorKind::PortInUse(_, _, _) => 31,
+        ErrorKind::AuthenticationFailed(_) => 32,
+        ErrorKind::AssociationTimeout(_) => 33,
+        ErrorKind::DhcpFailed(_) => 34,
+        ErrorKind::ClockUnsynchronized(_) => 35,
+        ErrorKind::RolledBack(_) => 36,
+        ErrorKind::Io
+        | ErrorKind::Recv
+        | ErrorKind::SendNetworkCommand
+        | ErrorKind::Nix
+        | ErrorKind::NetworkManager
+        | ErrorKind::SendNetworkCommandStop
+        | ErrorKind::NetworkNotFound(_)
+        | ErrorKind::ConnectionNotActivated(_, _)
+        | ErrorKind::UnsupportedByBackend(_, _)
+        | ErrorKind::Iwctl(_)
+        | ErrorKind::Ubus(_)
+        | ErrorKind::Uci(_)
+        | ErrorKind::Notify(_)
+        | ErrorKind::ModifyConnection(_)
+        | ErrorKind::AddConnection(_)
+        | ErrorKind::InvalidSecret(_, _)
+        | ErrorKind::NoWpaControlInterface(_)
+        | ErrorKind::MqttSubscribe(_)
+        | ErrorKind::PidFile(_)
+        | ErrorKind::MockScript(_)
+        | ErrorKind::InvalidSsid(_, _)
+        | ErrorKind::DnsRedirect(_)
+        | ErrorKind::MacFilter(_)
+        | ErrorKind::ChannelScan(_)
+        | ErrorKind::ReadConnection(_)
+        | ErrorKind::InvalidProfileUpdate(_)
+        | ErrorKind::ConfirmationRequired(_)
+        | ErrorKind::SpeedTest(_)
+        | ErrorKind::Ping(_)
+        | ErrorKind::History(_)
+        | ErrorKind::SetTimezone(_)
+        | ErrorKind::AcceptEula(_)
+        | ErrorKind::ExtraFields(_)
+        | ErrorKind::SendHotspotCommand
+        | ErrorKind::StartGrpcServer(_, _)
+        | ErrorKind::NoGuestHotspot
+        | ErrorKind::StartGuestHotspot(_)
+        | ErrorKind::StopGuestHotspot(_)
+        | ErrorKind::VirtualInterface(_)
+        | ErrorKind::StartDualBandPortal
+        | ErrorKind::RotatePassphrase
+        | ErrorKind::PassphraseRotationDisabled
+        | ErrorKind::QrCode
+        | ErrorKind::ProxyFile(_)
+        | ErrorKind::ActivateVpn(_)
+        | ErrorKind::ProvisionWireguard
+        | ErrorKind::WireguardHandshake(_)
+        | ErrorKind::PowerSave(_)
+        | ErrorKind::Checkpoint(_)
+        | ErrorKind::DesiredState(_)
+        | ErrorKind::StateSignature(_)
+        | ErrorKind::SecretEncryption(_)
+        | ErrorKind::PlaintextSecret(_)
+        | ErrorKind::Blocklist(_)
+        | ErrorKind::BlockedSsid(_)
+        | ErrorKind::Certificate(_)
+        | ErrorKind::Passpoint(_)
+        | ErrorKind::SimNotFound
+        | ErrorKind::ConnectionTimestamps
+        | ErrorKind::AccessLog(_)
+        | ErrorKind::DBus(_, _) => 1,
     }
 }
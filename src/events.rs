@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use state::State;
+
+/// Number of events kept before the oldest ones are dropped
+pub const EVENT_HISTORY_SIZE: usize = 200;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum EventKind {
+    Transition { from: String, to: State },
+    Error { message: String },
+    /// Station associated with the access point
+    ClientJoined { mac: String, signal: Option<i32> },
+    /// Station gone from the access point, with its last signal
+    ClientLeft { mac: String, signal: Option<i32> },
+}
+
+/// Entry of the event history served by `/events`. IDs increase by one per
+/// event, so that pollers can ask for everything after the last one seen.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub id: u64,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Ring buffer of the latest state transitions, errors and station
+/// associations, kept in memory
+/// so that failed provisioning attempts can be reconstructed without logs
+pub struct EventLog {
+    events: VecDeque<Event>,
+    next_id: u64,
+}
+
+pub type SharedEventLog = Arc<Mutex<EventLog>>;
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            events: VecDeque::with_capacity(EVENT_HISTORY_SIZE),
+            next_id: 1,
+        }
+    }
+
+    pub fn push(&mut self, kind: EventKind) {
+        if self.events.len() == EVENT_HISTORY_SIZE {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(Event {
+            id: self.next_id,
+            timestamp: now_ms(),
+            kind,
+        });
+
+        self.next_id += 1;
+    }
+
+    /// Events with an ID above `since`, or all kept events without it
+    pub fn since(&self, since: Option<u64>) -> Vec<Event> {
+        let since = since.unwrap_or(0);
+
+        self.events
+            .iter()
+            .filter(|event| event.id > since)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()))
+        .unwrap_or(0)
+}
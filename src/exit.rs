@@ -1,5 +1,19 @@
+use std::cmp;
+#[cfg(target_os = "linux")]
+use std::convert::TryFrom;
+#[cfg(not(target_os = "linux"))]
+use std::mem;
+#[cfg(target_os = "linux")]
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use nix::libc;
+#[cfg(target_os = "linux")]
+use nix::sys::signal::Signal;
 use nix::sys::signal::{SigSet, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
 
 use errors::*;
@@ -10,6 +24,44 @@ pub fn exit(exit_tx: &Sender<ExitResult>, error: Error) {
     let _ = exit_tx.send(Err(error));
 }
 
+/// Flag raised once an exit signal is trapped, so that long waits (scans,
+/// connectivity checks) give up early instead of delaying shutdown
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for `duration` unless shutdown is triggered in the meantime and
+    /// returns whether it was
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            if self.is_triggered() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            thread::sleep(cmp::min(deadline - now, Duration::from_millis(100)));
+        }
+    }
+}
+
 /// Block exit signals from the main thread with mask inherited by children
 pub fn block_exit_signals() -> Result<()> {
     let mask = create_exit_sigmask();
@@ -28,6 +80,66 @@ pub fn trap_exit_signals() -> Result<()> {
     Ok(())
 }
 
+/// Waits up to `timeout` for an exit signal on the calling thread and returns
+/// whether one was trapped. Unlike a signal handling thread this leaves no
+/// waiter behind to compete with the one of a mode started afterwards.
+#[cfg(target_os = "linux")]
+pub fn wait_exit_signal(timeout: Duration) -> bool {
+    let mask = create_exit_sigmask();
+
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+
+    let sig = unsafe { libc::sigtimedwait(mask.as_ref(), ptr::null_mut(), &timeout) };
+
+    match Signal::try_from(sig) {
+        Ok(sig) => {
+            info!("\nReceived {:?}", sig);
+            true
+        }
+        // Timed out or interrupted
+        Err(_) => false,
+    }
+}
+
+/// There is no `sigtimedwait` on macOS, so the blocked exit signals are
+/// polled for until one is pending and then taken with `sigwait`
+#[cfg(not(target_os = "linux"))]
+pub fn wait_exit_signal(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let mut pending: libc::sigset_t = unsafe { mem::zeroed() };
+        unsafe { libc::sigpending(&mut pending) };
+
+        let trapped = [SIGINT, SIGQUIT, SIGTERM, SIGHUP]
+            .iter()
+            .find(|sig| unsafe { libc::sigismember(&pending, **sig as libc::c_int) } == 1);
+
+        if let Some(&sig) = trapped {
+            let mut mask = SigSet::empty();
+            mask.add(sig);
+
+            return match mask.wait() {
+                Ok(sig) => {
+                    info!("\nReceived {:?}", sig);
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+
+        thread::sleep(cmp::min(deadline - now, Duration::from_millis(100)));
+    }
+}
+
 fn create_exit_sigmask() -> SigSet {
     let mut mask = SigSet::empty();
 
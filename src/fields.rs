@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use errors::*;
+use low_memory::to_json_file;
+
+pub const DEFAULT_EXTRA_FIELDS_FILE: &str = "/var/lib/wifi-connect/extra-fields.json";
+
+/// Longest value accepted for an extra field
+const MAX_FIELD_LENGTH: usize = 256;
+
+/// Product specific form field shown by the portal next to the WiFi
+/// credentials, declared with `--extra-field name,label[,required]`
+#[derive(Clone, Debug, Serialize)]
+pub struct ExtraField {
+    pub name: String,
+    pub label: String,
+    pub required: bool,
+}
+
+pub fn parse_extra_field(value: &str) -> ::std::result::Result<ExtraField, String> {
+    let fields: Vec<&str> = value.split(',').map(|field| field.trim()).collect();
+
+    let required = match fields.get(2) {
+        None | Some(&"optional") => false,
+        Some(&"required") => true,
+        Some(_) => return Err(extra_field_error(value)),
+    };
+
+    let valid = (fields.len() == 2 || fields.len() == 3)
+        && !fields[0].is_empty()
+        && fields[0]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !fields[1].is_empty();
+
+    if !valid {
+        return Err(extra_field_error(value));
+    }
+
+    Ok(ExtraField {
+        name: fields[0].to_string(),
+        label: fields[1].to_string(),
+        required,
+    })
+}
+
+pub fn validate_extra_field(value: String) -> ::std::result::Result<(), String> {
+    parse_extra_field(&value).map(|_| ())
+}
+
+fn extra_field_error(value: &str) -> String {
+    format!(
+        "'{}' is not an extra field of the form name,label[,required]",
+        value
+    )
+}
+
+/// Values of the declared fields looked up with `value`. Required fields
+/// have to be filled in, optional ones that are left empty are skipped.
+pub fn collect_extra_fields<F>(
+    fields: &[ExtraField],
+    value: F,
+) -> ::std::result::Result<BTreeMap<String, String>, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut values = BTreeMap::new();
+
+    for field in fields {
+        let entered = value(&field.name)
+            .map(|entered| entered.trim().to_string())
+            .unwrap_or_default();
+
+        if entered.is_empty() {
+            if field.required {
+                return Err(format!("'{}' is required", field.label));
+            }
+            continue;
+        }
+
+        if entered.len() > MAX_FIELD_LENGTH {
+            return Err(format!(
+                "'{}' is longer than {} characters",
+                field.label, MAX_FIELD_LENGTH
+            ));
+        }
+
+        values.insert(field.name.clone(), entered);
+    }
+
+    Ok(values)
+}
+
+/// Writes the entered values as a JSON object for the hook to pick up
+pub fn write_extra_fields(path: &Path, values: &BTreeMap<String, String>) -> Result<()> {
+    let error = || ErrorKind::ExtraFields(path.display().to_string());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(error)?;
+    }
+
+    let json = to_json_file(values).chain_err(error)?;
+
+    fs::write(path, json).chain_err(error)
+}
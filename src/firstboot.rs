@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use errors::*;
+use exit::Shutdown;
+
+/// Marker recording that the EULA was accepted, so that later boots skip it
+pub const EULA_ACCEPTED_FILE: &str = "/var/lib/wifi-connect/eula-accepted";
+
+/// Seconds to wait for the clock to be synchronized after connecting
+pub const NTP_SYNC_TIMEOUT: u64 = 60;
+
+/// 2024-01-01, a clock behind it has not been set since the device lost
+/// power without an RTC battery
+const MIN_CLOCK_TIME: u64 = 1_704_067_200;
+
+/// Extra steps of the `--first-boot` portal served by `/first-boot`
+#[derive(Debug, Serialize)]
+pub struct FirstBootStatus {
+    pub timezone: Option<String>,
+    pub timezones: Vec<String>,
+    pub ntp_synchronized: bool,
+    /// Text of the EULA to accept, if one is configured
+    pub eula: Option<String>,
+    pub eula_accepted: bool,
+}
+
+pub fn first_boot_status(eula: &Option<String>) -> FirstBootStatus {
+    let eula = eula.as_ref().map(|path| {
+        fs::read_to_string(path).unwrap_or_else(|e| {
+            warn!("Reading the EULA from {} failed: {}", path, e);
+            String::new()
+        })
+    });
+
+    FirstBootStatus {
+        timezone: timedatectl_property("Timezone"),
+        timezones: list_timezones(),
+        ntp_synchronized: is_ntp_synchronized(),
+        eula,
+        eula_accepted: is_eula_accepted(),
+    }
+}
+
+pub fn list_timezones() -> Vec<String> {
+    Command::new("timedatectl")
+        .arg("list-timezones")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Sets the system timezone, which has to be one of `list_timezones`
+pub fn set_timezone(timezone: &str) -> Result<()> {
+    if !list_timezones().iter().any(|known| known == timezone) {
+        bail!(ErrorKind::SetTimezone(timezone.into()));
+    }
+
+    let status = Command::new("timedatectl")
+        .args(&["set-timezone", timezone])
+        .status()
+        .chain_err(|| ErrorKind::SetTimezone(timezone.into()))?;
+
+    if !status.success() {
+        bail!(ErrorKind::SetTimezone(timezone.into()));
+    }
+
+    info!("Timezone set to {}", timezone);
+
+    Ok(())
+}
+
+pub fn is_ntp_synchronized() -> bool {
+    timedatectl_property("NTPSynchronized").map_or(false, |value| value == "yes")
+}
+
+/// Polls until the clock is synchronized, giving up after `timeout` seconds
+/// or on shutdown
+pub fn wait_for_ntp_sync(timeout: u64, shutdown: &Shutdown) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    loop {
+        if is_ntp_synchronized() {
+            return true;
+        }
+
+        if Instant::now() >= deadline || shutdown.sleep(Duration::from_secs(1)) {
+            return false;
+        }
+    }
+}
+
+/// Whether the clock can be trusted for TLS with `--require-time-sync`:
+/// synchronized over NTP within `timeout` seconds and past the earliest
+/// plausible time
+pub fn check_time_sync(timeout: u64, shutdown: &Shutdown) -> bool {
+    if !wait_for_ntp_sync(timeout, shutdown) {
+        warn!("Clock not synchronized over NTP within {} seconds", timeout);
+        return false;
+    }
+
+    if !is_clock_sane() {
+        warn!("Clock synchronized over NTP but still set before 2024");
+        return false;
+    }
+
+    info!("Clock synchronized over NTP");
+
+    true
+}
+
+fn is_clock_sane() -> bool {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(false, |elapsed| elapsed.as_secs() >= MIN_CLOCK_TIME)
+}
+
+pub fn is_eula_accepted() -> bool {
+    Path::new(EULA_ACCEPTED_FILE).exists()
+}
+
+pub fn accept_eula() -> Result<()> {
+    let path = Path::new(EULA_ACCEPTED_FILE);
+    let error = || ErrorKind::AcceptEula(EULA_ACCEPTED_FILE.into());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(error)?;
+    }
+
+    fs::write(path, b"").chain_err(error)?;
+
+    info!("EULA accepted");
+
+    Ok(())
+}
+
+fn timedatectl_property(property: &str) -> Option<String> {
+    let output = Command::new("timedatectl")
+        .args(&["show", "--property", property, "--value"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
@@ -0,0 +1,33 @@
+//! Pruning of saved WiFi networks. Profiles pile up on long-lived devices as
+//! they are moved between sites, and every operation listing them gets
+//! slower, so `--gc-interval` deletes the ones not used anymore as the
+//! one-off `--gc-connections` does.
+
+use std::thread;
+use std::time::Duration;
+
+use backend::init_backend;
+use config::Config;
+use errors::*;
+
+/// Prunes the saved networks with a backend of its own, as the ones of the
+/// daemon are tied to their threads
+pub fn gc_connections(config: &Config) -> Result<usize> {
+    let backend = init_backend(config)?;
+    let pruned = backend.gc_connections(&config.gc_policy)?;
+
+    info!("{} saved WiFi networks have been pruned", pruned);
+
+    Ok(pruned)
+}
+
+/// Prunes the saved networks every `interval` hours
+pub fn spawn_connection_gc(config: Config, interval: u64) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval * 60 * 60));
+
+        if let Err(e) = gc_connections(&config) {
+            error!("Pruning saved WiFi networks failed: {}", e);
+        }
+    });
+}
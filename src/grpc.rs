@@ -0,0 +1,608 @@
+//! Optional gRPC management interface, built with the `grpc` feature. It
+//! drives the same hotspot controller as the management HTTP API, see
+//! `proto/wifi_connect.proto` for the service definition.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use futures::SinkExt;
+pub use grpcio::Server as GrpcServer;
+
+use grpcio::{
+    pr_de, pr_ser, Environment, Marshaller, Method, MethodType, RpcContext, RpcStatus,
+    RpcStatusCode, Server, ServerBuilder, ServerStreamingSink, Service, ServiceBuilder, UnarySink,
+    WriteFlags,
+};
+
+use errors::*;
+use events::{Event, EventKind};
+use hotspot_manager::{HotspotCommand, HotspotCommandResponse, HotspotController, HotspotStatus};
+use network::{ConnectedNetwork, Network, NetworkCredentials};
+use proxy::collect_proxy_settings;
+use secret::Secret;
+use server::is_authorized;
+use ssid::validate_ssid;
+use state::State;
+use wireguard::collect_wireguard_config;
+
+/// Seconds between polls of the event history by `WatchStatus` streams
+const WATCH_INTERVAL: u64 = 1;
+
+/// Messages of `proto/wifi_connect.proto`
+pub mod proto {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ScanRequest {}
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Network {
+        #[prost(string, tag = "1")]
+        pub ssid: String,
+        #[prost(string, tag = "2")]
+        pub ssid_hex: String,
+        #[prost(string, tag = "3")]
+        pub security: String,
+        #[prost(uint32, tag = "4")]
+        pub signal_strength: u32,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ScanResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub networks: Vec<Network>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ConnectRequest {
+        #[prost(string, tag = "1")]
+        pub ssid: String,
+        #[prost(string, tag = "2")]
+        pub identity: String,
+        #[prost(string, tag = "3")]
+        pub passphrase: String,
+        #[prost(message, optional, tag = "4")]
+        pub proxy: Option<Proxy>,
+        #[prost(string, tag = "5")]
+        pub wireguard: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Proxy {
+        #[prost(string, tag = "1")]
+        pub host: String,
+        #[prost(uint32, tag = "2")]
+        pub port: u32,
+        #[prost(string, tag = "3")]
+        pub pac_url: String,
+        #[prost(string, tag = "4")]
+        pub username: String,
+        #[prost(string, tag = "5")]
+        pub password: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ConnectedNetwork {
+        #[prost(string, tag = "1")]
+        pub ssid: String,
+        #[prost(string, tag = "2")]
+        pub security: String,
+        #[prost(uint32, tag = "3")]
+        pub signal_strength: u32,
+        #[prost(string, tag = "4")]
+        pub interface: String,
+        #[prost(string, tag = "5")]
+        pub ip_address: String,
+        #[prost(string, repeated, tag = "6")]
+        pub ipv6_addresses: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ConnectResponse {
+        #[prost(message, optional, tag = "1")]
+        pub connected: Option<ConnectedNetwork>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ForgetRequest {
+        #[prost(string, tag = "1")]
+        pub ssid: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ForgetResponse {
+        #[prost(bool, tag = "1")]
+        pub forgotten: bool,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct StatusRequest {}
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct HotspotStatus {
+        #[prost(bool, tag = "1")]
+        pub is_running: bool,
+        #[prost(string, tag = "2")]
+        pub ssid: String,
+        #[prost(string, tag = "3")]
+        pub gateway: String,
+        #[prost(string, tag = "4")]
+        pub interface: String,
+        #[prost(bool, tag = "5")]
+        pub password_protected: bool,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct StatusResponse {
+        #[prost(message, optional, tag = "1")]
+        pub hotspot: Option<HotspotStatus>,
+        #[prost(message, optional, tag = "2")]
+        pub connected: Option<ConnectedNetwork>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+    pub enum HotspotAction {
+        Start = 0,
+        Stop = 1,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct HotspotControlRequest {
+        #[prost(enumeration = "HotspotAction", tag = "1")]
+        pub action: i32,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct WatchStatusRequest {
+        #[prost(uint64, tag = "1")]
+        pub since: u64,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct StatusUpdate {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(uint64, tag = "2")]
+        pub timestamp: u64,
+        #[prost(string, tag = "3")]
+        pub kind: String,
+        #[prost(string, tag = "4")]
+        pub state: String,
+        #[prost(string, tag = "5")]
+        pub ssid: String,
+        #[prost(string, tag = "6")]
+        pub ip: String,
+        #[prost(string, tag = "7")]
+        pub failure: String,
+        #[prost(string, tag = "8")]
+        pub reason: String,
+        #[prost(string, tag = "9")]
+        pub message: String,
+    }
+}
+
+macro_rules! method {
+    ($ty:ident, $name:expr) => {
+        Method {
+            ty: MethodType::$ty,
+            name: concat!("/wifi_connect.v1.WiFiConnect/", $name),
+            req_mar: Marshaller {
+                ser: pr_ser,
+                de: pr_de,
+            },
+            resp_mar: Marshaller {
+                ser: pr_ser,
+                de: pr_de,
+            },
+        }
+    };
+}
+
+const METHOD_SCAN: Method<proto::ScanRequest, proto::ScanResponse> = method!(Unary, "Scan");
+const METHOD_CONNECT: Method<proto::ConnectRequest, proto::ConnectResponse> =
+    method!(Unary, "Connect");
+const METHOD_FORGET: Method<proto::ForgetRequest, proto::ForgetResponse> = method!(Unary, "Forget");
+const METHOD_STATUS: Method<proto::StatusRequest, proto::StatusResponse> = method!(Unary, "Status");
+const METHOD_HOTSPOT_CONTROL: Method<proto::HotspotControlRequest, proto::HotspotStatus> =
+    method!(Unary, "HotspotControl");
+const METHOD_WATCH_STATUS: Method<proto::WatchStatusRequest, proto::StatusUpdate> =
+    method!(ServerStreaming, "WatchStatus");
+
+type RpcResult<T> = ::std::result::Result<T, RpcStatus>;
+
+/// Starts serving the gRPC interface on `address`, given as `host:port`.
+/// The interface stays up as long as the returned server is kept.
+pub fn start_grpc_server(
+    address: &str,
+    api_token: Option<String>,
+    controller: HotspotController,
+) -> Result<Server> {
+    let error = |reason: String| ErrorKind::StartGrpcServer(address.into(), reason);
+
+    let (host, port) = match address.rfind(':') {
+        Some(index) => (&address[..index], address[index + 1..].parse::<u16>().ok()),
+        None => (address, None),
+    };
+
+    let port = match port {
+        Some(port) => port,
+        None => bail!(error("expected host:port".into())),
+    };
+
+    let service = WiFiConnectService {
+        api_token,
+        controller,
+    };
+
+    let mut server = ServerBuilder::new(Arc::new(Environment::new(1)))
+        .register_service(service.build())
+        .bind(host, port)
+        .build()
+        .map_err(|e| error(e.to_string()))?;
+
+    server.start();
+
+    info!("Starting gRPC management server on {}", address);
+
+    Ok(server)
+}
+
+#[derive(Clone)]
+struct WiFiConnectService {
+    api_token: Option<String>,
+    controller: HotspotController,
+}
+
+impl WiFiConnectService {
+    fn build(self) -> Service {
+        let scan = self.clone();
+        let connect = self.clone();
+        let forget = self.clone();
+        let status = self.clone();
+        let hotspot_control = self.clone();
+        let watch_status = self;
+
+        ServiceBuilder::new()
+            .add_unary_handler(&METHOD_SCAN, move |ctx, _req, sink| {
+                let service = scan.clone();
+                respond(&ctx, &service, sink, move || service.scan())
+            })
+            .add_unary_handler(&METHOD_CONNECT, move |ctx, req, sink| {
+                let service = connect.clone();
+                respond(&ctx, &service, sink, move || service.connect(req))
+            })
+            .add_unary_handler(&METHOD_FORGET, move |ctx, req, sink| {
+                let service = forget.clone();
+                respond(&ctx, &service, sink, move || service.forget(req))
+            })
+            .add_unary_handler(&METHOD_STATUS, move |ctx, _req, sink| {
+                let service = status.clone();
+                respond(&ctx, &service, sink, move || service.status())
+            })
+            .add_unary_handler(&METHOD_HOTSPOT_CONTROL, move |ctx, req, sink| {
+                let service = hotspot_control.clone();
+                respond(&ctx, &service, sink, move || service.hotspot_control(req))
+            })
+            .add_server_streaming_handler(&METHOD_WATCH_STATUS, move |ctx, req, sink| {
+                watch_status.watch_status(&ctx, req, sink)
+            })
+            .build()
+    }
+
+    fn scan(&self) -> RpcResult<proto::ScanResponse> {
+        info!("Incoming gRPC `scan` request");
+
+        match self.send(HotspotCommand::Scan)? {
+            HotspotCommandResponse::Networks(networks) => Ok(proto::ScanResponse {
+                networks: networks.iter().map(proto::Network::from).collect(),
+            }),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn connect(&self, req: proto::ConnectRequest) -> RpcResult<proto::ConnectResponse> {
+        validate(&req.ssid)?;
+
+        info!(
+            "Incoming gRPC `connect` to access point `{}` request",
+            req.ssid
+        );
+
+        let proxy = match req.proxy {
+            Some(ref proxy) => collect_proxy_settings(|name| {
+                let value = match name {
+                    "proxy_host" => proxy.host.clone(),
+                    "proxy_port" if proxy.port != 0 => proxy.port.to_string(),
+                    "proxy_pac_url" => proxy.pac_url.clone(),
+                    "proxy_username" => proxy.username.clone(),
+                    "proxy_password" => proxy.password.clone(),
+                    _ => String::new(),
+                };
+                Some(value)
+            })
+            .map_err(|e| RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e)))?,
+            None => None,
+        };
+
+        let wireguard = collect_wireguard_config(Some(req.wireguard))
+            .map_err(|e| RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e)))?;
+
+        let network = NetworkCredentials {
+            ssid: req.ssid,
+            identity: req.identity,
+            passphrase: Secret::from(req.passphrase),
+            proxy,
+            wireguard,
+        };
+
+        match self.send(HotspotCommand::Connect(network))? {
+            HotspotCommandResponse::ConnectedNetwork(connected) => Ok(proto::ConnectResponse {
+                connected: connected.as_ref().map(proto::ConnectedNetwork::from),
+            }),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn forget(&self, req: proto::ForgetRequest) -> RpcResult<proto::ForgetResponse> {
+        validate(&req.ssid)?;
+
+        info!(
+            "Incoming gRPC `forget` of saved network `{}` request",
+            req.ssid
+        );
+
+        match self.send(HotspotCommand::Forget(req.ssid))? {
+            HotspotCommandResponse::Forgotten(forgotten) => Ok(proto::ForgetResponse { forgotten }),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn status(&self) -> RpcResult<proto::StatusResponse> {
+        let hotspot = match self.send(HotspotCommand::Status)? {
+            HotspotCommandResponse::Status(status) => proto::HotspotStatus::from(&status),
+            _ => return Err(unexpected_response()),
+        };
+
+        let connected = match self.send(HotspotCommand::ConnectedNetwork)? {
+            HotspotCommandResponse::ConnectedNetwork(connected) => connected,
+            _ => return Err(unexpected_response()),
+        };
+
+        Ok(proto::StatusResponse {
+            hotspot: Some(hotspot),
+            connected: connected.as_ref().map(proto::ConnectedNetwork::from),
+        })
+    }
+
+    fn hotspot_control(
+        &self,
+        req: proto::HotspotControlRequest,
+    ) -> RpcResult<proto::HotspotStatus> {
+        let command = match proto::HotspotAction::from_i32(req.action) {
+            Some(proto::HotspotAction::Start) => HotspotCommand::Start,
+            Some(proto::HotspotAction::Stop) => HotspotCommand::Stop,
+            None => {
+                return Err(RpcStatus::new(
+                    RpcStatusCode::INVALID_ARGUMENT,
+                    Some(format!("Unknown hotspot action {}", req.action)),
+                ))
+            }
+        };
+
+        info!("Incoming gRPC `hotspot control` request");
+
+        match self.send(command)? {
+            HotspotCommandResponse::Status(status) => Ok(proto::HotspotStatus::from(&status)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    /// Polls the event history and streams new events until the client goes
+    /// away
+    fn watch_status(
+        &self,
+        ctx: &RpcContext,
+        req: proto::WatchStatusRequest,
+        mut sink: ServerStreamingSink<proto::StatusUpdate>,
+    ) {
+        if let Err(status) = self.authorize(ctx) {
+            ctx.spawn(async_fail(sink.fail(status)));
+            return;
+        }
+
+        let service = self.clone();
+        let mut since = if req.since == 0 {
+            None
+        } else {
+            Some(req.since)
+        };
+
+        thread::spawn(move || loop {
+            let events = match service.send(HotspotCommand::Events(since)) {
+                Ok(HotspotCommandResponse::Events(events)) => events,
+                Ok(_) => Vec::new(),
+                Err(status) => {
+                    let _ = block_on(sink.fail(status));
+                    return;
+                }
+            };
+
+            for event in &events {
+                since = Some(event.id);
+
+                let update = (proto::StatusUpdate::from(event), WriteFlags::default());
+
+                if block_on(sink.send(update)).is_err() {
+                    debug!("gRPC status stream closed");
+                    return;
+                }
+            }
+
+            thread::sleep(Duration::from_secs(WATCH_INTERVAL));
+        });
+    }
+
+    /// Calls require `authorization: Bearer <token>` matching `--api-token`,
+    /// like the management HTTP API
+    fn authorize(&self, ctx: &RpcContext) -> RpcResult<()> {
+        let bearer = ctx
+            .request_headers()
+            .iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| ::std::str::from_utf8(value).ok())
+            .and_then(|value| {
+                if value.starts_with("Bearer ") {
+                    Some(value["Bearer ".len()..].trim().to_string())
+                } else {
+                    None
+                }
+            });
+
+        if is_authorized(&bearer, &self.api_token) {
+            Ok(())
+        } else {
+            warn!("Unauthorized gRPC management request");
+            Err(RpcStatus::new(RpcStatusCode::UNAUTHENTICATED, None))
+        }
+    }
+
+    fn send(&self, command: HotspotCommand) -> RpcResult<HotspotCommandResponse> {
+        match self.controller.send(command) {
+            Ok(HotspotCommandResponse::Failed(reason)) => {
+                error!("Hotspot command failed: {}", reason);
+                Err(RpcStatus::new(RpcStatusCode::INTERNAL, Some(reason)))
+            }
+            Ok(response) => Ok(response),
+            Err(e) => Err(RpcStatus::new(
+                RpcStatusCode::UNAVAILABLE,
+                Some(e.to_string()),
+            )),
+        }
+    }
+}
+
+/// Answers a unary call from its own thread, as hotspot commands like
+/// scanning block for a while
+fn respond<T, F>(ctx: &RpcContext, service: &WiFiConnectService, sink: UnarySink<T>, handle: F)
+where
+    T: Send + 'static,
+    F: FnOnce() -> RpcResult<T> + Send + 'static,
+{
+    if let Err(status) = service.authorize(ctx) {
+        ctx.spawn(async_fail(sink.fail(status)));
+        return;
+    }
+
+    thread::spawn(move || {
+        let sent = match handle() {
+            Ok(response) => block_on(sink.success(response)),
+            Err(status) => block_on(sink.fail(status)),
+        };
+
+        if let Err(e) = sent {
+            warn!("Sending gRPC response failed: {}", e);
+        }
+    });
+}
+
+fn async_fail<F>(fail: F) -> impl ::futures::Future<Output = ()>
+where
+    F: ::futures::Future<Output = ::grpcio::Result<()>>,
+{
+    use futures::FutureExt;
+
+    fail.map(|sent| {
+        if let Err(e) = sent {
+            warn!("Sending gRPC response failed: {}", e);
+        }
+    })
+}
+
+fn validate(ssid: &str) -> RpcResult<()> {
+    validate_ssid(ssid)
+        .map_err(|e| RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e.to_string())))
+}
+
+fn unexpected_response() -> RpcStatus {
+    RpcStatus::new(
+        RpcStatusCode::INTERNAL,
+        Some("Unexpected hotspot controller response".into()),
+    )
+}
+
+impl<'a> From<&'a Network> for proto::Network {
+    fn from(network: &Network) -> Self {
+        proto::Network {
+            ssid: network.ssid.clone(),
+            ssid_hex: network.ssid_hex.clone(),
+            security: network.security.clone(),
+            signal_strength: u32::from(network.signal_strength),
+        }
+    }
+}
+
+impl<'a> From<&'a ConnectedNetwork> for proto::ConnectedNetwork {
+    fn from(connected: &ConnectedNetwork) -> Self {
+        proto::ConnectedNetwork {
+            ssid: connected.ssid.clone(),
+            security: connected.security.clone(),
+            signal_strength: u32::from(connected.signal_strength),
+            interface: connected.interface.clone(),
+            ip_address: connected.ip_address.clone().unwrap_or_default(),
+            ipv6_addresses: connected.ipv6_addresses.clone(),
+        }
+    }
+}
+
+impl<'a> From<&'a HotspotStatus> for proto::HotspotStatus {
+    fn from(status: &HotspotStatus) -> Self {
+        proto::HotspotStatus {
+            is_running: status.is_running,
+            ssid: status.ssid.clone().unwrap_or_default(),
+            gateway: status.gateway.clone().unwrap_or_default(),
+            interface: status.interface.clone().unwrap_or_default(),
+            password_protected: status.password_protected,
+        }
+    }
+}
+
+impl<'a> From<&'a Event> for proto::StatusUpdate {
+    fn from(event: &Event) -> Self {
+        let mut update = proto::StatusUpdate {
+            id: event.id,
+            timestamp: event.timestamp,
+            ..Default::default()
+        };
+
+        match event.kind {
+            EventKind::Transition { ref to, .. } => {
+                update.kind = "transition".into();
+                update.state = to.name().into();
+                update.ssid = to.ssid().unwrap_or_default().into();
+                update.failure = to.failure().map_or("", |failure| failure.name()).into();
+                update.reason = to.reason().unwrap_or_default().into();
+
+                if let State::Connected {
+                    ip: Some(ref ip), ..
+                } = *to
+                {
+                    update.ip = ip.clone();
+                }
+            }
+            EventKind::Error { ref message } => {
+                update.kind = "error".into();
+                update.message = message.clone();
+            }
+            EventKind::ClientJoined { ref mac, .. } => {
+                update.kind = "client-joined".into();
+                update.message = mac.clone();
+            }
+            EventKind::ClientLeft { ref mac, .. } => {
+                update.kind = "client-left".into();
+                update.message = mac.clone();
+            }
+        }
+
+        update
+    }
+}
@@ -0,0 +1,166 @@
+//! Guest hotspot, an always-on access point sharing the internet connection
+//! next to the temporary setup hotspot. It runs on its own interface, either
+//! a second radio or a virtual AP interface added to the WiFi interface of
+//! the portal, and is left to NetworkManager's shared mode for DHCP and NAT.
+
+use errors::*;
+use network::get_interface_ipv4;
+use profile::{managed_connection_id, nmcli, read_profile_properties, set_profile_secrets};
+use secret::Secret;
+use wiphy::{add_ap_interface, delete_interface, interface_exists};
+
+pub const DEFAULT_GUEST_INTERFACE: &str = "uap0";
+
+pub struct GuestHotspot {
+    ssid: String,
+    passphrase: Option<Secret>,
+    interface: String,
+    /// Whether stations on the guest network are kept from reaching each
+    /// other
+    client_isolation: bool,
+    /// Whether the virtual AP interface was added by us and is removed on
+    /// stop
+    added_interface: bool,
+}
+
+impl GuestHotspot {
+    pub fn new(
+        ssid: &str,
+        passphrase: Option<Secret>,
+        interface: &str,
+        client_isolation: bool,
+    ) -> Self {
+        GuestHotspot {
+            ssid: ssid.to_string(),
+            passphrase,
+            interface: interface.to_string(),
+            client_isolation,
+            added_interface: false,
+        }
+    }
+
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn is_password_protected(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Brings the guest access point up, adding the virtual AP interface to
+    /// `parent` if the guest interface does not exist yet
+    pub fn start(&mut self, parent: &str) -> Result<()> {
+        let ssid = self.ssid.clone();
+        let error = || ErrorKind::StartGuestHotspot(ssid.clone());
+
+        if self.is_running() {
+            return Ok(());
+        }
+
+        if !interface_exists(&self.interface) {
+            add_ap_interface(parent, &self.interface).chain_err(error)?;
+            self.added_interface = true;
+        }
+
+        let id = connection_id();
+
+        // A profile left behind by an earlier run may hold stale settings
+        nmcli(&["connection", "delete", &id]);
+
+        let mut args = vec![
+            "connection",
+            "add",
+            "type",
+            "wifi",
+            "ifname",
+            self.interface.as_str(),
+            "con-name",
+            id.as_str(),
+            "autoconnect",
+            "no",
+            "ssid",
+            self.ssid.as_str(),
+            "802-11-wireless.mode",
+            "ap",
+            "ipv4.method",
+            "shared",
+        ];
+
+        if self.client_isolation {
+            args.extend(&["802-11-wireless.ap-isolation", "yes"]);
+        }
+
+        // The passphrase is stored separately to keep it off the command line
+        if self.passphrase.is_some() {
+            args.extend(&["wifi-sec.key-mgmt", "wpa-psk"]);
+        }
+
+        if !nmcli(&args) {
+            bail!(error());
+        }
+
+        if let Some(ref passphrase) = self.passphrase {
+            let psk = ("802-11-wireless-security.psk", passphrase.expose());
+
+            if !set_profile_secrets(&["id", &id], &[psk]) {
+                bail!(error());
+            }
+        }
+
+        if !nmcli(&["connection", "up", &id]) {
+            bail!(error());
+        }
+
+        info!(
+            "Guest hotspot '{}' started on {}",
+            self.ssid, self.interface
+        );
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        let ssid = self.ssid.clone();
+        let error = || ErrorKind::StopGuestHotspot(ssid.clone());
+
+        let id = connection_id();
+
+        if self.is_running() && !nmcli(&["connection", "down", &id]) {
+            bail!(error());
+        }
+
+        nmcli(&["connection", "delete", &id]);
+
+        if self.added_interface {
+            delete_interface(&self.interface).chain_err(error)?;
+            self.added_interface = false;
+        }
+
+        info!("Guest hotspot '{}' stopped", self.ssid);
+
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        read_profile_properties(&connection_id(), &["GENERAL.STATE"])
+            .map(|properties| {
+                properties
+                    .get("GENERAL.STATE")
+                    .map_or(false, |state| state == "activated")
+            })
+            .unwrap_or(false)
+    }
+
+    /// Address NetworkManager assigned to the guest interface
+    pub fn gateway(&self) -> Option<String> {
+        get_interface_ipv4(&self.interface)
+    }
+}
+
+fn connection_id() -> String {
+    managed_connection_id("guest")
+}
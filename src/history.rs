@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use errors::*;
+use low_memory::to_json_file;
+use notifier::Notifier;
+use state::State;
+
+pub const DEFAULT_HISTORY_FILE: &str = "/var/lib/wifi-connect/history.json";
+
+/// Attempts kept before the oldest ones are dropped
+const HISTORY_SIZE: usize = 50;
+
+/// Outcome of a provisioning attempt, kept across restarts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Attempt {
+    /// Unix timestamp of the outcome
+    pub timestamp: u64,
+    pub ssid: String,
+    pub connected: bool,
+    /// Failure class like `wrong-password`, see `state::Failure`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Attempts stored in `path`, oldest first. A missing file is an empty
+/// history.
+pub fn load_history(path: &Path) -> Result<Vec<Attempt>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).chain_err(|| ErrorKind::History(path.display().to_string())),
+    };
+
+    serde_json::from_str(&contents).chain_err(|| ErrorKind::History(path.display().to_string()))
+}
+
+/// Appends `attempt` to the history in `path`, dropping the oldest attempts
+/// beyond the history size. The file is replaced atomically.
+pub fn record_attempt(path: &Path, attempt: Attempt) -> Result<()> {
+    let mut attempts = load_history(path).unwrap_or_else(|e| {
+        warn!("Discarding unreadable provisioning history: {}", e);
+        Vec::new()
+    });
+
+    attempts.push(attempt);
+
+    let excess = attempts.len().saturating_sub(HISTORY_SIZE);
+    attempts.drain(..excess);
+
+    let error = || ErrorKind::History(path.display().to_string());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(error)?;
+    }
+
+    let json = to_json_file(&attempts).chain_err(error)?;
+
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, json).chain_err(error)?;
+    fs::rename(&temporary, path).chain_err(error)?;
+
+    Ok(())
+}
+
+pub fn print_history(attempts: &[Attempt]) {
+    if attempts.is_empty() {
+        println!("No provisioning attempts recorded");
+        return;
+    }
+
+    for attempt in attempts {
+        let result = if attempt.connected {
+            "connected".to_string()
+        } else {
+            format!(
+                "failed ({})",
+                attempt.failure.as_ref().map_or("other", String::as_str)
+            )
+        };
+
+        print!(
+            "{}  {}  {}",
+            format_utc(attempt.timestamp),
+            attempt.ssid,
+            result
+        );
+
+        match attempt.reason {
+            Some(ref reason) => println!(": {}", reason),
+            None => println!(),
+        }
+    }
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`
+fn format_utc(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, second) = utc_fields(timestamp);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Year, month, day, hour, minute and second of a Unix timestamp in UTC
+pub fn utc_fields(timestamp: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days = timestamp / 86_400;
+    let seconds = timestamp % 86_400;
+
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// Records the outcome of every connection attempt in the history file
+pub struct HistoryNotifier {
+    path: PathBuf,
+}
+
+impl HistoryNotifier {
+    pub fn new(path: &Path) -> Self {
+        HistoryNotifier {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Notifier for HistoryNotifier {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn notify(&mut self, _previous: &State, current: &State) -> Result<()> {
+        let attempt = match *current {
+            State::Connected { ref ssid, .. } => Attempt {
+                timestamp: now(),
+                ssid: ssid.clone(),
+                connected: true,
+                failure: None,
+                reason: None,
+            },
+            State::Failed {
+                ref ssid,
+                ref reason,
+                failure,
+            } => Attempt {
+                timestamp: now(),
+                ssid: ssid.clone(),
+                connected: false,
+                failure: Some(failure.name().to_string()),
+                reason: Some(reason.clone()),
+            },
+            _ => return Ok(()),
+        };
+
+        record_attempt(&self.path, attempt)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
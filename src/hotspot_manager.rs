@@ -1,14 +1,68 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use network_manager::{Device, NetworkManager};
-
+use backend::{init_backend, Backend};
+use blocklist::is_blocked;
+use clients::{apply_mac_filter, spawn_client_monitor, ClientMonitor, MacFilterRules};
+use compatibility::flag_unsupported_networks;
 use config::Config;
-use dnsmasq::start_dnsmasq;
+use dnsmasq::{start_dnsmasq, Dnsmasq};
 use errors::*;
-use network::find_device;
+use events::Event;
+use exit::Shutdown;
+use firstboot::check_time_sync;
+use guest::GuestHotspot;
+use network::{
+    get_interface_address, ConnectedNetwork, Network, NetworkCredentials, SavedNetworkDetails,
+};
+use notifier::init_notifiers;
+use passpoint::PasspointProfile;
+use profile::ProfileUpdate;
+use proxy::write_proxy_file;
+use rogue::flag_rogue_access_points;
+use rotation::{
+    device_passphrase, generate_passphrase, load_passphrase, print_qr_code, store_passphrase,
+    wifi_qr_payload, DEFAULT_PASSPHRASE_LENGTH,
+};
+use secret::Secret;
+use state::{Failure, State, StateMachine};
+use vpn::{activate_vpn, vpn_status, VPN_CONNECTIVITY_TIMEOUT};
+use wireguard::enroll_wireguard;
+
+pub enum HotspotCommand {
+    Start,
+    Stop,
+    Status,
+    SavedNetwork(String),
+    UpdateNetwork(String, ProfileUpdate),
+    ConnectedNetwork,
+    Events(Option<u64>),
+    Scan,
+    Connect(NetworkCredentials),
+    Forget(String),
+    StartGuest,
+    StopGuest,
+    GuestStatus,
+    RotatePassphrase,
+    QrCode,
+    InstallPasspoint(PasspointProfile),
+}
+
+pub enum HotspotCommandResponse {
+    Status(HotspotStatus),
+    SavedNetwork(Option<SavedNetworkDetails>),
+    ConnectedNetwork(Option<ConnectedNetwork>),
+    Events(Vec<Event>),
+    Networks(Vec<Network>),
+    Forgotten(bool),
+    /// Payload of the QR code joining the hotspot
+    QrCode(String),
+    Failed(String),
+}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct HotspotStatus {
     pub is_running: bool,
     pub ssid: Option<String>,
@@ -43,24 +97,57 @@ impl HotspotStatus {
 
 pub struct HotspotManager {
     config: Config,
-    manager: NetworkManager,
-    device: Device,
-    dnsmasq_process: Option<std::process::Child>,
+    backend: Box<dyn Backend>,
+    dnsmasq_process: Option<Dnsmasq>,
+    client_monitor: Option<ClientMonitor>,
+    /// Only kept while the hotspot is up, as it would filter the frames of
+    /// the network being connected to as well
+    mac_filter_rules: Option<MacFilterRules>,
+    /// Guest hotspot with `--guest-ssid`, started and stopped independently
+    /// of the setup hotspot
+    guest: Option<GuestHotspot>,
+    state: StateMachine,
 }
 
 impl HotspotManager {
-    pub fn new(config: Config) -> Result<Self> {
-        let manager = NetworkManager::new();
-        let device = find_device(&manager, &config.interface)?;
+    pub fn new(mut config: Config) -> Result<Self> {
+        if config.rotate_passphrase.is_some() {
+            let passphrase = load_passphrase(&config.secrets).or_else(|| config.passphrase.take());
+
+            config.passphrase = match passphrase {
+                Some(passphrase) => Some(passphrase),
+                None => Some(device_passphrase(
+                    DEFAULT_PASSPHRASE_LENGTH,
+                    &config.secrets,
+                )?),
+            };
+        }
+
+        let backend = init_backend(&config)?;
+        let state = StateMachine::new(init_notifiers(&config));
+
+        let guest = config.guest_ssid.as_ref().map(|ssid| {
+            GuestHotspot::new(
+                ssid,
+                config.guest_passphrase.clone(),
+                &config.guest_interface,
+                config.client_isolation,
+            )
+        });
 
         Ok(HotspotManager {
             config,
-            manager,
-            device,
+            backend,
             dnsmasq_process: None,
+            client_monitor: None,
+            mac_filter_rules: None,
+            guest,
+            state,
         })
     }
 
+    /// Starts the access point with DHCP and the client monitor, but not the
+    /// captive portal, which only runs in the portal mode
     pub fn start_hotspot(&mut self) -> Result<()> {
         info!("Starting hotspot '{}'...", self.config.ssid);
 
@@ -70,21 +157,38 @@ impl HotspotManager {
             thread::sleep(Duration::from_secs(2));
         }
 
-        // Create the access point using NetworkManager
-        let wifi_device = self.device.as_wifi_device().unwrap();
-        let passphrase = self.config.passphrase.as_ref().map(|p| p.as_str());
-        
-        let (_connection, _state) = wifi_device.create_hotspot(
-            self.config.ssid.as_str(),
-            passphrase,
-            Some(self.config.gateway),
-        )?;
+        self.backend.start_hotspot(&self.config)?;
+
+        self.mac_filter_rules =
+            apply_mac_filter(self.backend.interface(), &self.config.mac_filter)?;
 
         // Start dnsmasq for DHCP
-        let dnsmasq = start_dnsmasq(&self.config, &self.device)?;
-        self.dnsmasq_process = Some(dnsmasq);
+        if self.backend.uses_dnsmasq() {
+            let dnsmasq = start_dnsmasq(&self.config, self.backend.interface(), |error| {
+                error!("Hotspot has no DHCP and DNS: {}", error);
+            })?;
+            self.dnsmasq_process = Some(dnsmasq);
+        }
+
+        // Kicks stations that got past the MAC filter and records joining
+        // and leaving clients
+        // in the event history
+        self.client_monitor = Some(spawn_client_monitor(
+            self.backend.interface(),
+            self.config.mac_filter.clone(),
+            self.state.events(),
+        ));
+
+        self.state.transition(State::HotspotUp {
+            ssid: self.config.ssid.clone(),
+        });
 
         info!("Hotspot '{}' started successfully", self.config.ssid);
+
+        if self.config.print_qr_code {
+            print_qr_code(&self.qr_code_payload());
+        }
+
         Ok(())
     }
 
@@ -93,23 +197,19 @@ impl HotspotManager {
 
         // Stop dnsmasq if running
         if let Some(mut dnsmasq) = self.dnsmasq_process.take() {
-            let _ = dnsmasq.kill();
-            let _ = dnsmasq.wait();
-        }
-
-        // Find and deactivate any active hotspot connections
-        let connections = self.manager.get_connections()?;
-        for connection in connections {
-            let settings = connection.settings();
-            if settings.kind == "802-11-wireless" 
-                && settings.mode == "ap" 
-                && settings.ssid.as_str().unwrap_or("") == self.config.ssid {
-                info!("Deactivating hotspot connection");
-                let _ = connection.deactivate();
-                let _ = connection.delete();
-            }
+            let _ = dnsmasq.stop();
+        }
+
+        if let Some(client_monitor) = self.client_monitor.take() {
+            client_monitor.stop();
         }
 
+        self.mac_filter_rules.take();
+
+        self.backend.stop_hotspot(&self.config)?;
+
+        self.state.transition(State::HotspotDown);
+
         info!("Hotspot stopped");
         Ok(())
     }
@@ -123,21 +223,240 @@ impl HotspotManager {
         Ok(())
     }
 
+    /// Replaces the passphrase with a random one, restarting a running
+    /// hotspot so that stations joined with the old one have to join again
+    pub fn rotate_passphrase(&mut self) -> Result<()> {
+        if self.config.rotate_passphrase.is_none() {
+            bail!(ErrorKind::PassphraseRotationDisabled);
+        }
+
+        let length = self
+            .config
+            .random_passphrase
+            .unwrap_or(DEFAULT_PASSPHRASE_LENGTH);
+
+        let passphrase = generate_passphrase(length)?;
+        store_passphrase(&passphrase, &self.config.secrets)?;
+        self.config.passphrase = Some(passphrase);
+
+        info!("Hotspot passphrase rotated");
+
+        if self.is_hotspot_running() {
+            self.restart_hotspot()?;
+        } else if self.config.print_qr_code {
+            print_qr_code(&self.qr_code_payload());
+        }
+
+        Ok(())
+    }
+
+    pub fn passphrase(&self) -> Option<&Secret> {
+        self.config.passphrase.as_ref()
+    }
+
+    pub fn qr_code_payload(&self) -> String {
+        wifi_qr_payload(&self.config.ssid, &self.config.passphrase)
+    }
+
     pub fn is_hotspot_running(&self) -> bool {
-        // Check if there's an active access point connection with our SSID
-        if let Ok(connections) = self.manager.get_connections() {
-            for connection in connections {
-                let settings = connection.settings();
-                if settings.kind == "802-11-wireless" 
-                    && settings.mode == "ap" 
-                    && settings.ssid.as_str().unwrap_or("") == self.config.ssid {
-                    if let Ok(state) = connection.get_state() {
-                        return state == network_manager::ConnectionState::Activated;
-                    }
+        self.backend.is_hotspot_running(&self.config.ssid)
+    }
+
+    pub fn has_guest_hotspot(&self) -> bool {
+        self.guest.is_some()
+    }
+
+    pub fn start_guest_hotspot(&mut self) -> Result<()> {
+        let parent = self.backend.interface().to_string();
+
+        match self.guest {
+            Some(ref mut guest) => guest.start(&parent),
+            None => bail!(ErrorKind::NoGuestHotspot),
+        }
+    }
+
+    pub fn stop_guest_hotspot(&mut self) -> Result<()> {
+        match self.guest {
+            Some(ref mut guest) => guest.stop(),
+            None => bail!(ErrorKind::NoGuestHotspot),
+        }
+    }
+
+    pub fn get_guest_hotspot_status(&self) -> Result<HotspotStatus> {
+        let guest = match self.guest {
+            Some(ref guest) => guest,
+            None => bail!(ErrorKind::NoGuestHotspot),
+        };
+
+        let status = if guest.is_running() {
+            HotspotStatus {
+                is_running: true,
+                ssid: Some(guest.ssid().to_string()),
+                gateway: guest.gateway(),
+                interface: Some(guest.interface().to_string()),
+                password_protected: guest.is_password_protected(),
+                uptime: None,
+            }
+        } else {
+            HotspotStatus {
+                is_running: false,
+                ssid: None,
+                gateway: None,
+                interface: None,
+                password_protected: false,
+                uptime: None,
+            }
+        };
+
+        Ok(status)
+    }
+
+    pub fn scan(&mut self) -> Result<Vec<Network>> {
+        let mut networks = self.backend.scan()?;
+        flag_rogue_access_points(&*self.backend, &self.config.ssid, &mut networks);
+        flag_unsupported_networks(&*self.backend, &mut networks);
+        networks.retain(|network| !is_blocked(&self.config.blocked_ssids, &network.ssid));
+        Ok(networks)
+    }
+
+    /// Connects to `network` with the configured profile settings, taking
+    /// down the hotspot first as it holds the WiFi interface
+    pub fn connect(&mut self, network: &NetworkCredentials) -> Result<Option<ConnectedNetwork>> {
+        let ssid = network.ssid.clone();
+
+        if is_blocked(&self.config.blocked_ssids, &ssid) {
+            bail!(ErrorKind::BlockedSsid(ssid));
+        }
+
+        self.state
+            .transition(State::CredentialsReceived { ssid: ssid.clone() });
+
+        if self.is_hotspot_running() {
+            self.stop_hotspot()?;
+        }
+
+        let mut settings = self.config.profile_settings.clone();
+        settings.proxy_pac_url = network
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.pac_url.clone());
+
+        if let Err(e) = self.backend.connect(network, &settings) {
+            self.state.transition(State::Failed {
+                ssid,
+                reason: e.to_string(),
+                failure: Failure::from(e.kind()),
+            });
+            return Err(e);
+        }
+
+        if let Err(e) = write_proxy_file(&self.config.proxy_file, &network.proxy) {
+            warn!("{}", e);
+        }
+
+        // The network only counts as connected once the tunnel is up
+        if let Some(ref config) = network.wireguard {
+            if let Err(e) = enroll_wireguard(config) {
+                if let Err(e) = self.backend.disconnect() {
+                    warn!("Disconnecting from '{}' failed: {}", ssid, e);
+                }
+
+                if let Err(e) = write_proxy_file(&self.config.proxy_file, &None) {
+                    warn!("{}", e);
                 }
+
+                self.state.transition(State::Failed {
+                    ssid,
+                    reason: e.to_string(),
+                    failure: Failure::from(e.kind()),
+                });
+                return Err(e);
+            }
+        }
+
+        let clock_synchronized = self
+            .config
+            .require_time_sync
+            .map(|timeout| check_time_sync(timeout, &Shutdown::new()));
+
+        self.state.transition(State::Connected {
+            ssid,
+            ip: get_interface_address(self.backend.interface()),
+            clock_synchronized,
+        });
+
+        if let Some(ref name) = self.config.activate_vpn {
+            self.activate_vpn(name);
+        }
+
+        self.connected_network()
+    }
+
+    /// Activates the VPN once the network has Internet connectivity, a
+    /// failure leaves the WiFi connection in place
+    fn activate_vpn(&self, name: &str) {
+        match self.backend.wait_for_connectivity(VPN_CONNECTIVITY_TIMEOUT) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(
+                    "No Internet connectivity, not activating the VPN connection '{}'",
+                    name
+                );
+                return;
             }
+            Err(e) => {
+                warn!("Getting Internet connectivity failed: {}", e);
+                return;
+            }
+        }
+
+        if let Err(e) = activate_vpn(name) {
+            warn!("{}", e);
+            self.state.record_error(e.to_string());
         }
-        false
+    }
+
+    pub fn forget_network(&self, ssid: &str) -> Result<bool> {
+        self.backend.forget_network(ssid)
+    }
+
+    /// Event history after the event with ID `since`
+    pub fn events(&self, since: Option<u64>) -> Vec<Event> {
+        self.state.events().lock().unwrap().since(since)
+    }
+
+    /// The connected network, with the status of the `--activate-vpn`
+    /// connection
+    pub fn connected_network(&self) -> Result<Option<ConnectedNetwork>> {
+        Ok(self.backend.connected_network()?.map(|mut connected| {
+            connected.vpn = self
+                .config
+                .activate_vpn
+                .as_ref()
+                .map(|name| vpn_status(name));
+            connected
+        }))
+    }
+
+    pub fn install_passpoint(&self, profile: &PasspointProfile) -> Result<()> {
+        self.backend.install_passpoint(profile)
+    }
+
+    pub fn saved_network_details(&self, ssid: &str) -> Result<Option<SavedNetworkDetails>> {
+        self.backend.saved_network_details(ssid)
+    }
+
+    /// Details of the network after the update, if it is saved
+    pub fn update_network(
+        &self,
+        ssid: &str,
+        update: &ProfileUpdate,
+    ) -> Result<Option<SavedNetworkDetails>> {
+        if !self.backend.update_network(ssid, update)? {
+            return Ok(None);
+        }
+
+        self.backend.saved_network_details(ssid)
     }
 
     pub fn get_hotspot_status(&self) -> HotspotStatus {
@@ -148,7 +467,7 @@ impl HotspotManager {
                 is_running: true,
                 ssid: Some(self.config.ssid.clone()),
                 gateway: Some(self.config.gateway.to_string()),
-                interface: Some(self.device.interface().to_string()),
+                interface: Some(self.backend.interface().to_string()),
                 password_protected: self.config.passphrase.is_some(),
                 uptime: None, // Could be implemented by tracking start time
             }
@@ -169,8 +488,109 @@ impl Drop for HotspotManager {
     fn drop(&mut self) {
         // Ensure cleanup when the manager is dropped
         if let Some(mut dnsmasq) = self.dnsmasq_process.take() {
-            let _ = dnsmasq.kill();
-            let _ = dnsmasq.wait();
+            let _ = dnsmasq.stop();
+        }
+    }
+}
+/// Handle to the hotspot controller thread, shared by the management
+/// servers. Every command is answered before the next one is sent, so the
+/// command and response channels are locked together.
+#[derive(Clone)]
+pub struct HotspotController {
+    channels: Arc<Mutex<(Sender<HotspotCommand>, Receiver<HotspotCommandResponse>)>>,
+}
+
+impl HotspotController {
+    pub fn send(&self, command: HotspotCommand) -> Result<HotspotCommandResponse> {
+        let channels = self.channels.lock().unwrap();
+
+        channels
+            .0
+            .send(command)
+            .chain_err(|| ErrorKind::SendHotspotCommand)?;
+
+        Ok(channels.1.recv()?)
+    }
+}
+
+/// Runs a `HotspotManager` on a dedicated thread, as the NetworkManager
+/// connection cannot be shared with the HTTP handlers directly
+pub fn spawn_hotspot_controller(config: Config) -> HotspotController {
+    let (hotspot_tx, hotspot_rx) = channel();
+    let (response_tx, response_rx) = channel();
+
+    thread::spawn(move || {
+        let mut hotspot = match HotspotManager::new(config) {
+            Ok(hotspot) => hotspot,
+            Err(e) => {
+                error!("Initializing hotspot controller failed: {}", e);
+                return;
+            }
+        };
+
+        for command in hotspot_rx {
+            let result = match command {
+                HotspotCommand::Start => hotspot.start_hotspot().map(|()| None),
+                HotspotCommand::Stop => hotspot.stop_hotspot().map(|()| None),
+                HotspotCommand::Status => Ok(None),
+                HotspotCommand::SavedNetwork(ssid) => hotspot
+                    .saved_network_details(&ssid)
+                    .map(|details| Some(HotspotCommandResponse::SavedNetwork(details))),
+                HotspotCommand::UpdateNetwork(ssid, update) => hotspot
+                    .update_network(&ssid, &update)
+                    .map(|details| Some(HotspotCommandResponse::SavedNetwork(details))),
+                HotspotCommand::ConnectedNetwork => hotspot
+                    .connected_network()
+                    .map(|connected| Some(HotspotCommandResponse::ConnectedNetwork(connected))),
+                HotspotCommand::Events(since) => {
+                    Ok(Some(HotspotCommandResponse::Events(hotspot.events(since))))
+                }
+                HotspotCommand::Scan => hotspot
+                    .scan()
+                    .map(|networks| Some(HotspotCommandResponse::Networks(networks))),
+                HotspotCommand::Connect(network) => hotspot
+                    .connect(&network)
+                    .map(|connected| Some(HotspotCommandResponse::ConnectedNetwork(connected))),
+                HotspotCommand::Forget(ssid) => hotspot
+                    .forget_network(&ssid)
+                    .map(|forgotten| Some(HotspotCommandResponse::Forgotten(forgotten))),
+                HotspotCommand::StartGuest => hotspot
+                    .start_guest_hotspot()
+                    .and_then(|()| hotspot.get_guest_hotspot_status())
+                    .map(|status| Some(HotspotCommandResponse::Status(status))),
+                HotspotCommand::StopGuest => hotspot
+                    .stop_guest_hotspot()
+                    .and_then(|()| hotspot.get_guest_hotspot_status())
+                    .map(|status| Some(HotspotCommandResponse::Status(status))),
+                HotspotCommand::GuestStatus => hotspot
+                    .get_guest_hotspot_status()
+                    .map(|status| Some(HotspotCommandResponse::Status(status))),
+                HotspotCommand::RotatePassphrase => hotspot.rotate_passphrase().map(|()| None),
+                HotspotCommand::QrCode => Ok(Some(HotspotCommandResponse::QrCode(
+                    hotspot.qr_code_payload(),
+                ))),
+                HotspotCommand::InstallPasspoint(profile) => {
+                    hotspot.install_passpoint(&profile).map(|()| None)
+                }
+            };
+
+            // Hotspot commands answer with the resulting status
+            let response = match result {
+                Ok(Some(response)) => response,
+                Ok(None) => HotspotCommandResponse::Status(hotspot.get_hotspot_status()),
+                Err(e) => {
+                    hotspot.state.record_error(e.to_string());
+                    HotspotCommandResponse::Failed(e.to_string())
+                }
+            };
+
+            if response_tx.send(response).is_err() {
+                break;
+            }
         }
+    });
+
+    HotspotController {
+        channels: Arc::new(Mutex::new((hotspot_tx, response_rx))),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,97 @@
+use std::process::Command;
+
+/// Addresses, default routes and DNS servers of one address family, as
+/// configured by NetworkManager in the IP config object of the device
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IpConfig {
+    /// Addresses with their prefix length
+    pub addresses: Vec<String>,
+    /// Next hops of the default routes
+    pub gateways: Vec<String>,
+    pub dns: Vec<String>,
+}
+
+/// Reads the IPv4 and IPv6 config of `interface` from
+/// `nmcli device show`. Both are empty if the device is not managed by
+/// NetworkManager.
+pub fn get_ip_configs(interface: &str) -> (IpConfig, IpConfig) {
+    let output = Command::new("nmcli")
+        .args(&["-t", "-f", "IP4,IP6", "device", "show", interface])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+
+    let mut ipv4 = IpConfig::default();
+    let mut ipv6 = IpConfig::default();
+
+    for line in output.lines() {
+        let mut parts = line.splitn(2, ':');
+
+        let (field, value) = match (parts.next(), parts.next()) {
+            (Some(field), Some(value)) if !value.is_empty() => (field, unescape(value)),
+            _ => continue,
+        };
+
+        // Multiple values are numbered, e.g. `IP4.ADDRESS[1]`
+        let field = field.split('[').next().unwrap_or(field);
+
+        let (config, property) = if field.starts_with("IP4.") {
+            (&mut ipv4, &field[4..])
+        } else if field.starts_with("IP6.") {
+            (&mut ipv6, &field[4..])
+        } else {
+            continue;
+        };
+
+        match property {
+            "ADDRESS" => config.addresses.push(value),
+            "DNS" => config.dns.push(value),
+            "ROUTE" => {
+                if let Some(gateway) = default_route_gateway(&value) {
+                    if !config.gateways.contains(&gateway) {
+                        config.gateways.push(gateway);
+                    }
+                }
+            }
+            "GATEWAY" => {
+                if !config.gateways.contains(&value) {
+                    config.gateways.insert(0, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (ipv4, ipv6)
+}
+
+/// Next hop of a route like `dst = 0.0.0.0/0, nh = 192.168.1.1, mt = 600`,
+/// if it is a default route
+fn default_route_gateway(route: &str) -> Option<String> {
+    let mut destination = None;
+    let mut next_hop = None;
+
+    for attribute in route.split(',') {
+        let mut parts = attribute.splitn(2, '=');
+
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some("dst"), Some(value)) => destination = Some(value),
+            (Some("nh"), Some(value)) => next_hop = Some(value),
+            _ => {}
+        }
+    }
+
+    match (destination, next_hop) {
+        (Some("0.0.0.0/0"), Some(next_hop)) | (Some("::/0"), Some(next_hop))
+            if next_hop != "0.0.0.0" && next_hop != "::" =>
+        {
+            Some(next_hop.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Terse `nmcli` output escapes colons in values, e.g. in IPv6 addresses
+fn unescape(value: &str) -> String {
+    value.replace("\\:", ":").replace("\\\\", "\\")
+}
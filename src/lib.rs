@@ -0,0 +1,143 @@
+//! WiFi Connect as a library, for daemons that want to embed WiFi
+//! provisioning instead of running the `wifi-connect` binary.
+//!
+//! The captive portal flow is available through [`run_portal`], while the
+//! individual building blocks (backends, hotspot control, dnsmasq and the
+//! portal HTTP server) are exposed through their modules.
+
+#![recursion_limit = "1024"]
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate serde_derive;
+
+#[macro_use]
+extern crate clap;
+
+#[macro_use]
+extern crate serde_json;
+
+#[cfg(feature = "grpc")]
+extern crate futures;
+#[cfg(feature = "grpc")]
+extern crate grpcio;
+#[cfg(feature = "grpc")]
+extern crate prost;
+#[cfg(feature = "grpc")]
+#[macro_use]
+extern crate prost_derive;
+
+extern crate env_logger;
+#[cfg(feature = "compression")]
+extern crate flate2;
+extern crate hmac;
+extern crate iron;
+extern crate iron_cors;
+extern crate mount;
+#[cfg(feature = "network-manager")]
+extern crate network_manager;
+extern crate nix;
+extern crate params;
+extern crate persistent;
+extern crate router;
+extern crate serde;
+extern crate sha2;
+extern crate staticfile;
+
+#[macro_use]
+pub mod errors;
+
+pub mod access_log;
+pub mod assets;
+pub mod backend;
+pub mod blocklist;
+pub mod capport;
+pub mod certificates;
+pub mod channels;
+pub mod checkpoint;
+pub mod clients;
+pub mod coap;
+pub mod compatibility;
+pub mod config;
+#[cfg(feature = "network-manager")]
+pub mod connections;
+pub mod credentials;
+pub mod dbus;
+pub mod desired_state;
+pub mod dnsmasq;
+pub mod doctor;
+pub mod dualband;
+pub mod encryption;
+pub mod events;
+pub mod exit;
+pub mod fields;
+pub mod firstboot;
+pub mod gc;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod guest;
+pub mod history;
+pub mod hotspot_manager;
+pub mod ipconfig;
+pub mod link;
+pub mod logger;
+pub mod low_memory;
+pub mod manual;
+pub mod mqtt;
+pub mod network;
+#[cfg(feature = "network-manager")]
+pub mod nm;
+pub mod notifier;
+pub mod openapi;
+pub mod passphrase_policy;
+pub mod passpoint;
+pub mod pidfile;
+pub mod ping;
+pub mod ports;
+pub mod privileges;
+pub mod profile;
+pub mod proxy;
+pub mod redirect;
+pub mod remote_state;
+pub mod retry;
+pub mod rogue;
+pub mod rotation;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
+pub mod secret;
+pub mod server;
+pub mod signing;
+pub mod sim;
+pub mod speedtest;
+pub mod ssdp;
+pub mod ssid;
+pub mod state;
+pub mod survey;
+pub mod timings;
+pub mod vpn;
+pub mod watchdog;
+pub mod webhook;
+pub mod wiphy;
+pub mod wireguard;
+
+use std::sync::mpsc::channel;
+use std::thread;
+
+pub use config::{get_config, Config};
+pub use errors::{Error, ErrorKind, Result};
+
+/// Runs the captive portal until a network is connected, the activity
+/// timeout elapses or the process receives an exit signal
+pub fn run_portal(config: Config) -> Result<()> {
+    network::init_networking(&config)?;
+
+    let (exit_tx, exit_rx) = channel();
+
+    thread::spawn(move || {
+        network::process_network_commands(&config, &exit_tx);
+    });
+
+    exit_rx.recv()?
+}
@@ -0,0 +1,61 @@
+use std::process::Command;
+use std::str::FromStr;
+
+/// Link quality of an associated interface as reported by the driver over
+/// nl80211. Fields the driver does not report are left empty.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LinkStatistics {
+    /// Receive bitrate in MBit/s
+    pub rx_bitrate: Option<f64>,
+    /// Transmit bitrate in MBit/s
+    pub tx_bitrate: Option<f64>,
+    /// Transmit power in dBm
+    pub tx_power: Option<f64>,
+    /// Frequency of the associated channel in MHz
+    pub frequency: Option<u32>,
+    /// Signal of the associated access point in dBm
+    pub signal: Option<i32>,
+    /// Retransmitted frames since association
+    pub tx_retries: Option<u64>,
+    /// Frames that failed after all retransmissions
+    pub tx_failed: Option<u64>,
+}
+
+/// Reads the link statistics of `interface` from `iw dev <interface> link`,
+/// `station dump` and `info`
+pub fn get_link_statistics(interface: &str) -> LinkStatistics {
+    let link = iw_dev(interface, &["link"]);
+    let station = iw_dev(interface, &["station", "dump"]);
+    let info = iw_dev(interface, &["info"]);
+
+    LinkStatistics {
+        rx_bitrate: parse_field(&link, "rx bitrate:"),
+        tx_bitrate: parse_field(&link, "tx bitrate:"),
+        tx_power: parse_field(&info, "txpower"),
+        frequency: parse_field::<f64>(&link, "freq:").map(|frequency| frequency as u32),
+        signal: parse_field(&link, "signal:"),
+        tx_retries: parse_field(&station, "tx retries:"),
+        tx_failed: parse_field(&station, "tx failed:"),
+    }
+}
+
+/// Output of an `iw dev` command, empty when it cannot be run
+fn iw_dev(interface: &str, command: &[&str]) -> String {
+    Command::new("iw")
+        .args(&["dev", interface])
+        .args(command)
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+/// First value following `key` at the start of a line, e.g. `65.0` for
+/// `rx bitrate: 65.0 MBit/s MCS 7`
+fn parse_field<T: FromStr>(output: &str, key: &str) -> Option<T> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(key))
+        .next()
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|value| value.parse::<T>().ok())
+}
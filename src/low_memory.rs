@@ -0,0 +1,33 @@
+//! `--low-memory` profile for routers with 128 MB of RAM or less, trading
+//! speed for a smaller footprint: fewer HTTP threads, nothing kept between
+//! requests that can be listed again from NetworkManager, UI assets streamed
+//! from flash instead of compressed in memory and files written as compact
+//! JSON.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+use serde_json;
+
+/// HTTP connections served at once with `--low-memory`
+pub const LOW_MEMORY_HTTP_CONNECTIONS: &str = "4";
+
+static LOW_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// Switches to the low memory profile, from `--low-memory`
+pub fn set_low_memory(enabled: bool) {
+    LOW_MEMORY.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_low_memory() -> bool {
+    LOW_MEMORY.load(Ordering::SeqCst)
+}
+
+/// JSON of the files kept on disk, indented unless saving memory
+pub fn to_json_file<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    if is_low_memory() {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    }
+}
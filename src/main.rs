@@ -1,51 +1,52 @@
-#![recursion_limit = "1024"]
-
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate error_chain;
-
-#[macro_use]
-extern crate serde_derive;
+#[cfg(feature = "network-manager")]
+extern crate network_manager;
+extern crate nix;
 
 #[macro_use]
-extern crate clap;
+extern crate wifi_connect;
 
-extern crate env_logger;
-extern crate iron;
-extern crate iron_cors;
-extern crate mount;
-extern crate network_manager;
-extern crate nix;
-extern crate params;
-extern crate persistent;
-extern crate router;
-extern crate serde_json;
-extern crate staticfile;
-
-mod config;
-mod dnsmasq;
-mod errors;
-mod exit;
-mod logger;
-mod network;
-mod privileges;
-mod server;
-mod hotspot_manager;
-
-use std::io::Write;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
 use std::process;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time::Duration;
 
-use config::get_config;
-use errors::*;
-use exit::block_exit_signals;
-use hotspot_manager::HotspotManager;
-use network::{init_networking, process_network_commands};
-use privileges::require_root;
+use nix::libc::STDIN_FILENO;
+use nix::unistd::isatty;
+
+use wifi_connect::backend::{
+    self, init_backend, request_and_wait_for_scan, Backend, ForgetFilter, ForgetScope,
+};
+use wifi_connect::blocklist::update_blocklist;
+use wifi_connect::config::{self, get_config};
+use wifi_connect::doctor;
+use wifi_connect::encryption::{self, SecretsKey};
+use wifi_connect::errors::{self, *};
+use wifi_connect::exit::{self, block_exit_signals};
+#[cfg(feature = "grpc")]
+use wifi_connect::grpc;
+use wifi_connect::history;
+use wifi_connect::hotspot_manager::{
+    spawn_hotspot_controller, HotspotCommand, HotspotCommandResponse, HotspotController,
+    HotspotManager,
+};
+use wifi_connect::network::NetworkCredentials;
+#[cfg(feature = "network-manager")]
+use wifi_connect::nm;
+use wifi_connect::pidfile::{self, PidFile};
+use wifi_connect::privileges::{require_privileges, PrivilegeReport};
+#[cfg(target_os = "linux")]
+use wifi_connect::sandbox;
+use wifi_connect::secret::Secret;
+use wifi_connect::{
+    channels, checkpoint, desired_state, firstboot, gc, logger, low_memory, passpoint, ping, proxy,
+    remote_state, retry, rotation, run_portal, server, sim, ssdp, survey, vpn, watchdog,
+};
 
 fn main() {
     if let Err(ref e) = run() {
@@ -67,19 +68,70 @@ fn run() -> Result<()> {
 
     logger::init();
 
-    let config = get_config();
+    let mut config = get_config();
 
-    require_root()?;
+    retry::set_nm_retries(config.nm_retries);
+    low_memory::set_low_memory(config.low_memory);
 
-    // Handle hotspot management commands first
-    if config.start_hotspot {
-        return handle_start_hotspot(config);
+    if config.check_privileges {
+        return handle_check_privileges(&config);
+    }
+
+    if config.doctor {
+        return handle_doctor(&config);
+    }
+
+    if let Some(ref host) = config.ping {
+        return handle_ping(host);
+    }
+
+    if config.history {
+        history::print_history(&history::load_history(&config.history_file)?);
+        return Ok(());
+    }
+
+    if let Some(ref key) = config.secrets.key {
+        if config.encrypt_passphrase {
+            return handle_encrypt_passphrase(key);
+        }
     }
 
+    require_privileges(&config)?;
+
+    #[cfg(feature = "network-manager")]
+    {
+        if config.backend == backend::DEFAULT_BACKEND {
+            nm::start_network_manager_service(&config)?;
+        }
+    }
+
+    if config.dry_run {
+        return handle_dry_run(&config);
+    }
+
+    config.blocked_ssids = update_blocklist(
+        &config.blocklist_file,
+        &config.block_ssids,
+        &config.unblock_ssids,
+    )?;
+
+    // Stopping goes through the instance owning the PID file, so handle it
+    // before trying to acquire the lock ourselves
     if config.stop_hotspot {
         return handle_stop_hotspot(config);
     }
 
+    // Only modes that bring up the access point need exclusive access
+    let _pidfile = match config.pidfile {
+        Some(ref path) if manages_interface(&config) => Some(PidFile::acquire(path)?),
+        _ => None,
+    };
+
+    // Handle hotspot management commands first
+    if config.start_hotspot {
+        return handle_start_hotspot(config);
+    }
+
     if config.check_hotspot {
         return handle_check_hotspot(config);
     }
@@ -88,17 +140,56 @@ fn run() -> Result<()> {
         return handle_restart_hotspot(config);
     }
 
+    if config.api_server {
+        return handle_api_server(config);
+    }
+
+    if let Some(settings) = config.watchdog.clone() {
+        return handle_watchdog(config, &settings);
+    }
+
     // Handle existing WiFi management commands
     if config.forget_all {
-        let manager = network_manager::NetworkManager::new();
-        network::forget_all_wifi_connections(&manager)?;
-        info!("All WiFi networks have been forgotten");
+        let mut backend = init_backend(&config)?;
+
+        if !config.yes && !confirm_forget_all(&*backend, &config.forget_filter)? {
+            info!("No WiFi networks have been forgotten");
+            return Ok(());
+        }
+
+        match config.with_rollback {
+            Some(timeout) => checkpoint::with_rollback(&mut *backend, timeout, |backend| {
+                backend.forget_all(&config.forget_filter)
+            })?,
+            None => backend.forget_all(&config.forget_filter)?,
+        }
+
+        if config.forget_filter.except.is_empty() && config.forget_filter.older_than.is_none() {
+            info!("All WiFi networks have been forgotten");
+        } else {
+            info!("WiFi networks matching the filters have been forgotten");
+        }
+        return Ok(());
+    }
+
+    if config.forget_managed {
+        let backend = init_backend(&config)?;
+        let forgotten = backend.forget_managed()?;
+        info!(
+            "{} WiFi networks saved by WiFi Connect have been forgotten",
+            forgotten
+        );
+        return Ok(());
+    }
+
+    if config.gc_connections {
+        gc::gc_connections(&config)?;
         return Ok(());
     }
 
     if let Some(ref ssid) = config.forget_network {
-        let manager = network_manager::NetworkManager::new();
-        let found = network::forget_specific_network(&manager, ssid)?;
+        let backend = init_backend(&config)?;
+        let found = backend.forget_network(ssid)?;
         if found {
             info!("WiFi network '{}' has been forgotten", ssid);
         } else {
@@ -107,20 +198,78 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(ref ssid) = config.show_network {
+        return handle_show_network(&config, ssid);
+    }
+
+    if let Some((ref ssid, ref update)) = config.update_network {
+        let backend = init_backend(&config)?;
+        if backend.update_network(ssid, update)? {
+            info!(
+                "WiFi network '{}' has been updated, changes apply on its next activation",
+                ssid
+            );
+        } else {
+            info!("WiFi network '{}' was not found in saved connections", ssid);
+        }
+        return Ok(());
+    }
+
+    if let Some(ref path) = config.apply_state {
+        let mut backend = init_backend(&config)?;
+        let state = desired_state::load_desired_state(path, &config.secrets)?;
+        let drift = desired_state::apply_desired_state(&mut *backend, &state)?;
+        desired_state::print_drift(&drift);
+        return Ok(());
+    }
+
+    if let Some(ref state_url) = config.state_url {
+        let mut backend = init_backend(&config)?;
+        let state = remote_state::fetch_desired_state(state_url, &config.secrets)?;
+        let drift = desired_state::apply_desired_state(&mut *backend, &state)?;
+        desired_state::print_drift(&drift);
+        return Ok(());
+    }
+
+    if let Some(ref path) = config.show_drift {
+        let backend = init_backend(&config)?;
+        let state = desired_state::load_desired_state(path, &config.secrets)?;
+        desired_state::print_drift(&desired_state::find_drift(&*backend, &state)?);
+        return Ok(());
+    }
+
+    if let Some(ref path) = config.install_passpoint {
+        let backend = init_backend(&config)?;
+        let profile = passpoint::load_passpoint_profile(path, &config.secrets)?;
+        backend.install_passpoint(&profile)?;
+        info!("Passpoint profile '{}' has been installed", profile.name);
+        return Ok(());
+    }
+
+    if config.list_interfaces {
+        return handle_list_interfaces(&config);
+    }
+
+    if config.channel_report {
+        return handle_channel_report(&config);
+    }
+
+    if config.survey {
+        return handle_survey(&config);
+    }
+
+    if config.list_networks && config.all_interfaces {
+        return handle_list_networks_all(&config);
+    }
+
     if config.list_networks {
-        let manager = network_manager::NetworkManager::new();
-        let device = network::find_device(&manager, &config.interface)?;
-        
-        // Force a scan for networks
-        if let Some(wifi_device) = device.as_wifi_device() {
-            info!("Scanning for WiFi networks...");
-            if let Err(e) = wifi_device.request_scan() {
-                warn!("Failed to request scan: {}", e);
-            }
-            // Wait a bit for the scan to complete
-            thread::sleep(Duration::from_secs(2));
+        let mut backend = init_backend(&config)?;
+
+        info!("Scanning for WiFi networks...");
+        if request_and_wait_for_scan(&*backend, config.scan_timeout, exit::wait_exit_signal) {
+            return Ok(());
         }
-        let networks = network::get_networks(&device, &config.ssid);
+        let networks = backend.scan().unwrap_or_default();
         
         println!("\nAvailable WiFi Networks:");
         println!("----------------------");
@@ -128,38 +277,44 @@ fn run() -> Result<()> {
             println!("No networks found. Please try again.");
         } else {
             for network in networks {
-                println!("SSID: {}, Security: {}", network.ssid, network.security);
+                println!(
+                    "SSID: {}, Hex: {}, Security: {}",
+                    network.ssid, network.ssid_hex, network.security
+                );
             }
         }
         return Ok(());
     }
 
     if config.list_connected {
-        let manager = network_manager::NetworkManager::new();
-        match network::get_connected_network(&manager, &config.interface) {
-            Ok(Some(connected)) => {
-                println!("Connected Network:");
-                println!("SSID: {}, Security: {}, Signal: {}%, Interface: {}, IP: {}", 
-                         connected.ssid, 
-                         connected.security, 
-                         connected.signal_strength,
-                         connected.interface,
-                         connected.ip_address.unwrap_or_else(|| "N/A".to_string()));
-            }
-            Ok(None) => {
-                println!("No network connected");
-            }
-            Err(e) => {
-                error!("Failed to get connected network: {}", e);
-                return Err(e);
+        let backend = init_backend(&config)?;
+
+        if let Some(interval) = config.watch {
+            return watch_connected_network(&*backend, interval);
+        }
+
+        return print_connected_network(&*backend);
+    }
+
+    if config.list_managed {
+        let backend = init_backend(&config)?;
+        let managed_networks = backend.managed_networks()?;
+
+        println!("\nWiFi Networks Saved by WiFi Connect:");
+        println!("------------------------------------");
+        if managed_networks.is_empty() {
+            println!("No networks saved by WiFi Connect found.");
+        } else {
+            for network in managed_networks {
+                println!("SSID: {}", network.ssid);
             }
         }
         return Ok(());
     }
 
     if config.list_saved {
-        let manager = network_manager::NetworkManager::new();
-        let saved_networks = network::get_saved_networks(&manager)?;
+        let backend = init_backend(&config)?;
+        let saved_networks = backend.saved_networks()?;
         
         println!("\nSaved WiFi Networks:");
         println!("-------------------");
@@ -174,68 +329,54 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
-    if let Some((ssid, passphrase)) = config.connect {
-        let manager = network_manager::NetworkManager::new();
-        let device = network::find_device(&manager, &config.interface)?;
-        let access_points = network::get_access_points(&device, "")?;
-        
-        if let Some(access_point) = network::find_access_point(&access_points, &ssid) {
-            let wifi_device = device.as_wifi_device().unwrap();
-            let credentials = network::init_access_point_credentials(access_point, "", &passphrase);
-            
-            info!("Connecting to '{}'...", ssid);
-            match wifi_device.connect(access_point, &credentials) {
-                Ok((connection, state)) => {
-                    if state == network_manager::ConnectionState::Activated {
-                        match network::wait_for_connectivity(&manager, 20) {
-                            Ok(has_connectivity) => {
-                                if has_connectivity {
-                                    info!("Successfully connected to '{}'", ssid);
-                                } else {
-                                    warn!("Connected to '{}' but no internet connectivity", ssid);
-                                }
-                            }
-                            Err(err) => error!("Getting Internet connectivity failed: {}", err),
-                        }
-                    } else {
-                        warn!("Failed to connect to '{}': {:?}", ssid, state);
-                    }
-                }
-                Err(e) => error!("Error connecting to '{}': {}", ssid, e),
-            }
-        } else {
-            error!("Network '{}' not found", ssid);
-        }
-        return Ok(());
+    if let Some((ssid, passphrase)) = config.connect.clone() {
+        let mut backend = init_backend(&config)?;
+        backend.scan()?;
+
+        return match config.with_rollback {
+            Some(timeout) => checkpoint::with_rollback(&mut *backend, timeout, |backend| {
+                handle_connect(&config, backend, &ssid, passphrase)
+            }),
+            None => handle_connect(&config, &mut *backend, &ssid, passphrase),
+        };
     }
 
         // Handle disconnect command
     if config.disconnect {
-        let manager = network_manager::NetworkManager::new();
-        match network::disconnect_from_network(&manager, &config.interface) {
+        let mut backend = init_backend(&config)?;
+        match backend.disconnect() {
             Ok(_) => info!("Disconnected successfully."),
             Err(e) => error!("Failed to disconnect: {}", e),
         }
         return Ok(());
     }
 
-    // If no specific commands, fall back to original captive portal mode
-    init_networking(&config)?;
+    #[cfg(feature = "network-manager")]
+    {
+        if config.prefer_wifi_over_wwan && config.backend == backend::DEFAULT_BACKEND {
+            let manager = network_manager::NetworkManager::new();
 
-    let (exit_tx, exit_rx) = channel();
+            if nm::is_wifi_connected(&manager)? {
+                info!("WiFi is already connected, not starting the captive portal");
+                return Ok(());
+            }
 
-    thread::spawn(move || {
-        process_network_commands(&config, &exit_tx);
-    });
+            if nm::get_modems(&manager)?.iter().any(|modem| modem.connected) {
+                info!("Cellular modem is connected, not starting the captive portal");
+                return Ok(());
+            }
+        }
+    }
 
-    match exit_rx.recv() {
-        Ok(result) => result?,
-        Err(e) => {
-            return Err(e.into());
+    #[cfg(target_os = "linux")]
+    {
+        if config.sandbox {
+            sandbox::apply_sandbox(&config)?;
         }
     }
 
-    Ok(())
+    // If no specific commands, fall back to original captive portal mode
+    run_portal(config)
 }
 
 // New hotspot management functions
@@ -279,8 +420,200 @@ fn handle_start_hotspot(config: config::Config) -> Result<()> {
     Ok(())
 }
 
+fn handle_connect(
+    config: &config::Config,
+    backend: &mut dyn Backend,
+    ssid: &str,
+    passphrase: Secret,
+) -> Result<()> {
+    let network = NetworkCredentials {
+        ssid: ssid.to_string(),
+        identity: String::new(),
+        passphrase,
+        proxy: config.connect_proxy.clone(),
+        wireguard: None,
+    };
+
+    let mut settings = config.profile_settings.clone();
+    settings.proxy_pac_url = network
+        .proxy
+        .as_ref()
+        .and_then(|proxy| proxy.pac_url.clone());
+
+    info!("Connecting to '{}'...", ssid);
+    let connected = match config.connect_eap_sim {
+        Some(method) => {
+            sim::connect_eap_sim(backend, ssid, method, &settings, config.connect_timeout)
+        }
+        None => backend.connect(&network, &settings),
+    };
+
+    match connected {
+        Ok(()) => {
+            if let Err(e) = proxy::write_proxy_file(&config.proxy_file, &network.proxy) {
+                warn!("{}", e);
+            }
+
+            match backend.wait_for_connectivity(config.connect_timeout) {
+                Ok(has_connectivity) => {
+                    if has_connectivity {
+                        info!("Successfully connected to '{}'", ssid);
+
+                        if let Some(ref name) = config.activate_vpn {
+                            if let Err(e) = vpn::activate_vpn(name) {
+                                error!("{}", e);
+                            }
+                        }
+                    } else {
+                        warn!("Connected to '{}' but no internet connectivity", ssid);
+                    }
+                }
+                Err(err) => error!("Getting Internet connectivity failed: {}", err),
+            }
+
+            if let Some(timeout) = config.require_time_sync {
+                if !firstboot::check_time_sync(timeout, &exit::Shutdown::new()) {
+                    bail!(ErrorKind::ClockUnsynchronized(timeout));
+                }
+            }
+        }
+        Err(e) => match *e.kind() {
+            ErrorKind::NetworkNotFound(_) => error!("Network '{}' not found", ssid),
+            // Reported with their own exit code, so that scripts can
+            // tell a wrong password from a network without DHCP
+            ErrorKind::AuthenticationFailed(_)
+            | ErrorKind::AssociationTimeout(_)
+            | ErrorKind::DhcpFailed(_) => return Err(e),
+            _ => error!("Error connecting to '{}': {}", ssid, e),
+        },
+    }
+
+    Ok(())
+}
+
+/// Reads the passphrase from stdin, so that it stays out of the shell history
+fn handle_encrypt_passphrase(key: &SecretsKey) -> Result<()> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+
+    let passphrase = Secret::from(line.trim_end_matches(|c| c == '\n' || c == '\r'));
+    let encrypted = encryption::encrypt_passphrase(key, &passphrase, "stdin")?;
+
+    println!("{}", encrypted.expose());
+
+    Ok(())
+}
+
+/// Whether the selected mode brings up the access point, as opposed to one-off
+/// commands like listing or forgetting networks
+fn manages_interface(config: &config::Config) -> bool {
+    let one_off = config.check_hotspot
+        || config.forget_all
+        || config.forget_network.is_some()
+        || config.show_network.is_some()
+        || config.update_network.is_some()
+        || config.apply_state.is_some()
+        || config.show_drift.is_some()
+        || config.install_passpoint.is_some()
+        || config.state_url.is_some()
+        || config.list_networks
+        || config.list_interfaces
+        || config.survey
+        || config.channel_report
+        || config.list_connected
+        || config.list_saved
+        || config.list_managed
+        || config.forget_managed
+        || config.gc_connections
+        || config.connect.is_some()
+        || config.disconnect;
+
+    !one_off
+}
+
+/// Lists the networks `--forget-all` would delete and asks whether to go
+/// ahead, which needs a terminal
+fn confirm_forget_all(backend: &dyn Backend, filter: &ForgetFilter) -> Result<bool> {
+    let profiles = backend.profiles_in_scope(ForgetScope::All(filter))?;
+
+    if profiles.is_empty() {
+        return Ok(true);
+    }
+
+    if !isatty(STDIN_FILENO).unwrap_or(false) {
+        bail!(ErrorKind::ConfirmationRequired("--forget-all".into()));
+    }
+
+    println!("\n--forget-all will delete:");
+    for profile in &profiles {
+        println!("{}", profile);
+    }
+
+    print!("\nForget these {} WiFi networks? [y/N] ", profiles.len());
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(["y", "yes"].contains(&answer.trim().to_lowercase().as_str()))
+}
+
+/// Prints what the destructive command would delete or stop, handled before
+/// anything else so that no other code path runs with `--dry-run`
+fn handle_dry_run(config: &config::Config) -> Result<()> {
+    // Stopping goes through the instance owning the PID file when there is one
+    let running = match config.pidfile {
+        Some(ref path) if config.stop_hotspot => pidfile::running_instance(path),
+        _ => None,
+    };
+
+    if let Some(pid) = running {
+        println!("\nDry run, --stop-hotspot would stop:");
+        println!("WiFi Connect instance with PID {}", pid);
+        return Ok(());
+    }
+
+    let backend = init_backend(config)?;
+
+    let (action, scope) = if config.forget_all {
+        ("--forget-all", ForgetScope::All(&config.forget_filter))
+    } else if let Some(ref ssid) = config.forget_network {
+        ("--forget-network", ForgetScope::Network(ssid))
+    } else if config.forget_managed {
+        ("--forget-managed", ForgetScope::Managed)
+    } else if config.stop_hotspot {
+        ("--stop-hotspot", ForgetScope::Portal(&config.ssid))
+    } else if manages_interface(config) {
+        // Leftover portal profiles are deleted when the portal starts
+        ("Starting the portal", ForgetScope::Portal(&config.ssid))
+    } else {
+        warn!("--dry-run has no effect on the given command, nothing was run");
+        return Ok(());
+    };
+
+    let profiles = backend.profiles_in_scope(scope)?;
+
+    println!("\nDry run, {} would delete:", action);
+    if profiles.is_empty() {
+        println!("No connection profiles.");
+    } else {
+        for profile in profiles {
+            println!("{}", profile);
+        }
+    }
+
+    Ok(())
+}
+
 fn handle_stop_hotspot(config: config::Config) -> Result<()> {
     info!("Stopping hotspot...");
+
+    if let Some(ref path) = config.pidfile {
+        if let Some(pid) = pidfile::stop_running_instance(path, config.shutdown_timeout + 5)? {
+            info!("Hotspot stopped by shutting down instance {}", pid);
+            return Ok(());
+        }
+    }
     
     let mut hotspot = HotspotManager::new(config)?;
     
@@ -295,11 +628,27 @@ fn handle_stop_hotspot(config: config::Config) -> Result<()> {
 }
 
 fn handle_check_hotspot(config: config::Config) -> Result<()> {
+    // Generated passphrases are only known to the device, so they are
+    // printed for the label
+    let generated = config.random_passphrase.is_some() || config.rotate_passphrase.is_some();
+    let print_qr_code = config.print_qr_code;
+
     let hotspot = HotspotManager::new(config)?;
     let status = hotspot.get_hotspot_status();
     
     println!("\n=== Hotspot Status ===");
     status.print_status();
+
+    if generated {
+        if let Some(passphrase) = hotspot.passphrase() {
+            println!("Passphrase: {}", passphrase.expose());
+        }
+    }
+
+    if print_qr_code {
+        println!();
+        rotation::print_qr_code(&hotspot.qr_code_payload());
+    }
     
     if status.is_running {
         println!("\nTo stop the hotspot, run: {} --stop-hotspot", env!("CARGO_PKG_NAME"));
@@ -310,6 +659,308 @@ fn handle_check_hotspot(config: config::Config) -> Result<()> {
     Ok(())
 }
 
+fn handle_list_interfaces(config: &config::Config) -> Result<()> {
+    let interfaces = backend::list_interfaces(config)?;
+
+    let unknown = || "N/A".to_string();
+
+    println!("\nWiFi Interfaces:");
+    println!("----------------");
+    if interfaces.is_empty() {
+        println!("No WiFi interfaces found.");
+    } else {
+        for interface in interfaces {
+            let ap_mode = match interface.ap_mode {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            };
+
+            println!(
+                "Interface: {}, Driver: {}, MAC: {}, AP mode: {}, State: {}",
+                interface.interface,
+                interface.driver.unwrap_or_else(unknown),
+                interface.mac_address.unwrap_or_else(unknown),
+                ap_mode,
+                interface.state
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_list_networks_all(config: &config::Config) -> Result<()> {
+    let interfaces = backend::list_interfaces(config)?
+        .into_iter()
+        .map(|interface| interface.interface)
+        .collect::<Vec<_>>();
+
+    info!("Scanning for WiFi networks on {}...", interfaces.join(", "));
+    let networks = backend::scan_interfaces(config, &interfaces);
+
+    println!("\nAvailable WiFi Networks:");
+    println!("----------------------");
+    if networks.is_empty() {
+        println!("No networks found. Please try again.");
+    } else {
+        for scanned in networks {
+            println!(
+                "SSID: {}, Hex: {}, Security: {}, Interfaces: {}",
+                scanned.network.ssid,
+                scanned.network.ssid_hex,
+                scanned.network.security,
+                scanned.interfaces.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_channel_report(config: &config::Config) -> Result<()> {
+    let backend = init_backend(config)?;
+
+    info!("Scanning for WiFi networks...");
+    if request_and_wait_for_scan(&*backend, config.scan_timeout, exit::wait_exit_signal) {
+        return Ok(());
+    }
+
+    let access_points = channels::scan_access_points(backend.interface())?;
+    let report = channels::channel_report(&access_points);
+
+    println!("\nChannel Report ({}):", backend.interface());
+    println!("----------------------");
+    for usage in &report {
+        let strongest = match usage.access_points.first() {
+            Some(ap) => format!(", strongest: {} ({} dBm)", ap.ssid, ap.signal),
+            None => String::new(),
+        };
+
+        println!(
+            "Channel {} ({}): {} APs, congestion {}{}",
+            usage.channel,
+            usage.band().name(),
+            usage.access_points.len(),
+            usage.congestion,
+            strongest
+        );
+    }
+
+    for &band in &[channels::Band::TwoGHz, channels::Band::FiveGHz] {
+        if let Some(channel) = channels::recommend_channel(&report, band) {
+            println!("Recommended {} channel: {}", band.name(), channel);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_survey(config: &config::Config) -> Result<()> {
+    let shutdown = exit::Shutdown::new();
+    let trap_shutdown = shutdown.clone();
+
+    // Exit signals are blocked, so stop surveying once one is trapped
+    thread::spawn(move || {
+        if let Err(e) = exit::trap_exit_signals() {
+            error!("Signal handling failed: {}", e);
+        }
+        trap_shutdown.trigger();
+    });
+
+    let survey = survey::run_survey(config, &shutdown)?;
+
+    if !config.survey_json {
+        survey::print_summary(&survey);
+    }
+
+    Ok(())
+}
+
+fn print_connected_network(backend: &dyn Backend) -> Result<()> {
+    match backend.connected_network() {
+        Ok(Some(connected)) => {
+            println!("Connected Network:");
+            println!(
+                "SSID: {}, Security: {}, Signal: {}%, Interface: {}, IP: {}",
+                connected.ssid,
+                connected.security,
+                connected.signal_strength,
+                connected.interface,
+                connected.ip_address.unwrap_or_else(|| "N/A".to_string())
+            );
+
+            let link = &connected.link;
+            println!(
+                "RX bitrate: {}, TX bitrate: {}, TX power: {}, Frequency: {}, Signal: {}",
+                with_unit(link.rx_bitrate, "MBit/s"),
+                with_unit(link.tx_bitrate, "MBit/s"),
+                with_unit(link.tx_power, "dBm"),
+                with_unit(link.frequency, "MHz"),
+                with_unit(link.signal, "dBm")
+            );
+            println!(
+                "TX retries: {}, TX failed: {}",
+                with_unit(link.tx_retries, ""),
+                with_unit(link.tx_failed, "")
+            );
+        }
+        Ok(None) => {
+            println!("No network connected");
+        }
+        Err(e) => {
+            error!("Failed to get connected network: {}", e);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a link statistic the driver may not report
+fn with_unit<T: fmt::Display>(value: Option<T>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{} {}", value, unit).trim_end().to_string(),
+        None => "N/A".to_string(),
+    }
+}
+
+/// Redraws the connected network every `interval` seconds, similar to
+/// `watch nmcli`, until an exit signal is trapped
+fn watch_connected_network(backend: &dyn Backend, interval: u64) -> Result<()> {
+    let shutdown = exit::Shutdown::new();
+    let trap_shutdown = shutdown.clone();
+
+    // Exit signals are blocked, so stop watching once one is trapped
+    thread::spawn(move || {
+        if let Err(e) = exit::trap_exit_signals() {
+            error!("Signal handling failed: {}", e);
+        }
+        trap_shutdown.trigger();
+    });
+
+    loop {
+        // Clear the terminal and move the cursor home before redrawing
+        print!("\x1B[2J\x1B[H");
+        println!(
+            "Every {}s: {} --list-connected\n",
+            interval,
+            env!("CARGO_PKG_NAME")
+        );
+
+        // Failures are logged and retried on the next refresh
+        let _ = print_connected_network(backend);
+
+        io::stdout().flush()?;
+
+        if shutdown.sleep(Duration::from_secs(interval)) {
+            return Ok(());
+        }
+    }
+}
+
+fn handle_show_network(config: &config::Config, ssid: &str) -> Result<()> {
+    let backend = init_backend(config)?;
+
+    let details = match backend.saved_network_details(ssid)? {
+        Some(details) => details,
+        None => {
+            info!("WiFi network '{}' was not found in saved connections", ssid);
+            return Ok(());
+        }
+    };
+
+    let unknown = || "N/A".to_string();
+
+    println!("\nSaved Network:");
+    println!("--------------");
+    println!("SSID: {}", details.ssid);
+    println!("Security: {}", details.security);
+    println!("Autoconnect: {}", if details.autoconnect { "yes" } else { "no" });
+    println!(
+        "Autoconnect priority: {}",
+        details
+            .autoconnect_priority
+            .map_or_else(unknown, |priority| priority.to_string())
+    );
+    println!(
+        "Last used: {}",
+        details
+            .last_used
+            .map_or_else(|| "never".to_string(), |timestamp| timestamp.to_string())
+    );
+    println!("IPv4 method: {}", details.ipv4_method.unwrap_or_else(unknown));
+    println!("IPv6 method: {}", details.ipv6_method.unwrap_or_else(unknown));
+    if details.dns.is_empty() {
+        println!("DNS: automatic");
+    } else {
+        println!("DNS: {}", details.dns.join(", "));
+    }
+    println!("MAC address: {}", details.cloned_mac.unwrap_or_else(unknown));
+
+    Ok(())
+}
+
+fn handle_ping(host: &str) -> Result<()> {
+    let report = ping::ping(host)?;
+
+    println!("PING {} ({})", report.host, report.address);
+
+    for rtt in &report.rtt_ms {
+        println!("Answer from {}: time={:.1} ms", report.address, rtt);
+    }
+
+    println!(
+        "{} attempts, {} answered, {:.0}% loss",
+        report.sent,
+        report.received,
+        100.0 * (report.sent - report.received) as f64 / report.sent as f64
+    );
+
+    if let (Some(min), Some(avg), Some(max)) = (report.min_ms, report.avg_ms, report.max_ms) {
+        println!("rtt min/avg/max = {:.1}/{:.1}/{:.1} ms", min, avg, max);
+    }
+
+    Ok(())
+}
+
+fn handle_doctor(config: &config::Config) -> Result<()> {
+    let mut checks = doctor::run_checks(config, true);
+
+    if config.doctor_network {
+        checks.extend(doctor::run_network_checks());
+    }
+
+    println!("\n=== Self-diagnostics ===");
+    doctor::print_checks(&checks);
+
+    let failed = checks.iter().filter(|check| !check.passed).count();
+
+    if failed != 0 {
+        bail!(ErrorKind::DiagnosticsFailed(failed));
+    }
+
+    println!("\nAll checks passed");
+
+    Ok(())
+}
+
+fn handle_check_privileges(config: &config::Config) -> Result<()> {
+    let report = PrivilegeReport::collect(config);
+
+    println!("\n=== Privileges ===");
+    report.print();
+
+    if !report.is_sufficient() {
+        bail!(ErrorKind::InsufficientPrivileges(
+            env!("CARGO_PKG_NAME").into(),
+            report.missing().join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
 fn handle_restart_hotspot(config: config::Config) -> Result<()> {
     info!("Restarting hotspot '{}'...", config.ssid);
     
@@ -323,12 +974,151 @@ fn handle_restart_hotspot(config: config::Config) -> Result<()> {
     Ok(())
 }
 
+/// Monitors the connection until an exit signal is trapped. The portal takes
+/// over the process if no saved network stays in range, and the service
+/// manager restarts the watchdog once it exits.
+fn handle_watchdog(config: config::Config, settings: &watchdog::WatchdogSettings) -> Result<()> {
+    if let Some(interval) = config.gc_interval {
+        gc::spawn_connection_gc(config.clone(), interval);
+    }
+
+    let launch_portal = {
+        let mut backend = init_backend(&config)?;
+        watchdog::run_watchdog(&mut *backend, settings)
+    };
+
+    if launch_portal {
+        run_portal(config)
+    } else {
+        Ok(())
+    }
+}
+
+fn handle_api_server(config: config::Config) -> Result<()> {
+    if config.api_token.is_none() {
+        warn!("No --api-token configured, hotspot control routes are disabled");
+    }
+
+    let controller = spawn_hotspot_controller(config.clone());
+    let hotspot_stop = controller.clone();
+
+    let _grpc_server = start_grpc_server(&config, &controller)?;
+
+    match config.rotate_passphrase {
+        Some(0) | None => {}
+        Some(interval) => rotation::spawn_passphrase_rotation(controller.clone(), interval),
+    }
+
+    if let Some(interval) = config.gc_interval {
+        gc::spawn_connection_gc(config.clone(), interval);
+    }
+
+    // The guest hotspot is always on, unlike the setup hotspot
+    let guest_hotspot = config.guest_ssid.is_some();
+
+    if guest_hotspot {
+        if let Ok(HotspotCommandResponse::Failed(reason)) =
+            controller.send(HotspotCommand::StartGuest)
+        {
+            error!("{}", reason);
+        }
+    }
+
+    let ssdp_description = if config.ssdp {
+        match config.api_address.parse::<SocketAddr>() {
+            Ok(address) => ssdp::spawn_ssdp_advertiser(&config, address),
+            Err(_) => warn!(
+                "Not advertising over SSDP, {} is not an IP address and port",
+                config.api_address
+            ),
+        }
+
+        Some(ssdp::device_description(&config))
+    } else {
+        None
+    };
+
+    let (exit_tx, exit_rx) = channel();
+    let exit_tx_server = exit_tx.clone();
+
+    let server = server::ServerHandle::new();
+    let server_api = server.clone();
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+
+    thread::spawn(move || {
+        server::start_api_server(
+            &config.api_address,
+            config.api_token.clone(),
+            controller,
+            ssdp_description,
+            config.access_log.clone(),
+            config.http_limits,
+            exit_tx_server,
+            server_api,
+        );
+    });
+
+    thread::spawn(move || {
+        if let Err(e) = exit::trap_exit_signals() {
+            exit::exit(&exit_tx, e);
+            return;
+        }
+
+        let _ = exit_tx.send(Ok(()));
+    });
+
+    let result = exit_rx.recv()?;
+
+    server.drain(shutdown_timeout);
+
+    // Tear down a hotspot that may have been started through the API
+    let _ = hotspot_stop.send(HotspotCommand::Stop);
+
+    if guest_hotspot {
+        let _ = hotspot_stop.send(HotspotCommand::StopGuest);
+    }
+
+    result
+}
+
+#[cfg(feature = "grpc")]
+fn start_grpc_server(
+    config: &config::Config,
+    controller: &HotspotController,
+) -> Result<Option<grpc::GrpcServer>> {
+    match config.grpc_address {
+        Some(ref address) => {
+            grpc::start_grpc_server(address, config.api_token.clone(), controller.clone()).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "grpc"))]
+fn start_grpc_server(
+    config: &config::Config,
+    _controller: &HotspotController,
+) -> Result<Option<()>> {
+    if let Some(ref address) = config.grpc_address {
+        bail!(ErrorKind::StartGrpcServer(
+            address.clone(),
+            "built without the `grpc` feature".into()
+        ));
+    }
+
+    Ok(None)
+}
+
 // Helper function to create a persistent hotspot that stays running
 pub fn run_persistent_hotspot(config: config::Config) -> Result<()> {
     info!("Starting persistent hotspot '{}'...", config.ssid);
     
     let mut hotspot = HotspotManager::new(config.clone())?;
     hotspot.start_hotspot()?;
+
+    if hotspot.has_guest_hotspot() {
+        hotspot.start_guest_hotspot()?;
+    }
     
     let status = hotspot.get_hotspot_status();
     status.print_status();
@@ -360,6 +1150,10 @@ pub fn run_persistent_hotspot(config: config::Config) -> Result<()> {
             hotspot.stop_hotspot()?;
         }
     }
+
+    if hotspot.has_guest_hotspot() {
+        hotspot.stop_guest_hotspot()?;
+    }
     
     Ok(())
 }
\ No newline at end of file
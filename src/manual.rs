@@ -0,0 +1,369 @@
+//! Markdown and man page renderings of the command line interface, generated
+//! from the same clap definitions that parse the arguments.
+
+use clap::App;
+
+use config::env_var_of;
+
+/// Flag or option of the command line interface as shown in the manuals
+struct Entry {
+    short: Option<char>,
+    long: Option<String>,
+    value: Option<String>,
+    possible_values: Vec<String>,
+    env_var: Option<&'static str>,
+    help: String,
+}
+
+impl Entry {
+    fn switches(&self) -> String {
+        let mut switches = Vec::new();
+
+        if let Some(short) = self.short {
+            switches.push(format!("-{}", short));
+        }
+
+        if let Some(ref long) = self.long {
+            switches.push(format!("--{}", long));
+        }
+
+        switches.join(", ")
+    }
+}
+
+/// Flags and options of `app` sorted by their long switches. They are read
+/// back from the help clap renders, so that the manuals depend only on the
+/// public interface of clap rather than on its internals.
+fn entries(app: App) -> (Vec<Entry>, Vec<Entry>) {
+    let mut help = Vec::new();
+
+    // A terminal width of 0 keeps every help text on a single line
+    app.set_term_width(0)
+        .write_long_help(&mut help)
+        .expect("Cannot render the command line help");
+
+    parse_help(&String::from_utf8_lossy(&help))
+}
+
+/// Parses the `FLAGS:` and `OPTIONS:` sections of a help rendered by clap
+fn parse_help(help: &str) -> (Vec<Entry>, Vec<Entry>) {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut in_arguments = false;
+
+    for line in help.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if !line.starts_with(' ') {
+            in_arguments = line == "FLAGS:" || line == "OPTIONS:";
+            continue;
+        }
+
+        if !in_arguments {
+            continue;
+        }
+
+        let line = line.trim();
+
+        if line.starts_with('-') {
+            entries.push(parse_entry(line));
+        } else if let Some(entry) = entries.last_mut() {
+            // Help placed on its own line or spanning several lines
+            if !entry.help.is_empty() {
+                entry.help.push(' ');
+            }
+
+            entry.help.push_str(line);
+        }
+    }
+
+    for entry in &mut entries {
+        split_possible_values(entry);
+    }
+
+    let (mut flags, mut options): (Vec<_>, Vec<_>) =
+        entries.into_iter().partition(|entry| entry.value.is_none());
+
+    flags.sort_by(|a, b| a.long.cmp(&b.long));
+    options.sort_by(|a, b| a.long.cmp(&b.long));
+
+    (flags, options)
+}
+
+/// Parses a line like `-s, --portal-ssid <ssid>    SSID of the ...`
+fn parse_entry(line: &str) -> Entry {
+    let (switches, help) = match line.find("  ") {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    let mut entry = Entry {
+        short: None,
+        long: None,
+        value: None,
+        possible_values: Vec::new(),
+        env_var: None,
+        help: help.to_string(),
+    };
+
+    let mut values = Vec::new();
+
+    for token in switches.split_whitespace() {
+        let token = token.trim_end_matches(',');
+
+        if token.starts_with("--") {
+            entry.long = Some(token[2..].to_string());
+        } else if token.starts_with('-') {
+            entry.short = token[1..].chars().next();
+        } else {
+            values.push(
+                token
+                    .trim_end_matches("...")
+                    .trim_matches(|c| c == '<' || c == '>')
+                    .to_string(),
+            );
+        }
+    }
+
+    if !values.is_empty() {
+        entry.value = Some(values.join(" "));
+    }
+
+    entry.env_var = entry.long.as_ref().and_then(|long| env_var_of(long));
+
+    entry
+}
+
+/// Moves the `[possible values: ...]` suffix clap appends to the help text
+/// into `possible_values`
+fn split_possible_values(entry: &mut Entry) {
+    const PREFIX: &str = "[possible values: ";
+
+    let index = match entry.help.rfind(PREFIX) {
+        Some(index) if entry.help.ends_with(']') => index,
+        _ => return,
+    };
+
+    entry.possible_values = entry.help[index + PREFIX.len()..entry.help.len() - 1]
+        .split(", ")
+        .map(String::from)
+        .collect();
+
+    entry.help.truncate(index);
+
+    let len = entry.help.trim_end().len();
+    entry.help.truncate(len);
+}
+
+/// Renders the command line interface in the format of
+/// `docs/command-line-arguments.md`
+pub fn markdown(app: App) -> String {
+    let (flags, options) = entries(app);
+
+    let mut out = String::from("# WiFi Connect Command Line Arguments\n\n");
+
+    out.push_str(
+        "This reference is generated from the command line definitions with \
+         `scripts/manuals.sh`, which also writes the `wifi-connect.1` man page next to the \
+         binary. Both are printed by `wifi-connect --help-markdown` and `wifi-connect \
+         --help-man`.\n\n",
+    );
+
+    out.push_str("## Flags\n\n");
+
+    for flag in &flags {
+        push_markdown_entry(&mut out, flag);
+    }
+
+    out.push_str("## Options\n\n");
+    out.push_str(
+        "Command line options have environment variable counterpart. If both a command line \
+         option and its environment variable counterpart are defined, the command line option \
+         will take higher precedence.\n\n",
+    );
+
+    for option in &options {
+        push_markdown_entry(&mut out, option);
+    }
+
+    out
+}
+
+fn push_markdown_entry(out: &mut String, entry: &Entry) {
+    out.push_str(&format!("*   **{}**", entry.switches()));
+
+    if let Some(ref value) = entry.value {
+        out.push_str(&format!(" {}", value));
+    }
+
+    if let Some(env_var) = entry.env_var {
+        out.push_str(&format!(", **${}**", env_var));
+    }
+
+    out.push_str(&format!("\n\n    {}\n\n", entry.help));
+
+    if !entry.possible_values.is_empty() {
+        out.push_str(&format!(
+            "    Possible values: _{}_\n\n",
+            entry.possible_values.join(", ")
+        ));
+    }
+}
+
+/// Renders the command line interface as a roff man page
+pub fn man_page(app: App) -> String {
+    let (flags, options) = entries(app);
+
+    let mut out = String::new();
+
+    out.push_str(&format!(
+        ".TH {} 1 \"\" \"{} {}\"\n",
+        roff_escape(&crate_name!().to_uppercase()),
+        roff_escape(crate_name!()),
+        roff_escape(crate_version!())
+    ));
+
+    out.push_str(".SH NAME\n");
+    out.push_str(&format!(
+        "{} \\- {}\n",
+        roff_escape(crate_name!()),
+        roff_escape(crate_description!())
+    ));
+
+    out.push_str(".SH SYNOPSIS\n");
+    out.push_str(&format!(
+        ".B {}\n[\\fIFLAGS\\fR] [\\fIOPTIONS\\fR]\n",
+        roff_escape(crate_name!())
+    ));
+
+    out.push_str(".SH FLAGS\n");
+
+    for flag in &flags {
+        push_man_entry(&mut out, flag);
+    }
+
+    out.push_str(".SH OPTIONS\n");
+
+    for option in &options {
+        push_man_entry(&mut out, option);
+    }
+
+    let env_entries = flags
+        .iter()
+        .chain(options.iter())
+        .filter(|entry| entry.env_var.is_some())
+        .collect::<Vec<_>>();
+
+    out.push_str(".SH ENVIRONMENT\n");
+    out.push_str(
+        "Options have environment variable counterparts. An option given on the command line \
+         takes precedence over its environment variable.\n",
+    );
+
+    for entry in env_entries {
+        out.push_str(&format!(
+            ".TP\n.B {}\nSee \\fB{}\\fR.\n",
+            entry.env_var.unwrap_or_default(),
+            roff_escape(&entry.switches())
+        ));
+    }
+
+    out
+}
+
+fn push_man_entry(out: &mut String, entry: &Entry) {
+    out.push_str(&format!(".TP\n\\fB{}\\fR", roff_escape(&entry.switches())));
+
+    if let Some(ref value) = entry.value {
+        out.push_str(&format!(" \\fI{}\\fR", roff_escape(value)));
+    }
+
+    out.push_str(&format!("\n{}\n", roff_escape(&entry.help)));
+
+    if !entry.possible_values.is_empty() {
+        out.push_str(&format!(
+            ".br\nPossible values: {}\n",
+            roff_escape(&entry.possible_values.join(", "))
+        ));
+    }
+
+    if let Some(env_var) = entry.env_var {
+        out.push_str(&format!(".br\nEnvironment: \\fB{}\\fR\n", env_var));
+    }
+}
+
+/// Escapes text so roff neither interprets backslashes and dashes nor
+/// treats leading dots and quotes as requests
+fn roff_escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_help;
+
+    const HELP: &str = "wifi-connect 4.11.1
+WiFi Connect
+
+USAGE:
+    wifi-connect [FLAGS] [OPTIONS] [SUBCOMMAND]
+
+FLAGS:
+        --dry-run    Print the connection profiles and processes --forget-all would affect
+    -h, --help       Prints help information
+
+OPTIONS:
+        --backend <backend>          WiFi management backend (default: network-manager) [possible values: network-manager, iwd, openwrt, mock]
+        --dhcp-host <mac,ip[,name]>...
+            Static DHCP reservation, can be given multiple times
+
+    -s, --portal-ssid <ssid>         SSID of the captive portal WiFi network (default: WiFi Connect)
+
+SUBCOMMANDS:
+    help        Prints this message or the help of the given subcommand(s)
+    watchdog    Monitor the WiFi connection without the portal
+";
+
+    #[test]
+    fn parses_flags_and_options() {
+        let (flags, options) = parse_help(HELP);
+
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[0].long, Some("dry-run".to_string()));
+        assert_eq!(flags[0].short, None);
+        assert_eq!(
+            flags[0].help,
+            "Print the connection profiles and processes --forget-all would affect"
+        );
+        assert_eq!(flags[1].switches(), "-h, --help");
+
+        assert_eq!(options.len(), 3);
+        assert_eq!(options[0].long, Some("backend".to_string()));
+        assert_eq!(options[0].value, Some("backend".to_string()));
+        assert_eq!(options[0].env_var, Some("BACKEND"));
+        assert_eq!(
+            options[0].help,
+            "WiFi management backend (default: network-manager)"
+        );
+        assert_eq!(
+            options[0].possible_values,
+            vec!["network-manager", "iwd", "openwrt", "mock"]
+        );
+
+        assert_eq!(options[1].value, Some("mac,ip[,name]".to_string()));
+        assert_eq!(
+            options[1].help,
+            "Static DHCP reservation, can be given multiple times"
+        );
+
+        assert_eq!(options[2].switches(), "-s, --portal-ssid");
+        assert_eq!(options[2].env_var, Some("PORTAL_SSID"));
+    }
+}
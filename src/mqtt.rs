@@ -0,0 +1,202 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use serde_json;
+
+use backend::init_backend;
+use config::Config;
+use errors::*;
+use network::NetworkCommand;
+use notifier::Notifier;
+use proxy::ProxySettings;
+use secret::Secret;
+use ssid::validate_ssid;
+use state::State;
+use wireguard::validate_wireguard_config;
+
+const DEFAULT_MQTT_PORT: &str = "1883";
+
+/// Broker address split into the `-h`/`-p` arguments of the mosquitto clients
+#[derive(Clone)]
+struct Broker {
+    host: String,
+    port: String,
+}
+
+impl Broker {
+    fn parse(broker: &str) -> Self {
+        let mut parts = broker.splitn(2, ':');
+        let host = parts.next().unwrap_or("").to_string();
+        let port = parts.next().unwrap_or(DEFAULT_MQTT_PORT).to_string();
+
+        Broker { host, port }
+    }
+
+    fn command(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+        command.arg("-h").arg(&self.host).arg("-p").arg(&self.port);
+        command
+    }
+}
+
+/// Publishes every state transition as a retained JSON message on
+/// `<prefix>/state`
+pub struct MqttNotifier {
+    broker: Broker,
+    topic: String,
+}
+
+impl MqttNotifier {
+    pub fn new(broker: &str, topic_prefix: &str) -> Self {
+        MqttNotifier {
+            broker: Broker::parse(broker),
+            topic: format!("{}/state", topic_prefix),
+        }
+    }
+}
+
+impl Notifier for MqttNotifier {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    fn notify(&mut self, _previous: &State, current: &State) -> Result<()> {
+        let payload = serde_json::to_string(current).chain_err(|| ErrorKind::Notify("mqtt".into()))?;
+
+        let status = self
+            .broker
+            .command("mosquitto_pub")
+            .arg("-r")
+            .arg("-q")
+            .arg("1")
+            .arg("-t")
+            .arg(&self.topic)
+            .arg("-m")
+            .arg(payload)
+            .status()
+            .chain_err(|| ErrorKind::Notify("mqtt".into()))?;
+
+        if !status.success() {
+            bail!(ErrorKind::Notify("mqtt".into()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ConnectCommand {
+    ssid: String,
+    #[serde(default)]
+    identity: String,
+    #[serde(default)]
+    passphrase: Secret,
+    #[serde(default)]
+    proxy: Option<ProxySettings>,
+    #[serde(default)]
+    wireguard: Option<Secret>,
+}
+
+#[derive(Deserialize)]
+struct ForgetCommand {
+    ssid: String,
+}
+
+/// Subscribes to `<prefix>/command/+` and forwards `connect` and `forget`
+/// commands received from the broker
+pub fn spawn_command_listener(config: &Config, network_tx: Sender<NetworkCommand>) {
+    let broker = match config.mqtt_broker {
+        Some(ref broker) if config.mqtt_commands => Broker::parse(broker),
+        _ => return,
+    };
+
+    let topic = format!("{}/command/+", config.mqtt_topic_prefix);
+    let config = config.clone();
+
+    thread::spawn(move || {
+        if let Err(e) = listen_for_commands(&config, &broker, &topic, &network_tx) {
+            error!("MQTT command listener failed: {}", e);
+        }
+    });
+}
+
+fn listen_for_commands(
+    config: &Config,
+    broker: &Broker,
+    topic: &str,
+    network_tx: &Sender<NetworkCommand>,
+) -> Result<()> {
+    let mut child = broker
+        .command("mosquitto_sub")
+        .arg("-v")
+        .arg("-t")
+        .arg(topic)
+        .stdout(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::MqttSubscribe(topic.to_string()))?;
+
+    info!("Listening for MQTT commands on '{}'", topic);
+
+    let stdout = child.stdout.take().unwrap();
+
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+
+        let mut parts = line.splitn(2, ' ');
+        let command_topic = parts.next().unwrap_or("");
+        let payload = parts.next().unwrap_or("");
+
+        match command_topic.rsplit('/').next() {
+            Some("connect") => match serde_json::from_str::<ConnectCommand>(payload) {
+                Ok(ref command) if validate_ssid(&command.ssid).is_err() => {
+                    warn!("Invalid MQTT connect command: SSID `{}` is invalid", command.ssid)
+                }
+                Ok(command) => {
+                    if let Some(Err(reason)) = command.proxy.as_ref().map(ProxySettings::validate) {
+                        warn!("Invalid MQTT connect command: {}", reason);
+                        continue;
+                    }
+
+                    if let Some(Err(reason)) = command
+                        .wireguard
+                        .as_ref()
+                        .map(|config| validate_wireguard_config(config.expose()))
+                    {
+                        warn!("Invalid MQTT connect command: {}", reason);
+                        continue;
+                    }
+
+                    info!("MQTT `connect` to access point `{}` command", command.ssid);
+                    network_tx
+                        .send(NetworkCommand::Connect {
+                            ssid: command.ssid,
+                            identity: command.identity,
+                            passphrase: command.passphrase,
+                            proxy: command.proxy,
+                            wireguard: command.wireguard,
+                        })
+                        .chain_err(|| ErrorKind::SendNetworkCommandConnect)?;
+                }
+                Err(e) => warn!("Invalid MQTT connect command: {}", e),
+            },
+            Some("forget") => match serde_json::from_str::<ForgetCommand>(payload) {
+                Ok(command) => {
+                    info!("MQTT `forget` network `{}` command", command.ssid);
+                    let result = init_backend(config)
+                        .and_then(|backend| backend.forget_network(&command.ssid));
+                    if let Err(e) = result {
+                        warn!("Forgetting '{}' failed: {}", command.ssid, e);
+                    }
+                }
+                Err(e) => warn!("Invalid MQTT forget command: {}", e),
+            },
+            _ => warn!("Unknown MQTT command topic: {}", command_topic),
+        }
+    }
+
+    let _ = child.wait();
+
+    Ok(())
+}
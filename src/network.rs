@@ -1,43 +1,123 @@
-use std::collections::HashSet;
-use std::net::Ipv4Addr;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::process;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
-
-use network_manager::{
-    AccessPoint, AccessPointCredentials, Connection, ConnectionState, Connectivity, Device,
-    DeviceState, DeviceType, NetworkManager, Security, ServiceState,
-};
+use std::time::{Duration, Instant};
 
+use backend::{init_backend, Backend};
+use blocklist::is_blocked;
+use clients::{apply_mac_filter, spawn_client_monitor, ClientMonitor, Clients, MacFilterRules};
+use coap::spawn_coap_server;
+use compatibility::flag_unsupported_networks;
 use config::Config;
-use dnsmasq::{start_dnsmasq, stop_dnsmasq};
+use dnsmasq::{start_dnsmasq, stop_dnsmasq, Dnsmasq};
 use errors::*;
-use exit::{exit, trap_exit_signals, ExitResult};
-use server::start_server;
-use std::rc::Rc;
+use events::SharedEventLog;
+use exit::{exit, trap_exit_signals, ExitResult, Shutdown};
+use firstboot::{check_time_sync, wait_for_ntp_sync, NTP_SYNC_TIMEOUT};
+use ipconfig::IpConfig;
+use link::LinkStatistics;
+use mqtt::spawn_command_listener;
+#[cfg(feature = "network-manager")]
+use nm::delete_exising_wifi_connect_ap_profile;
+use notifier::init_notifiers;
+use profile::{managed_connection_id, nmcli, ProfileSettings};
+use proxy::{write_proxy_file, ProxySettings};
+use retry::set_nm_retries;
+use rogue::flag_rogue_access_points;
+use secret::Secret;
+use server::{start_server, ServerHandle};
+use speedtest::run_speed_test;
+use ssdp::spawn_ssdp_advertiser;
+use ssid::{display_ssid, hex_ssid};
+use state::{Failure, SharedConnectStatus, State, StateMachine};
+use timings::AttemptTimer;
+use vpn::{activate_vpn, VpnStatus};
+use wireguard::enroll_wireguard;
+
+/// Device state reasons of NetworkManager telling why an activation failed,
+/// see `NMDeviceStateReason` in its D-Bus API
+const REASON_IP_CONFIG_UNAVAILABLE: u32 = 5;
+const REASON_NO_SECRETS: u32 = 7;
+const REASON_SUPPLICANT_DISCONNECT: u32 = 8;
+const REASON_SUPPLICANT_TIMEOUT: u32 = 11;
+const REASON_DHCP_START_FAILED: u32 = 15;
+const REASON_DHCP_ERROR: u32 = 16;
+const REASON_DHCP_FAILED: u32 = 17;
+const REASON_SSID_NOT_FOUND: u32 = 53;
+
+/// Host of the connectivity probe, with both A and AAAA records
+const CONNECTIVITY_PROBE_HOST: &str = "connectivity-check.ubuntu.com:80";
+
+/// Seconds to wait for each address of the connectivity probe
+const CONNECTIVITY_PROBE_TIMEOUT: u64 = 5;
 
 pub enum NetworkCommand {
-    Activate,
+    /// Answered on the channel of the request, so that a reply arriving after
+    /// the requester gave up is dropped instead of answering the next request
+    Activate(Sender<NetworkCommandResponse>),
+    /// Like `Activate`, leaving out blocked networks
+    ListNetworks(Sender<NetworkCommandResponse>),
     Timeout,
     Exit,
+    Failed(Error),
     Connect {
         ssid: String,
         identity: String,
-        passphrase: String,
+        passphrase: Secret,
+        proxy: Option<ProxySettings>,
+        wireguard: Option<Secret>,
+    },
+    ConnectMultiple {
+        networks: Vec<NetworkCredentials>,
     },
 }
 
-pub struct HotspotManager {
-    manager: NetworkManager,
-    device: Rc<Device>,
-    hotspot_connection: Option<Connection>,
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct NetworkCredentials {
+    pub ssid: String,
+    #[serde(default)]
+    pub identity: String,
+    #[serde(default)]
+    pub passphrase: Secret,
+    #[serde(default)]
+    pub proxy: Option<ProxySettings>,
+    /// WireGuard configuration enrolled once connected
+    #[serde(default)]
+    pub wireguard: Option<Secret>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Network {
+    /// SSID with bytes that are not valid UTF-8 escaped as `\xNN`
     pub ssid: String,
+    /// Raw SSID bytes, accepted with a `hex:` prefix when connecting
+    #[serde(default)]
+    pub ssid_hex: String,
     pub security: String,
+    /// Signal quality in percent
+    #[serde(default)]
+    pub signal_strength: u8,
+    /// Why the network looks like it is impersonated, for the UI to flag it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    /// Why connecting to the network would certainly fail, for the UI to
+    /// grey it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsupported: Option<String>,
+}
+
+impl Network {
+    pub fn new(ssid: &[u8], security: &str, signal_strength: u8) -> Self {
+        Network {
+            ssid: display_ssid(ssid),
+            ssid_hex: hex_ssid(ssid),
+            security: security.to_string(),
+            signal_strength,
+            warning: None,
+            unsupported: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -46,6 +126,22 @@ pub struct SavedNetwork {
     pub security: String,
 }
 
+/// Settings of a saved network profile, to verify what was provisioned.
+/// Backends fill in what they know of.
+#[derive(Debug, Default, Serialize)]
+pub struct SavedNetworkDetails {
+    pub ssid: String,
+    pub security: String,
+    pub autoconnect: bool,
+    pub autoconnect_priority: Option<i32>,
+    /// Unix timestamp of the last activation
+    pub last_used: Option<u64>,
+    pub ipv4_method: Option<String>,
+    pub ipv6_method: Option<String>,
+    pub dns: Vec<String>,
+    pub cloned_mac: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct ConnectedNetwork {
     pub ssid: String,
@@ -53,6 +149,18 @@ pub struct ConnectedNetwork {
     pub signal_strength: u8,
     pub interface: String,
     pub ip_address: Option<String>,
+    /// Global IPv6 addresses from SLAAC or DHCPv6
+    #[serde(default)]
+    pub ipv6_addresses: Vec<String>,
+    #[serde(default)]
+    pub ipv4: IpConfig,
+    #[serde(default)]
+    pub ipv6: IpConfig,
+    #[serde(default)]
+    pub link: LinkStatistics,
+    /// Status of the `--activate-vpn` connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vpn: Option<VpnStatus>,
 }
 
 pub enum NetworkCommandResponse {
@@ -60,99 +168,140 @@ pub enum NetworkCommandResponse {
 }
 
 struct NetworkCommandHandler {
-    manager: NetworkManager,
-    device: Device,
-    access_points: Vec<AccessPoint>,
-    portal_connection: Option<Connection>,
+    backend: Box<dyn Backend>,
+    networks: Vec<Network>,
+    portal_active: bool,
     config: Config,
-    dnsmasq: process::Child,
-    server_tx: Sender<NetworkCommandResponse>,
+    dnsmasq: Option<Dnsmasq>,
     network_rx: Receiver<NetworkCommand>,
+    server: ServerHandle,
     activated: bool,
+    activity_deadline: Option<Instant>,
+    shutdown: Shutdown,
+    state: StateMachine,
+    client_monitor: ClientMonitor,
+    /// Only kept while the hotspot is up, as it would filter the frames of
+    /// the network being connected to as well
+    mac_filter_rules: Option<MacFilterRules>,
 }
 
 impl NetworkCommandHandler {
     fn new(config: &Config, exit_tx: &Sender<ExitResult>) -> Result<Self> {
         let (network_tx, network_rx) = channel();
 
-        Self::spawn_trap_exit_signals(exit_tx, network_tx.clone());
+        let shutdown = Shutdown::new();
 
-        let manager = NetworkManager::new();
-        debug!("NetworkManager connection initialized");
+        Self::spawn_trap_exit_signals(exit_tx, network_tx.clone(), shutdown.clone());
 
-        let device = find_device(&manager, &config.interface)?;
+        let mut backend = init_backend(config)?;
+        backend.set_shutdown(shutdown.clone());
 
-        let access_points = get_access_points(&device, &config.ssid)?;
+        let mut networks = backend.scan()?;
+        flag_rogue_access_points(&*backend, &config.ssid, &mut networks);
+        flag_unsupported_networks(&*backend, &mut networks);
 
-        let portal_connection = Some(create_portal(&device, config)?);
+        let mut state = StateMachine::new(init_notifiers(config));
 
-        let dnsmasq = start_dnsmasq(config, &device)?;
+        backend.start_hotspot(config)?;
+        let portal_active = true;
 
-        let (server_tx, server_rx) = channel();
+        let mac_filter_rules = apply_mac_filter(backend.interface(), &config.mac_filter)?;
 
-        Self::spawn_server(config, exit_tx, server_rx, network_tx.clone());
+        state.transition(State::HotspotUp {
+            ssid: config.ssid.clone(),
+        });
 
-        Self::spawn_activity_timeout(config, network_tx);
+        let dnsmasq = if backend.uses_dnsmasq() {
+            let failure_tx = network_tx.clone();
+            Some(start_dnsmasq(config, backend.interface(), move |error| {
+                let _ = failure_tx.send(NetworkCommand::Failed(error));
+            })?)
+        } else {
+            None
+        };
+
+        let client_monitor = spawn_client_monitor(
+            backend.interface(),
+            config.mac_filter.clone(),
+            state.events(),
+        );
+
+        let server = ServerHandle::new();
+
+        Self::spawn_server(
+            config,
+            exit_tx,
+            network_tx.clone(),
+            client_monitor.clients.clone(),
+            state.status(),
+            state.events(),
+            server.clone(),
+        );
+
+        spawn_coap_server(config, network_tx.clone());
+
+        spawn_ssdp_advertiser(
+            config,
+            SocketAddr::from((config.gateway, config.listening_port)),
+        );
+
+        spawn_command_listener(config, network_tx);
+
+        let activity_deadline = match config.activity_timeout {
+            0 => None,
+            timeout => Some(Instant::now() + Duration::from_secs(timeout)),
+        };
 
         let config = config.clone();
         let activated = false;
 
         Ok(NetworkCommandHandler {
-            manager,
-            device,
-            access_points,
-            portal_connection,
+            backend,
+            networks,
+            portal_active,
             config,
             dnsmasq,
-            server_tx,
             network_rx,
+            server,
             activated,
+            activity_deadline,
+            shutdown,
+            state,
+            client_monitor,
+            mac_filter_rules,
         })
     }
 
     fn spawn_server(
         config: &Config,
         exit_tx: &Sender<ExitResult>,
-        server_rx: Receiver<NetworkCommandResponse>,
         network_tx: Sender<NetworkCommand>,
+        clients: Clients,
+        status: SharedConnectStatus,
+        events: SharedEventLog,
+        server: ServerHandle,
     ) {
-        let gateway = config.gateway;
-        let listening_port = config.listening_port;
+        let config = config.clone();
         let exit_tx_server = exit_tx.clone();
-        let ui_directory = config.ui_directory.clone();
 
         thread::spawn(move || {
             start_server(
-                gateway,
-                listening_port,
-                server_rx,
+                config,
                 network_tx,
                 exit_tx_server,
-                &ui_directory,
+                clients,
+                status,
+                events,
+                server,
             );
         });
     }
 
-    fn spawn_activity_timeout(config: &Config, network_tx: Sender<NetworkCommand>) {
-        let activity_timeout = config.activity_timeout;
-
-        if activity_timeout == 0 {
-            return;
-        }
-
-        thread::spawn(move || {
-            thread::sleep(Duration::from_secs(activity_timeout));
-
-            if let Err(err) = network_tx.send(NetworkCommand::Timeout) {
-                error!(
-                    "Sending NetworkCommand::Timeout failed: {}",
-                    err.to_string()
-                );
-            }
-        });
-    }
-
-    fn spawn_trap_exit_signals(exit_tx: &Sender<ExitResult>, network_tx: Sender<NetworkCommand>) {
+    fn spawn_trap_exit_signals(
+        exit_tx: &Sender<ExitResult>,
+        network_tx: Sender<NetworkCommand>,
+        shutdown: Shutdown,
+    ) {
         let exit_tx_trap = exit_tx.clone();
 
         thread::spawn(move || {
@@ -161,6 +310,9 @@ impl NetworkCommandHandler {
                 return;
             }
 
+            // Interrupts a connection attempt that is in progress
+            shutdown.trigger();
+
             if let Err(err) = network_tx.send(NetworkCommand::Exit) {
                 error!("Sending NetworkCommand::Exit failed: {}", err.to_string());
             }
@@ -177,8 +329,8 @@ impl NetworkCommandHandler {
             let command = self.receive_network_command()?;
 
             match command {
-                NetworkCommand::Activate => {
-                    self.activate()?;
+                NetworkCommand::Activate(reply_tx) | NetworkCommand::ListNetworks(reply_tx) => {
+                    self.activate(&reply_tx);
                 }
                 NetworkCommand::Timeout => {
                     if !self.activated {
@@ -190,13 +342,25 @@ impl NetworkCommandHandler {
                     info!("Exiting...");
                     return Ok(());
                 }
+                NetworkCommand::Failed(error) => {
+                    self.state.record_error(error.to_string());
+                    return Err(error);
+                }
                 NetworkCommand::Connect {
                     ssid,
                     identity,
                     passphrase,
+                    proxy,
+                    wireguard,
                 } => {
                     info!("connecting...");
-                    if self.connect(&ssid, &identity, &passphrase)? {
+                    if self.connect(&ssid, &identity, &passphrase, proxy, wireguard)? {
+                        return Ok(());
+                    }
+                }
+                NetworkCommand::ConnectMultiple { networks } => {
+                    info!("connecting to {} networks...", networks.len());
+                    if self.connect_networks(&networks)? {
                         return Ok(());
                     }
                 }
@@ -204,8 +368,26 @@ impl NetworkCommandHandler {
         }
     }
 
-    fn receive_network_command(&self) -> Result<NetworkCommand> {
-        match self.network_rx.recv() {
+    /// Waits for the next command, turning the activity timeout into a
+    /// `NetworkCommand::Timeout` once it elapses
+    fn receive_network_command(&mut self) -> Result<NetworkCommand> {
+        let result = match self.activity_deadline {
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                self.network_rx.recv_timeout(timeout)
+            }
+            None => self
+                .network_rx
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        if let Err(RecvTimeoutError::Timeout) = result {
+            self.activity_deadline = None;
+            return Ok(NetworkCommand::Timeout);
+        }
+
+        match result {
             Ok(command) => Ok(command),
             Err(e) => {
                 // Sleep for a second, so that other threads may log error info.
@@ -215,596 +397,565 @@ impl NetworkCommandHandler {
         }
     }
 
+    /// Tears down in order: HTTP requests, dnsmasq, then the access point, so
+    /// that no request is left talking to a network that is going away
     fn stop(&mut self, exit_tx: &Sender<ExitResult>, result: ExitResult) {
-        let _ = stop_dnsmasq(&mut self.dnsmasq);
+        // Answer the `/networks` requests whose commands will not be handled
+        while let Ok(command) = self.network_rx.try_recv() {
+            match command {
+                NetworkCommand::Activate(reply_tx) | NetworkCommand::ListNetworks(reply_tx) => {
+                    let _ = reply_tx.send(NetworkCommandResponse::Networks(self.visible_networks()));
+                }
+                _ => {}
+            }
+        }
+
+        self.server
+            .drain(Duration::from_secs(self.config.shutdown_timeout));
+
+        self.client_monitor.stop();
+        self.mac_filter_rules.take();
 
-        if let Some(ref connection) = self.portal_connection {
-            let _ = stop_portal_impl(connection, &self.config);
+        if let Some(ref mut dnsmasq) = self.dnsmasq {
+            let _ = stop_dnsmasq(dnsmasq);
+        }
+
+        if self.portal_active {
+            let _ = self.backend.stop_hotspot(&self.config);
+            self.state.transition(State::HotspotDown);
         }
 
         let _ = exit_tx.send(result);
     }
 
-    fn activate(&mut self) -> ExitResult {
+    /// The requester may have timed out in the meantime, which is not an
+    /// error
+    fn activate(&mut self, reply_tx: &Sender<NetworkCommandResponse>) {
         self.activated = true;
 
-        // Fix: Pass the device and ssid to get_networks_from_access_points instead
-        let networks = get_networks_from_access_points(&self.access_points);
+        self.state.transition(State::ClientJoined);
+
+        let _ = reply_tx.send(NetworkCommandResponse::Networks(self.visible_networks()));
+    }
+
+    /// The scanned networks, leaving out the blocked ones
+    fn visible_networks(&self) -> Vec<Network> {
+        self.networks
+            .iter()
+            .filter(|network| !is_blocked(&self.config.blocked_ssids, &network.ssid))
+            .cloned()
+            .collect()
+    }
+
+    /// Scans for networks, flagging the ones rogue access points impersonate
+    fn scan(&mut self) -> Result<()> {
+        self.networks = self.backend.scan()?;
+        flag_rogue_access_points(&*self.backend, &self.config.ssid, &mut self.networks);
+        flag_unsupported_networks(&*self.backend, &mut self.networks);
+        Ok(())
+    }
+
+    fn connect(
+        &mut self,
+        ssid: &str,
+        identity: &str,
+        passphrase: &Secret,
+        proxy: Option<ProxySettings>,
+        wireguard: Option<Secret>,
+    ) -> Result<bool> {
+        let network = NetworkCredentials {
+            ssid: ssid.to_string(),
+            identity: identity.to_string(),
+            passphrase: passphrase.clone(),
+            proxy,
+            wireguard,
+        };
 
-        self.server_tx
-            .send(NetworkCommandResponse::Networks(networks))
-            .chain_err(|| ErrorKind::SendAccessPointSSIDs)
+        self.connect_networks(&[network])
     }
 
-    fn connect(&mut self, ssid: &str, identity: &str, passphrase: &str) -> Result<bool> {
-        delete_existing_connections_to_same_network(&self.manager, ssid);
-        if let Some(ref connection) = self.portal_connection {
-            stop_portal(connection, &self.config)?;
+    /// Activates the first network in range and saves the remaining ones with
+    /// descending autoconnect priority, so that they serve as fallbacks
+    fn connect_networks(&mut self, networks: &[NetworkCredentials]) -> Result<bool> {
+        let blocked = &self.config.blocked_ssids;
+        let networks = networks
+            .iter()
+            .filter(|network| {
+                let refused = is_blocked(blocked, &network.ssid);
+                if refused {
+                    warn!("Refusing to connect to blocked network '{}'", network.ssid);
+                }
+                !refused
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut ssid = match networks.first() {
+            Some(network) => network.ssid.clone(),
+            None => return Ok(false),
+        };
+
+        self.state.transition(State::CredentialsReceived { ssid: ssid.clone() });
+
+        if self.portal_active {
+            self.mac_filter_rules.take();
+            self.backend.stop_hotspot(&self.config)?;
+            self.portal_active = false;
         }
-        self.portal_connection = None;
-        self.access_points = get_access_points(&self.device, &self.config.ssid)?;
-        if let Some(access_point) = find_access_point(&self.access_points, ssid) {
-            let wifi_device = self.device.as_wifi_device().unwrap();
-
-            info!("Connecting to access point '{}'...", ssid);
-
-            let credentials = init_access_point_credentials(access_point, identity, passphrase);
-
-            match wifi_device.connect(access_point, &credentials) {
-                Ok((connection, state)) => {
-                    if state == ConnectionState::Activated {
-                        match wait_for_connectivity(&self.manager, 200000) {
-                            Ok(has_connectivity) => {
-                                if has_connectivity {
-                                    info!("Internet connectivity established");
-                                } else {
-                                    warn!("Cannot establish Internet connectivity");
-                                }
-                            }
-                            Err(err) => error!("Getting Internet connectivity failed: {}", err),
-                        }
+        self.state.transition(State::HotspotDown);
+        self.scan()?;
+
+        let mut connected: Option<String> = None;
+        let mut timer: Option<AttemptTimer> = None;
+        let mut proxy: Option<ProxySettings> = None;
+        let mut wireguard: Option<Secret> = None;
+        let mut reason = "network not found".to_string();
+        let mut failure = Failure::NetworkNotFound;
+
+        for (index, network) in networks.iter().enumerate() {
+            let mut settings = self.config.profile_settings.clone();
+            settings.proxy_pac_url = network
+                .proxy
+                .as_ref()
+                .and_then(|proxy| proxy.pac_url.clone());
+            if networks.len() > 1 {
+                settings.autoconnect_priority = Some((networks.len() - index) as i32);
+            }
 
-                        return Ok(true);
-                    }
+            if connected.is_some() {
+                self.save_network(network, &settings);
+                continue;
+            }
 
-                    if let Err(err) = connection.delete() {
-                        error!("Deleting connection object failed: {}", err)
+            timer = Some(AttemptTimer::start(
+                self.backend.interface(),
+                &network.ssid,
+                self.state.status(),
+            ));
+
+            match self.backend.connect(network, &settings) {
+                Ok(()) => {
+                    if self.wait_for_address(self.config.dhcp_timeout) {
+                        connected = Some(network.ssid.clone());
+                        proxy = network.proxy.clone();
+                        wireguard = network.wireguard.clone();
+                        continue;
                     }
 
                     warn!(
-                        "Connection to access point not activated '{}': {:?}",
-                        ssid, state
+                        "No IP address obtained via DHCP on '{}' within {} seconds",
+                        network.ssid, self.config.dhcp_timeout
                     );
+
+                    if let Err(e) = self.backend.disconnect() {
+                        warn!("Disconnecting from '{}' failed: {}", network.ssid, e);
+                    }
+
+                    reason = "no IP address obtained via DHCP".into();
+                    failure = Failure::DhcpFailed;
+
+                    self.state.record_error(format!(
+                        "Connecting to '{}' failed: {}",
+                        network.ssid, reason
+                    ));
                 }
                 Err(e) => {
-                    warn!("Error connecting to access point '{}': {}", ssid, e);
-                }
-            }
-        }
+                    warn!("Error connecting to access point '{}': {}", network.ssid, e);
 
-        self.access_points = get_access_points(&self.device, &self.config.ssid)?;
-
-        self.portal_connection = Some(create_portal(&self.device, &self.config)?);
+                    if let ErrorKind::NetworkNotFound(_) = *e.kind() {
+                        self.save_network(network, &settings);
+                    }
 
-        Ok(false)
-    }
-}
+                    reason = e.to_string();
+                    failure = Failure::from(e.kind());
 
-pub fn init_access_point_credentials(
-    access_point: &AccessPoint,
-    identity: &str,
-    passphrase: &str,
-) -> AccessPointCredentials {
-    if access_point.security.contains(Security::ENTERPRISE) {
-        AccessPointCredentials::Enterprise {
-            identity: identity.to_string(),
-            passphrase: passphrase.to_string(),
-        }
-    } else if access_point.security.contains(Security::WPA2)
-        || access_point.security.contains(Security::WPA)
-    {
-        AccessPointCredentials::Wpa {
-            passphrase: passphrase.to_string(),
-        }
-    } else if access_point.security.contains(Security::WEP) {
-        AccessPointCredentials::Wep {
-            passphrase: passphrase.to_string(),
+                    self.state.record_error(format!(
+                        "Connecting to '{}' failed: {}",
+                        network.ssid, reason
+                    ));
+                }
+            }
         }
-    } else {
-        AccessPointCredentials::None
-    }
-}
 
-pub fn process_network_commands(config: &Config, exit_tx: &Sender<ExitResult>) {
-    let mut command_handler = match NetworkCommandHandler::new(config, exit_tx) {
-        Ok(command_handler) => command_handler,
-        Err(e) => {
-            exit(exit_tx, e);
-            return;
+        // Stopped before the hotspot comes back up on the interface
+        if connected.is_none() {
+            timer = None;
         }
-    };
-
-    command_handler.run(exit_tx);
-}
 
-pub fn init_networking(config: &Config) -> Result<()> {
-    start_network_manager_service()?;
-
-    delete_exising_wifi_connect_ap_profile(&config.ssid).chain_err(|| ErrorKind::DeleteAccessPoint)
-}
-
-pub fn find_device(manager: &NetworkManager, interface: &Option<String>) -> Result<Device> {
-    if let Some(ref interface) = *interface {
-        let device = manager
-            .get_device_by_interface(interface)
-            .chain_err(|| ErrorKind::DeviceByInterface(interface.clone()))?;
-
-        info!("Targeted WiFi device: {}", interface);
+        if let Some(connected) = connected {
+            if let Err(e) = write_proxy_file(&self.config.proxy_file, &proxy) {
+                warn!("{}", e);
+            }
 
-        if *device.device_type() != DeviceType::WiFi {
-            bail!(ErrorKind::NotAWiFiDevice(interface.clone()))
-        }
+            let checking = Instant::now();
+            let connectivity = self.wait_for_connectivity(self.config.connect_timeout);
 
-        if device.get_state()? == DeviceState::Unmanaged {
-            bail!(ErrorKind::UnmanagedDevice(interface.clone()))
-        }
+            if let Some(ref mut timer) = timer {
+                timer.record_connectivity(checking.elapsed());
+            }
 
-        Ok(device)
-    } else {
-        let devices = manager.get_devices()?;
+            match connectivity {
+                Ok(has_connectivity) => {
+                    if has_connectivity {
+                        info!("Internet connectivity established");
 
-        if let Some(device) = find_wifi_managed_device(devices)? {
-            info!("WiFi device: {}", device.interface());
-            Ok(device)
-        } else {
-            bail!(ErrorKind::NoWiFiDevice)
-        }
-    }
-}
+                        if let Some(ref url) = self.config.speed_test {
+                            report_speed_test(url);
+                        }
 
-fn find_wifi_managed_device(devices: Vec<Device>) -> Result<Option<Device>> {
-    for device in devices {
-        if *device.device_type() == DeviceType::WiFi
-            && device.get_state()? != DeviceState::Unmanaged
-        {
-            return Ok(Some(device));
-        }
-    }
+                        // Checked below before reporting success instead
+                        if self.config.first_boot && self.config.require_time_sync.is_none() {
+                            self.check_ntp_sync();
+                        }
 
-    Ok(None)
-}
+                        if let Some(ref name) = self.config.activate_vpn {
+                            if let Err(e) = activate_vpn(name) {
+                                warn!("{}", e);
+                                self.state.record_error(e.to_string());
+                            }
+                        }
+                    } else {
+                        warn!("Cannot establish Internet connectivity");
+                    }
+                }
+                Err(err) => {
+                    error!("Getting Internet connectivity failed: {}", err);
+                    self.state
+                        .record_error(format!("Getting Internet connectivity failed: {}", err));
+                }
+            }
 
-pub fn get_access_points(device: &Device, ssid: &str) -> Result<Vec<AccessPoint>> {
-    get_access_points_impl(device, ssid).chain_err(|| ErrorKind::NoAccessPoints)
-}
+            match wireguard.as_ref().map_or(Ok(()), enroll_wireguard) {
+                Ok(()) => {
+                    let clock_synchronized = self
+                        .config
+                        .require_time_sync
+                        .map(|timeout| check_time_sync(timeout, &self.shutdown));
+
+                    self.state.transition(State::Connected {
+                        ssid: connected,
+                        ip: get_interface_address(self.backend.interface()),
+                        clock_synchronized,
+                    });
 
-fn get_access_points_impl(device: &Device, ssid: &str) -> Result<Vec<AccessPoint>> {
-    info!("Scanning for available networks...");
-    let retries_allowed = 10;
-    let mut retries = 0;
+                    // Connected, but exiting with its own code so that
+                    // scripts hold back on TLS with a wrong clock
+                    if let (Some(false), Some(timeout)) =
+                        (clock_synchronized, self.config.require_time_sync)
+                    {
+                        bail!(ErrorKind::ClockUnsynchronized(timeout));
+                    }
 
-    // After stopping the hotspot we may have to wait a bit for the list
-    // of access points to become available
-    while retries < retries_allowed {
-        let wifi_device = device.as_wifi_device().unwrap();
-        let mut access_points = wifi_device.get_access_points()?;
+                    return Ok(true);
+                }
+                Err(e) => {
+                    warn!("{}", e);
 
-        // Only keep access points with valid SSIDs
-        access_points.retain(|ap| ap.ssid().as_str().is_ok());
+                    // The WiFi network only counts as connected with the
+                    // tunnel, and the hotspot needs the interface back
+                    if let Err(e) = self.backend.disconnect() {
+                        warn!("Disconnecting from '{}' failed: {}", connected, e);
+                    }
 
-        // Purge access points with duplicate SSIDs
-        let mut inserted = HashSet::new();
-        access_points.retain(|ap| inserted.insert(ap.ssid.clone()));
+                    if let Err(e) = write_proxy_file(&self.config.proxy_file, &None) {
+                        warn!("{}", e);
+                    }
 
-        // Remove access points without SSID (hidden)
-        access_points.retain(|ap| !ap.ssid().as_str().unwrap().is_empty());
+                    reason = e.to_string();
+                    failure = Failure::from(e.kind());
 
-        // Only filter by SSID if a specific SSID was provided
-        if !ssid.is_empty() {
-            access_points.retain(|ap| ap.ssid().as_str().unwrap() != ssid);
-        }
+                    self.state
+                        .record_error(format!("Connecting to '{}' failed: {}", connected, reason));
 
-        if !access_points.is_empty() {
-            info!(
-                "Found {} access points: {:?}",
-                access_points.len(),
-                get_access_points_ssids(&access_points)
-            );
-            return Ok(access_points);
+                    ssid = connected;
+                }
+            }
         }
 
-        retries += 1;
-        info!("No access points found - retry #{}", retries);
-        thread::sleep(Duration::from_secs(1));
-    }
+        self.state.transition(State::Failed {
+            ssid,
+            reason,
+            failure,
+        });
 
-    warn!("No access points found - giving up...");
-    Ok(vec![])
-}
+        self.scan()?;
 
-fn get_access_points_ssids(access_points: &[AccessPoint]) -> Vec<&str> {
-    access_points
-        .iter()
-        .map(|ap| ap.ssid().as_str().unwrap())
-        .collect()
-}
-
-pub fn get_networks(device: &Device, ssid: &String) -> Vec<Network> {
-    let access_points = get_access_points_impl(device, ssid).unwrap_or_default();
-    access_points.iter().map(get_network_info).collect()
-}
+        self.backend.start_hotspot(&self.config)?;
+        self.portal_active = true;
+        self.mac_filter_rules =
+            apply_mac_filter(self.backend.interface(), &self.config.mac_filter)?;
 
-// New function to convert access points to networks
-fn get_networks_from_access_points(access_points: &[AccessPoint]) -> Vec<Network> {
-    access_points.iter().map(get_network_info).collect()
-}
+        self.state.transition(State::HotspotUp {
+            ssid: self.config.ssid.clone(),
+        });
 
-fn get_network_info(access_point: &AccessPoint) -> Network {
-    Network {
-        ssid: access_point.ssid().as_str().unwrap().to_string(),
-        security: get_network_security(access_point).to_string(),
+        Ok(false)
     }
-}
 
-fn get_network_security(access_point: &AccessPoint) -> &str {
-    if access_point.security.contains(Security::ENTERPRISE) {
-        "enterprise"
-    } else if access_point.security.contains(Security::WPA2)
-        || access_point.security.contains(Security::WPA)
-    {
-        "wpa"
-    } else if access_point.security.contains(Security::WEP) {
-        "wep"
-    } else {
-        "none"
-    }
-}
+    /// Polls the backend for connectivity, giving up early on shutdown
+    fn wait_for_connectivity(&self, timeout: u64) -> Result<bool> {
+        let deadline = Instant::now() + Duration::from_secs(timeout);
 
-pub fn find_access_point<'a>(access_points: &'a [AccessPoint], ssid: &str) -> Option<&'a AccessPoint> {
-    for access_point in access_points.iter() {
-        if let Ok(access_point_ssid) = access_point.ssid().as_str() {
-            if access_point_ssid == ssid {
-                return Some(access_point);
+        loop {
+            if self.backend.wait_for_connectivity(0)? {
+                return Ok(true);
+            }
+
+            if Instant::now() >= deadline || self.shutdown.sleep(Duration::from_secs(1)) {
+                return Ok(false);
             }
         }
     }
 
-    None
-}
-
-// New function to get currently connected network - improved version
-pub fn get_connected_network(manager: &NetworkManager, interface: &Option<String>) -> Result<Option<ConnectedNetwork>> {
-    let device = find_device(manager, interface)?;
-    
-    // Check if device is connected
-    let device_state = device.get_state()?;
-    if device_state != DeviceState::Activated {
-        return Ok(None);
+    /// Waits for the clock to be synchronized in first boot mode, as the
+    /// device may have booted with a wrong time
+    fn check_ntp_sync(&self) {
+        if wait_for_ntp_sync(NTP_SYNC_TIMEOUT, &self.shutdown) {
+            info!("Clock synchronized over NTP");
+        } else {
+            let message = format!(
+                "Clock not synchronized over NTP within {} seconds",
+                NTP_SYNC_TIMEOUT
+            );
+            warn!("{}", message);
+            self.state.record_error(message);
+        }
     }
 
-    // Try to get the currently connected network by checking access points
-    let wifi_device = device.as_wifi_device().unwrap();
-    
-    // First, try to scan for current access points to see which one we're connected to
-    if let Ok(access_points) = wifi_device.get_access_points() {
-        // Look for access points with high signal strength that might indicate connection
-        for ap in &access_points {
-            if let Ok(ssid) = ap.ssid().as_str() {
-                if !ssid.is_empty() && ap.strength > 50 { // Assume high signal might indicate connection
-                    // Check if we have a saved connection for this SSID
-                    let connections = manager.get_connections()?;
-                    for connection in connections {
-                        if is_wifi_connection(&connection) {
-                            let settings = connection.settings();
-                            if let Ok(conn_ssid) = settings.ssid.as_str() {
-                                if conn_ssid == ssid {
-                                    return Ok(Some(ConnectedNetwork {
-                                        ssid: ssid.to_string(),
-                                        security: get_network_security(ap).to_string(),
-                                        signal_strength: (ap.strength as u8).min(100),
-                                        interface: device.interface().to_string(),
-                                        ip_address: None,
-                                    }));
-                                }
-                            }
-                        }
-                    }
+    /// Polls for an address assigned to the connected network, giving up
+    /// early on shutdown. An IPv6-only network without a DHCP lease counts
+    /// once SLAAC or DHCPv6 assigned a global address.
+    fn wait_for_address(&self, timeout: u64) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+
+        loop {
+            match self.backend.connected_network() {
+                Ok(Some(ref connected))
+                    if connected.ip_address.is_some() || !connected.ipv6_addresses.is_empty() =>
+                {
+                    return true
                 }
+                Ok(_) => {}
+                Err(e) => debug!("Getting the connected network failed: {}", e),
+            }
+
+            if Instant::now() >= deadline || self.shutdown.sleep(Duration::from_secs(1)) {
+                return false;
             }
         }
     }
 
-    Ok(None)
-}
-
-// New function to list all saved networks
-pub fn get_saved_networks(manager: &NetworkManager) -> Result<Vec<SavedNetwork>> {
-    let connections = manager.get_connections()?;
-    let mut saved_networks = Vec::new();
-    let mut seen_ssids = HashSet::new();
-
-    for connection in &connections {
-        if is_wifi_connection(connection) && !is_access_point_connection(connection) {
-            let settings = connection.settings();
-            
-            if let Ok(ssid) = settings.ssid.as_str() {
-                if !ssid.is_empty() && !seen_ssids.contains(ssid) {
-                    seen_ssids.insert(ssid.to_string());
-                    
-                    // Simplified security detection - could be enhanced
-                    let security = "wpa"; // Default assumption for saved networks
-
-                    saved_networks.push(SavedNetwork {
-                        ssid: ssid.to_string(),
-                        security: security.to_string(),
-                    });
-                }
-            }
+    /// Networks that are out of range or listed after the activated one are
+    /// saved so that they can be used as fallbacks later
+    fn save_network(&mut self, network: &NetworkCredentials, settings: &ProfileSettings) {
+        if let Err(e) = self.backend.save_network(network, settings) {
+            warn!("Saving network '{}' failed: {}", network.ssid, e);
         }
     }
+}
 
-    saved_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
-    Ok(saved_networks)
+/// Runs the speed test requested with `--speed-test` and logs the outcome
+fn report_speed_test(url: &str) {
+    info!("Running speed test against {}", url);
+
+    match run_speed_test(url) {
+        Ok(result) => info!(
+            "Speed test: {:.1} Mbps, {:.0} ms latency ({} bytes)",
+            result.mbps, result.latency_ms, result.bytes
+        ),
+        Err(e) => warn!("{}", e),
+    }
 }
 
-// New function to forget a specific network
-pub fn forget_specific_network(manager: &NetworkManager, ssid: &str) -> Result<bool> {
-    let connections = manager.get_connections()?;
-    let mut found = false;
-
-    for connection in &connections {
-        if is_wifi_connection(connection) && !is_access_point_connection(connection) {
-            if let Some(connection_ssid) = connection_ssid_as_str(connection) {
-                if connection_ssid == ssid {
-                    info!("Forgetting WiFi network: {}", ssid);
-                    connection.delete().chain_err(|| ErrorKind::DeleteAccessPoint)?;
-                    found = true;
-                }
-            }
-        }
+/// Activates the profile WiFi Connect saved for the network with `nmcli`,
+/// deleting it again should the network not come up
+pub fn activate_profile(interface: &str, ssid: &str, timeout: u64) -> Result<()> {
+    let id = managed_connection_id(ssid);
+
+    if nmcli(&[
+        "--wait",
+        &timeout.to_string(),
+        "connection",
+        "up",
+        "id",
+        &id,
+        "ifname",
+        interface,
+    ]) {
+        return Ok(());
     }
 
-    if !found {
-        warn!("Network '{}' not found in saved connections", ssid);
+    // Read before deleting the connection moves the device on
+    let reason = get_device_state_reason(interface);
+
+    if !nmcli(&["connection", "delete", "id", &id]) {
+        error!("Deleting connection '{}' failed", id);
     }
 
-    Ok(found)
+    bail!(connection_failure(ssid, "Deactivated", reason))
 }
 
-fn create_portal(device: &Device, config: &Config) -> Result<Connection> {
-    let portal_passphrase = config.passphrase.as_ref().map(|p| p as &str);
+pub fn process_network_commands(config: &Config, exit_tx: &Sender<ExitResult>) {
+    let mut command_handler = match NetworkCommandHandler::new(config, exit_tx) {
+        Ok(command_handler) => command_handler,
+        Err(e) => {
+            exit(exit_tx, e);
+            return;
+        }
+    };
 
-    create_portal_impl(device, &config.ssid, &config.gateway, &portal_passphrase)
-        .chain_err(|| ErrorKind::CreateCaptivePortal)
+    command_handler.run(exit_tx);
 }
 
-fn create_portal_impl(
-    device: &Device,
-    ssid: &str,
-    gateway: &Ipv4Addr,
-    passphrase: &Option<&str>,
-) -> Result<Connection> {
-    info!("Starting access point...");
-    let wifi_device = device.as_wifi_device().unwrap();
-    let (portal_connection, _) = wifi_device.create_hotspot(ssid, *passphrase, Some(*gateway))?;
-    info!("Access point '{}' created", ssid);
-    Ok(portal_connection)
-}
+pub fn init_networking(config: &Config) -> Result<()> {
+    set_nm_retries(config.nm_retries);
 
-fn stop_portal(connection: &Connection, config: &Config) -> Result<()> {
-    stop_portal_impl(connection, config).chain_err(|| ErrorKind::StopAccessPoint)
-}
+    #[cfg(feature = "network-manager")]
+    {
+        if config.backend == ::backend::DEFAULT_BACKEND {
+            delete_exising_wifi_connect_ap_profile(&config.ssid)
+                .chain_err(|| ErrorKind::DeleteAccessPoint)?;
+        }
+    }
 
-fn stop_portal_impl(connection: &Connection, config: &Config) -> Result<()> {
-    info!("Stopping access point '{}'...", config.ssid);
-    connection.deactivate()?;
-    connection.delete()?;
-    thread::sleep(Duration::from_secs(1));
-    info!("Access point '{}' stopped", config.ssid);
     Ok(())
 }
 
-pub fn wait_for_connectivity(manager: &NetworkManager, timeout: u64) -> Result<bool> {
-    let mut total_time = 0;
-
-    loop {
-        let connectivity = manager.get_connectivity()?;
+/// Reads the first IPv4 address assigned to an interface from `ip addr`
+pub fn get_interface_ipv4(interface: &str) -> Option<String> {
+    let output = process::Command::new("ip")
+        .args(&["-4", "-o", "addr", "show", "dev", interface])
+        .output()
+        .ok()?;
 
-        if connectivity == Connectivity::Full || connectivity == Connectivity::Limited {
-            debug!(
-                "Connectivity established: {:?} / {}s elapsed",
-                connectivity, total_time
-            );
-
-            return Ok(true);
-        } else if total_time >= timeout {
-            debug!(
-                "Timeout reached in waiting for connectivity: {:?} / {}s elapsed",
-                connectivity, total_time
-            );
-
-            return Ok(false);
-        }
-
-        ::std::thread::sleep(::std::time::Duration::from_secs(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut tokens = stdout.split_whitespace();
 
-        total_time += 1;
+    tokens.find(|token| *token == "inet")?;
 
-        debug!(
-            "Still waiting for connectivity: {:?} / {}s elapsed",
-            connectivity, total_time
-        );
-    }
+    tokens
+        .next()
+        .and_then(|address| address.split('/').next())
+        .map(|address| address.to_string())
 }
 
-pub fn start_network_manager_service() -> Result<()> {
-    let state = match NetworkManager::get_service_state() {
-        Ok(state) => state,
-        _ => {
-            info!("Cannot get the NetworkManager service state");
-            return Ok(());
-        }
+/// Global IPv6 addresses of the interface, leaving out link-local ones that
+/// every interface has
+pub fn get_interface_ipv6(interface: &str) -> Vec<String> {
+    let output = match process::Command::new("ip")
+        .args(&[
+            "-6", "-o", "addr", "show", "dev", interface, "scope", "global",
+        ])
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
     };
 
-    if state != ServiceState::Active {
-        let state =
-            NetworkManager::start_service(15).chain_err(|| ErrorKind::StartNetworkManager)?;
-        if state != ServiceState::Active {
-            bail!(ErrorKind::StartActiveNetworkManager);
-        } else {
-            info!("NetworkManager service started successfully");
-        }
-    } else {
-        debug!("NetworkManager service already running");
-    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
 
-    Ok(())
+    stdout
+        .lines()
+        .filter(|line| !line.contains("tentative"))
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            tokens.find(|token| *token == "inet6")?;
+            tokens.next()?.split('/').next().map(String::from)
+        })
+        .collect()
 }
 
-fn delete_exising_wifi_connect_ap_profile(ssid: &str) -> Result<()> {
-    let manager = NetworkManager::new();
-
-    for connection in &manager.get_connections()? {
-        if is_access_point_connection(connection) && is_same_ssid(connection, ssid) {
-            info!(
-                "Deleting already created by WiFi Connect access point connection profile: {:?}",
-                connection.settings().ssid,
-            );
-            connection.delete()?;
-        }
-    }
-
-    Ok(())
+/// IPv4 address of the interface, or its first global IPv6 address on an
+/// IPv6-only network
+pub fn get_interface_address(interface: &str) -> Option<String> {
+    get_interface_ipv4(interface).or_else(|| get_interface_ipv6(interface).into_iter().next())
 }
 
-fn delete_existing_connections_to_same_network(manager: &NetworkManager, ssid: &str) {
-    let connections = match manager.get_connections() {
-        Ok(connections) => connections,
+/// Probes Internet access with a TCP connection to a host resolved to both
+/// IPv4 and IPv6 addresses, for when NetworkManager has its connectivity
+/// check disabled
+pub fn probe_connectivity() -> bool {
+    let addresses = match CONNECTIVITY_PROBE_HOST.to_socket_addrs() {
+        Ok(addresses) => addresses,
         Err(e) => {
-            error!("Getting existing connections failed: {}", e);
-            return;
+            debug!("Resolving {} failed: {}", CONNECTIVITY_PROBE_HOST, e);
+            return false;
         }
     };
 
-    for connection in &connections {
-        if is_wifi_connection(connection) && is_same_ssid(connection, ssid) {
-            info!(
-                "Deleting existing WiFi connection to the same network: {:?}",
-                connection.settings().ssid,
-            );
+    let timeout = Duration::from_secs(CONNECTIVITY_PROBE_TIMEOUT);
 
-            if let Err(e) = connection.delete() {
-                error!("Deleting existing WiFi connection failed: {}", e);
-            }
+    for address in addresses {
+        if TcpStream::connect_timeout(&address, timeout).is_ok() {
+            debug!("Connectivity probe reached {}", address);
+            return true;
         }
     }
-}
-
-fn is_same_ssid(connection: &Connection, ssid: &str) -> bool {
-    connection_ssid_as_str(connection) == Some(ssid)
-}
 
-fn connection_ssid_as_str(connection: &Connection) -> Option<&str> {
-    // An access point SSID could be random bytes and not a UTF-8 encoded string
-    connection.settings().ssid.as_str().ok()
-}
+    false
+}
+
+/// Reason NetworkManager gives for the last state change of a device, read
+/// from the `StateReason` property of its D-Bus object
+pub fn get_device_state_reason(interface: &str) -> Option<u32> {
+    let path = get_device_path(interface)?;
+
+    let output = process::Command::new("dbus-send")
+        .args(&[
+            "--system",
+            "--print-reply",
+            "--dest=org.freedesktop.NetworkManager",
+            &path,
+            "org.freedesktop.DBus.Properties.Get",
+            "string:org.freedesktop.NetworkManager.Device",
+            "string:StateReason",
+        ])
+        .output()
+        .ok()?;
+
+    // The reply is a struct of the device state followed by the reason
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut tokens = stdout.split_whitespace();
+    let mut values = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        if token == "uint32" {
+            values.push(tokens.next()?.parse::<u32>().ok()?);
+        }
+    }
 
-fn is_access_point_connection(connection: &Connection) -> bool {
-    is_wifi_connection(connection) && connection.settings().mode == "ap"
+    values.get(1).cloned()
 }
 
-fn is_wifi_connection(connection: &Connection) -> bool {
-    connection.settings().kind == "802-11-wireless"
-}
+/// D-Bus object path of the NetworkManager device of `interface`
+pub fn get_device_path(interface: &str) -> Option<String> {
+    let output = process::Command::new("nmcli")
+        .args(&["-g", "GENERAL.DBUS-PATH", "device", "show", interface])
+        .output()
+        .ok()?;
 
-pub fn disconnect_from_network(manager: &NetworkManager, interface: &Option<String>) -> Result<()> {
-    let device = find_device(manager, interface)?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
-    // Check if device is connected
-    let device_state = device.get_state()?;
-    if device_state != DeviceState::Activated {
-        println!("No active connection found.");
-        return Ok(());
+    if !output.status.success() || path.is_empty() {
+        return None;
     }
 
-    // Deactivate the device to disconnect from the network
-    device.disconnect()?; // Assuming there is a `disconnect` method
-
-    println!("Disconnected successfully.");
-
-    Ok(())
+    Some(path)
 }
 
-pub fn forget_all_wifi_connections(manager: &NetworkManager) -> Result<()> {
-    let connections = match manager.get_connections() {
-        Ok(connections) => connections,
-        Err(e) => {
-            error!("Getting existing connections failed: {}", e);
-            return Err(e.into());
-        }
-    };
-
-    info!("Forgetting all WiFi connections...");
-    
-    for connection in &connections {
-        if is_wifi_connection(connection) {
-            if let Some(ssid) = connection_ssid_as_str(connection) {
-                info!("Deleting WiFi connection: {}", ssid);
-                
-                if let Err(e) = connection.delete() {
-                    error!("Deleting WiFi connection failed: {}", e);
-                }
-            }
+/// Error for a connection that did not activate, telling a wrong password
+/// apart from association and DHCP failures by the device state reason
+pub fn connection_failure(ssid: &str, state: &str, reason: Option<u32>) -> ErrorKind {
+    match reason {
+        Some(REASON_NO_SECRETS) | Some(REASON_SUPPLICANT_DISCONNECT) => {
+            ErrorKind::AuthenticationFailed(ssid.into())
         }
+        Some(REASON_SUPPLICANT_TIMEOUT) => ErrorKind::AssociationTimeout(ssid.into()),
+        Some(REASON_IP_CONFIG_UNAVAILABLE)
+        | Some(REASON_DHCP_START_FAILED)
+        | Some(REASON_DHCP_ERROR)
+        | Some(REASON_DHCP_FAILED) => ErrorKind::DhcpFailed(ssid.into()),
+        Some(REASON_SSID_NOT_FOUND) => ErrorKind::NetworkNotFound(ssid.into()),
+        _ => ErrorKind::ConnectionNotActivated(ssid.into(), state.into()),
     }
-    
-    Ok(())
 }
-
-pub fn list_connected_connections() -> Result<Vec<Connection>> {
-    let manager = NetworkManager::new();
-    
-    let connections = match manager.get_connections() {
-        Ok(conns) => conns,
-        Err(e) => {
-            warn!("Failed to get connections: {}", e);
-            return Ok(Vec::new()); // Return empty list instead of failing completely
-        }
-    };
-    
-    let connected_connections: Vec<Connection> = connections.into_iter()
-        .filter_map(|conn| {
-            match conn.get_state() {
-                Ok(ConnectionState::Activated) => {
-                    // Additional check: make sure the connection has valid devices
-                    match conn.get_devices() {
-                        Ok(devices) => {
-                            // Skip connections that only have unknown/problematic device types
-                            let has_known_devices = devices.iter().any(|device| {
-                                !matches!(device.device_type(), DeviceType::Unknown)
-                            });
-                            
-                            if has_known_devices {
-                                Some(conn)
-                            } else {
-                                info!("Skipping connection with only unknown device types");
-                                None
-                            }
-                        },
-                        Err(e) => {
-                            info!("Skipping connection due to device enumeration error: {}", e);
-                            None
-                        }
-                    }
-                },
-                Ok(_) => None,
-                Err(e) => {
-                    debug!("Skipping connection due to state error: {}", e);
-                    None // Skip problematic connections instead of failing
-                }
-            }
-        })
-        .collect();
-    
-    Ok(connected_connections)
-}
\ No newline at end of file
@@ -0,0 +1,1277 @@
+//! NetworkManager side of the `network-manager` backend, driving it over
+//! D-Bus through the network-manager crate and with `nmcli` where the crate
+//! falls short. Only built with the `network-manager` Cargo feature.
+
+use std::cmp;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use network_manager::{
+    AccessPoint, AccessPointCredentials, Connection, ConnectionState, Connectivity, Device,
+    DeviceState, DeviceType, NetworkManager, Security, ServiceState,
+};
+
+use backend::{ForgetFilter, ForgetScope, GcPolicy};
+use certificates::EapTls;
+use channels::{resolve_portal_channel, Band};
+use config::Config;
+use connections::with_connections;
+use dbus::{self, Value};
+use errors::*;
+use exit::Shutdown;
+use ipconfig::get_ip_configs;
+use link::get_link_statistics;
+use network::{
+    activate_profile, get_interface_ipv4, get_interface_ipv6, probe_connectivity, ConnectedNetwork,
+    Network, NetworkCredentials, SavedNetwork, SavedNetworkDetails,
+};
+use profile::{
+    add_connection_profile, apply_and_reactivate, has_managed_marker, profile_security,
+    read_connection_timestamps, read_profile_properties, remove_profile_setting,
+    update_connection_profile, ProfileSettings, ProfileUpdate, MANAGED_ID_PREFIX,
+};
+use retry::{retry_nm, retry_nm_change};
+use ssid::{display_ssid, matches_ssid};
+use wiphy::{supports_ap_mode, WiFiInterface};
+
+#[derive(Debug, Serialize)]
+pub struct ModemStatus {
+    pub interface: String,
+    pub state: String,
+    pub connected: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    pub version: String,
+    pub wifi_connected: bool,
+    pub modems: Vec<ModemStatus>,
+}
+
+/// Saves a network that is not being activated right now as a connection
+/// profile, so that NetworkManager can fall back to it later
+pub fn add_network_profile(
+    network: &NetworkCredentials,
+    access_points: &[AccessPoint],
+    certificates: Option<&EapTls>,
+    settings: &ProfileSettings,
+) -> Result<()> {
+    let security = match find_access_point(access_points, &network.ssid) {
+        Some(access_point) => get_network_security(access_point),
+        None if network.passphrase.is_empty() => "none",
+        None => "wpa",
+    };
+
+    info!("Saving network '{}' for later use", network.ssid);
+
+    add_connection_profile(
+        &network.ssid,
+        security,
+        &network.identity,
+        network.passphrase.expose(),
+        certificates,
+        settings,
+    )
+}
+
+/// Connects to an enterprise network over EAP-TLS with the certificates
+/// uploaded for it, or else with the configured EAP method. The
+/// NetworkManager bindings only know of PEAP with MSCHAPv2, so the profile is
+/// added and activated with `nmcli`.
+pub fn connect_enterprise(
+    interface: &str,
+    network: &NetworkCredentials,
+    certificates: Option<&EapTls>,
+    settings: &ProfileSettings,
+    timeout: u64,
+) -> Result<()> {
+    add_connection_profile(
+        &network.ssid,
+        "enterprise",
+        &network.identity,
+        network.passphrase.expose(),
+        certificates,
+        settings,
+    )?;
+
+    activate_profile(interface, &network.ssid, timeout)
+}
+
+/// Connects by adding the profile with `nmcli` first, for settings that have
+/// to be in place before the first association, like a cloned MAC address,
+/// which the NetworkManager bindings cannot add
+pub fn connect_with_profile(
+    interface: &str,
+    access_point: &AccessPoint,
+    network: &NetworkCredentials,
+    settings: &ProfileSettings,
+    timeout: u64,
+) -> Result<()> {
+    add_connection_profile(
+        &network.ssid,
+        get_network_security(access_point),
+        &network.identity,
+        network.passphrase.expose(),
+        None,
+        settings,
+    )?;
+
+    activate_profile(interface, &network.ssid, timeout)
+}
+
+pub fn init_access_point_credentials(
+    access_point: &AccessPoint,
+    identity: &str,
+    passphrase: &str,
+) -> AccessPointCredentials {
+    if access_point.security.contains(Security::ENTERPRISE) {
+        AccessPointCredentials::Enterprise {
+            identity: identity.to_string(),
+            passphrase: passphrase.to_string(),
+        }
+    } else if access_point.security.contains(Security::WPA2)
+        || access_point.security.contains(Security::WPA)
+    {
+        AccessPointCredentials::Wpa {
+            passphrase: passphrase.to_string(),
+        }
+    } else if access_point.security.contains(Security::WEP) {
+        AccessPointCredentials::Wep {
+            passphrase: passphrase.to_string(),
+        }
+    } else {
+        AccessPointCredentials::None
+    }
+}
+
+/// Connects to the access point and applies the configured profile settings
+/// to the resulting connection. Waits up to `timeout` seconds for a
+/// connection that is still activating, e.g. on slow enterprise networks.
+pub fn connect_to_access_point(
+    device: &Device,
+    access_point: &AccessPoint,
+    credentials: &AccessPointCredentials,
+    settings: &ProfileSettings,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Result<(Connection, ConnectionState)> {
+    let wifi_device = device.as_wifi_device().unwrap();
+
+    // Profiles for the network were deleted beforehand, so one showing up
+    // was added by the call that failed
+    let ssid = access_point.ssid().as_bytes();
+    let (connection, state) = retry_nm_change(
+        "Activating the connection",
+        || find_added_connection(|connection| !is_access_point_connection(connection), ssid),
+        || wifi_device.connect(access_point, credentials),
+    )?;
+    let state = wait_for_activation(&connection, state, timeout, shutdown)?;
+
+    if settings.is_empty() {
+        return Ok((connection, state));
+    }
+
+    // Settings like the band only take effect on activation. A cloned MAC
+    // address is set up front by adding the profile with `nmcli` instead.
+    let state = match apply_and_reactivate(&connection, settings, state.clone()) {
+        Ok(state) => wait_for_activation(&connection, state, timeout, shutdown)?,
+        Err(e) => {
+            warn!("Applying connection profile settings failed: {}", e);
+            state
+        }
+    };
+
+    Ok((connection, state))
+}
+
+/// Connection to `ssid` a failed call added anyway, activated if it is not
+/// already
+fn find_added_connection<F>(matches: F, ssid: &[u8]) -> Option<(Connection, ConnectionState)>
+where
+    F: Fn(&Connection) -> bool,
+{
+    let connections = NetworkManager::new().get_connections().ok()?;
+
+    let connection = connections.into_iter().find(|connection| {
+        is_wifi_connection(connection)
+            && connection.settings().ssid.as_bytes() == ssid
+            && matches(connection)
+    })?;
+
+    let state = match activation_state(&connection) {
+        Some(state) => state,
+        None => connection.activate().ok()?,
+    };
+
+    Some((connection, state))
+}
+
+/// State of a connection that is activating or activated
+fn activation_state(connection: &Connection) -> Option<ConnectionState> {
+    match connection.get_state() {
+        Ok(ConnectionState::Activating) => Some(ConnectionState::Activating),
+        Ok(ConnectionState::Activated) => Some(ConnectionState::Activated),
+        _ => None,
+    }
+}
+
+/// Activates a connection, without activating it again should a call that
+/// failed with a transient D-Bus error have done so
+pub fn activate_connection(connection: &Connection) -> Result<ConnectionState> {
+    retry_nm_change(
+        "Activating the connection",
+        || activation_state(connection),
+        || connection.activate(),
+    )
+}
+
+pub fn deactivate_connection(connection: &Connection) -> Result<()> {
+    retry_nm_change(
+        "Deactivating the connection",
+        || match connection.get_state() {
+            Ok(ConnectionState::Deactivating) | Ok(ConnectionState::Deactivated) => Some(()),
+            _ => None,
+        },
+        || connection.deactivate().map(|_| ()),
+    )
+}
+
+/// Deletes a connection profile, done once it is no longer listed
+pub fn delete_connection(connection: &Connection) -> Result<()> {
+    let uuid = &connection.settings().uuid;
+
+    retry_nm_change(
+        "Deleting the connection",
+        || {
+            let connections = NetworkManager::new().get_connections().ok()?;
+
+            if connections.iter().any(|other| other.settings().uuid == *uuid) {
+                None
+            } else {
+                Some(())
+            }
+        },
+        || connection.delete(),
+    )
+}
+
+fn wait_for_activation(
+    connection: &Connection,
+    mut state: ConnectionState,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Result<ConnectionState> {
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    while state == ConnectionState::Activating && Instant::now() < deadline {
+        if shutdown.sleep(Duration::from_secs(1)) {
+            break;
+        }
+        state = retry_nm("Getting the connection state", || connection.get_state())?;
+    }
+
+    Ok(state)
+}
+
+pub fn find_device(manager: &NetworkManager, interface: &Option<String>) -> Result<Device> {
+    if let Some(ref interface) = *interface {
+        let device = retry_nm("Getting the device", || {
+            manager.get_device_by_interface(interface)
+        })
+        .chain_err(|| ErrorKind::DeviceByInterface(interface.clone()))?;
+
+        info!("Targeted WiFi device: {}", interface);
+
+        if *device.device_type() != DeviceType::WiFi {
+            bail!(ErrorKind::NotAWiFiDevice(interface.clone()))
+        }
+
+        let state = retry_nm("Getting the device state", || device.get_state())?;
+
+        if state == DeviceState::Unmanaged {
+            bail!(ErrorKind::UnmanagedDevice(interface.clone()))
+        }
+
+        Ok(device)
+    } else {
+        let devices = retry_nm("Listing devices", || manager.get_devices())?;
+
+        if let Some(device) = find_wifi_managed_device(devices)? {
+            info!("WiFi device: {}", device.interface());
+            Ok(device)
+        } else {
+            bail!(ErrorKind::NoWiFiDevice)
+        }
+    }
+}
+
+/// Lists WWAN modem devices, e.g. an LTE backup uplink
+pub fn get_modems(manager: &NetworkManager) -> Result<Vec<ModemStatus>> {
+    let mut modems = Vec::new();
+
+    for device in retry_nm("Listing devices", || manager.get_devices())? {
+        if *device.device_type() != DeviceType::Modem {
+            continue;
+        }
+
+        let state = retry_nm("Getting the device state", || device.get_state())?;
+
+        modems.push(ModemStatus {
+            interface: device.interface().to_string(),
+            state: format!("{:?}", state),
+            connected: state == DeviceState::Activated,
+        });
+    }
+
+    Ok(modems)
+}
+
+/// Whether a station (not access point) WiFi connection is active
+pub fn is_wifi_connected(manager: &NetworkManager) -> Result<bool> {
+    let connections = retry_nm("Listing active connections", || {
+        manager.get_active_connections()
+    })?;
+
+    Ok(connections
+        .iter()
+        .any(|connection| is_wifi_connection(connection) && !is_access_point_connection(connection)))
+}
+
+pub fn get_device_info(manager: &NetworkManager) -> Result<DeviceInfo> {
+    Ok(DeviceInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        wifi_connected: is_wifi_connected(manager)?,
+        modems: get_modems(manager)?,
+    })
+}
+
+/// Picks the WiFi device for the portal when several radios are managed,
+/// preferring ones that support access point mode and are not connected
+fn find_wifi_managed_device(devices: Vec<Device>) -> Result<Option<Device>> {
+    let mut candidates = Vec::new();
+
+    for device in devices {
+        if *device.device_type() != DeviceType::WiFi {
+            continue;
+        }
+
+        let state = retry_nm("Getting the device state", || device.get_state())?;
+
+        if state == DeviceState::Unmanaged {
+            continue;
+        }
+
+        let ap_capable = supports_ap_mode(device.interface()) != Some(false);
+        let connected = state == DeviceState::Activated;
+
+        candidates.push((!ap_capable, connected, device));
+    }
+
+    if candidates.len() > 1 {
+        debug!(
+            "Multiple WiFi devices found: {}",
+            candidates
+                .iter()
+                .map(|&(_, _, ref device)| device.interface())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // Stable sort keeps NetworkManager's order among equally suited devices
+    candidates.sort_by_key(|&(not_ap_capable, connected, _)| (not_ap_capable, connected));
+
+    Ok(candidates.into_iter().next().map(|(_, _, device)| device))
+}
+
+/// WiFi devices known to NetworkManager with their device state
+pub fn list_wifi_interfaces(manager: &NetworkManager) -> Result<Vec<WiFiInterface>> {
+    let mut interfaces = Vec::new();
+
+    for device in retry_nm("Listing devices", || manager.get_devices())? {
+        if *device.device_type() != DeviceType::WiFi {
+            continue;
+        }
+
+        let state = retry_nm("Getting the device state", || device.get_state())?;
+        let state = format!("{:?}", state);
+        interfaces.push(WiFiInterface::new(device.interface(), state));
+    }
+
+    Ok(interfaces)
+}
+
+pub fn get_access_points(
+    device: &Device,
+    ssid: &str,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Result<Vec<AccessPoint>> {
+    get_access_points_impl(device, ssid, timeout, shutdown).chain_err(|| ErrorKind::NoAccessPoints)
+}
+
+fn get_access_points_impl(
+    device: &Device,
+    ssid: &str,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Result<Vec<AccessPoint>> {
+    info!("Scanning for available networks...");
+    let retries_allowed = cmp::max(timeout, 1);
+    let mut retries = 0;
+
+    // After stopping the hotspot we may have to wait a bit for the list
+    // of access points to become available
+    while retries < retries_allowed {
+        let wifi_device = device.as_wifi_device().unwrap();
+        let mut access_points =
+            retry_nm("Listing access points", || wifi_device.get_access_points())?;
+
+        // Purge access points with duplicate SSIDs
+        let mut inserted = HashSet::new();
+        access_points.retain(|ap| inserted.insert(ap.ssid.clone()));
+
+        // Remove access points without SSID (hidden)
+        access_points.retain(|ap| !ap.ssid().as_bytes().is_empty());
+
+        // Only filter by SSID if a specific SSID was provided
+        if !ssid.is_empty() {
+            access_points.retain(|ap| ap.ssid().as_bytes() != ssid.as_bytes());
+        }
+
+        if !access_points.is_empty() {
+            info!(
+                "Found {} access points: {:?}",
+                access_points.len(),
+                get_access_points_ssids(&access_points)
+            );
+            return Ok(access_points);
+        }
+
+        retries += 1;
+        info!("No access points found - retry #{}", retries);
+
+        if shutdown.sleep(Duration::from_secs(1)) {
+            break;
+        }
+    }
+
+    warn!("No access points found - giving up...");
+    Ok(vec![])
+}
+
+/// When the WiFi device last completed a scan, in `CLOCK_BOOTTIME`
+/// milliseconds. The network-manager crate does not expose `LastScan`, so it
+/// is read over the D-Bus client of WiFi Connect. NetworkManager only has it
+/// since 1.12.
+pub fn get_last_scan(interface: &str) -> Option<i64> {
+    const NM: &str = "org.freedesktop.NetworkManager";
+
+    let bus = dbus::Connection::system().ok()?;
+
+    let reply = bus
+        .call(
+            NM,
+            "/org/freedesktop/NetworkManager",
+            NM,
+            "GetDeviceByIpIface",
+            &[Value::String(interface.to_string())],
+        )
+        .ok()?;
+    let device = reply.first()?.as_str()?;
+
+    bus.get_property(
+        NM,
+        device,
+        "org.freedesktop.NetworkManager.Device.Wireless",
+        "LastScan",
+    )
+    .ok()?
+    .as_i64()
+}
+
+fn get_access_points_ssids(access_points: &[AccessPoint]) -> Vec<String> {
+    access_points
+        .iter()
+        .map(|ap| display_ssid(ap.ssid().as_bytes()))
+        .collect()
+}
+
+pub fn get_networks(
+    device: &Device,
+    ssid: &String,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Vec<Network> {
+    let access_points = get_access_points_impl(device, ssid, timeout, shutdown).unwrap_or_default();
+    access_points.iter().map(get_network_info).collect()
+}
+
+pub fn get_networks_from_access_points(access_points: &[AccessPoint]) -> Vec<Network> {
+    access_points.iter().map(get_network_info).collect()
+}
+
+fn get_network_info(access_point: &AccessPoint) -> Network {
+    Network::new(
+        access_point.ssid().as_bytes(),
+        get_network_security(access_point),
+        (access_point.strength as u8).min(100),
+    )
+}
+
+fn get_network_security(access_point: &AccessPoint) -> &str {
+    if access_point.security.contains(Security::ENTERPRISE) {
+        "enterprise"
+    } else if access_point.security.contains(Security::WPA2)
+        || access_point.security.contains(Security::WPA)
+    {
+        "wpa"
+    } else if access_point.security.contains(Security::WEP) {
+        "wep"
+    } else {
+        "none"
+    }
+}
+
+pub fn find_access_point<'a>(access_points: &'a [AccessPoint], ssid: &str) -> Option<&'a AccessPoint> {
+    access_points
+        .iter()
+        .find(|access_point| matches_ssid(access_point.ssid().as_bytes(), ssid))
+}
+
+// New function to get currently connected network - improved version
+pub fn get_connected_network(manager: &NetworkManager, interface: &Option<String>) -> Result<Option<ConnectedNetwork>> {
+    let device = find_device(manager, interface)?;
+    
+    // Check if device is connected
+    let device_state = retry_nm("Getting the device state", || device.get_state())?;
+    if device_state != DeviceState::Activated {
+        return Ok(None);
+    }
+
+    // Try to get the currently connected network by checking access points
+    let wifi_device = device.as_wifi_device().unwrap();
+    
+    // First, try to scan for current access points to see which one we're connected to
+    if let Ok(access_points) =
+        retry_nm("Listing access points", || wifi_device.get_access_points()) {
+        // Look for access points with high signal strength that might indicate connection
+        for ap in &access_points {
+            let ssid = ap.ssid().as_bytes();
+            if !ssid.is_empty() && ap.strength > 50 { // Assume high signal might indicate connection
+                // Check if we have a saved connection for this SSID
+                let connections =
+                    retry_nm("Listing connections", || manager.get_connections())?;
+                for connection in connections {
+                    if is_wifi_connection(&connection) {
+                        let settings = connection.settings();
+                        if settings.ssid.as_bytes() == ssid {
+                            let (ipv4, ipv6) = get_ip_configs(device.interface());
+
+                            return Ok(Some(ConnectedNetwork {
+                                ssid: display_ssid(ssid),
+                                security: get_network_security(ap).to_string(),
+                                signal_strength: (ap.strength as u8).min(100),
+                                interface: device.interface().to_string(),
+                                ip_address: get_interface_ipv4(device.interface()),
+                                ipv6_addresses: get_interface_ipv6(device.interface()),
+                                ipv4,
+                                ipv6,
+                                link: get_link_statistics(device.interface()),
+                                vpn: None,
+                            }));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// New function to list all saved networks
+pub fn get_saved_networks(manager: &NetworkManager) -> Result<Vec<SavedNetwork>> {
+    let mut saved_networks = with_connections(manager, |connections| {
+        let mut saved_networks = Vec::new();
+        let mut seen_ssids = HashSet::new();
+
+        for connection in connections {
+            if is_wifi_connection(connection) && !is_access_point_connection(connection) {
+                let ssid = display_ssid(connection.settings().ssid.as_bytes());
+
+                if !ssid.is_empty() && seen_ssids.insert(ssid.clone()) {
+                    // Simplified security detection - could be enhanced
+                    let security = "wpa"; // Default assumption for saved networks
+
+                    saved_networks.push(SavedNetwork {
+                        ssid,
+                        security: security.to_string(),
+                    });
+                }
+            }
+        }
+
+        saved_networks
+    })?;
+
+    saved_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+    Ok(saved_networks)
+}
+
+/// Details of the saved profile of a network, read with `nmcli` as the
+/// network-manager crate only exposes a few of the settings
+pub fn get_saved_network_details(
+    manager: &NetworkManager,
+    ssid: &str,
+) -> Result<Option<SavedNetworkDetails>> {
+    let connection = match find_saved_connection(manager, ssid)? {
+        Some(connection) => connection,
+        None => return Ok(None),
+    };
+
+    let settings = connection.settings();
+
+    let properties = read_profile_properties(
+        &settings.uuid,
+        &[
+            "connection.autoconnect",
+            "connection.autoconnect-priority",
+            "connection.timestamp",
+            "802-11-wireless.cloned-mac-address",
+            "802-11-wireless-security.key-mgmt",
+            "ipv4.method",
+            "ipv4.dns",
+            "ipv6.method",
+            "ipv6.dns",
+        ],
+    )?;
+
+    let property = |name: &str| {
+        properties
+            .get(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let security = profile_security(property("802-11-wireless-security.key-mgmt"));
+
+    let dns = ["ipv4.dns", "ipv6.dns"]
+        .iter()
+        .filter_map(|name| property(name))
+        .flat_map(|servers| {
+            servers
+                .split(',')
+                .map(|server| server.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(Some(SavedNetworkDetails {
+        ssid: display_ssid(settings.ssid.as_bytes()),
+        security: security.to_string(),
+        autoconnect: property("connection.autoconnect").map_or(true, |value| value == "yes"),
+        autoconnect_priority: property("connection.autoconnect-priority")
+            .and_then(|value| value.parse().ok()),
+        // Zero until the profile is activated for the first time
+        last_used: property("connection.timestamp")
+            .and_then(|value| value.parse().ok())
+            .filter(|&timestamp| timestamp != 0),
+        ipv4_method: property("ipv4.method"),
+        ipv6_method: property("ipv6.method"),
+        dns,
+        cloned_mac: property("802-11-wireless.cloned-mac-address"),
+    }))
+}
+
+// New function to forget a specific network
+/// Changes the saved profile of a network, returning whether there is one
+pub fn update_saved_network(
+    manager: &NetworkManager,
+    ssid: &str,
+    update: &ProfileUpdate,
+) -> Result<bool> {
+    let connection = match find_saved_connection(manager, ssid)? {
+        Some(connection) => connection,
+        None => {
+            warn!("Network '{}' not found in saved connections", ssid);
+            return Ok(false);
+        }
+    };
+
+    let uuid = connection.settings().uuid.clone();
+
+    let key_mgmt = read_key_mgmt(&uuid)?;
+
+    info!("Updating WiFi network: {}", ssid);
+    update_connection_profile(&uuid, profile_security(key_mgmt), update)?;
+
+    Ok(true)
+}
+
+fn find_saved_connection(manager: &NetworkManager, ssid: &str) -> Result<Option<Connection>> {
+    let connections = retry_nm("Listing connections", || manager.get_connections())?;
+
+    Ok(connections.into_iter().find(|connection| {
+        is_wifi_connection(connection)
+            && !is_access_point_connection(connection)
+            && is_same_ssid(connection, ssid)
+    }))
+}
+
+/// `key-mgmt` property of a saved profile, `None` for open networks
+fn read_key_mgmt(uuid: &str) -> Result<Option<String>> {
+    Ok(
+        read_profile_properties(uuid, &["802-11-wireless-security.key-mgmt"])?
+            .remove("802-11-wireless-security.key-mgmt")
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty()),
+    )
+}
+
+pub fn forget_specific_network(manager: &NetworkManager, ssid: &str) -> Result<bool> {
+    let connections = retry_nm("Listing connections", || manager.get_connections())?;
+    let mut found = false;
+
+    for connection in &connections {
+        if in_forget_scope(connection, ForgetScope::Network(ssid)) {
+            info!("Forgetting WiFi network: {}", ssid);
+            delete_connection(connection).chain_err(|| ErrorKind::DeleteAccessPoint)?;
+            found = true;
+        }
+    }
+
+    if !found {
+        warn!("Network '{}' not found in saved connections", ssid);
+    }
+
+    Ok(found)
+}
+
+pub fn create_portal(device: &Device, config: &Config) -> Result<Connection> {
+    let portal_passphrase = config.passphrase.as_ref().map(|p| p.expose());
+
+    let band = config.portal_band.map(|band| band.primary());
+
+    // Scanned before the access point takes over the interface
+    let channel = config.portal_channel.and_then(|channel| {
+        resolve_portal_channel(device.interface(), channel, band.unwrap_or(Band::TwoGHz))
+    });
+
+    let settings = ProfileSettings {
+        channel,
+        band,
+        ap_isolation: config.client_isolation,
+        ..Default::default()
+    };
+
+    // Enhanced Open only replaces an open access point
+    let owe = config.portal_owe && portal_passphrase.is_none();
+
+    create_portal_impl(
+        device,
+        &config.ssid,
+        &config.gateway,
+        &portal_passphrase,
+        &settings,
+        owe,
+    )
+    .chain_err(|| ErrorKind::CreateCaptivePortal)
+}
+
+fn create_portal_impl(
+    device: &Device,
+    ssid: &str,
+    gateway: &Ipv4Addr,
+    passphrase: &Option<&str>,
+    settings: &ProfileSettings,
+    owe: bool,
+) -> Result<Connection> {
+    info!("Starting access point...");
+    let wifi_device = device.as_wifi_device().unwrap();
+    let (portal_connection, state) = retry_nm_change(
+        "Creating the access point",
+        || find_added_connection(is_access_point_connection, ssid.as_bytes()),
+        || wifi_device.create_hotspot(ssid, *passphrase, Some(*gateway)),
+    )?;
+
+    if let Some(channel) = settings.channel {
+        info!("Moving access point to channel {}...", channel);
+    } else if let Some(band) = settings.band {
+        info!("Moving access point to {}...", band.name());
+    }
+
+    if settings.ap_isolation {
+        info!("Isolating access point clients...");
+    }
+
+    if !settings.is_empty() {
+        if let Err(e) = apply_and_reactivate(&portal_connection, settings, state) {
+            warn!("Setting up the access point failed: {}", e);
+        }
+    }
+
+    if owe {
+        info!("Enabling Enhanced Open (OWE)...");
+
+        if !enable_owe(&portal_connection)? {
+            warn!("Enhanced Open (OWE) is not supported, falling back to an open access point");
+        }
+    }
+
+    info!("Access point '{}' created", ssid);
+    Ok(portal_connection)
+}
+
+/// Switches an open access point to OWE, reverting to plain open if the
+/// adapter or its driver cannot bring it up
+fn enable_owe(connection: &Connection) -> Result<bool> {
+    let settings = ProfileSettings {
+        owe: true,
+        ..Default::default()
+    };
+
+    if let Ok(ConnectionState::Activated) =
+        apply_and_reactivate(connection, &settings, ConnectionState::Activated)
+    {
+        return Ok(true);
+    }
+
+    remove_profile_setting(connection, "802-11-wireless-security")?;
+    activate_connection(connection)?;
+
+    Ok(false)
+}
+
+pub fn stop_portal(connection: &Connection, config: &Config) -> Result<()> {
+    stop_portal_impl(connection, config).chain_err(|| ErrorKind::StopAccessPoint)
+}
+
+fn stop_portal_impl(connection: &Connection, config: &Config) -> Result<()> {
+    info!("Stopping access point '{}'...", config.ssid);
+    deactivate_connection(connection)?;
+    delete_connection(connection)?;
+    thread::sleep(Duration::from_secs(1));
+    info!("Access point '{}' stopped", config.ssid);
+    Ok(())
+}
+
+pub fn wait_for_connectivity(
+    manager: &NetworkManager,
+    timeout: u64,
+    shutdown: &Shutdown,
+) -> Result<bool> {
+    let mut total_time = 0;
+
+    loop {
+        let connectivity =
+            retry_nm("Checking connectivity", || manager.get_connectivity())?;
+
+        // NetworkManager reports an unknown state with its check disabled
+        let connected = match connectivity {
+            Connectivity::Full | Connectivity::Limited => true,
+            Connectivity::Unknown => probe_connectivity(),
+            _ => false,
+        };
+
+        if connected {
+            debug!(
+                "Connectivity established: {:?} / {}s elapsed",
+                connectivity, total_time
+            );
+
+            return Ok(true);
+        } else if total_time >= timeout {
+            debug!(
+                "Timeout reached in waiting for connectivity: {:?} / {}s elapsed",
+                connectivity, total_time
+            );
+
+            return Ok(false);
+        }
+
+        if shutdown.sleep(Duration::from_secs(1)) {
+            return Ok(false);
+        }
+
+        total_time += 1;
+
+        debug!(
+            "Still waiting for connectivity: {:?} / {}s elapsed",
+            connectivity, total_time
+        );
+    }
+}
+
+pub fn start_network_manager_service(config: &Config) -> Result<()> {
+    let timeout = config.network_manager_timeout;
+
+    let state = match NetworkManager::get_service_state() {
+        Ok(state) => state,
+        Err(e) => {
+            // No access to systemd (e.g. inside a container) - check that
+            // NetworkManager answers over D-Bus instead
+            debug!("Cannot get the NetworkManager service state: {}", e);
+            return wait_for_network_manager(timeout);
+        }
+    };
+
+    if state == ServiceState::Active {
+        debug!("NetworkManager service already running");
+        return wait_for_network_manager(timeout);
+    }
+
+    if !config.start_network_manager {
+        bail!(ErrorKind::NetworkManagerNotRunning(format!("{:?}", state)));
+    }
+
+    info!("Starting the NetworkManager service...");
+
+    let state =
+        NetworkManager::start_service(timeout).chain_err(|| ErrorKind::StartNetworkManager)?;
+    if state != ServiceState::Active {
+        bail!(ErrorKind::StartActiveNetworkManager);
+    }
+
+    info!("NetworkManager service started successfully");
+
+    wait_for_network_manager(timeout)
+}
+
+/// Waits for NetworkManager to answer D-Bus calls, as the service may be
+/// active before its D-Bus interface is on first boot
+fn wait_for_network_manager(timeout: u64) -> Result<()> {
+    let mut total_time = 0;
+
+    loop {
+        match NetworkManager::new().get_state() {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                if total_time >= timeout {
+                    return Err(e).chain_err(|| ErrorKind::NetworkManagerUnavailable);
+                }
+
+                debug!(
+                    "Still waiting for NetworkManager: {} / {}s elapsed",
+                    e, total_time
+                );
+            }
+        }
+
+        thread::sleep(Duration::from_secs(1));
+
+        total_time += 1;
+    }
+}
+
+pub fn delete_exising_wifi_connect_ap_profile(ssid: &str) -> Result<()> {
+    let manager = NetworkManager::new();
+
+    with_connections(&manager, |connections| -> Result<()> {
+        for connection in connections {
+            if in_forget_scope(connection, ForgetScope::Portal(ssid)) {
+                info!(
+                    "Deleting already created by WiFi Connect access point connection profile: {:?}",
+                    connection.settings().ssid,
+                );
+                delete_connection(connection)?;
+            }
+        }
+
+        Ok(())
+    })?
+}
+
+/// Saved networks whose connection profiles were created by WiFi Connect
+pub fn get_managed_networks(manager: &NetworkManager) -> Result<Vec<SavedNetwork>> {
+    let mut managed_networks = with_connections(manager, |connections| {
+        connections
+            .iter()
+            .filter(|connection| is_managed_connection(connection))
+            .map(|connection| SavedNetwork {
+                ssid: display_ssid(connection.settings().ssid.as_bytes()),
+                security: profile_security(
+                    read_key_mgmt(&connection.settings().uuid).unwrap_or_default(),
+                )
+                .to_string(),
+            })
+            .collect::<Vec<_>>()
+    })?;
+
+    managed_networks.sort_by(|a, b| a.ssid.cmp(&b.ssid));
+    Ok(managed_networks)
+}
+
+/// Deletes the connection profiles created by WiFi Connect, leaving those
+/// provisioned otherwise in place
+pub fn forget_managed_connections(manager: &NetworkManager) -> Result<usize> {
+    let connections = retry_nm("Listing connections", || manager.get_connections())?;
+    let mut forgotten = 0;
+
+    info!("Forgetting WiFi networks saved by WiFi Connect...");
+
+    for connection in &connections {
+        if !in_forget_scope(connection, ForgetScope::Managed) {
+            continue;
+        }
+
+        info!("Deleting WiFi connection: {}", connection.settings().id);
+
+        match delete_connection(connection) {
+            Ok(()) => forgotten += 1,
+            Err(e) => error!("Deleting WiFi connection failed: {}", e),
+        }
+    }
+
+    Ok(forgotten)
+}
+
+/// Deletes the WiFi profiles the policy prunes, returning how many. Active
+/// profiles and access points are never pruned.
+pub fn gc_wifi_connections(manager: &NetworkManager, policy: &GcPolicy) -> Result<usize> {
+    let timestamps = read_connection_timestamps()?;
+
+    with_connections(manager, |connections| {
+        let mut candidates = connections
+            .iter()
+            .filter(|connection| {
+                is_wifi_connection(connection) && !is_access_point_connection(connection)
+            })
+            .filter_map(|connection| {
+                // Active profiles and ones added after the timestamps were
+                // read are left alone
+                match timestamps.get(&connection.settings().uuid) {
+                    Some(&(last_used, false)) => Some((connection, last_used)),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Most recently used first, never used last
+        candidates.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut pruned = 0;
+
+        for &(connection, last_used) in candidates.iter().skip(policy.keep) {
+            if !policy.is_stale(last_used) {
+                continue;
+            }
+
+            info!(
+                "Pruning WiFi connection: {}",
+                display_ssid(connection.settings().ssid.as_bytes())
+            );
+
+            match delete_connection(connection) {
+                Ok(()) => pruned += 1,
+                Err(e) => error!("Deleting WiFi connection failed: {}", e),
+            }
+        }
+
+        pruned
+    })
+}
+
+/// Connection profiles a destructive command would delete, as their name,
+/// UUID and SSID
+pub fn describe_connections_in_scope(
+    manager: &NetworkManager,
+    scope: ForgetScope,
+) -> Result<Vec<String>> {
+    let connections = retry_nm("Listing connections", || manager.get_connections())?;
+
+    Ok(connections
+        .iter()
+        .filter(|connection| in_forget_scope(connection, scope))
+        .map(|connection| {
+            let settings = connection.settings();
+
+            format!(
+                "{} ({}, SSID: {})",
+                settings.id,
+                settings.uuid,
+                display_ssid(settings.ssid.as_bytes())
+            )
+        })
+        .collect())
+}
+
+/// Whether a destructive command deletes the connection, shared by the
+/// commands and their dry runs so that both agree
+pub fn in_forget_scope(connection: &Connection, scope: ForgetScope) -> bool {
+    match scope {
+        ForgetScope::All(filter) => {
+            is_wifi_connection(connection)
+                && !filter.keeps_ssid(connection.settings().ssid.as_bytes())
+                && (filter.older_than.is_none() || is_stale_connection(connection, filter))
+        }
+        ForgetScope::Network(ssid) => {
+            is_wifi_connection(connection)
+                && !is_access_point_connection(connection)
+                && is_same_ssid(connection, ssid)
+        }
+        ForgetScope::Managed => is_managed_connection(connection),
+        ForgetScope::Portal(ssid) => {
+            is_access_point_connection(connection) && is_same_ssid(connection, ssid)
+        }
+    }
+}
+
+pub fn delete_existing_connections_to_same_network(manager: &NetworkManager, ssid: &str) {
+    let connections = match retry_nm("Listing connections", || manager.get_connections()) {
+        Ok(connections) => connections,
+        Err(e) => {
+            error!("Getting existing connections failed: {}", e);
+            return;
+        }
+    };
+
+    for connection in &connections {
+        if is_wifi_connection(connection) && is_same_ssid(connection, ssid) {
+            info!(
+                "Deleting existing WiFi connection to the same network: {:?}",
+                connection.settings().ssid,
+            );
+
+            if let Err(e) = delete_connection(connection) {
+                error!("Deleting existing WiFi connection failed: {}", e);
+            }
+        }
+    }
+}
+
+pub fn is_same_ssid(connection: &Connection, ssid: &str) -> bool {
+    // An access point SSID could be random bytes and not a UTF-8 encoded string
+    matches_ssid(connection.settings().ssid.as_bytes(), ssid)
+}
+
+pub fn is_access_point_connection(connection: &Connection) -> bool {
+    is_wifi_connection(connection) && connection.settings().mode == "ap"
+}
+
+pub fn is_wifi_connection(connection: &Connection) -> bool {
+    connection.settings().kind == "802-11-wireless"
+}
+
+/// Whether the connection was last used long enough ago for the filter.
+/// Connections whose last use cannot be read are kept.
+fn is_stale_connection(connection: &Connection, filter: &ForgetFilter) -> bool {
+    let uuid = &connection.settings().uuid;
+
+    match read_profile_properties(uuid, &["connection.timestamp"]) {
+        Ok(properties) => filter.is_stale(
+            properties
+                .get("connection.timestamp")
+                .and_then(|timestamp| timestamp.trim().parse().ok())
+                .filter(|&timestamp| timestamp != 0),
+        ),
+        Err(e) => {
+            warn!("Keeping connection {}: {}", uuid, e);
+            false
+        }
+    }
+}
+
+/// Connections to networks WiFi Connect created, recognized by their name
+/// and the marker in their user data
+pub fn is_managed_connection(connection: &Connection) -> bool {
+    is_wifi_connection(connection)
+        && !is_access_point_connection(connection)
+        && connection.settings().id.starts_with(MANAGED_ID_PREFIX)
+        && has_managed_marker(&connection.settings().uuid)
+}
+
+pub fn disconnect_from_network(manager: &NetworkManager, interface: &Option<String>) -> Result<()> {
+    let device = find_device(manager, interface)?;
+
+    // Check if device is connected
+    let device_state = retry_nm("Getting the device state", || device.get_state())?;
+    if device_state != DeviceState::Activated {
+        println!("No active connection found.");
+        return Ok(());
+    }
+
+    // Deactivate the device to disconnect from the network
+    device.disconnect()?; // Assuming there is a `disconnect` method
+
+    println!("Disconnected successfully.");
+
+    Ok(())
+}
+
+pub fn forget_all_wifi_connections(manager: &NetworkManager, filter: &ForgetFilter) -> Result<()> {
+    let connections = match retry_nm("Listing connections", || manager.get_connections()) {
+        Ok(connections) => connections,
+        Err(e) => {
+            error!("Getting existing connections failed: {}", e);
+            return Err(e.into());
+        }
+    };
+
+    info!("Forgetting all WiFi connections...");
+    
+    for connection in &connections {
+        if in_forget_scope(connection, ForgetScope::All(filter)) {
+            info!(
+                "Deleting WiFi connection: {}",
+                display_ssid(connection.settings().ssid.as_bytes())
+            );
+
+            if let Err(e) = delete_connection(connection) {
+                error!("Deleting WiFi connection failed: {}", e);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+pub fn list_connected_connections() -> Result<Vec<Connection>> {
+    let manager = NetworkManager::new();
+    
+    let connections = match retry_nm("Listing connections", || manager.get_connections()) {
+        Ok(conns) => conns,
+        Err(e) => {
+            warn!("Failed to get connections: {}", e);
+            return Ok(Vec::new()); // Return empty list instead of failing completely
+        }
+    };
+    
+    let connected_connections: Vec<Connection> = connections.into_iter()
+        .filter_map(|conn| {
+            match conn.get_state() {
+                Ok(ConnectionState::Activated) => {
+                    // Additional check: make sure the connection has valid devices
+                    match conn.get_devices() {
+                        Ok(devices) => {
+                            // Skip connections that only have unknown/problematic device types
+                            let has_known_devices = devices.iter().any(|device| {
+                                !matches!(device.device_type(), DeviceType::Unknown)
+                            });
+                            
+                            if has_known_devices {
+                                Some(conn)
+                            } else {
+                                info!("Skipping connection with only unknown device types");
+                                None
+                            }
+                        },
+                        Err(e) => {
+                            info!("Skipping connection due to device enumeration error: {}", e);
+                            None
+                        }
+                    }
+                },
+                Ok(_) => None,
+                Err(e) => {
+                    debug!("Skipping connection due to state error: {}", e);
+                    None // Skip problematic connections instead of failing
+                }
+            }
+        })
+        .collect();
+    
+    Ok(connected_connections)
+}
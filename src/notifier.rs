@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use config::Config;
+use errors::*;
+use history::HistoryNotifier;
+use mqtt::MqttNotifier;
+use state::State;
+use webhook::WebhookNotifier;
+
+const DBUS_OBJECT_PATH: &str = "/io/balena/WiFiConnect";
+const DBUS_SIGNAL: &str = "io.balena.WiFiConnect.StateChanged";
+const LEDS_DIRECTORY: &str = "/sys/class/leds";
+
+/// A backend receiving every provisioning state transition
+pub trait Notifier: Send {
+    fn name(&self) -> &str;
+
+    fn notify(&mut self, previous: &State, current: &State) -> Result<()>;
+}
+
+pub fn init_notifiers(config: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    if let Some(ref command) = config.notify_exec {
+        let extra_fields_file = if config.extra_fields.is_empty() {
+            None
+        } else {
+            Some(config.extra_fields_file.as_path())
+        };
+
+        notifiers.push(Box::new(ExecNotifier::new(
+            command,
+            extra_fields_file,
+            &config.proxy_file,
+        )));
+    }
+
+    if config.notify_dbus {
+        notifiers.push(Box::new(DbusNotifier));
+    }
+
+    if let Some(ref led) = config.notify_led {
+        notifiers.push(Box::new(LedNotifier::new(led)));
+    }
+
+    if let Some(ref broker) = config.mqtt_broker {
+        notifiers.push(Box::new(MqttNotifier::new(
+            broker,
+            &config.mqtt_topic_prefix,
+        )));
+    }
+
+    if let Some(ref url) = config.webhook_url {
+        notifiers.push(Box::new(WebhookNotifier::new(
+            url,
+            config.webhook_secret.clone(),
+            &config.device_id,
+        )));
+    }
+
+    notifiers.push(Box::new(HistoryNotifier::new(&config.history_file)));
+
+    notifiers
+}
+
+/// Runs a shell command with the state exported through environment variables
+pub struct ExecNotifier {
+    command: String,
+    extra_fields_file: Option<PathBuf>,
+    proxy_file: PathBuf,
+}
+
+impl ExecNotifier {
+    pub fn new(command: &str, extra_fields_file: Option<&Path>, proxy_file: &Path) -> Self {
+        ExecNotifier {
+            command: command.to_string(),
+            extra_fields_file: extra_fields_file.map(Path::to_path_buf),
+            proxy_file: proxy_file.to_path_buf(),
+        }
+    }
+}
+
+impl Notifier for ExecNotifier {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn notify(&mut self, previous: &State, current: &State) -> Result<()> {
+        let mut command = Command::new("sh");
+
+        // The values entered into the extra form fields, as a JSON object
+        if let Some(ref path) = self.extra_fields_file {
+            command.env("WIFI_CONNECT_EXTRA_FIELDS", path);
+        }
+
+        // Shell variables with the proxy of the connected network, to be
+        // sourced by the hook
+        if self.proxy_file.exists() {
+            command.env("WIFI_CONNECT_PROXY", &self.proxy_file);
+        }
+
+        let mut child = command
+            .arg("-c")
+            .arg(&self.command)
+            .env("WIFI_CONNECT_STATE", current.name())
+            .env("WIFI_CONNECT_PREVIOUS_STATE", previous.name())
+            .env("WIFI_CONNECT_SSID", current.ssid().unwrap_or(""))
+            .env("WIFI_CONNECT_REASON", current.reason().unwrap_or(""))
+            .env(
+                "WIFI_CONNECT_FAILURE",
+                current.failure().map_or("", |failure| failure.name()),
+            )
+            .spawn()
+            .chain_err(|| ErrorKind::Notify("exec".into()))?;
+
+        // Reap the hook in the background so that a slow command does not
+        // hold up the transition
+        thread::spawn(move || {
+            let _ = child.wait();
+        });
+
+        Ok(())
+    }
+}
+
+/// Emits a `StateChanged` signal on the system bus
+pub struct DbusNotifier;
+
+impl Notifier for DbusNotifier {
+    fn name(&self) -> &str {
+        "dbus"
+    }
+
+    fn notify(&mut self, _previous: &State, current: &State) -> Result<()> {
+        let status = Command::new("dbus-send")
+            .arg("--system")
+            .arg("--type=signal")
+            .arg(DBUS_OBJECT_PATH)
+            .arg(DBUS_SIGNAL)
+            .arg(format!("string:{}", current.name()))
+            .arg(format!("string:{}", current.ssid().unwrap_or("")))
+            .status()
+            .chain_err(|| ErrorKind::Notify("dbus".into()))?;
+
+        if !status.success() {
+            bail!(ErrorKind::Notify("dbus".into()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Drives a sysfs LED: slow blink while the portal is up, fast blink while
+/// connecting, solid on when connected and a short flash on failure
+pub struct LedNotifier {
+    path: PathBuf,
+}
+
+impl LedNotifier {
+    pub fn new(led: &str) -> Self {
+        LedNotifier {
+            path: PathBuf::from(LEDS_DIRECTORY).join(led),
+        }
+    }
+
+    fn write(&self, attribute: &str, value: &str) -> Result<()> {
+        fs::write(self.path.join(attribute), value).chain_err(|| ErrorKind::Notify("led".into()))
+    }
+
+    fn blink(&self, delay_on: u32, delay_off: u32) -> Result<()> {
+        self.write("trigger", "timer")?;
+        self.write("delay_on", &delay_on.to_string())?;
+        self.write("delay_off", &delay_off.to_string())
+    }
+
+    fn set(&self, on: bool) -> Result<()> {
+        self.write("trigger", "none")?;
+        self.write("brightness", if on { "1" } else { "0" })
+    }
+}
+
+impl Notifier for LedNotifier {
+    fn name(&self) -> &str {
+        "led"
+    }
+
+    fn notify(&mut self, _previous: &State, current: &State) -> Result<()> {
+        match *current {
+            State::HotspotUp { .. } => self.blink(500, 500),
+            State::ClientJoined | State::CredentialsReceived { .. } => self.blink(100, 100),
+            State::Connected { .. } => self.set(true),
+            State::Failed { .. } => self.blink(50, 950),
+            State::Idle | State::HotspotDown => self.set(false),
+        }
+    }
+}
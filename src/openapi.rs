@@ -0,0 +1,855 @@
+//! OpenAPI document of the portal and management HTTP APIs, served at
+//! `/api/openapi.json` by both servers.
+
+use serde_json::{Map, Value};
+
+use server::API_PREFIX;
+
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// The management routes are served on `--api-address` and require the
+/// `--api-token` bearer token, the portal routes are served on the gateway.
+/// Only the versioned paths are listed, the unversioned aliases are left
+/// out.
+pub fn openapi_document() -> Value {
+    let mut paths = portal_paths();
+    merge(&mut paths, first_boot_paths());
+    merge(&mut paths, management_paths());
+
+    let mut paths = versioned(paths);
+    merge(&mut paths, discovery_paths());
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "WiFi Connect",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Captive portal routes are served on the portal gateway, \
+                management routes on the management API address.",
+        },
+        "tags": [
+            { "name": "portal", "description": "Captive portal routes" },
+            { "name": "management", "description": "Management API routes" },
+        ],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+fn portal_paths() -> Value {
+    json!({
+        "/networks": {
+            "get": portal(
+                "List the networks in range",
+                json_response("Networks in range", array_of("Network")),
+            ),
+        },
+        "/clients": {
+            "get": portal(
+                "List the stations associated with the hotspot",
+                json_response("Associated stations", array_of("Client")),
+            ),
+        },
+        "/device-info": {
+            "get": portal(
+                "Version and connectivity of the device",
+                json_response("Device information", schema_ref("DeviceInfo")),
+            ),
+        },
+        "/health": {
+            "get": portal(
+                "Self-diagnostic checks, answered with 503 when one fails",
+                json_response("Checks passed", schema_ref("Health")),
+            ),
+        },
+        "/connect-status": {
+            "get": portal(
+                "Current provisioning state and the last failure",
+                json_response("Provisioning state", schema_ref("ConnectStatus")),
+            ),
+        },
+        "/extra-fields": {
+            "get": portal(
+                "Form fields to fill in next to the credentials",
+                json_response("Extra form fields", array_of("ExtraField")),
+            ),
+        },
+        "/events": {
+            "get": with_parameters(
+                portal(
+                    "State transitions and errors, also served by the management API",
+                    json_response("Events, oldest first", array_of("Event")),
+                ),
+                vec![since_parameter()],
+            ),
+        },
+        "/events/stream": {
+            "get": with_parameters(
+                portal(
+                    "Events as Server-Sent Events while they are recorded, resuming after Last-Event-ID",
+                    json!({
+                        "description": "Stream of events, each with its ID and the Event as JSON data",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    }),
+                ),
+                vec![since_parameter()],
+            ),
+        },
+        "/diagnostics": {
+            "get": portal(
+                "Self-diagnostic and network checks",
+                json_response("Checks", array_of("Check")),
+            ),
+        },
+        "/diagnostics/ping": {
+            "get": with_parameters(
+                portal(
+                    "Time TCP connections to a host",
+                    json_response("Round trip times", schema_ref("PingReport")),
+                ),
+                vec![query_parameter(
+                    "host",
+                    "Host as name, name:port or IP address",
+                    "string",
+                    true,
+                )],
+            ),
+        },
+        "/connect": {
+            "post": with_body(
+                portal("Connect to a network", connect_response()),
+                json!({
+                    "type": "object",
+                    "required": ["ssid", "identity", "passphrase"],
+                    "properties": {
+                        "ssid": { "type": "string" },
+                        "identity": { "type": "string" },
+                        "passphrase": { "type": "string", "format": "password" },
+                        "proxy_host": { "type": "string" },
+                        "proxy_port": { "type": "integer" },
+                        "proxy_pac_url": { "type": "string" },
+                        "proxy_username": { "type": "string" },
+                        "proxy_password": { "type": "string", "format": "password" },
+                        "wireguard": {
+                            "type": "string",
+                            "description": "WireGuard configuration in the wg-quick format, the connection succeeds once the tunnel has a handshake",
+                        },
+                    },
+                    "additionalProperties": { "type": "string" },
+                }),
+            ),
+        },
+        "/connect-multiple": {
+            "post": with_body(
+                portal(
+                    "Connect to the first reachable of several networks",
+                    connect_response(),
+                ),
+                json!({
+                    "type": "object",
+                    "required": ["networks"],
+                    "properties": {
+                        "networks": array_of("NetworkCredentials"),
+                    },
+                }),
+            ),
+        },
+        "/validate": {
+            "post": with_body(
+                portal(
+                    "Check credentials for a network without connecting",
+                    json_response("Problems of the credentials", schema_ref("Validation")),
+                ),
+                json!({
+                    "type": "object",
+                    "required": ["ssid", "security"],
+                    "properties": {
+                        "ssid": { "type": "string" },
+                        "security": {
+                            "type": "string",
+                            "enum": ["none", "wep", "wpa", "enterprise"],
+                            "description": "Security of the network as listed by /networks",
+                        },
+                        "identity": { "type": "string" },
+                        "passphrase": { "type": "string", "format": "password" },
+                        "proxy_host": { "type": "string" },
+                        "proxy_port": { "type": "integer" },
+                        "proxy_pac_url": { "type": "string" },
+                        "proxy_username": { "type": "string" },
+                        "proxy_password": { "type": "string", "format": "password" },
+                        "wireguard": { "type": "string" },
+                    },
+                }),
+            ),
+        },
+        "/certificates": {
+            "post": with_upload(
+                portal(
+                    "Upload a certificate of an EAP-TLS network, used once a client certificate and its key are uploaded",
+                    empty_response("Certificate stored"),
+                ),
+                json!({
+                    "type": "object",
+                    "required": ["ssid", "kind", "certificate"],
+                    "properties": {
+                        "ssid": { "type": "string" },
+                        "kind": { "type": "string", "enum": ["ca", "client", "key"] },
+                        "certificate": {
+                            "type": "string",
+                            "description": "PEM encoded, as a file upload or a string",
+                        },
+                    },
+                }),
+            ),
+        },
+    })
+}
+
+fn discovery_paths() -> Value {
+    json!({
+        "/api/version": {
+            "get": portal(
+                "Firmware version, API versions and capabilities, also served by the \
+                    management API",
+                json_response("Versions and capabilities", schema_ref("ApiVersion")),
+            ),
+        },
+        "/api/openapi.json": {
+            "get": portal(
+                "This document, also served by the management API",
+                json_response("OpenAPI document", json!({ "type": "object" })),
+            ),
+        },
+        "/.well-known/captive-portal": {
+            "get": portal(
+                "Captive Portal API (RFC 8908), advertised with DHCP option 114",
+                json!({
+                    "description": "Captive portal state",
+                    "content": {
+                        "application/captive+json": {
+                            "schema": schema_ref("CaptivePortalState"),
+                        },
+                    },
+                }),
+            ),
+        },
+    })
+}
+
+fn first_boot_paths() -> Value {
+    json!({
+        "/first-boot": {
+            "get": portal(
+                "Timezone, clock and EULA steps, only served with --first-boot",
+                json_response("First boot status", schema_ref("FirstBootStatus")),
+            ),
+        },
+        "/timezone": {
+            "post": with_body(
+                portal(
+                    "Set the system timezone, only served with --first-boot",
+                    empty_response("Timezone set"),
+                ),
+                json!({
+                    "type": "object",
+                    "required": ["timezone"],
+                    "properties": { "timezone": { "type": "string" } },
+                }),
+            ),
+        },
+        "/eula": {
+            "post": portal(
+                "Accept the EULA, only served with --first-boot",
+                empty_response("EULA accepted"),
+            ),
+        },
+    })
+}
+
+fn management_paths() -> Value {
+    json!({
+        "/hotspot": {
+            "get": management(
+                "Status of the hotspot",
+                json_response("Hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/hotspot/start": {
+            "post": management(
+                "Start the access point with DHCP, without the captive portal",
+                json_response("Hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/hotspot/stop": {
+            "post": management(
+                "Stop the access point started with /hotspot/start",
+                json_response("Hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/hotspot/rotate": {
+            "post": management(
+                "Replace the hotspot passphrase with a random one, requires --rotate-passphrase",
+                json_response("Hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/hotspot/qr-code": {
+            "get": with_parameters(
+                management("QR code joining the hotspot", qr_code_response()),
+                vec![query_parameter(
+                    "format",
+                    "`text` for the WIFI: payload instead of SVG",
+                    "string",
+                    false,
+                )],
+            ),
+        },
+        "/guest": {
+            "get": management(
+                "Status of the guest hotspot configured with --guest-ssid",
+                json_response("Guest hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/guest/start": {
+            "post": management(
+                "Start the guest hotspot",
+                json_response("Guest hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/guest/stop": {
+            "post": management(
+                "Stop the guest hotspot",
+                json_response("Guest hotspot status", schema_ref("HotspotStatus")),
+            ),
+        },
+        "/connection": {
+            "get": management(
+                "Currently connected network",
+                json_response(
+                    "Connected network, null if there is none",
+                    nullable(schema_ref("ConnectedNetwork")),
+                ),
+            ),
+        },
+        "/speed-test": {
+            "post": with_body(
+                management(
+                    "Measure the download speed",
+                    json_response("Speed test result", schema_ref("SpeedTest")),
+                ),
+                json!({
+                    "type": "object",
+                    "properties": { "url": { "type": "string", "format": "uri" } },
+                }),
+            ),
+        },
+        "/saved-networks/{ssid}": {
+            "parameters": [{
+                "name": "ssid",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            }],
+            "get": management(
+                "Settings of a saved network",
+                json_response("Saved network", schema_ref("SavedNetworkDetails")),
+            ),
+            "patch": with_body(
+                management(
+                    "Update a saved network",
+                    json_response("Saved network", schema_ref("SavedNetworkDetails")),
+                ),
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "passphrase": { "type": "string", "format": "password" },
+                        "priority": { "type": "integer" },
+                        "autoconnect": { "type": "boolean" },
+                        "address": { "type": "string", "description": "IPv4 address with prefix" },
+                        "gateway": { "type": "string" },
+                        "dns": { "type": "array", "items": { "type": "string" } },
+                        "dhcp": { "type": "boolean" },
+                    },
+                }),
+            ),
+        },
+        "/passpoint": {
+            "post": with_body(
+                management(
+                    "Install a Passpoint (Hotspot 2.0) profile, with the iwd backend",
+                    json_response("Hotspot status", schema_ref("HotspotStatus")),
+                ),
+                json!({
+                    "type": "object",
+                    "required": ["name", "realm", "identity"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "realm": { "type": "string" },
+                        "domain": { "type": "string" },
+                        "roaming_consortium": {
+                            "type": "array",
+                            "items": { "type": "string", "description": "Hex encoded OI" },
+                        },
+                        "eap": { "type": "string", "enum": ["ttls", "peap"] },
+                        "identity": { "type": "string" },
+                        "passphrase": { "type": "string", "format": "password" },
+                        "ca_cert": { "type": "string", "description": "Path of the CA certificate" },
+                    },
+                }),
+            ),
+        },
+    })
+}
+
+fn schemas() -> Value {
+    json!({
+        "ApiVersion": {
+            "type": "object",
+            "properties": {
+                "version": { "type": "string" },
+                "api_versions": { "type": "array", "items": { "type": "string" } },
+                "capabilities": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "CaptivePortalState": {
+            "type": "object",
+            "properties": {
+                "captive": { "type": "boolean" },
+                "user-portal-url": { "type": "string" },
+                "venue-info-url": { "type": "string" },
+                "can-extend-session": { "type": "boolean" },
+            },
+        },
+        "Network": {
+            "type": "object",
+            "properties": {
+                "ssid": { "type": "string" },
+                "ssid_hex": { "type": "string", "description": "Raw SSID bytes" },
+                "security": { "type": "string" },
+                "signal_strength": { "type": "integer", "description": "Percent" },
+                "warning": {
+                    "type": "string",
+                    "description": "Set when rogue access points impersonate the network",
+                },
+                "unsupported": {
+                    "type": "string",
+                    "description": "Set when connecting would certainly fail, e.g. WPA3 without driver support",
+                },
+            },
+        },
+        "NetworkCredentials": {
+            "type": "object",
+            "required": ["ssid"],
+            "properties": {
+                "ssid": { "type": "string" },
+                "identity": { "type": "string" },
+                "passphrase": { "type": "string", "format": "password" },
+                "proxy_host": { "type": "string" },
+                "proxy_port": { "type": "integer" },
+                "proxy_pac_url": { "type": "string" },
+                "proxy_username": { "type": "string" },
+                "proxy_password": { "type": "string", "format": "password" },
+                "wireguard": {
+                    "type": "string",
+                    "description": "WireGuard configuration in the wg-quick format, the connection succeeds once the tunnel has a handshake",
+                },
+            },
+        },
+        "ConnectAccepted": {
+            "type": "object",
+            "properties": {
+                "redirect_url": { "type": "string" },
+            },
+        },
+        "Validation": {
+            "type": "object",
+            "properties": {
+                "valid": { "type": "boolean" },
+                "problems": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "field": { "type": "string" },
+                            "message": { "type": "string" },
+                        },
+                    },
+                },
+            },
+        },
+        "ConnectedNetwork": {
+            "type": "object",
+            "properties": {
+                "ssid": { "type": "string" },
+                "security": { "type": "string" },
+                "signal_strength": { "type": "integer" },
+                "interface": { "type": "string" },
+                "ip_address": nullable(json!({ "type": "string" })),
+                "ipv6_addresses": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Global IPv6 addresses",
+                },
+                "ipv4": schema_ref("IpConfig"),
+                "ipv6": schema_ref("IpConfig"),
+                "link": schema_ref("LinkStatistics"),
+                "vpn": schema_ref("VpnStatus"),
+            },
+        },
+        "LinkStatistics": {
+            "type": "object",
+            "properties": {
+                "rx_bitrate": nullable(json!({ "type": "number", "description": "MBit/s" })),
+                "tx_bitrate": nullable(json!({ "type": "number", "description": "MBit/s" })),
+                "tx_power": nullable(json!({ "type": "number", "description": "dBm" })),
+                "frequency": nullable(json!({ "type": "integer", "description": "MHz" })),
+                "signal": nullable(json!({ "type": "integer", "description": "dBm" })),
+                "tx_retries": nullable(json!({ "type": "integer" })),
+                "tx_failed": nullable(json!({ "type": "integer" })),
+            },
+        },
+        "IpConfig": {
+            "type": "object",
+            "properties": {
+                "addresses": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Addresses with prefix length",
+                },
+                "gateways": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Next hops of the default routes",
+                },
+                "dns": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "VpnStatus": {
+            "type": "object",
+            "description": "Only with --activate-vpn",
+            "properties": {
+                "name": { "type": "string" },
+                "state": nullable(json!({ "type": "string" })),
+                "active": { "type": "boolean" },
+            },
+        },
+        "SavedNetworkDetails": {
+            "type": "object",
+            "properties": {
+                "ssid": { "type": "string" },
+                "security": { "type": "string" },
+                "autoconnect": { "type": "boolean" },
+                "autoconnect_priority": nullable(json!({ "type": "integer" })),
+                "last_used": nullable(json!({ "type": "integer", "description": "Unix timestamp" })),
+                "ipv4_method": nullable(json!({ "type": "string" })),
+                "ipv6_method": nullable(json!({ "type": "string" })),
+                "dns": { "type": "array", "items": { "type": "string" } },
+                "cloned_mac": nullable(json!({ "type": "string" })),
+            },
+        },
+        "Client": {
+            "type": "object",
+            "properties": {
+                "mac": { "type": "string" },
+                "signal": nullable(json!({ "type": "integer", "description": "dBm" })),
+                "connected_time": nullable(json!({ "type": "integer", "description": "Seconds" })),
+            },
+        },
+        "DeviceInfo": {
+            "type": "object",
+            "properties": {
+                "version": { "type": "string" },
+                "wifi_connected": { "type": "boolean" },
+                "modems": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "interface": { "type": "string" },
+                            "state": { "type": "string" },
+                            "connected": { "type": "boolean" },
+                        },
+                    },
+                },
+            },
+        },
+        "Check": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "passed": { "type": "boolean" },
+                "detail": { "type": "string" },
+                "hint": { "type": "string" },
+            },
+        },
+        "Health": {
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["ok", "degraded"] },
+                "checks": array_of("Check"),
+            },
+        },
+        "State": {
+            "type": "object",
+            "required": ["state"],
+            "properties": {
+                "state": {
+                    "type": "string",
+                    "enum": [
+                        "idle",
+                        "hotspot-up",
+                        "client-joined",
+                        "credentials-received",
+                        "connected",
+                        "failed",
+                        "hotspot-down",
+                    ],
+                },
+                "ssid": { "type": "string" },
+                "ip": nullable(json!({ "type": "string" })),
+                "clock_synchronized": nullable(json!({
+                    "type": "boolean",
+                    "description": "Only checked with --require-time-sync",
+                })),
+                "reason": { "type": "string" },
+                "failure": {
+                    "type": "string",
+                    "enum": [
+                        "wrong-password",
+                        "association-timeout",
+                        "dhcp-failed",
+                        "network-not-found",
+                        "tunnel-failed",
+                        "other",
+                    ],
+                },
+            },
+        },
+        "ConnectStatus": {
+            "allOf": [
+                schema_ref("State"),
+                {
+                    "type": "object",
+                    "properties": {
+                        "last_failure": nullable(schema_ref("State")),
+                        "timings": nullable(schema_ref("ConnectTimings")),
+                    },
+                },
+            ],
+        },
+        "ConnectTimings": {
+            "type": "object",
+            "description": "Milliseconds spent in each phase of the current or last connection attempt, missing for phases not completed",
+            "properties": {
+                "ssid": { "type": "string" },
+                "association_ms": { "type": "integer" },
+                "authentication_ms": { "type": "integer" },
+                "dhcp_ms": { "type": "integer" },
+                "connectivity_ms": { "type": "integer" },
+                "total_ms": { "type": "integer" },
+            },
+        },
+        "Event": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "integer" },
+                "timestamp": { "type": "integer", "description": "Unix timestamp in milliseconds" },
+                "type": {
+                    "type": "string",
+                    "enum": ["transition", "error", "client-joined", "client-left"],
+                },
+                "from": { "type": "string" },
+                "to": schema_ref("State"),
+                "message": { "type": "string" },
+                "mac": { "type": "string" },
+                "signal": nullable(json!({ "type": "integer" })),
+            },
+        },
+        "HotspotStatus": {
+            "type": "object",
+            "properties": {
+                "is_running": { "type": "boolean" },
+                "ssid": nullable(json!({ "type": "string" })),
+                "gateway": nullable(json!({ "type": "string" })),
+                "interface": nullable(json!({ "type": "string" })),
+                "password_protected": { "type": "boolean" },
+                "uptime": nullable(json!({ "type": "string" })),
+            },
+        },
+        "PingReport": {
+            "type": "object",
+            "properties": {
+                "host": { "type": "string" },
+                "address": { "type": "string" },
+                "sent": { "type": "integer" },
+                "received": { "type": "integer" },
+                "rtt_ms": { "type": "array", "items": { "type": "number" } },
+                "min_ms": nullable(json!({ "type": "number" })),
+                "avg_ms": nullable(json!({ "type": "number" })),
+                "max_ms": nullable(json!({ "type": "number" })),
+            },
+        },
+        "SpeedTest": {
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "bytes": { "type": "integer" },
+                "mbps": { "type": "number" },
+                "latency_ms": { "type": "number" },
+            },
+        },
+        "ExtraField": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "label": { "type": "string" },
+                "required": { "type": "boolean" },
+            },
+        },
+        "FirstBootStatus": {
+            "type": "object",
+            "properties": {
+                "timezone": nullable(json!({ "type": "string" })),
+                "timezones": { "type": "array", "items": { "type": "string" } },
+                "ntp_synchronized": { "type": "boolean" },
+                "eula": nullable(json!({ "type": "string" })),
+                "eula_accepted": { "type": "boolean" },
+            },
+        },
+    })
+}
+
+fn portal(summary: &str, response: Value) -> Value {
+    json!({
+        "tags": ["portal"],
+        "summary": summary,
+        "responses": { "200": response },
+    })
+}
+
+fn management(summary: &str, response: Value) -> Value {
+    json!({
+        "tags": ["management"],
+        "summary": summary,
+        "security": [{ "bearer": [] }],
+        "responses": {
+            "200": response,
+            "401": { "description": "Missing or wrong API token" },
+        },
+    })
+}
+
+fn with_parameters(mut operation: Value, parameters: Vec<Value>) -> Value {
+    operation["parameters"] = Value::Array(parameters);
+    operation
+}
+
+/// Request parameters are accepted as JSON or as form data
+fn with_body(mut operation: Value, schema: Value) -> Value {
+    operation["requestBody"] = json!({
+        "content": {
+            "application/json": { "schema": schema },
+            "application/x-www-form-urlencoded": { "schema": schema },
+        },
+    });
+    operation
+}
+
+/// Like `with_body`, additionally accepting the parameters as a multipart
+/// file upload
+fn with_upload(operation: Value, schema: Value) -> Value {
+    let mut operation = with_body(operation, schema.clone());
+    operation["requestBody"]["content"]["multipart/form-data"] = json!({ "schema": schema });
+    operation
+}
+
+fn since_parameter() -> Value {
+    query_parameter(
+        "since",
+        "Only return events after this ID",
+        "integer",
+        false,
+    )
+}
+
+fn query_parameter(name: &str, description: &str, schema_type: &str, required: bool) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "required": required,
+        "schema": { "type": schema_type },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// Empty unless `--redirect-url` is set, HTML clients then get a landing
+/// page instead
+fn connect_response() -> Value {
+    json!({
+        "description": "Connecting, with the link to follow if --redirect-url is set",
+        "content": {
+            "application/json": { "schema": schema_ref("ConnectAccepted") },
+            "text/html": { "schema": { "type": "string" } },
+        },
+    })
+}
+
+fn qr_code_response() -> Value {
+    json!({
+        "description": "QR code, rendered with qrencode",
+        "content": {
+            "image/svg+xml": { "schema": { "type": "string" } },
+            "text/plain": { "schema": { "type": "string" } },
+        },
+    })
+}
+
+fn empty_response(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(name: &str) -> Value {
+    json!({ "type": "array", "items": schema_ref(name) })
+}
+
+fn nullable(mut schema: Value) -> Value {
+    if schema.get("$ref").is_some() {
+        return json!({ "allOf": [schema], "nullable": true });
+    }
+
+    schema["nullable"] = Value::Bool(true);
+    schema
+}
+
+fn versioned(paths: Value) -> Value {
+    match paths {
+        Value::Object(paths) => Value::Object(
+            paths
+                .into_iter()
+                .map(|(path, item)| (format!("{}{}", API_PREFIX, path), item))
+                .collect::<Map<String, Value>>(),
+        ),
+        other => other,
+    }
+}
+
+fn merge(paths: &mut Value, other: Value) {
+    if let (&mut Value::Object(ref mut paths), Value::Object(other)) = (paths, other) {
+        paths.extend(other);
+    }
+}
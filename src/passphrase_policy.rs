@@ -0,0 +1,95 @@
+//! Rules for the hotspot passphrase. WPA2 takes 8 to 63 printable ASCII
+//! characters, or a raw PSK of 64 hex digits. WPA3 (SAE) uses the
+//! passphrase itself and has no raw PSK, and its transition mode with WPA2
+//! keeps the WPA2 limits, so only passphrases valid for both are accepted.
+//! `--portal-passphrase-strength-check` additionally refuses passphrases
+//! found in password dictionaries, for hotspots customers join.
+
+/// Lengths of a WPA2 passphrase
+pub const MIN_PASSPHRASE_LENGTH: usize = 8;
+pub const MAX_PASSPHRASE_LENGTH: usize = 63;
+
+/// Fewest distinct characters of a passphrase passing the strength check
+const MIN_DISTINCT_CHARACTERS: usize = 5;
+
+/// Most common WiFi and device passwords of the leaked password lists, which
+/// are tried first by anyone guessing, separated by spaces
+const COMMON_PASSPHRASES: &str = "password passw0rd p@ssw0rd password1 12345678 \
+     123456789 1234567890 87654321 11111111 00000000 88888888 iloveyou sunshine \
+     princess football baseball welcome welcome1 letmein trustno1 superman qwerty \
+     qwertyuiop asdfghjkl zxcvbnm qazwsxedc 1q2w3e4r 1qaz2wsx abc12345 admin \
+     administrator changeme default internet wireless wifi wifipassword hotspot \
+     guest guestwifi network secret master monkey dragon shadow michael computer \
+     whatever starwars freewifi access";
+
+/// Keyboard rows and runs of characters typed in sequence
+const SEQUENCES: &[&str] = &[
+    "abcdefghijklmnopqrstuvwxyz",
+    "01234567890",
+    "qwertyuiopasdfghjklzxcvbnm",
+];
+
+/// Checks the length and characters of a passphrase, without including the
+/// passphrase in the error
+pub fn validate_passphrase(passphrase: &str) -> ::std::result::Result<(), String> {
+    if passphrase.len() < MIN_PASSPHRASE_LENGTH || passphrase.len() > MAX_PASSPHRASE_LENGTH {
+        return Err(format!(
+            "The hotspot passphrase has to be {} to {} characters long, not {}",
+            MIN_PASSPHRASE_LENGTH,
+            MAX_PASSPHRASE_LENGTH,
+            passphrase.chars().count()
+        ));
+    }
+
+    if !passphrase.chars().all(|c| c >= ' ' && c <= '~') {
+        return Err("The hotspot passphrase may only contain printable ASCII characters".into());
+    }
+
+    // The passphrase is stored through the line based `nmcli` editor
+    if passphrase.trim() != passphrase {
+        return Err("The hotspot passphrase may not start or end with a space".into());
+    }
+
+    Ok(())
+}
+
+/// Refuses passphrases guessed easily: common passwords, also with digits
+/// or punctuation appended, sequences, repetitions and the SSID itself
+pub fn check_strength(passphrase: &str, ssid: &str) -> ::std::result::Result<(), String> {
+    let lower = passphrase.to_lowercase();
+    let stem = lower.trim_matches(|c: char| !c.is_ascii_alphabetic());
+
+    if COMMON_PASSPHRASES
+        .split_whitespace()
+        .any(|common| common == lower || common == stem)
+    {
+        return Err("The hotspot passphrase is a common password".into());
+    }
+
+    let ssid = ssid.to_lowercase();
+    if ssid.len() >= 4 && lower.contains(&ssid) {
+        return Err("The hotspot passphrase must not contain the SSID".into());
+    }
+
+    let is_sequence = SEQUENCES.iter().any(|sequence| {
+        let reversed = sequence.chars().rev().collect::<String>();
+        sequence.contains(&lower) || reversed.contains(&lower)
+    });
+
+    if is_sequence {
+        return Err("The hotspot passphrase is a sequence of characters".into());
+    }
+
+    let mut distinct = lower.chars().collect::<Vec<_>>();
+    distinct.sort();
+    distinct.dedup();
+
+    if distinct.len() < MIN_DISTINCT_CHARACTERS {
+        return Err(format!(
+            "The hotspot passphrase needs at least {} different characters",
+            MIN_DISTINCT_CHARACTERS
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Passpoint (Hotspot 2.0) profiles, installed with `--install-passpoint`
+//! or through the management API. A profile holds the credentials of a home
+//! operator, and any venue network advertising its realm or one of its
+//! roaming consortium OIs over 802.11u is then joined without provisioning.
+//!
+//! ```json
+//! {"name": "Carrier", "realm": "carrier.example", "roaming_consortium": ["5a03ba"],
+//!  "identity": "device-42", "passphrase": "..."}
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use encryption::SecretsPolicy;
+use errors::*;
+use secret::Secret;
+
+/// EAP methods of Passpoint profiles with a username and password, both
+/// with MSCHAPv2 inside the tunnel
+pub const PASSPOINT_EAP_METHODS: &[&str] = &["ttls", "peap"];
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasspointProfile {
+    /// Name of the home operator, which the installed profile is named after
+    pub name: String,
+    /// NAI realm of the home operator
+    pub realm: String,
+    /// Home domain, to tell home networks from roaming partners
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Hex encoded organization identifiers of the roaming consortiums the
+    /// operator is part of
+    #[serde(default)]
+    pub roaming_consortium: Vec<String>,
+    #[serde(default = "default_eap")]
+    pub eap: String,
+    pub identity: String,
+    #[serde(default)]
+    pub passphrase: Secret,
+    /// CA certificate the authentication server is verified against
+    #[serde(default)]
+    pub ca_cert: Option<PathBuf>,
+}
+
+fn default_eap() -> String {
+    "ttls".into()
+}
+
+impl PasspointProfile {
+    pub fn validate(&self) -> ::std::result::Result<(), String> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            && !self.name.starts_with('.');
+
+        if !valid_name {
+            return Err(format!(
+                "'{}' is not a profile name of letters, digits, '-', '_' and '.'",
+                self.name
+            ));
+        }
+
+        if self.realm.is_empty() {
+            return Err("A Passpoint profile needs a realm".into());
+        }
+
+        // Organization identifiers are 3 or 5 octets
+        for oi in &self.roaming_consortium {
+            let valid =
+                (oi.len() == 6 || oi.len() == 10) && oi.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(format!("'{}' is not a roaming consortium OI", oi));
+            }
+        }
+
+        if !PASSPOINT_EAP_METHODS.contains(&self.eap.as_str()) {
+            return Err(format!(
+                "'{}' is not one of the EAP methods {}",
+                self.eap,
+                PASSPOINT_EAP_METHODS.join(", ")
+            ));
+        }
+
+        if self.identity.is_empty() {
+            return Err("A Passpoint profile needs an identity".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Profile read from `path`, with the passphrase decrypted
+pub fn load_passpoint_profile(path: &Path, secrets: &SecretsPolicy) -> Result<PasspointProfile> {
+    let source = path.display().to_string();
+
+    let contents = fs::read_to_string(path).chain_err(|| ErrorKind::Passpoint(source.clone()))?;
+
+    let mut profile: PasspointProfile =
+        serde_json::from_str(&contents).chain_err(|| ErrorKind::Passpoint(source.clone()))?;
+
+    if let Err(reason) = profile.validate() {
+        bail!(ErrorKind::Passpoint(format!("{}: {}", source, reason)));
+    }
+
+    profile.passphrase = secrets.open(&profile.passphrase, &source)?;
+
+    Ok(profile)
+}
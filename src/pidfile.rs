@@ -0,0 +1,108 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use errors::*;
+
+/// Exclusive lock on the PID file, held for as long as this instance manages
+/// the interface. The file is removed when the guard is dropped.
+pub struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+impl PidFile {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut file = open(path)?;
+
+        if let Err(e) = flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            if e == Errno::EWOULDBLOCK {
+                let pid = read_pid(&mut file).map_or_else(|| "unknown".into(), |pid| pid.to_string());
+                bail!(ErrorKind::AlreadyRunning(pid));
+            }
+
+            return Err(e).chain_err(|| ErrorKind::PidFile(path.display().to_string()));
+        }
+
+        write_pid(&mut file).chain_err(|| ErrorKind::PidFile(path.display().to_string()))?;
+
+        debug!("Acquired PID file {}", path.display());
+
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            _file: file,
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// PID of the instance holding the lock on the PID file, if any
+pub fn running_instance(path: &Path) -> Option<i32> {
+    let mut file = File::open(path).ok()?;
+
+    match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+        Err(Errno::EWOULDBLOCK) => read_pid(&mut file),
+        _ => None,
+    }
+}
+
+/// Asks the running instance to shut down and waits until it has released
+/// the PID file. Returns the PID of the stopped instance.
+pub fn stop_running_instance(path: &Path, timeout: u64) -> Result<Option<i32>> {
+    let pid = match running_instance(path) {
+        Some(pid) => pid,
+        None => return Ok(None),
+    };
+
+    info!("Stopping running instance with PID {}", pid);
+
+    kill(Pid::from_raw(pid), Signal::SIGTERM)?;
+
+    for _ in 0..timeout * 10 {
+        if running_instance(path).is_none() {
+            return Ok(Some(pid));
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    warn!("Instance with PID {} did not exit within {}s", pid, timeout);
+
+    Ok(Some(pid))
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)
+        .chain_err(|| ErrorKind::PidFile(path.display().to_string()))
+}
+
+fn read_pid(file: &mut File) -> Option<i32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+fn write_pid(file: &mut File) -> ::std::io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    writeln!(file, "{}", process::id())?;
+    file.sync_all()
+}
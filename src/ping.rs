@@ -0,0 +1,98 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use errors::*;
+
+/// Port probed when the host is given without one
+pub const DEFAULT_PING_PORT: u16 = 80;
+
+/// Connection attempts made per ping
+const PING_COUNT: usize = 4;
+
+/// Seconds an attempt may take before it counts as lost
+const PING_TIMEOUT: u64 = 2;
+
+/// Pause between attempts
+const PING_INTERVAL_MS: u64 = 500;
+
+/// Round trip times of TCP connection attempts to a host. Raw ICMP sockets
+/// need extra privileges, while timing the TCP handshake works for any user.
+#[derive(Debug, Serialize)]
+pub struct PingReport {
+    pub host: String,
+    pub address: String,
+    pub sent: usize,
+    pub received: usize,
+    /// Round trip time of every answered attempt in milliseconds
+    pub rtt_ms: Vec<f64>,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+/// Times the TCP handshake with `host`, given as `name`, `name:port` or an
+/// IP address. A refused connection still proves the host answered.
+pub fn ping(host: &str) -> Result<PingReport> {
+    let address = resolve(host)?;
+
+    let mut rtt_ms = Vec::new();
+
+    for attempt in 0..PING_COUNT {
+        if attempt > 0 {
+            thread::sleep(Duration::from_millis(PING_INTERVAL_MS));
+        }
+
+        let start = Instant::now();
+
+        match TcpStream::connect_timeout(&address, Duration::from_secs(PING_TIMEOUT)) {
+            Ok(_) => rtt_ms.push(elapsed_ms(start)),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                rtt_ms.push(elapsed_ms(start))
+            }
+            Err(e) => debug!("Ping attempt to {} failed: {}", address, e),
+        }
+    }
+
+    let min_ms = rtt_ms.iter().cloned().fold(None, |min: Option<f64>, rtt| {
+        Some(min.map_or(rtt, |min| min.min(rtt)))
+    });
+    let max_ms = rtt_ms.iter().cloned().fold(None, |max: Option<f64>, rtt| {
+        Some(max.map_or(rtt, |max| max.max(rtt)))
+    });
+    let avg_ms = if rtt_ms.is_empty() {
+        None
+    } else {
+        Some(rtt_ms.iter().sum::<f64>() / rtt_ms.len() as f64)
+    };
+
+    Ok(PingReport {
+        host: host.into(),
+        address: address.to_string(),
+        sent: PING_COUNT,
+        received: rtt_ms.len(),
+        rtt_ms,
+        min_ms,
+        avg_ms,
+        max_ms,
+    })
+}
+
+fn resolve(host: &str) -> Result<SocketAddr> {
+    let mut addresses = match host.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => (host, DEFAULT_PING_PORT)
+            .to_socket_addrs()
+            .chain_err(|| ErrorKind::Ping(host.into()))?,
+    };
+
+    addresses
+        .next()
+        .ok_or_else(|| ErrorKind::Ping(host.into()).into())
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0
+}
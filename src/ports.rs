@@ -0,0 +1,180 @@
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use config::Config;
+use errors::*;
+
+pub const DNS_PORT: u16 = 53;
+pub const DHCP_PORT: u16 = 67;
+
+/// TCP socket state of a listening socket in `/proc/net/tcp`
+const TCP_LISTEN: &str = "0A";
+
+/// UDP socket state of a bound, unconnected socket in `/proc/net/udp`
+const UDP_UNCONNECTED: &str = "07";
+
+/// Socket bound to a local port, as listed in `/proc/net`
+pub struct Listener {
+    pub protocol: &'static str,
+    pub address: String,
+    pub port: u16,
+    wildcard: bool,
+    inode: u64,
+}
+
+impl Listener {
+    /// Process holding the socket, e.g. `systemd-resolved (PID 412)`. Only
+    /// known when running as root or as the owner of the process.
+    pub fn owner(&self) -> Option<String> {
+        let target = format!("socket:[{}]", self.inode);
+
+        for entry in fs::read_dir("/proc").ok()?.filter_map(|entry| entry.ok()) {
+            let pid = match entry.file_name().into_string() {
+                Ok(ref name) if name.chars().all(|c| c.is_ascii_digit()) => name.clone(),
+                _ => continue,
+            };
+
+            let fds = match fs::read_dir(entry.path().join("fd")) {
+                Ok(fds) => fds,
+                Err(_) => continue,
+            };
+
+            let owns = fds
+                .filter_map(|fd| fd.ok())
+                .filter_map(|fd| fs::read_link(fd.path()).ok())
+                .any(|link| link.to_str() == Some(target.as_str()));
+
+            if owns {
+                let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+                return Some(format!("{} (PID {})", name.trim(), pid));
+            }
+        }
+
+        None
+    }
+
+    fn describe(&self) -> String {
+        let owner = self.owner().unwrap_or_else(|| "another process".into());
+        format!("{} on {}/{}", owner, self.address, self.protocol)
+    }
+}
+
+/// Sockets bound to a local port over both IPv4 and IPv6
+pub fn find_listeners(port: u16) -> Vec<Listener> {
+    let tables: &[(&str, &'static str, &str)] = &[
+        ("/proc/net/udp", "udp", UDP_UNCONNECTED),
+        ("/proc/net/udp6", "udp", UDP_UNCONNECTED),
+        ("/proc/net/tcp", "tcp", TCP_LISTEN),
+        ("/proc/net/tcp6", "tcp", TCP_LISTEN),
+    ];
+
+    let mut listeners = Vec::new();
+
+    for &(path, protocol, state) in tables {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+
+        for line in contents.lines().skip(1) {
+            if let Some(listener) = parse_socket_line(line, protocol, state) {
+                if listener.port == port {
+                    listeners.push(listener);
+                }
+            }
+        }
+    }
+
+    listeners
+}
+
+fn parse_socket_line(line: &str, protocol: &'static str, listen_state: &str) -> Option<Listener> {
+    let columns: Vec<&str> = line.split_whitespace().collect();
+
+    if columns.len() < 10 || columns[3] != listen_state {
+        return None;
+    }
+
+    let mut local = columns[1].split(':');
+    let address = local.next()?;
+    let port = u16::from_str_radix(local.next()?, 16).ok()?;
+    let inode = columns[9].parse().ok()?;
+
+    let (address, wildcard) = parse_address(address)?;
+
+    Some(Listener {
+        protocol,
+        address,
+        port,
+        wildcard,
+        inode,
+    })
+}
+
+/// Addresses in `/proc/net` are the raw network order bytes printed as
+/// native endian 32 bit words
+fn parse_address(hex: &str) -> Option<(String, bool)> {
+    let mut bytes = Vec::new();
+
+    for index in (0..hex.len()).step_by(8) {
+        let word = u32::from_str_radix(hex.get(index..index + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+
+    match bytes.len() {
+        4 => {
+            let address = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+            Some((address.to_string(), address.is_unspecified()))
+        }
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&bytes);
+            let address = Ipv6Addr::from(octets);
+            Some((address.to_string(), address.is_unspecified()))
+        }
+        _ => None,
+    }
+}
+
+/// Fails with the process holding a port dnsmasq needs on the portal
+/// address. Listeners bound to other addresses, like the systemd-resolved
+/// stub on 127.0.0.53, do not conflict as dnsmasq binds to the portal
+/// address only. DHCP servers listen on the wildcard address and are tied to
+/// their interfaces with `SO_BINDTODEVICE`, which `/proc/net` does not show,
+/// so only one on the portal address itself counts as a conflict there.
+pub fn check_port_conflicts(config: &Config) -> Result<()> {
+    let gateway = config.gateway.to_string();
+
+    let ports = [(config.dns_port, "DNS"), (DHCP_PORT, "DHCP")];
+
+    for &(port, service) in &ports {
+        for listener in find_listeners(port) {
+            let wildcard_conflicts = listener.wildcard && port != DHCP_PORT;
+
+            if wildcard_conflicts || listener.address == gateway {
+                bail!(ErrorKind::PortInUse(
+                    port,
+                    listener.describe(),
+                    remediation(port, service)
+                ));
+            }
+
+            debug!(
+                "Port {} is also used by {}, which does not conflict",
+                port,
+                listener.describe()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn remediation(port: u16, service: &str) -> String {
+    if port == DHCP_PORT {
+        format!("stop the {} server using it", service)
+    } else if port == DNS_PORT {
+        "set DNSStubListener=no in /etc/systemd/resolved.conf, stop the DNS server using it, \
+         or pass --dns-port to use an alternate port"
+            .into()
+    } else {
+        "pass a free port with --dns-port".into()
+    }
+}
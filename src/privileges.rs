@@ -1,7 +1,119 @@
+use std::fs;
+use std::process::{self, Command, Stdio};
+
 use nix::unistd::Uid;
 
+use backend::{DEFAULT_BACKEND, MOCK_BACKEND};
+use config::Config;
 use errors::*;
 
+/// NetworkManager actions needed for scanning, connecting and sharing the
+/// hotspot connection
+const POLKIT_ACTIONS: &[&str] = &[
+    "org.freedesktop.NetworkManager.network-control",
+    "org.freedesktop.NetworkManager.settings.modify.system",
+    "org.freedesktop.NetworkManager.enable-disable-wifi",
+    "org.freedesktop.NetworkManager.wifi.share.open",
+    "org.freedesktop.NetworkManager.wifi.share.protected",
+];
+
+/// Capabilities needed by dnsmasq and for binding the portal to port 80,
+/// with their bit in the effective capability set
+const CAPABILITIES: &[(&str, u32)] = &[
+    ("CAP_NET_BIND_SERVICE", 10),
+    ("CAP_NET_ADMIN", 12),
+    ("CAP_NET_RAW", 13),
+];
+
+pub struct PrivilegeCheck {
+    pub name: String,
+    pub granted: bool,
+}
+
+/// What the current process is allowed to do, used to run without root when
+/// polkit and file capabilities grant enough
+pub struct PrivilegeReport {
+    pub root: bool,
+    pub checks: Vec<PrivilegeCheck>,
+}
+
+impl PrivilegeReport {
+    pub fn collect(config: &Config) -> Self {
+        let mut checks = Vec::new();
+
+        if config.backend == DEFAULT_BACKEND {
+            for action in POLKIT_ACTIONS {
+                checks.push(PrivilegeCheck {
+                    name: format!("polkit: {}", action),
+                    granted: is_polkit_authorized(action),
+                });
+            }
+        }
+
+        let effective = get_effective_capabilities();
+
+        for &(capability, bit) in CAPABILITIES {
+            checks.push(PrivilegeCheck {
+                name: format!("capability: {}", capability),
+                granted: effective & (1 << bit) != 0,
+            });
+        }
+
+        PrivilegeReport {
+            root: Uid::effective().is_root(),
+            checks,
+        }
+    }
+
+    pub fn missing(&self) -> Vec<&str> {
+        self.checks
+            .iter()
+            .filter(|check| !check.granted)
+            .map(|check| check.name.as_str())
+            .collect()
+    }
+
+    pub fn is_sufficient(&self) -> bool {
+        self.root || self.missing().is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Running as root: {}", if self.root { "yes" } else { "no" });
+
+        for check in &self.checks {
+            let status = if check.granted { "ok" } else { "MISSING" };
+            println!("  [{:>7}] {}", status, check.name);
+        }
+
+        if self.is_sufficient() {
+            println!("\nAll required privileges are available");
+        } else {
+            println!("\nRun as root or grant the missing privileges");
+        }
+    }
+}
+
+/// Proceeds as root, or as a regular user holding every required polkit
+/// authorization and capability
+pub fn require_privileges(config: &Config) -> Result<()> {
+    // The mock backend does not touch the system's network configuration
+    if Uid::effective().is_root() || config.backend == MOCK_BACKEND {
+        return Ok(());
+    }
+
+    let report = PrivilegeReport::collect(config);
+
+    if report.is_sufficient() {
+        info!("Not running as root, using polkit authorizations and capabilities");
+        return Ok(());
+    }
+
+    bail!(ErrorKind::InsufficientPrivileges(
+        env!("CARGO_PKG_NAME").into(),
+        report.missing().join(", ")
+    ))
+}
+
 pub fn require_root() -> Result<()> {
     if !Uid::effective().is_root() {
         bail!(ErrorKind::RootPrivilegesRequired(
@@ -11,3 +123,27 @@ pub fn require_root() -> Result<()> {
         Ok(())
     }
 }
+
+fn is_polkit_authorized(action: &str) -> bool {
+    Command::new("pkcheck")
+        .arg("--action-id")
+        .arg(action)
+        .arg("--process")
+        .arg(process::id().to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+fn get_effective_capabilities() -> u64 {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+
+    status
+        .lines()
+        .find(|line| line.starts_with("CapEff:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|value| u64::from_str_radix(value, 16).ok())
+        .unwrap_or(0)
+}
@@ -0,0 +1,780 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+#[cfg(feature = "network-manager")]
+use network_manager::{Connection, ConnectionState};
+
+use certificates::EapTls;
+use channels::{band_of_channel, scan_access_points, Band};
+use errors::*;
+#[cfg(feature = "network-manager")]
+use nm::activate_connection;
+use secret::Secret;
+
+/// Prefix of the names of the connection profiles WiFi Connect creates, to
+/// tell them apart from ones provisioned otherwise, e.g. by the OS image
+pub const MANAGED_ID_PREFIX: &str = "wifi-connect-";
+
+/// User data marking the profiles WiFi Connect created, as other tools may
+/// name profiles alike or rename ours
+pub const MANAGED_MARKER: &str = "user.wifi-connect.managed";
+
+pub const CONNECTION_PROFILES: &[&str] = &["default", "campus", "low-power"];
+
+/// Tunneled EAP methods of enterprise networks authenticating with a password
+pub const EAP_METHODS: &[&str] = &["peap", "ttls"];
+
+/// Inner authentication of the tunneled EAP methods
+pub const PHASE2_AUTHS: &[&str] = &["mschapv2", "mschap", "pap", "chap", "gtc", "md5"];
+
+/// The only EAP configuration the network-manager crate knows of
+const DEFAULT_EAP_METHOD: &str = "peap";
+const DEFAULT_PHASE2_AUTH: &str = "mschapv2";
+
+/// Weakest signal in dBm of a 5 GHz access point that a campus profile
+/// locks the connection to 5 GHz for
+const CAMPUS_MIN_SIGNAL: i32 = -75;
+
+/// Tuning of client connections selected with `--connection-profile`.
+/// NetworkManager enables fast transition (802.11r) by itself where the
+/// supplicant supports it, and keeps background scanning to itself, so the
+/// profiles come down to power saving and the band.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionProfile {
+    /// NetworkManager defaults
+    Default,
+    /// Devices moving between the access points of a campus WLAN: power
+    /// saving off so that roaming scans are not delayed, and 5 GHz where
+    /// the network has it in range
+    Campus,
+    /// Stationary devices on battery: power saving on
+    LowPower,
+}
+
+impl Default for ConnectionProfile {
+    fn default() -> Self {
+        ConnectionProfile::Default
+    }
+}
+
+impl FromStr for ConnectionProfile {
+    type Err = String;
+
+    fn from_str(value: &str) -> ::std::result::Result<Self, String> {
+        match value {
+            "default" => Ok(ConnectionProfile::Default),
+            "campus" => Ok(ConnectionProfile::Campus),
+            "low-power" => Ok(ConnectionProfile::LowPower),
+            _ => Err(format!(
+                "'{}' is not one of {}",
+                value,
+                CONNECTION_PROFILES.join(", ")
+            )),
+        }
+    }
+}
+
+impl ConnectionProfile {
+    /// Value of the `802-11-wireless.powersave` NetworkManager property
+    fn powersave(&self) -> Option<u32> {
+        match *self {
+            ConnectionProfile::Default => None,
+            ConnectionProfile::Campus => Some(2),
+            ConnectionProfile::LowPower => Some(3),
+        }
+    }
+}
+
+/// Additional NetworkManager settings applied to the connections created by
+/// WiFi Connect, which the network-manager crate does not expose
+#[derive(Clone, Debug, Default)]
+pub struct ProfileSettings {
+    /// Name of the connection profile, only set on the ones WiFi Connect
+    /// manages, which get the managed marker along with it
+    pub id: Option<String>,
+    pub cloned_mac: Option<String>,
+    pub metered: bool,
+    pub no_autoconnect: bool,
+    pub autoconnect_retries: Option<u32>,
+    pub autoconnect_priority: Option<i32>,
+    /// Channel of an access point connection
+    pub channel: Option<u32>,
+    /// Band of an access point connection, implied by the channel if set
+    pub band: Option<Band>,
+    /// Keep the stations of an access point connection from reaching each
+    /// other
+    pub ap_isolation: bool,
+    /// Opportunistic Wireless Encryption (Enhanced Open) of an open access
+    /// point connection
+    pub owe: bool,
+    /// Proxy auto-config script of a client connection
+    pub proxy_pac_url: Option<String>,
+    pub connection_profile: ConnectionProfile,
+    /// Power saving of a client connection with `--wifi-powersave`, taking
+    /// precedence over the one of the connection profile
+    pub powersave: Option<bool>,
+    /// EAP method of enterprise networks with a password, PEAP if not set
+    pub eap_method: Option<String>,
+    /// Inner authentication of the EAP method, MSCHAPv2 if not set
+    pub phase2_auth: Option<String>,
+}
+
+impl ProfileSettings {
+    pub fn is_empty(&self) -> bool {
+        self.properties().is_empty()
+    }
+
+    /// Whether the settings only take effect once the connection is activated
+    pub fn requires_reactivation(&self) -> bool {
+        self.cloned_mac.is_some()
+            || self.channel.is_some()
+            || self.band.is_some()
+            || self.ap_isolation
+            || self.owe
+            || self.proxy_pac_url.is_some()
+            || self.connection_profile != ConnectionProfile::Default
+            || self.powersave.is_some()
+    }
+
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = Vec::new();
+
+        if let Some(ref id) = self.id {
+            properties.push(("connection.id", id.clone()));
+            properties.push((MANAGED_MARKER, "yes".to_string()));
+        }
+
+        if let Some(ref cloned_mac) = self.cloned_mac {
+            properties.push(("802-11-wireless.cloned-mac-address", cloned_mac.clone()));
+        }
+
+        if self.metered {
+            properties.push(("connection.metered", "yes".to_string()));
+        }
+
+        if self.no_autoconnect {
+            properties.push(("connection.autoconnect", "no".to_string()));
+        }
+
+        if let Some(autoconnect_retries) = self.autoconnect_retries {
+            properties.push((
+                "connection.autoconnect-retries",
+                autoconnect_retries.to_string(),
+            ));
+        }
+
+        if let Some(autoconnect_priority) = self.autoconnect_priority {
+            properties.push((
+                "connection.autoconnect-priority",
+                autoconnect_priority.to_string(),
+            ));
+        }
+
+        if let Some(channel) = self.channel {
+            if let Some(band) = band_of_channel(channel) {
+                properties.push(("802-11-wireless.band", band.nm_band().to_string()));
+            }
+            properties.push(("802-11-wireless.channel", channel.to_string()));
+        } else if let Some(band) = self.band {
+            properties.push(("802-11-wireless.band", band.nm_band().to_string()));
+        }
+
+        if self.ap_isolation {
+            properties.push(("802-11-wireless.ap-isolation", "yes".to_string()));
+        }
+
+        if self.owe {
+            properties.push(("802-11-wireless-security.key-mgmt", "owe".to_string()));
+        }
+
+        if let Some(ref proxy_pac_url) = self.proxy_pac_url {
+            properties.push(("proxy.method", "auto".to_string()));
+            properties.push(("proxy.pac-url", proxy_pac_url.clone()));
+        }
+
+        if let Some(powersave) = self.powersave_mode() {
+            properties.push(("802-11-wireless.powersave", powersave.to_string()));
+        }
+
+        properties
+    }
+
+    /// Value of the `802-11-wireless.powersave` NetworkManager property,
+    /// from `--wifi-powersave` or else the connection profile
+    pub fn powersave_mode(&self) -> Option<u32> {
+        match self.powersave {
+            Some(true) => Some(3),
+            Some(false) => Some(2),
+            None => self.connection_profile.powersave(),
+        }
+    }
+
+    /// Settings of a client connection to `ssid`, with a campus profile
+    /// locked to 5 GHz if the last scan of `interface` saw the network on it
+    /// with a usable signal
+    pub fn for_network(&self, interface: &str, ssid: &str) -> ProfileSettings {
+        let mut settings = self.clone();
+
+        if self.connection_profile == ConnectionProfile::Campus && self.band.is_none() {
+            let on_five_ghz = scan_access_points(interface)
+                .unwrap_or_default()
+                .iter()
+                .any(|access_point| {
+                    access_point.ssid == ssid
+                        && band_of_channel(access_point.channel) == Some(Band::FiveGHz)
+                        && access_point.signal >= CAMPUS_MIN_SIGNAL
+                });
+
+            if on_five_ghz {
+                info!("Preferring 5 GHz access points of '{}'", ssid);
+                settings.band = Some(Band::FiveGHz);
+            }
+        }
+
+        settings
+    }
+
+    /// Whether enterprise networks need an EAP configuration other than the
+    /// one of the network-manager crate, so that they are added with `nmcli`
+    pub fn has_custom_eap(&self) -> bool {
+        self.eap_method
+            .as_ref()
+            .map_or(false, |method| method != DEFAULT_EAP_METHOD)
+            || self
+                .phase2_auth
+                .as_ref()
+                .map_or(false, |auth| auth != DEFAULT_PHASE2_AUTH)
+    }
+
+    fn eap_method(&self) -> &str {
+        self.eap_method
+            .as_ref()
+            .map_or(DEFAULT_EAP_METHOD, String::as_str)
+    }
+
+    fn phase2_auth(&self) -> &str {
+        self.phase2_auth
+            .as_ref()
+            .map_or(DEFAULT_PHASE2_AUTH, String::as_str)
+    }
+}
+
+/// Name of the connection profile WiFi Connect creates for a network
+pub fn managed_connection_id(ssid: &str) -> String {
+    format!("{}{}", MANAGED_ID_PREFIX, ssid)
+}
+
+pub fn validate_cloned_mac(value: String) -> ::std::result::Result<(), String> {
+    match value.as_str() {
+        "random" | "stable" | "preserve" | "permanent" => Ok(()),
+        mac if is_mac_address(mac) => Ok(()),
+        _ => Err(format!(
+            "'{}' is not a MAC address or one of random, stable, preserve, permanent",
+            value
+        )),
+    }
+}
+
+pub fn is_mac_address(value: &str) -> bool {
+    let octets: Vec<&str> = value.split(':').collect();
+
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.bytes().all(|byte| byte.is_ascii_hexdigit()))
+}
+
+/// Runs `nmcli` with the arguments, whether it succeeded
+pub fn nmcli(args: &[&str]) -> bool {
+    Command::new("nmcli")
+        .args(args)
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Whether `nmcli` can store the secret, as its connection editor strips
+/// values and reads them line by line
+pub fn validate_secret(secret: &str) -> ::std::result::Result<(), String> {
+    if secret.trim() != secret || secret.contains(|c: char| c == '\n' || c == '\r') {
+        return Err(
+            "passphrases with leading or trailing whitespace or line breaks cannot be stored"
+                .into(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Stores secrets like the passphrase in a connection profile through the
+/// `nmcli` connection editor reading from stdin, as arguments of processes
+/// are visible to every user. `connection` is `id <name>` or `uuid <uuid>`.
+pub fn set_profile_secrets(connection: &[&str], secrets: &[(&str, &str)]) -> bool {
+    // The editor would prompt for an empty value
+    if secrets
+        .iter()
+        .any(|&(_, secret)| secret.is_empty() || validate_secret(secret).is_err())
+    {
+        return false;
+    }
+
+    let mut child = match Command::new("nmcli")
+        .args(&["connection", "edit"])
+        .args(connection)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+
+    // Without asking for confirmation to save a profile that connects
+    // automatically
+    let mut script = String::from("nmcli save-confirmation no\n");
+    for &(property, secret) in secrets {
+        script.push_str(&format!("set {} {}\n", property, secret));
+    }
+    script.push_str("save persistent\nquit\n");
+
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(script.as_bytes()).is_ok(),
+        None => false,
+    };
+
+    let output = match child.wait_with_output() {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+
+    // The editor exits successfully after commands failed, which it reports
+    let failed = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+        .any(|line| line.trim().starts_with("Error"));
+
+    written && output.status.success() && !failed
+}
+
+/// Writes the settings into the connection profile with `nmcli`
+#[cfg(feature = "network-manager")]
+pub fn apply_profile_settings(connection: &Connection, settings: &ProfileSettings) -> Result<()> {
+    let uuid = connection.settings().uuid.clone();
+
+    let mut command = Command::new("nmcli");
+    command.arg("connection").arg("modify").arg(&uuid);
+
+    for (property, value) in settings.properties() {
+        command.arg(property).arg(value);
+    }
+
+    let status = command
+        .status()
+        .chain_err(|| ErrorKind::ModifyConnection(uuid.clone()))?;
+
+    if !status.success() {
+        bail!(ErrorKind::ModifyConnection(uuid));
+    }
+
+    Ok(())
+}
+
+/// Changes to the profile of a saved network, from `--update-network` or
+/// `PATCH /saved-networks/:ssid`. Unset fields keep their current value.
+#[derive(Clone, Debug, Default)]
+pub struct ProfileUpdate {
+    pub passphrase: Option<Secret>,
+    pub autoconnect_priority: Option<i32>,
+    pub autoconnect: Option<bool>,
+    /// Static IPv4 address with prefix length, e.g. `192.168.1.10/24`
+    pub address: Option<String>,
+    pub gateway: Option<String>,
+    pub dns: Vec<String>,
+    /// Switches a static address back to one obtained via DHCP
+    pub dhcp: bool,
+}
+
+impl ProfileUpdate {
+    pub fn validate(&self) -> ::std::result::Result<(), String> {
+        if self.passphrase.is_none()
+            && self.autoconnect_priority.is_none()
+            && self.autoconnect.is_none()
+            && self.address.is_none()
+            && self.gateway.is_none()
+            && self.dns.is_empty()
+            && !self.dhcp
+        {
+            return Err("no changes to the saved network given".into());
+        }
+
+        if self.dhcp && (self.address.is_some() || self.gateway.is_some()) {
+            return Err("a static address and DHCP cannot be used together".into());
+        }
+
+        if self.gateway.is_some() && self.address.is_none() {
+            return Err("a gateway requires a static address".into());
+        }
+
+        if let Some(ref passphrase) = self.passphrase {
+            validate_secret(passphrase.expose())?;
+        }
+
+        if let Some(ref address) = self.address {
+            validate_ipv4_prefix(address)?;
+        }
+
+        for address in self.gateway.iter().chain(self.dns.iter()) {
+            if address.parse::<Ipv4Addr>().is_err() {
+                return Err(format!("'{}' is not an IPv4 address", address));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Property the passphrase is stored in, matching the security of the
+    /// network
+    fn passphrase_property(&self, security: &str) -> Result<Option<&'static str>> {
+        if self.passphrase.is_none() {
+            return Ok(None);
+        }
+
+        let property = match security {
+            "wpa" => "802-11-wireless-security.psk",
+            "wep" => "802-11-wireless-security.wep-key0",
+            "enterprise" => "802-1x.password",
+            _ => bail!(ErrorKind::InvalidProfileUpdate(
+                "open networks have no passphrase".into()
+            )),
+        };
+
+        Ok(Some(property))
+    }
+
+    /// Properties to modify, apart from the passphrase
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = Vec::new();
+
+        if let Some(autoconnect) = self.autoconnect {
+            let value = if autoconnect { "yes" } else { "no" };
+            properties.push(("connection.autoconnect", value.to_string()));
+        }
+
+        if let Some(autoconnect_priority) = self.autoconnect_priority {
+            properties.push((
+                "connection.autoconnect-priority",
+                autoconnect_priority.to_string(),
+            ));
+        }
+
+        if let Some(ref address) = self.address {
+            properties.push(("ipv4.method", "manual".to_string()));
+            properties.push(("ipv4.addresses", address.clone()));
+            properties.push(("ipv4.gateway", self.gateway.clone().unwrap_or_default()));
+        }
+
+        if self.dhcp {
+            properties.push(("ipv4.addresses", String::new()));
+            properties.push(("ipv4.gateway", String::new()));
+            properties.push(("ipv4.method", "auto".to_string()));
+        }
+
+        if !self.dns.is_empty() {
+            properties.push(("ipv4.dns", self.dns.join(",")));
+        }
+
+        properties
+    }
+}
+
+fn validate_ipv4_prefix(value: &str) -> ::std::result::Result<(), String> {
+    let mut parts = value.splitn(2, '/');
+    let address = parts
+        .next()
+        .and_then(|address| address.parse::<Ipv4Addr>().ok());
+    let prefix = parts.next().and_then(|prefix| prefix.parse::<u8>().ok());
+
+    match (address, prefix) {
+        (Some(_), Some(prefix)) if prefix >= 1 && prefix <= 32 => Ok(()),
+        _ => Err(format!(
+            "'{}' is not an IPv4 address with prefix length, e.g. 192.168.1.10/24",
+            value
+        )),
+    }
+}
+
+/// Modifies the saved profile with `nmcli`. Changes take effect the next time
+/// the connection is activated.
+pub fn update_connection_profile(uuid: &str, security: &str, update: &ProfileUpdate) -> Result<()> {
+    if let Err(reason) = update.validate() {
+        bail!(ErrorKind::InvalidProfileUpdate(reason));
+    }
+
+    let passphrase_property = update.passphrase_property(security)?;
+    let properties = update.properties();
+
+    if !properties.is_empty() {
+        let mut command = Command::new("nmcli");
+        command.arg("connection").arg("modify").arg(uuid);
+
+        for (property, value) in properties {
+            command.arg(property).arg(value);
+        }
+
+        let status = command
+            .status()
+            .chain_err(|| ErrorKind::ModifyConnection(uuid.to_string()))?;
+
+        if !status.success() {
+            bail!(ErrorKind::ModifyConnection(uuid.to_string()));
+        }
+    }
+
+    if let (Some(property), Some(passphrase)) = (passphrase_property, update.passphrase.as_ref()) {
+        if !set_profile_secrets(&["uuid", uuid], &[(property, passphrase.expose())]) {
+            bail!(ErrorKind::ModifyConnection(uuid.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads properties of a connection profile with `nmcli`, keyed by their
+/// names, e.g. `ipv4.method`
+pub fn read_profile_properties(uuid: &str, properties: &[&str]) -> Result<HashMap<String, String>> {
+    let fields = properties.join(",");
+
+    let output = Command::new("nmcli")
+        .args(&["-t", "-f", &fields, "connection", "show", uuid])
+        .output()
+        .chain_err(|| ErrorKind::ReadConnection(uuid.to_string()))?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::ReadConnection(uuid.to_string()));
+    }
+
+    // Terse output escapes colons and backslashes in values
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let separator = line.find(':')?;
+            let value = line[separator + 1..]
+                .replace("\\:", ":")
+                .replace("\\\\", "\\");
+            Some((line[..separator].to_string(), value))
+        })
+        .collect())
+}
+
+/// Security of a saved profile from its `key-mgmt` property
+pub fn profile_security(key_mgmt: Option<String>) -> &'static str {
+    match key_mgmt {
+        None => "none",
+        Some(ref key_mgmt) if key_mgmt == "none" => "wep",
+        Some(ref key_mgmt) if key_mgmt.starts_with("wpa-eap") => "enterprise",
+        Some(_) => "wpa",
+    }
+}
+
+/// Whether the profile carries the marker of the ones WiFi Connect created
+pub fn has_managed_marker(uuid: &str) -> bool {
+    read_profile_properties(uuid, &[MANAGED_MARKER])
+        .ok()
+        .and_then(|mut properties| properties.remove(MANAGED_MARKER))
+        .map_or(false, |value| value.trim() == "yes")
+}
+
+/// When each profile was last used, as a Unix time or `None` if never, and
+/// whether it is active, by UUID. Listed with a single `nmcli` call rather
+/// than one per profile.
+pub fn read_connection_timestamps() -> Result<HashMap<String, (Option<u64>, bool)>> {
+    let output = Command::new("nmcli")
+        .args(&["-t", "-f", "UUID,TIMESTAMP,ACTIVE", "connection", "show"])
+        .output()
+        .chain_err(|| ErrorKind::ConnectionTimestamps)?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::ConnectionTimestamps);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let uuid = fields.next()?.to_string();
+            let timestamp = fields
+                .next()?
+                .parse::<u64>()
+                .ok()
+                .filter(|&timestamp| timestamp != 0);
+            let active = fields.next()? == "yes";
+            Some((uuid, (timestamp, active)))
+        })
+        .collect())
+}
+
+/// Creates a connection profile without activating it, e.g. for a fallback
+/// network that is not in range during provisioning. The passphrase is stored
+/// separately, so that it does not show on the `nmcli` command line.
+pub fn add_connection_profile(
+    ssid: &str,
+    security: &str,
+    identity: &str,
+    passphrase: &str,
+    certificates: Option<&EapTls>,
+    settings: &ProfileSettings,
+) -> Result<()> {
+    if let Err(reason) = validate_secret(passphrase) {
+        bail!(ErrorKind::InvalidSecret(ssid.to_string(), reason));
+    }
+
+    let id = settings
+        .id
+        .clone()
+        .unwrap_or_else(|| managed_connection_id(ssid));
+    let mut secrets = Vec::new();
+
+    let mut command = Command::new("nmcli");
+    command
+        .args(&["connection", "add", "type", "wifi", "ifname", "*"])
+        .arg("con-name")
+        .arg(&id)
+        .args(&[MANAGED_MARKER, "yes"])
+        .arg("ssid")
+        .arg(ssid);
+
+    match (security, certificates) {
+        ("enterprise", Some(certificates)) => {
+            command
+                .args(&["wifi-sec.key-mgmt", "wpa-eap", "802-1x.eap", "tls"])
+                .arg("802-1x.identity")
+                .arg(identity)
+                .arg("802-1x.client-cert")
+                .arg(&certificates.client_cert)
+                .arg("802-1x.private-key")
+                .arg(&certificates.private_key);
+
+            // NetworkManager asks for the password of an unencrypted key
+            // unless told that there is none
+            if passphrase.is_empty() {
+                command.args(&["802-1x.private-key-password-flags", "4"]);
+            } else {
+                secrets.push(("802-1x.private-key-password", passphrase));
+            }
+
+            if let Some(ref ca_cert) = certificates.ca_cert {
+                command.arg("802-1x.ca-cert").arg(ca_cert);
+            }
+        }
+        ("enterprise", None) => {
+            command
+                .args(&["wifi-sec.key-mgmt", "wpa-eap", "802-1x.eap"])
+                .arg(settings.eap_method())
+                .arg("802-1x.phase2-auth")
+                .arg(settings.phase2_auth())
+                .arg("802-1x.identity")
+                .arg(identity);
+            secrets.push(("802-1x.password", passphrase));
+        }
+        ("wpa", _) => {
+            command.args(&["wifi-sec.key-mgmt", "wpa-psk"]);
+            secrets.push(("802-11-wireless-security.psk", passphrase));
+        }
+        ("wep", _) => {
+            command.args(&["wifi-sec.key-mgmt", "none"]);
+            secrets.push(("802-11-wireless-security.wep-key0", passphrase));
+        }
+        _ => {}
+    }
+
+    for (property, value) in settings.properties() {
+        command.arg(property).arg(value);
+    }
+
+    let status = command
+        .status()
+        .chain_err(|| ErrorKind::AddConnection(ssid.to_string()))?;
+
+    if !status.success() {
+        bail!(ErrorKind::AddConnection(ssid.to_string()));
+    }
+
+    secrets.retain(|&(_, secret)| !secret.is_empty());
+
+    if !secrets.is_empty() && !set_profile_secrets(&["id", &id], &secrets) {
+        nmcli(&["connection", "delete", "id", &id]);
+        bail!(ErrorKind::AddConnection(ssid.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Adds a profile authenticating with the SIM of a modem, `eap` being one of
+/// `sim`, `aka` and `aka'`
+pub fn add_eap_sim_profile(
+    ssid: &str,
+    eap: &str,
+    identity: &str,
+    settings: &ProfileSettings,
+) -> Result<()> {
+    let mut command = Command::new("nmcli");
+    command
+        .args(&["connection", "add", "type", "wifi", "ifname", "*"])
+        .arg("con-name")
+        .arg(managed_connection_id(ssid))
+        .args(&[MANAGED_MARKER, "yes"])
+        .arg("ssid")
+        .arg(ssid)
+        .args(&["wifi-sec.key-mgmt", "wpa-eap", "802-1x.eap", eap])
+        .arg("802-1x.identity")
+        .arg(identity);
+
+    for (property, value) in settings.properties() {
+        command.arg(property).arg(value);
+    }
+
+    let status = command
+        .status()
+        .chain_err(|| ErrorKind::AddConnection(ssid.to_string()))?;
+
+    if !status.success() {
+        bail!(ErrorKind::AddConnection(ssid.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Removes a whole setting like `802-11-wireless-security` from a connection
+#[cfg(feature = "network-manager")]
+pub fn remove_profile_setting(connection: &Connection, setting: &str) -> Result<()> {
+    let uuid = connection.settings().uuid.clone();
+
+    if !nmcli(&["connection", "modify", &uuid, "remove", setting]) {
+        bail!(ErrorKind::ModifyConnection(uuid));
+    }
+
+    Ok(())
+}
+
+/// Applies the settings and, when needed, activates the connection again so
+/// that they take effect, e.g. a cloned MAC address is only used on activation
+#[cfg(feature = "network-manager")]
+pub fn apply_and_reactivate(
+    connection: &Connection,
+    settings: &ProfileSettings,
+    state: ConnectionState,
+) -> Result<ConnectionState> {
+    apply_profile_settings(connection, settings)?;
+
+    if !settings.requires_reactivation() {
+        return Ok(state);
+    }
+
+    info!("Reactivating connection with updated profile settings...");
+
+    activate_connection(connection)
+}
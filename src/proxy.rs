@@ -0,0 +1,161 @@
+//! HTTP proxy of the provisioned network, for enterprise networks only
+//! reaching the internet through a mandatory proxy. A PAC URL is stored in
+//! the NetworkManager connection. NetworkManager has no manual proxy
+//! settings, so a host and port are written to an environment file instead,
+//! passed to the `--notify-exec` hook in `WIFI_CONNECT_PROXY` to configure
+//! the applications on the device.
+
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use capport::validate_portal_url;
+use errors::*;
+use redirect::percent_encode;
+use secret::Secret;
+
+pub const DEFAULT_PROXY_FILE: &str = "/var/lib/wifi-connect/proxy.env";
+
+/// Destinations reached without the proxy
+const NO_PROXY: &str = "localhost,127.0.0.1,::1";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ProxySettings {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Proxy auto-config script, used instead of a host and port
+    pub pac_url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+}
+
+impl ProxySettings {
+    pub fn validate(&self) -> ::std::result::Result<(), String> {
+        match (&self.host, self.port) {
+            (&Some(ref host), Some(port)) => {
+                if host.is_empty() || host.chars().any(|c| "/@'\" \t".contains(c)) {
+                    return Err(format!("'{}' is not a proxy host", host));
+                }
+
+                if port == 0 {
+                    return Err("the proxy port cannot be 0".into());
+                }
+            }
+            (&None, None) => {
+                if self.username.is_some() || self.password.is_some() {
+                    return Err("proxy credentials require a proxy host and port".into());
+                }
+            }
+            _ => return Err("a proxy host and port have to be given together".into()),
+        }
+
+        if self.password.is_some() && self.username.is_none() {
+            return Err("a proxy password requires a proxy username".into());
+        }
+
+        if let Some(ref pac_url) = self.pac_url {
+            validate_portal_url(pac_url.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Proxy URL with the credentials, if a host and port are set
+    pub fn url(&self) -> Option<String> {
+        let (host, port) = match (&self.host, self.port) {
+            (&Some(ref host), Some(port)) => (host, port),
+            _ => return None,
+        };
+
+        let credentials = match (&self.username, &self.password) {
+            (&Some(ref username), &Some(ref password)) => format!(
+                "{}:{}@",
+                percent_encode(username),
+                percent_encode(password.expose())
+            ),
+            (&Some(ref username), &None) => format!("{}@", percent_encode(username)),
+            _ => String::new(),
+        };
+
+        Some(format!("http://{}{}:{}", credentials, host, port))
+    }
+}
+
+/// Proxy settings entered in the `proxy_*` fields looked up with `value`,
+/// none if they are all left empty
+pub fn collect_proxy_settings<F>(value: F) -> ::std::result::Result<Option<ProxySettings>, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let field = |name: &str| {
+        value(name)
+            .map(|entered| entered.trim().to_string())
+            .filter(|entered| !entered.is_empty())
+    };
+
+    let port = match field("proxy_port") {
+        Some(port) => Some(
+            port.parse::<u16>()
+                .map_err(|_| format!("'{}' is not a proxy port", port))?,
+        ),
+        None => None,
+    };
+
+    let proxy = ProxySettings {
+        host: field("proxy_host"),
+        port,
+        pac_url: field("proxy_pac_url"),
+        username: field("proxy_username"),
+        password: field("proxy_password").map(Secret::from),
+    };
+
+    if proxy == ProxySettings::default() {
+        return Ok(None);
+    }
+
+    proxy.validate()?;
+
+    Ok(Some(proxy))
+}
+
+/// Writes the proxy of the connected network to `path` as shell variables,
+/// readable by root only as they may carry credentials. Without a proxy
+/// host a file left from an earlier network is removed.
+pub fn write_proxy_file(path: &Path, proxy: &Option<ProxySettings>) -> Result<()> {
+    let error = || ErrorKind::ProxyFile(path.display().to_string());
+
+    let url = match proxy.as_ref().and_then(ProxySettings::url) {
+        Some(url) => url,
+        None => {
+            return match fs::remove_file(path) {
+                Err(ref e) if e.kind() == IoErrorKind::NotFound => Ok(()),
+                result => result.chain_err(error),
+            };
+        }
+    };
+
+    let mut contents = String::new();
+
+    for name in &["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+        contents.push_str(&format!("{}='{}'\n", name, url));
+    }
+
+    for name in &["no_proxy", "NO_PROXY"] {
+        contents.push_str(&format!("{}='{}'\n", name, NO_PROXY));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(error)?;
+    }
+
+    let temporary = path.with_extension("tmp");
+
+    fs::write(&temporary, contents).chain_err(error)?;
+    fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600)).chain_err(error)?;
+    fs::rename(&temporary, path).chain_err(error)?;
+
+    info!("Proxy of the network written to {}", path.display());
+
+    Ok(())
+}
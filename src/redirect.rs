@@ -0,0 +1,83 @@
+//! `--redirect-url` handling. Once credentials are accepted the portal sends
+//! the client on to the URL, typically a deep link like
+//! `myapp://provisioned?device={device_id}` closing app-driven onboarding.
+
+use webhook::resolve_device_id;
+
+/// Placeholders expanded in `--redirect-url`, percent-encoded
+const SSID_PLACEHOLDER: &str = "{ssid}";
+const DEVICE_ID_PLACEHOLDER: &str = "{device_id}";
+
+pub fn validate_redirect_url(value: String) -> ::std::result::Result<(), String> {
+    let scheme = value.split(':').next().unwrap_or("");
+
+    let valid_scheme = value.contains(':')
+        && scheme
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+
+    if valid_scheme {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not an absolute URL", value))
+    }
+}
+
+/// `--redirect-url` with the placeholders filled in
+pub fn expand_redirect_url(template: &str, ssid: &str, device_id: &Option<String>) -> String {
+    template
+        .replace(SSID_PLACEHOLDER, &percent_encode(ssid))
+        .replace(
+            DEVICE_ID_PLACEHOLDER,
+            &percent_encode(&resolve_device_id(device_id)),
+        )
+}
+
+/// Page following the redirect, with a link for browsers that refuse to
+/// open the app on their own
+pub fn landing_page(url: &str) -> String {
+    let url = escape_html(url);
+
+    format!(
+        concat!(
+            "<!DOCTYPE html>\n",
+            "<html>\n",
+            "<head>\n",
+            "<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<meta http-equiv=\"refresh\" content=\"0; url={url}\">\n",
+            "<title>Connecting</title>\n",
+            "</head>\n",
+            "<body>\n",
+            "<p>The device is connecting to the network.</p>\n",
+            "<p><a href=\"{url}\">Continue</a></p>\n",
+            "</body>\n",
+            "</html>\n"
+        ),
+        url = url
+    )
+}
+
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
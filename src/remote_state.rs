@@ -0,0 +1,117 @@
+//! Desired state pulled from `--state-url`, for rotating WiFi credentials
+//! across a fleet from one place. The manifest has the format of a desired
+//! state file and is only applied if the server signed it with
+//! `--state-secret`, in the same header and format as webhook payloads.
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use desired_state::{parse_desired_state, DesiredState};
+use encryption::SecretsPolicy;
+use errors::*;
+use secret::Secret;
+use signing::verify_hmac_sha256_hex;
+use webhook::SIGNATURE_HEADER;
+
+/// Seconds between pulls of the manifest by the watchdog
+pub const DEFAULT_STATE_INTERVAL: u64 = 900;
+
+/// Seconds a single pull may take
+const FETCH_TIMEOUT: u64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct StateUrl {
+    pub url: String,
+    pub secret: Secret,
+    /// Seconds between pulls
+    pub interval: u64,
+}
+
+impl StateUrl {
+    /// Whether the manifest is due again, `pulled` being the last attempt
+    pub fn is_due(&self, pulled: Option<Instant>) -> bool {
+        pulled.map_or(true, |pulled| {
+            pulled.elapsed() >= Duration::from_secs(self.interval)
+        })
+    }
+}
+
+/// Downloads the manifest and verifies its signature
+pub fn fetch_desired_state(state_url: &StateUrl, secrets: &SecretsPolicy) -> Result<DesiredState> {
+    let url = &state_url.url;
+    let error = || ErrorKind::DesiredState(url.clone());
+
+    // The response headers come first on stdout, for the signature
+    let output = Command::new("curl")
+        .args(&[
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--include",
+            "--max-time",
+            &FETCH_TIMEOUT.to_string(),
+            url,
+        ])
+        .output()
+        .chain_err(error)?;
+
+    if !output.status.success() {
+        warn!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        bail!(error());
+    }
+
+    let response = String::from_utf8_lossy(&output.stdout);
+
+    let (headers, body) = match split_response(&response) {
+        Some(response) => response,
+        None => bail!(error()),
+    };
+
+    let key = state_url.secret.expose().as_bytes();
+
+    let signed = header(headers, SIGNATURE_HEADER).map_or(false, |signature| {
+        match (signature.get(..7), signature.get(7..)) {
+            (Some(scheme), Some(hex)) => {
+                scheme.eq_ignore_ascii_case("sha256=")
+                    && verify_hmac_sha256_hex(key, body.as_bytes(), hex)
+            }
+            _ => false,
+        }
+    });
+
+    if !signed {
+        bail!(ErrorKind::StateSignature(url.clone()));
+    }
+
+    parse_desired_state(body, url, secrets)
+}
+
+/// Headers and body of the final response, skipping the ones of proxies
+/// and `100 Continue`
+fn split_response(response: &str) -> Option<(&str, &str)> {
+    let mut rest = response;
+
+    loop {
+        let end = rest.find("\r\n\r\n")?;
+        let (headers, body) = (&rest[..end], &rest[end + 4..]);
+
+        if !body.starts_with("HTTP/") {
+            return Some((headers, body));
+        }
+
+        rest = body;
+    }
+}
+
+fn header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(header), Some(value)) if header.trim().eq_ignore_ascii_case(name) => {
+                Some(value.trim())
+            }
+            _ => None,
+        }
+    })
+}
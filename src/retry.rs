@@ -0,0 +1,104 @@
+use std::cmp;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use errors::*;
+
+pub const DEFAULT_NM_RETRIES: usize = 3;
+
+/// Delay before the first retry, doubled on each further one
+const INITIAL_BACKOFF: u64 = 250;
+
+/// Upper bound of the delay between retries
+const MAX_BACKOFF: u64 = 4000;
+
+/// D-Bus errors NetworkManager returns while it is busy, which succeed when
+/// the call is repeated
+const TRANSIENT_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Timeout",
+    "org.freedesktop.DBus.Error.TimedOut",
+];
+
+static NM_RETRIES: AtomicUsize = AtomicUsize::new(DEFAULT_NM_RETRIES);
+
+/// Sets how often NetworkManager calls are retried, from `--nm-retries`
+pub fn set_nm_retries(retries: usize) {
+    NM_RETRIES.store(retries, Ordering::SeqCst);
+}
+
+/// Runs a NetworkManager call, retrying it with exponential backoff and
+/// jitter while it fails with a transient D-Bus error
+pub fn retry_nm<T, E, F>(operation: &str, call: F) -> Result<T>
+where
+    E: Into<Error> + Debug,
+    F: FnMut() -> ::std::result::Result<T, E>,
+{
+    retry_nm_change(operation, || None, call)
+}
+
+/// Like `retry_nm` for calls that change state, e.g. activating a
+/// connection. A call failing with a transient error may have taken effect
+/// all the same, so `applied` looks at the current state before each retry
+/// and returns what the call would have, to not repeat it.
+pub fn retry_nm_change<T, E, A, F>(operation: &str, mut applied: A, mut call: F) -> Result<T>
+where
+    E: Into<Error> + Debug,
+    A: FnMut() -> Option<T>,
+    F: FnMut() -> ::std::result::Result<T, E>,
+{
+    let retries = NM_RETRIES.load(Ordering::SeqCst);
+    let mut attempt = 0;
+
+    loop {
+        if attempt > 0 {
+            if let Some(value) = applied() {
+                info!("{} took effect despite the D-Bus error", operation);
+                return Ok(value);
+            }
+        }
+
+        match call() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                if attempt >= retries || !is_transient(&e) {
+                    return Err(e.into());
+                }
+
+                attempt += 1;
+
+                let delay = backoff(attempt);
+
+                warn!(
+                    "{} failed with a transient D-Bus error, retrying in {}ms ({}/{})",
+                    operation, delay, attempt, retries
+                );
+
+                thread::sleep(Duration::from_millis(delay));
+            }
+        }
+    }
+}
+
+/// The D-Bus error name is only part of the debug form of the error chain
+fn is_transient<E: Debug>(e: &E) -> bool {
+    let debug = format!("{:?}", e);
+
+    TRANSIENT_ERRORS.iter().any(|name| debug.contains(name))
+}
+
+/// Delay in milliseconds before the given retry, with up to half of it
+/// added as jitter so that concurrent callers do not retry in lockstep
+fn backoff(attempt: usize) -> u64 {
+    let exponent = cmp::min(attempt - 1, 16) as u32;
+    let delay = cmp::min(INITIAL_BACKOFF.saturating_mul(1 << exponent), MAX_BACKOFF);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| u64::from(elapsed.subsec_nanos()))
+        .unwrap_or(0);
+
+    delay + nanos % (delay / 2 + 1)
+}
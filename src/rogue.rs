@@ -0,0 +1,85 @@
+//! Evil twin detection. An access point advertising the SSID of the portal,
+//! or the SSID of a saved network with other security than the saved
+//! profile, is likely out to lure clients or the device into handing over
+//! credentials. The backends merge scan results by SSID, so the access
+//! points are compared by BSSID from the scan results of the kernel.
+
+use backend::Backend;
+use channels::scan_access_points;
+use network::Network;
+use wiphy::interface_address;
+
+/// Logs the rogue access points seen by the last scan of `backend` and sets
+/// the warning of the networks in `networks` they impersonate
+pub fn flag_rogue_access_points(
+    backend: &dyn Backend,
+    portal_ssid: &str,
+    networks: &mut [Network],
+) {
+    let scanned = match scan_access_points(backend.interface()) {
+        Ok(scanned) => scanned,
+        Err(e) => {
+            debug!("Skipping the rogue access point check: {}", e);
+            return;
+        }
+    };
+
+    // The portal's own access point shows up while it is being torn down
+    let own_bssid = interface_address(backend.interface()).unwrap_or_default();
+
+    for access_point in &scanned {
+        if access_point.ssid == portal_ssid && !access_point.bssid.eq_ignore_ascii_case(&own_bssid)
+        {
+            warn!(
+                "Security warning: access point {} advertises the SSID of the portal '{}'",
+                access_point.bssid, portal_ssid
+            );
+        }
+    }
+
+    let saved = match backend.saved_networks() {
+        Ok(saved) => saved,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+
+    for network in networks.iter_mut() {
+        if !saved.iter().any(|saved| saved.ssid == network.ssid) {
+            continue;
+        }
+
+        let expected = match backend.saved_network_details(&network.ssid) {
+            Ok(Some(details)) => details.security,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+
+        let mismatched = scanned
+            .iter()
+            .filter(|access_point| {
+                access_point.ssid == network.ssid && access_point.security != expected
+            })
+            .map(|access_point| format!("{} ({})", access_point.bssid, access_point.security))
+            .collect::<Vec<_>>();
+
+        if mismatched.is_empty() {
+            continue;
+        }
+
+        let warning = format!(
+            "'{}' is saved with {} security, but advertised by {}",
+            network.ssid,
+            expected,
+            mismatched.join(", ")
+        );
+
+        warn!("Security warning: {}", warning);
+
+        network.warning = Some(warning);
+    }
+}
@@ -0,0 +1,249 @@
+//! Generated hotspot passphrases. `--portal-passphrase random[:length]`
+//! gives every device its own passphrase on first run instead of a default
+//! shared by the fleet, and `--rotate-passphrase` replaces it regularly for
+//! semi-public kiosks where a passphrase shown on screen should not stay
+//! valid for long. The current passphrase is kept in a state file so that a
+//! restart does not hand out another one, encrypted with
+//! `--encrypt-secrets`.
+
+use std::fs::{self, File};
+use std::io::ErrorKind as IoErrorKind;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use encryption::SecretsPolicy;
+use errors::*;
+use hotspot_manager::{HotspotCommand, HotspotCommandResponse, HotspotController};
+use passphrase_policy::{validate_passphrase, MAX_PASSPHRASE_LENGTH, MIN_PASSPHRASE_LENGTH};
+use secret::Secret;
+
+pub const PASSPHRASE_FILE: &str = "/var/lib/wifi-connect/portal-passphrase";
+
+/// Characters of generated passphrases, leaving out the ones easily mistaken
+/// for each other when typed from a screen
+const PASSPHRASE_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyzACDEFGHJKLMNPQRTUVWXY346789";
+
+pub const DEFAULT_PASSPHRASE_LENGTH: usize = 12;
+
+/// `--portal-passphrase` value asking for a generated passphrase
+const RANDOM_PASSPHRASE: &str = "random";
+
+const RANDOM_SOURCE: &str = "/dev/urandom";
+
+/// Random WPA2 passphrase of `length` characters
+pub fn generate_passphrase(length: usize) -> Result<Secret> {
+    let mut random = File::open(RANDOM_SOURCE).chain_err(|| ErrorKind::RotatePassphrase)?;
+
+    // Bytes past the largest multiple of the alphabet size are dropped, so
+    // that every character is equally likely
+    let limit = 256 - 256 % PASSPHRASE_ALPHABET.len();
+
+    let mut passphrase = String::with_capacity(length);
+    let mut buffer = [0u8; 32];
+
+    while passphrase.len() < length {
+        random
+            .read_exact(&mut buffer)
+            .chain_err(|| ErrorKind::RotatePassphrase)?;
+
+        for &byte in buffer.iter().filter(|&&byte| (byte as usize) < limit) {
+            if passphrase.len() == length {
+                break;
+            }
+
+            let index = byte as usize % PASSPHRASE_ALPHABET.len();
+            passphrase.push(PASSPHRASE_ALPHABET[index] as char);
+        }
+    }
+
+    Ok(Secret::from(passphrase))
+}
+
+/// Length of the passphrase to generate if `value` is `random[:length]`
+pub fn parse_random_passphrase(value: &str) -> Option<usize> {
+    if value == RANDOM_PASSPHRASE {
+        return Some(DEFAULT_PASSPHRASE_LENGTH);
+    }
+
+    let mut parts = value.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some(RANDOM_PASSPHRASE), Some(length)) => length
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length >= MIN_PASSPHRASE_LENGTH && *length <= MAX_PASSPHRASE_LENGTH),
+        _ => None,
+    }
+}
+
+/// Rejects `random:length` with a length WPA2 does not allow, and literal
+/// passphrases WPA2 or WPA3 do not allow
+pub fn validate_portal_passphrase(value: String) -> ::std::result::Result<(), String> {
+    if parse_random_passphrase(&value).is_some() {
+        Ok(())
+    } else if value.starts_with("random:") {
+        Err(format!(
+            "'{}' is not random:length with a length from {} to {}",
+            value, MIN_PASSPHRASE_LENGTH, MAX_PASSPHRASE_LENGTH
+        ))
+    } else {
+        validate_passphrase(&value)
+    }
+}
+
+/// Passphrase generated for this device on first run, kept in the state
+/// file afterwards
+pub fn device_passphrase(length: usize, secrets: &SecretsPolicy) -> Result<Secret> {
+    if let Some(passphrase) = load_passphrase(secrets) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = generate_passphrase(length)?;
+    store_passphrase(&passphrase, secrets)?;
+
+    info!(
+        "Generated the hotspot passphrase, stored in {}",
+        PASSPHRASE_FILE
+    );
+
+    Ok(passphrase)
+}
+
+/// Passphrase stored by an earlier run, if any
+pub fn load_passphrase(secrets: &SecretsPolicy) -> Option<Secret> {
+    match fs::read_to_string(PASSPHRASE_FILE) {
+        Ok(contents) => {
+            let passphrase = match secrets.open(&Secret::from(contents.trim()), PASSPHRASE_FILE) {
+                Ok(passphrase) => passphrase,
+                Err(e) => {
+                    warn!("{}", e);
+                    return None;
+                }
+            };
+
+            if passphrase.is_empty() {
+                None
+            } else {
+                Some(passphrase)
+            }
+        }
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => None,
+        Err(e) => {
+            warn!(
+                "Reading the rotated passphrase from {} failed: {}",
+                PASSPHRASE_FILE, e
+            );
+            None
+        }
+    }
+}
+
+/// Stores `passphrase` readable by root only. The file is replaced
+/// atomically.
+pub fn store_passphrase(passphrase: &Secret, secrets: &SecretsPolicy) -> Result<()> {
+    let path = Path::new(PASSPHRASE_FILE);
+    let passphrase = secrets.seal(passphrase, PASSPHRASE_FILE)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(|| ErrorKind::RotatePassphrase)?;
+    }
+
+    let temporary = path.with_extension("tmp");
+
+    fs::write(&temporary, passphrase.expose()).chain_err(|| ErrorKind::RotatePassphrase)?;
+    fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600))
+        .chain_err(|| ErrorKind::RotatePassphrase)?;
+    fs::rename(&temporary, path).chain_err(|| ErrorKind::RotatePassphrase)?;
+
+    Ok(())
+}
+
+/// Rotates the passphrase every `interval` seconds through the hotspot
+/// controller
+pub fn spawn_passphrase_rotation(controller: HotspotController, interval: u64) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval));
+
+        match controller.send(HotspotCommand::RotatePassphrase) {
+            Ok(HotspotCommandResponse::Failed(reason)) => error!("{}", reason),
+            Ok(_) => {}
+            Err(e) => {
+                error!("Rotating the hotspot passphrase failed: {}", e);
+                return;
+            }
+        }
+    });
+}
+
+/// Network configuration understood by the camera apps of phones, encoded in
+/// the QR code of the hotspot
+pub fn wifi_qr_payload(ssid: &str, passphrase: &Option<Secret>) -> String {
+    match *passphrase {
+        Some(ref passphrase) => format!(
+            "WIFI:T:WPA;S:{};P:{};;",
+            escape_qr_field(ssid),
+            escape_qr_field(passphrase.expose())
+        ),
+        None => format!("WIFI:T:nopass;S:{};;", escape_qr_field(ssid)),
+    }
+}
+
+/// QR code of `payload` as SVG
+pub fn qr_code_svg(payload: &str) -> Result<String> {
+    qrencode(payload, "SVG")
+}
+
+/// Prints the QR code of `payload` to the terminal, or the payload itself
+/// without `qrencode`
+pub fn print_qr_code(payload: &str) {
+    match qrencode(payload, "UTF8") {
+        Ok(qr_code) => println!("{}", qr_code),
+        Err(e) => {
+            warn!("{}", e);
+            println!("{}", payload);
+        }
+    }
+}
+
+/// The payload is written to the standard input of `qrencode`, keeping the
+/// passphrase off its command line
+fn qrencode(payload: &str, format: &str) -> Result<String> {
+    let mut child = Command::new("qrencode")
+        .args(&["-t", format, "-o", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::QrCode)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(payload.as_bytes())
+            .chain_err(|| ErrorKind::QrCode)?;
+    }
+
+    let output = child.wait_with_output().chain_err(|| ErrorKind::QrCode)?;
+
+    if !output.status.success() {
+        bail!(ErrorKind::QrCode);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn escape_qr_field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if "\\;,:\"".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
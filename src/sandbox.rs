@@ -0,0 +1,333 @@
+//! Optional hardening of the captive portal process, which serves untrusted
+//! clients on an open access point. Before the portal is set up, a Landlock
+//! ruleset confines filesystem access and a seccomp filter denies syscalls
+//! the daemon and the tools it runs never need. Neither reaches threads that
+//! already run, so both are applied while the process is still single
+//! threaded and the rules allow what setting up the portal needs. Every
+//! thread and child process spawned afterwards inherits them.
+
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::libc;
+
+use access_log::AccessLogTarget;
+use backend::{IWD_BACKEND, OPENWRT_BACKEND};
+use config::Config;
+use dnsmasq::{DNSMASQ_LEASE_FILE, DNSMASQ_PID_FILE};
+use errors::*;
+
+const LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const LANDLOCK_ADD_RULE: libc::c_long = 445;
+const LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+
+const ACCESS_FS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+/// Rights Landlock accepts on a rule for a file instead of a directory
+const ACCESS_FS_FILE: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE;
+
+/// Tools like nmcli, iw and dnsmasq are executed from these
+const READ_ONLY_PATHS: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc", "/proc", "/sys", "/dev",
+];
+
+/// State and runtime files of WiFi Connect. Connecting to the D-Bus and
+/// NetworkManager sockets is not a filesystem access Landlock restricts.
+const STATE_DIRECTORY: &str = "/var/lib/wifi-connect";
+const RUNTIME_DIRECTORY: &str = "/run/wifi-connect";
+const READ_WRITE_PATHS: &[&str] = &[STATE_DIRECTORY, RUNTIME_DIRECTORY, "/dev/null"];
+
+/// Where `wpa_cli` binds its end of the control socket, for the WiFi Direct
+/// fallback
+const WPA_CLIENT_DIRECTORY: &str = "/tmp";
+
+/// Changes of `uci` that are not committed yet
+const UCI_SAVE_DIRECTORY: &str = "/tmp/.uci";
+
+const IWD_STATE_DIRECTORY: &str = "/var/lib/iwd";
+
+const LEDS_DIRECTORY: &str = "/sys/class/leds";
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+pub fn apply_sandbox(config: &Config) -> Result<()> {
+    set_no_new_privs()?;
+
+    let mut read_only: Vec<PathBuf> = READ_ONLY_PATHS.iter().map(PathBuf::from).collect();
+    read_only.push(config.ui_directory.clone());
+    if let Some(parent) = config.dnsmasq_conf.as_ref().and_then(|path| path.parent()) {
+        read_only.push(parent.to_path_buf());
+    }
+
+    let mut read_write: Vec<PathBuf> = READ_WRITE_PATHS.iter().map(PathBuf::from).collect();
+
+    // Written to atomically through a temporary file next to them, or
+    // created along with their directory
+    let mut files = vec![
+        PathBuf::from(DNSMASQ_LEASE_FILE),
+        PathBuf::from(DNSMASQ_PID_FILE),
+        config.proxy_file.clone(),
+        config.extra_fields_file.clone(),
+        config.history_file.clone(),
+        config.certificate_directory.clone(),
+    ];
+    files.extend(config.pidfile.clone());
+    if let Some(ref access_log) = config.access_log {
+        if let AccessLogTarget::File(ref path) = access_log.target {
+            files.push(path.clone());
+        }
+    }
+    for file in &files {
+        if let Some(parent) = file.parent() {
+            read_write.push(parent.to_path_buf());
+        }
+    }
+
+    // Only rules for existing paths can be added
+    for directory in &[STATE_DIRECTORY, RUNTIME_DIRECTORY] {
+        fs::create_dir_all(directory).chain_err(|| ErrorKind::Sandbox("landlock".into()))?;
+    }
+
+    // The attributes of an LED are reached through a symbolic link, which
+    // Landlock resolves
+    if let Some(ref led) = config.notify_led {
+        if let Ok(path) = fs::canonicalize(Path::new(LEDS_DIRECTORY).join(led)) {
+            read_write.push(path);
+        }
+    }
+
+    if config.wifi_direct_fallback {
+        read_write.push(PathBuf::from(WPA_CLIENT_DIRECTORY));
+    }
+
+    if config.backend == IWD_BACKEND {
+        read_write.push(PathBuf::from(IWD_STATE_DIRECTORY));
+    }
+
+    // uci commits the wireless, network and dhcp configs of the openwrt
+    // backend
+    if config.backend == OPENWRT_BACKEND {
+        read_write.push(PathBuf::from("/etc/config"));
+        read_write.push(PathBuf::from(UCI_SAVE_DIRECTORY));
+    }
+
+    match restrict_filesystem(&read_only, &read_write) {
+        Ok(()) => info!("Filesystem access restricted with Landlock"),
+        Err(Errno::ENOSYS) | Err(Errno::EOPNOTSUPP) => {
+            warn!("Landlock is not supported by this kernel, filesystem access is not restricted")
+        }
+        Err(e) => return Err(e).chain_err(|| ErrorKind::Sandbox("landlock".into())),
+    }
+
+    match seccomp::install_filter() {
+        Ok(true) => info!("Syscall filter installed"),
+        Ok(false) => warn!("Seccomp filtering is not available on this architecture"),
+        Err(e) => return Err(e).chain_err(|| ErrorKind::Sandbox("seccomp".into())),
+    }
+
+    Ok(())
+}
+
+fn set_no_new_privs() -> Result<()> {
+    let result = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+
+    Errno::result(result)
+        .map(drop)
+        .chain_err(|| ErrorKind::Sandbox("no_new_privs".into()))
+}
+
+fn restrict_filesystem(read_only: &[PathBuf], read_write: &[PathBuf]) -> ::std::result::Result<(), Errno> {
+    let attr = LandlockRulesetAttr {
+        handled_access_fs: ACCESS_FS_ALL,
+    };
+
+    let ruleset_fd = unsafe {
+        libc::syscall(
+            LANDLOCK_CREATE_RULESET,
+            &attr as *const LandlockRulesetAttr,
+            mem::size_of::<LandlockRulesetAttr>(),
+            0,
+        )
+    };
+    let ruleset_fd = Errno::result(ruleset_fd)? as libc::c_int;
+
+    let result = add_rules(ruleset_fd, read_only, ACCESS_FS_READ)
+        .and_then(|_| add_rules(ruleset_fd, read_write, ACCESS_FS_ALL))
+        .and_then(|_| {
+            let result = unsafe { libc::syscall(LANDLOCK_RESTRICT_SELF, ruleset_fd, 0) };
+            Errno::result(result).map(drop)
+        });
+
+    unsafe { libc::close(ruleset_fd) };
+
+    result
+}
+
+fn add_rules(ruleset_fd: libc::c_int, paths: &[PathBuf], access: u64) -> ::std::result::Result<(), Errno> {
+    for path in paths {
+        if !path.exists() {
+            continue;
+        }
+
+        let parent_fd = open_path(path)?;
+
+        // Device nodes like /dev/null are allowed as files
+        let allowed_access = if path.is_dir() {
+            access
+        } else {
+            access & ACCESS_FS_FILE
+        };
+
+        let rule = LandlockPathBeneathAttr {
+            allowed_access,
+            parent_fd,
+        };
+
+        let result = unsafe {
+            libc::syscall(
+                LANDLOCK_ADD_RULE,
+                ruleset_fd,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule as *const LandlockPathBeneathAttr,
+                0,
+            )
+        };
+
+        unsafe { libc::close(parent_fd) };
+
+        Errno::result(result)?;
+    }
+
+    Ok(())
+}
+
+fn open_path(path: &Path) -> ::std::result::Result<libc::c_int, Errno> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)?;
+
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+
+    Errno::result(fd)
+}
+
+mod seccomp {
+    use nix::errno::Errno;
+    use nix::libc;
+
+    const SECCOMP_DATA_NR: u32 = 0;
+    const SECCOMP_DATA_ARCH: u32 = 4;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(target_arch = "arm")]
+    const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    /// Syscalls neither the portal nor the tools it runs need, which would
+    /// give a compromised process a way out
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+    ];
+
+    fn statement(code: u16, k: u32) -> libc::sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    /// Returns `false` when the architecture is not supported
+    pub fn install_filter() -> Result<bool, Errno> {
+        let arch = match AUDIT_ARCH {
+            Some(arch) => arch,
+            None => return Ok(false),
+        };
+
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+        let mut filter = vec![
+            statement(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+            jump(BPF_JMP_JEQ_K, arch, 1, 0),
+            statement(BPF_RET_K, deny),
+            statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+            // x32 syscalls share the x86_64 audit arch
+            jump(BPF_JMP_JGE_K, 0x4000_0000, 0, 1),
+            statement(BPF_RET_K, deny),
+        ];
+
+        for &syscall in DENIED_SYSCALLS {
+            filter.push(jump(BPF_JMP_JEQ_K, syscall as u32, 0, 1));
+            filter.push(statement(BPF_RET_K, deny));
+        }
+
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+
+        let result = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            )
+        };
+
+        Errno::result(result).map(|_| true)
+    }
+}
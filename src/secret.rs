@@ -0,0 +1,74 @@
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+use serde::{Deserialize, Deserializer};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Passphrase or other credential that is redacted when formatted and wiped
+/// from memory when dropped. Copies handed to NetworkManager or external
+/// tools through `expose` are outside of its control.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: String) -> Self {
+        Secret(secret)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Secret(secret)
+    }
+}
+
+impl<'a> From<&'a str> for Secret {
+    fn from(secret: &'a str) -> Self {
+        Secret(secret.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Volatile writes, so that wiping memory that is about to be freed is
+        // not optimized away
+        unsafe {
+            for byte in self.0.as_mut_vec().iter_mut() {
+                ptr::write_volatile(byte, 0);
+            }
+        }
+
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
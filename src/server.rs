@@ -1,37 +1,284 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::fs;
+use std::io;
 use std::net::Ipv4Addr;
-use std::sync::mpsc::{Receiver, Sender};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use iron::modifiers::Redirect;
+use iron::method::Method;
+use iron::mime::Mime;
+use iron::modifiers::{Header, Redirect};
 use iron::prelude::*;
+use iron::response::WriteBody;
 use iron::{
-    headers, status, typemap, AfterMiddleware, Iron, IronError, IronResult, Request, Response, Url,
+    headers, status, typemap, AfterMiddleware, AroundMiddleware, BeforeMiddleware, Handler, Iron,
+    IronError, IronResult, Request, Response, Timeouts, Url,
 };
 use iron_cors::CorsMiddleware;
 use mount::Mount;
-use params::{FromValue, Params};
-use std::path::PathBuf;
+use params::{FromValue, Params, Value};
 use persistent::Write;
 use router::Router;
 use serde_json;
-use staticfile::Static;
 
+use access_log::{AccessLog, AccessLogSettings};
+use assets::static_assets;
+use backend::MOCK_BACKEND;
+use blocklist::is_blocked;
+use capport::{captive_portal_state, validate_portal_url, CAPPORT_PATH};
+use certificates::{
+    eap_tls_certificates, store_certificate, validate_certificate, CertificateKind,
+    MAX_CERTIFICATE_SIZE,
+};
+use clients::Clients;
+use config::Config;
+use credentials::{check_credentials, CredentialProblem};
+use doctor::{run_checks, run_network_checks, Check};
 use errors::*;
+use events::SharedEventLog;
 use exit::{exit, ExitResult};
-use network::{NetworkCommand, NetworkCommandResponse};
+use fields::{collect_extra_fields, write_extra_fields};
+use firstboot::{self, is_eula_accepted};
+use hotspot_manager::{HotspotCommand, HotspotCommandResponse, HotspotController};
+use network::{NetworkCommand, NetworkCommandResponse, NetworkCredentials};
+#[cfg(feature = "network-manager")]
+use network_manager::NetworkManager;
+#[cfg(feature = "network-manager")]
+use nm::get_device_info;
+use openapi::openapi_document;
+use passpoint::PasspointProfile;
+use ping::ping;
+use profile::ProfileUpdate;
+use proxy::collect_proxy_settings;
+use redirect::{expand_redirect_url, landing_page};
+use rotation::qr_code_svg;
+use secret::Secret;
+use signing::constant_time_eq;
+use speedtest::{run_speed_test, DEFAULT_SPEED_TEST_URL};
+use ssdp::{device_description, SSDP_DESCRIPTION_PATH};
+use ssid::validate_ssid;
+use state::SharedConnectStatus;
+use wireguard::collect_wireguard_config;
+
+/// Answer to connection requests while the EULA is not accepted yet
+const EULA_PENDING: &str = "The EULA has to be accepted before connecting";
+
+/// Seconds the `/networks` handler waits for the network thread to respond
+const NETWORKS_TIMEOUT: u64 = 30;
+
+/// Milliseconds between checks of the event history for `/events/stream`
+const EVENT_STREAM_INTERVAL: u64 = 500;
+
+/// Seconds without events after which `/events/stream` sends a comment, so
+/// that proxies keep the connection open and gone clients are noticed
+const EVENT_STREAM_KEEP_ALIVE: u64 = 15;
+
+/// Each event stream occupies a server thread, so only this fraction of
+/// `--http-connections` may stream at once
+const EVENT_STREAM_SHARE: usize = 4;
+
+/// Prefix of the versioned JSON routes
+pub const API_PREFIX: &str = "/api/v1";
+
+/// API versions served, reported by `/api/version`
+const API_VERSIONS: &[&str] = &["v1"];
+
+/// Limits of the HTTP servers. Each connection occupies one of the server's
+/// threads for as long as it is kept alive, and phones probing for a captive
+/// portal open many, so the threads are capped and idle connections closed
+/// early to bound the memory used.
+#[derive(Clone, Copy, Debug)]
+pub struct HttpLimits {
+    /// Connections served at once, further ones wait in the listen backlog
+    pub connections: usize,
+    /// Seconds an idle connection is kept open, 0 closes it after a request
+    pub keep_alive: u64,
+    /// Seconds to read a request or write a response
+    pub timeout: u64,
+}
+
+impl HttpLimits {
+    fn server<H: Handler>(&self, handler: H) -> Iron<H> {
+        let mut server = Iron::new(handler);
+        server.threads = self.connections;
+        server.timeouts = Timeouts {
+            keep_alive: match self.keep_alive {
+                0 => None,
+                keep_alive => Some(Duration::from_secs(keep_alive)),
+            },
+            read: Some(Duration::from_secs(self.timeout)),
+            write: Some(Duration::from_secs(self.timeout)),
+        };
+        server
+    }
+}
 
 struct RequestSharedState {
     gateway: Ipv4Addr,
-    server_rx: Receiver<NetworkCommandResponse>,
+    config: Config,
     network_tx: Sender<NetworkCommand>,
     exit_tx: Sender<ExitResult>,
+    clients: Clients,
+    status: SharedConnectStatus,
+    events: SharedEventLog,
+    server: ServerHandle,
+    event_streams: Arc<AtomicUsize>,
 }
 
 impl typemap::Key for RequestSharedState {
     type Value = RequestSharedState;
 }
 
+/// Served by `/api/version`, so that clients can tell what a device
+/// supports before relying on it
+#[derive(Serialize)]
+struct ApiVersion {
+    version: &'static str,
+    api_versions: &'static [&'static str],
+    capabilities: Vec<&'static str>,
+}
+
+/// Answer to accepted credentials with `--redirect-url`, the portal UI
+/// follows the link
+#[derive(Serialize)]
+struct ConnectAccepted {
+    redirect_url: String,
+}
+
+/// Answer to `POST /validate`, valid when there are no problems
+#[derive(Serialize)]
+struct Validation {
+    valid: bool,
+    problems: Vec<CredentialProblem>,
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    checks: Vec<Check>,
+}
+
+struct ApiSharedState {
+    api_token: Option<String>,
+    controller: HotspotController,
+}
+
+impl typemap::Key for ApiSharedState {
+    type Value = ApiSharedState;
+}
+
+/// Lets the owner of an HTTP server stop it from serving new requests and
+/// wait for the in-flight ones before tearing down the network
+#[derive(Clone, Default)]
+pub struct ServerHandle {
+    requests: Arc<(Mutex<Requests>, Condvar)>,
+}
+
+#[derive(Default)]
+struct Requests {
+    draining: bool,
+    in_flight: usize,
+}
+
+impl ServerHandle {
+    pub fn new() -> Self {
+        ServerHandle::default()
+    }
+
+    /// Rejects new requests and waits up to `timeout` for in-flight ones to
+    /// finish, returning whether they all did
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let (ref requests, ref finished) = *self.requests;
+
+        let mut requests = requests.lock().unwrap();
+        requests.draining = true;
+
+        let (requests, wait) = finished
+            .wait_timeout_while(requests, timeout, |requests| requests.in_flight > 0)
+            .unwrap();
+
+        if wait.timed_out() {
+            warn!(
+                "{} HTTP requests still in flight on shutdown",
+                requests.in_flight
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped, or
+    /// returns `None` once draining
+    fn track(&self) -> Option<InFlight> {
+        let mut requests = self.requests.0.lock().unwrap();
+
+        if requests.draining {
+            return None;
+        }
+
+        requests.in_flight += 1;
+
+        Some(InFlight(self.clone()))
+    }
+
+    fn is_draining(&self) -> bool {
+        self.requests.0.lock().unwrap().draining
+    }
+}
+
+/// Finishes an in-flight request when dropped, also when its handler panics
+struct InFlight(ServerHandle);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let (ref requests, ref finished) = *self.0.requests;
+
+        // A panic elsewhere must not keep the request counted
+        let mut requests = requests.lock().unwrap_or_else(PoisonError::into_inner);
+        requests.in_flight -= 1;
+
+        if requests.in_flight == 0 {
+            finished.notify_all();
+        }
+    }
+}
+
+struct TrackedHandler {
+    handler: Box<dyn Handler>,
+    server: ServerHandle,
+}
+
+impl Handler for TrackedHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let _in_flight = match self.server.track() {
+            Some(in_flight) => in_flight,
+            None => {
+                return Ok(Response::with((
+                    status::ServiceUnavailable,
+                    "Shutting down",
+                )))
+            }
+        };
+
+        self.handler.handle(req)
+    }
+}
+
+impl AroundMiddleware for ServerHandle {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(TrackedHandler {
+            handler,
+            server: self,
+        })
+    }
+}
+
 #[derive(Debug)]
 struct StringError(String);
 
@@ -98,6 +345,19 @@ macro_rules! get_request_state {
     };
 }
 
+macro_rules! get_api_state {
+    ($req:ident) => {
+        get_request_ref!(
+            $req,
+            Write<ApiSharedState>,
+            "Getting reference to API shared state failed"
+        )
+        .as_ref()
+        .lock()
+        .unwrap()
+    };
+}
+
 fn exit_with_error<E>(state: &RequestSharedState, e: E, e_kind: ErrorKind) -> IronResult<Response>
 where
     E: ::std::error::Error + Send + 'static,
@@ -111,6 +371,37 @@ where
     ))
 }
 
+/// Logs every request with the client address and user agent, to see whether
+/// a device attempted captive portal detection at all
+struct TrafficLogger;
+
+impl BeforeMiddleware for TrafficLogger {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let host = req
+            .headers
+            .get::<headers::Host>()
+            .map(|host| host.hostname.clone())
+            .unwrap_or_default();
+
+        let user_agent = req
+            .headers
+            .get::<headers::UserAgent>()
+            .map(|user_agent| user_agent.to_string())
+            .unwrap_or_default();
+
+        info!(
+            "HTTP {} {}/{} from {} ({})",
+            req.method,
+            host,
+            req.url.path().join("/"),
+            req.remote_addr.ip(),
+            user_agent
+        );
+
+        Ok(())
+    }
+}
+
 struct RedirectMiddleware;
 
 impl AfterMiddleware for RedirectMiddleware {
@@ -131,48 +422,134 @@ impl AfterMiddleware for RedirectMiddleware {
     }
 }
 
+/// Registers JSON routes under `API_PREFIX`, keeping the unversioned paths
+/// as aliases for clients predating it
+struct VersionedRouter<'a>(&'a mut Router);
+
+impl<'a> VersionedRouter<'a> {
+    fn get<H: Handler + Copy>(&mut self, path: &str, handler: H, route_id: &str) {
+        self.route(Method::Get, path, handler, route_id);
+    }
+
+    fn post<H: Handler + Copy>(&mut self, path: &str, handler: H, route_id: &str) {
+        self.route(Method::Post, path, handler, route_id);
+    }
+
+    fn patch<H: Handler + Copy>(&mut self, path: &str, handler: H, route_id: &str) {
+        self.route(Method::Patch, path, handler, route_id);
+    }
+
+    fn route<H: Handler + Copy>(&mut self, method: Method, path: &str, handler: H, route_id: &str) {
+        self.0.route(
+            method.clone(),
+            format!("{}{}", API_PREFIX, path),
+            handler,
+            format!("{}_v1", route_id),
+        );
+        self.0.route(method, path, handler, route_id);
+    }
+}
+
 pub fn start_server(
-    gateway: Ipv4Addr,
-    listening_port: u16,
-    server_rx: Receiver<NetworkCommandResponse>,
+    config: Config,
     network_tx: Sender<NetworkCommand>,
     exit_tx: Sender<ExitResult>,
-    ui_directory: &PathBuf,
+    clients: Clients,
+    status: SharedConnectStatus,
+    events: SharedEventLog,
+    server: ServerHandle,
 ) {
+    let gateway = config.gateway;
+    let listening_port = config.listening_port;
+    let debug_portal_traffic = config.debug_portal_traffic;
+    let access_log = config.access_log.clone();
+    let http_limits = config.http_limits;
+    let ui_directory: PathBuf = config.ui_directory.clone();
+    let first_boot = config.first_boot;
+    let ssdp_description = if config.ssdp {
+        Some(device_description(&config))
+    } else {
+        None
+    };
     let exit_tx_clone = exit_tx.clone();
     let gateway_clone = gateway;
     let request_state = RequestSharedState {
         gateway,
-        server_rx,
+        config,
         network_tx,
         exit_tx,
+        clients,
+        status,
+        events,
+        server: server.clone(),
+        event_streams: Arc::new(AtomicUsize::new(0)),
     };
 
     let mut router = Router::new();
-    router.get("/", Static::new(ui_directory), "index");
-    router.get("/networks", networks, "networks");
+    router.get("/", static_assets(&ui_directory), "index");
+    router.get("/api/version", portal_version, "version");
+    router.get("/api/openapi.json", openapi, "openapi");
+    router.get(CAPPORT_PATH, captive_portal, "captive_portal");
+    route_ssdp_description(&mut router, ssdp_description);
+
+    {
+        let mut api = VersionedRouter(&mut router);
+        api.get("/networks", networks, "networks");
+        api.get("/clients", list_clients, "clients");
+        #[cfg(feature = "network-manager")]
+        api.get("/device-info", device_info, "device_info");
+        api.get("/health", health, "health");
+        api.get("/connect-status", connect_status, "connect_status");
+        api.get("/extra-fields", extra_fields, "extra_fields");
+        api.get("/events", events, "events");
+        api.get("/events/stream", event_stream, "event_stream");
+        api.get("/diagnostics", diagnostics, "diagnostics");
+        api.get("/diagnostics/ping", diagnostics_ping, "diagnostics_ping");
+
+        api.post("/connect", connect, "connect");
+        api.post("/connect-multiple", connect_multiple, "connect_multiple");
+        api.post("/validate", validate, "validate");
+        api.post("/certificates", upload_certificate, "upload_certificate");
 
-    router.post("/connect", connect, "connect");
+        if first_boot {
+            api.get("/first-boot", first_boot_status, "first_boot_status");
+            api.post("/timezone", timezone, "timezone");
+            api.post("/eula", eula, "eula");
+        }
+    }
 
     let mut assets = Mount::new();
     assets.mount("/", router);
-    assets.mount("/static", Static::new(ui_directory.join("static")));
-    assets.mount("/css", Static::new(ui_directory.join("css")));
-    assets.mount("/img", Static::new(ui_directory.join("img")));
-    assets.mount("/js", Static::new(ui_directory.join("js")));
+    assets.mount("/static", static_assets(&ui_directory.join("static")));
+    assets.mount("/css", static_assets(&ui_directory.join("css")));
+    assets.mount("/img", static_assets(&ui_directory.join("img")));
+    assets.mount("/js", static_assets(&ui_directory.join("js")));
 
     let cors_middleware = CorsMiddleware::with_allow_any();
 
     let mut chain = Chain::new(assets);
+    if debug_portal_traffic {
+        chain.link_before(TrafficLogger);
+    }
     chain.link(Write::<RequestSharedState>::both(request_state));
     chain.link_after(RedirectMiddleware);
     chain.link_around(cors_middleware);
+    chain.link_around(server);
+
+    if let Some(ref settings) = access_log {
+        match AccessLog::open(settings) {
+            Ok(access_log) => {
+                chain.link_around(access_log);
+            }
+            Err(e) => return exit(&exit_tx_clone, e),
+        }
+    }
 
     let address = format!("{}:{}", gateway_clone, listening_port);
 
     info!("Starting HTTP server on {}", &address);
 
-    if let Err(e) = Iron::new(chain).http(&address) {
+    if let Err(e) = http_limits.server(chain).http(&address) {
         exit(
             &exit_tx_clone,
             ErrorKind::StartHTTPServer(address, e.to_string()).into(),
@@ -183,49 +560,1262 @@ pub fn start_server(
 fn networks(req: &mut Request) -> IronResult<Response> {
     info!("User connected to the captive portal");
 
-    let request_state = get_request_state!(req);
+    // Only the sender is taken from the shared state, which every other
+    // handler needs as well, for the wait on the network thread
+    let network_tx = get_request_state!(req).network_tx.clone();
 
-    if let Err(e) = request_state.network_tx.send(NetworkCommand::Activate) {
-        return exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandActivate);
+    let (reply_tx, reply_rx) = channel();
+
+    if let Err(e) = network_tx.send(NetworkCommand::Activate(reply_tx)) {
+        return exit_with_error(
+            &get_request_state!(req),
+            e,
+            ErrorKind::SendNetworkCommandActivate,
+        );
     }
 
-    let networks = match request_state.server_rx.recv() {
-        Ok(result) => match result {
-            NetworkCommandResponse::Networks(networks) => networks,
-        },
-        Err(e) => return exit_with_error(&request_state, e, ErrorKind::RecvAccessPointSSIDs),
+    // The network thread may be busy scanning or connecting, or shutting
+    // down without answering
+    let networks = match reply_rx.recv_timeout(Duration::from_secs(NETWORKS_TIMEOUT)) {
+        Ok(NetworkCommandResponse::Networks(networks)) => networks,
+        Err(_) => {
+            warn!("Timed out waiting for the list of networks");
+            return Err(IronError::new(
+                StringError("Timed out waiting for the list of networks".into()),
+                status::ServiceUnavailable,
+            ));
+        }
     };
 
     let access_points_json = match serde_json::to_string(&networks) {
         Ok(json) => json,
-        Err(e) => return exit_with_error(&request_state, e, ErrorKind::SerializeAccessPointSSIDs),
+        Err(e) => {
+            return exit_with_error(
+                &get_request_state!(req),
+                e,
+                ErrorKind::SerializeAccessPointSSIDs,
+            )
+        }
     };
 
     Ok(Response::with((status::Ok, access_points_json)))
 }
 
-fn connect(req: &mut Request) -> IronResult<Response> {
-    let (ssid, identity, passphrase) = {
+fn list_clients(req: &mut Request) -> IronResult<Response> {
+    let request_state = get_request_state!(req);
+
+    let clients = request_state.clients.lock().unwrap().clone();
+
+    match serde_json::to_string(&clients) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn connect_status(req: &mut Request) -> IronResult<Response> {
+    let request_state = get_request_state!(req);
+
+    let current = request_state.status.lock().unwrap().clone();
+
+    match serde_json::to_string(&current) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn events(req: &mut Request) -> IronResult<Response> {
+    let since = {
         let params = get_request_ref!(req, Params, "Getting request params failed");
-        let ssid = get_param!(params, "ssid", String);
-        let identity = get_param!(params, "identity", String);
-        let passphrase = get_param!(params, "passphrase", String);
-        (ssid, identity, passphrase)
+
+        params.get("since").and_then(u64::from_value)
     };
 
-    info!("Incoming `connect` to access point `{}` request", ssid);
+    let request_state = get_request_state!(req);
+
+    let events = request_state.events.lock().unwrap().since(since);
+
+    match serde_json::to_string(&events) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Streams the events as Server-Sent Events while they are recorded, from
+/// the one after `since` or, when reconnecting, after `Last-Event-ID`
+fn event_stream(req: &mut Request) -> IronResult<Response> {
+    let since = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        params.get("since").and_then(u64::from_value)
+    };
+
+    let last_event_id = req
+        .headers
+        .get_raw("Last-Event-ID")
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8_lossy(value).trim().parse::<u64>().ok());
 
     let request_state = get_request_state!(req);
 
-    let command = NetworkCommand::Connect {
-        ssid,
-        identity,
-        passphrase,
+    let max_streams = request_state.config.http_limits.connections / EVENT_STREAM_SHARE;
+
+    let slot = match EventStreamSlot::take(&request_state.event_streams, max_streams) {
+        Some(slot) => slot,
+        None => {
+            return Ok(Response::with((
+                status::ServiceUnavailable,
+                "Too many event streams",
+            )))
+        }
     };
 
-    if let Err(e) = request_state.network_tx.send(command) {
-        exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandConnect)
+    let stream: Box<dyn WriteBody> = Box::new(EventStream {
+        events: request_state.events.clone(),
+        since: last_event_id.or(since),
+        server: request_state.server.clone(),
+        _slot: slot,
+    });
+
+    let content_type: Mime = "text/event-stream".parse().unwrap();
+
+    Ok(Response::with((
+        status::Ok,
+        Header(headers::ContentType(content_type)),
+        Header(headers::CacheControl(vec![headers::CacheDirective::NoCache])),
+        stream,
+    )))
+}
+
+/// Body of `/events/stream`, written until the client goes away or the
+/// server drains
+struct EventStream {
+    events: SharedEventLog,
+    since: Option<u64>,
+    server: ServerHandle,
+    _slot: EventStreamSlot,
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut dyn io::Write) -> io::Result<()> {
+        // Sends the headers right away instead of with the first event
+        res.write_all(b": connected\n\n")?;
+        res.flush()?;
+
+        let mut last_write = Instant::now();
+
+        while !self.server.is_draining() {
+            let events = self.events.lock().unwrap().since(self.since);
+
+            for event in &events {
+                let json = serde_json::to_string(event).map_err(io::Error::other)?;
+
+                write!(res, "id: {}\ndata: {}\n\n", event.id, json)?;
+
+                self.since = Some(event.id);
+            }
+
+            if !events.is_empty() {
+                res.flush()?;
+                last_write = Instant::now();
+            } else if last_write.elapsed() >= Duration::from_secs(EVENT_STREAM_KEEP_ALIVE) {
+                res.write_all(b": keep-alive\n\n")?;
+                res.flush()?;
+                last_write = Instant::now();
+            }
+
+            thread::sleep(Duration::from_millis(EVENT_STREAM_INTERVAL));
+        }
+
+        Ok(())
+    }
+}
+
+/// Place among the event streams served at once, freed when dropped
+struct EventStreamSlot(Arc<AtomicUsize>);
+
+impl EventStreamSlot {
+    fn take(streams: &Arc<AtomicUsize>, max_streams: usize) -> Option<Self> {
+        if streams.fetch_add(1, Ordering::SeqCst) >= max_streams {
+            streams.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(EventStreamSlot(streams.clone()))
+    }
+}
+
+impl Drop for EventStreamSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "network-manager")]
+fn device_info(_req: &mut Request) -> IronResult<Response> {
+    let manager = NetworkManager::new();
+
+    let device_info = match get_device_info(&manager) {
+        Ok(device_info) => device_info,
+        Err(e) => {
+            error!("Getting device info failed: {}", e);
+            return Err(IronError::new(
+                StringError(e.to_string()),
+                status::InternalServerError,
+            ));
+        }
+    };
+
+    match serde_json::to_string(&device_info) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn diagnostics(req: &mut Request) -> IronResult<Response> {
+    let config = get_request_state!(req).config.clone();
+
+    let mut checks = run_checks(&config, false);
+    checks.extend(run_network_checks());
+
+    match serde_json::to_string(&checks) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn diagnostics_ping(req: &mut Request) -> IronResult<Response> {
+    let host = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        params.get("host").and_then(String::from_value)
+    };
+
+    let host = match host {
+        Some(ref host) if !host.trim().is_empty() => host.trim().to_string(),
+        _ => {
+            return Ok(Response::with((
+                status::BadRequest,
+                "Missing 'host' parameter",
+            )))
+        }
+    };
+
+    info!("Incoming `ping` request to {}", host);
+
+    let report = match ping(&host) {
+        Ok(report) => report,
+        Err(e) => {
+            warn!("{}", e);
+            return Ok(Response::with((status::BadRequest, e.to_string())));
+        }
+    };
+
+    match serde_json::to_string(&report) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn portal_version(req: &mut Request) -> IronResult<Response> {
+    let config = get_request_state!(req).config.clone();
+
+    let mut capabilities = vec![
+        "networks",
+        "connect",
+        "connect-multiple",
+        "clients",
+        "health",
+        "connect-status",
+        "events",
+        "diagnostics",
+        "captive-portal-api",
+        "wireguard",
+        "validate",
+    ];
+
+    if cfg!(feature = "network-manager") {
+        capabilities.push("device-info");
+    }
+
+    if !config.extra_fields.is_empty() {
+        capabilities.push("extra-fields");
+    }
+
+    if config.first_boot {
+        capabilities.push("first-boot");
+    }
+
+    if config.redirect_url.is_some() {
+        capabilities.push("redirect-url");
+    }
+
+    // Nothing is connected for real, for UI development on other systems
+    if config.backend == MOCK_BACKEND {
+        capabilities.push("simulation");
+    }
+
+    send_api_version(capabilities)
+}
+
+/// Management routes are only reported once a token is configured, as
+/// they are refused otherwise
+fn api_version(req: &mut Request) -> IronResult<Response> {
+    let capabilities = if get_api_state!(req).api_token.is_some() {
+        vec![
+            "hotspot",
+            "passphrase-rotation",
+            "guest-hotspot",
+            "connection",
+            "events",
+            "speed-test",
+            "saved-networks",
+            "passpoint",
+        ]
+    } else {
+        Vec::new()
+    };
+
+    send_api_version(capabilities)
+}
+
+fn send_api_version(capabilities: Vec<&'static str>) -> IronResult<Response> {
+    let version = ApiVersion {
+        version: env!("CARGO_PKG_VERSION"),
+        api_versions: API_VERSIONS,
+        capabilities,
+    };
+
+    match serde_json::to_string(&version) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Served without authorization by both servers, so that clients can be
+/// generated against the device
+fn openapi(_req: &mut Request) -> IronResult<Response> {
+    match serde_json::to_string(&openapi_document()) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Captive Portal API (RFC 8908) state of the client
+fn captive_portal(req: &mut Request) -> IronResult<Response> {
+    let config = get_request_state!(req).config.clone();
+
+    let content_type: Mime = "application/captive+json".parse().unwrap();
+
+    match serde_json::to_string(&captive_portal_state(&config)) {
+        Ok(json) => Ok(Response::with((
+            status::Ok,
+            Header(headers::ContentType(content_type)),
+            Header(headers::CacheControl(vec![headers::CacheDirective::NoStore])),
+            json,
+        ))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Serves the UPnP device description advertised over SSDP with `--ssdp`
+fn route_ssdp_description(router: &mut Router, description: Option<String>) {
+    if let Some(description) = description {
+        router.get(
+            SSDP_DESCRIPTION_PATH,
+            move |_: &mut Request| {
+                let content_type: Mime = "text/xml; charset=utf-8".parse().unwrap();
+
+                Ok(Response::with((
+                    status::Ok,
+                    Header(headers::ContentType(content_type)),
+                    description.clone(),
+                )))
+            },
+            "ssdp_description",
+        );
+    }
+}
+
+fn health(req: &mut Request) -> IronResult<Response> {
+    let config = get_request_state!(req).config.clone();
+
+    // The portal itself holds the DNS, DHCP and HTTP ports while running
+    let checks = run_checks(&config, false);
+
+    let (state, code) = if checks.iter().all(|check| check.passed) {
+        ("ok", status::Ok)
     } else {
-        Ok(Response::with(status::Ok))
+        ("degraded", status::ServiceUnavailable)
+    };
+
+    let health = Health {
+        status: state,
+        checks,
+    };
+
+    match serde_json::to_string(&health) {
+        Ok(json) => Ok(Response::with((code, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
     }
-}
\ No newline at end of file
+}
+
+fn connect(req: &mut Request) -> IronResult<Response> {
+    let (ssid, identity, passphrase, proxy, wireguard) = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        let ssid = get_param!(params, "ssid", String);
+        let identity = get_param!(params, "identity", String);
+        let passphrase = Secret::from(get_param!(params, "passphrase", String));
+        let proxy = collect_proxy_settings(|name| params.get(name).and_then(param_string));
+        let wireguard =
+            collect_wireguard_config(params.get("wireguard").and_then(String::from_value));
+        (ssid, identity, passphrase, proxy, wireguard)
+    };
+
+    info!("Incoming `connect` to access point `{}` request", ssid);
+
+    if let Err(e) = validate_ssid(&ssid) {
+        let err = e.to_string();
+        error!("{}", err);
+        return Err(IronError::new(StringError(err), status::BadRequest));
+    }
+
+    let proxy = match proxy {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            error!("{}", err);
+            return Err(IronError::new(StringError(err), status::BadRequest));
+        }
+    };
+
+    let wireguard = match wireguard {
+        Ok(wireguard) => wireguard,
+        Err(err) => {
+            error!("{}", err);
+            return Err(IronError::new(StringError(err), status::BadRequest));
+        }
+    };
+
+    if is_eula_pending(&get_request_state!(req).config) {
+        return Ok(Response::with((status::Forbidden, EULA_PENDING)));
+    }
+
+    if is_blocked(&get_request_state!(req).config.blocked_ssids, &ssid) {
+        let err = ErrorKind::BlockedSsid(ssid).to_string();
+        warn!("{}", err);
+        return Err(IronError::new(StringError(err), status::Forbidden));
+    }
+
+    if let Some(response) = store_extra_fields(req)? {
+        return Ok(response);
+    }
+
+    let accepted = connect_accepted(req, &ssid)?;
+
+    let request_state = get_request_state!(req);
+
+    let command = NetworkCommand::Connect {
+        ssid,
+        identity,
+        passphrase,
+        proxy,
+        wireguard,
+    };
+
+    if let Err(e) = request_state.network_tx.send(command) {
+        exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandConnect)
+    } else {
+        Ok(accepted)
+    }
+}
+
+fn connect_multiple(req: &mut Request) -> IronResult<Response> {
+    let networks = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        match params.get("networks") {
+            Some(&Value::Array(ref values)) => values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| get_network_credentials(index, value))
+                .collect::<::std::result::Result<Vec<_>, String>>(),
+            _ => Ok(Vec::new()),
+        }
+    };
+
+    let networks = match networks {
+        Ok(networks) => networks,
+        Err(err) => {
+            error!("{}", err);
+            return Err(IronError::new(StringError(err), status::BadRequest));
+        }
+    };
+
+    if networks.is_empty() {
+        let err = "'networks' must be a non-empty list of networks".to_string();
+        error!("{}", err);
+        return Err(IronError::new(StringError(err), status::BadRequest));
+    }
+
+    info!(
+        "Incoming `connect` to access points {:?} request",
+        networks.iter().map(|network| &network.ssid).collect::<Vec<_>>()
+    );
+
+    if is_eula_pending(&get_request_state!(req).config) {
+        return Ok(Response::with((status::Forbidden, EULA_PENDING)));
+    }
+
+    let blocked_ssids = get_request_state!(req).config.blocked_ssids.clone();
+
+    if let Some(blocked) = networks
+        .iter()
+        .find(|network| is_blocked(&blocked_ssids, &network.ssid))
+    {
+        let err = ErrorKind::BlockedSsid(blocked.ssid.clone()).to_string();
+        warn!("{}", err);
+        return Err(IronError::new(StringError(err), status::Forbidden));
+    }
+
+    if let Some(response) = store_extra_fields(req)? {
+        return Ok(response);
+    }
+
+    // The first network is the one tried first
+    let accepted = connect_accepted(req, &networks[0].ssid)?;
+
+    let request_state = get_request_state!(req);
+
+    let command = NetworkCommand::ConnectMultiple { networks };
+
+    if let Err(e) = request_state.network_tx.send(command) {
+        exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandConnect)
+    } else {
+        Ok(accepted)
+    }
+}
+
+/// Checks credentials for the network without connecting, so that the
+/// portal can point out typos right away
+fn validate(req: &mut Request) -> IronResult<Response> {
+    let (ssid, security, identity, passphrase, proxy, wireguard) = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        let field = |name: &str| params.get(name).and_then(String::from_value);
+        let ssid = get_param!(params, "ssid", String);
+        let security = get_param!(params, "security", String);
+        let proxy = collect_proxy_settings(|name| params.get(name).and_then(param_string));
+        let wireguard = collect_wireguard_config(field("wireguard"));
+        (
+            ssid,
+            security,
+            field("identity").unwrap_or_default(),
+            Secret::from(field("passphrase").unwrap_or_default()),
+            proxy,
+            wireguard,
+        )
+    };
+
+    debug!("Incoming `validate` of credentials for '{}'", ssid);
+
+    let directory = get_request_state!(req).config.certificate_directory.clone();
+    let has_certificates = eap_tls_certificates(&directory, &ssid).is_some();
+
+    let mut problems = Vec::new();
+
+    if let Err(e) = validate_ssid(&ssid) {
+        problems.push(CredentialProblem::new("ssid", e.to_string()));
+    }
+
+    problems.extend(check_credentials(
+        &security,
+        &identity,
+        passphrase.expose(),
+        has_certificates,
+    ));
+
+    if let Err(err) = proxy {
+        problems.push(CredentialProblem::new("proxy", err));
+    }
+
+    if let Err(err) = wireguard {
+        problems.push(CredentialProblem::new("wireguard", err));
+    }
+
+    let validation = Validation {
+        valid: problems.is_empty(),
+        problems,
+    };
+
+    match serde_json::to_string(&validation) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Response to accepted credentials. With `--redirect-url` browsers posting
+/// a form get a landing page following the link, other clients the link as
+/// JSON.
+fn connect_accepted(req: &mut Request, ssid: &str) -> IronResult<Response> {
+    let config = get_request_state!(req).config.clone();
+
+    let redirect_url = match config.redirect_url {
+        Some(ref template) => expand_redirect_url(template, ssid, &config.device_id),
+        None => return Ok(Response::with(status::Ok)),
+    };
+
+    if accepts_html(req) {
+        let content_type: Mime = "text/html; charset=utf-8".parse().unwrap();
+
+        return Ok(Response::with((
+            status::Ok,
+            Header(headers::ContentType(content_type)),
+            landing_page(&redirect_url),
+        )));
+    }
+
+    match serde_json::to_string(&ConnectAccepted { redirect_url }) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn accepts_html(req: &Request) -> bool {
+    req.headers.get_raw("Accept").map_or(false, |values| {
+        values
+            .iter()
+            .any(|value| String::from_utf8_lossy(value).contains("text/html"))
+    })
+}
+
+/// Credentials of the network at `index` of the `networks` list. Errors name
+/// the offending entry, so that no network is dropped silently.
+fn get_network_credentials(
+    index: usize,
+    value: &Value,
+) -> ::std::result::Result<NetworkCredentials, String> {
+    let map = match *value {
+        Value::Map(ref map) => map,
+        _ => return Err(format!("Network {} is not an object", index)),
+    };
+
+    let field = |name: &str| map.get(name).and_then(String::from_value);
+
+    let ssid = match field("ssid") {
+        Some(ssid) => ssid,
+        None => return Err(format!("Network {} has no 'ssid'", index)),
+    };
+
+    validate_ssid(&ssid).map_err(|e| format!("Network {}: {}", index, e))?;
+
+    let invalid = |e: String| format!("Network {} ('{}'): {}", index, ssid, e);
+
+    let proxy =
+        collect_proxy_settings(|name| map.get(name).and_then(param_string)).map_err(&invalid)?;
+
+    let wireguard = collect_wireguard_config(field("wireguard")).map_err(&invalid)?;
+
+    Ok(NetworkCredentials {
+        ssid,
+        identity: field("identity").unwrap_or_default(),
+        passphrase: field("passphrase").map(Secret::from).unwrap_or_default(),
+        proxy,
+        wireguard,
+    })
+}
+
+/// String parameter, also accepting a number like a JSON proxy port
+fn param_string(value: &Value) -> Option<String> {
+    String::from_value(value).or_else(|| u64::from_value(value).map(|number| number.to_string()))
+}
+
+/// Validates the extra form fields sent along with the credentials and
+/// stores them for the hook. Returns the response rejecting the request if
+/// they are invalid.
+fn store_extra_fields(req: &mut Request) -> IronResult<Option<Response>> {
+    let (fields, path) = {
+        let request_state = get_request_state!(req);
+        (
+            request_state.config.extra_fields.clone(),
+            request_state.config.extra_fields_file.clone(),
+        )
+    };
+
+    if fields.is_empty() {
+        return Ok(None);
+    }
+
+    let values = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        collect_extra_fields(&fields, |name| {
+            params.get(name).and_then(String::from_value)
+        })
+    };
+
+    let values = match values {
+        Ok(values) => values,
+        Err(reason) => {
+            warn!("Rejecting extra form fields: {}", reason);
+            return Ok(Some(Response::with((status::BadRequest, reason))));
+        }
+    };
+
+    if let Err(e) = write_extra_fields(&path, &values) {
+        error!("{}", e);
+        return Err(IronError::new(
+            StringError(e.to_string()),
+            status::InternalServerError,
+        ));
+    }
+
+    Ok(None)
+}
+
+/// Stores a CA or client certificate, or the private key of the latter, for
+/// an EAP-TLS network. Accepts a file upload or the PEM as a string.
+fn upload_certificate(req: &mut Request) -> IronResult<Response> {
+    let (ssid, kind, contents) = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        let ssid = get_param!(params, "ssid", String);
+        let kind = get_param!(params, "kind", String);
+        let contents = match params.get("certificate") {
+            Some(&Value::File(ref file)) if file.size > MAX_CERTIFICATE_SIZE as u64 => Err(
+                format!("Certificates are limited to {} bytes", MAX_CERTIFICATE_SIZE),
+            ),
+            Some(&Value::File(ref file)) => {
+                fs::read(&file.path).map_err(|e| format!("Reading the upload failed: {}", e))
+            }
+            Some(&Value::String(ref pem)) => Ok(pem.clone().into_bytes()),
+            _ => Err("'certificate' must be a file or a PEM string".to_string()),
+        };
+        (ssid, kind, contents)
+    };
+
+    info!("Incoming `certificates` upload of {} for '{}'", kind, ssid);
+
+    let kind = match CertificateKind::parse(&kind) {
+        Some(kind) => kind,
+        None => {
+            let err = format!("'{}' is not one of ca, client or key", kind);
+            error!("{}", err);
+            return Err(IronError::new(StringError(err), status::BadRequest));
+        }
+    };
+
+    let validated = contents.and_then(|contents| {
+        validate_certificate(kind, &contents)?;
+        Ok(contents)
+    });
+
+    let contents = match validated {
+        Ok(contents) => contents,
+        Err(err) => {
+            error!("{}", err);
+            return Err(IronError::new(StringError(err), status::BadRequest));
+        }
+    };
+
+    if let Err(e) = validate_ssid(&ssid) {
+        let err = e.to_string();
+        error!("{}", err);
+        return Err(IronError::new(StringError(err), status::BadRequest));
+    }
+
+    let directory = get_request_state!(req).config.certificate_directory.clone();
+
+    match store_certificate(&directory, &ssid, kind, &contents) {
+        Ok(_) => Ok(Response::with(status::Ok)),
+        Err(e) => {
+            error!("{}", e);
+            Err(IronError::new(
+                StringError(e.to_string()),
+                status::InternalServerError,
+            ))
+        }
+    }
+}
+
+fn extra_fields(req: &mut Request) -> IronResult<Response> {
+    let fields = get_request_state!(req).config.extra_fields.clone();
+
+    match serde_json::to_string(&fields) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+/// Connecting is refused in first boot mode until the configured EULA has
+/// been accepted
+pub fn is_eula_pending(config: &Config) -> bool {
+    config.first_boot && config.eula.is_some() && !is_eula_accepted()
+}
+
+fn first_boot_status(req: &mut Request) -> IronResult<Response> {
+    let eula = get_request_state!(req).config.eula.clone();
+
+    match serde_json::to_string(&firstboot::first_boot_status(&eula)) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn timezone(req: &mut Request) -> IronResult<Response> {
+    let timezone = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        get_param!(params, "timezone", String)
+    };
+
+    info!("Incoming `timezone` request: {}", timezone);
+
+    match firstboot::set_timezone(&timezone) {
+        Ok(()) => Ok(Response::with(status::Ok)),
+        Err(e) => {
+            warn!("{}", e);
+            Ok(Response::with((status::BadRequest, e.to_string())))
+        }
+    }
+}
+
+fn eula(_req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `eula` acceptance request");
+
+    match firstboot::accept_eula() {
+        Ok(()) => Ok(Response::with(status::Ok)),
+        Err(e) => {
+            error!("{}", e);
+            Err(IronError::new(
+                StringError(e.to_string()),
+                status::InternalServerError,
+            ))
+        }
+    }
+}
+
+pub fn start_api_server(
+    address: &str,
+    api_token: Option<String>,
+    controller: HotspotController,
+    ssdp_description: Option<String>,
+    access_log: Option<AccessLogSettings>,
+    http_limits: HttpLimits,
+    exit_tx: Sender<ExitResult>,
+    server: ServerHandle,
+) {
+    let api_state = ApiSharedState {
+        api_token,
+        controller,
+    };
+
+    let mut router = Router::new();
+    router.get("/api/version", api_version, "version");
+    router.get("/api/openapi.json", openapi, "openapi");
+    route_ssdp_description(&mut router, ssdp_description);
+
+    {
+        let mut api = VersionedRouter(&mut router);
+        api.get("/hotspot", hotspot_status, "hotspot_status");
+        api.post("/hotspot/start", access_point_start, "access_point_start");
+        api.post("/hotspot/stop", access_point_stop, "access_point_stop");
+        api.post("/hotspot/rotate", hotspot_rotate, "hotspot_rotate");
+        api.get("/hotspot/qr-code", hotspot_qr_code, "hotspot_qr_code");
+        api.get("/guest", guest_status, "guest_status");
+        api.post("/guest/start", guest_start, "guest_start");
+        api.post("/guest/stop", guest_stop, "guest_stop");
+        api.get("/connection", connection, "connection");
+        api.get("/events", hotspot_events, "hotspot_events");
+        api.post("/speed-test", speed_test, "speed_test");
+        api.get("/saved-networks/:ssid", saved_network, "saved_network");
+        api.patch("/saved-networks/:ssid", update_network, "update_network");
+        api.post("/passpoint", install_passpoint, "install_passpoint");
+    }
+
+    let mut chain = Chain::new(router);
+    chain.link(Write::<ApiSharedState>::both(api_state));
+    chain.link_around(CorsMiddleware::with_allow_any());
+    chain.link_around(server);
+
+    if let Some(ref settings) = access_log {
+        match AccessLog::open(settings) {
+            Ok(access_log) => {
+                chain.link_around(access_log);
+            }
+            Err(e) => return exit(&exit_tx, e),
+        }
+    }
+
+    info!("Starting management API server on {}", address);
+
+    if let Err(e) = http_limits.server(chain).http(address) {
+        exit(
+            &exit_tx,
+            ErrorKind::StartHTTPServer(address.to_string(), e.to_string()).into(),
+        );
+    }
+}
+
+/// Management routes require `Authorization: Bearer <token>` matching
+/// `--api-token`; without a configured token they stay disabled
+pub fn is_authorized(bearer: &Option<String>, api_token: &Option<String>) -> bool {
+    match (bearer, api_token) {
+        (&Some(ref bearer), &Some(ref api_token)) => {
+            constant_time_eq(bearer.as_bytes(), api_token.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+fn get_bearer_token(req: &Request) -> Option<String> {
+    req.headers
+        .get::<headers::Authorization<headers::Bearer>>()
+        .map(|authorization| authorization.token.clone())
+}
+
+fn authorize(req: &mut Request) -> IronResult<bool> {
+    let bearer = get_bearer_token(req);
+    let url = req.url.to_string();
+
+    let api_state = get_api_state!(req);
+
+    if !is_authorized(&bearer, &api_state.api_token) {
+        warn!("Unauthorized management API request to {}", url);
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+fn send_hotspot_command(req: &mut Request, command: HotspotCommand) -> IronResult<Response> {
+    if !authorize(req)? {
+        return Ok(Response::with(status::Unauthorized));
+    }
+
+    let controller = get_api_state!(req).controller.clone();
+
+    let json = match controller.send(command) {
+        Ok(HotspotCommandResponse::Status(hotspot_status)) => {
+            serde_json::to_string(&hotspot_status)
+        }
+        Ok(HotspotCommandResponse::SavedNetwork(Some(details))) => serde_json::to_string(&details),
+        Ok(HotspotCommandResponse::SavedNetwork(None)) => {
+            return Ok(Response::with(status::NotFound));
+        }
+        Ok(HotspotCommandResponse::ConnectedNetwork(connected)) => {
+            serde_json::to_string(&connected)
+        }
+        Ok(HotspotCommandResponse::Events(events)) => serde_json::to_string(&events),
+        Ok(HotspotCommandResponse::Networks(networks)) => serde_json::to_string(&networks),
+        Ok(HotspotCommandResponse::Forgotten(forgotten)) => serde_json::to_string(&forgotten),
+        Ok(HotspotCommandResponse::QrCode(payload)) => serde_json::to_string(&payload),
+        Ok(HotspotCommandResponse::Failed(reason)) => {
+            error!("Hotspot command failed: {}", reason);
+            return Err(IronError::new(
+                StringError(reason),
+                status::InternalServerError,
+            ));
+        }
+        Err(e) => {
+            return Err(IronError::new(
+                StringError(e.to_string()),
+                status::InternalServerError,
+            ))
+        }
+    };
+
+    match json {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn hotspot_status(req: &mut Request) -> IronResult<Response> {
+    send_hotspot_command(req, HotspotCommand::Status)
+}
+
+/// Brings up the access point with DHCP and the client monitor only. The
+/// captive portal is not served, as its HTTP server runs with the portal
+/// mode of the main process and cannot be stopped again.
+fn access_point_start(req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `access point start` request");
+
+    send_hotspot_command(req, HotspotCommand::Start)
+}
+
+fn access_point_stop(req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `access point stop` request");
+
+    send_hotspot_command(req, HotspotCommand::Stop)
+}
+
+fn hotspot_rotate(req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `hotspot rotate` request");
+
+    send_hotspot_command(req, HotspotCommand::RotatePassphrase)
+}
+
+/// QR code joining the hotspot as SVG, or its payload with `format=text`
+/// for clients rendering the code themselves
+fn hotspot_qr_code(req: &mut Request) -> IronResult<Response> {
+    if !authorize(req)? {
+        return Ok(Response::with(status::Unauthorized));
+    }
+
+    let text = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        params.get("format").and_then(String::from_value) == Some("text".to_string())
+    };
+
+    let controller = get_api_state!(req).controller.clone();
+
+    let payload = match controller.send(HotspotCommand::QrCode) {
+        Ok(HotspotCommandResponse::QrCode(payload)) => payload,
+        Ok(_) => {
+            return Err(IronError::new(
+                StringError("Unexpected hotspot command response".into()),
+                status::InternalServerError,
+            ))
+        }
+        Err(e) => {
+            return Err(IronError::new(
+                StringError(e.to_string()),
+                status::InternalServerError,
+            ))
+        }
+    };
+
+    let (content_type, body) = if text {
+        ("text/plain; charset=utf-8", payload)
+    } else {
+        match qr_code_svg(&payload) {
+            Ok(svg) => ("image/svg+xml", svg),
+            Err(e) => {
+                return Err(IronError::new(
+                    StringError(e.to_string()),
+                    status::InternalServerError,
+                ))
+            }
+        }
+    };
+
+    Ok(Response::with((
+        status::Ok,
+        Header(headers::ContentType(content_type.parse::<Mime>().unwrap())),
+        Header(headers::CacheControl(vec![headers::CacheDirective::NoStore])),
+        body,
+    )))
+}
+
+fn guest_status(req: &mut Request) -> IronResult<Response> {
+    send_hotspot_command(req, HotspotCommand::GuestStatus)
+}
+
+fn guest_start(req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `guest hotspot start` request");
+
+    send_hotspot_command(req, HotspotCommand::StartGuest)
+}
+
+fn guest_stop(req: &mut Request) -> IronResult<Response> {
+    info!("Incoming `guest hotspot stop` request");
+
+    send_hotspot_command(req, HotspotCommand::StopGuest)
+}
+
+fn hotspot_events(req: &mut Request) -> IronResult<Response> {
+    let since = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        params.get("since").and_then(u64::from_value)
+    };
+
+    send_hotspot_command(req, HotspotCommand::Events(since))
+}
+
+fn connection(req: &mut Request) -> IronResult<Response> {
+    send_hotspot_command(req, HotspotCommand::ConnectedNetwork)
+}
+
+fn speed_test(req: &mut Request) -> IronResult<Response> {
+    if !authorize(req)? {
+        return Ok(Response::with(status::Unauthorized));
+    }
+
+    let url = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        params
+            .get("url")
+            .and_then(String::from_value)
+            .unwrap_or_else(|| DEFAULT_SPEED_TEST_URL.to_string())
+    };
+
+    if let Err(e) = validate_portal_url(url.clone()) {
+        return Ok(Response::with((status::BadRequest, e)));
+    }
+
+    info!("Incoming `speed test` request against {}", url);
+
+    let result = match run_speed_test(&url) {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("{}", e);
+            return Ok(Response::with((status::BadGateway, e.to_string())));
+        }
+    };
+
+    match serde_json::to_string(&result) {
+        Ok(json) => Ok(Response::with((status::Ok, json))),
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+    }
+}
+
+fn saved_network(req: &mut Request) -> IronResult<Response> {
+    let ssid = get_ssid_param(req);
+
+    if let Err(e) = validate_ssid(&ssid) {
+        return Ok(Response::with((status::BadRequest, e.to_string())));
+    }
+
+    send_hotspot_command(req, HotspotCommand::SavedNetwork(ssid))
+}
+
+fn update_network(req: &mut Request) -> IronResult<Response> {
+    let ssid = get_ssid_param(req);
+
+    if let Err(e) = validate_ssid(&ssid) {
+        return Ok(Response::with((status::BadRequest, e.to_string())));
+    }
+
+    let update = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+
+        ProfileUpdate {
+            passphrase: params
+                .get("passphrase")
+                .and_then(String::from_value)
+                .map(Secret::from),
+            autoconnect_priority: params.get("priority").and_then(i32::from_value),
+            autoconnect: params.get("autoconnect").and_then(bool::from_value),
+            address: params.get("address").and_then(String::from_value),
+            gateway: params.get("gateway").and_then(String::from_value),
+            dns: params
+                .get("dns")
+                .and_then(Vec::<String>::from_value)
+                .unwrap_or_default(),
+            dhcp: params
+                .get("dhcp")
+                .and_then(bool::from_value)
+                .unwrap_or(false),
+        }
+    };
+
+    if let Err(reason) = update.validate() {
+        return Ok(Response::with((status::BadRequest, reason)));
+    }
+
+    info!("Incoming `update` of saved network `{}` request", ssid);
+
+    send_hotspot_command(req, HotspotCommand::UpdateNetwork(ssid, update))
+}
+
+fn install_passpoint(req: &mut Request) -> IronResult<Response> {
+    let profile = {
+        let params = get_request_ref!(req, Params, "Getting request params failed");
+        let string = |name: &str| params.get(name).and_then(String::from_value);
+
+        PasspointProfile {
+            name: string("name").unwrap_or_default(),
+            realm: string("realm").unwrap_or_default(),
+            domain: string("domain"),
+            roaming_consortium: params
+                .get("roaming_consortium")
+                .and_then(Vec::<String>::from_value)
+                .unwrap_or_default(),
+            eap: string("eap").unwrap_or_else(|| "ttls".into()),
+            identity: string("identity").unwrap_or_default(),
+            passphrase: Secret::from(string("passphrase").unwrap_or_default()),
+            ca_cert: string("ca_cert").map(PathBuf::from),
+        }
+    };
+
+    if let Err(reason) = profile.validate() {
+        return Ok(Response::with((status::BadRequest, reason)));
+    }
+
+    info!("Incoming `passpoint` install of `{}` request", profile.name);
+
+    send_hotspot_command(req, HotspotCommand::InstallPasspoint(profile))
+}
+
+fn get_ssid_param(req: &Request) -> String {
+    req.extensions
+        .get::<Router>()
+        .and_then(|router| router.find("ssid"))
+        .map(percent_decode)
+        .unwrap_or_default()
+}
+
+/// Decodes `%XX` escapes in a route parameter, leaving malformed ones as is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{is_authorized, ServerHandle};
+
+    #[test]
+    fn authorizes_bearer_tokens() {
+        let token = Some("secret".to_string());
+
+        assert!(is_authorized(&Some("secret".to_string()), &token));
+        assert!(!is_authorized(&Some("secreT".to_string()), &token));
+        assert!(!is_authorized(&Some("secret2".to_string()), &token));
+        assert!(!is_authorized(&None, &token));
+        assert!(!is_authorized(&Some("secret".to_string()), &None));
+        assert!(!is_authorized(&None, &None));
+    }
+
+    #[test]
+    fn drains_requests_whose_handler_panicked() {
+        let server = ServerHandle::new();
+
+        let panicking = server.clone();
+        let _ = thread::spawn(move || {
+            let _in_flight = panicking.track().unwrap();
+            panic!("handler failed");
+        })
+        .join();
+
+        let start = Instant::now();
+
+        assert!(server.drain(Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(server.track().is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! HMAC-SHA256 for signing webhook payloads and verifying signed state
+//! manifests, on top of the RustCrypto `hmac` and `sha2` crates. The key
+//! stays in memory instead of being handed to an external tool on its
+//! command line.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Hex encoded HMAC-SHA256 of `message` keyed with `key`
+pub fn hmac_sha256_hex(key: &[u8], message: &[u8]) -> String {
+    hmac_sha256(key, message)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether `signature` is the hex encoded HMAC-SHA256 of `message` keyed
+/// with `key`, in either case. The comparison takes the same time wherever
+/// the signature differs, so it cannot be guessed byte by byte.
+pub fn verify_hmac_sha256_hex(key: &[u8], message: &[u8], signature: &str) -> bool {
+    let signature = match decode_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = new_hmac(key);
+    mac.update(message);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Compares secrets without returning early at the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = new_hmac(key);
+    mac.update(message);
+
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&mac.finalize().into_bytes());
+    digest
+}
+
+pub fn sha256(message: &[u8]) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&Sha256::digest(message));
+    digest
+}
+
+fn new_hmac(key: &[u8]) -> HmacSha256 {
+    // HMAC hashes longer keys and pads shorter ones, any length is fine
+    HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let digit = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, hmac_sha256_hex, sha256, verify_hmac_sha256_hex};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // FIPS 180-2, appendix B
+    #[test]
+    fn sha256_test_vectors() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 1_000_000])),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    // RFC 4231, section 4. Test case 5 checks a truncated output and is left
+    // out.
+    #[test]
+    fn hmac_sha256_test_vectors() {
+        let key: Vec<u8> = (1..26).collect();
+
+        let cases: &[(&[u8], &[u8], &str)] = &[
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &key,
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the HMAC \
+                  algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for &(key, message, expected) in cases {
+            assert_eq!(hmac_sha256_hex(key, message), expected);
+        }
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let signature = "5BDCC146BF60754E6A042426089575C75A003F089D2739839DEC58B964EC3843";
+
+        assert!(verify_hmac_sha256_hex(
+            b"Jefe",
+            b"what do ya want for nothing?",
+            signature
+        ));
+        assert!(!verify_hmac_sha256_hex(
+            b"Jefe",
+            b"what do ya want for nothing!",
+            signature
+        ));
+        assert!(!verify_hmac_sha256_hex(
+            b"Jefe",
+            b"what do ya want for nothing?",
+            &signature[..62]
+        ));
+    }
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"tokens"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
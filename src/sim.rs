@@ -0,0 +1,169 @@
+//! EAP-SIM and EAP-AKA authentication with the SIM of a cellular modem, for
+//! the WiFi offload networks of carriers. The modem is found through
+//! ModemManager and the IMSI of its SIM yields the permanent identity of
+//! RFC 4186 and RFC 4187, while the SIM answers the challenges itself.
+
+use std::process::Command;
+
+use backend::{Backend, DEFAULT_BACKEND};
+use errors::*;
+use network::activate_profile;
+use profile::{add_eap_sim_profile, managed_connection_id, nmcli, ProfileSettings};
+
+pub const SIM_METHODS: &[&str] = &["sim", "aka", "aka-prime"];
+
+const MM_DESTINATION: &str = "--dest=org.freedesktop.ModemManager1";
+const MM_PATH: &str = "/org/freedesktop/ModemManager1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimMethod {
+    Sim,
+    Aka,
+    AkaPrime,
+}
+
+impl SimMethod {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sim" => Some(SimMethod::Sim),
+            "aka" => Some(SimMethod::Aka),
+            "aka-prime" => Some(SimMethod::AkaPrime),
+            _ => None,
+        }
+    }
+
+    /// Value of the `802-1x.eap` property
+    fn eap(&self) -> &'static str {
+        match *self {
+            SimMethod::Sim => "sim",
+            SimMethod::Aka => "aka",
+            SimMethod::AkaPrime => "aka'",
+        }
+    }
+
+    /// Leading digit of the permanent identity telling the method apart
+    fn identity_prefix(&self) -> char {
+        match *self {
+            SimMethod::Sim => '1',
+            SimMethod::Aka => '0',
+            SimMethod::AkaPrime => '6',
+        }
+    }
+}
+
+/// Connects to the network authenticating with the SIM of the modem
+pub fn connect_eap_sim(
+    backend: &dyn Backend,
+    ssid: &str,
+    method: SimMethod,
+    settings: &ProfileSettings,
+    timeout: u64,
+) -> Result<()> {
+    if backend.name() != DEFAULT_BACKEND {
+        bail!(ErrorKind::UnsupportedByBackend(
+            backend.name().into(),
+            "EAP-SIM and EAP-AKA".into()
+        ));
+    }
+
+    let identity = sim_identity(method)?;
+
+    info!("Authenticating to '{}' as {}", ssid, identity);
+
+    let id = managed_connection_id(ssid);
+
+    // A profile left over from an earlier attempt would be activated instead
+    nmcli(&["connection", "delete", "id", &id]);
+
+    add_eap_sim_profile(ssid, method.eap(), &identity, settings)?;
+
+    activate_profile(backend.interface(), ssid, timeout)
+}
+
+/// Permanent identity of the SIM, e.g.
+/// `1001010123456789@wlan.mnc001.mcc001.3gppnetwork.org` for EAP-SIM
+fn sim_identity(method: SimMethod) -> Result<String> {
+    let sim = find_sim()?;
+
+    let imsi = sim_property(&sim, "Imsi")?;
+    let operator = sim_property(&sim, "OperatorIdentifier")?;
+
+    if operator.len() < 5 || !imsi.starts_with(&operator) {
+        bail!(ErrorKind::SimNotFound);
+    }
+
+    let (mcc, mnc) = operator.split_at(3);
+
+    Ok(format!(
+        "{}{}@wlan.mnc{:0>3}.mcc{}.3gppnetwork.org",
+        method.identity_prefix(),
+        imsi,
+        mnc,
+        mcc
+    ))
+}
+
+/// Object path of the SIM of the first modem with one
+fn find_sim() -> Result<String> {
+    let reply = call(
+        MM_PATH,
+        "org.freedesktop.DBus.ObjectManager.GetManagedObjects",
+        &[],
+    )?;
+
+    // Every modem has a `Sim` property, `/` without a SIM
+    let mut tokens = reply.split_whitespace();
+
+    while let Some(token) = tokens.next() {
+        if token != "\"Sim\"" {
+            continue;
+        }
+
+        let path = tokens
+            .by_ref()
+            .skip_while(|token| *token != "path")
+            .nth(1)
+            .map(|path| path.trim_matches('"'));
+
+        if let Some(path) = path {
+            if path != "/" {
+                return Ok(path.to_string());
+            }
+        }
+    }
+
+    bail!(ErrorKind::SimNotFound)
+}
+
+fn sim_property(sim: &str, property: &str) -> Result<String> {
+    let reply = call(
+        sim,
+        "org.freedesktop.DBus.Properties.Get",
+        &[
+            "string:org.freedesktop.ModemManager1.Sim",
+            &format!("string:{}", property),
+        ],
+    )?;
+
+    // The reply is a variant holding a single string
+    match reply.split('"').nth(1) {
+        Some(value) if !value.is_empty() => Ok(value.to_string()),
+        _ => bail!(ErrorKind::SimNotFound),
+    }
+}
+
+/// Calls `method` of a ModemManager object and returns the reply
+fn call(path: &str, method: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new("dbus-send")
+        .args(&["--system", "--print-reply", MM_DESTINATION, path, method])
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::SimNotFound)?;
+
+    if !output.status.success() {
+        warn!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        bail!(ErrorKind::SimNotFound);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
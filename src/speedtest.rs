@@ -0,0 +1,67 @@
+use std::process::Command;
+
+use errors::*;
+
+/// Payload downloaded when `--speed-test` is given without a URL
+pub const DEFAULT_SPEED_TEST_URL: &str = "http://speedtest.tele2.net/10MB.zip";
+
+/// Seconds a speed test download may take before it is cut short
+const SPEED_TEST_TIMEOUT: u64 = 30;
+
+/// Throughput and latency measured by downloading a test payload
+#[derive(Debug, Serialize)]
+pub struct SpeedTest {
+    pub url: String,
+    /// Downloaded bytes, less than the payload size when the download timed out
+    pub bytes: u64,
+    /// Average download speed in MBit/s
+    pub mbps: f64,
+    /// Time to establish the TCP connection in milliseconds
+    pub latency_ms: f64,
+}
+
+/// Downloads `url` with `curl` and measures the throughput. Downloads that
+/// do not finish within the timeout still report what was transferred.
+/// Only HTTP and HTTPS are allowed, also for redirects, so that the URL
+/// cannot make `curl` read local files or reach other kinds of services.
+pub fn run_speed_test(url: &str) -> Result<SpeedTest> {
+    let output = Command::new("curl")
+        .args(&[
+            "--silent",
+            "--location",
+            "--proto",
+            "=http,https",
+            "--proto-redir",
+            "=http,https",
+            "--output",
+            "/dev/null",
+            "--max-time",
+            &SPEED_TEST_TIMEOUT.to_string(),
+            "--write-out",
+            "%{size_download} %{speed_download} %{time_connect}",
+            "--",
+            url,
+        ])
+        .output()
+        .chain_err(|| ErrorKind::SpeedTest(url.into()))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut values = stdout
+        .split_whitespace()
+        .map(|value| value.parse::<f64>().ok());
+
+    let (bytes, speed, connect) = match (values.next(), values.next(), values.next()) {
+        (Some(Some(bytes)), Some(Some(speed)), Some(Some(connect))) if bytes > 0.0 => {
+            (bytes, speed, connect)
+        }
+        _ => bail!(ErrorKind::SpeedTest(url.into())),
+    };
+
+    Ok(SpeedTest {
+        url: url.into(),
+        bytes: bytes as u64,
+        // `curl` reports bytes per second
+        mbps: speed * 8.0 / 1_000_000.0,
+        latency_ms: connect * 1000.0,
+    })
+}
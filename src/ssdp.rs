@@ -0,0 +1,350 @@
+//! SSDP advertisement (UPnP Device Architecture 1.1), so that the companion
+//! app finds devices on the LAN without the user typing addresses. The device
+//! description served at `SSDP_DESCRIPTION_PATH` by the HTTP servers points
+//! at the JSON API through `presentationURL`.
+
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use server::API_PREFIX;
+use signing::sha256;
+use webhook::resolve_device_id;
+
+/// Path of the UPnP device description on the HTTP servers
+pub const SSDP_DESCRIPTION_PATH: &str = "/ssdp/description.xml";
+
+const SSDP_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+const SSDP_PORT: u16 = 1900;
+
+const DEVICE_TYPE: &str = "urn:schemas-balena-io:device:WiFiConnect:1";
+
+const HOSTNAME_FILE: &str = "/proc/sys/kernel/hostname";
+
+/// Seconds control points may cache an advertisement, kept short as the
+/// address changes when leaving portal mode
+const MAX_AGE: u64 = 120;
+
+/// Seconds between `ssdp:alive` announcements
+const ANNOUNCE_INTERVAL: u64 = 60;
+
+/// Hop limit of the announcements recommended by UPnP
+const MULTICAST_TTL: u32 = 2;
+
+struct Advertiser {
+    /// HTTP server serving the description, unspecified if it listens on all
+    /// addresses
+    server: SocketAddr,
+    gateway: Ipv4Addr,
+    uuid: String,
+}
+
+/// Advertises the HTTP server on `server` over SSDP if `--ssdp` is set
+pub fn spawn_ssdp_advertiser(config: &Config, server: SocketAddr) {
+    if !config.ssdp {
+        return;
+    }
+
+    let advertiser = Advertiser {
+        server,
+        gateway: config.gateway,
+        uuid: device_uuid(config),
+    };
+
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SSDP_PORT)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!(
+                    "Starting SSDP advertisement on port {} failed: {}",
+                    SSDP_PORT, e
+                );
+                return;
+            }
+        };
+
+        info!("Advertising {} over SSDP", server);
+
+        advertiser.run(&socket);
+    });
+}
+
+/// UPnP device description of this device
+pub fn device_description(config: &Config) -> String {
+    let friendly_name = fs::read_to_string(HOSTNAME_FILE)
+        .map(|hostname| hostname.trim().to_string())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "WiFi Connect".to_string());
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<root xmlns=\"urn:schemas-upnp-org:device-1-0\">\n",
+            "  <specVersion>\n",
+            "    <major>1</major>\n",
+            "    <minor>1</minor>\n",
+            "  </specVersion>\n",
+            "  <device>\n",
+            "    <deviceType>{}</deviceType>\n",
+            "    <friendlyName>{}</friendlyName>\n",
+            "    <manufacturer>balena</manufacturer>\n",
+            "    <modelName>WiFi Connect</modelName>\n",
+            "    <modelNumber>{}</modelNumber>\n",
+            "    <serialNumber>{}</serialNumber>\n",
+            "    <UDN>uuid:{}</UDN>\n",
+            "    <presentationURL>{}</presentationURL>\n",
+            "  </device>\n",
+            "</root>\n"
+        ),
+        DEVICE_TYPE,
+        escape_xml(&friendly_name),
+        env!("CARGO_PKG_VERSION"),
+        escape_xml(&resolve_device_id(&config.device_id)),
+        device_uuid(config),
+        API_PREFIX
+    )
+}
+
+impl Advertiser {
+    fn run(&self, socket: &UdpSocket) {
+        let mut buffer = [0u8; 2048];
+        let mut next_announcement = Instant::now();
+
+        loop {
+            let now = Instant::now();
+
+            if now >= next_announcement {
+                // Memberships are renewed as the portal gateway comes and goes
+                self.join(socket);
+                self.announce();
+                next_announcement = now + Duration::from_secs(ANNOUNCE_INTERVAL);
+            }
+
+            let _ = socket.set_read_timeout(Some(next_announcement - now));
+
+            match socket.recv_from(&mut buffer) {
+                Ok((length, peer)) => self.respond(socket, &buffer[..length], peer),
+                Err(ref e)
+                    if e.kind() == IoErrorKind::WouldBlock || e.kind() == IoErrorKind::TimedOut => {
+                }
+                Err(e) => warn!("Receiving SSDP request failed: {}", e),
+            }
+        }
+    }
+
+    fn join(&self, socket: &UdpSocket) {
+        for interface in self.interfaces() {
+            // Fails if the address is gone or the membership is already in
+            // place
+            if let Err(e) = socket.join_multicast_v4(&SSDP_MULTICAST_ADDRESS, &interface) {
+                debug!(
+                    "Joining SSDP multicast group on {} failed: {}",
+                    interface, e
+                );
+            }
+        }
+    }
+
+    fn announce(&self) {
+        let destination = SocketAddr::from((SSDP_MULTICAST_ADDRESS, SSDP_PORT));
+
+        let mut sources = self.interfaces();
+
+        if let Some(address) = local_address(destination) {
+            sources.push(address);
+        }
+
+        sources.retain(|source| !source.is_unspecified());
+        sources.sort();
+        sources.dedup();
+
+        for source in sources {
+            // Binding to the address picks its interface for the multicast
+            // and only succeeds while the address is assigned
+            let socket = match UdpSocket::bind((source, 0)) {
+                Ok(socket) => socket,
+                Err(_) => continue,
+            };
+
+            let _ = socket.set_multicast_ttl_v4(MULTICAST_TTL);
+
+            for (target, usn) in self.targets() {
+                let notify = format!(
+                    concat!(
+                        "NOTIFY * HTTP/1.1\r\n",
+                        "HOST: {}\r\n",
+                        "CACHE-CONTROL: max-age={}\r\n",
+                        "LOCATION: {}\r\n",
+                        "NT: {}\r\n",
+                        "NTS: ssdp:alive\r\n",
+                        "SERVER: {}\r\n",
+                        "USN: {}\r\n",
+                        "\r\n"
+                    ),
+                    destination,
+                    MAX_AGE,
+                    self.location(source),
+                    target,
+                    server_header(),
+                    usn
+                );
+
+                if let Err(e) = socket.send_to(notify.as_bytes(), destination) {
+                    debug!("Sending SSDP announcement from {} failed: {}", source, e);
+                }
+            }
+        }
+    }
+
+    fn respond(&self, socket: &UdpSocket, request: &[u8], peer: SocketAddr) {
+        let request = String::from_utf8_lossy(request);
+        let mut lines = request.lines();
+
+        if !lines
+            .next()
+            .map_or(false, |line| line.trim().starts_with("M-SEARCH * "))
+        {
+            return;
+        }
+
+        let mut search_target = None;
+        let mut discover = false;
+
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_ascii_uppercase();
+            let value = parts.next().unwrap_or("").trim();
+
+            match &name[..] {
+                "ST" => search_target = Some(value.to_string()),
+                "MAN" => discover = value.trim_matches('"') == "ssdp:discover",
+                _ => {}
+            }
+        }
+
+        let search_target = match search_target {
+            Some(search_target) if discover => search_target,
+            _ => return,
+        };
+
+        let address = match self.server.ip() {
+            IpAddr::V4(ip) if !ip.is_unspecified() => ip,
+            _ => match local_address(peer) {
+                Some(address) => address,
+                None => return,
+            },
+        };
+
+        for (target, usn) in self.targets() {
+            if search_target != "ssdp:all" && search_target != target {
+                continue;
+            }
+
+            let response = format!(
+                concat!(
+                    "HTTP/1.1 200 OK\r\n",
+                    "CACHE-CONTROL: max-age={}\r\n",
+                    "EXT:\r\n",
+                    "LOCATION: {}\r\n",
+                    "SERVER: {}\r\n",
+                    "ST: {}\r\n",
+                    "USN: {}\r\n",
+                    "\r\n"
+                ),
+                MAX_AGE,
+                self.location(address),
+                server_header(),
+                target,
+                usn
+            );
+
+            if let Err(e) = socket.send_to(response.as_bytes(), peer) {
+                debug!("Answering SSDP search from {} failed: {}", peer, e);
+            }
+        }
+    }
+
+    /// Addresses to receive searches on, the portal gateway covers the
+    /// hotspot while the unspecified address follows the default route
+    fn interfaces(&self) -> Vec<Ipv4Addr> {
+        match self.server.ip() {
+            IpAddr::V4(ip) if !ip.is_unspecified() => vec![ip],
+            _ => vec![self.gateway, Ipv4Addr::UNSPECIFIED],
+        }
+    }
+
+    /// Notification types with their unique service names
+    fn targets(&self) -> Vec<(String, String)> {
+        let udn = format!("uuid:{}", self.uuid);
+
+        vec![
+            (
+                "upnp:rootdevice".to_string(),
+                format!("{}::upnp:rootdevice", udn),
+            ),
+            (udn.clone(), udn.clone()),
+            (DEVICE_TYPE.to_string(), format!("{}::{}", udn, DEVICE_TYPE)),
+        ]
+    }
+
+    fn location(&self, address: Ipv4Addr) -> String {
+        format!(
+            "http://{}:{}{}",
+            address,
+            self.server.port(),
+            SSDP_DESCRIPTION_PATH
+        )
+    }
+}
+
+/// Local address the system routes through to reach `destination`
+fn local_address(destination: SocketAddr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+
+    socket.connect(destination).ok()?;
+
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+/// Stable UUID derived from the device ID
+fn device_uuid(config: &Config) -> String {
+    let mut bytes = sha256(resolve_device_id(&config.device_id).as_bytes());
+
+    // Version 8 (custom) and RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x80;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn server_header() -> String {
+    format!("Linux UPnP/1.1 wifi-connect/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
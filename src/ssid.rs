@@ -0,0 +1,171 @@
+use std::str;
+
+use errors::*;
+
+/// Prefix selecting a hex-encoded SSID on the command line and in the API
+pub const HEX_PREFIX: &str = "hex:";
+
+/// 802.11 limits SSIDs to 32 bytes, which are fewer than 32 characters when
+/// they contain multi-byte UTF-8
+pub const MAX_SSID_BYTES: usize = 32;
+
+/// Human readable form of a raw SSID. Valid UTF-8 is kept as is, while any
+/// other byte is escaped as `\xNN`.
+pub fn display_ssid(ssid: &[u8]) -> String {
+    let mut display = String::new();
+    let mut remaining = ssid;
+
+    while !remaining.is_empty() {
+        match str::from_utf8(remaining) {
+            Ok(valid) => {
+                display.push_str(valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = remaining.split_at(e.valid_up_to());
+                display.push_str(str::from_utf8(valid).unwrap());
+
+                let invalid_len = e.error_len().unwrap_or_else(|| invalid.len());
+                for byte in &invalid[..invalid_len] {
+                    display.push_str(&format!("\\x{:02x}", byte));
+                }
+
+                remaining = &invalid[invalid_len..];
+            }
+        }
+    }
+
+    display
+}
+
+pub fn hex_ssid(ssid: &[u8]) -> String {
+    ssid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Raw bytes of an SSID given by the user, either as text or hex-encoded
+/// with the `hex:` prefix
+pub fn parse_ssid(ssid: &str) -> Result<Vec<u8>> {
+    if !ssid.starts_with(HEX_PREFIX) {
+        return Ok(ssid.as_bytes().to_vec());
+    }
+
+    let hex = &ssid[HEX_PREFIX.len()..];
+
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        bail!(ErrorKind::InvalidSsid(ssid.into(), "malformed hex encoding".into()));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .chain_err(|| ErrorKind::InvalidSsid(ssid.into(), "malformed hex encoding".into()))
+        })
+        .collect()
+}
+
+/// Whether an SSID given by the user refers to the raw SSID, matching its
+/// text, its escaped display form or its hex encoding
+pub fn matches_ssid(ssid: &[u8], input: &str) -> bool {
+    if ssid == input.as_bytes() || display_ssid(ssid) == input {
+        return true;
+    }
+
+    match parse_ssid(input) {
+        Ok(ref parsed) => parsed.as_slice() == ssid,
+        Err(_) => false,
+    }
+}
+
+/// Checks that an SSID given by the user is not empty and fits into 32
+/// bytes, before it reaches the backend
+pub fn validate_ssid(ssid: &str) -> Result<()> {
+    let len = ssid_len(ssid)?;
+
+    if len == 0 {
+        bail!(ErrorKind::InvalidSsid(ssid.into(), "it is empty".into()));
+    }
+
+    if len > MAX_SSID_BYTES {
+        bail!(ErrorKind::InvalidSsid(
+            ssid.into(),
+            format!("it is {} bytes long, at most {} are allowed", len, MAX_SSID_BYTES)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Length in bytes of the raw SSID, counting `\xNN` escapes of the display
+/// form as a single byte
+fn ssid_len(ssid: &str) -> Result<usize> {
+    if ssid.starts_with(HEX_PREFIX) {
+        return Ok(parse_ssid(ssid)?.len());
+    }
+
+    let bytes = ssid.as_bytes();
+    let escapes = bytes
+        .windows(4)
+        .filter(|window| {
+            window[0] == b'\\'
+                && window[1] == b'x'
+                && window[2].is_ascii_hexdigit()
+                && window[3].is_ascii_hexdigit()
+        })
+        .count();
+
+    Ok(bytes.len() - 3 * escapes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_ssid, matches_ssid, parse_ssid, ssid_len, validate_ssid};
+
+    #[test]
+    fn displays_invalid_utf8_escaped() {
+        assert_eq!(display_ssid(b"Caf\xc3\xa9"), "Café");
+        assert_eq!(display_ssid(b"Lab\xff\xfe5"), "Lab\\xff\\xfe5");
+        assert_eq!(display_ssid(b"\xc3"), "\\xc3");
+    }
+
+    #[test]
+    fn parses_hex_ssids() {
+        assert_eq!(parse_ssid("Office").unwrap(), b"Office");
+        assert_eq!(parse_ssid("hex:4c6162ff").unwrap(), b"Lab\xff");
+        assert_eq!(parse_ssid("hex:4C41").unwrap(), b"LA");
+
+        assert!(parse_ssid("hex:").is_err());
+        assert!(parse_ssid("hex:4c6").is_err());
+        assert!(parse_ssid("hex:zz").is_err());
+    }
+
+    #[test]
+    fn counts_ssid_bytes() {
+        assert_eq!(ssid_len("Office").unwrap(), 6);
+        assert_eq!(ssid_len("Café").unwrap(), 5);
+        assert_eq!(ssid_len("Lab\\xff\\xfe").unwrap(), 5);
+        assert_eq!(ssid_len("hex:4c6162").unwrap(), 3);
+    }
+
+    #[test]
+    fn validates_ssid_length() {
+        assert!(validate_ssid("Office").is_ok());
+        assert!(validate_ssid(&"a".repeat(32)).is_ok());
+        assert!(validate_ssid(&"\\xff".repeat(32)).is_ok());
+
+        assert!(validate_ssid("").is_err());
+        assert!(validate_ssid(&"a".repeat(33)).is_err());
+        // 11 three byte characters are 33 bytes
+        assert!(validate_ssid(&"€".repeat(11)).is_err());
+        assert!(validate_ssid(&format!("hex:{}", "61".repeat(33))).is_err());
+    }
+
+    #[test]
+    fn matches_ssid_forms() {
+        let ssid = b"Lab\xff";
+
+        assert!(matches_ssid(ssid, "Lab\\xff"));
+        assert!(matches_ssid(ssid, "hex:4c6162ff"));
+        assert!(!matches_ssid(ssid, "Lab"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::{Arc, Mutex};
+
+use errors::*;
+use events::{EventKind, EventLog, SharedEventLog};
+use notifier::Notifier;
+use timings::ConnectTimings;
+
+/// Provisioning states reported to the configured notifiers
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum State {
+    Idle,
+    HotspotUp {
+        ssid: String,
+    },
+    ClientJoined,
+    CredentialsReceived {
+        ssid: String,
+    },
+    Connected {
+        ssid: String,
+        ip: Option<String>,
+        clock_synchronized: Option<bool>,
+    },
+    Failed {
+        ssid: String,
+        reason: String,
+        failure: Failure,
+    },
+    HotspotDown,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            State::Idle => "idle",
+            State::HotspotUp { .. } => "hotspot-up",
+            State::ClientJoined => "client-joined",
+            State::CredentialsReceived { .. } => "credentials-received",
+            State::Connected {
+                clock_synchronized: Some(false),
+                ..
+            } => "connected-clock-unsynced",
+            State::Connected { .. } => "connected",
+            State::Failed { .. } => "failed",
+            State::HotspotDown => "hotspot-down",
+        }
+    }
+
+    pub fn ssid(&self) -> Option<&str> {
+        match *self {
+            State::HotspotUp { ref ssid }
+            | State::CredentialsReceived { ref ssid }
+            | State::Connected { ref ssid, .. }
+            | State::Failed { ref ssid, .. } => Some(ssid),
+            _ => None,
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match *self {
+            State::Failed { ref reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn failure(&self) -> Option<Failure> {
+        match *self {
+            State::Failed { failure, .. } => Some(failure),
+            _ => None,
+        }
+    }
+}
+
+/// Cause of a failed connection attempt, telling e.g. a wrong password apart
+/// from a network that did not hand out an address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Failure {
+    WrongPassword,
+    AssociationTimeout,
+    DhcpFailed,
+    NetworkNotFound,
+    TunnelFailed,
+    Other,
+}
+
+impl Failure {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Failure::WrongPassword => "wrong-password",
+            Failure::AssociationTimeout => "association-timeout",
+            Failure::DhcpFailed => "dhcp-failed",
+            Failure::NetworkNotFound => "network-not-found",
+            Failure::TunnelFailed => "tunnel-failed",
+            Failure::Other => "other",
+        }
+    }
+}
+
+impl<'a> From<&'a ErrorKind> for Failure {
+    fn from(kind: &ErrorKind) -> Self {
+        match *kind {
+            ErrorKind::AuthenticationFailed(_) => Failure::WrongPassword,
+            ErrorKind::AssociationTimeout(_) => Failure::AssociationTimeout,
+            ErrorKind::DhcpFailed(_) => Failure::DhcpFailed,
+            ErrorKind::NetworkNotFound(_) => Failure::NetworkNotFound,
+            ErrorKind::ProvisionWireguard | ErrorKind::WireguardHandshake(_) => {
+                Failure::TunnelFailed
+            }
+            _ => Failure::Other,
+        }
+    }
+}
+
+/// Served by `/connect-status`. The last failed attempt is kept once the
+/// hotspot is back up, so that the portal can show it after reconnecting.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectStatus {
+    #[serde(flatten)]
+    pub state: State,
+    pub last_failure: Option<State>,
+    /// Where the time of the current or last connection attempt went
+    pub timings: Option<ConnectTimings>,
+}
+
+pub type SharedConnectStatus = Arc<Mutex<ConnectStatus>>;
+
+/// Central state machine - every transition is fanned out to all notifiers
+pub struct StateMachine {
+    state: State,
+    notifiers: Vec<Box<dyn Notifier>>,
+    status: SharedConnectStatus,
+    events: SharedEventLog,
+}
+
+impl StateMachine {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        StateMachine {
+            state: State::Idle,
+            notifiers,
+            status: Arc::new(Mutex::new(ConnectStatus {
+                state: State::Idle,
+                last_failure: None,
+                timings: None,
+            })),
+            events: Arc::new(Mutex::new(EventLog::new())),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn status(&self) -> SharedConnectStatus {
+        self.status.clone()
+    }
+
+    pub fn events(&self) -> SharedEventLog {
+        self.events.clone()
+    }
+
+    /// Adds an error to the event history without changing the state
+    pub fn record_error(&self, message: String) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(EventKind::Error { message });
+    }
+
+    pub fn transition(&mut self, next: State) {
+        if next == self.state {
+            return;
+        }
+
+        debug!("State transition: {} -> {}", self.state.name(), next.name());
+
+        let previous = ::std::mem::replace(&mut self.state, next);
+
+        {
+            let mut status = self.status.lock().unwrap();
+            status.state = self.state.clone();
+
+            match self.state {
+                State::Failed { .. } => status.last_failure = Some(self.state.clone()),
+                State::Connected { .. } => status.last_failure = None,
+                _ => {}
+            }
+        }
+
+        self.events.lock().unwrap().push(EventKind::Transition {
+            from: previous.name().to_string(),
+            to: self.state.clone(),
+        });
+
+        for notifier in &mut self.notifiers {
+            if let Err(e) = notifier.notify(&previous, &self.state) {
+                warn!("{} notifier failed: {}", notifier.name(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use backend::{init_backend, request_and_wait_for_scan};
+use config::Config;
+use errors::*;
+use exit::Shutdown;
+use network::Network;
+
+/// Signal strengths a network was seen with over the course of a survey
+#[derive(Clone, Debug, Serialize)]
+pub struct SignalHistory {
+    pub ssid: String,
+    pub ssid_hex: String,
+    pub security: String,
+    /// Signal quality in percent, one per scan the network was seen in
+    pub samples: Vec<u8>,
+}
+
+impl SignalHistory {
+    pub fn last(&self) -> u8 {
+        self.samples.last().cloned().unwrap_or(0)
+    }
+
+    pub fn min(&self) -> u8 {
+        self.samples.iter().cloned().min().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> u8 {
+        self.samples.iter().cloned().max().unwrap_or(0)
+    }
+
+    pub fn average(&self) -> u8 {
+        if self.samples.is_empty() {
+            return 0;
+        }
+
+        let sum: u32 = self.samples.iter().map(|&sample| u32::from(sample)).sum();
+        (sum / self.samples.len() as u32) as u8
+    }
+}
+
+/// JSON line streamed for every network of a survey scan
+#[derive(Serialize)]
+struct Sample<'a> {
+    timestamp: u64,
+    scan: usize,
+    ssid: &'a str,
+    ssid_hex: &'a str,
+    security: &'a str,
+    signal_strength: u8,
+    min: u8,
+    max: u8,
+    average: u8,
+    samples: usize,
+}
+
+/// Signal histories of the networks seen so far, in the order they first
+/// showed up
+#[derive(Default)]
+pub struct Survey {
+    scans: usize,
+    histories: Vec<SignalHistory>,
+    index: HashMap<String, usize>,
+}
+
+impl Survey {
+    pub fn new() -> Self {
+        Survey::default()
+    }
+
+    pub fn scans(&self) -> usize {
+        self.scans
+    }
+
+    pub fn histories(&self) -> &[SignalHistory] {
+        &self.histories
+    }
+
+    /// Adds the results of a scan and returns the histories of the networks
+    /// in it
+    pub fn record(&mut self, networks: &[Network]) -> Vec<&SignalHistory> {
+        self.scans += 1;
+
+        let mut positions = Vec::new();
+
+        for network in networks {
+            let position = match self.index.get(&network.ssid_hex) {
+                Some(&position) => position,
+                None => {
+                    self.index
+                        .insert(network.ssid_hex.clone(), self.histories.len());
+                    self.histories.push(SignalHistory {
+                        ssid: network.ssid.clone(),
+                        ssid_hex: network.ssid_hex.clone(),
+                        security: network.security.clone(),
+                        samples: Vec::new(),
+                    });
+                    self.histories.len() - 1
+                }
+            };
+
+            self.histories[position]
+                .samples
+                .push(network.signal_strength);
+            positions.push(position);
+        }
+
+        positions
+            .into_iter()
+            .map(|position| &self.histories[position])
+            .collect()
+    }
+}
+
+/// Scans every `--interval` seconds until `--duration` elapses or shutdown
+/// is triggered, printing each scan as it completes
+pub fn run_survey(config: &Config, shutdown: &Shutdown) -> Result<Survey> {
+    let mut backend = init_backend(config)?;
+    let mut survey = Survey::new();
+
+    let deadline = match config.survey_duration {
+        0 => None,
+        duration => Some(Instant::now() + Duration::from_secs(duration)),
+    };
+
+    info!(
+        "Surveying networks on {} every {}s...",
+        backend.interface(),
+        config.survey_interval
+    );
+
+    loop {
+        if request_and_wait_for_scan(&*backend, config.scan_timeout, |duration| {
+            shutdown.sleep(duration)
+        }) {
+            break;
+        }
+
+        let mut networks = backend.scan()?;
+        networks.sort_by(|a, b| b.signal_strength.cmp(&a.signal_strength));
+
+        let scan = survey.scans() + 1;
+        let histories = survey.record(&networks);
+
+        if config.survey_json {
+            print_json_lines(scan, &histories);
+        } else {
+            print_scan(scan, &histories);
+        }
+
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        if shutdown.sleep(Duration::from_secs(config.survey_interval)) {
+            break;
+        }
+    }
+
+    Ok(survey)
+}
+
+fn print_json_lines(scan: usize, histories: &[&SignalHistory]) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    for history in histories {
+        let sample = Sample {
+            timestamp,
+            scan,
+            ssid: &history.ssid,
+            ssid_hex: &history.ssid_hex,
+            security: &history.security,
+            signal_strength: history.last(),
+            min: history.min(),
+            max: history.max(),
+            average: history.average(),
+            samples: history.samples.len(),
+        };
+
+        if let Ok(json) = serde_json::to_string(&sample) {
+            println!("{}", json);
+        }
+    }
+}
+
+fn print_scan(scan: usize, histories: &[&SignalHistory]) {
+    println!("\n=== Scan {} ===", scan);
+
+    if histories.is_empty() {
+        println!("No networks found.");
+    }
+
+    for history in histories {
+        println!(
+            "SSID: {}, Security: {}, Signal: {}% (min {}%, avg {}%, max {}%)",
+            history.ssid,
+            history.security,
+            history.last(),
+            history.min(),
+            history.average(),
+            history.max()
+        );
+    }
+}
+
+/// Summary printed once the survey ends, strongest networks first
+pub fn print_summary(survey: &Survey) {
+    let mut histories = survey.histories().iter().collect::<Vec<_>>();
+    histories.sort_by(|a, b| b.average().cmp(&a.average()));
+
+    println!("\n=== Survey summary ({} scans) ===", survey.scans());
+
+    for history in histories {
+        println!(
+            "SSID: {}, Seen: {}/{}, Signal: min {}%, avg {}%, max {}%",
+            history.ssid,
+            history.samples.len(),
+            survey.scans(),
+            history.min(),
+            history.average(),
+            history.max()
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Timing of the current connection attempt, served with `/connect-status`
+//! so that users and support can see where a slow onboarding stalls. The
+//! phases are told apart by the kernel's view of the interface, whichever
+//! daemon manages it: associating brings the carrier up, the supplicant
+//! marks the link up once authenticated and DHCP assigns an address.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use network::get_interface_ipv4;
+use state::SharedConnectStatus;
+use wiphy::{has_carrier, operstate};
+
+/// How often the interface is looked at during an attempt
+const POLL_INTERVAL: u64 = 100;
+
+/// Milliseconds spent in each phase of a connection attempt, missing for
+/// the phases not completed (yet)
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ConnectTimings {
+    pub ssid: String,
+    pub association_ms: Option<u64>,
+    pub authentication_ms: Option<u64>,
+    pub dhcp_ms: Option<u64>,
+    pub connectivity_ms: Option<u64>,
+    /// Time since the attempt started, or its duration once it is over
+    pub total_ms: u64,
+}
+
+/// Follows the interface during a connection attempt, updating the timings
+/// of the connect status as phases complete
+pub struct AttemptTimer {
+    started: Instant,
+    status: SharedConnectStatus,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AttemptTimer {
+    /// Starts timing an attempt, replacing the timings of the previous one
+    pub fn start(interface: &str, ssid: &str, status: SharedConnectStatus) -> Self {
+        let started = Instant::now();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        status.lock().unwrap().timings = Some(ConnectTimings {
+            ssid: ssid.to_string(),
+            ..Default::default()
+        });
+
+        let handle = {
+            let interface = interface.to_string();
+            let status = status.clone();
+            let stop = stop.clone();
+            thread::spawn(move || follow_link(&interface, started, &status, &stop))
+        };
+
+        AttemptTimer {
+            started,
+            status,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stops following the interface, once it has an address or the
+    /// attempt failed
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+
+        self.update(|_| ());
+    }
+
+    /// Records how long the connectivity check took after DHCP
+    pub fn record_connectivity(&mut self, elapsed: Duration) {
+        self.stop();
+        self.update(|timings| timings.connectivity_ms = Some(milliseconds(elapsed)));
+    }
+
+    fn update<F: FnOnce(&mut ConnectTimings)>(&self, update: F) {
+        if let Some(ref mut timings) = self.status.lock().unwrap().timings {
+            update(timings);
+            timings.total_ms = milliseconds(self.started.elapsed());
+        }
+    }
+}
+
+impl Drop for AttemptTimer {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.stop();
+        }
+    }
+}
+
+fn follow_link(interface: &str, started: Instant, status: &SharedConnectStatus, stop: &AtomicBool) {
+    // Start of the current phase
+    let mut phase_started = started;
+
+    while !stop.load(Ordering::SeqCst) {
+        {
+            let mut status = status.lock().unwrap();
+            let timings = match status.timings {
+                Some(ref mut timings) => timings,
+                None => return,
+            };
+
+            let now = Instant::now();
+            let phase = milliseconds(now - phase_started);
+
+            if timings.association_ms.is_none() {
+                if has_carrier(interface) {
+                    timings.association_ms = Some(phase);
+                    phase_started = now;
+                }
+            } else if timings.authentication_ms.is_none() {
+                if operstate(interface).map_or(false, |state| state == "up") {
+                    timings.authentication_ms = Some(phase);
+                    phase_started = now;
+                }
+            } else if timings.dhcp_ms.is_none() && get_interface_ipv4(interface).is_some() {
+                timings.dhcp_ms = Some(phase);
+            }
+
+            timings.total_ms = milliseconds(now - started);
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    }
+}
+
+fn milliseconds(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
@@ -0,0 +1,63 @@
+//! Management overlay of the device. A VPN or WireGuard connection
+//! provisioned in NetworkManager beforehand is activated once the WiFi
+//! network has Internet connectivity, so that gateways come online directly
+//! onto the overlay.
+
+use std::process::Command;
+
+use errors::*;
+
+/// Seconds to wait for Internet connectivity before activating the VPN
+pub const VPN_CONNECTIVITY_TIMEOUT: u64 = 60;
+
+/// Seconds `nmcli` waits for the VPN connection to activate
+const VPN_ACTIVATION_TIMEOUT: u64 = 30;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct VpnStatus {
+    pub name: String,
+    /// NetworkManager activation state, none while the connection is down
+    pub state: Option<String>,
+    pub active: bool,
+}
+
+/// Activates the NetworkManager connection `name`
+pub fn activate_vpn(name: &str) -> Result<()> {
+    let output = Command::new("nmcli")
+        .args(&[
+            "--wait",
+            &VPN_ACTIVATION_TIMEOUT.to_string(),
+            "connection",
+            "up",
+            "id",
+            name,
+        ])
+        .output()
+        .chain_err(|| ErrorKind::ActivateVpn(name.into()))?;
+
+    if !output.status.success() {
+        warn!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        bail!(ErrorKind::ActivateVpn(name.into()));
+    }
+
+    info!("VPN connection '{}' activated", name);
+
+    Ok(())
+}
+
+/// Activation state of the NetworkManager connection `name`
+pub fn vpn_status(name: &str) -> VpnStatus {
+    let state = Command::new("nmcli")
+        .args(&["-g", "GENERAL.STATE", "connection", "show", "id", name])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|state| !state.is_empty());
+
+    VpnStatus {
+        name: name.into(),
+        active: state.as_ref().map_or(false, |state| state == "activated"),
+        state,
+    }
+}
@@ -0,0 +1,222 @@
+//! `wifi-connect watchdog`: monitoring of the WiFi connection without the
+//! portal, meant to run permanently as a low-footprint service. A device
+//! that stays disconnected while a saved network is in range is nudged to
+//! reconnect, since NetworkManager gives up on a network after its
+//! autoconnect retries. Once no saved network has been in range for a while
+//! the portal can take over. A desired state file given with
+//! `--apply-state` is reconciled whenever it changes, and one from
+//! `--state-url` every `--state-interval`.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use backend::{request_and_wait_for_scan, Backend, DEFAULT_BACKEND};
+use desired_state::{apply_desired_state, desired_state_modified, load_desired_state};
+use encryption::SecretsPolicy;
+use exit::wait_exit_signal;
+use profile::nmcli;
+use remote_state::{fetch_desired_state, StateUrl};
+
+/// Minutes disconnected before a reconnect is nudged
+pub const DEFAULT_RECONNECT_AFTER: u64 = 5;
+
+/// Seconds between checks of the connection
+const WATCHDOG_INTERVAL: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchdogSettings {
+    /// Minutes disconnected before a reconnect is nudged
+    pub reconnect_after: u64,
+    /// Minutes without a saved network in range before launching the
+    /// portal, none to keep waiting for one
+    pub portal_after: Option<u64>,
+    pub desired_state: Option<PathBuf>,
+    pub state_url: Option<StateUrl>,
+    pub secrets: SecretsPolicy,
+    /// Seconds a scan for saved networks is given to complete
+    pub scan_timeout: u64,
+}
+
+/// Monitors the connection of `backend` until an exit signal is trapped and
+/// returns whether the portal should be launched. The signals are waited for
+/// on the calling thread, so that the portal can trap them itself afterwards.
+pub fn run_watchdog(backend: &mut dyn Backend, settings: &WatchdogSettings) -> bool {
+    info!(
+        "Watching the WiFi connection of {} every {}s",
+        backend.interface(),
+        WATCHDOG_INTERVAL
+    );
+
+    let mut disconnected_since: Option<Instant> = None;
+    let mut out_of_range_since: Option<Instant> = None;
+    let mut reconciled: Option<SystemTime> = None;
+    let mut pulled: Option<Instant> = None;
+
+    loop {
+        if let Some(ref path) = settings.desired_state {
+            reconcile(backend, path, &settings.secrets, &mut reconciled);
+        }
+
+        if let Some(ref state_url) = settings.state_url {
+            if state_url.is_due(pulled) {
+                pulled = Some(Instant::now());
+                pull(backend, state_url, &settings.secrets);
+            }
+        }
+
+        let connected = match backend.connected_network() {
+            Ok(connected) => connected.is_some(),
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        };
+
+        if connected {
+            if disconnected_since.take().is_some() {
+                info!("WiFi is connected again");
+            }
+
+            out_of_range_since = None;
+        } else {
+            let now = Instant::now();
+
+            if disconnected_since.is_none() {
+                warn!("WiFi is disconnected");
+            }
+
+            let since = *disconnected_since.get_or_insert(now);
+
+            if request_and_wait_for_scan(&*backend, settings.scan_timeout, wait_exit_signal) {
+                return false;
+            }
+
+            let in_range = saved_networks_in_range(backend);
+
+            if in_range.is_empty() {
+                let since = *out_of_range_since.get_or_insert(now);
+
+                if let Some(portal_after) = settings.portal_after {
+                    if now - since >= minutes(portal_after) {
+                        info!(
+                            "No saved WiFi network in range for {} minutes, launching the portal",
+                            portal_after
+                        );
+                        return true;
+                    }
+                }
+            } else {
+                out_of_range_since = None;
+
+                if now - since >= minutes(settings.reconnect_after) {
+                    info!(
+                        "Disconnected for {} minutes with saved WiFi networks in range: {}",
+                        settings.reconnect_after,
+                        in_range.join(", ")
+                    );
+
+                    reconnect(backend);
+
+                    // Leave the connection time to come up before the next nudge
+                    disconnected_since = Some(now);
+                }
+            }
+        }
+
+        if wait_exit_signal(Duration::from_secs(WATCHDOG_INTERVAL)) {
+            return false;
+        }
+    }
+}
+
+/// Applies the desired state file unless it is unchanged since it was last
+/// applied. Failures, e.g. while NetworkManager is unreachable, are retried
+/// on the next check.
+fn reconcile(
+    backend: &mut dyn Backend,
+    path: &Path,
+    secrets: &SecretsPolicy,
+    reconciled: &mut Option<SystemTime>,
+) {
+    let modified = desired_state_modified(path);
+
+    if modified.is_some() && modified == *reconciled {
+        return;
+    }
+
+    match load_desired_state(path, secrets).and_then(|state| apply_desired_state(backend, &state)) {
+        Ok(drift) => {
+            if !drift.is_empty() {
+                info!(
+                    "Reconciled {} saved networks with {}",
+                    drift.len(),
+                    path.display()
+                );
+            }
+
+            *reconciled = modified;
+        }
+        Err(e) => warn!("{}", e),
+    }
+}
+
+/// Applies the desired state from `--state-url`. Failures are retried on the
+/// next pull only, so that a fleet does not hammer a failing server.
+fn pull(backend: &mut dyn Backend, state_url: &StateUrl, secrets: &SecretsPolicy) {
+    match fetch_desired_state(state_url, secrets)
+        .and_then(|state| apply_desired_state(backend, &state))
+    {
+        Ok(drift) => {
+            if !drift.is_empty() {
+                info!(
+                    "Reconciled {} saved networks with {}",
+                    drift.len(),
+                    state_url.url
+                );
+            }
+        }
+        Err(e) => warn!("{}", e),
+    }
+}
+
+/// SSIDs of the saved networks seen by the last scan
+fn saved_networks_in_range(backend: &mut dyn Backend) -> Vec<String> {
+    let saved = match backend.saved_networks() {
+        Ok(saved) => saved,
+        Err(e) => {
+            warn!("{}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut in_range = backend
+        .scan()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|network| saved.iter().any(|saved| saved.ssid == network.ssid))
+        .map(|network| network.ssid)
+        .collect::<Vec<_>>();
+
+    in_range.sort();
+    in_range.dedup();
+    in_range
+}
+
+/// Lets NetworkManager activate the best available connection of the device
+/// again. Other backends keep retrying known networks on their own.
+fn reconnect(backend: &dyn Backend) {
+    if backend.name() != DEFAULT_BACKEND {
+        info!("Leaving the reconnect to the {} backend", backend.name());
+        return;
+    }
+
+    info!("Reconnecting {}...", backend.interface());
+
+    if !nmcli(&["device", "connect", backend.interface()]) {
+        warn!("Reconnecting {} failed", backend.interface());
+    }
+}
+
+fn minutes(minutes: u64) -> Duration {
+    Duration::from_secs(minutes * 60)
+}
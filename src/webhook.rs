@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use errors::*;
+use notifier::Notifier;
+use secret::Secret;
+use signing::hmac_sha256_hex;
+use state::State;
+
+const MACHINE_ID_FILE: &str = "/etc/machine-id";
+
+/// Header carrying the HMAC-SHA256 of the body when `--webhook-secret` is
+/// set, and of desired state manifests from `--state-url`
+pub const SIGNATURE_HEADER: &str = "X-WiFi-Connect-Signature";
+
+/// Deliveries attempted before a notification is dropped
+const WEBHOOK_ATTEMPTS: u32 = 5;
+
+/// Seconds a single delivery may take
+const WEBHOOK_TIMEOUT: u64 = 10;
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    device_id: &'a str,
+    ssid: &'a str,
+    ip: Option<&'a str>,
+    /// Unix timestamp of the outcome
+    timestamp: u64,
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+/// POSTs the outcome of provisioning to `--webhook-url`, retrying with
+/// exponential backoff in the background
+pub struct WebhookNotifier {
+    url: String,
+    secret: Option<Secret>,
+    device_id: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, secret: Option<Secret>, device_id: &Option<String>) -> Self {
+        WebhookNotifier {
+            url: url.to_string(),
+            secret,
+            device_id: resolve_device_id(device_id),
+        }
+    }
+}
+
+/// `--device-id`, defaulting to the machine ID of the system
+pub fn resolve_device_id(device_id: &Option<String>) -> String {
+    device_id.clone().unwrap_or_else(|| {
+        fs::read_to_string(MACHINE_ID_FILE)
+            .map(|machine_id| machine_id.trim().to_string())
+            .unwrap_or_default()
+    })
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn notify(&mut self, _previous: &State, current: &State) -> Result<()> {
+        let (ip, result) = match *current {
+            State::Connected { ref ip, .. } => (ip.as_ref().map(String::as_str), current.name()),
+            State::Failed { .. } => (None, "failed"),
+            _ => return Ok(()),
+        };
+
+        let payload = WebhookPayload {
+            device_id: &self.device_id,
+            ssid: current.ssid().unwrap_or(""),
+            ip,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            result,
+            failure: current.failure().map(|failure| failure.name()),
+            reason: current.reason(),
+        };
+
+        let body =
+            serde_json::to_string(&payload).chain_err(|| ErrorKind::Notify("webhook".into()))?;
+
+        let signature = self
+            .secret
+            .as_ref()
+            .map(|secret| hmac_sha256_hex(secret.expose().as_bytes(), body.as_bytes()));
+
+        let url = self.url.clone();
+
+        // Deliver in the background so that retries do not hold up the
+        // transition
+        thread::spawn(move || {
+            for attempt in 0..WEBHOOK_ATTEMPTS {
+                match post(&url, &body, &signature) {
+                    Ok(()) => return,
+                    Err(e) => warn!("Webhook delivery attempt {} failed: {}", attempt + 1, e),
+                }
+
+                if attempt + 1 < WEBHOOK_ATTEMPTS {
+                    thread::sleep(Duration::from_secs(1 << attempt));
+                }
+            }
+
+            error!("Giving up on webhook delivery to {}", url);
+        });
+
+        Ok(())
+    }
+}
+
+fn post(url: &str, body: &str, signature: &Option<String>) -> Result<()> {
+    let error = || ErrorKind::Notify("webhook".into());
+
+    let mut command = Command::new("curl");
+
+    command.args(&[
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--max-time",
+        &WEBHOOK_TIMEOUT.to_string(),
+        "--request",
+        "POST",
+        "--header",
+        "Content-Type: application/json",
+    ]);
+
+    if let Some(ref signature) = *signature {
+        command
+            .arg("--header")
+            .arg(format!("{}: sha256={}", SIGNATURE_HEADER, signature));
+    }
+
+    // The body goes through stdin to keep it off the command line
+    let mut child = command
+        .args(&["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .chain_err(error)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body.as_bytes()).chain_err(error)?;
+    }
+
+    if !child.wait().chain_err(error)?.success() {
+        bail!(error());
+    }
+
+    Ok(())
+}
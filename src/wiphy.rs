@@ -0,0 +1,388 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use config::Config;
+use errors::*;
+
+/// Interface mode a driver has to list for the hotspot
+const AP_MODE: &str = "AP";
+
+/// Interface mode needed for the WiFi Direct fallback
+const P2P_GO_MODE: &str = "P2P-GO";
+
+/// Where wpa_supplicant creates the control sockets `wpa_cli` talks to
+const WPA_CONTROL_DIRECTORIES: &[&str] = &["/var/run/wpa_supplicant", "/run/wpa_supplicant"];
+
+/// Name of the wiphy behind a network interface, e.g. `phy0`
+pub fn phy_name(interface: &str) -> Option<String> {
+    read_attribute(interface, "phy80211/name")
+}
+
+/// Interface modes supported by the driver of an interface, as listed by
+/// `iw phy`. `None` when they cannot be determined.
+pub fn interface_modes(interface: &str) -> Option<Vec<String>> {
+    let phy = phy_name(interface)?;
+
+    let output = Command::new("iw")
+        .args(&["phy", &phy, "info"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(parse_interface_modes(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub fn supports_ap_mode(interface: &str) -> Option<bool> {
+    interface_modes(interface).map(|modes| modes.iter().any(|mode| mode == AP_MODE))
+}
+
+pub fn supports_p2p_go_mode(interface: &str) -> Option<bool> {
+    interface_modes(interface).map(|modes| modes.iter().any(|mode| mode == P2P_GO_MODE))
+}
+
+/// Whether the driver of an interface lists 5 GHz frequencies, as `Band 2`
+/// of `iw phy`
+pub fn supports_5ghz(interface: &str) -> Option<bool> {
+    let phy = phy_name(interface)?;
+
+    let output = Command::new("iw")
+        .args(&["phy", &phy, "info"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.trim() == "Band 2:"),
+    )
+}
+
+/// Whether the driver of an interface can authenticate with SAE, which WPA3
+/// personal networks require, either in wpa_supplicant or iwd through the
+/// `AUTHENTICATE` command or offloaded to the firmware
+pub fn supports_sae(interface: &str) -> Option<bool> {
+    let phy = phy_name(interface)?;
+
+    let output = Command::new("iw")
+        .args(&["phy", &phy, "info"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+        let line = line.trim();
+        line.starts_with("Device supports SAE") || line.contains("SAE_OFFLOAD")
+    }))
+}
+
+fn parse_interface_modes(phy_info: &str) -> Vec<String> {
+    let mut modes = Vec::new();
+    let mut in_modes = false;
+
+    for line in phy_info.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("Supported interface modes") {
+            in_modes = true;
+            continue;
+        }
+
+        if in_modes {
+            if !trimmed.starts_with('*') {
+                break;
+            }
+
+            modes.push(trimmed.trim_start_matches('*').trim().to_string());
+        }
+    }
+
+    modes
+}
+
+/// How the hotspot can be provided on an interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HotspotMode {
+    AccessPoint,
+    WiFiDirect,
+}
+
+/// Fails fast when the driver cannot run an access point, instead of letting
+/// the backend fail with an unspecific error. Falls back to a WiFi Direct
+/// group owner when allowed by `--wifi-direct-fallback` and supported.
+pub fn require_hotspot_mode(interface: &str, config: &Config) -> Result<HotspotMode> {
+    match supports_ap_mode(interface) {
+        Some(true) => return Ok(HotspotMode::AccessPoint),
+        None => {
+            warn!(
+                "Could not determine whether {} supports access point mode",
+                interface
+            );
+            return Ok(HotspotMode::AccessPoint);
+        }
+        Some(false) => (),
+    }
+
+    if config.wifi_direct_fallback && supports_p2p_go_mode(interface) == Some(true) {
+        warn!(
+            "{} does not support access point mode, falling back to WiFi Direct",
+            interface
+        );
+        return Ok(HotspotMode::WiFiDirect);
+    }
+
+    bail!(ErrorKind::ApModeUnsupported(interface.into()))
+}
+
+/// Starts an autonomous WiFi Direct group owner through wpa_supplicant and
+/// returns the group interface it created. wpa_supplicant prefixes the SSID
+/// with `DIRECT-xy` and generates the group passphrase, which is logged as
+/// there is no other way for clients to learn it.
+pub fn start_p2p_group(interface: &str, config: &Config) -> Result<String> {
+    info!("Starting WiFi Direct group...");
+
+    // wpa_supplicant started by NetworkManager only listens on D-Bus
+    let has_control_interface = WPA_CONTROL_DIRECTORIES
+        .iter()
+        .any(|directory| Path::new(directory).join(interface).exists());
+
+    if !has_control_interface {
+        bail!(ErrorKind::NoWpaControlInterface(interface.into()));
+    }
+
+    if config.passphrase.is_some() {
+        warn!("WiFi Direct groups use a passphrase generated by wpa_supplicant");
+    }
+
+    wpa_cli(interface, &["p2p_set", "ssid_postfix", &config.ssid])?;
+    wpa_cli(interface, &["p2p_group_add"])?;
+
+    let prefix = format!("p2p-{}-", interface);
+    let mut group = None;
+
+    for _ in 0..10 {
+        group = find_interface_with_prefix(&prefix);
+
+        if group.is_some() {
+            break;
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    let group = match group {
+        Some(group) => group,
+        None => bail!(ErrorKind::CreateCaptivePortal),
+    };
+
+    // Nothing assigns the gateway address used by dnsmasq
+    let address = format!("{}/24", config.gateway);
+    let assigned = Command::new("ip")
+        .args(&["addr", "add", address.as_str(), "dev", group.as_str()])
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+
+    if !assigned {
+        error!("Assigning {} to {} failed", address, group);
+        let _ = stop_p2p_group(interface, &group);
+        bail!(ErrorKind::CreateCaptivePortal);
+    }
+
+    let ssid = wpa_cli(&group, &["status"])?
+        .lines()
+        .find(|line| line.starts_with("ssid="))
+        .map(|line| line["ssid=".len()..].to_string())
+        .unwrap_or_else(|| config.ssid.clone());
+    let passphrase = wpa_cli(&group, &["p2p_get_passphrase"])?;
+
+    info!(
+        "WiFi Direct group '{}' started on {}, passphrase: {}",
+        ssid,
+        group,
+        passphrase.trim()
+    );
+
+    Ok(group)
+}
+
+pub fn stop_p2p_group(interface: &str, group: &str) -> Result<()> {
+    info!("Stopping WiFi Direct group on {}...", group);
+
+    wpa_cli(interface, &["p2p_group_remove", group])
+        .map(|_| ())
+        .chain_err(|| ErrorKind::StopAccessPoint)
+}
+
+/// Runs `wpa_cli` on the interface and returns its output
+fn wpa_cli(interface: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new("wpa_cli")
+        .arg("-i")
+        .arg(interface)
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    if !output.status.success() || stdout.trim() == "FAIL" {
+        error!("wpa_cli {} failed: {}", args.join(" "), stdout.trim());
+        bail!(ErrorKind::CreateCaptivePortal);
+    }
+
+    Ok(stdout.into_owned())
+}
+
+pub fn interface_exists(interface: &str) -> bool {
+    Path::new("/sys/class/net").join(interface).exists()
+}
+
+/// Adds a virtual AP interface on the wiphy of `parent`, for access points
+/// run next to the one on `parent`. The driver has to allow the interface
+/// combination.
+pub fn add_ap_interface(parent: &str, interface: &str) -> Result<()> {
+    info!("Adding virtual AP interface {} to {}", interface, parent);
+
+    iw(
+        interface,
+        &["dev", parent, "interface", "add", interface, "type", "__ap"],
+    )
+}
+
+pub fn delete_interface(interface: &str) -> Result<()> {
+    iw(interface, &["dev", interface, "del"])
+}
+
+/// Switches the power saving of the driver over nl80211, for backends that
+/// do not manage it as a connection setting
+pub fn set_power_save(interface: &str, enabled: bool) -> Result<()> {
+    let value = if enabled { "on" } else { "off" };
+    let error = || ErrorKind::PowerSave(interface.into());
+
+    let status = Command::new("iw")
+        .args(&["dev", interface, "set", "power_save", value])
+        .status()
+        .chain_err(error)?;
+
+    if !status.success() {
+        bail!(error());
+    }
+
+    info!("Power saving of {} turned {}", interface, value);
+
+    Ok(())
+}
+
+fn iw(interface: &str, args: &[&str]) -> Result<()> {
+    let error = || ErrorKind::VirtualInterface(interface.into());
+
+    let status = Command::new("iw").args(args).status().chain_err(error)?;
+
+    if !status.success() {
+        bail!(error());
+    }
+
+    Ok(())
+}
+
+fn find_interface_with_prefix(prefix: &str) -> Option<String> {
+    fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .find(|name| name.starts_with(prefix))
+}
+
+/// WiFi interface as listed by `--list-interfaces`
+#[derive(Debug, Serialize)]
+pub struct WiFiInterface {
+    pub interface: String,
+    pub driver: Option<String>,
+    pub mac_address: Option<String>,
+    pub ap_mode: Option<bool>,
+    pub state: String,
+}
+
+impl WiFiInterface {
+    pub fn new(interface: &str, state: String) -> Self {
+        WiFiInterface {
+            interface: interface.to_string(),
+            driver: driver_name(interface),
+            mac_address: read_attribute(interface, "address"),
+            ap_mode: supports_ap_mode(interface),
+            state,
+        }
+    }
+}
+
+/// All wireless interfaces with their kernel operational state, for backends
+/// without device enumeration of their own
+pub fn list_wireless_interfaces() -> Vec<WiFiInterface> {
+    let mut interfaces = fs::read_dir("/sys/class/net")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("wireless").exists())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    interfaces.sort();
+
+    interfaces
+        .iter()
+        .map(|interface| {
+            let state = read_attribute(interface, "operstate").unwrap_or_else(|| "unknown".into());
+            WiFiInterface::new(interface, state)
+        })
+        .collect()
+}
+
+/// Kernel driver bound to an interface, e.g. `brcmfmac`
+pub fn driver_name(interface: &str) -> Option<String> {
+    fs::read_link(
+        Path::new("/sys/class/net")
+            .join(interface)
+            .join("device/driver"),
+    )
+    .ok()?
+    .file_name()?
+    .to_str()
+    .map(|name| name.to_string())
+}
+
+/// Whether the link of an interface is up, which for WiFi means that it is
+/// associated with an access point
+pub fn has_carrier(interface: &str) -> bool {
+    read_attribute(interface, "carrier").map_or(false, |carrier| carrier == "1")
+}
+
+/// Operational state of an interface, `dormant` while associated but not
+/// yet authorized by the supplicant and `up` once it is
+pub fn operstate(interface: &str) -> Option<String> {
+    read_attribute(interface, "operstate")
+}
+
+/// MAC address of an interface, which is the BSSID of its access point
+pub fn interface_address(interface: &str) -> Option<String> {
+    read_attribute(interface, "address")
+}
+
+fn read_attribute(interface: &str, attribute: &str) -> Option<String> {
+    fs::read_to_string(Path::new("/sys/class/net").join(interface).join(attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
@@ -0,0 +1,207 @@
+//! WireGuard tunnel enrolled along with the WiFi credentials, for zero-touch
+//! secure enrollment of field devices. The configuration in the `wg-quick`
+//! format is imported as a NetworkManager WireGuard connection, and the
+//! connection only counts as successful once the tunnel has completed a
+//! handshake with its peer.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use profile::nmcli;
+use secret::Secret;
+
+/// Name of the connection and its interface, at most 15 characters
+pub const WIREGUARD_INTERFACE: &str = "wg-connect";
+
+/// `nmcli` names the imported connection after the file
+const IMPORT_FILE: &str = "/run/wifi-connect/wg-connect.conf";
+
+/// Seconds to wait for the first handshake with the peer
+const HANDSHAKE_TIMEOUT: u64 = 30;
+
+/// Upper bound of a configuration sent with the credentials
+const MAX_CONFIG_LENGTH: usize = 16 * 1024;
+
+/// WireGuard configuration entered in the `wireguard` field, none if it is
+/// left empty
+pub fn collect_wireguard_config(
+    value: Option<String>,
+) -> ::std::result::Result<Option<Secret>, String> {
+    let config = match value {
+        Some(ref value) if !value.trim().is_empty() => Secret::from(value.trim()),
+        _ => return Ok(None),
+    };
+
+    validate_wireguard_config(config.expose())?;
+
+    Ok(Some(config))
+}
+
+/// Requires the settings without which the tunnel cannot come up: the
+/// private key of the device and a peer with a public key and an endpoint
+pub fn validate_wireguard_config(config: &str) -> ::std::result::Result<(), String> {
+    if config.len() > MAX_CONFIG_LENGTH {
+        return Err(format!(
+            "the WireGuard configuration exceeds {} bytes",
+            MAX_CONFIG_LENGTH
+        ));
+    }
+
+    let mut section = String::new();
+    let mut private_key = false;
+    let mut peers = 0;
+    let mut public_keys = 0;
+    let mut endpoints = 0;
+
+    for line in config.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_lowercase();
+
+            if section == "peer" {
+                peers += 1;
+            }
+
+            continue;
+        }
+
+        let key = match line.find('=') {
+            Some(index) => line[..index].trim().to_lowercase(),
+            None => return Err(format!("'{}' is not a WireGuard setting", line)),
+        };
+
+        match (section.as_str(), key.as_str()) {
+            ("interface", "privatekey") => private_key = true,
+            ("peer", "publickey") => public_keys += 1,
+            ("peer", "endpoint") => endpoints += 1,
+            _ => {}
+        }
+    }
+
+    if !private_key {
+        return Err("the WireGuard configuration has no [Interface] PrivateKey".into());
+    }
+
+    if peers == 0 || public_keys < peers {
+        return Err("every WireGuard [Peer] needs a PublicKey".into());
+    }
+
+    if endpoints == 0 {
+        return Err("the WireGuard configuration has no peer Endpoint to connect to".into());
+    }
+
+    Ok(())
+}
+
+/// Imports `config` replacing an earlier tunnel and waits for the handshake
+/// with the peer. A tunnel that does not come up is removed again.
+pub fn enroll_wireguard(config: &Secret) -> Result<()> {
+    remove_wireguard();
+
+    import_wireguard(config)?;
+
+    if !nmcli(&["connection", "up", "id", WIREGUARD_INTERFACE]) {
+        remove_wireguard();
+        bail!(ErrorKind::ProvisionWireguard);
+    }
+
+    if !wait_for_handshake(HANDSHAKE_TIMEOUT) {
+        remove_wireguard();
+        bail!(ErrorKind::WireguardHandshake(HANDSHAKE_TIMEOUT));
+    }
+
+    info!("WireGuard tunnel {} established", WIREGUARD_INTERFACE);
+
+    Ok(())
+}
+
+pub fn remove_wireguard() {
+    nmcli(&["connection", "delete", "id", WIREGUARD_INTERFACE]);
+}
+
+/// The configuration holds the private key, so the file handed to `nmcli`
+/// is readable by root only and removed right after the import
+fn import_wireguard(config: &Secret) -> Result<()> {
+    let path = Path::new(IMPORT_FILE);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).chain_err(|| ErrorKind::ProvisionWireguard)?;
+    }
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(config.expose().as_bytes()));
+
+    let imported = written.is_ok()
+        && nmcli(&[
+            "connection",
+            "import",
+            "type",
+            "wireguard",
+            "file",
+            IMPORT_FILE,
+        ]);
+
+    let _ = fs::remove_file(path);
+
+    written.chain_err(|| ErrorKind::ProvisionWireguard)?;
+
+    if !imported {
+        bail!(ErrorKind::ProvisionWireguard);
+    }
+
+    Ok(())
+}
+
+/// Polls `wg` for a handshake with any peer of the tunnel
+fn wait_for_handshake(timeout: u64) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    loop {
+        if has_handshake() {
+            return true;
+        }
+
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// `wg show` lists the Unix time of the latest handshake of every peer, 0
+/// before the first one
+fn has_handshake() -> bool {
+    let output = match Command::new("wg")
+        .args(&["show", WIREGUARD_INTERFACE, "latest-handshakes"])
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+
+    if !output.status.success() {
+        return false;
+    }
+
+    String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+        line.split_whitespace()
+            .nth(1)
+            .and_then(|timestamp| timestamp.parse::<u64>().ok())
+            .map_or(false, |timestamp| timestamp > 0)
+    })
+}
@@ -0,0 +1,353 @@
+//! End-to-end test of the captive portal flow on virtual radios.
+//!
+//! Three `mac80211_hwsim` radios are created. The first stays in the root
+//! network namespace and is managed by NetworkManager for WiFi Connect, the
+//! second is moved into an "upstream" namespace where hostapd serves the
+//! network to provision, and the third is moved into a "phone" namespace that
+//! joins the portal and submits the credentials over HTTP.
+//!
+//! The test needs root, NetworkManager, hostapd, iw, curl and a DHCP client
+//! (dhclient or udhcpc), and is skipped unless explicitly enabled:
+//!
+//! ```text
+//! sudo WIFI_CONNECT_HWSIM=1 cargo test --test hwsim -- --nocapture
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const UPSTREAM_NS: &str = "wifi-connect-upstream";
+const PHONE_NS: &str = "wifi-connect-phone";
+
+const PORTAL_SSID: &str = "WiFi Connect Test";
+const PORTAL_GATEWAY: &str = "192.168.42.1";
+
+const UPSTREAM_SSID: &str = "Upstream";
+const UPSTREAM_PASSPHRASE: &str = "upstream-passphrase";
+const UPSTREAM_ADDRESS: &str = "10.42.0.1/24";
+/// Profiles WiFi Connect creates are named with a `wifi-connect-` prefix
+const UPSTREAM_CONNECTION: &str = "wifi-connect-Upstream";
+
+/// Tears everything down in reverse order, also when an assertion fails
+struct Harness {
+    work_dir: PathBuf,
+    processes: Vec<Child>,
+    namespaces: Vec<&'static str>,
+    module_loaded: bool,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let work_dir = env::temp_dir().join("wifi-connect-hwsim");
+        let _ = fs::remove_dir_all(&work_dir);
+        fs::create_dir_all(&work_dir).unwrap();
+
+        Harness {
+            work_dir,
+            processes: Vec::new(),
+            namespaces: Vec::new(),
+            module_loaded: false,
+        }
+    }
+
+    fn load_radios(&mut self, radios: usize) -> Vec<String> {
+        run("modprobe", &["-r", "mac80211_hwsim"]);
+        assert!(run("modprobe", &["mac80211_hwsim", &format!("radios={}", radios)]).success());
+        self.module_loaded = true;
+
+        wait_for("hwsim radios", Duration::from_secs(10), || {
+            hwsim_phys().len() == radios
+        });
+
+        hwsim_phys()
+    }
+
+    fn add_namespace(&mut self, namespace: &'static str, phy: &str) -> String {
+        run("ip", &["netns", "del", namespace]);
+        assert!(run("ip", &["netns", "add", namespace]).success());
+        self.namespaces.push(namespace);
+
+        let interface = phy_interface(phy);
+
+        assert!(run("iw", &["phy", phy, "set", "netns", "name", namespace]).success());
+        assert!(netns(namespace, "ip", &["link", "set", "lo", "up"]).success());
+        assert!(netns(namespace, "ip", &["link", "set", &interface, "up"]).success());
+
+        interface
+    }
+
+    fn spawn(&mut self, command: &mut Command) -> usize {
+        let child = command.spawn().expect("Spawning process failed");
+        self.processes.push(child);
+        self.processes.len() - 1
+    }
+
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.work_dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for child in self.processes.iter_mut().rev() {
+            let _ = Command::new("kill")
+                .arg("-TERM")
+                .arg(child.id().to_string())
+                .status();
+            let _ = child.wait();
+        }
+
+        for namespace in &self.namespaces {
+            run("ip", &["netns", "del", namespace]);
+        }
+
+        if self.module_loaded {
+            run("modprobe", &["-r", "mac80211_hwsim"]);
+        }
+
+        let _ = fs::remove_dir_all(&self.work_dir);
+    }
+}
+
+fn is_enabled() -> bool {
+    if env::var("WIFI_CONNECT_HWSIM").is_err() {
+        println!("Skipping hwsim test, set WIFI_CONNECT_HWSIM=1 to run it");
+        return false;
+    }
+
+    let uid = String::from_utf8(capture("id", &["-u"]).stdout).unwrap_or_default();
+    if uid.trim() != "0" {
+        println!("Skipping hwsim test, it has to run as root");
+        return false;
+    }
+
+    true
+}
+
+fn run(program: &str, args: &[&str]) -> ExitStatus {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap_or_else(|e| panic!("Running {} failed: {}", program, e))
+}
+
+fn capture(program: &str, args: &[&str]) -> Output {
+    Command::new(program)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("Running {} failed: {}", program, e))
+}
+
+fn netns_command(namespace: &str, program: &str, args: &[&str]) -> Command {
+    let mut command = Command::new("ip");
+    command.args(&["netns", "exec", namespace, program]).args(args);
+    command
+}
+
+fn netns(namespace: &str, program: &str, args: &[&str]) -> ExitStatus {
+    netns_command(namespace, program, args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap_or_else(|e| panic!("Running {} in {} failed: {}", program, namespace, e))
+}
+
+fn wait_for<F>(what: &str, timeout: Duration, mut condition: F)
+where
+    F: FnMut() -> bool,
+{
+    let deadline = Instant::now() + timeout;
+
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn hwsim_phys() -> Vec<String> {
+    let mut phys: Vec<String> = fs::read_dir("/sys/class/ieee80211")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    fs::read_link(entry.path().join("device/driver"))
+                        .map(|driver| driver.ends_with("mac80211_hwsim"))
+                        .unwrap_or(false)
+                })
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    phys.sort();
+    phys
+}
+
+fn phy_interface(phy: &str) -> String {
+    let path = Path::new("/sys/class/ieee80211").join(phy).join("device/net");
+
+    fs::read_dir(&path)
+        .ok()
+        .and_then(|mut entries| entries.next())
+        .and_then(|entry| entry.ok())
+        .and_then(|entry| entry.file_name().into_string().ok())
+        .unwrap_or_else(|| panic!("No interface found for {}", phy))
+}
+
+fn obtain_address(namespace: &str, interface: &str) -> bool {
+    if netns(namespace, "dhclient", &["-1", "-v", interface]).success() {
+        return true;
+    }
+
+    netns(namespace, "udhcpc", &["-n", "-q", "-i", interface]).success()
+}
+
+fn curl(namespace: &str, args: &[&str]) -> Option<String> {
+    let output = netns_command(namespace, "curl", &["-sf", "--max-time", "60"])
+        .args(args)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+fn active_connection(interface: &str) -> String {
+    let output = capture(
+        "nmcli",
+        &["-t", "-g", "GENERAL.CONNECTION", "device", "show", interface],
+    );
+
+    String::from_utf8(output.stdout).unwrap_or_default().trim().to_string()
+}
+
+#[test]
+fn provisions_network_through_captive_portal() {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut harness = Harness::new();
+
+    let phys = harness.load_radios(3);
+    let portal_interface = phy_interface(&phys[0]);
+    let upstream_interface = harness.add_namespace(UPSTREAM_NS, &phys[1]);
+    let phone_interface = harness.add_namespace(PHONE_NS, &phys[2]);
+
+    // Upstream network that the phone provisions through the portal
+    let hostapd_conf = harness.write(
+        "hostapd.conf",
+        &format!(
+            "interface={}\ndriver=nl80211\nssid={}\nhw_mode=g\nchannel=6\n\
+             wpa=2\nwpa_key_mgmt=WPA-PSK\nrsn_pairwise=CCMP\nwpa_passphrase={}\n",
+            upstream_interface, UPSTREAM_SSID, UPSTREAM_PASSPHRASE
+        ),
+    );
+    assert!(netns(UPSTREAM_NS, "ip", &["addr", "add", UPSTREAM_ADDRESS, "dev", &upstream_interface]).success());
+    harness.spawn(&mut netns_command(
+        UPSTREAM_NS,
+        "hostapd",
+        &[hostapd_conf.to_str().unwrap()],
+    ));
+
+    wait_for("NetworkManager to manage the portal radio", Duration::from_secs(20), || {
+        capture("nmcli", &["-t", "-g", "GENERAL.STATE", "device", "show", &portal_interface])
+            .status
+            .success()
+    });
+
+    // Scan -> portal
+    let wifi_connect = harness.spawn(
+        Command::new(env!("CARGO_BIN_EXE_wifi-connect"))
+            .args(&["--portal-interface", &portal_interface])
+            .args(&["--portal-ssid", PORTAL_SSID])
+            .args(&["--portal-gateway", PORTAL_GATEWAY])
+            .args(&["--activity-timeout", "300"])
+            .env("RUST_LOG", "wifi_connect=debug"),
+    );
+
+    wait_for("the phone to join the portal", Duration::from_secs(60), || {
+        netns(PHONE_NS, "iw", &["dev", &phone_interface, "connect", PORTAL_SSID]);
+        thread::sleep(Duration::from_secs(2));
+        netns_command(PHONE_NS, "iw", &["dev", &phone_interface, "link"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(PORTAL_SSID))
+            .unwrap_or(false)
+    });
+
+    assert!(
+        obtain_address(PHONE_NS, &phone_interface),
+        "dnsmasq did not hand out an address to the phone"
+    );
+
+    let networks = curl(PHONE_NS, &[&format!("http://{}/networks", PORTAL_GATEWAY)])
+        .expect("Fetching the list of networks failed");
+    assert!(
+        networks.contains(UPSTREAM_SSID),
+        "Upstream network missing from scan results: {}",
+        networks
+    );
+
+    // Connect
+    curl(
+        PHONE_NS,
+        &[
+            "-X",
+            "POST",
+            "--data-urlencode",
+            &format!("ssid={}", UPSTREAM_SSID),
+            "--data-urlencode",
+            &format!("passphrase={}", UPSTREAM_PASSPHRASE),
+            &format!("http://{}/connect", PORTAL_GATEWAY),
+        ],
+    )
+    .expect("Submitting the credentials failed");
+
+    // Teardown: WiFi Connect exits on its own once connected
+    let deadline = Instant::now() + Duration::from_secs(120);
+    let status = loop {
+        if let Some(status) = harness.processes[wifi_connect].try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "WiFi Connect did not exit after connecting");
+        thread::sleep(Duration::from_secs(1));
+    };
+
+    assert!(status.success(), "WiFi Connect exited with {}", status);
+    assert_eq!(active_connection(&portal_interface), UPSTREAM_CONNECTION);
+
+    let managed = capture(
+        "nmcli",
+        &[
+            "-t",
+            "-g",
+            "user.wifi-connect.managed",
+            "connection",
+            "show",
+            UPSTREAM_CONNECTION,
+        ],
+    )
+    .stdout;
+    assert_eq!(
+        String::from_utf8_lossy(&managed).trim(),
+        "yes",
+        "Connection profile is not marked as managed"
+    );
+
+    let hotspot_left = capture("nmcli", &["-t", "-g", "NAME", "connection", "show"]).stdout;
+    assert!(
+        !String::from_utf8_lossy(&hotspot_left).contains(PORTAL_SSID),
+        "Portal connection profile was not removed"
+    );
+}
@@ -0,0 +1,77 @@
+#[macro_use]
+extern crate log;
+extern crate serde_json;
+extern crate wifi_connect;
+
+use std::sync::{Mutex, Once};
+
+use log::{LogLevelFilter, LogMetadata, LogRecord};
+
+use wifi_connect::network::NetworkCredentials;
+use wifi_connect::secret::Secret;
+
+const PASSPHRASE: &str = "correct horse battery staple";
+
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static INIT: Once = Once::new();
+
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        LOGS.lock().unwrap().push(record.args().to_string());
+    }
+}
+
+fn init_logger() {
+    INIT.call_once(|| {
+        log::set_logger(|max_log_level| {
+            max_log_level.set(LogLevelFilter::Trace);
+            Box::new(CaptureLogger)
+        })
+        .unwrap();
+    });
+}
+
+fn assert_not_logged(secret: &str) {
+    for line in LOGS.lock().unwrap().iter() {
+        assert!(!line.contains(secret), "secret found in log line: {}", line);
+    }
+}
+
+#[test]
+fn secret_is_redacted_when_formatted() {
+    let secret = Secret::from(PASSPHRASE);
+
+    assert!(!format!("{}", secret).contains(PASSPHRASE));
+    assert!(!format!("{:?}", secret).contains(PASSPHRASE));
+    assert_eq!(secret.expose(), PASSPHRASE);
+}
+
+#[test]
+fn credentials_do_not_leak_into_logs() {
+    init_logger();
+
+    let json = format!(r#"{{"ssid": "home", "passphrase": "{}"}}"#, PASSPHRASE);
+    let credentials: NetworkCredentials = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(credentials.passphrase.expose(), PASSPHRASE);
+
+    debug!("Received credentials: {:?}", credentials);
+    info!("Connecting with passphrase {}", credentials.passphrase);
+    warn!("Passphrase {:?} rejected", credentials.passphrase);
+
+    assert!(!LOGS.lock().unwrap().is_empty());
+    assert_not_logged(PASSPHRASE);
+}
+
+#[test]
+fn missing_passphrase_deserializes_as_empty_secret() {
+    let credentials: NetworkCredentials = serde_json::from_str(r#"{"ssid": "cafe"}"#).unwrap();
+
+    assert!(credentials.passphrase.is_empty());
+}
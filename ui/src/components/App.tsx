@@ -24,11 +24,27 @@ export interface NetworkInfo {
 	ssid?: string;
 	identity?: string;
 	passphrase?: string;
+	proxy_host?: string;
+	proxy_port?: string;
+	proxy_pac_url?: string;
+	proxy_username?: string;
+	proxy_password?: string;
+	wireguard?: string;
+}
+
+export interface ConnectAccepted {
+	redirect_url?: string;
+}
+
+interface Validation {
+	valid: boolean;
+	problems: Array<{ field: string; message: string }>;
 }
 
 export interface Network {
 	ssid: string;
 	security: string;
+	unsupported?: string;
 }
 
 const NavbarBrand = styled.div`
@@ -72,10 +88,29 @@ const App = () => {
 			});
 	}, []);
 
-	const onConnect = (data: NetworkInfo) => {
-		setAttemptedConnect(true);
-		setError('');
+	// Catches typos right away instead of after a failed connection attempt,
+	// which takes the hotspot down for a while
+	const validate = (data: NetworkInfo) => {
+		const network = availableNetworks.find(({ ssid }) => ssid === data.ssid);
 
+		return fetch('/validate', {
+			method: 'POST',
+			body: JSON.stringify({ ...data, security: network?.security ?? 'none' }),
+			headers: {
+				'Content-Type': 'application/json',
+			},
+		})
+			.then((resp) => (resp.status === 200 ? resp.json() : null))
+			.then((validation: Validation | null) =>
+				// Older devices without the endpoint are left to the connect
+				validation && !validation.valid
+					? validation.problems.map(({ message }) => message).join(' ')
+					: '',
+			)
+			.catch(() => '');
+	};
+
+	const connect = (data: NetworkInfo) =>
 		fetch('/connect', {
 			method: 'POST',
 			body: JSON.stringify(data),
@@ -87,10 +122,32 @@ const App = () => {
 				if (resp.status !== 200) {
 					throw new Error(resp.statusText);
 				}
+
+				// The body carries a link to follow with --redirect-url
+				return resp.text();
+			})
+			.then((body) => {
+				const accepted: ConnectAccepted = body ? JSON.parse(body) : {};
+				if (accepted.redirect_url) {
+					window.location.assign(accepted.redirect_url);
+				}
 			})
 			.catch((e: Error) => {
 				setError(`Failed to connect to the network. ${e.message || e}`);
 			});
+
+	const onConnect = (data: NetworkInfo) => {
+		setError('');
+
+		validate(data).then((problems) => {
+			if (problems) {
+				setError(problems);
+				return;
+			}
+
+			setAttemptedConnect(true);
+			return connect(data);
+		});
 	};
 
 	return (
@@ -9,10 +9,12 @@ const getSchema = (availableNetworks: Network[]): JSONSchema => ({
 		ssid: {
 			title: 'SSID',
 			type: 'string',
-			default: availableNetworks[0]?.ssid,
+			default: availableNetworks.find((network) => !network.unsupported)?.ssid,
 			oneOf: availableNetworks.map((network) => ({
 				const: network.ssid,
-				title: network.ssid,
+				title: network.unsupported
+					? `${network.ssid} (${network.unsupported})`
+					: network.ssid,
 			})),
 		},
 		identity: {
@@ -25,13 +27,50 @@ const getSchema = (availableNetworks: Network[]): JSONSchema => ({
 			type: 'string',
 			default: '',
 		},
+		proxy_host: {
+			title: 'Proxy host (optional)',
+			type: 'string',
+			default: '',
+		},
+		proxy_port: {
+			title: 'Proxy port',
+			type: 'string',
+			default: '',
+		},
+		proxy_pac_url: {
+			title: 'Proxy auto-config URL (optional)',
+			type: 'string',
+			default: '',
+		},
+		proxy_username: {
+			title: 'Proxy user',
+			type: 'string',
+			default: '',
+		},
+		proxy_password: {
+			title: 'Proxy password',
+			type: 'string',
+			default: '',
+		},
+		wireguard: {
+			title: 'WireGuard configuration (optional)',
+			type: 'string',
+			default: '',
+		},
 	},
 	required: ['ssid'],
 });
 
-const getUiSchema = (isEnterprise: boolean): RenditionUiSchema => ({
+const getUiSchema = (
+	availableNetworks: Network[],
+	isEnterprise: boolean,
+): RenditionUiSchema => ({
 	ssid: {
 		'ui:placeholder': 'Select SSID',
+		// Networks the device cannot connect to are shown greyed out
+		'ui:enumDisabled': availableNetworks
+			.filter((network) => network.unsupported)
+			.map((network) => network.ssid),
 		'ui:options': {
 			emphasized: true,
 		},
@@ -48,6 +87,12 @@ const getUiSchema = (isEnterprise: boolean): RenditionUiSchema => ({
 			emphasized: true,
 		},
 	},
+	proxy_password: {
+		'ui:widget': 'password',
+	},
+	wireguard: {
+		'ui:widget': 'textarea',
+	},
 });
 
 const isEnterpriseNetwork = (
@@ -98,7 +143,7 @@ export const NetworkInfoForm = ({
 				onFormSubmit={({ formData }) => onSubmit(formData)}
 				value={data}
 				schema={getSchema(availableNetworks)}
-				uiSchema={getUiSchema(isSelectedNetworkEnterprise)}
+				uiSchema={getUiSchema(availableNetworks, isSelectedNetworkEnterprise)}
 				submitButtonProps={{
 					width: '60%',
 					mx: '20%',